@@ -257,7 +257,7 @@ enum Cmd {
 
     /// Run a WebAssembly file or Wasmer container.
     #[clap(alias = "run-unstable")]
-    Run(Run),
+    Run(Box<Run>),
 
     // DEPLOY commands
     /// Deploy apps to the Wasmer Edge.
@@ -108,6 +108,9 @@ impl Run {
         let runtime = self
             .wasi
             .prepare_runtime(store.engine().clone(), &self.env, runtime)?;
+        // The runtime sets up its engine to enforce the resource limits of
+        // processes, so modules are instantiated in its stores
+        let store = runtime.new_store();
 
         // This is a slow operation, so let's temporarily wrap the runtime with
         // something that displays progress
@@ -121,8 +124,8 @@ impl Run {
 
         let result = {
             match target {
-                ExecutableTarget::WebAssembly { module, path } => {
-                    self.execute_wasm(&path, &module, store, runtime)
+                ExecutableTarget::WebAssembly { module, hash, path } => {
+                    self.execute_wasm(&path, &module, hash, store, runtime)
                 }
                 ExecutableTarget::Package(pkg) => self.execute_webc(&pkg, runtime),
             }
@@ -140,13 +143,14 @@ impl Run {
         &self,
         path: &Path,
         module: &Module,
+        module_hash: ModuleHash,
         mut store: Store,
         runtime: Arc<dyn Runtime + Send + Sync>,
     ) -> Result<(), Error> {
         if wasmer_emscripten::is_emscripten_module(module) {
             self.execute_emscripten_module()
         } else if wasmer_wasix::is_wasi_module(module) || wasmer_wasix::is_wasix_module(module) {
-            self.execute_wasi_module(path, module, runtime, store)
+            self.execute_wasi_module(path, module, module_hash, runtime, store)
         } else {
             self.execute_pure_wasm_module(module, &mut store)
         }
@@ -303,14 +307,16 @@ impl Run {
         &self,
         wasm_path: &Path,
         module: &Module,
+        module_hash: ModuleHash,
         runtime: Arc<dyn Runtime + Send + Sync>,
         store: Store,
     ) -> Result<(), Error> {
         let program_name = wasm_path.display().to_string();
 
-        let builder = self
+        let mut builder = self
             .wasi
             .prepare(module, program_name, self.args.clone(), runtime)?;
+        builder.capabilities_mut().snapshot.module_hash = Some(module_hash);
 
         builder.run_with_store_async(module.clone(), store)?;
 
@@ -548,7 +554,11 @@ impl TargetOnDisk {
 
 #[derive(Debug, Clone)]
 enum ExecutableTarget {
-    WebAssembly { module: Module, path: PathBuf },
+    WebAssembly {
+        module: Module,
+        hash: ModuleHash,
+        path: PathBuf,
+    },
     Package(BinaryPackage),
 }
 
@@ -596,6 +606,7 @@ impl ExecutableTarget {
 
                 Ok(ExecutableTarget::WebAssembly {
                     module,
+                    hash: ModuleHash::sha256(&wasm),
                     path: path.to_path_buf(),
                 })
             }
@@ -603,9 +614,11 @@ impl ExecutableTarget {
                 let engine = runtime.engine();
                 pb.set_message("Deserializing pre-compiled WebAssembly module");
                 let module = unsafe { Module::deserialize_from_file(&engine, path)? };
+                let hash = ModuleHash::sha256(std::fs::read(path)?);
 
                 Ok(ExecutableTarget::WebAssembly {
                     module,
+                    hash,
                     path: path.to_path_buf(),
                 })
             }
@@ -808,6 +821,14 @@ impl<R: wasmer_wasix::Runtime + Send + Sync> wasmer_wasix::Runtime for Monitorin
     fn tty(&self) -> Option<&(dyn wasmer_wasix::os::TtyBridge + Send + Sync)> {
         self.runtime.tty()
     }
+
+    fn determinism(&self) -> Option<&Arc<wasmer_wasix::runtime::Determinism>> {
+        self.runtime.determinism()
+    }
+
+    fn journal(&self) -> Option<&Arc<wasmer_wasix::runtime::SyscallJournal>> {
+        self.runtime.journal()
+    }
 }
 
 #[derive(Debug)]
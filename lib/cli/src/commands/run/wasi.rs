@@ -1,5 +1,6 @@
 use std::{
     collections::{BTreeSet, HashMap},
+    net::{IpAddr, SocketAddr},
     path::{Path, PathBuf},
     sync::{mpsc::Sender, Arc},
     time::Duration,
@@ -11,6 +12,9 @@ use clap::Parser;
 use tokio::runtime::Handle;
 use url::Url;
 use virtual_fs::{DeviceFile, FileSystem, PassthruFileSystem, RootFileSystemBuilder};
+use virtual_net::{
+    CaptureNetworking, FirewallPolicy, FirewallRule, NetworkCapture, ResolverConfig,
+};
 use wasmer::{Engine, Function, Instance, Memory32, Memory64, Module, RuntimeError, Store, Value};
 use wasmer_registry::wasmer_env::WasmerEnv;
 use wasmer_wasix::{
@@ -32,14 +36,17 @@ use wasmer_wasix::{
             tokio::{RuntimeOrHandle, TokioTaskManager},
             VirtualTaskManagerExt,
         },
+        StackLink, SyscallJournal,
     },
     types::__WASI_STDIN_FILENO,
     wasmer_wasix_types::wasi::Errno,
-    PluggableRuntime, RewindState, Runtime, WasiEnv, WasiEnvBuilder, WasiError, WasiFunctionEnv,
-    WasiVersion,
+    PluggableRuntime, ProcessSnapshot, RewindState, Runtime, WasiEnv, WasiEnvBuilder, WasiError,
+    WasiFunctionEnv, WasiVersion,
 };
 
-use crate::utils::{parse_envvar, parse_mapdir};
+use crate::utils::{
+    parse_envvar, parse_host_entry, parse_mapdir, parse_nameserver, parse_stack_link,
+};
 
 const WAPM_SOURCE_CACHE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
 
@@ -97,6 +104,63 @@ pub struct Wasi {
     #[clap(long = "net")]
     pub networking: bool,
 
+    /// Only let through the network traffic that matches this rule, can be
+    /// given several times.
+    ///
+    /// Rules are comma separated `key=value` pairs with the keys `dir`
+    /// (`in` or `out`), `proto` (`tcp`, `udp` or `icmp`), `cidr`, `host`
+    /// (`*.example.com` matches subdomains) and `port` (a port or a range),
+    /// for instance `proto=tcp,cidr=10.0.0.0/8,port=5432`. For incoming
+    /// traffic the address is that of the peer and the port is the local
+    /// one. Host names have to be allowed for the guest to resolve them.
+    #[clap(long = "net-allow", name = "ALLOW_RULE", requires = "networking")]
+    pub net_allow: Vec<FirewallRule>,
+
+    /// Block the traffic that matches this rule, even if it is allowed by
+    /// `--net-allow`. Uses the same syntax as `--net-allow`.
+    #[clap(long = "net-deny", name = "DENY_RULE", requires = "networking")]
+    pub net_deny: Vec<FirewallRule>,
+
+    /// Resolve a host name of the guest to an IP address without asking
+    /// any name server (e.g. `--net-host api.example.com=127.0.0.1`).
+    #[clap(
+        long = "net-host",
+        name = "NAME=IP",
+        value_parser=parse_host_entry,
+        requires = "networking",
+    )]
+    pub net_hosts: Vec<(String, IpAddr)>,
+
+    /// Send the DNS queries of the guest to this name server instead of
+    /// the one of the host. The port defaults to 53.
+    #[clap(
+        long = "net-nameserver",
+        name = "NAMESERVER",
+        value_parser=parse_nameserver,
+        requires = "networking",
+    )]
+    pub net_nameservers: Vec<SocketAddr>,
+
+    /// Record the network traffic of the guest into a pcapng file that can
+    /// be opened with Wireshark.
+    ///
+    /// The TCP/IP headers of stream sockets are made up from the payloads.
+    #[clap(long = "net-capture", name = "CAPTURE_PATH", requires = "networking")]
+    pub net_capture: Option<PathBuf>,
+
+    /// Run a TCP/IP stack in userspace that sends Ethernet frames over this
+    /// link, which gives the guest raw sockets, DHCP and bridging.
+    ///
+    /// The link is `switch` for a switch in memory that the guest can bridge
+    /// with `port_bridge`, or `udp:<ip>:<port>` for a UDP tunnel to a peer.
+    #[clap(
+        long = "net-stack",
+        name = "LINK",
+        value_parser=parse_stack_link,
+        requires = "networking",
+    )]
+    pub net_stack: Option<StackLink>,
+
     /// Disables the TTY bridge
     #[clap(long = "no-tty")]
     pub no_tty: bool,
@@ -114,6 +178,29 @@ pub struct Wasi {
     /// Require WASI modules to only import 1 version of WASI.
     #[clap(long = "deny-multiple-wasi-versions")]
     pub deny_multiple_wasi_versions: bool,
+
+    /// Record everything the program receives from the host (clocks, random
+    /// bytes, reads, ...) into a journal file.
+    #[clap(
+        long = "journal-record",
+        name = "RECORD_PATH",
+        conflicts_with = "REPLAY_PATH"
+    )]
+    pub journal_record: Option<PathBuf>,
+
+    /// Replay a journal written with `--journal-record`, feeding the recorded
+    /// values back to the program instead of asking the host.
+    #[clap(long = "journal-replay", name = "REPLAY_PATH")]
+    pub journal_replay: Option<PathBuf>,
+
+    /// Allow the program to save a snapshot of itself to this file with
+    /// `proc_snapshot`.
+    #[clap(long = "snapshot-to", name = "SNAPSHOT_PATH")]
+    pub snapshot_to: Option<PathBuf>,
+
+    /// Resume a program from a snapshot instead of starting it afresh.
+    #[clap(long = "restore", name = "RESTORE_PATH")]
+    pub restore: Option<PathBuf>,
 }
 
 pub struct RunProperties {
@@ -227,6 +314,13 @@ impl Wasi {
 
         *builder.capabilities_mut() = self.capabilities();
 
+        if let Some(path) = &self.restore {
+            let snapshot = ProcessSnapshot::load(path).with_context(|| {
+                format!("Unable to load the snapshot at \"{}\"", path.display())
+            })?;
+            builder.set_restore_snapshot(snapshot);
+        }
+
         #[cfg(feature = "experimental-io-devices")]
         {
             if self.enable_experimental_io_devices {
@@ -245,7 +339,18 @@ impl Wasi {
             caps.http_client = wasmer_wasix::http::HttpClientCapabilityV1::new_allow_all();
         }
 
+        caps.networking.firewall = FirewallPolicy {
+            allow: self.net_allow.clone(),
+            deny: self.net_deny.clone(),
+        };
+        let mut resolver = ResolverConfig::new();
+        for (name, ip) in self.net_hosts.iter() {
+            resolver.add_host(name, *ip);
+        }
+        resolver.nameservers = self.net_nameservers.clone();
+        caps.networking.resolver = resolver;
         caps.threading.enable_asynchronous_threading = self.enable_async_threads;
+        caps.snapshot.path = self.snapshot_to.clone();
 
         caps
     }
@@ -263,6 +368,22 @@ impl Wasi {
 
         if self.networking {
             rt.set_networking_implementation(virtual_net::host::LocalNetworking::default());
+            if let Some(link) = &self.net_stack {
+                rt.set_stack_networking(link.clone())
+                    .context("Unable to start the TCP/IP stack")?;
+            }
+            if let Some(path) = &self.net_capture {
+                let file = std::fs::File::create(path).with_context(|| {
+                    format!(
+                        "Unable to create the network capture at \"{}\"",
+                        path.display()
+                    )
+                })?;
+                let capture = NetworkCapture::with_writer(std::io::BufWriter::new(file))
+                    .context("Unable to write the network capture")?;
+                let net = rt.networking.clone();
+                rt.set_networking_implementation(CaptureNetworking::new(net, capture));
+            }
         } else {
             rt.set_networking_implementation(virtual_net::UnsupportedVirtualNetworking::default());
         }
@@ -292,6 +413,17 @@ impl Wasi {
             .set_source(registry)
             .set_engine(Some(engine));
 
+        if let Some(path) = &self.journal_record {
+            let journal = SyscallJournal::record_to_file(path).with_context(|| {
+                format!("Unable to create the journal at \"{}\"", path.display())
+            })?;
+            rt.set_journal(journal);
+        } else if let Some(path) = &self.journal_replay {
+            let journal = SyscallJournal::replay_file(path)
+                .with_context(|| format!("Unable to load the journal at \"{}\"", path.display()))?;
+            rt.set_journal(journal);
+        }
+
         Ok(rt)
     }
 
@@ -2,8 +2,10 @@
 use anyhow::{bail, Result};
 use is_terminal::IsTerminal;
 use std::env;
+use std::net::{IpAddr, SocketAddr};
 use std::path::PathBuf;
-use wasmer_wasix::runners::MappedDirectory;
+use virtual_net::resolver::DNS_PORT;
+use wasmer_wasix::{runners::MappedDirectory, runtime::StackLink};
 
 /// Whether or not Wasmer should print with color
 pub fn wasmer_should_print_color() -> bool {
@@ -69,9 +71,60 @@ pub fn parse_envvar(entry: &str) -> Result<(String, String)> {
     }
 }
 
+/// Parses a host entry that maps a name to an IP address.
+pub fn parse_host_entry(entry: &str) -> Result<(String, IpAddr)> {
+    let entry = entry.trim();
+
+    match entry.split_once('=') {
+        Some((name, ip)) if !name.is_empty() => match ip.parse::<IpAddr>() {
+            Ok(ip) => Ok((name.to_string(), ip)),
+            Err(_) => bail!("Host entry has an invalid IP address `{}`", ip),
+        },
+        _ => bail!(
+            "Host entry must be of the form `<name>=<ip>`; found `{}`",
+            &entry
+        ),
+    }
+}
+
+/// Parses the address of a name server, the port defaults to the DNS port.
+pub fn parse_nameserver(entry: &str) -> Result<SocketAddr> {
+    let entry = entry.trim();
+
+    if let Ok(ip) = entry.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, DNS_PORT));
+    }
+    match entry.parse::<SocketAddr>() {
+        Ok(addr) => Ok(addr),
+        Err(_) => bail!(
+            "Name server must be of the form `<ip>` or `<ip>:<port>`; found `{}`",
+            &entry
+        ),
+    }
+}
+
+/// Parses the link of the TCP/IP stack, which is either `switch` or
+/// `udp:<ip>:<port>` for a UDP tunnel to a peer.
+pub fn parse_stack_link(entry: &str) -> Result<StackLink> {
+    let entry = entry.trim();
+
+    if entry == "switch" {
+        return Ok(StackLink::Switch(Default::default()));
+    }
+    match entry.strip_prefix("udp:").map(str::parse::<SocketAddr>) {
+        Some(Ok(peer)) => Ok(StackLink::UdpTunnel(peer)),
+        _ => bail!(
+            "Network link must be `switch` or of the form `udp:<ip>:<port>`; found `{}`",
+            &entry
+        ),
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::parse_envvar;
+    use super::{parse_envvar, parse_host_entry, parse_nameserver, parse_stack_link};
+    use std::net::SocketAddr;
+    use wasmer_wasix::runtime::StackLink;
 
     #[test]
     fn test_parse_envvar() {
@@ -94,4 +147,69 @@ mod tests {
             ("A".into(), "B=C=D".into())
         );
     }
+    #[test]
+    fn test_parse_host_entry() {
+        assert_eq!(
+            parse_host_entry("api.example.com").unwrap_err().to_string(),
+            "Host entry must be of the form `<name>=<ip>`; found `api.example.com`"
+        );
+        assert_eq!(
+            parse_host_entry("=10.0.0.1").unwrap_err().to_string(),
+            "Host entry must be of the form `<name>=<ip>`; found `=10.0.0.1`"
+        );
+        assert_eq!(
+            parse_host_entry("api.example.com=localhost")
+                .unwrap_err()
+                .to_string(),
+            "Host entry has an invalid IP address `localhost`"
+        );
+        assert_eq!(
+            parse_host_entry(" api.example.com=10.0.0.1 ").unwrap(),
+            ("api.example.com".into(), "10.0.0.1".parse().unwrap())
+        );
+        assert_eq!(
+            parse_host_entry("api.example.com=::1").unwrap(),
+            ("api.example.com".into(), "::1".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_parse_nameserver() {
+        assert_eq!(
+            parse_nameserver("10.0.0.53").unwrap(),
+            "10.0.0.53:53".parse::<SocketAddr>().unwrap()
+        );
+        assert_eq!(
+            parse_nameserver("10.0.0.53:5353").unwrap(),
+            "10.0.0.53:5353".parse::<SocketAddr>().unwrap()
+        );
+        assert_eq!(
+            parse_nameserver("::1").unwrap(),
+            "[::1]:53".parse::<SocketAddr>().unwrap()
+        );
+        assert_eq!(
+            parse_nameserver("[::1]:5353").unwrap(),
+            "[::1]:5353".parse::<SocketAddr>().unwrap()
+        );
+        assert_eq!(
+            parse_nameserver("dns.example.com").unwrap_err().to_string(),
+            "Name server must be of the form `<ip>` or `<ip>:<port>`; found `dns.example.com`"
+        );
+    }
+
+    #[test]
+    fn test_parse_stack_link() {
+        assert!(matches!(
+            parse_stack_link("switch").unwrap(),
+            StackLink::Switch(_)
+        ));
+        assert!(matches!(
+            parse_stack_link("udp:10.0.0.1:4789").unwrap(),
+            StackLink::UdpTunnel(peer) if peer == "10.0.0.1:4789".parse::<SocketAddr>().unwrap()
+        ));
+        assert_eq!(
+            parse_stack_link("udp:10.0.0.1").unwrap_err().to_string(),
+            "Network link must be `switch` or of the form `udp:<ip>:<port>`; found `udp:10.0.0.1`"
+        );
+    }
 }
@@ -0,0 +1,397 @@
+//! Exporting the tree of a [`FileSystem`] into a tar archive, and importing
+//! an archive back into a [`FileSystem`] (e.g. a [`crate::mem_fs`]).
+//!
+//! Directories, file contents, permissions, ownership and modification times
+//! are kept, along with symbolic links when the file system supports them.
+//! Hard links found in an archive are restored, but the metadata of a
+//! [`FileSystem`] doesn't tell which entries share their contents, so every
+//! hard link is exported as a file of its own. Other kinds of entries
+//! (devices, sockets, fifos, ...) are skipped.
+
+use std::{
+    io::{self, Read, Write},
+    path::{Component, Path, PathBuf},
+    time::{Duration, UNIX_EPOCH},
+};
+
+use tar::{Archive, Builder, EntryType, Header};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::{ops, FileSystem, FsError, Metadata, Result, VirtualFile};
+
+/// The size of the chunks file contents are copied in.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Writes the tree found at `root` in `fs` as a tar archive into `writer`,
+/// the paths in the archive are relative to `root`.
+///
+/// The writer is returned once the archive is complete.
+pub async fn export_tar<F, W>(fs: &F, root: &Path, writer: W) -> Result<W>
+where
+    F: FileSystem + ?Sized,
+    W: Write,
+{
+    let mut builder = Builder::new(writer);
+    let mut pending = vec![root.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        let mut entries = fs.read_dir(&dir)?.collect::<Result<Vec<_>>>()?;
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+        for entry in entries {
+            let path = entry.path;
+            let name = path.strip_prefix(root).unwrap_or(&path);
+            let mut metadata = fs.symlink_metadata(&path)?;
+
+            if metadata.ft.is_symlink() {
+                match fs.read_link(&path) {
+                    Ok(target) => {
+                        let mut header = header_for(&metadata, EntryType::Symlink);
+                        builder.append_link(&mut header, name, target)?;
+                        continue;
+                    }
+                    // File systems without symlinks still report what the link
+                    // points to.
+                    Err(FsError::Unsupported) => metadata = fs.metadata(&path)?,
+                    Err(e) => return Err(e),
+                }
+            }
+
+            if metadata.is_dir() {
+                let mut header = header_for(&metadata, EntryType::Directory);
+                builder.append_data(&mut header, name, io::empty())?;
+                pending.push(path);
+            } else if metadata.is_file() {
+                let mut file = fs.new_open_options().read(true).open(&path)?;
+
+                // The builder only writes the header (and any long name
+                // extension), the contents are then streamed in after it
+                // rather than read into memory first.
+                let mut header = header_for(&metadata, EntryType::Regular);
+                header.set_size(metadata.len);
+                builder.append_data(&mut header, name, io::empty())?;
+                write_contents(file.as_mut(), builder.get_mut(), metadata.len).await?;
+            } else {
+                tracing::debug!(
+                    path=%path.display(),
+                    file_type=?metadata.ft,
+                    "Skipping an entry that can't be archived",
+                );
+            }
+        }
+    }
+
+    builder.into_inner().map_err(Into::into)
+}
+
+/// Extracts the tar archive read from `reader` into `fs`, below `root`.
+///
+/// Entries whose path would end up outside of `root` are rejected, as are
+/// entries that would be written through a symbolic link.
+pub async fn import_tar<F, R>(reader: R, fs: &F, root: &Path) -> Result<()>
+where
+    F: FileSystem + ?Sized,
+    R: Read,
+{
+    let mut archive = Archive::new(reader);
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let relative = relative_path(&entry.path()?)?;
+        let path = root.join(&relative);
+        let entry_type = entry.header().entry_type();
+        let mode = entry.header().mode().ok();
+        let modified = entry.header().mtime().ok();
+
+        // A link that is replaced by the entry is fine, writing through one
+        // is not.
+        let replace_link = entry_type != EntryType::Directory;
+        ensure_no_symlinks(fs, root, &relative, replace_link)?;
+
+        if let Some(parent) = path.parent() {
+            ops::create_dir_all(fs, parent)?;
+        }
+
+        match entry_type {
+            EntryType::Directory => {
+                ops::create_dir_all(fs, &path)?;
+            }
+            EntryType::Regular | EntryType::Continuous => {
+                let mut file = fs
+                    .new_open_options()
+                    .create(true)
+                    .write(true)
+                    .truncate(true)
+                    .open(&path)?;
+
+                let mut buffer = vec![0; CHUNK_SIZE];
+                loop {
+                    let read = entry.read(&mut buffer)?;
+                    if read == 0 {
+                        break;
+                    }
+                    file.write_all(&buffer[..read]).await?;
+                }
+                file.flush().await?;
+            }
+            EntryType::Symlink => {
+                let target = entry.link_name()?.ok_or(FsError::InvalidData)?;
+                fs.symlink(&target, &path)?;
+                // The permissions of a symlink are meaningless.
+                continue;
+            }
+            EntryType::Link => {
+                let original = entry.link_name()?.ok_or(FsError::InvalidData)?;
+                let original = relative_path(&original)?;
+                ensure_no_symlinks(fs, root, &original, false)?;
+                fs.hard_link(&root.join(original), &path)?;
+                // The permissions are the ones of the original.
+                continue;
+            }
+            other => {
+                tracing::debug!(
+                    path=%path.display(),
+                    entry_type=?other,
+                    "Skipping an unsupported tar entry",
+                );
+                continue;
+            }
+        }
+
+        if let Some(mode) = mode {
+            match fs.set_permissions(&path, mode) {
+                Ok(()) | Err(FsError::Unsupported) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        if let Some(modified) = modified {
+            let modified = UNIX_EPOCH + Duration::from_secs(modified);
+            match fs.set_times(&path, None, Some(modified)) {
+                Ok(()) | Err(FsError::Unsupported) => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Copies `len` bytes of `file` into `writer` followed by the padding of the
+/// entry. A file that changed size since its header was written is cut or
+/// filled with zeros, so the archive stays readable.
+async fn write_contents<W>(file: &mut dyn VirtualFile, writer: &mut W, len: u64) -> Result<()>
+where
+    W: Write,
+{
+    let mut buffer = vec![0; CHUNK_SIZE];
+    let mut remaining = len;
+
+    while remaining > 0 {
+        let wanted = remaining.min(CHUNK_SIZE as u64) as usize;
+        let mut read = file.read(&mut buffer[..wanted]).await?;
+        if read == 0 {
+            buffer[..wanted].fill(0);
+            read = wanted;
+        }
+        writer.write_all(&buffer[..read])?;
+        remaining -= read as u64;
+    }
+
+    let padding = (512 - len % 512) % 512;
+    writer.write_all(&[0; 512][..padding as usize])?;
+    Ok(())
+}
+
+/// Makes sure that none of the components of `relative` below `root` is a
+/// symbolic link, otherwise an archive could first add a link pointing
+/// anywhere and then write entries through it.
+///
+/// With `replace_last`, a link in place of the last component is removed so
+/// the entry can take its place, like `tar` does.
+fn ensure_no_symlinks<F>(fs: &F, root: &Path, relative: &Path, replace_last: bool) -> Result<()>
+where
+    F: FileSystem + ?Sized,
+{
+    let mut path = root.to_path_buf();
+    let mut components = relative.components().peekable();
+
+    while let Some(component) = components.next() {
+        path.push(component);
+        match fs.symlink_metadata(&path) {
+            Ok(metadata) if metadata.ft.is_symlink() => {
+                if replace_last && components.peek().is_none() {
+                    fs.remove_file(&path)?;
+                } else {
+                    return Err(FsError::InvalidInput);
+                }
+            }
+            Ok(_) => {}
+            // Nothing below a missing entry exists either
+            Err(_) => break,
+        }
+    }
+
+    Ok(())
+}
+
+fn header_for(metadata: &Metadata, entry_type: EntryType) -> Header {
+    let mut header = Header::new_gnu();
+    header.set_entry_type(entry_type);
+    header.set_mode(metadata.mode);
+    header.set_uid(metadata.uid.into());
+    header.set_gid(metadata.gid.into());
+    header.set_mtime(unix_seconds(metadata.modified));
+    header.set_size(0);
+    header
+}
+
+/// Some file systems report their times in seconds and others in
+/// nanoseconds, while tar only knows about seconds.
+fn unix_seconds(time: u64) -> u64 {
+    // Anything this large would be in seconds beyond the year 5000.
+    if time > 100_000_000_000 {
+        time / 1_000_000_000
+    } else {
+        time
+    }
+}
+
+/// Turns the path of an archive entry into a path relative to the root it
+/// is extracted to, refusing anything that could escape it.
+fn relative_path(path: &Path) -> Result<PathBuf> {
+    let mut relative = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::RootDir | Component::CurDir => {}
+            Component::Normal(name) => relative.push(name),
+            Component::ParentDir | Component::Prefix(_) => return Err(FsError::InvalidInput),
+        }
+    }
+    Ok(relative)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem_fs;
+
+    #[tokio::test]
+    async fn round_trip_through_a_tar() {
+        let fs = mem_fs::FileSystem::default();
+        ops::create_dir_all(&fs, "/out/bin").unwrap();
+        ops::create_dir_all(&fs, "/out/empty").unwrap();
+        ops::write(&fs, "/out/bin/tool", b"#!/bin/sh")
+            .await
+            .unwrap();
+        ops::write(&fs, "/out/readme.md", b"# Hello").await.unwrap();
+        fs.set_permissions(Path::new("/out/bin/tool"), 0o755)
+            .unwrap();
+        fs.symlink(Path::new("bin/tool"), Path::new("/out/tool"))
+            .unwrap();
+        let modified = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        fs.set_times(Path::new("/out/readme.md"), None, Some(modified))
+            .unwrap();
+
+        let archive = export_tar(&fs, Path::new("/out"), Vec::new())
+            .await
+            .unwrap();
+
+        let mut names = Archive::new(archive.as_slice())
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().display().to_string())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(
+            names,
+            ["bin", "bin/tool", "empty", "readme.md", "tool"],
+            "the paths are relative to the exported root",
+        );
+
+        let imported = mem_fs::FileSystem::default();
+        import_tar(archive.as_slice(), &imported, Path::new("/restored"))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            ops::read_to_string(&imported, "/restored/bin/tool")
+                .await
+                .unwrap(),
+            "#!/bin/sh",
+        );
+        assert_eq!(
+            ops::read_to_string(&imported, "/restored/readme.md")
+                .await
+                .unwrap(),
+            "# Hello",
+        );
+        assert!(ops::is_dir(&imported, "/restored/empty"));
+        assert_eq!(
+            imported
+                .metadata(Path::new("/restored/bin/tool"))
+                .unwrap()
+                .mode(),
+            0o755,
+        );
+        assert_eq!(
+            imported.read_link(Path::new("/restored/tool")).unwrap(),
+            Path::new("bin/tool"),
+        );
+        assert_eq!(
+            imported
+                .metadata(Path::new("/restored/readme.md"))
+                .unwrap()
+                .modified(),
+            1_000_000,
+            "the modification times are restored",
+        );
+    }
+
+    #[tokio::test]
+    async fn entries_cant_escape_the_root() {
+        let mut builder = Builder::new(Vec::new());
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Regular);
+        header.set_size(4);
+        header.set_mode(0o644);
+        // `append_data` refuses `..`, so the path is written by hand.
+        header.as_gnu_mut().unwrap().name[..9].copy_from_slice(b"../escape");
+        header.set_cksum();
+        builder.append(&header, &b"nope"[..]).unwrap();
+        let archive = builder.into_inner().unwrap();
+
+        let fs = mem_fs::FileSystem::default();
+        ops::create_dir_all(&fs, "/root").unwrap();
+        assert_eq!(
+            import_tar(archive.as_slice(), &fs, Path::new("/root")).await,
+            Err(FsError::InvalidInput),
+        );
+        assert!(!ops::exists(&fs, "/escape"));
+    }
+
+    #[tokio::test]
+    async fn entries_cant_be_written_through_symlinks() {
+        let mut builder = Builder::new(Vec::new());
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Symlink);
+        header.set_size(0);
+        builder.append_link(&mut header, "link", "/etc").unwrap();
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Regular);
+        header.set_size(4);
+        header.set_mode(0o644);
+        builder
+            .append_data(&mut header, "link/passwd", &b"nope"[..])
+            .unwrap();
+        let archive = builder.into_inner().unwrap();
+
+        let fs = mem_fs::FileSystem::default();
+        ops::create_dir_all(&fs, "/root").unwrap();
+        ops::create_dir_all(&fs, "/etc").unwrap();
+        assert_eq!(
+            import_tar(archive.as_slice(), &fs, Path::new("/root")).await,
+            Err(FsError::InvalidInput),
+        );
+        assert!(!ops::exists(&fs, "/etc/passwd"));
+    }
+}
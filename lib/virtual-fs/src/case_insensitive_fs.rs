@@ -0,0 +1,252 @@
+//! A [`FileSystem`] wrapper that makes path lookups case-insensitive and
+//! insensitive to the Unicode normalization of names, like programs ported
+//! from Windows or macOS expect.
+
+use std::time::SystemTime;
+use std::{ffi::OsStr, path::Component};
+
+use unicode_normalization::UnicodeNormalization;
+
+use super::*;
+
+/// Wraps a [`FileSystem`] and resolves every path by matching its
+/// components against the existing entries after case folding and NFC
+/// normalisation, `/Assets/HERO.png` finds `/assets/hero.png`.
+///
+/// Entries keep the names they were created with and [`FileSystem::read_dir`]
+/// returns them unchanged. When more than one entry matches a component, the
+/// one with the exact name wins. The targets of symbolic links are stored as
+/// they are and resolved by the inner file system.
+#[derive(Debug, Clone)]
+pub struct CaseInsensitiveFileSystem<F> {
+    inner: F,
+}
+
+impl<F> CaseInsensitiveFileSystem<F>
+where
+    F: FileSystem,
+{
+    pub fn new(inner: F) -> Self {
+        Self { inner }
+    }
+
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> F {
+        self.inner
+    }
+
+    /// Turns `path` into the path of the matching entry in the inner file
+    /// system. The components that don't match anything are kept as they
+    /// are, so entries can be created.
+    fn resolve(&self, path: &Path) -> PathBuf {
+        let mut resolved = PathBuf::new();
+        let mut missing = false;
+
+        for component in path.components() {
+            let name = match component {
+                Component::Normal(name) if !missing => name,
+                other => {
+                    resolved.push(other.as_os_str());
+                    continue;
+                }
+            };
+
+            let exact = resolved.join(name);
+            if self.inner.symlink_metadata(&exact).is_ok() {
+                resolved = exact;
+            } else if let Some(found) = self.find_entry(&resolved, name) {
+                resolved = found;
+            } else {
+                missing = true;
+                resolved = exact;
+            }
+        }
+
+        resolved
+    }
+
+    /// Looks for an entry of `dir` whose name matches `name`.
+    fn find_entry(&self, dir: &Path, name: &OsStr) -> Option<PathBuf> {
+        let wanted = fold(name)?;
+        let dir = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        };
+
+        self.inner
+            .read_dir(dir)
+            .ok()?
+            .filter_map(|entry| entry.ok())
+            .find(|entry| entry.path.file_name().and_then(fold).as_ref() == Some(&wanted))
+            .map(|entry| entry.path)
+    }
+}
+
+/// The form names are compared in, `None` for names that aren't valid UTF-8
+/// and can only match exactly.
+fn fold(name: &OsStr) -> Option<String> {
+    let name = name.to_str()?;
+    Some(name.nfd().flat_map(char::to_lowercase).nfc().collect())
+}
+
+impl<F> FileSystem for CaseInsensitiveFileSystem<F>
+where
+    F: FileSystem,
+{
+    fn read_dir(&self, path: &Path) -> Result<ReadDir> {
+        self.inner.read_dir(&self.resolve(path))
+    }
+
+    fn create_dir(&self, path: &Path) -> Result<()> {
+        self.inner.create_dir(&self.resolve(path))
+    }
+
+    fn remove_dir(&self, path: &Path) -> Result<()> {
+        self.inner.remove_dir(&self.resolve(path))
+    }
+
+    fn rename<'a>(&'a self, from: &'a Path, to: &'a Path) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let from = self.resolve(from);
+            let mut resolved_to = self.resolve(to);
+            // Renaming an entry to another case of its own name changes it
+            if resolved_to == from {
+                if let Some(name) = to.file_name() {
+                    resolved_to.set_file_name(name);
+                }
+            }
+            self.inner.rename(&from, &resolved_to).await
+        })
+    }
+
+    fn metadata(&self, path: &Path) -> Result<Metadata> {
+        self.inner.metadata(&self.resolve(path))
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> Result<Metadata> {
+        self.inner.symlink_metadata(&self.resolve(path))
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<()> {
+        self.inner.remove_file(&self.resolve(path))
+    }
+
+    fn new_open_options(&self) -> OpenOptions {
+        OpenOptions::new(self)
+    }
+
+    fn symlink(&self, target: &Path, link: &Path) -> Result<()> {
+        self.inner.symlink(target, &self.resolve(link))
+    }
+
+    fn hard_link(&self, original: &Path, link: &Path) -> Result<()> {
+        self.inner
+            .hard_link(&self.resolve(original), &self.resolve(link))
+    }
+
+    fn read_link(&self, path: &Path) -> Result<PathBuf> {
+        self.inner.read_link(&self.resolve(path))
+    }
+
+    fn set_permissions(&self, path: &Path, mode: u32) -> Result<()> {
+        self.inner.set_permissions(&self.resolve(path), mode)
+    }
+
+    fn set_times(
+        &self,
+        path: &Path,
+        accessed: Option<SystemTime>,
+        modified: Option<SystemTime>,
+    ) -> Result<()> {
+        self.inner
+            .set_times(&self.resolve(path), accessed, modified)
+    }
+
+    fn watch(&self, path: &Path, recursive: bool) -> Result<FsWatcher> {
+        self.inner.watch(&self.resolve(path), recursive)
+    }
+}
+
+impl<F> FileOpener for CaseInsensitiveFileSystem<F>
+where
+    F: FileSystem,
+{
+    fn open(
+        &self,
+        path: &Path,
+        conf: &OpenOptionsConfig,
+    ) -> Result<Box<dyn VirtualFile + Send + Sync + 'static>> {
+        self.inner
+            .new_open_options()
+            .options(conf.clone())
+            .open(self.resolve(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn lookups_ignore_case_and_normalization() {
+        let fs = CaseInsensitiveFileSystem::new(mem_fs::FileSystem::default());
+        ops::create_dir_all(&fs, "/Assets/Textures").unwrap();
+        ops::write(&fs, "/ASSETS/textures/Hero.PNG", b"hero")
+            .await
+            .unwrap();
+        // "Café" in its composed form
+        ops::write(&fs, "/Caf\u{e9}.txt", b"coffee").await.unwrap();
+
+        assert_eq!(
+            ops::read(&fs, "/assets/TEXTURES/hero.png").await.unwrap(),
+            b"hero"
+        );
+        let mut file = fs
+            .new_open_options()
+            .read(true)
+            .open("/CAFE\u{301}.TXT")
+            .unwrap();
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).await.unwrap();
+        assert_eq!(contents, "coffee", "decomposed names match");
+
+        let names: Vec<_> = fs
+            .read_dir(Path::new("/assets/textures"))
+            .unwrap()
+            .map(|entry| entry.unwrap().path)
+            .collect();
+        assert_eq!(
+            names,
+            [PathBuf::from("/Assets/Textures/Hero.PNG")],
+            "the original names are kept"
+        );
+        assert_eq!(
+            fs.create_dir(Path::new("/assets")),
+            Err(FsError::AlreadyExists)
+        );
+    }
+
+    #[tokio::test]
+    async fn renaming_can_change_the_case() {
+        let fs = CaseInsensitiveFileSystem::new(mem_fs::FileSystem::default());
+        ops::write(&fs, "/readme.txt", b"hello").await.unwrap();
+
+        fs.rename(Path::new("/README.txt"), Path::new("/README.TXT"))
+            .await
+            .unwrap();
+
+        let names: Vec<_> = fs
+            .inner()
+            .read_dir(Path::new("/"))
+            .unwrap()
+            .map(|entry| entry.unwrap().path)
+            .collect();
+        assert_eq!(names, [PathBuf::from("/README.TXT")]);
+    }
+}
@@ -0,0 +1,551 @@
+//! A writable layer that lives in a directory on the host, meant to be used
+//! as the primary of an [`OverlayFileSystem`](crate::OverlayFileSystem) so
+//! that the changes made on top of read-only file systems survive the
+//! process and can be reopened later.
+//!
+//! The directory of a layer is laid out as follows:
+//!
+//! - `data/` holds the files and directories that were created or copied up
+//!   into the layer,
+//! - `whiteouts` is an append-only journal of the whiteouts, one entry per
+//!   line where `+/path` hides `/path` from the lower layers and `-/path`
+//!   reveals it again.
+//!
+//! Whiteouts are never written into `data/` as marker files, the journal is
+//! replayed (and compacted) when the layer is opened.
+//!
+//! Only this directory layout is supported, a layer can't be kept in a
+//! single archive file. The `archive` module (behind the `archive` feature)
+//! can pack the `data/` directory of a layer into a tar file and unpack it
+//! again, but the layer itself always works on the directory. Symbolic
+//! links can't be created in a layer either: `symlink` and `read_link`
+//! return [`FsError::Unsupported`], while hard links and permissions are
+//! passed through to the host.
+
+use std::time::SystemTime;
+use std::{
+    collections::HashSet,
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+};
+
+use futures::future::BoxFuture;
+
+use crate::{
+    host_fs, ops, watch::WatchRegistry, DirEntry, FileOpener, FileSystem, FileType, FsError,
+    FsEvent, FsWatcher, Metadata, NullFile, OpenOptions, OpenOptionsConfig, ReadDir, Result,
+    VirtualFile, DEFAULT_FILE_MODE,
+};
+
+/// The directory, relative to the root of the layer, holding its contents.
+const DATA_DIR: &str = "data";
+/// The file, relative to the root of the layer, holding the whiteout journal.
+const JOURNAL_FILE: &str = "whiteouts";
+
+/// A [`FileSystem`] that persists its contents and whiteouts in a host
+/// directory, see the [module documentation](self) for its layout.
+///
+/// Symbolic links are not supported, as they would be resolved by the host
+/// and could point outside of the layer.
+///
+/// ```no_run
+/// # use virtual_fs::{DiskLayerFileSystem, OverlayFileSystem, mem_fs};
+/// # fn example() -> virtual_fs::Result<()> {
+/// let upper = DiskLayerFileSystem::open("/var/cache/my-package")?;
+/// let fs = OverlayFileSystem::new(upper, [mem_fs::FileSystem::default()]);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct DiskLayerFileSystem {
+    inner: Arc<DiskLayerInner>,
+}
+
+#[derive(Debug)]
+struct DiskLayerInner {
+    root: PathBuf,
+    data: PathBuf,
+    host: host_fs::FileSystem,
+    whiteouts: RwLock<HashSet<PathBuf>>,
+    journal: Mutex<fs::File>,
+    /// The watchers of the whiteouts, the host reports everything else.
+    watchers: WatchRegistry,
+}
+
+impl DiskLayerFileSystem {
+    /// Opens the layer stored in `root`, creating it when it doesn't exist
+    /// yet.
+    ///
+    /// This must be called from within a tokio runtime, which is used to
+    /// access the host files.
+    pub fn open(root: impl Into<PathBuf>) -> Result<Self> {
+        let root = root.into();
+        let data = root.join(DATA_DIR);
+        fs::create_dir_all(&data)?;
+
+        let journal_path = root.join(JOURNAL_FILE);
+        let whiteouts = replay_journal(&journal_path)?;
+
+        // Compact the journal so it only holds the whiteouts that are still
+        // in place, the new journal atomically replaces the old one.
+        let compacted_path = root.join(format!("{}.tmp", JOURNAL_FILE));
+        {
+            let mut whiteouts = whiteouts.iter().collect::<Vec<_>>();
+            whiteouts.sort();
+
+            let mut compacted = fs::File::create(&compacted_path)?;
+            for path in whiteouts {
+                writeln!(compacted, "+{}", path.display())?;
+            }
+            compacted.sync_all()?;
+        }
+        fs::rename(&compacted_path, &journal_path)?;
+
+        let journal = fs::OpenOptions::new().append(true).open(&journal_path)?;
+
+        Ok(Self {
+            inner: Arc::new(DiskLayerInner {
+                root,
+                data,
+                host: host_fs::FileSystem::default(),
+                whiteouts: RwLock::new(whiteouts),
+                journal: Mutex::new(journal),
+                watchers: WatchRegistry::default(),
+            }),
+        })
+    }
+
+    /// The host directory that holds the layer.
+    pub fn root(&self) -> &Path {
+        &self.inner.root
+    }
+
+    /// The paths that are currently hidden from the lower layers.
+    pub fn whiteouts(&self) -> Result<Vec<PathBuf>> {
+        let whiteouts = self.inner.whiteouts.read().map_err(|_| FsError::Lock)?;
+        let mut whiteouts = whiteouts.iter().cloned().collect::<Vec<_>>();
+        whiteouts.sort();
+        Ok(whiteouts)
+    }
+
+    /// Maps a path of the layer to its location on the host.
+    fn host_path(&self, path: &Path) -> Result<PathBuf> {
+        let path = normalize(path)?;
+        Ok(self
+            .inner
+            .data
+            .join(path.strip_prefix("/").unwrap_or(&path)))
+    }
+
+    /// Returns the path hidden by `path` when it is a whiteout.
+    fn white_out(&self, path: &Path) -> Result<Option<PathBuf>> {
+        match ops::is_white_out(path) {
+            Some(hidden) => Ok(Some(normalize(&hidden)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Appends an entry to the whiteout journal, the caller must be holding
+    /// the write lock of the whiteouts.
+    fn record(&self, hidden: bool, path: &Path) -> Result<()> {
+        let path = path
+            .to_str()
+            .filter(|path| !path.contains('\n'))
+            .ok_or(FsError::InvalidInput)?;
+        let marker = if hidden { '+' } else { '-' };
+
+        let mut journal = self.inner.journal.lock().map_err(|_| FsError::Lock)?;
+        writeln!(journal, "{}{}", marker, path)?;
+        journal.sync_data()?;
+
+        if let Some(white_out) = ops::white_out_path(path) {
+            self.inner.watchers.notify(if hidden {
+                FsEvent::Create(white_out)
+            } else {
+                FsEvent::Remove(white_out)
+            });
+        }
+        Ok(())
+    }
+}
+
+impl FileSystem for DiskLayerFileSystem {
+    fn read_dir(&self, path: &Path) -> Result<ReadDir> {
+        let path = normalize(path)?;
+        let host_path = self.host_path(&path)?;
+
+        let mut entries = Vec::new();
+        for entry in self.inner.host.read_dir(&host_path)? {
+            let entry = entry?;
+            entries.push(DirEntry {
+                path: path.join(entry.file_name()),
+                metadata: entry.metadata,
+            });
+        }
+
+        // The whiteouts aren't on disk, so they are added back as entries
+        // for the overlay to find them.
+        let whiteouts = self.inner.whiteouts.read().map_err(|_| FsError::Lock)?;
+        for hidden in whiteouts.iter() {
+            if hidden.parent() == Some(path.as_path()) {
+                if let Some(white_out) = ops::white_out_path(hidden) {
+                    entries.push(DirEntry {
+                        path: white_out,
+                        metadata: Ok(white_out_metadata()),
+                    });
+                }
+            }
+        }
+
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(ReadDir::new(entries))
+    }
+
+    fn create_dir(&self, path: &Path) -> Result<()> {
+        if ops::is_white_out(path).is_some() {
+            return Err(FsError::InvalidInput);
+        }
+        self.inner.host.create_dir(&self.host_path(path)?)
+    }
+
+    fn remove_dir(&self, path: &Path) -> Result<()> {
+        if ops::is_white_out(path).is_some() {
+            return Err(FsError::EntryNotFound);
+        }
+
+        let path = normalize(path)?;
+        if path.parent().is_none() {
+            return Err(FsError::BaseNotDirectory);
+        }
+
+        // The whiteouts count as entries of their directory.
+        let whiteouts = self.inner.whiteouts.read().map_err(|_| FsError::Lock)?;
+        if whiteouts
+            .iter()
+            .any(|hidden| hidden.parent() == Some(path.as_path()))
+        {
+            return Err(FsError::DirectoryNotEmpty);
+        }
+
+        self.inner.host.remove_dir(&self.host_path(&path)?)
+    }
+
+    fn rename<'a>(&'a self, from: &'a Path, to: &'a Path) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            if ops::is_white_out(from).is_some() || ops::is_white_out(to).is_some() {
+                return Err(FsError::InvalidInput);
+            }
+
+            let from = normalize(from)?;
+            let to = normalize(to)?;
+            let host_from = self.host_path(&from)?;
+            let host_to = self.host_path(&to)?;
+            self.inner.host.rename(&host_from, &host_to).await?;
+
+            // Whiteouts within a renamed directory move along with it.
+            let mut whiteouts = self.inner.whiteouts.write().map_err(|_| FsError::Lock)?;
+            let moved = whiteouts
+                .iter()
+                .filter(|hidden| hidden.starts_with(&from))
+                .cloned()
+                .collect::<Vec<_>>();
+            for hidden in moved {
+                // SAFETY: the path was filtered on this prefix just above.
+                let renamed = to.join(hidden.strip_prefix(&from).unwrap());
+                self.record(false, &hidden)?;
+                self.record(true, &renamed)?;
+                whiteouts.remove(&hidden);
+                whiteouts.insert(renamed);
+            }
+
+            Ok(())
+        })
+    }
+
+    fn metadata(&self, path: &Path) -> Result<Metadata> {
+        if let Some(hidden) = self.white_out(path)? {
+            let whiteouts = self.inner.whiteouts.read().map_err(|_| FsError::Lock)?;
+            if whiteouts.contains(&hidden) {
+                return Ok(white_out_metadata());
+            }
+            return Err(FsError::EntryNotFound);
+        }
+        self.inner.host.metadata(&self.host_path(path)?)
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> Result<Metadata> {
+        if self.white_out(path)?.is_some() {
+            return self.metadata(path);
+        }
+        self.inner.host.symlink_metadata(&self.host_path(path)?)
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<()> {
+        if let Some(hidden) = self.white_out(path)? {
+            let mut whiteouts = self.inner.whiteouts.write().map_err(|_| FsError::Lock)?;
+            if !whiteouts.contains(&hidden) {
+                return Err(FsError::EntryNotFound);
+            }
+            self.record(false, &hidden)?;
+            whiteouts.remove(&hidden);
+            return Ok(());
+        }
+        self.inner.host.remove_file(&self.host_path(path)?)
+    }
+
+    fn new_open_options(&self) -> OpenOptions {
+        OpenOptions::new(self)
+    }
+
+    fn hard_link(&self, original: &Path, link: &Path) -> Result<()> {
+        if ops::is_white_out(original).is_some() || ops::is_white_out(link).is_some() {
+            return Err(FsError::InvalidInput);
+        }
+        self.inner
+            .host
+            .hard_link(&self.host_path(original)?, &self.host_path(link)?)
+    }
+
+    fn set_permissions(&self, path: &Path, mode: u32) -> Result<()> {
+        if ops::is_white_out(path).is_some() {
+            return Err(FsError::InvalidInput);
+        }
+        self.inner
+            .host
+            .set_permissions(&self.host_path(path)?, mode)
+    }
+
+    fn set_times(
+        &self,
+        path: &Path,
+        accessed: Option<SystemTime>,
+        modified: Option<SystemTime>,
+    ) -> Result<()> {
+        if ops::is_white_out(path).is_some() {
+            return Err(FsError::InvalidInput);
+        }
+        self.inner
+            .host
+            .set_times(&self.host_path(path)?, accessed, modified)
+    }
+
+    fn watch(&self, path: &Path, recursive: bool) -> Result<FsWatcher> {
+        let path = normalize(path)?;
+
+        // The host reports paths within the data directory, possibly after
+        // resolving the symlinks leading to it.
+        let data = self.inner.data.clone();
+        let canonical_data = fs::canonicalize(&data)?;
+        let host = self
+            .inner
+            .host
+            .watch(&self.host_path(&path)?, recursive)?
+            .map(move |event| {
+                event.map_paths(|path| {
+                    let relative = path
+                        .strip_prefix(&data)
+                        .or_else(|_| path.strip_prefix(&canonical_data))
+                        .ok()?;
+                    Some(Path::new("/").join(relative))
+                })
+            });
+
+        let whiteouts = self.inner.watchers.subscribe(&path, recursive);
+        Ok(FsWatcher::merge([host, whiteouts]))
+    }
+}
+
+impl FileOpener for DiskLayerFileSystem {
+    fn open(
+        &self,
+        path: &Path,
+        conf: &OpenOptionsConfig,
+    ) -> Result<Box<dyn VirtualFile + Send + Sync + 'static>> {
+        if let Some(hidden) = self.white_out(path)? {
+            let mut whiteouts = self.inner.whiteouts.write().map_err(|_| FsError::Lock)?;
+            if whiteouts.contains(&hidden) {
+                if conf.create_new() {
+                    return Err(FsError::AlreadyExists);
+                }
+            } else if conf.create() || conf.create_new() {
+                // Just like any other file, the parent must exist.
+                let parent = hidden.parent().ok_or(FsError::BaseNotDirectory)?;
+                if !self.host_path(parent)?.is_dir() {
+                    return Err(FsError::EntryNotFound);
+                }
+                self.record(true, &hidden)?;
+                whiteouts.insert(hidden);
+            } else {
+                return Err(FsError::EntryNotFound);
+            }
+
+            // Whiteouts are markers, their contents are never used.
+            return Ok(Box::<NullFile>::default());
+        }
+
+        self.inner
+            .host
+            .new_open_options()
+            .options(conf.clone())
+            .open(self.host_path(path)?)
+    }
+}
+
+/// Normalizes a path of the layer, `..` never goes above its root.
+fn normalize(path: &Path) -> Result<PathBuf> {
+    let mut normalized = PathBuf::from("/");
+    for component in path.components() {
+        match component {
+            Component::RootDir | Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            Component::Normal(name) => normalized.push(name),
+            Component::Prefix(_) => return Err(FsError::InvalidInput),
+        }
+    }
+    Ok(normalized)
+}
+
+/// Reads the whiteouts that are in place from a journal.
+fn replay_journal(path: &Path) -> Result<HashSet<PathBuf>> {
+    let mut whiteouts = HashSet::new();
+
+    let journal = match fs::File::open(path) {
+        Ok(journal) => journal,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(whiteouts),
+        Err(e) => return Err(e.into()),
+    };
+
+    for line in BufReader::new(journal).lines() {
+        let line = line?;
+        if let Some(path) = line.strip_prefix('+') {
+            whiteouts.insert(PathBuf::from(path));
+        } else if let Some(path) = line.strip_prefix('-') {
+            whiteouts.remove(Path::new(path));
+        } else if !line.is_empty() {
+            return Err(FsError::InvalidData);
+        }
+    }
+
+    Ok(whiteouts)
+}
+
+fn white_out_metadata() -> Metadata {
+    Metadata {
+        ft: FileType {
+            file: true,
+            ..Default::default()
+        },
+        accessed: 0,
+        created: 0,
+        modified: 0,
+        len: 0,
+        mode: DEFAULT_FILE_MODE,
+        uid: 0,
+        gid: 0,
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{mem_fs, OverlayFileSystem};
+
+    #[tokio::test]
+    async fn changes_survive_reopening() {
+        let temp = TempDir::new().unwrap();
+        let lower = mem_fs::FileSystem::default();
+        ops::create_dir_all(&lower, "/etc").unwrap();
+        ops::write(&lower, "/etc/hosts", b"127.0.0.1 localhost")
+            .await
+            .unwrap();
+        ops::write(&lower, "/etc/passwd", b"root:x:0:0")
+            .await
+            .unwrap();
+
+        {
+            let upper = DiskLayerFileSystem::open(temp.path()).unwrap();
+            let fs = OverlayFileSystem::new(upper, [lower.clone()]);
+
+            ops::write(&fs, "/etc/hosts", b"10.0.0.1 server")
+                .await
+                .unwrap();
+            fs.remove_file(Path::new("/etc/passwd")).unwrap();
+            ops::create_dir_all(&fs, "/var/cache").unwrap();
+            ops::write(&fs, "/var/cache/warm", b"ready").await.unwrap();
+        }
+
+        // The whiteout is in the journal rather than on disk
+        assert!(temp.path().join("data/etc/hosts").is_file());
+        assert!(!temp.path().join("data/etc/.wh.passwd").exists());
+
+        let upper = DiskLayerFileSystem::open(temp.path()).unwrap();
+        assert_eq!(
+            upper.whiteouts().unwrap(),
+            vec![PathBuf::from("/etc/passwd")]
+        );
+        let fs = OverlayFileSystem::new(upper, [lower]);
+
+        assert_eq!(
+            ops::read_to_string(&fs, "/etc/hosts").await.unwrap(),
+            "10.0.0.1 server",
+        );
+        assert_eq!(
+            ops::read_to_string(&fs, "/var/cache/warm").await.unwrap(),
+            "ready",
+        );
+        assert!(!ops::exists(&fs, "/etc/passwd"));
+        let entries = fs
+            .read_dir(Path::new("/etc"))
+            .unwrap()
+            .map(|entry| entry.unwrap().path)
+            .collect::<Vec<_>>();
+        assert_eq!(entries, vec![PathBuf::from("/etc/hosts")]);
+
+        // Recreating the file removes the whiteout again
+        ops::write(&fs, "/etc/passwd", b"user:x:1000:1000")
+            .await
+            .unwrap();
+        assert_eq!(
+            ops::read_to_string(&fs, "/etc/passwd").await.unwrap(),
+            "user:x:1000:1000",
+        );
+        assert!(fs.primary().whiteouts().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn journal_is_compacted() {
+        let temp = TempDir::new().unwrap();
+        {
+            let fs = DiskLayerFileSystem::open(temp.path()).unwrap();
+            ops::create_white_out(&fs, "/a").unwrap();
+            ops::create_white_out(&fs, "/b").unwrap();
+            ops::remove_white_out(&fs, "/a");
+        }
+
+        let journal = std::fs::read_to_string(temp.path().join(JOURNAL_FILE)).unwrap();
+        assert_eq!(journal, "+/a\n+/b\n-/a\n");
+
+        DiskLayerFileSystem::open(temp.path()).unwrap();
+        let journal = std::fs::read_to_string(temp.path().join(JOURNAL_FILE)).unwrap();
+        assert_eq!(journal, "+/b\n");
+    }
+
+    #[tokio::test]
+    async fn paths_never_escape_the_layer() {
+        let temp = TempDir::new().unwrap();
+        let fs = DiskLayerFileSystem::open(temp.path().join("layer")).unwrap();
+
+        ops::write(&fs, "/../../escaped.txt", b"nope")
+            .await
+            .unwrap();
+        assert!(!temp.path().join("escaped.txt").exists());
+        assert!(temp.path().join("layer/data/escaped.txt").is_file());
+    }
+}
@@ -1,6 +1,6 @@
 use crate::{
-    DirEntry, FileType, FsError, Metadata, OpenOptions, OpenOptionsConfig, ReadDir, Result,
-    VirtualFile,
+    DirEntry, FileLockKind, FileType, FsError, FsEvent, FsWatcher, Metadata, OpenOptions,
+    OpenOptionsConfig, ReadDir, Result, VirtualFile,
 };
 use bytes::{Buf, Bytes};
 use futures::future::BoxFuture;
@@ -144,6 +144,153 @@ impl crate::FileSystem for FileSystem {
             .and_then(TryInto::try_into)
             .map_err(Into::into)
     }
+
+    fn symlink_metadata(&self, path: &Path) -> Result<Metadata> {
+        fs::symlink_metadata(path)
+            .and_then(TryInto::try_into)
+            .map_err(Into::into)
+    }
+
+    fn symlink(&self, target: &Path, link: &Path) -> Result<()> {
+        if link.parent().is_none() {
+            return Err(FsError::BaseNotDirectory);
+        }
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(target, link).map_err(Into::into)
+        }
+        #[cfg(windows)]
+        {
+            // Windows needs to know up front what kind of entry the link
+            // points to, relative targets are resolved from the link.
+            let resolved = link.parent().unwrap().join(target);
+            if resolved.is_dir() {
+                std::os::windows::fs::symlink_dir(target, link).map_err(Into::into)
+            } else {
+                std::os::windows::fs::symlink_file(target, link).map_err(Into::into)
+            }
+        }
+        #[cfg(not(any(unix, windows)))]
+        {
+            let _ = target;
+            Err(FsError::Unsupported)
+        }
+    }
+
+    fn hard_link(&self, original: &Path, link: &Path) -> Result<()> {
+        if link.parent().is_none() {
+            return Err(FsError::BaseNotDirectory);
+        }
+        fs::hard_link(original, link).map_err(Into::into)
+    }
+
+    fn read_link(&self, path: &Path) -> Result<PathBuf> {
+        fs::read_link(path).map_err(Into::into)
+    }
+
+    fn set_permissions(&self, path: &Path, mode: u32) -> Result<()> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o7777)).map_err(Into::into)
+        }
+        #[cfg(not(unix))]
+        {
+            // Only the read-only flag can be changed on other platforms.
+            let mut permissions = fs::metadata(path)?.permissions();
+            permissions.set_readonly(mode & 0o222 == 0);
+            fs::set_permissions(path, permissions).map_err(Into::into)
+        }
+    }
+
+    fn set_times(
+        &self,
+        path: &Path,
+        accessed: Option<SystemTime>,
+        modified: Option<SystemTime>,
+    ) -> Result<()> {
+        use filetime::{set_file_atime, set_file_mtime, FileTime};
+
+        if let Some(accessed) = accessed {
+            set_file_atime(path, FileTime::from_system_time(accessed))?;
+        }
+        if let Some(modified) = modified {
+            set_file_mtime(path, FileTime::from_system_time(modified))?;
+        }
+        Ok(())
+    }
+
+    fn watch(&self, path: &Path, recursive: bool) -> Result<FsWatcher> {
+        use notify::Watcher;
+
+        let (tx, watcher) = FsWatcher::channel();
+        let mut host_watcher =
+            notify::recommended_watcher(move |res: notify::Result<notify::Event>| match res {
+                Ok(event) => {
+                    for event in host_events(event) {
+                        let _ = tx.send(event);
+                    }
+                }
+                Err(e) => tracing::debug!(error = %e, "Unable to watch the host file system"),
+            })
+            .map_err(notify_error)?;
+
+        let mode = if recursive {
+            notify::RecursiveMode::Recursive
+        } else {
+            notify::RecursiveMode::NonRecursive
+        };
+        host_watcher.watch(path, mode).map_err(notify_error)?;
+
+        // The host stops watching when its watcher is dropped.
+        Ok(watcher.with_guard(std::sync::Mutex::new(host_watcher)))
+    }
+}
+
+/// Turns the notifications of the host into [`FsEvent`]s.
+fn host_events(event: notify::Event) -> Vec<FsEvent> {
+    use notify::event::{ModifyKind, RenameMode};
+    use notify::EventKind;
+
+    let mut paths = event.paths.into_iter();
+    match event.kind {
+        EventKind::Create(_) => paths.map(FsEvent::Create).collect(),
+        EventKind::Remove(_) => paths.map(FsEvent::Remove).collect(),
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {
+            match (paths.next(), paths.next()) {
+                (Some(from), Some(to)) => vec![FsEvent::Rename { from, to }],
+                _ => Vec::new(),
+            }
+        }
+        EventKind::Modify(ModifyKind::Name(RenameMode::From)) => {
+            paths.map(FsEvent::Remove).collect()
+        }
+        EventKind::Modify(ModifyKind::Name(RenameMode::To)) => paths.map(FsEvent::Create).collect(),
+        // Not all hosts say which side of the rename a path is on.
+        EventKind::Modify(ModifyKind::Name(_)) => paths
+            .map(|path| {
+                if path.exists() {
+                    FsEvent::Create(path)
+                } else {
+                    FsEvent::Remove(path)
+                }
+            })
+            .collect(),
+        EventKind::Modify(ModifyKind::Metadata(_)) => Vec::new(),
+        EventKind::Modify(_) => paths.map(FsEvent::Modify).collect(),
+        EventKind::Access(_) | EventKind::Any | EventKind::Other => Vec::new(),
+    }
+}
+
+fn notify_error(error: notify::Error) -> FsError {
+    match error.kind {
+        notify::ErrorKind::Io(e) => e.into(),
+        notify::ErrorKind::PathNotFound | notify::ErrorKind::WatchNotFound => {
+            FsError::EntryNotFound
+        }
+        notify::ErrorKind::MaxFilesWatch => FsError::StorageFull,
+        _ => FsError::UnknownError,
+    }
 }
 
 impl TryInto<Metadata> for std::fs::Metadata {
@@ -151,6 +298,31 @@ impl TryInto<Metadata> for std::fs::Metadata {
 
     fn try_into(self) -> std::result::Result<Metadata, Self::Error> {
         let filetype = self.file_type();
+        let (mode, uid, gid, dev, ino) = {
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::MetadataExt;
+                (
+                    self.mode() & 0o7777,
+                    self.uid(),
+                    self.gid(),
+                    self.dev(),
+                    self.ino(),
+                )
+            }
+            #[cfg(not(unix))]
+            {
+                // There are no execute bits to read here, so everything is
+                // assumed to be executable and only the read-only flag is
+                // taken from the host
+                let mode = if self.permissions().readonly() {
+                    0o555
+                } else {
+                    0o755
+                };
+                (mode, 0, 0, 0, 0)
+            }
+        };
         let (char_device, block_device, socket, fifo) = {
             #[cfg(unix)]
             {
@@ -200,6 +372,11 @@ impl TryInto<Metadata> for std::fs::Metadata {
                 })
                 .map_or(0, |time| time.as_nanos() as u64),
             len: self.len(),
+            mode,
+            uid,
+            gid,
+            dev,
+            ino,
         })
     }
 }
@@ -429,6 +606,59 @@ impl VirtualFile for File {
         None
     }
 
+    #[cfg(target_os = "linux")]
+    fn host_lock(&self, kind: FileLockKind, start: u64, len: u64) -> Result<()> {
+        use std::os::unix::io::AsRawFd;
+
+        // Open file description locks belong to this handle rather than to
+        // the whole host process, so guests holding different handles
+        // conflict with each other the same way separate processes would.
+        let mut lock: libc::flock = unsafe { std::mem::zeroed() };
+        lock.l_type = match kind {
+            FileLockKind::Shared => libc::F_RDLCK,
+            FileLockKind::Exclusive => libc::F_WRLCK,
+            FileLockKind::Unlock => libc::F_UNLCK,
+        } as libc::c_short;
+        lock.l_whence = libc::SEEK_SET as libc::c_short;
+        lock.l_start = start.try_into().map_err(|_| FsError::InvalidInput)?;
+        lock.l_len = len.try_into().map_err(|_| FsError::InvalidInput)?;
+
+        let ret = unsafe { libc::fcntl(self.inner_std.as_raw_fd(), libc::F_OFD_SETLK, &lock) };
+        if ret == -1 {
+            let err = io::Error::last_os_error();
+            return Err(match err.raw_os_error() {
+                Some(libc::EAGAIN) | Some(libc::EACCES) => FsError::WouldBlock,
+                _ => err.into(),
+            });
+        }
+        Ok(())
+    }
+
+    #[cfg(all(unix, not(target_os = "linux")))]
+    fn host_lock(&self, kind: FileLockKind, start: u64, len: u64) -> Result<()> {
+        use std::os::unix::io::AsRawFd;
+
+        // Only whole file locks can be passed through to `flock()`
+        if start != 0 || len != 0 {
+            return Err(FsError::Unsupported);
+        }
+        let operation = match kind {
+            FileLockKind::Shared => libc::LOCK_SH,
+            FileLockKind::Exclusive => libc::LOCK_EX,
+            FileLockKind::Unlock => libc::LOCK_UN,
+        };
+
+        let ret = unsafe { libc::flock(self.inner_std.as_raw_fd(), operation | libc::LOCK_NB) };
+        if ret == -1 {
+            let err = io::Error::last_os_error();
+            return Err(match err.raw_os_error() {
+                Some(libc::EWOULDBLOCK) => FsError::WouldBlock,
+                _ => err.into(),
+            });
+        }
+        Ok(())
+    }
+
     fn poll_read_ready(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
         let cursor = match self.inner_std.stream_position() {
             Ok(a) => a,
@@ -941,6 +1171,36 @@ mod tests {
         );
     }
 
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_host_lock() {
+        use crate::FileLockKind;
+
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("locked.db");
+        std::fs::write(&path, b"").unwrap();
+
+        let fs = FileSystem::default();
+        let first = fs
+            .new_open_options()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        let second = fs.new_open_options().read(true).open(&path).unwrap();
+
+        first.host_lock(FileLockKind::Exclusive, 0, 10).unwrap();
+        assert_eq!(
+            second.host_lock(FileLockKind::Shared, 5, 1),
+            Err(FsError::WouldBlock),
+            "overlapping ranges conflict"
+        );
+        assert_eq!(second.host_lock(FileLockKind::Shared, 10, 0), Ok(()));
+
+        first.host_lock(FileLockKind::Unlock, 0, 0).unwrap();
+        assert_eq!(second.host_lock(FileLockKind::Shared, 0, 0), Ok(()));
+    }
+
     #[tokio::test]
     async fn test_create_dir() {
         let temp = TempDir::new().unwrap();
@@ -1275,6 +1535,62 @@ mod tests {
         );
     }
 
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_links() {
+        let fs = FileSystem::default();
+        let temp = TempDir::new().unwrap();
+        let original = temp.path().join("original.txt");
+        std::fs::write(&original, b"Hello, World!").unwrap();
+
+        let symlink = temp.path().join("symlink.txt");
+        assert_eq!(
+            fs.symlink(Path::new("original.txt"), &symlink),
+            Ok(()),
+            "creating a relative symlink",
+        );
+        assert_eq!(fs.read_link(&symlink), Ok("original.txt".into()));
+        assert!(fs.metadata(&symlink).unwrap().is_file());
+        assert!(fs.symlink_metadata(&symlink).unwrap().ft.symlink);
+
+        let hard_link = temp.path().join("hard_link.txt");
+        assert_eq!(fs.hard_link(&original, &hard_link), Ok(()));
+        assert_eq!(fs.remove_file(&original), Ok(()));
+        assert_eq!(
+            std::fs::read_to_string(&hard_link).unwrap(),
+            "Hello, World!",
+            "the hard link outlives the original",
+        );
+        assert_eq!(
+            fs.metadata(&symlink),
+            Err(FsError::EntryNotFound),
+            "the symlink is now dangling",
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_set_permissions() {
+        let fs = FileSystem::default();
+        let temp = TempDir::new().unwrap();
+        let script = temp.path().join("script.sh");
+        std::fs::write(&script, b"#!/bin/sh").unwrap();
+
+        assert_eq!(fs.set_permissions(&script, 0o750), Ok(()));
+        let metadata = fs.metadata(&script).unwrap();
+        assert_eq!(
+            metadata.mode(),
+            0o750,
+            "the mode is read back from the host"
+        );
+        assert!(metadata.is_executable());
+        assert_eq!(
+            metadata.uid(),
+            std::os::unix::fs::MetadataExt::uid(&std::fs::metadata(&script).unwrap()),
+            "the owner is read back from the host",
+        );
+    }
+
     #[tokio::test]
     async fn test_readdir() {
         let temp = TempDir::new().unwrap();
@@ -0,0 +1,1067 @@
+//! A read-only [`FileSystem`] for the volumes of a webc container which reads
+//! files on demand instead of keeping the whole container in memory.
+//!
+//! Only the directory index of each volume is read when the container is
+//! loaded, the contents of files are fetched from a [`WebcSource`] as they are
+//! read. Version 1 and version 2 containers are supported.
+
+use std::{
+    collections::BTreeMap,
+    fmt, io,
+    ops::Range,
+    path::{Component, Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use futures::future::BoxFuture;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWrite, ReadBuf};
+use webc::{
+    compat::SharedBytes,
+    v2::{
+        read::{DirEntry as V2DirEntry, Section},
+        Tag,
+    },
+};
+
+use crate::{
+    DirEntry, EmptyFileSystem, FileOpener, FileSystem, FileType, FsError, Metadata,
+    OpenOptionsConfig, OverlayFileSystem, ReadDir, VirtualFile,
+};
+
+const MAGIC: &[u8] = b"\0webc";
+const VERSION_1: &[u8] = b"001";
+const VERSION_2: &[u8] = b"002";
+/// The checksum type and the checksum.
+const CHECKSUM_LEN: u64 = 16 + 256;
+/// The length of the signature and the signature.
+const SIGNATURE_LEN: u64 = 4 + 1024;
+/// The longest encoding of a LEB128 `u64`.
+const MAX_LEB128_LEN: u64 = 10;
+/// The tag and the `u64` length in front of a version 2 section.
+const V2_SECTION_HEADER_LEN: u64 = 1 + 8;
+/// How deeply directories can be nested in a volume.
+const MAX_DEPTH: usize = 256;
+
+/// The top byte of the name length of an entry tells its kind.
+const KIND_SHIFT: u32 = 56;
+const NAME_LEN_MASK: u64 = (1 << KIND_SHIFT) - 1;
+const KIND_DIRECTORY: u64 = 0;
+const KIND_FILE: u64 = 1;
+
+/// The least that is fetched from the source when a file is read.
+const READ_AHEAD: usize = 64 * 1024;
+
+/// Where the bytes of a container are read from.
+pub trait WebcSource: fmt::Debug + Send + Sync + 'static {
+    /// Reads exactly `len` bytes, starting at `offset`.
+    fn read_at(&self, offset: u64, len: usize) -> BoxFuture<'static, io::Result<Vec<u8>>>;
+
+    /// The size of the container in bytes.
+    fn size(&self) -> BoxFuture<'static, io::Result<u64>>;
+}
+
+/// A container stored in a local file, which is opened every time something
+/// is read from it.
+#[cfg(feature = "host-fs")]
+#[derive(Debug, Clone)]
+pub struct WebcFileSource {
+    path: PathBuf,
+}
+
+#[cfg(feature = "host-fs")]
+impl WebcFileSource {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        WebcFileSource { path: path.into() }
+    }
+}
+
+#[cfg(feature = "host-fs")]
+impl WebcSource for WebcFileSource {
+    fn read_at(&self, offset: u64, len: usize) -> BoxFuture<'static, io::Result<Vec<u8>>> {
+        let path = self.path.clone();
+        Box::pin(async move {
+            let mut file = tokio::fs::File::open(&path).await?;
+            file.seek(io::SeekFrom::Start(offset)).await?;
+            let mut buf = vec![0; len];
+            file.read_exact(&mut buf).await?;
+            Ok(buf)
+        })
+    }
+
+    fn size(&self) -> BoxFuture<'static, io::Result<u64>> {
+        let path = self.path.clone();
+        Box::pin(async move { Ok(tokio::fs::metadata(&path).await?.len()) })
+    }
+}
+
+/// A container read through any [`AsyncRead`] + [`AsyncSeek`] provider.
+/// Reads are done one at a time.
+#[derive(Debug)]
+pub struct WebcReaderSource<R> {
+    reader: Arc<tokio::sync::Mutex<R>>,
+}
+
+impl<R> WebcReaderSource<R> {
+    pub fn new(reader: R) -> Self {
+        WebcReaderSource {
+            reader: Arc::new(tokio::sync::Mutex::new(reader)),
+        }
+    }
+}
+
+impl<R> Clone for WebcReaderSource<R> {
+    fn clone(&self) -> Self {
+        WebcReaderSource {
+            reader: self.reader.clone(),
+        }
+    }
+}
+
+impl<R> WebcSource for WebcReaderSource<R>
+where
+    R: AsyncRead + AsyncSeek + fmt::Debug + Unpin + Send + Sync + 'static,
+{
+    fn read_at(&self, offset: u64, len: usize) -> BoxFuture<'static, io::Result<Vec<u8>>> {
+        let reader = self.reader.clone();
+        Box::pin(async move {
+            let mut reader = reader.lock().await;
+            reader.seek(io::SeekFrom::Start(offset)).await?;
+            let mut buf = vec![0; len];
+            reader.read_exact(&mut buf).await?;
+            Ok(buf)
+        })
+    }
+
+    fn size(&self) -> BoxFuture<'static, io::Result<u64>> {
+        let reader = self.reader.clone();
+        Box::pin(async move { reader.lock().await.seek(io::SeekFrom::End(0)).await })
+    }
+}
+
+#[derive(Debug)]
+enum Node {
+    Dir(BTreeMap<String, Node>),
+    /// A file, found at `offset` in the container.
+    File {
+        offset: u64,
+        len: u64,
+    },
+}
+
+impl Node {
+    fn metadata(&self) -> Metadata {
+        match self {
+            Node::Dir(_) => Metadata {
+                ft: FileType {
+                    dir: true,
+                    ..Default::default()
+                },
+                mode: crate::DEFAULT_DIR_MODE,
+                ..Default::default()
+            },
+            Node::File { len, .. } => Metadata {
+                ft: FileType {
+                    file: true,
+                    ..Default::default()
+                },
+                len: *len,
+                mode: crate::PACKAGE_MODE,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// A volume of a webc container, read lazily from a [`WebcSource`].
+#[derive(Debug, Clone)]
+pub struct LazyWebcVolumeFileSystem {
+    name: Arc<str>,
+    root: Arc<Node>,
+    source: Arc<dyn WebcSource>,
+}
+
+impl LazyWebcVolumeFileSystem {
+    /// Reads the directory index of every volume in a container.
+    pub async fn load_volumes(
+        source: impl WebcSource,
+    ) -> io::Result<BTreeMap<String, LazyWebcVolumeFileSystem>> {
+        let source: Arc<dyn WebcSource> = Arc::new(source);
+        let volumes = read_volumes(&*source).await?;
+
+        Ok(volumes
+            .into_iter()
+            .map(|(name, root)| {
+                let fs = LazyWebcVolumeFileSystem {
+                    name: name.as_str().into(),
+                    root: Arc::new(root),
+                    source: source.clone(),
+                };
+                (name, fs)
+            })
+            .collect())
+    }
+
+    /// Get a filesystem where all the volumes in a container are mounted to
+    /// the root directory.
+    pub async fn mount_all(
+        source: impl WebcSource,
+    ) -> io::Result<OverlayFileSystem<EmptyFileSystem, Vec<LazyWebcVolumeFileSystem>>> {
+        let volumes = Self::load_volumes(source).await?;
+        Ok(OverlayFileSystem::new(
+            EmptyFileSystem::default(),
+            volumes.into_values().collect::<Vec<_>>(),
+        ))
+    }
+
+    /// The name of the volume.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn find(&self, segments: &[&str]) -> Result<&Node, FsError> {
+        let mut node = &*self.root;
+        for segment in segments {
+            node = match node {
+                Node::Dir(entries) => entries.get(*segment).ok_or(FsError::EntryNotFound)?,
+                Node::File { .. } => return Err(FsError::EntryNotFound),
+            };
+        }
+        Ok(node)
+    }
+}
+
+impl FileSystem for LazyWebcVolumeFileSystem {
+    fn read_dir(&self, path: &Path) -> Result<ReadDir, FsError> {
+        let segments = normalize(path)?;
+        let entries = match self.find(&segments)? {
+            Node::Dir(entries) => entries,
+            Node::File { .. } => return Err(FsError::BaseNotDirectory),
+        };
+
+        let dir: PathBuf = std::iter::once("/").chain(segments).collect();
+        let entries = entries
+            .iter()
+            .map(|(name, node)| DirEntry {
+                path: dir.join(name),
+                metadata: Ok(node.metadata()),
+            })
+            .collect();
+
+        Ok(ReadDir::new(entries))
+    }
+
+    fn create_dir(&self, path: &Path) -> Result<(), FsError> {
+        // the directory shouldn't exist yet
+        if self.metadata(path).is_ok() {
+            return Err(FsError::AlreadyExists);
+        }
+
+        // it's parent should exist
+        let parent = path.parent().unwrap_or_else(|| Path::new("/"));
+
+        match self.metadata(parent) {
+            Ok(parent_meta) if parent_meta.is_dir() => {
+                // The operation would normally be doable... but we're a readonly
+                // filesystem
+                Err(FsError::PermissionDenied)
+            }
+            Ok(_) | Err(FsError::EntryNotFound) => Err(FsError::BaseNotDirectory),
+            Err(other) => Err(other),
+        }
+    }
+
+    fn remove_dir(&self, path: &Path) -> Result<(), FsError> {
+        if !self.metadata(path)?.is_dir() {
+            return Err(FsError::BaseNotDirectory);
+        }
+
+        Err(FsError::PermissionDenied)
+    }
+
+    fn rename<'a>(&'a self, from: &'a Path, to: &'a Path) -> BoxFuture<'a, Result<(), FsError>> {
+        Box::pin(async {
+            // The original file should exist
+            let _ = self.metadata(from)?;
+
+            // we also want to make sure the destination's folder exists, too
+            let dest_parent = to.parent().unwrap_or_else(|| Path::new("/"));
+            if !self.metadata(dest_parent)?.is_dir() {
+                return Err(FsError::BaseNotDirectory);
+            }
+
+            Err(FsError::PermissionDenied)
+        })
+    }
+
+    fn metadata(&self, path: &Path) -> Result<Metadata, FsError> {
+        let segments = normalize(path)?;
+        self.find(&segments).map(Node::metadata)
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<(), FsError> {
+        if !self.metadata(path)?.is_file() {
+            return Err(FsError::NotAFile);
+        }
+
+        Err(FsError::PermissionDenied)
+    }
+
+    fn new_open_options(&self) -> crate::OpenOptions {
+        crate::OpenOptions::new(self)
+    }
+}
+
+impl FileOpener for LazyWebcVolumeFileSystem {
+    fn open(
+        &self,
+        path: &Path,
+        conf: &OpenOptionsConfig,
+    ) -> crate::Result<Box<dyn VirtualFile + Send + Sync + 'static>> {
+        if let Some(parent) = path.parent() {
+            if !self.metadata(parent)?.is_dir() {
+                return Err(FsError::BaseNotDirectory);
+            }
+        }
+
+        let segments = normalize(path)?;
+        match self.find(&segments) {
+            Ok(Node::File { offset, len }) => Ok(Box::new(File {
+                source: self.source.clone(),
+                offset: *offset,
+                len: *len,
+                position: 0,
+                buffer: Vec::new(),
+                buffer_start: 0,
+                pending: Mutex::new(None),
+            })),
+            Ok(Node::Dir(_)) => Err(FsError::NotAFile),
+            Err(FsError::EntryNotFound) if conf.create() || conf.create_new() => {
+                // The file would normally be created, but we are a readonly fs.
+                Err(FsError::PermissionDenied)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// A file whose contents are fetched from the source as they are read.
+struct File {
+    source: Arc<dyn WebcSource>,
+    /// Where the file starts in the container.
+    offset: u64,
+    len: u64,
+    position: u64,
+    /// The bytes fetched last, starting at `buffer_start` in the file.
+    buffer: Vec<u8>,
+    buffer_start: u64,
+    /// A fetch in progress and where it starts in the file. The future is
+    /// behind a mutex so the file stays [`Sync`].
+    pending: Mutex<Option<(u64, Fetch)>>,
+}
+
+type Fetch = BoxFuture<'static, io::Result<Vec<u8>>>;
+
+impl File {
+    /// The fetched bytes found at the current position.
+    fn buffered(&self) -> Option<&[u8]> {
+        let start = self.position.checked_sub(self.buffer_start)?;
+        let start = usize::try_from(start).ok()?;
+        self.buffer.get(start..).filter(|bytes| !bytes.is_empty())
+    }
+}
+
+impl fmt::Debug for File {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("File")
+            .field("source", &self.source)
+            .field("offset", &self.offset)
+            .field("len", &self.len)
+            .field("position", &self.position)
+            .finish()
+    }
+}
+
+impl VirtualFile for File {
+    fn last_accessed(&self) -> u64 {
+        0
+    }
+
+    fn last_modified(&self) -> u64 {
+        0
+    }
+
+    fn created_time(&self) -> u64 {
+        0
+    }
+
+    fn size(&self) -> u64 {
+        self.len
+    }
+
+    fn set_len(&mut self, _new_size: u64) -> crate::Result<()> {
+        Err(FsError::PermissionDenied)
+    }
+
+    fn unlink(&mut self) -> BoxFuture<'static, crate::Result<()>> {
+        Box::pin(async { Err(FsError::PermissionDenied) })
+    }
+
+    fn poll_read_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        let remaining = self.len.saturating_sub(self.position);
+        Poll::Ready(Ok(usize::try_from(remaining).unwrap_or(usize::MAX)))
+    }
+
+    fn poll_write_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        Poll::Ready(Err(io::ErrorKind::PermissionDenied.into()))
+    }
+}
+
+impl AsyncRead for File {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        loop {
+            if this.position >= this.len || buf.remaining() == 0 {
+                return Poll::Ready(Ok(()));
+            }
+
+            if let Some(bytes) = this.buffered() {
+                let len = bytes.len().min(buf.remaining());
+                buf.put_slice(&bytes[..len]);
+                this.position += len as u64;
+                return Poll::Ready(Ok(()));
+            }
+
+            let pending = this.pending.get_mut().unwrap();
+            let (start, fetch) = pending.get_or_insert_with(|| {
+                let wanted = buf.remaining().max(READ_AHEAD) as u64;
+                let len = wanted.min(this.len - this.position) as usize;
+                let fetch = this.source.read_at(this.offset + this.position, len);
+                (this.position, fetch)
+            });
+
+            let result = match fetch.as_mut().poll(cx) {
+                Poll::Ready(result) => result,
+                Poll::Pending => return Poll::Pending,
+            };
+            this.buffer_start = *start;
+            *pending = None;
+            this.buffer = result?;
+        }
+    }
+}
+
+impl AsyncSeek for File {
+    fn start_seek(mut self: Pin<&mut Self>, position: io::SeekFrom) -> io::Result<()> {
+        let position = match position {
+            io::SeekFrom::Start(offset) => Some(offset),
+            io::SeekFrom::End(delta) => self.len.checked_add_signed(delta),
+            io::SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+        };
+
+        match position {
+            Some(position) => {
+                self.position = position;
+                Ok(())
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Poll::Ready(Ok(self.position))
+    }
+}
+
+impl AsyncWrite for File {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        _buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(Err(io::ErrorKind::PermissionDenied.into()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Err(io::ErrorKind::PermissionDenied.into()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Err(io::ErrorKind::PermissionDenied.into()))
+    }
+}
+
+/// Turns an absolute path into the names leading to it, dealing with things
+/// like `..` and skipping `.`'s.
+fn normalize(path: &Path) -> Result<Vec<&str>, FsError> {
+    if !path.has_root() {
+        return Err(FsError::InvalidInput);
+    }
+
+    let mut segments = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir | Component::CurDir => {}
+            Component::ParentDir => {
+                segments.pop();
+            }
+            Component::Normal(name) => segments.push(name.to_str().ok_or(FsError::InvalidInput)?),
+        }
+    }
+
+    Ok(segments)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Reads the sections of a container one after the other.
+struct SectionReader<'a> {
+    source: &'a dyn WebcSource,
+    offset: u64,
+    size: u64,
+}
+
+impl SectionReader<'_> {
+    fn skip(&mut self, len: u64) -> io::Result<()> {
+        self.offset = self
+            .offset
+            .checked_add(len)
+            .filter(|end| *end <= self.size)
+            .ok_or_else(|| invalid_data("a section goes past the end of the container"))?;
+        Ok(())
+    }
+
+    async fn read(&mut self, len: u64) -> io::Result<Vec<u8>> {
+        let start = self.offset;
+        self.skip(len)?;
+        let len = usize::try_from(len).map_err(|_| invalid_data("a section is too large"))?;
+        self.source.read_at(start, len).await
+    }
+
+    async fn read_leb128(&mut self) -> io::Result<u64> {
+        let len = MAX_LEB128_LEN.min(self.size.saturating_sub(self.offset));
+        let bytes = self.source.read_at(self.offset, len as usize).await?;
+
+        let mut value = 0_u64;
+        for (i, byte) in bytes.iter().enumerate() {
+            value |= u64::from(byte & 0x7f) << (7 * i);
+            if byte & 0x80 == 0 {
+                self.offset += i as u64 + 1;
+                return Ok(value);
+            }
+        }
+
+        Err(invalid_data("invalid LEB128 number"))
+    }
+}
+
+/// Reads the directory index of all the volumes, skipping the manifest and
+/// the atoms.
+async fn read_volumes(source: &dyn WebcSource) -> io::Result<Vec<(String, Node)>> {
+    let size = source.size().await?;
+    let mut reader = SectionReader {
+        source,
+        offset: 0,
+        size,
+    };
+
+    let header_len = (MAGIC.len() + VERSION_1.len()) as u64;
+    if size < header_len {
+        return Err(invalid_data("not a webc container"));
+    }
+    let header = reader.read(header_len).await?;
+    let (magic, version) = header.split_at(MAGIC.len());
+    if magic != MAGIC {
+        return Err(invalid_data("not a webc container"));
+    }
+    match version {
+        VERSION_1 => read_volumes_v1(reader).await,
+        VERSION_2 => read_volumes_v2(reader).await,
+        _ => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "version {} webc containers are not supported",
+                String::from_utf8_lossy(version)
+            ),
+        )),
+    }
+}
+
+async fn read_volumes_v1(mut reader: SectionReader<'_>) -> io::Result<Vec<(String, Node)>> {
+    reader.skip(CHECKSUM_LEN + SIGNATURE_LEN)?;
+    let manifest_len = reader.read_leb128().await?;
+    reader.skip(manifest_len)?;
+    let atoms_len = reader.read_leb128().await?;
+    reader.skip(atoms_len)?;
+
+    let mut volumes = Vec::new();
+    while reader.offset < reader.size {
+        let name_len = reader.read_leb128().await?;
+        let name = String::from_utf8(reader.read(name_len).await?)
+            .map_err(|_| invalid_data("the name of a volume isn't valid UTF-8"))?;
+
+        let volume_len = reader.read_leb128().await?;
+        let volume_start = reader.offset;
+        reader.skip(volume_len)?;
+        let volume_end = reader.offset;
+        reader.offset = volume_start;
+
+        let header_len = reader.read_leb128().await?;
+        let header = reader.read(header_len).await?;
+        if reader.offset > volume_end {
+            return Err(invalid_data(
+                "a volume header goes past the end of its volume",
+            ));
+        }
+
+        let root = read_header(&header, reader.offset..volume_end)?;
+        volumes.push((name, root));
+        reader.offset = volume_end;
+    }
+
+    Ok(volumes)
+}
+
+/// Reads the volumes of a version 2 container with the `webc` crate, skipping
+/// the other sections.
+///
+/// A volume section holds its index and the contents of its files, so it is
+/// read in full while the container is loaded. Only the index is kept, files
+/// are read from the source again when they are opened.
+async fn read_volumes_v2(mut reader: SectionReader<'_>) -> io::Result<Vec<(String, Node)>> {
+    let mut volumes = Vec::new();
+    while reader.offset < reader.size {
+        let section_header = reader.read(V2_SECTION_HEADER_LEN).await?;
+        let tag = section_header[0];
+        let section_len = read_u64(&section_header, 1)?;
+        if tag != Tag::Volume.as_u8() {
+            reader.skip(section_len)?;
+            continue;
+        }
+
+        let section_start = reader.offset;
+        let section = SharedBytes::from(reader.read(section_len).await?);
+        let volume = match Section::parse(tag, section.clone()) {
+            Ok(Section::Volume(volume)) => volume,
+            Ok(_) => unreachable!("the section was tagged as a volume"),
+            Err(e) => return Err(invalid_data(&format!("invalid volume: {e}"))),
+        };
+        let root = volume
+            .root()
+            .map_err(|e| invalid_data(&format!("invalid volume header: {e}")))?;
+
+        let mut walker = DirectoryWalker {
+            section: section.as_slice(),
+            section_start,
+            // Every entry takes up 16 bytes of its parent directory, anything
+            // past that means directories are shared
+            entries_left: section.len() / 16,
+        };
+        let root = walker.read_directory(&root, 0)?;
+        volumes.push((volume.name().to_string(), Node::Dir(root)));
+    }
+
+    Ok(volumes)
+}
+
+/// Builds the directory tree of a version 2 volume.
+struct DirectoryWalker<'a> {
+    section: &'a [u8],
+    section_start: u64,
+    entries_left: usize,
+}
+
+impl DirectoryWalker<'_> {
+    fn read_directory(
+        &mut self,
+        dir: &webc::v2::read::Directory<'_>,
+        depth: usize,
+    ) -> io::Result<BTreeMap<String, Node>> {
+        if depth > MAX_DEPTH {
+            return Err(invalid_data("directories are nested too deeply"));
+        }
+
+        let mut entries = BTreeMap::new();
+        for entry in dir.entries() {
+            let (name, entry) =
+                entry.map_err(|e| invalid_data(&format!("invalid directory entry: {e}")))?;
+            self.entries_left = self
+                .entries_left
+                .checked_sub(1)
+                .ok_or_else(|| invalid_data("directories of a volume overlap"))?;
+
+            let node = match entry {
+                V2DirEntry::Dir(dir) => Node::Dir(self.read_directory(&dir, depth + 1)?),
+                V2DirEntry::File(file) => self.file(file.bytes().as_slice())?,
+            };
+            entries.insert(name.to_string(), node);
+        }
+
+        Ok(entries)
+    }
+
+    /// Finds where the contents of a file are in the container, they are a
+    /// slice of the section that was read.
+    fn file(&self, contents: &[u8]) -> io::Result<Node> {
+        if contents.is_empty() {
+            return Ok(Node::File { offset: 0, len: 0 });
+        }
+
+        let offset = (contents.as_ptr() as usize)
+            .checked_sub(self.section.as_ptr() as usize)
+            .filter(|offset| offset + contents.len() <= self.section.len())
+            .ok_or_else(|| invalid_data("a file goes past the end of its volume"))?;
+        Ok(Node::File {
+            offset: self.section_start + offset as u64,
+            len: contents.len() as u64,
+        })
+    }
+}
+
+/// Reads the directory tree of a version 1 volume, given where its data is
+/// found in the container.
+///
+/// The header is made of directories, each one a `u64` length followed by its
+/// entries. An entry is the length of its name (its top byte being the kind
+/// of entry), where its contents start and end, and its name. The contents of
+/// a directory are the range of the header holding its entries, those of a
+/// file are the range of the data.
+fn read_header(header: &[u8], data: Range<u64>) -> io::Result<Node> {
+    if header.is_empty() {
+        return Ok(Node::Dir(BTreeMap::new()));
+    }
+
+    let len = read_u64(header, 0)?;
+    let end = len
+        .checked_add(8)
+        .ok_or_else(|| invalid_data("a directory is too large"))?;
+    let mut reader = HeaderReader {
+        header,
+        data,
+        directories: BTreeMap::new(),
+    };
+    reader.read_directory(0..end, 0).map(Node::Dir)
+}
+
+struct HeaderReader<'a> {
+    header: &'a [u8],
+    data: Range<u64>,
+    /// The ranges of the header holding the directories read so far, keyed
+    /// by where they start. They never overlap.
+    directories: BTreeMap<u64, u64>,
+}
+
+impl HeaderReader<'_> {
+    fn read_directory(
+        &mut self,
+        range: Range<u64>,
+        depth: usize,
+    ) -> io::Result<BTreeMap<String, Node>> {
+        if depth > MAX_DEPTH {
+            return Err(invalid_data("directories are nested too deeply"));
+        }
+        // Several entries pointing at the same directory would have it read
+        // once for each of them
+        let overlaps = self
+            .directories
+            .range(..range.end)
+            .next_back()
+            .map_or(false, |(_, end)| *end > range.start);
+        if overlaps {
+            return Err(invalid_data("directories of a volume overlap"));
+        }
+        self.directories.insert(range.start, range.end);
+
+        let header = self.header;
+        let block = usize::try_from(range.start)
+            .ok()
+            .zip(usize::try_from(range.end).ok())
+            .and_then(|(start, end)| header.get(start..end))
+            .ok_or_else(|| invalid_data("a directory goes past the end of the header"))?;
+        if read_u64(block, 0)?.checked_add(8) != Some(block.len() as u64) {
+            return Err(invalid_data("the length of a directory doesn't match"));
+        }
+
+        let mut entries = BTreeMap::new();
+        let mut rest = &block[8..];
+        while !rest.is_empty() {
+            let name_len = read_u64(rest, 0)?;
+            let start = read_u64(rest, 8)?;
+            let end = read_u64(rest, 16)?;
+            let name = usize::try_from(name_len & NAME_LEN_MASK)
+                .ok()
+                .and_then(|len| rest.get(24..24 + len))
+                .ok_or_else(|| invalid_data("the name of an entry goes past its directory"))?;
+            rest = &rest[24 + name.len()..];
+            let name = std::str::from_utf8(name)
+                .map_err(|_| invalid_data("the name of an entry isn't valid UTF-8"))?;
+
+            let data = &self.data;
+            let node = match name_len >> KIND_SHIFT {
+                // Directories always come after their parent
+                KIND_DIRECTORY if start >= range.end && start <= end => {
+                    Node::Dir(self.read_directory(start..end, depth + 1)?)
+                }
+                KIND_FILE
+                    if start <= end
+                        && matches!(data.start.checked_add(end), Some(end) if end <= data.end) =>
+                {
+                    Node::File {
+                        offset: data.start + start,
+                        len: end - start,
+                    }
+                }
+                _ => return Err(invalid_data("invalid directory entry")),
+            };
+            entries.insert(name.to_string(), node);
+        }
+
+        Ok(entries)
+    }
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> io::Result<u64> {
+    bytes
+        .get(at..at + 8)
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(u64::from_le_bytes)
+        .ok_or_else(|| invalid_data("unexpected end of a volume header"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    const HELLO_WEBC: &[u8] = include_bytes!(
+        "../../../tests/integration/cli/tests/webc/hello-0.1.0-665d2ddc-80e6-4845-85d3-4587b1693bb7.webc"
+    );
+
+    #[tokio::test]
+    async fn read_files_on_demand() {
+        let volumes =
+            LazyWebcVolumeFileSystem::load_volumes(WebcReaderSource::new(Cursor::new(HELLO_WEBC)))
+                .await
+                .unwrap();
+        assert_eq!(
+            volumes.keys().collect::<Vec<_>>(),
+            ["atom", "metadata"],
+            "the volumes are listed"
+        );
+        let fs = &volumes["atom"];
+
+        let entries: Vec<_> = fs
+            .read_dir(Path::new("/public"))
+            .unwrap()
+            .map(|entry| entry.unwrap())
+            .collect();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].path, Path::new("/public/index.html"));
+        assert_eq!(entries[0].metadata.as_ref().unwrap().len(), 1099);
+        assert!(fs
+            .metadata(Path::new("/public/../public/./"))
+            .unwrap()
+            .is_dir());
+
+        let mut file = fs
+            .new_open_options()
+            .read(true)
+            .open("/public/index.html")
+            .unwrap();
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).await.unwrap();
+        assert!(contents.starts_with("<!DOCTYPE html>"));
+        assert_eq!(contents.len(), 1099);
+
+        file.seek(io::SeekFrom::End(-9)).await.unwrap();
+        let mut tail = String::new();
+        file.read_to_string(&mut tail).await.unwrap();
+        assert_eq!(tail, "</html>\r\n");
+    }
+
+    #[tokio::test]
+    async fn mount_all_volumes() {
+        let fs =
+            LazyWebcVolumeFileSystem::mount_all(WebcReaderSource::new(Cursor::new(HELLO_WEBC)))
+                .await
+                .unwrap();
+
+        assert!(fs
+            .metadata(Path::new("/public/index.html"))
+            .unwrap()
+            .is_file());
+    }
+
+    #[tokio::test]
+    async fn the_file_system_is_read_only() {
+        let volumes =
+            LazyWebcVolumeFileSystem::load_volumes(WebcReaderSource::new(Cursor::new(HELLO_WEBC)))
+                .await
+                .unwrap();
+        let fs = &volumes["atom"];
+
+        assert_eq!(
+            fs.new_open_options()
+                .create(true)
+                .write(true)
+                .open("/public/new.html")
+                .unwrap_err(),
+            FsError::PermissionDenied,
+        );
+        assert_eq!(
+            fs.new_open_options()
+                .read(true)
+                .open("/public")
+                .unwrap_err(),
+            FsError::NotAFile,
+        );
+        assert_eq!(
+            fs.remove_file(Path::new("/public/index.html")).unwrap_err(),
+            FsError::PermissionDenied,
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_containers_are_rejected() {
+        let err = LazyWebcVolumeFileSystem::load_volumes(WebcReaderSource::new(Cursor::new(
+            b"\0webc003".to_vec(),
+        )))
+        .await
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+
+        let mut truncated = HELLO_WEBC.to_vec();
+        truncated.truncate(truncated.len() - 20);
+        let err =
+            LazyWebcVolumeFileSystem::load_volumes(WebcReaderSource::new(Cursor::new(truncated)))
+                .await
+                .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn read_a_version_2_container() {
+        use webc::v2::{
+            write::{Directory, Writer},
+            ChecksumAlgorithm, SignatureAlgorithm,
+        };
+
+        let public = Directory {
+            children: [
+                ("index.html".parse().unwrap(), b"<html></html>".into()),
+                ("empty.txt".parse().unwrap(), b"".into()),
+            ]
+            .into_iter()
+            .collect(),
+        };
+        let root = Directory {
+            children: [("public".parse().unwrap(), public.into())]
+                .into_iter()
+                .collect(),
+        };
+        let webc = Writer::new(ChecksumAlgorithm::None)
+            .write_manifest(&Default::default())
+            .unwrap()
+            .write_atoms(BTreeMap::new())
+            .unwrap()
+            .with_volume("atom", root)
+            .unwrap()
+            .finish(SignatureAlgorithm::None)
+            .unwrap();
+
+        let volumes =
+            LazyWebcVolumeFileSystem::load_volumes(WebcReaderSource::new(Cursor::new(webc)))
+                .await
+                .unwrap();
+        let fs = &volumes["atom"];
+
+        assert_eq!(
+            fs.metadata(Path::new("/public/empty.txt")).unwrap().len(),
+            0
+        );
+        let mut file = fs
+            .new_open_options()
+            .read(true)
+            .open("/public/index.html")
+            .unwrap();
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).await.unwrap();
+        assert_eq!(contents, "<html></html>");
+    }
+
+    /// Encodes a version 1 directory out of `(kind, start, end, name)`
+    /// entries.
+    fn directory(entries: &[(u64, u64, u64, &str)]) -> Vec<u8> {
+        let mut entries_bytes = Vec::new();
+        for (kind, start, end, name) in entries {
+            entries_bytes.extend((kind << KIND_SHIFT | name.len() as u64).to_le_bytes());
+            entries_bytes.extend(start.to_le_bytes());
+            entries_bytes.extend(end.to_le_bytes());
+            entries_bytes.extend(name.as_bytes());
+        }
+        let mut bytes = (entries_bytes.len() as u64).to_le_bytes().to_vec();
+        bytes.extend(entries_bytes);
+        bytes
+    }
+
+    #[test]
+    fn shared_directories_are_rejected() {
+        // Both entries of the root take up 25 bytes and point at the same
+        // empty directory
+        let mut header = directory(&[(KIND_DIRECTORY, 58, 66, "a"), (KIND_DIRECTORY, 58, 66, "b")]);
+        assert_eq!(header.len(), 58);
+        header.extend(directory(&[]));
+
+        let err = read_header(&header, 0..0).unwrap_err();
+        assert_eq!(err.to_string(), "directories of a volume overlap");
+    }
+
+    #[test]
+    fn deeply_nested_directories_are_rejected() {
+        // Every directory holds the next one and takes up 33 bytes, the
+        // innermost one is empty
+        let nested = |depth: u64| {
+            let mut header = Vec::new();
+            for i in 1..=depth {
+                let end = if i == depth { i * 33 + 8 } else { (i + 1) * 33 };
+                header.extend(directory(&[(KIND_DIRECTORY, i * 33, end, "d")]));
+            }
+            header.extend(directory(&[]));
+            header
+        };
+
+        assert!(read_header(&nested(MAX_DEPTH as u64), 0..0).is_ok());
+        let err = read_header(&nested(MAX_DEPTH as u64 + 1), 0..0).unwrap_err();
+        assert_eq!(err.to_string(), "directories are nested too deeply");
+    }
+
+    #[cfg(feature = "host-fs")]
+    #[tokio::test]
+    async fn read_from_a_local_file() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../../tests/integration/cli/tests/webc")
+            .join("dash-1.0.18-f0d13233-bcda-4cf1-9a23-3460bffaae2a.webc");
+        let volumes = LazyWebcVolumeFileSystem::load_volumes(WebcFileSource::new(path))
+            .await
+            .unwrap();
+        let fs = &volumes["atom"];
+
+        let readme = Path::new("/usr/coreutils/README.md");
+        assert_eq!(fs.metadata(readme).unwrap().len(), 1675);
+        let mut file = fs.new_open_options().read(true).open(readme).unwrap();
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents).await.unwrap();
+        assert_eq!(contents.len(), 1675);
+    }
+}
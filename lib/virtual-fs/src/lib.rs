@@ -5,6 +5,8 @@
 extern crate pretty_assertions;
 
 use futures::future::BoxFuture;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 use std::any::Any;
 use std::ffi::OsString;
 use std::fmt;
@@ -14,15 +16,21 @@ use std::path::{Path, PathBuf};
 use std::pin::Pin;
 use std::task::Context;
 use std::task::Poll;
+use std::time::SystemTime;
 use thiserror::Error;
 
 pub mod arc_box_file;
 pub mod arc_file;
 pub mod arc_fs;
+#[cfg(feature = "archive")]
+pub mod archive;
 pub mod buffer_file;
 pub mod builder;
+mod case_insensitive_fs;
 pub mod combine_file;
 pub mod cow_file;
+#[cfg(feature = "host-fs")]
+pub mod disk_layer_fs;
 pub mod dual_write_file;
 pub mod empty_fs;
 #[cfg(feature = "host-fs")]
@@ -37,13 +45,18 @@ pub mod union_fs;
 pub mod zero_file;
 // tty_file -> see wasmer_wasi::tty_file
 mod filesystems;
+#[cfg(feature = "webc-fs")]
+mod lazy_webc_fs;
 pub(crate) mod ops;
 mod overlay_fs;
 pub mod pipe;
+mod quota_fs;
+mod replay_fs;
 mod static_file;
 #[cfg(feature = "static-fs")]
 pub mod static_fs;
 mod trace_fs;
+pub mod watch;
 #[cfg(feature = "webc-fs")]
 pub mod webc_fs;
 #[cfg(feature = "webc-fs")]
@@ -56,20 +69,30 @@ pub use arc_file::*;
 pub use arc_fs::*;
 pub use buffer_file::*;
 pub use builder::*;
+pub use case_insensitive_fs::CaseInsensitiveFileSystem;
 pub use combine_file::*;
 pub use cow_file::*;
+#[cfg(feature = "host-fs")]
+pub use disk_layer_fs::DiskLayerFileSystem;
 pub use dual_write_file::*;
 pub use empty_fs::*;
 pub use filesystems::FileSystems;
+#[cfg(all(feature = "host-fs", feature = "webc-fs"))]
+pub use lazy_webc_fs::WebcFileSource;
+#[cfg(feature = "webc-fs")]
+pub use lazy_webc_fs::{LazyWebcVolumeFileSystem, WebcReaderSource, WebcSource};
 pub use null_file::*;
 pub use overlay_fs::OverlayFileSystem;
 pub use passthru_fs::*;
 pub use pipe::*;
+pub use quota_fs::{Quota, QuotaFileSystem, QuotaUsage};
+pub use replay_fs::ReplayFileSystem;
 pub use special_file::*;
 pub use static_file::StaticFile;
 pub use tmp_fs::*;
-pub use trace_fs::TraceFileSystem;
+pub use trace_fs::{FsOperation, FsOutcome, TraceFileSystem, TraceRecord, TraceRecorder};
 pub use union_fs::*;
+pub use watch::{FsEvent, FsWatcher};
 #[cfg(feature = "webc-fs")]
 pub use webc_volume_fs::WebcVolumeFileSystem;
 pub use zero_file::*;
@@ -93,14 +116,63 @@ pub trait FileSystem: fmt::Debug + Send + Sync + 'static + Upcastable {
     fn rename<'a>(&'a self, from: &'a Path, to: &'a Path) -> BoxFuture<'a, Result<()>>;
     fn metadata(&self, path: &Path) -> Result<Metadata>;
     /// This method gets metadata without following symlinks in the path.
-    /// Currently identical to `metadata` because symlinks aren't implemented
-    /// yet.
+    /// Defaults to `metadata` for file systems that don't support symlinks.
     fn symlink_metadata(&self, path: &Path) -> Result<Metadata> {
         self.metadata(path)
     }
     fn remove_file(&self, path: &Path) -> Result<()>;
 
     fn new_open_options(&self) -> OpenOptions;
+
+    /// Creates a new symbolic link at `link` which points to `target`.
+    ///
+    /// The `target` is stored verbatim and is only resolved when the link
+    /// is followed, relative targets are resolved from the directory that
+    /// contains the link.
+    #[allow(unused_variables)]
+    fn symlink(&self, target: &Path, link: &Path) -> Result<()> {
+        Err(FsError::Unsupported)
+    }
+
+    /// Creates a new hard link at `link` which refers to the same file as
+    /// `original`.
+    #[allow(unused_variables)]
+    fn hard_link(&self, original: &Path, link: &Path) -> Result<()> {
+        Err(FsError::Unsupported)
+    }
+
+    /// Reads the target of the symbolic link at `path`.
+    #[allow(unused_variables)]
+    fn read_link(&self, path: &Path) -> Result<PathBuf> {
+        Err(FsError::Unsupported)
+    }
+
+    /// Changes the permission bits of the file or directory at `path`,
+    /// only the lower 12 bits of `mode` (`0o7777`) are kept.
+    #[allow(unused_variables)]
+    fn set_permissions(&self, path: &Path, mode: u32) -> Result<()> {
+        Err(FsError::Unsupported)
+    }
+
+    /// Changes the last accessed and last modified times of the file or
+    /// directory at `path`, the times that are `None` are left alone.
+    #[allow(unused_variables)]
+    fn set_times(
+        &self,
+        path: &Path,
+        accessed: Option<SystemTime>,
+        modified: Option<SystemTime>,
+    ) -> Result<()> {
+        Err(FsError::Unsupported)
+    }
+
+    /// Subscribes to the changes made to `path` and, when it is a directory,
+    /// to its direct children. With `recursive`, every change below `path`
+    /// is reported.
+    #[allow(unused_variables)]
+    fn watch(&self, path: &Path, recursive: bool) -> Result<FsWatcher> {
+        Err(FsError::Unsupported)
+    }
 }
 
 impl dyn FileSystem + 'static {
@@ -140,6 +212,10 @@ where
         (**self).metadata(path)
     }
 
+    fn symlink_metadata(&self, path: &Path) -> Result<Metadata> {
+        (**self).symlink_metadata(path)
+    }
+
     fn remove_file(&self, path: &Path) -> Result<()> {
         (**self).remove_file(path)
     }
@@ -147,6 +223,35 @@ where
     fn new_open_options(&self) -> OpenOptions {
         (**self).new_open_options()
     }
+
+    fn symlink(&self, target: &Path, link: &Path) -> Result<()> {
+        (**self).symlink(target, link)
+    }
+
+    fn hard_link(&self, original: &Path, link: &Path) -> Result<()> {
+        (**self).hard_link(original, link)
+    }
+
+    fn read_link(&self, path: &Path) -> Result<PathBuf> {
+        (**self).read_link(path)
+    }
+
+    fn set_permissions(&self, path: &Path, mode: u32) -> Result<()> {
+        (**self).set_permissions(path, mode)
+    }
+
+    fn set_times(
+        &self,
+        path: &Path,
+        accessed: Option<SystemTime>,
+        modified: Option<SystemTime>,
+    ) -> Result<()> {
+        (**self).set_times(path, accessed, modified)
+    }
+
+    fn watch(&self, path: &Path, recursive: bool) -> Result<FsWatcher> {
+        (**self).watch(path, recursive)
+    }
 }
 
 pub trait FileOpener {
@@ -157,7 +262,8 @@ pub trait FileOpener {
     ) -> Result<Box<dyn VirtualFile + Send + Sync + 'static>>;
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct OpenOptionsConfig {
     pub read: bool,
     pub write: bool,
@@ -355,6 +461,17 @@ pub trait VirtualFile:
         None
     }
 
+    /// Takes, converts or releases an advisory lock on a range of bytes of
+    /// the file in the host, without blocking. A `len` of zero covers
+    /// everything up to the end of the file.
+    ///
+    /// Returns [`FsError::WouldBlock`] when someone else holds a conflicting
+    /// lock and [`FsError::Unsupported`] for files that aren't backed by a
+    /// host file.
+    fn host_lock(&self, _kind: FileLockKind, _start: u64, _len: u64) -> Result<()> {
+        Err(FsError::Unsupported)
+    }
+
     /// This method will copy a file from a source to this destination where
     /// the default is to do a straight byte copy however file system implementors
     /// may optimize this to do a zero copy
@@ -376,6 +493,17 @@ pub trait VirtualFile:
     fn poll_write_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<usize>>;
 }
 
+/// The kinds of advisory lock that can be taken on a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FileLockKind {
+    /// Any number of owners can hold a shared lock at the same time.
+    Shared,
+    /// Only one owner can hold an exclusive lock.
+    Exclusive,
+    /// Releases the lock.
+    Unlock,
+}
+
 // Implementation of `Upcastable` taken from https://users.rust-lang.org/t/why-does-downcasting-not-work-for-subtraits/33286/7 .
 /// Trait needed to get downcasting from `VirtualFile` to work.
 pub trait Upcastable {
@@ -414,6 +542,7 @@ pub enum StdioMode {
 
 /// Error type for external users
 #[derive(Error, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum FsError {
     /// The fd given as a base was not a directory so the operation was not possible
     #[error("fd not a directory")]
@@ -490,6 +619,12 @@ pub enum FsError {
     DirectoryNotEmpty,
     #[error("storage full")]
     StorageFull,
+    /// Too many symbolic links were encountered while resolving a path
+    #[error("too many levels of symbolic links")]
+    TooManyLinks,
+    /// The operation is not supported by this file system
+    #[error("unsupported operation")]
+    Unsupported,
     /// Some other unhandled error. If you see this, it's probably a bug.
     #[error("unknown error found")]
     UnknownError,
@@ -515,6 +650,7 @@ impl From<io::Error> for FsError {
             io::ErrorKind::UnexpectedEof => FsError::UnexpectedEof,
             io::ErrorKind::WouldBlock => FsError::WouldBlock,
             io::ErrorKind::WriteZero => FsError::WriteZero,
+            io::ErrorKind::Unsupported => FsError::Unsupported,
             // NOTE: Add this once the "io_error_more" Rust feature is stabilized
             // io::ErrorKind::StorageFull => FsError::StorageFull,
             io::ErrorKind::Other => FsError::IOError,
@@ -553,6 +689,8 @@ impl From<FsError> for io::Error {
             FsError::DirectoryNotEmpty => io::ErrorKind::Other,
             FsError::UnknownError => io::ErrorKind::Other,
             FsError::StorageFull => io::ErrorKind::Other,
+            FsError::TooManyLinks => io::ErrorKind::Other,
+            FsError::Unsupported => io::ErrorKind::Unsupported,
             // NOTE: Add this once the "io_error_more" Rust feature is stabilized
             // FsError::StorageFull => io::ErrorKind::StorageFull,
         };
@@ -577,6 +715,7 @@ impl ReadDir {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct DirEntry {
     pub path: PathBuf,
     // weird hack, to fix this we probably need an internal trait object or callbacks or something
@@ -609,8 +748,17 @@ impl DirEntry {
     }
 }
 
+/// The permission bits given to new files (`rw-r--r--`).
+pub const DEFAULT_FILE_MODE: u32 = 0o644;
+/// The permission bits given to new directories (`rwxr-xr-x`).
+pub const DEFAULT_DIR_MODE: u32 = 0o755;
+/// The permission bits of entries that come from packages and other
+/// read-only sources (`rwxr-xr-x`), so the programs they hold can be run.
+pub const PACKAGE_MODE: u32 = 0o755;
+
 #[allow(clippy::len_without_is_empty)] // Clippy thinks it's an iterator.
 #[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 // TODO: review this, proper solution would probably use a trait object internally
 pub struct Metadata {
     pub ft: FileType,
@@ -618,9 +766,52 @@ pub struct Metadata {
     pub created: u64,
     pub modified: u64,
     pub len: u64,
+    /// The permission bits, as in the lower 12 bits of `st_mode`
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    /// The device and the inode number that identify the entry, zero when
+    /// the file system doesn't number its entries
+    pub dev: u64,
+    pub ino: u64,
 }
 
 impl Metadata {
+    /// Creates the metadata of an entry with the default permissions of its
+    /// type, use the `with_*` methods to fill in the rest.
+    pub fn new(ft: FileType, len: u64) -> Self {
+        Metadata {
+            mode: ft.default_mode(),
+            ft,
+            len,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_times(mut self, accessed: u64, created: u64, modified: u64) -> Self {
+        self.accessed = accessed;
+        self.created = created;
+        self.modified = modified;
+        self
+    }
+
+    pub fn with_mode(mut self, mode: u32) -> Self {
+        self.mode = mode & 0o7777;
+        self
+    }
+
+    pub fn with_owner(mut self, uid: u32, gid: u32) -> Self {
+        self.uid = uid;
+        self.gid = gid;
+        self
+    }
+
+    pub fn with_inode(mut self, dev: u64, ino: u64) -> Self {
+        self.dev = dev;
+        self.ino = ino;
+        self
+    }
+
     pub fn is_file(&self) -> bool {
         self.ft.is_file()
     }
@@ -648,9 +839,32 @@ impl Metadata {
     pub fn len(&self) -> u64 {
         self.len
     }
+
+    pub fn mode(&self) -> u32 {
+        self.mode
+    }
+
+    pub fn uid(&self) -> u32 {
+        self.uid
+    }
+
+    pub fn gid(&self) -> u32 {
+        self.gid
+    }
+
+    /// Whether the owner is allowed to write to the file.
+    pub fn is_writable(&self) -> bool {
+        self.mode & 0o200 != 0
+    }
+
+    /// Whether any of the execute bits are set.
+    pub fn is_executable(&self) -> bool {
+        self.mode & 0o111 != 0
+    }
 }
 
 #[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 // TODO: review this, proper solution would probably use a trait object internally
 pub struct FileType {
     pub dir: bool,
@@ -686,6 +900,15 @@ impl FileType {
     pub fn is_fifo(&self) -> bool {
         self.fifo
     }
+
+    /// The permission bits given to new entries of this type.
+    pub fn default_mode(&self) -> u32 {
+        if self.dir {
+            DEFAULT_DIR_MODE
+        } else {
+            DEFAULT_FILE_MODE
+        }
+    }
 }
 
 impl Iterator for ReadDir {
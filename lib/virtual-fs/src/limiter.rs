@@ -2,7 +2,7 @@ use std::sync::Arc;
 
 use crate::FsError;
 
-pub use self::tracked_vec::TrackedVec;
+pub use self::tracked_vec::{TrackedBytes, TrackedVec};
 
 /// Allows tracking and limiting the memory usage of a memfs [`FileSystem`].
 pub trait FsMemoryLimiter: Send + Sync + std::fmt::Debug {
@@ -120,6 +120,31 @@ mod tracked_vec {
         }
     }
 
+    /// Accounts for bytes that are held somewhere else (e.g. by a chunk
+    /// shared between file systems) until it is dropped.
+    #[derive(Debug)]
+    pub struct TrackedBytes {
+        len: usize,
+        limiter: Option<DynFsMemoryLimiter>,
+    }
+
+    impl TrackedBytes {
+        pub fn new(len: usize, limiter: Option<DynFsMemoryLimiter>) -> Result<Self, FsError> {
+            if let Some(limiter) = &limiter {
+                limiter.on_grow(len)?;
+            }
+            Ok(Self { len, limiter })
+        }
+    }
+
+    impl Drop for TrackedBytes {
+        fn drop(&mut self) {
+            if let Some(limiter) = &self.limiter {
+                limiter.on_shrink(self.len);
+            }
+        }
+    }
+
     impl std::ops::Deref for TrackedVec {
         type Target = [u8];
 
@@ -194,6 +219,15 @@ mod tracked_vec {
         }
     }
 
+    #[derive(Debug)]
+    pub struct TrackedBytes;
+
+    impl TrackedBytes {
+        pub fn new(_len: usize, _limiter: Option<DynFsMemoryLimiter>) -> Result<Self, FsError> {
+            Ok(Self)
+        }
+    }
+
     impl std::ops::Deref for TrackedVec {
         type Target = Vec<u8>;
 
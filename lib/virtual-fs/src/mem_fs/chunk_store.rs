@@ -0,0 +1,383 @@
+//! Content addressed storage for the contents of files, shared between
+//! file systems.
+//!
+//! The contents of a file are split in chunks of [`CHUNK_SIZE`] bytes. Once a
+//! chunk is complete (or the file is flushed) it is interned in the
+//! [`ChunkStore`], so identical chunks written by different files, or by
+//! different file systems, are only kept once in memory. Chunks are reference
+//! counted and copied before being modified.
+
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    fmt,
+    hash::{Hash, Hasher},
+    sync::{Arc, Mutex, Weak},
+};
+
+use crate::{
+    limiter::{DynFsMemoryLimiter, TrackedBytes, TrackedVec},
+    FsError,
+};
+
+/// The size of the chunks the contents of files are split in.
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+/// The chunks shared by the file systems given to
+/// [`super::FileSystem::set_chunk_store`].
+///
+/// The memory of a shared chunk is accounted to the memory limiter of every
+/// file that uses it, so sharing can't be used to go beyond a limit.
+#[derive(Clone, Default)]
+pub struct ChunkStore {
+    inner: Arc<Mutex<StoreInner>>,
+}
+
+#[derive(Default)]
+struct StoreInner {
+    /// The chunks by hash, more than one chunk can have the same hash.
+    chunks: HashMap<u64, Vec<Weak<Chunk>>>,
+    count: usize,
+    bytes: usize,
+}
+
+impl ChunkStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of distinct chunks in use.
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The number of bytes held by the chunks in use.
+    pub fn bytes(&self) -> usize {
+        self.inner.lock().unwrap().bytes
+    }
+
+    /// Returns the chunk holding `data`, creating it if needed.
+    fn intern(&self, data: &[u8]) -> Arc<Chunk> {
+        let mut hasher = DefaultHasher::new();
+        data.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        // The chunks looked at are only dropped once the lock is released,
+        // dropping the last reference to a chunk needs the lock.
+        let mut candidates = Vec::new();
+        let mut inner = self.inner.lock().unwrap();
+        let bucket = inner.chunks.entry(hash).or_default();
+        candidates.extend(bucket.iter().filter_map(Weak::upgrade));
+
+        if let Some(chunk) = candidates.iter().find(|c| *c.data == *data) {
+            return chunk.clone();
+        }
+
+        let chunk = Arc::new(Chunk {
+            hash,
+            data: data.into(),
+            store: Arc::downgrade(&self.inner),
+        });
+        bucket.push(Arc::downgrade(&chunk));
+        inner.count += 1;
+        inner.bytes += data.len();
+        chunk
+    }
+}
+
+impl fmt::Debug for ChunkStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inner = self.inner.lock().unwrap();
+        f.debug_struct("ChunkStore")
+            .field("count", &inner.count)
+            .field("bytes", &inner.bytes)
+            .finish()
+    }
+}
+
+/// An immutable chunk of data, owned by a [`ChunkStore`].
+struct Chunk {
+    hash: u64,
+    data: Box<[u8]>,
+    store: Weak<Mutex<StoreInner>>,
+}
+
+impl Drop for Chunk {
+    fn drop(&mut self) {
+        // The store may already be gone
+        if let Some(store) = self.store.upgrade() {
+            let mut inner = store.lock().unwrap();
+            inner.count -= 1;
+            inner.bytes -= self.data.len();
+            if let Some(bucket) = inner.chunks.get_mut(&self.hash) {
+                bucket.retain(|chunk| chunk.strong_count() > 0);
+                if bucket.is_empty() {
+                    inner.chunks.remove(&self.hash);
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Chunk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Chunk")
+            .field("hash", &self.hash)
+            .field("len", &self.data.len())
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+enum Piece {
+    Shared(Arc<Chunk>, TrackedBytes),
+    /// A chunk that isn't complete yet, it is only visible to its file.
+    Owned(TrackedVec),
+}
+
+impl Piece {
+    fn data(&self) -> &[u8] {
+        match self {
+            Piece::Shared(chunk, _) => &chunk.data,
+            Piece::Owned(data) => data,
+        }
+    }
+}
+
+/// The contents of a file, stored as chunks. All the pieces but the last one
+/// are [`CHUNK_SIZE`] bytes long.
+#[derive(Debug)]
+pub(super) struct ChunkedData {
+    store: ChunkStore,
+    pieces: Vec<Piece>,
+    len: usize,
+    limiter: Option<DynFsMemoryLimiter>,
+}
+
+impl ChunkedData {
+    pub(super) fn new(store: ChunkStore, limiter: Option<DynFsMemoryLimiter>) -> Self {
+        Self {
+            store,
+            pieces: Vec::new(),
+            len: 0,
+            limiter,
+        }
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(super) fn clear(&mut self) {
+        self.pieces.clear();
+        self.len = 0;
+    }
+
+    /// Copies the bytes found at `offset` into `buf`, returns how many were
+    /// copied.
+    pub(super) fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let mut read = 0;
+        let mut offset = offset;
+        while read < buf.len() && offset < self.len {
+            let data = self.pieces[offset / CHUNK_SIZE].data();
+            let start = offset % CHUNK_SIZE;
+            let len = (data.len() - start).min(buf.len() - read);
+            buf[read..][..len].copy_from_slice(&data[start..][..len]);
+            read += len;
+            offset += len;
+        }
+        read
+    }
+
+    /// Inserts `data` at `offset`, moving what follows it.
+    pub(super) fn insert(&mut self, offset: usize, data: &[u8]) -> Result<(), FsError> {
+        if offset == self.len {
+            return self.append(data);
+        }
+
+        let (start, tail) = self.split_off(offset);
+        let at = offset - start;
+        let mut rewritten = Vec::with_capacity(tail.len() + data.len());
+        rewritten.extend_from_slice(&tail[..at]);
+        rewritten.extend_from_slice(data);
+        rewritten.extend_from_slice(&tail[at..]);
+        self.append(&rewritten)
+    }
+
+    pub(super) fn resize(&mut self, new_len: usize) -> Result<(), FsError> {
+        if new_len >= self.len {
+            return self.append(&vec![0; new_len - self.len]);
+        }
+
+        let (start, tail) = self.split_off(new_len);
+        self.append(&tail[..new_len - start])
+    }
+
+    /// Interns the pieces that aren't shared yet.
+    pub(super) fn seal(&mut self) {
+        for piece in &mut self.pieces {
+            if let Piece::Owned(data) = piece {
+                let chunk = self.store.intern(data);
+                // A piece whose shared chunk doesn't fit within the limit
+                // along with its own copy is left as it is.
+                if let Ok(tracked) = TrackedBytes::new(chunk.data.len(), self.limiter.clone()) {
+                    *piece = Piece::Shared(chunk, tracked);
+                }
+            }
+        }
+    }
+
+    fn append(&mut self, mut data: &[u8]) -> Result<(), FsError> {
+        while !data.is_empty() {
+            let last = self.last_owned()?;
+            let len = (CHUNK_SIZE - last.len()).min(data.len());
+            last.extend_from_slice(&data[..len])?;
+            let full = last.len() == CHUNK_SIZE;
+
+            self.len += len;
+            data = &data[len..];
+            if full {
+                self.seal();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The last piece, made writable by copying it if it is shared, or a new
+    /// piece when the last one is full.
+    fn last_owned(&mut self) -> Result<&mut TrackedVec, FsError> {
+        let last_len = self.pieces.last().map(|piece| piece.data().len());
+        if last_len.map_or(true, |len| len == CHUNK_SIZE) {
+            self.pieces
+                .push(Piece::Owned(TrackedVec::new(self.limiter.clone())));
+        } else if let Some(piece @ Piece::Shared(..)) = self.pieces.last_mut() {
+            let mut copy = TrackedVec::new(self.limiter.clone());
+            copy.extend_from_slice(piece.data())?;
+            *piece = Piece::Owned(copy);
+        }
+
+        match self.pieces.last_mut() {
+            Some(Piece::Owned(last)) => Ok(last),
+            _ => unreachable!("the last piece was just made writable"),
+        }
+    }
+
+    /// Removes the pieces from the one holding `offset` onward, and returns
+    /// where they started along with their contents.
+    fn split_off(&mut self, offset: usize) -> (usize, Vec<u8>) {
+        let index = offset / CHUNK_SIZE;
+        let start = index * CHUNK_SIZE;
+        let mut tail = Vec::with_capacity(self.len - start);
+        for piece in self.pieces.drain(index..) {
+            tail.extend_from_slice(piece.data());
+        }
+        self.len = start;
+        (start, tail)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::{mem_fs::FileSystem, ops, FileSystem as _};
+
+    #[tokio::test]
+    async fn identical_files_share_their_chunks() {
+        let store = ChunkStore::new();
+        let contents = (0..CHUNK_SIZE * 5 / 2)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<_>>();
+
+        let first = FileSystem::default();
+        first.set_chunk_store(store.clone());
+        ops::write(&first, "/data.bin", &contents).await.unwrap();
+        assert_eq!(store.len(), 3, "two full chunks and the rest");
+
+        let second = FileSystem::default();
+        second.set_chunk_store(store.clone());
+        ops::create_dir_all(&second, "/nested").unwrap();
+        ops::write(&second, "/nested/copy.bin", &contents)
+            .await
+            .unwrap();
+        assert_eq!(store.len(), 3, "the chunks are shared");
+        assert_eq!(store.bytes(), contents.len());
+
+        // Changing a copy leaves the other one alone
+        let mut file = second
+            .new_open_options()
+            .write(true)
+            .open(Path::new("/nested/copy.bin"))
+            .unwrap();
+        file.set_len(10).unwrap();
+        drop(file);
+        assert_eq!(ops::read(&first, "/data.bin").await.unwrap(), contents);
+        assert_eq!(
+            ops::read(&second, "/nested/copy.bin").await.unwrap(),
+            &contents[..10],
+        );
+
+        drop(first);
+        drop(second);
+        assert!(store.is_empty(), "unused chunks are released");
+    }
+
+    #[test]
+    fn inserting_in_the_middle() {
+        let store = ChunkStore::new();
+        let mut data = ChunkedData::new(store, None);
+        data.insert(0, &vec![1; CHUNK_SIZE + 2]).unwrap();
+        data.insert(CHUNK_SIZE + 1, &[2, 2]).unwrap();
+        data.insert(1, &[3]).unwrap();
+
+        let mut buf = vec![0; data.len()];
+        assert_eq!(data.read_at(0, &mut buf), CHUNK_SIZE + 5);
+        assert_eq!(&buf[..3], &[1, 3, 1]);
+        assert_eq!(&buf[CHUNK_SIZE..], &[1, 1, 2, 2, 1]);
+    }
+
+    #[cfg(feature = "tracking")]
+    #[tokio::test]
+    async fn shared_chunks_count_towards_the_limit() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        use crate::limiter::FsMemoryLimiter;
+
+        #[derive(Debug, Default)]
+        struct Counter(AtomicUsize);
+
+        impl FsMemoryLimiter for Counter {
+            fn on_grow(&self, grown_bytes: usize) -> Result<(), FsError> {
+                self.0.fetch_add(grown_bytes, Ordering::SeqCst);
+                Ok(())
+            }
+
+            fn on_shrink(&self, shrunk_bytes: usize) {
+                self.0.fetch_sub(shrunk_bytes, Ordering::SeqCst);
+            }
+        }
+
+        let store = ChunkStore::new();
+        let counter = Arc::new(Counter::default());
+        let fs = FileSystem::default();
+        fs.set_chunk_store(store.clone());
+        fs.set_memory_limiter(counter.clone());
+
+        ops::write(&fs, "/data.bin", vec![7; CHUNK_SIZE * 2])
+            .await
+            .unwrap();
+        assert_eq!(store.len(), 1, "both chunks are the same");
+        assert!(
+            counter.0.load(Ordering::SeqCst) >= CHUNK_SIZE * 2,
+            "each use of a chunk is accounted",
+        );
+
+        fs.remove_file(Path::new("/data.bin")).unwrap();
+        assert_eq!(counter.0.load(Ordering::SeqCst), 0);
+    }
+}
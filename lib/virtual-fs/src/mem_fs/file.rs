@@ -6,6 +6,7 @@ use futures::future::BoxFuture;
 use tokio::io::AsyncRead;
 use tokio::io::{AsyncSeek, AsyncWrite};
 
+use super::chunk_store::{ChunkStore, ChunkedData};
 use super::*;
 use crate::limiter::TrackedVec;
 use crate::{CopyOnWriteFile, FsError, Result, VirtualFile};
@@ -173,29 +174,31 @@ impl VirtualFile for FileHandle {
     }
 
     fn set_len(&mut self, new_size: u64) -> Result<()> {
-        let mut fs = self.filesystem.inner.write().map_err(|_| FsError::Lock)?;
+        {
+            let mut fs = self.filesystem.inner.write().map_err(|_| FsError::Lock)?;
 
-        let inode = fs.storage.get_mut(self.inode);
-        match inode {
-            Some(Node::File(FileNode { file, metadata, .. })) => {
-                file.buffer
-                    .resize(new_size.try_into().map_err(|_| FsError::UnknownError)?, 0)?;
-                metadata.len = new_size;
-            }
-            Some(Node::CustomFile(node)) => {
-                let mut file = node.file.lock().unwrap();
-                file.set_len(new_size)?;
-                node.metadata.len = new_size;
-            }
-            Some(Node::ReadOnlyFile { .. }) => return Err(FsError::PermissionDenied),
-            Some(Node::ArcFile { .. }) => {
-                drop(fs);
-                let file = self.lazy_load_arc_file_mut()?;
-                file.set_len(new_size)?;
+            let inode = fs.storage.get_mut(self.inode);
+            match inode {
+                Some(Node::File(FileNode { file, metadata, .. })) => {
+                    file.resize(new_size.try_into().map_err(|_| FsError::UnknownError)?)?;
+                    metadata.len = new_size;
+                }
+                Some(Node::CustomFile(node)) => {
+                    let mut file = node.file.lock().unwrap();
+                    file.set_len(new_size)?;
+                    node.metadata.len = new_size;
+                }
+                Some(Node::ReadOnlyFile { .. }) => return Err(FsError::PermissionDenied),
+                Some(Node::ArcFile { .. }) => {
+                    drop(fs);
+                    let file = self.lazy_load_arc_file_mut()?;
+                    file.set_len(new_size)?;
+                }
+                _ => return Err(FsError::NotAFile),
             }
-            _ => return Err(FsError::NotAFile),
         }
 
+        self.filesystem.notify_modified(self.inode);
         Ok(())
     }
 
@@ -237,11 +240,8 @@ impl VirtualFile for FileHandle {
                 // Write lock.
                 let mut fs = filesystem.inner.write().map_err(|_| FsError::Lock)?;
 
-                // Remove the file from the storage.
-                fs.storage.remove(inode_of_file);
-
-                // Remove the child from the parent directory.
-                fs.remove_child_from_node(inode_of_parent, position)?;
+                // Remove the file from the storage and the parent directory.
+                fs.unlink_node(inode_of_parent, position, inode_of_file)?;
             }
 
             Ok(())
@@ -300,6 +300,10 @@ impl VirtualFile for FileHandle {
                         created: src.created_time(),
                         modified: src.last_modified(),
                         len: src.size(),
+                        mode: inode.metadata().mode,
+                        uid: inode.metadata().uid,
+                        gid: inode.metadata().gid,
+                        ..Default::default()
                     };
 
                     *inode = Node::CustomFile(CustomFileNode {
@@ -334,7 +338,7 @@ impl VirtualFile for FileHandle {
         let inode = fs.storage.get_mut(self.inode);
         match inode {
             Some(Node::File(node)) => {
-                let remaining = node.file.buffer.len() - (self.cursor as usize);
+                let remaining = node.file.len() - (self.cursor as usize);
                 Poll::Ready(Ok(remaining))
             }
             Some(Node::ReadOnlyFile(node)) => {
@@ -855,6 +859,7 @@ impl AsyncWrite for FileHandle {
             }
         };
         self.cursor = cursor;
+        self.filesystem.notify_modified(self.inode);
         Poll::Ready(Ok(bytes_written))
     }
 
@@ -877,7 +882,7 @@ impl AsyncWrite for FileHandle {
                         .find(|b| !b.is_empty())
                         .map_or(&[][..], |b| &**b);
                     let bytes_written = node.file.write(buf, &mut cursor)?;
-                    node.metadata.len = node.file.buffer.len() as u64;
+                    node.metadata.len = node.file.len() as u64;
                     Poll::Ready(Ok(bytes_written))
                 }
                 Some(Node::ReadOnlyFile(node)) => {
@@ -914,6 +919,11 @@ impl AsyncWrite for FileHandle {
             }
         };
         self.cursor = cursor;
+        if let Poll::Ready(Ok(bytes_written)) = &ret {
+            if *bytes_written > 0 {
+                self.filesystem.notify_modified(self.inode);
+            }
+        }
         ret
     }
 
@@ -1268,30 +1278,66 @@ impl fmt::Debug for FileHandle {
 /// represents a read/write position in the buffer.
 #[derive(Debug)]
 pub(super) struct File {
-    buffer: TrackedVec,
+    data: FileData,
+}
+
+/// Where the bytes of a [`File`] are kept.
+#[derive(Debug)]
+enum FileData {
+    Buffer(TrackedVec),
+    /// The bytes are split in chunks shared through a [`ChunkStore`].
+    Chunks(ChunkedData),
 }
 
 impl File {
-    pub(super) fn new(limiter: Option<crate::limiter::DynFsMemoryLimiter>) -> Self {
-        Self {
-            buffer: TrackedVec::new(limiter),
-        }
+    pub(super) fn new(
+        limiter: Option<crate::limiter::DynFsMemoryLimiter>,
+        chunks: Option<ChunkStore>,
+    ) -> Self {
+        let data = match chunks {
+            Some(store) => FileData::Chunks(ChunkedData::new(store, limiter)),
+            None => FileData::Buffer(TrackedVec::new(limiter)),
+        };
+        Self { data }
     }
 
     pub(super) fn truncate(&mut self) {
-        self.buffer.clear();
+        match &mut self.data {
+            FileData::Buffer(buffer) => buffer.clear(),
+            FileData::Chunks(chunks) => chunks.clear(),
+        }
     }
 
     pub(super) fn len(&self) -> usize {
-        self.buffer.len()
+        match &self.data {
+            FileData::Buffer(buffer) => buffer.len(),
+            FileData::Chunks(chunks) => chunks.len(),
+        }
+    }
+
+    pub(super) fn resize(&mut self, new_len: usize) -> Result<()> {
+        match &mut self.data {
+            FileData::Buffer(buffer) => buffer.resize(new_len, 0),
+            FileData::Chunks(chunks) => chunks.resize(new_len),
+        }
     }
 }
 
 impl File {
     pub fn read(&self, buf: &mut [u8], cursor: &mut u64) -> io::Result<usize> {
         let cur_pos = *cursor as usize;
-        let max_to_read = cmp::min(self.buffer.len() - cur_pos, buf.len());
-        let data_to_copy = &self.buffer[cur_pos..][..max_to_read];
+
+        let buffer = match &self.data {
+            FileData::Buffer(buffer) => buffer,
+            FileData::Chunks(chunks) => {
+                let read = chunks.read_at(cur_pos, buf);
+                *cursor += read as u64;
+                return Ok(read);
+            }
+        };
+
+        let max_to_read = cmp::min(buffer.len() - cur_pos, buf.len());
+        let data_to_copy = &buffer[cur_pos..][..max_to_read];
 
         // SAFETY: `buf[..max_to_read]` and `data_to_copy` have the same size, due to
         // how `max_to_read` is computed.
@@ -1314,7 +1360,7 @@ impl File {
 
             // Calculate from the end, so `buffer.len() + offset`.
             io::SeekFrom::End(offset) => {
-                TryInto::<i64>::try_into(self.buffer.len()).map_err(to_err)? + offset
+                TryInto::<i64>::try_into(self.len()).map_err(to_err)? + offset
             }
 
             // Calculate from the current cursor, so `cursor + offset`.
@@ -1334,7 +1380,7 @@ impl File {
         // In this implementation, it's an error to seek beyond the
         // end of the buffer.
         let next_cursor = next_cursor.try_into().map_err(to_err)?;
-        *cursor = cmp::min(self.buffer.len() as u64, next_cursor);
+        *cursor = cmp::min(self.len() as u64, next_cursor);
 
         let cursor = *cursor;
         Ok(cursor)
@@ -1343,10 +1389,20 @@ impl File {
 
 impl File {
     pub fn write(&mut self, buf: &[u8], cursor: &mut u64) -> io::Result<usize> {
+        let buffer = match &mut self.data {
+            FileData::Buffer(buffer) => buffer,
+            FileData::Chunks(chunks) => {
+                // Like below, the data is inserted at the cursor.
+                chunks.insert(*cursor as usize, buf)?;
+                *cursor += buf.len() as u64;
+                return Ok(buf.len());
+            }
+        };
+
         match *cursor {
             // The cursor is at the end of the buffer: happy path!
-            position if position == self.buffer.len() as u64 => {
-                self.buffer.extend_from_slice(buf)?;
+            position if position == buffer.len() as u64 => {
+                buffer.extend_from_slice(buf)?;
             }
 
             // The cursor is at the beginning of the buffer (and the
@@ -1354,24 +1410,22 @@ impl File {
             // caught by the previous arm): almost a happy path!
             0 => {
                 // FIXME(perf,theduke): make this faster, it's horrible!
-                let mut new_buffer = TrackedVec::with_capacity(
-                    self.buffer.len() + buf.len(),
-                    self.buffer.limiter().cloned(),
-                )?;
+                let mut new_buffer =
+                    TrackedVec::with_capacity(buffer.len() + buf.len(), buffer.limiter().cloned())?;
                 new_buffer.extend_from_slice(buf)?;
-                new_buffer.append(&mut self.buffer)?;
+                new_buffer.append(buffer)?;
 
-                self.buffer = new_buffer;
+                *buffer = new_buffer;
             }
 
             // The cursor is somewhere in the buffer: not the happy path.
             position => {
-                self.buffer.reserve_exact(buf.len())?;
+                buffer.reserve_exact(buf.len())?;
 
                 // FIXME(perf,theduke): make this faster, it's horrible!
-                let mut remainder = self.buffer.split_off(position as usize)?;
-                self.buffer.extend_from_slice(buf)?;
-                self.buffer.append(&mut remainder)?;
+                let mut remainder = buffer.split_off(position as usize)?;
+                buffer.extend_from_slice(buf)?;
+                buffer.append(&mut remainder)?;
             }
         }
 
@@ -1381,6 +1435,10 @@ impl File {
     }
 
     fn flush(&mut self) -> io::Result<()> {
+        // What was written so far can now be shared.
+        if let FileData::Chunks(chunks) = &mut self.data {
+            chunks.seal();
+        }
         Ok(())
     }
 }
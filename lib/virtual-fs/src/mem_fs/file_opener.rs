@@ -1,6 +1,9 @@
 use super::filesystem::InodeResolution;
 use super::*;
-use crate::{FileType, FsError, Metadata, OpenOptionsConfig, Result, VirtualFile};
+use crate::{
+    FileType, FsError, FsEvent, Metadata, OpenOptionsConfig, Result, VirtualFile,
+    DEFAULT_FILE_MODE, PACKAGE_MODE,
+};
 use std::borrow::Cow;
 use std::path::Path;
 use tracing::*;
@@ -49,6 +52,10 @@ impl FileSystem {
                             created: time,
                             modified: time,
                             len: file_len,
+                            mode: PACKAGE_MODE,
+                            uid: 0,
+                            gid: 0,
+                            ..Default::default()
                         }
                     },
                 }));
@@ -109,6 +116,10 @@ impl FileSystem {
                             created: time,
                             modified: time,
                             len: 0,
+                            mode: PACKAGE_MODE,
+                            uid: 0,
+                            gid: 0,
+                            ..Default::default()
                         }
                     }
                 };
@@ -194,6 +205,10 @@ impl FileSystem {
                                 created: time,
                                 modified: time,
                                 len: 0,
+                                mode: PACKAGE_MODE,
+                                uid: 0,
+                                gid: 0,
+                                ..Default::default()
                             }
                         },
                     }));
@@ -265,6 +280,10 @@ impl FileSystem {
                     created: time,
                     modified: time,
                     len: 0,
+                    mode: DEFAULT_FILE_MODE,
+                    uid: 0,
+                    gid: 0,
+                    ..Default::default()
                 }
             },
         }));
@@ -353,6 +372,15 @@ impl crate::FileOpener for FileSystem {
             write = false;
         }
 
+        // Opening a symlink opens the file it points to, which might
+        // not exist yet.
+        let path = self
+            .inner
+            .read()
+            .map_err(|_| FsError::Lock)?
+            .follow_symlinks(path)?;
+        let path = path.as_path();
+
         let (inode_of_parent, maybe_inode_of_file, name_of_file) = self.insert_inode(path)?;
 
         let inode_of_parent = match inode_of_parent {
@@ -384,6 +412,19 @@ impl crate::FileOpener for FileSystem {
                 // Write lock.
                 let mut fs = self.inner.write().map_err(|_| FsError::Lock)?;
 
+                // A hard link shares the node it refers to.
+                let inode_of_file = fs.hard_link_target(inode_of_file);
+
+                // Writing requires the write permission bit.
+                if write || append || truncate {
+                    match fs.storage.get(inode_of_file) {
+                        Some(node) if !node.metadata().is_writable() => {
+                            return Err(FsError::PermissionDenied);
+                        }
+                        _ => {}
+                    }
+                }
+
                 let inode = fs.storage.get_mut(inode_of_file);
                 match inode {
                     Some(Node::File(FileNode { metadata, file, .. })) => {
@@ -460,6 +501,10 @@ impl crate::FileOpener for FileSystem {
                     _ => return Err(FsError::NotAFile),
                 }
 
+                if truncate {
+                    self.notify(|| FsEvent::Modify(path.to_path_buf()));
+                }
+
                 inode_of_file
             }
 
@@ -470,7 +515,7 @@ impl crate::FileOpener for FileSystem {
                 // Write lock.
                 let mut fs = self.inner.write().map_err(|_| FsError::Lock)?;
 
-                let file = File::new(fs.limiter.clone());
+                let file = File::new(fs.limiter.clone(), fs.chunks.clone());
 
                 // Creating the file in the storage.
                 let inode_of_file = fs.storage.vacant_entry().key();
@@ -490,6 +535,10 @@ impl crate::FileOpener for FileSystem {
                             created: time,
                             modified: time,
                             len: 0,
+                            mode: DEFAULT_FILE_MODE,
+                            uid: 0,
+                            gid: 0,
+                            ..Default::default()
                         }
                     },
                 }));
@@ -502,6 +551,8 @@ impl crate::FileOpener for FileSystem {
                 // Adding the new directory to its parent.
                 fs.add_child_to_node(inode_of_parent, inode_of_file)?;
 
+                self.notify(|| FsEvent::Create(path.to_path_buf()));
+
                 inode_of_file
             }
 
@@ -1,15 +1,20 @@
 //! This module contains the [`FileSystem`] type itself.
 
 use super::*;
-use crate::{DirEntry, FileSystem as _, FileType, FsError, Metadata, OpenOptions, ReadDir, Result};
+use crate::{
+    watch::WatchRegistry, DirEntry, FileSystem as _, FileType, FsError, FsEvent, FsWatcher,
+    Metadata, OpenOptions, ReadDir, Result, DEFAULT_DIR_MODE,
+};
 use futures::future::BoxFuture;
 use slab::Slab;
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 use std::convert::identity;
 use std::ffi::OsString;
 use std::fmt;
 use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
 
 /// The in-memory file system!
 ///
@@ -18,6 +23,7 @@ use std::sync::{Arc, RwLock};
 #[derive(Clone, Default)]
 pub struct FileSystem {
     pub(super) inner: Arc<RwLock<FileSystemInner>>,
+    pub(super) watchers: WatchRegistry,
 }
 
 impl FileSystem {
@@ -25,6 +31,36 @@ impl FileSystem {
         self.inner.write().unwrap().limiter = Some(limiter);
     }
 
+    /// Stores the contents of the files created from now on as chunks shared
+    /// through `store`, so identical files written by the file systems using
+    /// the same store are only kept once in memory.
+    pub fn set_chunk_store(&self, store: ChunkStore) {
+        self.inner.write().unwrap().chunks = Some(store);
+    }
+
+    /// Tells the watchers about a change, the event is only built when
+    /// someone is watching.
+    pub(super) fn notify(&self, event: impl FnOnce() -> FsEvent) {
+        if !self.watchers.is_empty() {
+            self.watchers.notify(event());
+        }
+    }
+
+    /// Tells the watchers that the contents of a file changed.
+    pub(super) fn notify_modified(&self, inode: Inode) {
+        if self.watchers.is_empty() {
+            return;
+        }
+
+        let path = match self.inner.read() {
+            Ok(fs) => fs.path_of(inode),
+            Err(_) => None,
+        };
+        if let Some(path) = path {
+            self.watchers.notify(FsEvent::Modify(path));
+        }
+    }
+
     pub fn new_open_options_ext(&self) -> &FileSystem {
         self
     }
@@ -223,6 +259,10 @@ impl FileSystem {
                         created: time,
                         modified: time,
                         len: 0,
+                        mode: DEFAULT_DIR_MODE,
+                        uid: 0,
+                        gid: 0,
+                        ..Default::default()
                     }
                 },
             }));
@@ -267,7 +307,7 @@ impl crate::FileSystem for FileSystem {
 
                         entry_path
                     },
-                    metadata: Ok(node.metadata().clone()),
+                    metadata: guard.metadata_of(guard.follow_hard_link(node).inode()),
                 })
                 .collect(),
 
@@ -342,6 +382,10 @@ impl crate::FileSystem for FileSystem {
                         created: time,
                         modified: time,
                         len: 0,
+                        mode: DEFAULT_DIR_MODE,
+                        uid: 0,
+                        gid: 0,
+                        ..Default::default()
                     }
                 },
             }));
@@ -355,6 +399,7 @@ impl crate::FileSystem for FileSystem {
             fs.add_child_to_node(inode_of_parent, inode_of_directory)?;
         }
 
+        self.notify(|| FsEvent::Create(path.to_path_buf()));
         Ok(())
     }
 
@@ -415,6 +460,7 @@ impl crate::FileSystem for FileSystem {
             fs.remove_child_from_node(inode_of_parent, position)?;
         }
 
+        self.notify(|| FsEvent::Remove(path.to_path_buf()));
         Ok(())
     }
 
@@ -487,17 +533,22 @@ impl crate::FileSystem for FileSystem {
                 let mut fs = self.inner.write().map_err(|_| FsError::Lock)?;
 
                 if let Some((position, inode_of_file)) = inode_dest {
-                    // Remove the file from the storage.
+                    // Remove the file from the storage and its parent.
                     match inode_of_file {
+                        // Both names refer to the same file, there is nothing
+                        // to do
+                        InodeResolution::Found(inode_of_file)
+                            if fs.hard_link_target(inode_of_file) == fs.hard_link_target(inode) =>
+                        {
+                            return Ok(());
+                        }
                         InodeResolution::Found(inode_of_file) => {
-                            fs.storage.remove(inode_of_file);
+                            fs.unlink_node(inode_of_to_parent, position, inode_of_file)?;
                         }
                         InodeResolution::Redirect(..) => {
                             return Err(FsError::InvalidInput);
                         }
                     }
-
-                    fs.remove_child_from_node(inode_of_to_parent, position)?;
                 }
 
                 // Update the file name, and update the modified time.
@@ -512,6 +563,11 @@ impl crate::FileSystem for FileSystem {
                     // Add the file to its new parent, and update the modified
                     // time.
                     fs.add_child_to_node(inode_of_to_parent, inode)?;
+                    if let Some(Node::HardLink(HardLinkNode { parent, .. })) =
+                        fs.storage.get_mut(inode)
+                    {
+                        *parent = inode_of_to_parent;
+                    }
                 }
                 // Otherwise, we need to at least update the modified time of the parent.
                 else {
@@ -524,6 +580,10 @@ impl crate::FileSystem for FileSystem {
                 }
             }
 
+            self.notify(|| FsEvent::Rename {
+                from: from.to_path_buf(),
+                to: to.to_path_buf(),
+            });
             Ok(())
         })
     }
@@ -532,12 +592,7 @@ impl crate::FileSystem for FileSystem {
         // Read lock.
         let guard = self.inner.read().map_err(|_| FsError::Lock)?;
         match guard.inode_of(path)? {
-            InodeResolution::Found(inode) => Ok(guard
-                .storage
-                .get(inode)
-                .ok_or(FsError::UnknownError)?
-                .metadata()
-                .clone()),
+            InodeResolution::Found(inode) => guard.metadata_of(inode),
             InodeResolution::Redirect(fs, path) => {
                 drop(guard);
                 fs.metadata(path.as_path())
@@ -545,6 +600,18 @@ impl crate::FileSystem for FileSystem {
         }
     }
 
+    fn symlink_metadata(&self, path: &Path) -> Result<Metadata> {
+        // Read lock.
+        let guard = self.inner.read().map_err(|_| FsError::Lock)?;
+        match guard.symlink_inode_of(path)? {
+            InodeResolution::Found(inode) => guard.metadata_of(inode),
+            InodeResolution::Redirect(fs, path) => {
+                drop(guard);
+                fs.symlink_metadata(path.as_path())
+            }
+        }
+    }
+
     fn remove_file(&self, path: &Path) -> Result<()> {
         let (inode_of_parent, position, inode_of_file) = {
             // Read lock.
@@ -592,19 +659,270 @@ impl crate::FileSystem for FileSystem {
             // Write lock.
             let mut fs = self.inner.write().map_err(|_| FsError::Lock)?;
 
-            // Remove the file from the storage.
-            fs.storage.remove(inode_of_file);
-
-            // Remove the child from the parent directory.
-            fs.remove_child_from_node(inode_of_parent, position)?;
+            // Remove the file from the storage and the parent directory.
+            fs.unlink_node(inode_of_parent, position, inode_of_file)?;
         }
 
+        self.notify(|| FsEvent::Remove(path.to_path_buf()));
         Ok(())
     }
 
     fn new_open_options(&self) -> OpenOptions {
         OpenOptions::new(self)
     }
+
+    fn symlink(&self, target: &Path, link: &Path) -> Result<()> {
+        let (inode_of_parent, name_of_link) = {
+            // Read lock.
+            let guard = self.inner.read().map_err(|_| FsError::Lock)?;
+
+            // Canonicalize the path without checking the path exists,
+            // because it's about to be created.
+            let path = guard.canonicalize_without_inode(link)?;
+
+            // Check the path has a parent.
+            let parent_of_path = path.parent().ok_or(FsError::BaseNotDirectory)?;
+
+            // Check the link name.
+            let name_of_link = path
+                .file_name()
+                .ok_or(FsError::InvalidInput)?
+                .to_os_string();
+
+            // Find the parent inode.
+            let inode_of_parent = match guard.inode_of_parent(parent_of_path)? {
+                InodeResolution::Found(a) => a,
+                InodeResolution::Redirect(fs, mut path) => {
+                    drop(guard);
+                    path.push(name_of_link);
+                    return fs.symlink(target, path.as_path());
+                }
+            };
+
+            // A symlink never replaces an existing entry.
+            if guard.symlink_inode_of(&path).is_ok() {
+                return Err(FsError::AlreadyExists);
+            }
+
+            (inode_of_parent, name_of_link)
+        };
+
+        {
+            // Write lock.
+            let mut fs = self.inner.write().map_err(|_| FsError::Lock)?;
+
+            // Creating the symlink in the storage.
+            let inode_of_link = fs.storage.vacant_entry().key();
+            let real_inode_of_link = fs.storage.insert(Node::Symlink(SymlinkNode {
+                inode: inode_of_link,
+                name: name_of_link,
+                target: target.to_path_buf(),
+                metadata: {
+                    let time = time();
+
+                    Metadata {
+                        ft: FileType {
+                            symlink: true,
+                            ..Default::default()
+                        },
+                        accessed: time,
+                        created: time,
+                        modified: time,
+                        len: target.as_os_str().len() as u64,
+                        mode: 0o777,
+                        uid: 0,
+                        gid: 0,
+                        ..Default::default()
+                    }
+                },
+            }));
+
+            assert_eq!(
+                inode_of_link, real_inode_of_link,
+                "new symlink inode should have been correctly calculated",
+            );
+
+            // Adding the new symlink to its parent.
+            fs.add_child_to_node(inode_of_parent, inode_of_link)?;
+        }
+
+        self.notify(|| FsEvent::Create(link.to_path_buf()));
+        Ok(())
+    }
+
+    fn hard_link(&self, original: &Path, link: &Path) -> Result<()> {
+        let (inode_of_original, inode_of_parent, name_of_link) = {
+            // Read lock.
+            let guard = self.inner.read().map_err(|_| FsError::Lock)?;
+
+            // Find the node to link to, without following a symlink that
+            // would be in its place.
+            let inode_of_original = match guard.symlink_inode_of(original)? {
+                InodeResolution::Found(a) => guard.hard_link_target(a),
+                // Hard links can't span across mounted file systems.
+                InodeResolution::Redirect(..) => return Err(FsError::InvalidInput),
+            };
+
+            // Directories can't be hard linked.
+            match guard.storage.get(inode_of_original) {
+                Some(Node::Directory(_)) | Some(Node::ArcDirectory(_)) => {
+                    return Err(FsError::PermissionDenied)
+                }
+                Some(_) => {}
+                None => return Err(FsError::EntryNotFound),
+            }
+
+            // Canonicalize the path without checking the path exists,
+            // because it's about to be created.
+            let path = guard.canonicalize_without_inode(link)?;
+
+            // Check the path has a parent.
+            let parent_of_path = path.parent().ok_or(FsError::BaseNotDirectory)?;
+
+            // Check the link name.
+            let name_of_link = path
+                .file_name()
+                .ok_or(FsError::InvalidInput)?
+                .to_os_string();
+
+            // Find the parent inode.
+            let inode_of_parent = match guard.inode_of_parent(parent_of_path)? {
+                InodeResolution::Found(a) => a,
+                InodeResolution::Redirect(..) => return Err(FsError::InvalidInput),
+            };
+
+            // A hard link never replaces an existing entry.
+            if guard.symlink_inode_of(&path).is_ok() {
+                return Err(FsError::AlreadyExists);
+            }
+
+            (inode_of_original, inode_of_parent, name_of_link)
+        };
+
+        {
+            // Write lock.
+            let mut fs = self.inner.write().map_err(|_| FsError::Lock)?;
+
+            let metadata = fs
+                .storage
+                .get(inode_of_original)
+                .ok_or(FsError::EntryNotFound)?
+                .metadata()
+                .clone();
+
+            // Creating the hard link in the storage.
+            let inode_of_link = fs.storage.vacant_entry().key();
+            let real_inode_of_link = fs.storage.insert(Node::HardLink(HardLinkNode {
+                inode: inode_of_link,
+                name: name_of_link,
+                target: inode_of_original,
+                parent: inode_of_parent,
+                metadata,
+            }));
+
+            assert_eq!(
+                inode_of_link, real_inode_of_link,
+                "new hard link inode should have been correctly calculated",
+            );
+
+            // Adding the new hard link to its parent.
+            fs.add_child_to_node(inode_of_parent, inode_of_link)?;
+            fs.hard_links
+                .entry(inode_of_original)
+                .or_default()
+                .push(inode_of_link);
+        }
+
+        self.notify(|| FsEvent::Create(link.to_path_buf()));
+        Ok(())
+    }
+
+    fn read_link(&self, path: &Path) -> Result<PathBuf> {
+        // Read lock.
+        let guard = self.inner.read().map_err(|_| FsError::Lock)?;
+        match guard.symlink_inode_of(path)? {
+            InodeResolution::Found(inode) => match guard.storage.get(inode) {
+                Some(Node::Symlink(SymlinkNode { target, .. })) => Ok(target.clone()),
+                Some(_) => Err(FsError::InvalidInput),
+                None => Err(FsError::EntryNotFound),
+            },
+            InodeResolution::Redirect(fs, path) => {
+                drop(guard);
+                fs.read_link(path.as_path())
+            }
+        }
+    }
+
+    fn set_permissions(&self, path: &Path, mode: u32) -> Result<()> {
+        let inode_of_node = {
+            // Read lock.
+            let guard = self.inner.read().map_err(|_| FsError::Lock)?;
+            match guard.inode_of(path)? {
+                InodeResolution::Found(inode) => inode,
+                InodeResolution::Redirect(fs, path) => {
+                    drop(guard);
+                    return fs.set_permissions(path.as_path(), mode);
+                }
+            }
+        };
+
+        {
+            // Write lock.
+            let mut fs = self.inner.write().map_err(|_| FsError::Lock)?;
+
+            let metadata = fs
+                .storage
+                .get_mut(inode_of_node)
+                .ok_or(FsError::EntryNotFound)?
+                .metadata_mut();
+            metadata.mode = mode & 0o7777;
+        }
+
+        Ok(())
+    }
+
+    fn set_times(
+        &self,
+        path: &Path,
+        accessed: Option<SystemTime>,
+        modified: Option<SystemTime>,
+    ) -> Result<()> {
+        let inode_of_node = {
+            // Read lock.
+            let guard = self.inner.read().map_err(|_| FsError::Lock)?;
+            match guard.inode_of(path)? {
+                InodeResolution::Found(inode) => inode,
+                InodeResolution::Redirect(fs, path) => {
+                    drop(guard);
+                    return fs.set_times(path.as_path(), accessed, modified);
+                }
+            }
+        };
+
+        {
+            // Write lock.
+            let mut fs = self.inner.write().map_err(|_| FsError::Lock)?;
+
+            let metadata = fs
+                .storage
+                .get_mut(inode_of_node)
+                .ok_or(FsError::EntryNotFound)?
+                .metadata_mut();
+            // The times are kept in seconds, like `time()` gives them.
+            if let Some(accessed) = accessed {
+                metadata.accessed = unix_seconds(accessed);
+            }
+            if let Some(modified) = modified {
+                metadata.modified = unix_seconds(modified);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn watch(&self, path: &Path, recursive: bool) -> Result<FsWatcher> {
+        Ok(self.watchers.subscribe(path, recursive))
+    }
 }
 
 impl fmt::Debug for FileSystem {
@@ -618,8 +936,13 @@ impl fmt::Debug for FileSystem {
 /// The core of the file system. It contains a collection of `Node`s,
 /// indexed by their respective `Inode` in a slab.
 pub(super) struct FileSystemInner {
+    /// Tells the entries of this file system apart from those of the others
+    pub(super) dev: u64,
     pub(super) storage: Slab<Node>,
+    /// The hard links that point at each node which has some
+    pub(super) hard_links: HashMap<Inode, Vec<Inode>>,
     pub(super) limiter: Option<crate::limiter::DynFsMemoryLimiter>,
+    pub(super) chunks: Option<ChunkStore>,
 }
 
 #[derive(Debug)]
@@ -641,10 +964,22 @@ impl InodeResolution {
 }
 
 impl FileSystemInner {
-    /// Get the inode associated to a path if it exists.
+    /// Get the inode associated to a path if it exists, following
+    /// symbolic links.
     pub(super) fn inode_of(&self, path: &Path) -> Result<InodeResolution> {
-        // SAFETY: The root node always exists, so it's safe to unwrap here.
-        let mut node = self.storage.get(ROOT_INODE).unwrap();
+        self.resolve(path, true)
+    }
+
+    /// Like `Self::inode_of` but when the last component of the path
+    /// is a symbolic link, the inode of the link itself is returned.
+    pub(super) fn symlink_inode_of(&self, path: &Path) -> Result<InodeResolution> {
+        self.resolve(path, false)
+    }
+
+    /// Walk a path from the root. Symbolic links are followed in all
+    /// the intermediate components, and in the last one too if
+    /// `follow_last` is set. Hard links are always followed.
+    fn resolve(&self, path: &Path, follow_last: bool) -> Result<InodeResolution> {
         let mut components = path.components();
 
         match components.next() {
@@ -652,28 +987,144 @@ impl FileSystemInner {
             _ => return Err(FsError::BaseNotDirectory),
         }
 
-        while let Some(component) = components.next() {
-            node = match node {
-                Node::Directory(DirectoryNode { children, .. }) => children
+        // The nodes walked so far, the last one is where the next
+        // component is looked up.
+        let mut ancestors = vec![ROOT_INODE];
+        // The components left to walk, in reverse order so that the
+        // targets of symbolic links can be pushed on top.
+        let mut remaining = components.rev().collect::<Vec<_>>();
+        let mut hops = 0;
+
+        while let Some(component) = remaining.pop() {
+            let name = match component {
+                Component::RootDir => {
+                    ancestors.truncate(1);
+                    continue;
+                }
+                Component::CurDir => continue,
+                Component::ParentDir => {
+                    if ancestors.len() > 1 {
+                        ancestors.pop();
+                    }
+                    continue;
+                }
+                Component::Normal(name) => name,
+                Component::Prefix(_) => return Err(FsError::InvalidInput),
+            };
+
+            // SAFETY: The root is never removed from the ancestors.
+            let node = match self.storage.get(*ancestors.last().unwrap()) {
+                Some(Node::Directory(DirectoryNode { children, .. })) => children
                     .iter()
                     .filter_map(|inode| self.storage.get(*inode))
-                    .find(|node| node.name() == component.as_os_str())
+                    .find(|node| node.name() == name)
                     .ok_or(FsError::EntryNotFound)?,
-                Node::ArcDirectory(ArcDirectoryNode {
+                Some(Node::ArcDirectory(ArcDirectoryNode {
                     fs, path: fs_path, ..
-                }) => {
+                })) => {
                     let mut path = fs_path.clone();
-                    path.push(PathBuf::from(component.as_os_str()));
-                    for component in components.by_ref() {
-                        path.push(PathBuf::from(component.as_os_str()));
+                    path.push(name);
+                    for component in remaining.iter().rev() {
+                        path.push(component.as_os_str());
                     }
                     return Ok(InodeResolution::Redirect(fs.clone(), path));
                 }
                 _ => return Err(FsError::BaseNotDirectory),
             };
+
+            match self.follow_hard_link(node) {
+                Node::Symlink(SymlinkNode { target, .. })
+                    if follow_last || !remaining.is_empty() =>
+                {
+                    hops += 1;
+                    if hops > MAX_SYMLINK_HOPS {
+                        return Err(FsError::TooManyLinks);
+                    }
+
+                    // Relative targets are resolved from the directory
+                    // containing the link, which is the last ancestor.
+                    remaining.extend(target.components().rev());
+                }
+                node => ancestors.push(node.inode()),
+            }
         }
 
-        Ok(InodeResolution::Found(node.inode()))
+        // SAFETY: The root is never removed from the ancestors.
+        Ok(InodeResolution::Found(*ancestors.last().unwrap()))
+    }
+
+    /// Follow the symbolic links found in the last component of a
+    /// path, and return the first path that isn't a symbolic link. The
+    /// returned path doesn't necessarily exist.
+    pub(super) fn follow_symlinks(&self, path: &Path) -> Result<PathBuf> {
+        let mut path = self.canonicalize_without_inode(path)?;
+
+        for _ in 0..MAX_SYMLINK_HOPS {
+            let target = match self.symlink_inode_of(&path) {
+                Ok(InodeResolution::Found(inode)) => match self.storage.get(inode) {
+                    Some(Node::Symlink(SymlinkNode { target, .. })) => target,
+                    _ => return Ok(path),
+                },
+                Ok(InodeResolution::Redirect(..)) | Err(_) => return Ok(path),
+            };
+
+            let parent = path.parent().unwrap_or_else(|| Path::new("/"));
+            path = self.canonicalize_without_inode(&parent.join(target))?;
+        }
+
+        Err(FsError::TooManyLinks)
+    }
+
+    /// Finds a path leading to `inode` by walking the tree from the root.
+    pub(super) fn path_of(&self, inode: Inode) -> Option<PathBuf> {
+        let mut pending = vec![(ROOT_INODE, PathBuf::from("/"))];
+
+        while let Some((current, path)) = pending.pop() {
+            if current == inode {
+                return Some(path);
+            }
+
+            if let Some(Node::Directory(DirectoryNode { children, .. })) = self.storage.get(current)
+            {
+                for child in children {
+                    if let Some(node) = self.storage.get(*child) {
+                        pending.push((*child, path.join(node.name())));
+                    }
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Get the inode a hard link refers to, or the given inode if it
+    /// isn't a hard link.
+    pub(super) fn hard_link_target(&self, inode: Inode) -> Inode {
+        match self.storage.get(inode) {
+            Some(Node::HardLink(HardLinkNode { target, .. })) => *target,
+            _ => inode,
+        }
+    }
+
+    /// The metadata of an entry, along with the numbers that identify it.
+    pub(super) fn metadata_of(&self, inode: Inode) -> Result<Metadata> {
+        let node = self.storage.get(inode).ok_or(FsError::UnknownError)?;
+        // Inode numbers start at one, zero means they aren't known
+        Ok(node
+            .metadata()
+            .clone()
+            .with_inode(self.dev, inode as u64 + 1))
+    }
+
+    /// Get the node a hard link refers to, or the given node if it
+    /// isn't a hard link.
+    pub(super) fn follow_hard_link<'a>(&'a self, node: &'a Node) -> &'a Node {
+        match node {
+            Node::HardLink(HardLinkNode { target, .. }) => {
+                self.storage.get(*target).unwrap_or(node)
+            }
+            node => node,
+        }
     }
 
     /// Get the inode associated to a “parent path”. The returned
@@ -757,6 +1208,8 @@ impl FileSystemInner {
                     | Node::ReadOnlyFile(ReadOnlyFileNode { inode, name, .. })
                     | Node::CustomFile(CustomFileNode { inode, name, .. })
                     | Node::ArcFile(ArcFileNode { inode, name, .. })
+                    | Node::Symlink(SymlinkNode { inode, name, .. })
+                    | Node::HardLink(HardLinkNode { inode, name, .. })
                         if name.as_os_str() == name_of_file =>
                     {
                         Some(Some((nth, InodeResolution::Found(*inode))))
@@ -797,6 +1250,8 @@ impl FileSystemInner {
                     | Node::ReadOnlyFile(ReadOnlyFileNode { inode, name, .. })
                     | Node::CustomFile(CustomFileNode { inode, name, .. })
                     | Node::ArcFile(ArcFileNode { inode, name, .. })
+                    | Node::Symlink(SymlinkNode { inode, name, .. })
+                    | Node::HardLink(HardLinkNode { inode, name, .. })
                         if name.as_os_str() == name_of =>
                     {
                         Some(Some((nth, InodeResolution::Found(*inode))))
@@ -875,6 +1330,70 @@ impl FileSystemInner {
         }
     }
 
+    /// Remove the node represented by `inode`, which is the child at
+    /// position `position` of the directory node `inode_of_parent`.
+    ///
+    /// If hard links to the node still exist, the node takes the place
+    /// of one of them instead of being removed from the storage, so
+    /// that its inode (used by the open handles) stays valid.
+    pub(super) fn unlink_node(
+        &mut self,
+        inode_of_parent: Inode,
+        position: usize,
+        inode: Inode,
+    ) -> Result<()> {
+        self.remove_child_from_node(inode_of_parent, position)?;
+
+        // Removing a hard link only forgets it on the side of its target.
+        if let Some(Node::HardLink(HardLinkNode { target, .. })) = self.storage.get(inode) {
+            let target = *target;
+            if let Some(links) = self.hard_links.get_mut(&target) {
+                links.retain(|link| *link != inode);
+                if links.is_empty() {
+                    self.hard_links.remove(&target);
+                }
+            }
+            self.storage.remove(inode);
+            return Ok(());
+        }
+
+        let inode_of_link = match self
+            .hard_links
+            .get_mut(&inode)
+            .and_then(|links| links.pop())
+        {
+            Some(a) => a,
+            None => {
+                self.hard_links.remove(&inode);
+                self.storage.remove(inode);
+                return Ok(());
+            }
+        };
+        if self.hard_links.get(&inode).map_or(false, Vec::is_empty) {
+            self.hard_links.remove(&inode);
+        }
+
+        // Move the node to where the hard link lives.
+        let (name_of_link, inode_of_link_parent) = match self.storage.remove(inode_of_link) {
+            Node::HardLink(HardLinkNode { name, parent, .. }) => (name, parent),
+            _ => return Err(FsError::UnknownError),
+        };
+        self.update_node_name(inode, name_of_link)?;
+
+        match self.storage.get_mut(inode_of_link_parent) {
+            Some(Node::Directory(DirectoryNode { children, .. })) => {
+                let position_of_link = children
+                    .iter()
+                    .position(|child| *child == inode_of_link)
+                    .ok_or(FsError::UnknownError)?;
+                children[position_of_link] = inode;
+
+                Ok(())
+            }
+            _ => Err(FsError::UnknownError),
+        }
+    }
+
     /// Canonicalize a path, i.e. try to resolve to a canonical,
     /// absolute form of the path with all intermediate components
     /// normalized:
@@ -961,6 +1480,8 @@ impl fmt::Debug for FileSystemInner {
                         Node::CustomFile { .. } => "custom-file",
                         Node::Directory { .. } => "dir",
                         Node::ArcDirectory { .. } => "arc-dir",
+                        Node::Symlink { .. } => "symlink",
+                        Node::HardLink { .. } => "hard-link",
                     },
                     name = node.name().to_string_lossy(),
                     indentation_symbol = " ",
@@ -1010,12 +1531,21 @@ impl Default for FileSystemInner {
                 created: time,
                 modified: time,
                 len: 0,
+                mode: DEFAULT_DIR_MODE,
+                uid: 0,
+                gid: 0,
+                ..Default::default()
             },
         }));
 
+        static NEXT_DEV: AtomicU64 = AtomicU64::new(1);
+
         Self {
+            dev: NEXT_DEV.fetch_add(1, Ordering::Relaxed),
             storage: slab,
+            hard_links: HashMap::new(),
             limiter: None,
+            chunks: None,
         }
     }
 }
@@ -1038,11 +1568,14 @@ impl DirectoryMustBeEmpty {
 
 #[cfg(test)]
 mod test_filesystem {
-    use std::{borrow::Cow, path::Path};
+    use std::{
+        borrow::Cow,
+        path::{Path, PathBuf},
+    };
 
     use tokio::io::AsyncReadExt;
 
-    use crate::{mem_fs::*, ops, DirEntry, FileSystem as FS, FileType, FsError};
+    use crate::{mem_fs::*, ops, DirEntry, FileSystem as FS, FileType, FsError, FsEvent};
 
     macro_rules! path {
         ($path:expr) => {
@@ -1469,7 +2002,8 @@ mod test_filesystem {
                 accessed,
                 created,
                 modified,
-                len: 0
+                len: 0,
+                ..
             }) if accessed == created && created == modified && modified > 0
         ));
 
@@ -1486,7 +2020,8 @@ mod test_filesystem {
                 accessed,
                 created,
                 modified,
-                len: 0
+                len: 0,
+                ..
             } if accessed == created && created == modified && modified > 0
         ));
 
@@ -1502,7 +2037,8 @@ mod test_filesystem {
                     accessed,
                     created,
                     modified,
-                    len: 0
+                    len: 0,
+                    ..
                 }) if
                     accessed == foo_metadata.accessed &&
                     created == foo_metadata.created &&
@@ -1518,7 +2054,8 @@ mod test_filesystem {
                     accessed,
                     created,
                     modified,
-                    len: 0
+                    len: 0,
+                    ..
                 }) if
                     accessed == foo_metadata.accessed &&
                     created == foo_metadata.created &&
@@ -1849,4 +2386,233 @@ mod test_filesystem {
 
         assert_eq!(buf, b"a");
     }
+
+    #[tokio::test]
+    async fn test_symlink() {
+        let fs = FileSystem::default();
+        ops::create_dir_all(&fs, "/a/b").unwrap();
+        ops::write(&fs, "/a/b/file.txt", b"Hello, World!")
+            .await
+            .unwrap();
+
+        assert_eq!(
+            fs.symlink(path!("b/file.txt"), path!("/a/link.txt")),
+            Ok(()),
+            "creating a relative symlink",
+        );
+        assert_eq!(
+            fs.symlink(path!("/a/b"), path!("/dir")),
+            Ok(()),
+            "creating an absolute symlink to a directory",
+        );
+        assert_eq!(
+            fs.symlink(path!("/a/b"), path!("/dir")),
+            Err(FsError::AlreadyExists),
+            "a symlink doesn't replace an existing entry",
+        );
+
+        assert_eq!(
+            fs.read_link(path!("/a/link.txt")),
+            Ok(path!(buf "b/file.txt")),
+        );
+        assert_eq!(
+            fs.read_link(path!("/a/b/file.txt")),
+            Err(FsError::InvalidInput),
+            "reading the target of a regular file",
+        );
+
+        assert!(fs.metadata(path!("/a/link.txt")).unwrap().is_file());
+        assert!(fs
+            .symlink_metadata(path!("/a/link.txt"))
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert!(fs.metadata(path!("/dir")).unwrap().is_dir());
+
+        assert_eq!(
+            ops::read_to_string(&fs, "/a/link.txt").await.unwrap(),
+            "Hello, World!",
+            "opening a file through a symlink",
+        );
+        assert_eq!(
+            ops::read_to_string(&fs, "/dir/file.txt").await.unwrap(),
+            "Hello, World!",
+            "resolving a symlink in the middle of a path",
+        );
+
+        assert_eq!(fs.remove_file(path!("/a/link.txt")), Ok(()));
+        assert!(
+            ops::is_file(&fs, "/a/b/file.txt"),
+            "removing the symlink keeps its target",
+        );
+    }
+
+    #[tokio::test]
+    async fn test_symlink_loop() {
+        let fs = FileSystem::default();
+
+        assert_eq!(fs.symlink(path!("/b"), path!("/a")), Ok(()));
+        assert_eq!(fs.symlink(path!("/a"), path!("/b")), Ok(()));
+
+        assert_eq!(fs.metadata(path!("/a")), Err(FsError::TooManyLinks));
+        assert_eq!(
+            fs.metadata(path!("/a/file.txt")),
+            Err(FsError::TooManyLinks),
+        );
+        assert!(
+            matches!(
+                fs.new_open_options().read(true).open(path!("/a")),
+                Err(FsError::TooManyLinks),
+            ),
+            "opening a symlink loop",
+        );
+        assert!(fs.symlink_metadata(path!("/a")).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_hard_link() {
+        let fs = FileSystem::default();
+        ops::write(&fs, "/original.txt", b"Hello").await.unwrap();
+        fs.create_dir(path!("/dir")).unwrap();
+
+        assert_eq!(
+            fs.hard_link(path!("/original.txt"), path!("/dir/link.txt")),
+            Ok(()),
+            "creating a hard link",
+        );
+        assert_eq!(
+            fs.hard_link(path!("/dir"), path!("/dir-link")),
+            Err(FsError::PermissionDenied),
+            "directories can't be hard linked",
+        );
+
+        ops::write(&fs, "/dir/link.txt", b"World").await.unwrap();
+        assert_eq!(
+            ops::read_to_string(&fs, "/original.txt").await.unwrap(),
+            "World",
+            "both names share the same contents",
+        );
+
+        assert_eq!(fs.remove_file(path!("/original.txt")), Ok(()));
+        assert!(!ops::exists(&fs, "/original.txt"));
+        assert_eq!(
+            ops::read_to_string(&fs, "/dir/link.txt").await.unwrap(),
+            "World",
+            "the contents outlive the original name",
+        );
+        assert!(fs.metadata(path!("/dir/link.txt")).unwrap().is_file());
+
+        // Links that were moved to another directory take the place of
+        // the removed name there
+        fs.create_dir(path!("/other")).unwrap();
+        fs.hard_link(path!("/dir/link.txt"), path!("/second.txt"))
+            .unwrap();
+        fs.rename(path!("/second.txt"), path!("/other/second.txt"))
+            .await
+            .unwrap();
+        assert_eq!(
+            fs.rename(path!("/dir/link.txt"), path!("/other/second.txt"))
+                .await,
+            Ok(()),
+            "renaming over another name of the same file does nothing",
+        );
+        assert!(ops::exists(&fs, "/dir/link.txt"));
+        assert_eq!(fs.remove_file(path!("/dir/link.txt")), Ok(()));
+        assert_eq!(
+            ops::read_to_string(&fs, "/other/second.txt").await.unwrap(),
+            "World",
+        );
+        assert_eq!(fs.remove_file(path!("/other/second.txt")), Ok(()));
+        assert!(fs.inner.read().unwrap().hard_links.is_empty());
+        assert_eq!(
+            fs.read_dir(path!("/other")).unwrap().count(),
+            0,
+            "the last name takes the file with it",
+        );
+    }
+
+    #[tokio::test]
+    async fn test_set_permissions() {
+        let fs = FileSystem::default();
+
+        ops::write(&fs, "/script.sh", b"#!/bin/sh").await.unwrap();
+        assert_eq!(
+            fs.metadata(path!("/script.sh")).unwrap().mode(),
+            crate::DEFAULT_FILE_MODE,
+            "new files get the default permissions",
+        );
+        assert_eq!(
+            fs.metadata(path!("/")).unwrap().mode(),
+            crate::DEFAULT_DIR_MODE,
+            "directories get the default permissions",
+        );
+
+        assert_eq!(fs.set_permissions(path!("/script.sh"), 0o100755), Ok(()));
+        let metadata = fs.metadata(path!("/script.sh")).unwrap();
+        assert_eq!(metadata.mode(), 0o755, "only the permission bits are kept");
+        assert!(metadata.is_executable());
+
+        assert_eq!(fs.set_permissions(path!("/script.sh"), 0o444), Ok(()));
+        assert!(
+            matches!(
+                fs.new_open_options().write(true).open(path!("/script.sh")),
+                Err(FsError::PermissionDenied),
+            ),
+            "read-only files can't be opened for writing",
+        );
+        assert!(
+            fs.new_open_options()
+                .read(true)
+                .open(path!("/script.sh"))
+                .is_ok(),
+            "read-only files can still be read",
+        );
+
+        assert_eq!(
+            fs.set_permissions(path!("/missing"), 0o644),
+            Err(FsError::EntryNotFound),
+        );
+
+        fs.new_open_options_ext()
+            .insert_ro_file(path!("/program.wasm"), Cow::Borrowed(b"\0asm"))
+            .unwrap();
+        assert!(
+            fs.metadata(path!("/program.wasm")).unwrap().is_executable(),
+            "files from packages can be executed",
+        );
+    }
+
+    #[tokio::test]
+    async fn test_watch() {
+        let fs = FileSystem::default();
+        ops::create_dir_all(&fs, "/app/src").unwrap();
+        let mut watcher = fs.watch(path!("/app"), true).unwrap();
+
+        ops::write(&fs, "/app/src/main.rs", b"fn main() {}")
+            .await
+            .unwrap();
+        fs.rename(path!("/app/src/main.rs"), path!("/app/src/lib.rs"))
+            .await
+            .unwrap();
+        fs.remove_file(path!("/app/src/lib.rs")).unwrap();
+        ops::write(&fs, "/elsewhere.txt", b"ignored").await.unwrap();
+
+        let mut events = Vec::new();
+        while let Some(event) = watcher.try_next() {
+            events.push(event);
+        }
+        assert_eq!(
+            events,
+            [
+                FsEvent::Create(PathBuf::from("/app/src/main.rs")),
+                FsEvent::Modify(PathBuf::from("/app/src/main.rs")),
+                FsEvent::Rename {
+                    from: PathBuf::from("/app/src/main.rs"),
+                    to: PathBuf::from("/app/src/lib.rs"),
+                },
+                FsEvent::Remove(PathBuf::from("/app/src/lib.rs")),
+            ],
+            "only the changes below the watched directory are reported",
+        );
+    }
 }
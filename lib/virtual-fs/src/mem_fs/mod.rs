@@ -1,8 +1,10 @@
+mod chunk_store;
 mod file;
 mod file_opener;
 mod filesystem;
 mod stdio;
 
+pub use chunk_store::{ChunkStore, CHUNK_SIZE};
 use file::{File, FileHandle, ReadOnlyFile};
 pub use filesystem::FileSystem;
 pub use stdio::{Stderr, Stdin, Stdout};
@@ -17,6 +19,10 @@ use std::{
 type Inode = usize;
 const ROOT_INODE: Inode = 0;
 
+/// The maximum number of symbolic links that are followed while
+/// resolving a single path, this mirrors `MAXSYMLINKS` on Linux.
+const MAX_SYMLINK_HOPS: usize = 40;
+
 #[derive(Debug)]
 struct FileNode {
     inode: Inode,
@@ -67,6 +73,28 @@ struct ArcDirectoryNode {
     metadata: Metadata,
 }
 
+/// A symbolic link, `target` is stored verbatim and resolved lazily
+/// when the link is followed.
+#[derive(Debug)]
+struct SymlinkNode {
+    inode: Inode,
+    name: OsString,
+    target: PathBuf,
+    metadata: Metadata,
+}
+
+/// An additional name for an existing (non-directory) node. The
+/// `metadata` of the `target` node is the one that is reported.
+#[derive(Debug)]
+struct HardLinkNode {
+    inode: Inode,
+    name: OsString,
+    target: Inode,
+    /// The directory the link lives in
+    parent: Inode,
+    metadata: Metadata,
+}
+
 #[derive(Debug)]
 enum Node {
     File(FileNode),
@@ -75,6 +103,8 @@ enum Node {
     CustomFile(CustomFileNode),
     Directory(DirectoryNode),
     ArcDirectory(ArcDirectoryNode),
+    Symlink(SymlinkNode),
+    HardLink(HardLinkNode),
 }
 
 impl Node {
@@ -86,6 +116,8 @@ impl Node {
             Self::CustomFile(CustomFileNode { inode, .. }) => inode,
             Self::Directory(DirectoryNode { inode, .. }) => inode,
             Self::ArcDirectory(ArcDirectoryNode { inode, .. }) => inode,
+            Self::Symlink(SymlinkNode { inode, .. }) => inode,
+            Self::HardLink(HardLinkNode { inode, .. }) => inode,
         }
     }
 
@@ -97,6 +129,8 @@ impl Node {
             Self::CustomFile(CustomFileNode { name, .. }) => name.as_os_str(),
             Self::Directory(DirectoryNode { name, .. }) => name.as_os_str(),
             Self::ArcDirectory(ArcDirectoryNode { name, .. }) => name.as_os_str(),
+            Self::Symlink(SymlinkNode { name, .. }) => name.as_os_str(),
+            Self::HardLink(HardLinkNode { name, .. }) => name.as_os_str(),
         }
     }
 
@@ -108,6 +142,8 @@ impl Node {
             Self::CustomFile(CustomFileNode { metadata, .. }) => metadata,
             Self::Directory(DirectoryNode { metadata, .. }) => metadata,
             Self::ArcDirectory(ArcDirectoryNode { metadata, .. }) => metadata,
+            Self::Symlink(SymlinkNode { metadata, .. }) => metadata,
+            Self::HardLink(HardLinkNode { metadata, .. }) => metadata,
         }
     }
 
@@ -119,6 +155,8 @@ impl Node {
             Self::CustomFile(CustomFileNode { metadata, .. }) => metadata,
             Self::Directory(DirectoryNode { metadata, .. }) => metadata,
             Self::ArcDirectory(ArcDirectoryNode { metadata, .. }) => metadata,
+            Self::Symlink(SymlinkNode { metadata, .. }) => metadata,
+            Self::HardLink(HardLinkNode { metadata, .. }) => metadata,
         }
     }
 
@@ -130,10 +168,18 @@ impl Node {
             Self::CustomFile(CustomFileNode { name, .. }) => *name = new_name,
             Self::Directory(DirectoryNode { name, .. }) => *name = new_name,
             Self::ArcDirectory(ArcDirectoryNode { name, .. }) => *name = new_name,
+            Self::Symlink(SymlinkNode { name, .. }) => *name = new_name,
+            Self::HardLink(HardLinkNode { name, .. }) => *name = new_name,
         }
     }
 }
 
+/// Converts a time into the seconds since the epoch that the nodes keep.
+fn unix_seconds(time: std::time::SystemTime) -> u64 {
+    time.duration_since(std::time::SystemTime::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
 fn time() -> u64 {
     #[cfg(not(feature = "no-time"))]
     {
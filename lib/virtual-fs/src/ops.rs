@@ -117,6 +117,14 @@ where
     }
 }
 
+/// Returns the path of the white out file that would hide the given path
+pub fn white_out_path(path: impl AsRef<Path>) -> Option<PathBuf> {
+    let filename = path.as_ref().file_name()?;
+    let mut path = path.as_ref().to_owned();
+    path.set_file_name(format!("{}{}", WHITEOUT_PREFIX, filename.to_string_lossy()));
+    Some(path)
+}
+
 /// Returns true if the path is a whiteout file
 pub fn is_white_out(path: impl AsRef<Path>) -> Option<PathBuf> {
     if let Some(filename) = path.as_ref().file_name() {
@@ -148,15 +156,21 @@ pub fn copy_reference_ext<'a>(
     let from = from.to_owned();
     let to = to.to_owned();
     Box::pin(async move {
-        let src = source.new_open_options().read(true).open(from)?;
+        let src = source.new_open_options().read(true).open(&from)?;
         let mut dst = destination
             .new_open_options()
             .create(true)
             .write(true)
             .truncate(true)
-            .open(to)?;
+            .open(&to)?;
 
         dst.copy_reference(src).await?;
+
+        // Keep the permissions of the original, when the destination
+        // supports them
+        if let Ok(metadata) = source.metadata(&from) {
+            destination.set_permissions(&to, metadata.mode).ok();
+        }
         Ok(())
     })
 }
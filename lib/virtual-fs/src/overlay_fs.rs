@@ -1,3 +1,4 @@
+use std::time::SystemTime;
 use std::{
     collections::HashSet,
     fmt::Debug,
@@ -13,8 +14,8 @@ use replace_with::replace_with_or_abort;
 use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
 
 use crate::{
-    ops, FileOpener, FileSystem, FileSystems, FsError, Metadata, OpenOptions, OpenOptionsConfig,
-    ReadDir, VirtualFile,
+    ops, FileOpener, FileSystem, FileSystems, FsError, FsEvent, FsWatcher, Metadata, OpenOptions,
+    OpenOptionsConfig, ReadDir, VirtualFile,
 };
 
 /// A primary filesystem and chain of secondary filesystems that are overlayed
@@ -52,11 +53,11 @@ use crate::{
 ///
 /// let fs = OverlayFileSystem::new(MemFS::default(), [HostFS::default()]);
 ///
-/// // This also has the benefit of storing the two values in-line with no extra
-/// // overhead or indirection.
+/// // This also has the benefit of storing the secondaries in-line with no
+/// // extra overhead or indirection.
 /// assert_eq!(
 ///     std::mem::size_of_val(&fs),
-///     std::mem::size_of::<(MemFS, HostFS)>(),
+///     std::mem::size_of::<(std::sync::Arc<MemFS>, HostFS)>(),
 /// );
 /// ```
 ///
@@ -105,6 +106,42 @@ where
 
         Err(FsError::EntryNotFound)
     }
+
+    /// Copies an entry that only exists in one of the secondaries into the
+    /// primary, so that it can be modified.
+    fn copy_up(&self, path: &Path) -> Result<(), FsError> {
+        if self.primary.symlink_metadata(path).is_ok() {
+            return Ok(());
+        }
+        if ops::has_white_out(&self.primary, path) {
+            return Err(FsError::EntryNotFound);
+        }
+
+        let mut found = None;
+        for fs in self.secondaries.filesystems() {
+            if let Ok(metadata) = fs.symlink_metadata(path) {
+                found = Some((fs, metadata));
+                break;
+            }
+        }
+        let (fs, metadata) = found.ok_or(FsError::EntryNotFound)?;
+
+        if let Some(parent) = path.parent() {
+            ops::create_dir_all(&self.primary, parent)?;
+        }
+        if metadata.is_dir() {
+            self.primary.create_dir(path)?;
+            self.primary.set_permissions(path, metadata.mode).ok();
+        } else if metadata.ft.is_symlink() {
+            self.primary.symlink(&fs.read_link(path)?, path)?;
+        } else {
+            // The copy is synchronous, but for the usual in-memory
+            // primary the copy reference completes immediately
+            futures::executor::block_on(ops::copy_reference_ext(fs, &self.primary, path, path))?;
+        }
+
+        Ok(())
+    }
 }
 
 impl<P, S> FileSystem for OverlayFileSystem<P, S>
@@ -357,6 +394,171 @@ where
     fn new_open_options(&self) -> OpenOptions<'_> {
         OpenOptions::new(self)
     }
+
+    fn symlink_metadata(&self, path: &Path) -> Result<Metadata, FsError> {
+        // Whiteout files can not be read, they are just markers
+        if ops::is_white_out(path).is_some() {
+            return Err(FsError::EntryNotFound);
+        }
+
+        // Check if the link is in the primary
+        match self.primary.symlink_metadata(path) {
+            Ok(meta) => return Ok(meta),
+            Err(e) if should_continue(e) => {}
+            Err(e) => return Err(e),
+        }
+
+        // There might be a whiteout, search for this
+        if ops::has_white_out(&self.primary, path) {
+            return Err(FsError::EntryNotFound);
+        }
+
+        // Otherwise scan the secondaries
+        for fs in self.secondaries.filesystems() {
+            match fs.symlink_metadata(path) {
+                Err(e) if should_continue(e) => continue,
+                other => return other,
+            }
+        }
+
+        Err(FsError::EntryNotFound)
+    }
+
+    fn symlink(&self, target: &Path, link: &Path) -> Result<(), FsError> {
+        // You can not create links that use the whiteout prefix
+        if ops::is_white_out(link).is_some() {
+            return Err(FsError::InvalidInput);
+        }
+
+        // The link can't replace something that is visible in any of the
+        // layers, even a dangling symlink
+        if self.symlink_metadata(link).is_ok() {
+            return Err(FsError::AlreadyExists);
+        }
+
+        // Make sure the parent tree is in place on the primary, it might
+        // only exist in the secondaries so far
+        if let Some(parent) = link.parent() {
+            if self.read_dir(parent).is_ok() {
+                ops::create_dir_all(&self.primary, parent).ok();
+            }
+        }
+
+        // Links are always created in the primary, any whiteout that hid an
+        // earlier entry is no longer needed
+        match self.primary.symlink(target, link) {
+            Ok(()) => {
+                ops::remove_white_out(self.primary.as_ref(), link);
+                return Ok(());
+            }
+            Err(e) if should_continue(e) => {}
+            Err(e) => return Err(e),
+        }
+
+        self.permission_error_or_not_found(link)
+    }
+
+    fn hard_link(&self, original: &Path, link: &Path) -> Result<(), FsError> {
+        if ops::is_white_out(original).is_some() || ops::is_white_out(link).is_some() {
+            return Err(FsError::InvalidInput);
+        }
+        if self.symlink_metadata(link).is_ok() {
+            return Err(FsError::AlreadyExists);
+        }
+
+        // A hard link can only be created in the primary, so when the
+        // original is in one of the secondaries it first needs to be
+        // copied up (just like a write to the file would do)
+        self.copy_up(original)?;
+
+        if let Some(parent) = link.parent() {
+            if self.read_dir(parent).is_ok() {
+                ops::create_dir_all(&self.primary, parent).ok();
+            }
+        }
+
+        self.primary.hard_link(original, link)?;
+        ops::remove_white_out(self.primary.as_ref(), link);
+        Ok(())
+    }
+
+    fn read_link(&self, path: &Path) -> Result<PathBuf, FsError> {
+        // Whiteout files can not be read, they are just markers
+        if ops::is_white_out(path).is_some() {
+            return Err(FsError::EntryNotFound);
+        }
+
+        // Check if the link is in the primary
+        match self.primary.read_link(path) {
+            Ok(target) => return Ok(target),
+            Err(e) if should_continue(e) && self.primary.symlink_metadata(path).is_err() => {}
+            Err(e) => return Err(e),
+        }
+
+        // There might be a whiteout, search for this
+        if ops::has_white_out(&self.primary, path) {
+            return Err(FsError::EntryNotFound);
+        }
+
+        // Otherwise scan the secondaries
+        for fs in self.secondaries.filesystems() {
+            match fs.read_link(path) {
+                Err(e) if should_continue(e) && fs.symlink_metadata(path).is_err() => continue,
+                other => return other,
+            }
+        }
+
+        Err(FsError::EntryNotFound)
+    }
+
+    fn set_permissions(&self, path: &Path, mode: u32) -> Result<(), FsError> {
+        // Whiteout files can not be changed, they are just markers
+        if ops::is_white_out(path).is_some() {
+            return Err(FsError::EntryNotFound);
+        }
+
+        // The secondaries are read-only so the entry is first copied up
+        // into the primary, where its permissions can be changed
+        self.copy_up(path)?;
+        self.primary.set_permissions(path, mode)
+    }
+
+    fn set_times(
+        &self,
+        path: &Path,
+        accessed: Option<SystemTime>,
+        modified: Option<SystemTime>,
+    ) -> Result<(), FsError> {
+        if ops::is_white_out(path).is_some() {
+            return Err(FsError::EntryNotFound);
+        }
+
+        self.copy_up(path)?;
+        self.primary.set_times(path, accessed, modified)
+    }
+
+    fn watch(&self, path: &Path, recursive: bool) -> Result<FsWatcher, FsError> {
+        let mut watchers = vec![self.primary.watch(path, recursive)?];
+        for fs in self.secondaries.filesystems() {
+            match fs.watch(path, recursive) {
+                Ok(watcher) => watchers.push(watcher),
+                // Read-only layers often have nothing to report
+                Err(FsError::Unsupported) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        // Whiteouts are how the primary hides what is in the secondaries, so
+        // creating one looks like removing the entry it hides
+        Ok(FsWatcher::merge(watchers).map(|event| match event {
+            FsEvent::Create(path) => match ops::is_white_out(&path) {
+                Some(hidden) => Some(FsEvent::Remove(hidden)),
+                None => Some(FsEvent::Create(path)),
+            },
+            other if ops::is_white_out(other.path()).is_some() => None,
+            other => Some(other),
+        }))
+    }
 }
 
 impl<P, S> FileOpener for OverlayFileSystem<P, S>
@@ -443,6 +645,10 @@ where
                 sub_conf.create_new = false;
                 sub_conf.append = false;
                 sub_conf.truncate = false;
+                // The original is read when it gets copied to the primary
+                if require_mutations {
+                    sub_conf.read = true;
+                }
                 match fs.new_open_options().options(sub_conf.clone()).open(path) {
                     Err(e) if should_continue(e) => continue,
                     Ok(file) if require_mutations => {
@@ -676,6 +882,24 @@ where
                                 let mut read_buf = ReadBuf::new(&mut buf);
                                 match Pin::new(src.as_mut()).poll_read(cx, &mut read_buf) {
                                     Poll::Ready(Ok(())) if read_buf.filled().is_empty() => {
+                                        // Everything needs to be written out before the
+                                        // cursor of the copy can be moved
+                                        match Pin::new(dst.as_mut()).poll_flush(cx) {
+                                            Poll::Ready(Ok(())) => {}
+                                            Poll::Ready(Err(err)) => {
+                                                return CowState::Error { err, src };
+                                            }
+                                            Poll::Pending => {
+                                                return CowState::Copying {
+                                                    original_offset,
+                                                    buf,
+                                                    buf_pos,
+                                                    src,
+                                                    dst,
+                                                };
+                                            }
+                                        }
+
                                         again = true;
 
                                         if self.append {
@@ -1003,7 +1227,8 @@ where
         state: CowState::ReadOnly(file),
         readable: conf.read,
         append: conf.append,
-        new_size: None,
+        // A truncated file is emptied once it has been copied
+        new_size: if conf.truncate { Some(0) } else { None },
     }))
 }
 
@@ -1629,4 +1854,132 @@ mod tests {
             FsError::EntryNotFound
         )
     }
+
+    #[tokio::test]
+    async fn symlinks_across_layers() {
+        let primary = MemFS::default();
+        let secondary = MemFS::default();
+        ops::create_dir_all(&secondary, "/secondary").unwrap();
+        ops::write(&secondary, "/secondary/file.txt", b"Hello, World!")
+            .await
+            .unwrap();
+        secondary
+            .symlink(Path::new("file.txt"), Path::new("/secondary/link.txt"))
+            .unwrap();
+
+        let fs = OverlayFileSystem::new(primary, [secondary]);
+
+        // Links in the secondaries are visible through the overlay
+        assert_eq!(
+            fs.read_link(Path::new("/secondary/link.txt")).unwrap(),
+            Path::new("file.txt"),
+        );
+        assert!(fs
+            .symlink_metadata(Path::new("/secondary/link.txt"))
+            .unwrap()
+            .file_type()
+            .is_symlink());
+
+        // New links are created in the primary
+        fs.symlink(
+            Path::new("/secondary/file.txt"),
+            Path::new("/secondary/abs.txt"),
+        )
+        .unwrap();
+        assert_eq!(
+            fs.symlink(Path::new("file.txt"), Path::new("/secondary/link.txt")),
+            Err(FsError::AlreadyExists),
+        );
+        assert!(fs
+            .primary
+            .symlink_metadata(Path::new("/secondary/abs.txt"))
+            .is_ok());
+        assert!(!ops::exists(&fs.secondaries[0], "/secondary/abs.txt"));
+
+        // Hard linking a file from a secondary copies it up first
+        fs.hard_link(
+            Path::new("/secondary/file.txt"),
+            Path::new("/secondary/hard.txt"),
+        )
+        .unwrap();
+        assert!(ops::is_file(&fs.primary, "/secondary/file.txt"));
+        assert_eq!(
+            ops::read_to_string(&fs, "/secondary/hard.txt")
+                .await
+                .unwrap(),
+            "Hello, World!",
+        );
+    }
+
+    #[tokio::test]
+    async fn set_permissions_copies_up() {
+        let primary = MemFS::default();
+        let secondary = MemFS::default();
+        ops::create_dir_all(&secondary, "/bin").unwrap();
+        ops::write(&secondary, "/bin/script.sh", b"#!/bin/sh")
+            .await
+            .unwrap();
+        secondary
+            .set_permissions(Path::new("/bin/script.sh"), 0o444)
+            .unwrap();
+
+        let fs = OverlayFileSystem::new(primary, [secondary]);
+
+        // The permissions of the secondaries are visible through the overlay
+        assert!(!fs
+            .metadata(Path::new("/bin/script.sh"))
+            .unwrap()
+            .is_writable());
+
+        // Changing them copies the file up into the primary
+        fs.set_permissions(Path::new("/bin/script.sh"), 0o755)
+            .unwrap();
+        assert_eq!(
+            fs.primary
+                .metadata(Path::new("/bin/script.sh"))
+                .unwrap()
+                .mode(),
+            0o755,
+        );
+        assert_eq!(
+            fs.secondaries[0]
+                .metadata(Path::new("/bin/script.sh"))
+                .unwrap()
+                .mode(),
+            0o444,
+        );
+        assert_eq!(
+            ops::read_to_string(&fs, "/bin/script.sh").await.unwrap(),
+            "#!/bin/sh",
+        );
+    }
+
+    #[tokio::test]
+    async fn watch_reports_whiteouts_as_removals() {
+        let primary = MemFS::default();
+        let secondary = MemFS::default();
+        ops::create_dir_all(&secondary, "/bin").unwrap();
+        ops::write(&secondary, "/bin/script.sh", b"#!/bin/sh")
+            .await
+            .unwrap();
+        let fs = OverlayFileSystem::new(primary, [secondary]);
+        let mut watcher = fs.watch(Path::new("/"), true).unwrap();
+
+        fs.remove_file(Path::new("/bin/script.sh")).unwrap();
+
+        let mut events = Vec::new();
+        while let Some(event) = watcher.try_next() {
+            events.push(event);
+        }
+        assert!(
+            events.contains(&FsEvent::Remove(PathBuf::from("/bin/script.sh"))),
+            "hiding a file removes it: {events:?}",
+        );
+        assert!(
+            events
+                .iter()
+                .all(|event| ops::is_white_out(event.path()).is_none()),
+            "whiteouts are never reported: {events:?}",
+        );
+    }
 }
@@ -3,6 +3,7 @@
 //! shared - some of the interfaces pass around a `Box<dyn VirtualFileSystem>`
 
 use std::path::Path;
+use std::time::SystemTime;
 
 use crate::*;
 
@@ -49,6 +50,35 @@ impl FileSystem for PassthruFileSystem {
     fn new_open_options(&self) -> OpenOptions {
         self.fs.new_open_options()
     }
+
+    fn symlink(&self, target: &Path, link: &Path) -> Result<()> {
+        self.fs.symlink(target, link)
+    }
+
+    fn hard_link(&self, original: &Path, link: &Path) -> Result<()> {
+        self.fs.hard_link(original, link)
+    }
+
+    fn read_link(&self, path: &Path) -> Result<PathBuf> {
+        self.fs.read_link(path)
+    }
+
+    fn set_permissions(&self, path: &Path, mode: u32) -> Result<()> {
+        self.fs.set_permissions(path, mode)
+    }
+
+    fn set_times(
+        &self,
+        path: &Path,
+        accessed: Option<SystemTime>,
+        modified: Option<SystemTime>,
+    ) -> Result<()> {
+        self.fs.set_times(path, accessed, modified)
+    }
+
+    fn watch(&self, path: &Path, recursive: bool) -> Result<FsWatcher> {
+        self.fs.watch(path, recursive)
+    }
 }
 
 #[cfg(test)]
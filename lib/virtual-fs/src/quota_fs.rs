@@ -0,0 +1,622 @@
+//! A [`FileSystem`] wrapper that enforces quotas on the bytes and inodes used
+//! by the file system it wraps, whatever that file system is (a `host_fs`,
+//! the primary of an [`crate::OverlayFileSystem`], ...).
+
+use std::collections::HashMap;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+};
+use std::time::SystemTime;
+
+use super::*;
+
+/// The limits enforced by a [`QuotaFileSystem`], `None` means unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quota {
+    /// The maximum number of bytes held by the files.
+    pub max_bytes: Option<u64>,
+    /// The maximum number of files, directories and links.
+    pub max_inodes: Option<u64>,
+}
+
+/// The resources currently used below a [`QuotaFileSystem`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QuotaUsage {
+    pub bytes: u64,
+    pub inodes: u64,
+}
+
+/// Wraps a [`FileSystem`] and fails the operations that would make it grow
+/// beyond its [`Quota`] with [`FsError::StorageFull`].
+///
+/// The usage is only tracked for the changes made through the wrapper, so
+/// the inner file system is assumed to be empty unless
+/// [`QuotaFileSystem::recalculate`] is used. Every hard link counts as an
+/// inode of its own, while the bytes of a file are only given back once the
+/// last of the hard links made through the wrapper is removed.
+#[derive(Debug, Clone)]
+pub struct QuotaFileSystem<F> {
+    inner: F,
+    state: Arc<QuotaState>,
+}
+
+impl<F> QuotaFileSystem<F>
+where
+    F: FileSystem,
+{
+    pub fn new(inner: F, quota: Quota) -> Self {
+        Self {
+            inner,
+            state: Arc::new(QuotaState {
+                quota,
+                bytes: AtomicU64::new(0),
+                inodes: AtomicU64::new(0),
+                links: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+
+    pub fn quota(&self) -> Quota {
+        self.state.quota
+    }
+
+    pub fn usage(&self) -> QuotaUsage {
+        QuotaUsage {
+            bytes: self.state.bytes.load(Ordering::Acquire),
+            inodes: self.state.inodes.load(Ordering::Acquire),
+        }
+    }
+
+    /// Walks the tree found at `root` in the inner file system and makes it
+    /// the current usage.
+    pub fn recalculate(&self, root: &Path) -> Result<QuotaUsage> {
+        let mut usage = QuotaUsage::default();
+        let mut pending = vec![root.to_path_buf()];
+
+        while let Some(dir) = pending.pop() {
+            for entry in self.inner.read_dir(&dir)? {
+                let entry = entry?;
+                let metadata = self.inner.symlink_metadata(&entry.path)?;
+                usage.inodes += 1;
+                if metadata.is_dir() {
+                    pending.push(entry.path);
+                } else if metadata.is_file() {
+                    usage.bytes += metadata.len;
+                }
+            }
+        }
+
+        self.state.bytes.store(usage.bytes, Ordering::Release);
+        self.state.inodes.store(usage.inodes, Ordering::Release);
+        Ok(usage)
+    }
+
+    /// The bytes held by whatever is at `path`, if anything.
+    fn bytes_of(&self, path: &Path) -> u64 {
+        match self.inner.symlink_metadata(path) {
+            Ok(metadata) if metadata.is_file() => metadata.len,
+            _ => 0,
+        }
+    }
+
+    /// Reserves an inode for the duration of `op`, which is given back if
+    /// the operation fails.
+    fn with_inode<T>(&self, op: impl FnOnce() -> Result<T>) -> Result<T> {
+        self.state.reserve_inodes(1)?;
+        op().map_err(|e| {
+            self.state.release_inodes(1);
+            e
+        })
+    }
+}
+
+impl<F> FileSystem for QuotaFileSystem<F>
+where
+    F: FileSystem,
+{
+    fn read_dir(&self, path: &Path) -> Result<ReadDir> {
+        self.inner.read_dir(path)
+    }
+
+    fn create_dir(&self, path: &Path) -> Result<()> {
+        self.with_inode(|| self.inner.create_dir(path))
+    }
+
+    fn remove_dir(&self, path: &Path) -> Result<()> {
+        self.inner.remove_dir(path)?;
+        self.state.release_inodes(1);
+        Ok(())
+    }
+
+    fn rename<'a>(&'a self, from: &'a Path, to: &'a Path) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            // A file that is replaced by the rename gives its resources back.
+            let replaced = if from != to {
+                self.inner
+                    .symlink_metadata(to)
+                    .ok()
+                    .filter(|metadata| !metadata.is_dir())
+            } else {
+                None
+            };
+
+            self.inner.rename(from, to).await?;
+
+            if let Some(metadata) = replaced {
+                if self.state.unlink_name(to) && metadata.is_file() {
+                    self.state.release_bytes(metadata.len);
+                }
+                self.state.release_inodes(1);
+            }
+            self.state.rename_name(from, to);
+            Ok(())
+        })
+    }
+
+    fn metadata(&self, path: &Path) -> Result<Metadata> {
+        self.inner.metadata(path)
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> Result<Metadata> {
+        self.inner.symlink_metadata(path)
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<()> {
+        let bytes = self.bytes_of(path);
+        self.inner.remove_file(path)?;
+        if self.state.unlink_name(path) {
+            self.state.release_bytes(bytes);
+        }
+        self.state.release_inodes(1);
+        Ok(())
+    }
+
+    fn new_open_options(&self) -> OpenOptions {
+        OpenOptions::new(self)
+    }
+
+    fn symlink(&self, target: &Path, link: &Path) -> Result<()> {
+        self.with_inode(|| self.inner.symlink(target, link))
+    }
+
+    fn hard_link(&self, original: &Path, link: &Path) -> Result<()> {
+        self.with_inode(|| self.inner.hard_link(original, link))?;
+        self.state.link_name(original, link);
+        Ok(())
+    }
+
+    fn read_link(&self, path: &Path) -> Result<PathBuf> {
+        self.inner.read_link(path)
+    }
+
+    fn set_permissions(&self, path: &Path, mode: u32) -> Result<()> {
+        self.inner.set_permissions(path, mode)
+    }
+
+    fn set_times(
+        &self,
+        path: &Path,
+        accessed: Option<SystemTime>,
+        modified: Option<SystemTime>,
+    ) -> Result<()> {
+        self.inner.set_times(path, accessed, modified)
+    }
+
+    fn watch(&self, path: &Path, recursive: bool) -> Result<FsWatcher> {
+        self.inner.watch(path, recursive)
+    }
+}
+
+impl<F> FileOpener for QuotaFileSystem<F>
+where
+    F: FileSystem,
+{
+    fn open(
+        &self,
+        path: &Path,
+        conf: &OpenOptionsConfig,
+    ) -> Result<Box<dyn VirtualFile + Send + Sync + 'static>> {
+        let existing = self.inner.metadata(path).ok();
+        let open = || {
+            self.inner
+                .new_open_options()
+                .options(conf.clone())
+                .open(path)
+        };
+
+        let file = match &existing {
+            None if conf.create() || conf.create_new() => self.with_inode(open)?,
+            _ => open()?,
+        };
+
+        // Truncating the file gives its bytes back.
+        if let Some(metadata) = existing {
+            if conf.truncate() && metadata.is_file() {
+                self.state.release_bytes(metadata.len);
+            }
+        }
+
+        if !conf.write() && !conf.append() {
+            return Ok(file);
+        }
+        Ok(Box::new(QuotaFile {
+            inner: file,
+            state: self.state.clone(),
+            path: path.to_path_buf(),
+            append: conf.append(),
+            position: 0,
+        }))
+    }
+}
+
+#[derive(Debug)]
+struct QuotaState {
+    quota: Quota,
+    bytes: AtomicU64,
+    inodes: AtomicU64,
+    /// The files that have more than one name because of hard links made
+    /// through the wrapper. All the names of a file share the same `Arc`, so
+    /// its strong count is the number of names left.
+    links: Mutex<HashMap<PathBuf, Arc<()>>>,
+}
+
+impl QuotaState {
+    fn reserve_bytes(&self, amount: u64) -> Result<()> {
+        reserve(&self.bytes, self.quota.max_bytes, amount)
+    }
+
+    /// Reserves as many of the `amount` bytes as still fit within the quota,
+    /// returning how many were reserved.
+    fn reserve_bytes_up_to(&self, amount: u64) -> u64 {
+        let mut reserved = 0;
+        // The closure always returns `Some`, so this never fails.
+        self.bytes
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |current| {
+                let available = match self.quota.max_bytes {
+                    Some(max) => max.saturating_sub(current),
+                    None => u64::MAX - current,
+                };
+                reserved = amount.min(available);
+                Some(current + reserved)
+            })
+            .ok();
+        reserved
+    }
+
+    fn release_bytes(&self, amount: u64) {
+        release(&self.bytes, amount)
+    }
+
+    fn reserve_inodes(&self, amount: u64) -> Result<()> {
+        reserve(&self.inodes, self.quota.max_inodes, amount)
+    }
+
+    fn release_inodes(&self, amount: u64) {
+        release(&self.inodes, amount)
+    }
+
+    /// Records that `link` is a new name for the file at `original`.
+    fn link_name(&self, original: &Path, link: &Path) {
+        let mut links = self.links.lock().unwrap();
+        let names = links.entry(original.to_path_buf()).or_default().clone();
+        links.insert(link.to_path_buf(), names);
+    }
+
+    /// Forgets the name `path`, returning whether it was the last name of
+    /// its file, so the bytes of the file are no longer used.
+    fn unlink_name(&self, path: &Path) -> bool {
+        let mut links = self.links.lock().unwrap();
+        match links.remove(path) {
+            Some(names) => Arc::strong_count(&names) == 1,
+            None => true,
+        }
+    }
+
+    fn rename_name(&self, from: &Path, to: &Path) {
+        let mut links = self.links.lock().unwrap();
+        if let Some(names) = links.remove(from) {
+            links.insert(to.to_path_buf(), names);
+        }
+    }
+}
+
+fn reserve(counter: &AtomicU64, max: Option<u64>, amount: u64) -> Result<()> {
+    counter
+        .fetch_update(Ordering::AcqRel, Ordering::Acquire, |current| {
+            let new = current.checked_add(amount)?;
+            match max {
+                Some(max) if new > max => None,
+                _ => Some(new),
+            }
+        })
+        .map(|_| ())
+        .map_err(|_| FsError::StorageFull)
+}
+
+fn release(counter: &AtomicU64, amount: u64) {
+    // The closure always returns `Some`, so this never fails.
+    counter
+        .fetch_update(Ordering::AcqRel, Ordering::Acquire, |current| {
+            Some(current.saturating_sub(amount))
+        })
+        .ok();
+}
+
+/// A file opened for writing through a [`QuotaFileSystem`], the writes are
+/// cut short when they would go beyond the quota.
+#[derive(Debug)]
+struct QuotaFile {
+    inner: Box<dyn VirtualFile + Send + Sync + 'static>,
+    state: Arc<QuotaState>,
+    path: PathBuf,
+    append: bool,
+    /// The cursor of the file, which is needed to know whether a write will
+    /// make the file grow.
+    position: u64,
+}
+
+impl VirtualFile for QuotaFile {
+    fn last_accessed(&self) -> u64 {
+        self.inner.last_accessed()
+    }
+
+    fn last_modified(&self) -> u64 {
+        self.inner.last_modified()
+    }
+
+    fn created_time(&self) -> u64 {
+        self.inner.created_time()
+    }
+
+    fn size(&self) -> u64 {
+        self.inner.size()
+    }
+
+    fn set_len(&mut self, new_size: u64) -> Result<()> {
+        let size = self.inner.size();
+        if new_size > size {
+            self.state.reserve_bytes(new_size - size)?;
+        }
+
+        match self.inner.set_len(new_size) {
+            Ok(()) => {
+                if new_size < size {
+                    self.state.release_bytes(size - new_size);
+                }
+                Ok(())
+            }
+            Err(e) => {
+                if new_size > size {
+                    self.state.release_bytes(new_size - size);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    fn unlink(&mut self) -> BoxFuture<'static, Result<()>> {
+        let size = self.inner.size();
+        let state = self.state.clone();
+        let path = self.path.clone();
+        let fut = self.inner.unlink();
+        Box::pin(async move {
+            fut.await?;
+            if state.unlink_name(&path) {
+                state.release_bytes(size);
+            }
+            state.release_inodes(1);
+            Ok(())
+        })
+    }
+
+    fn is_open(&self) -> bool {
+        self.inner.is_open()
+    }
+
+    fn poll_read_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        Pin::new(self.inner.as_mut()).poll_read_ready(cx)
+    }
+
+    fn poll_write_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        Pin::new(self.inner.as_mut()).poll_write_ready(cx)
+    }
+}
+
+impl AsyncWrite for QuotaFile {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let size = self.inner.size();
+        let start = if self.append { size } else { self.position };
+        let growth = (start + buf.len() as u64).saturating_sub(size);
+
+        // The growth is reserved before writing so concurrent writers can't
+        // both use the last bytes of the quota, only what fits is written.
+        let reserved = self.state.reserve_bytes_up_to(growth);
+        let buf = if reserved < growth {
+            let fits = buf.len() - (growth - reserved) as usize;
+            if fits == 0 {
+                self.state.release_bytes(reserved);
+                return Poll::Ready(Err(FsError::StorageFull.into()));
+            }
+            &buf[..fits]
+        } else {
+            buf
+        };
+
+        match Pin::new(&mut self.inner).poll_write(cx, buf) {
+            Poll::Ready(Ok(amt)) => {
+                self.position = start + amt as u64;
+                // A short write gives back what it didn't use.
+                let grown = self.inner.size().saturating_sub(size);
+                self.state.release_bytes(reserved.saturating_sub(grown));
+                Poll::Ready(Ok(amt))
+            }
+            res => {
+                self.state.release_bytes(reserved);
+                res
+            }
+        }
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+impl AsyncRead for QuotaFile {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let res = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = res {
+            self.position += (buf.filled().len() - before) as u64;
+        }
+        res
+    }
+}
+
+impl AsyncSeek for QuotaFile {
+    fn start_seek(mut self: Pin<&mut Self>, position: io::SeekFrom) -> io::Result<()> {
+        Pin::new(&mut self.inner).start_seek(position)
+    }
+
+    fn poll_complete(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        let res = Pin::new(&mut self.inner).poll_complete(cx);
+        if let Poll::Ready(Ok(position)) = res {
+            self.position = position;
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{mem_fs, ops};
+
+    fn quota_fs(max_bytes: u64, max_inodes: u64) -> QuotaFileSystem<mem_fs::FileSystem> {
+        QuotaFileSystem::new(
+            mem_fs::FileSystem::default(),
+            Quota {
+                max_bytes: Some(max_bytes),
+                max_inodes: Some(max_inodes),
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn writes_are_limited_to_the_quota() {
+        let fs = quota_fs(10, 10);
+
+        ops::write(&fs, "/a.txt", b"Hello").await.unwrap();
+        assert_eq!(
+            fs.usage(),
+            QuotaUsage {
+                bytes: 5,
+                inodes: 1
+            }
+        );
+
+        assert!(
+            ops::write(&fs, "/b.txt", b"Hello, World!").await.is_err(),
+            "the write doesn't fit in the quota",
+        );
+        assert_eq!(
+            ops::read_to_string(&fs, "/b.txt").await.unwrap(),
+            "Hello",
+            "the write stops when the quota is reached",
+        );
+        assert_eq!(fs.usage().bytes, 10);
+
+        // Overwriting a file doesn't use more space.
+        ops::write(&fs, "/a.txt", b"World").await.unwrap();
+        assert_eq!(fs.usage().bytes, 10);
+
+        fs.remove_file(Path::new("/a.txt")).unwrap();
+        assert_eq!(
+            fs.usage(),
+            QuotaUsage {
+                bytes: 5,
+                inodes: 1
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn inodes_are_limited_to_the_quota() {
+        let fs = quota_fs(1024, 2);
+
+        fs.create_dir(Path::new("/dir")).unwrap();
+        ops::touch(&fs, "/dir/file.txt").unwrap();
+        assert_eq!(
+            fs.create_dir(Path::new("/other")),
+            Err(FsError::StorageFull),
+        );
+        assert!(!ops::exists(&fs, "/other"));
+
+        fs.remove_file(Path::new("/dir/file.txt")).unwrap();
+        assert_eq!(fs.create_dir(Path::new("/other")), Ok(()));
+        assert_eq!(fs.usage().inodes, 2);
+    }
+
+    #[tokio::test]
+    async fn hard_links_share_their_bytes() {
+        let fs = quota_fs(1024, 10);
+
+        ops::write(&fs, "/a.txt", b"Hello").await.unwrap();
+        fs.hard_link(Path::new("/a.txt"), Path::new("/b.txt"))
+            .unwrap();
+        assert_eq!(
+            fs.usage(),
+            QuotaUsage {
+                bytes: 5,
+                inodes: 2
+            }
+        );
+
+        fs.remove_file(Path::new("/a.txt")).unwrap();
+        assert_eq!(
+            fs.usage(),
+            QuotaUsage {
+                bytes: 5,
+                inodes: 1
+            },
+            "the bytes are still used by the other link",
+        );
+
+        fs.remove_file(Path::new("/b.txt")).unwrap();
+        assert_eq!(fs.usage(), QuotaUsage::default());
+    }
+
+    #[tokio::test]
+    async fn recalculate_the_usage() {
+        let inner = mem_fs::FileSystem::default();
+        ops::create_dir_all(&inner, "/a/b").unwrap();
+        ops::write(&inner, "/a/b/c.txt", b"abc").await.unwrap();
+
+        let fs = QuotaFileSystem::new(inner, Quota::default());
+        assert_eq!(fs.usage(), QuotaUsage::default());
+        assert_eq!(
+            fs.recalculate(Path::new("/")).unwrap(),
+            QuotaUsage {
+                bytes: 3,
+                inodes: 3
+            },
+        );
+    }
+}
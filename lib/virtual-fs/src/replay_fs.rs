@@ -0,0 +1,419 @@
+//! A [`FileSystem`] that answers operations from the [`TraceRecord`]s of a
+//! [`crate::TraceFileSystem`], so a run can be reproduced without the files
+//! it was recorded against.
+
+use std::time::SystemTime;
+use std::{
+    collections::{HashMap, VecDeque},
+    io,
+    mem::discriminant,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use futures::future::BoxFuture;
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
+
+use crate::{
+    FileOpener, FileSystem, FsError, FsOperation, FsOutcome, Metadata, OpenOptionsConfig, ReadDir,
+    Result, TraceRecord, VirtualFile,
+};
+
+/// Plays back a recording made by a [`crate::TraceFileSystem`].
+///
+/// Each recorded operation is answered once, in the order it was recorded.
+/// Operations on files are matched by the order they were made on each file,
+/// so a guest doing the same things gets the same answers even when its
+/// threads interleave differently. Anything missing from the recording fails
+/// with [`FsError::UnknownError`].
+#[derive(Debug, Clone)]
+pub struct ReplayFileSystem {
+    state: Arc<Mutex<ReplayState>>,
+}
+
+#[derive(Debug, Default)]
+struct ReplayState {
+    /// The outcomes of the operations on the file system, in order.
+    operations: HashMap<FsOperation, VecDeque<FsOutcome>>,
+    /// The operations on each file, in order.
+    files: HashMap<u64, VecDeque<TraceRecord>>,
+}
+
+impl ReplayFileSystem {
+    pub fn new(records: impl IntoIterator<Item = TraceRecord>) -> Self {
+        let mut state = ReplayState::default();
+        for record in records {
+            match record.operation.handle() {
+                Some(handle) => state.files.entry(handle).or_default().push_back(record),
+                None => state
+                    .operations
+                    .entry(record.operation)
+                    .or_default()
+                    .push_back(record.outcome),
+            }
+        }
+
+        ReplayFileSystem {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    /// Whether every recorded operation has been replayed.
+    pub fn is_exhausted(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        state
+            .operations
+            .values()
+            .all(|outcomes| outcomes.is_empty())
+            && state.files.values().all(|records| records.is_empty())
+    }
+
+    fn replay(&self, operation: FsOperation) -> Result<FsOutcome> {
+        let outcome = self
+            .state
+            .lock()
+            .unwrap()
+            .operations
+            .get_mut(&operation)
+            .and_then(|outcomes| outcomes.pop_front());
+
+        match outcome {
+            Some(FsOutcome::Failed(e)) => Err(e),
+            Some(outcome) => Ok(outcome),
+            None => {
+                tracing::warn!(?operation, "The operation isn't in the recording");
+                Err(FsError::UnknownError)
+            }
+        }
+    }
+
+    fn replay_done(&self, operation: FsOperation) -> Result<()> {
+        match self.replay(operation)? {
+            FsOutcome::Done => Ok(()),
+            _ => Err(FsError::InvalidData),
+        }
+    }
+
+    fn replay_metadata(&self, operation: FsOperation) -> Result<Metadata> {
+        match self.replay(operation)? {
+            FsOutcome::Metadata(metadata) => Ok(metadata),
+            _ => Err(FsError::InvalidData),
+        }
+    }
+}
+
+impl FileSystem for ReplayFileSystem {
+    fn read_dir(&self, path: &Path) -> Result<ReadDir> {
+        match self.replay(FsOperation::ReadDir {
+            path: path.to_path_buf(),
+        })? {
+            FsOutcome::Entries(entries) => Ok(ReadDir::new(entries)),
+            _ => Err(FsError::InvalidData),
+        }
+    }
+
+    fn create_dir(&self, path: &Path) -> Result<()> {
+        self.replay_done(FsOperation::CreateDir {
+            path: path.to_path_buf(),
+        })
+    }
+
+    fn remove_dir(&self, path: &Path) -> Result<()> {
+        self.replay_done(FsOperation::RemoveDir {
+            path: path.to_path_buf(),
+        })
+    }
+
+    fn rename<'a>(&'a self, from: &'a Path, to: &'a Path) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            self.replay_done(FsOperation::Rename {
+                from: from.to_path_buf(),
+                to: to.to_path_buf(),
+            })
+        })
+    }
+
+    fn metadata(&self, path: &Path) -> Result<Metadata> {
+        self.replay_metadata(FsOperation::Metadata {
+            path: path.to_path_buf(),
+        })
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> Result<Metadata> {
+        self.replay_metadata(FsOperation::SymlinkMetadata {
+            path: path.to_path_buf(),
+        })
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<()> {
+        self.replay_done(FsOperation::RemoveFile {
+            path: path.to_path_buf(),
+        })
+    }
+
+    fn new_open_options(&self) -> crate::OpenOptions {
+        crate::OpenOptions::new(self)
+    }
+
+    fn symlink(&self, target: &Path, link: &Path) -> Result<()> {
+        self.replay_done(FsOperation::Symlink {
+            target: target.to_path_buf(),
+            link: link.to_path_buf(),
+        })
+    }
+
+    fn hard_link(&self, original: &Path, link: &Path) -> Result<()> {
+        self.replay_done(FsOperation::HardLink {
+            original: original.to_path_buf(),
+            link: link.to_path_buf(),
+        })
+    }
+
+    fn read_link(&self, path: &Path) -> Result<PathBuf> {
+        match self.replay(FsOperation::ReadLink {
+            path: path.to_path_buf(),
+        })? {
+            FsOutcome::Path(target) => Ok(target),
+            _ => Err(FsError::InvalidData),
+        }
+    }
+
+    fn set_permissions(&self, path: &Path, mode: u32) -> Result<()> {
+        self.replay_done(FsOperation::SetPermissions {
+            path: path.to_path_buf(),
+            mode,
+        })
+    }
+
+    fn set_times(
+        &self,
+        path: &Path,
+        accessed: Option<SystemTime>,
+        modified: Option<SystemTime>,
+    ) -> Result<()> {
+        self.replay_done(FsOperation::SetTimes {
+            path: path.to_path_buf(),
+            accessed,
+            modified,
+        })
+    }
+}
+
+impl FileOpener for ReplayFileSystem {
+    fn open(
+        &self,
+        path: &Path,
+        conf: &OpenOptionsConfig,
+    ) -> Result<Box<dyn VirtualFile + Send + Sync + 'static>> {
+        match self.replay(FsOperation::Open {
+            path: path.to_path_buf(),
+            options: conf.clone(),
+        })? {
+            FsOutcome::Opened { handle, size } => Ok(Box::new(ReplayFile {
+                state: self.state.clone(),
+                handle,
+                size,
+                position: 0,
+            })),
+            _ => Err(FsError::InvalidData),
+        }
+    }
+}
+
+/// A file opened by a [`ReplayFileSystem`].
+#[derive(Debug)]
+struct ReplayFile {
+    state: Arc<Mutex<ReplayState>>,
+    handle: u64,
+    /// The size of the file, as far as the recording tells.
+    size: u64,
+    position: u64,
+}
+
+impl ReplayFile {
+    /// Takes the next recorded operation of the same kind as `operation`.
+    fn replay(&self, operation: FsOperation) -> Option<FsOutcome> {
+        let mut state = self.state.lock().unwrap();
+        let records = state.files.get_mut(&self.handle)?;
+        let index = records
+            .iter()
+            .position(|r| discriminant(&r.operation) == discriminant(&operation));
+
+        match index.and_then(|index| records.remove(index)) {
+            Some(record) => Some(record.outcome),
+            None => {
+                tracing::warn!(?operation, "The operation isn't in the recording");
+                None
+            }
+        }
+    }
+
+    /// Looks at the next recorded operation of the same kind as `operation`.
+    fn peek(&self, operation: FsOperation) -> Option<FsOutcome> {
+        let state = self.state.lock().unwrap();
+        state
+            .files
+            .get(&self.handle)?
+            .iter()
+            .find(|r| discriminant(&r.operation) == discriminant(&operation))
+            .map(|r| r.outcome.clone())
+    }
+
+    fn replay_io(&self, operation: FsOperation) -> io::Result<FsOutcome> {
+        match self.replay(operation) {
+            Some(FsOutcome::Failed(e)) => Err(e.into()),
+            Some(outcome) => Ok(outcome),
+            None => Err(FsError::UnknownError.into()),
+        }
+    }
+}
+
+fn unexpected_outcome() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "the recording doesn't match the operation",
+    )
+}
+
+impl VirtualFile for ReplayFile {
+    fn last_accessed(&self) -> u64 {
+        0
+    }
+
+    fn last_modified(&self) -> u64 {
+        0
+    }
+
+    fn created_time(&self) -> u64 {
+        0
+    }
+
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn set_len(&mut self, new_size: u64) -> Result<()> {
+        let operation = FsOperation::SetLen {
+            handle: self.handle,
+            size: new_size,
+        };
+        match self.replay(operation) {
+            Some(FsOutcome::Done) => {
+                self.size = new_size;
+                Ok(())
+            }
+            Some(FsOutcome::Failed(e)) => Err(e),
+            Some(_) => Err(FsError::InvalidData),
+            None => Err(FsError::UnknownError),
+        }
+    }
+
+    fn unlink(&mut self) -> BoxFuture<'static, Result<()>> {
+        let result = match self.replay(FsOperation::Unlink {
+            handle: self.handle,
+        }) {
+            Some(FsOutcome::Done) => Ok(()),
+            Some(FsOutcome::Failed(e)) => Err(e),
+            Some(_) => Err(FsError::InvalidData),
+            None => Err(FsError::UnknownError),
+        };
+        Box::pin(async move { result })
+    }
+
+    fn poll_read_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        let next = self.peek(FsOperation::Read {
+            handle: self.handle,
+            len: 0,
+        });
+        match next {
+            Some(FsOutcome::Read(data)) => Poll::Ready(Ok(data.len())),
+            _ => Poll::Ready(Ok(0)),
+        }
+    }
+
+    fn poll_write_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        let next = self.peek(FsOperation::Write {
+            handle: self.handle,
+            len: 0,
+        });
+        match next {
+            Some(FsOutcome::Written(written)) => Poll::Ready(Ok(written)),
+            _ => Poll::Ready(Ok(0)),
+        }
+    }
+}
+
+impl AsyncRead for ReplayFile {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let outcome = self.replay_io(FsOperation::Read {
+            handle: self.handle,
+            len: buf.remaining(),
+        })?;
+        match outcome {
+            FsOutcome::Read(data) => {
+                // The guest is doing the same reads, so the buffer should
+                // always be large enough.
+                let len = data.len().min(buf.remaining());
+                buf.put_slice(&data[..len]);
+                self.position += len as u64;
+                Poll::Ready(Ok(()))
+            }
+            _ => Poll::Ready(Err(unexpected_outcome())),
+        }
+    }
+}
+
+impl AsyncWrite for ReplayFile {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let outcome = self.replay_io(FsOperation::Write {
+            handle: self.handle,
+            len: buf.len(),
+        })?;
+        match outcome {
+            FsOutcome::Written(written) => {
+                self.position += written as u64;
+                self.size = self.size.max(self.position);
+                Poll::Ready(Ok(written))
+            }
+            _ => Poll::Ready(Err(unexpected_outcome())),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncSeek for ReplayFile {
+    fn start_seek(self: Pin<&mut Self>, _position: io::SeekFrom) -> io::Result<()> {
+        // Where the seek ends up was recorded when it completed
+        Ok(())
+    }
+
+    fn poll_complete(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        let outcome = self.replay_io(FsOperation::Seek {
+            handle: self.handle,
+        })?;
+        match outcome {
+            FsOutcome::Position(position) => {
+                self.position = position;
+                Poll::Ready(Ok(position))
+            }
+            _ => Poll::Ready(Err(unexpected_outcome())),
+        }
+    }
+}
@@ -230,6 +230,10 @@ fn transform_into_read_dir(path: &Path, fs_entries: &[FsEntry<'_>]) -> crate::Re
                 created: 0,
                 modified: 0,
                 len: e.get_len(),
+                mode: crate::PACKAGE_MODE,
+                uid: 0,
+                gid: 0,
+                ..Default::default()
             }),
         })
         .collect();
@@ -307,6 +311,10 @@ impl FileSystem for StaticFileSystem {
                 created: 0,
                 modified: 0,
                 len: fs_entry.get_len(),
+                mode: crate::PACKAGE_MODE,
+                uid: 0,
+                gid: 0,
+                ..Default::default()
             })
         } else if let Some(_fs) = self.volumes.values().find_map(|v| v.read_dir(&path).ok()) {
             Ok(Metadata {
@@ -315,6 +323,10 @@ impl FileSystem for StaticFileSystem {
                 created: 0,
                 modified: 0,
                 len: 0,
+                mode: crate::DEFAULT_DIR_MODE,
+                uid: 0,
+                gid: 0,
+                ..Default::default()
             })
         } else {
             self.memory.metadata(Path::new(&path))
@@ -350,6 +362,10 @@ impl FileSystem for StaticFileSystem {
                 created: 0,
                 modified: 0,
                 len: fs_entry.get_len(),
+                mode: crate::PACKAGE_MODE,
+                uid: 0,
+                gid: 0,
+                ..Default::default()
             })
         } else if self
             .volumes
@@ -363,6 +379,10 @@ impl FileSystem for StaticFileSystem {
                 created: 0,
                 modified: 0,
                 len: 0,
+                mode: crate::DEFAULT_DIR_MODE,
+                uid: 0,
+                gid: 0,
+                ..Default::default()
             })
         } else {
             self.memory.symlink_metadata(Path::new(&path))
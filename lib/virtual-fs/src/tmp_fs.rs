@@ -2,14 +2,15 @@
 //! enhanced to support mounting file systems, shared static files,
 //! readonly files, etc...
 
+use std::time::SystemTime;
 use std::{
     path::{Path, PathBuf},
     sync::Arc,
 };
 
 use crate::{
-    limiter::DynFsMemoryLimiter, mem_fs, BoxFuture, FileSystem, Metadata, OpenOptions, ReadDir,
-    Result,
+    limiter::DynFsMemoryLimiter, mem_fs, BoxFuture, FileSystem, FsWatcher, Metadata, OpenOptions,
+    ReadDir, Result,
 };
 
 #[derive(Debug, Default, Clone)]
@@ -26,6 +27,11 @@ impl TmpFileSystem {
         self.fs.set_memory_limiter(limiter);
     }
 
+    /// See [`mem_fs::FileSystem::set_chunk_store`].
+    pub fn set_chunk_store(&self, store: mem_fs::ChunkStore) {
+        self.fs.set_chunk_store(store);
+    }
+
     pub fn new_open_options_ext(&self) -> &mem_fs::FileSystem {
         self.fs.new_open_options_ext()
     }
@@ -92,4 +98,33 @@ impl FileSystem for TmpFileSystem {
     fn new_open_options(&self) -> OpenOptions {
         self.fs.new_open_options()
     }
+
+    fn symlink(&self, target: &Path, link: &Path) -> Result<()> {
+        self.fs.symlink(target, link)
+    }
+
+    fn hard_link(&self, original: &Path, link: &Path) -> Result<()> {
+        self.fs.hard_link(original, link)
+    }
+
+    fn read_link(&self, path: &Path) -> Result<PathBuf> {
+        self.fs.read_link(path)
+    }
+
+    fn set_permissions(&self, path: &Path, mode: u32) -> Result<()> {
+        self.fs.set_permissions(path, mode)
+    }
+
+    fn set_times(
+        &self,
+        path: &Path,
+        accessed: Option<SystemTime>,
+        modified: Option<SystemTime>,
+    ) -> Result<()> {
+        self.fs.set_times(path, accessed, modified)
+    }
+
+    fn watch(&self, path: &Path, recursive: bool) -> Result<FsWatcher> {
+        self.fs.watch(path, recursive)
+    }
 }
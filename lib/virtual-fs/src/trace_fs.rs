@@ -1,57 +1,299 @@
+use std::time::SystemTime;
 use std::{
     path::PathBuf,
     pin::Pin,
+    sync::{Arc, Mutex},
     task::{Context, Poll},
 };
 
 use futures::future::BoxFuture;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
 
-use crate::{FileOpener, FileSystem, OpenOptionsConfig, VirtualFile};
+use crate::{DirEntry, FileOpener, FileSystem, FsError, Metadata, OpenOptionsConfig, VirtualFile};
 
 /// A [`FileSystem`] wrapper that will automatically log all operations at the
 /// `trace` level.
 ///
 /// To see these logs, you will typically need to set the `$RUST_LOG`
 /// environment variable to `virtual_fs::trace_fs=trace`.
+///
+/// When given a [`TraceRecorder`], every operation and its result are also
+/// recorded so they can be played back later by a
+/// [`crate::ReplayFileSystem`].
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct TraceFileSystem<F>(pub F);
+pub struct TraceFileSystem<F> {
+    inner: F,
+    recorder: Option<TraceRecorder>,
+}
 
 impl<F> TraceFileSystem<F> {
     pub fn new(filesystem: F) -> Self {
-        TraceFileSystem(filesystem)
+        TraceFileSystem {
+            inner: filesystem,
+            recorder: None,
+        }
+    }
+
+    /// Records the operations made through this file system into `recorder`.
+    pub fn with_recorder(mut self, recorder: TraceRecorder) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    pub fn recorder(&self) -> Option<&TraceRecorder> {
+        self.recorder.as_ref()
     }
 
     pub fn inner(&self) -> &F {
-        &self.0
+        &self.inner
     }
 
     pub fn inner_mut(&mut self) -> &mut F {
-        &mut self.0
+        &mut self.inner
     }
 
     pub fn into_inner(self) -> F {
-        self.0
+        self.inner
+    }
+
+    fn record<T>(
+        &self,
+        operation: impl FnOnce() -> FsOperation,
+        result: &crate::Result<T>,
+        outcome: impl FnOnce(&T) -> FsOutcome,
+    ) {
+        if let Some(recorder) = &self.recorder {
+            recorder.record(operation(), FsOutcome::of(result, outcome));
+        }
+    }
+}
+
+/// An operation made on a [`TraceFileSystem`] or on one of the files it
+/// opened.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum FsOperation {
+    ReadDir {
+        path: PathBuf,
+    },
+    CreateDir {
+        path: PathBuf,
+    },
+    RemoveDir {
+        path: PathBuf,
+    },
+    Rename {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    Metadata {
+        path: PathBuf,
+    },
+    SymlinkMetadata {
+        path: PathBuf,
+    },
+    RemoveFile {
+        path: PathBuf,
+    },
+    Symlink {
+        target: PathBuf,
+        link: PathBuf,
+    },
+    HardLink {
+        original: PathBuf,
+        link: PathBuf,
+    },
+    ReadLink {
+        path: PathBuf,
+    },
+    SetPermissions {
+        path: PathBuf,
+        mode: u32,
+    },
+    SetTimes {
+        path: PathBuf,
+        accessed: Option<SystemTime>,
+        modified: Option<SystemTime>,
+    },
+    Open {
+        path: PathBuf,
+        options: OpenOptionsConfig,
+    },
+    /// A read of up to `len` bytes from the file opened as `handle`.
+    Read {
+        handle: u64,
+        len: usize,
+    },
+    /// A write of `len` bytes to the file opened as `handle`.
+    Write {
+        handle: u64,
+        len: usize,
+    },
+    Seek {
+        handle: u64,
+    },
+    SetLen {
+        handle: u64,
+        size: u64,
+    },
+    Unlink {
+        handle: u64,
+    },
+}
+
+impl FsOperation {
+    /// The file the operation was made on, for the operations on files.
+    pub fn handle(&self) -> Option<u64> {
+        match self {
+            FsOperation::Read { handle, .. }
+            | FsOperation::Write { handle, .. }
+            | FsOperation::Seek { handle }
+            | FsOperation::SetLen { handle, .. }
+            | FsOperation::Unlink { handle } => Some(*handle),
+            _ => None,
+        }
+    }
+}
+
+/// What came out of an [`FsOperation`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum FsOutcome {
+    /// The operation succeeded without returning anything.
+    Done,
+    Failed(FsError),
+    Entries(Vec<DirEntry>),
+    Metadata(Metadata),
+    Path(PathBuf),
+    /// A file was opened, the operations on it refer to it by `handle`.
+    Opened {
+        handle: u64,
+        size: u64,
+    },
+    /// The bytes returned by a read.
+    Read(Vec<u8>),
+    Written(usize),
+    /// The position of the cursor after a seek.
+    Position(u64),
+}
+
+impl FsOutcome {
+    fn of<T>(result: &crate::Result<T>, f: impl FnOnce(&T) -> FsOutcome) -> Self {
+        match result {
+            Ok(value) => f(value),
+            Err(e) => FsOutcome::Failed(*e),
+        }
+    }
+}
+
+/// An [`FsOperation`] along with its outcome.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TraceRecord {
+    pub operation: FsOperation,
+    pub outcome: FsOutcome,
+}
+
+/// Collects the [`TraceRecord`]s of one or more [`TraceFileSystem`]s, in the
+/// order the operations completed.
+#[derive(Debug, Clone, Default)]
+pub struct TraceRecorder {
+    state: Arc<Mutex<RecorderState>>,
+}
+
+#[derive(Debug, Default)]
+struct RecorderState {
+    records: Vec<TraceRecord>,
+    last_handle: u64,
+}
+
+impl TraceRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A copy of everything recorded so far.
+    pub fn records(&self) -> Vec<TraceRecord> {
+        self.state.lock().unwrap().records.clone()
+    }
+
+    /// Takes everything recorded so far, leaving the recorder empty.
+    pub fn take(&self) -> Vec<TraceRecord> {
+        std::mem::take(&mut self.state.lock().unwrap().records)
+    }
+
+    fn record(&self, operation: FsOperation, outcome: FsOutcome) {
+        self.state
+            .lock()
+            .unwrap()
+            .records
+            .push(TraceRecord { operation, outcome });
+    }
+
+    fn next_handle(&self) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        state.last_handle += 1;
+        state.last_handle
+    }
+}
+
+impl PartialEq for TraceRecorder {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.state, &other.state)
     }
 }
 
+impl Eq for TraceRecorder {}
+
 impl<F> FileSystem for TraceFileSystem<F>
 where
     F: FileSystem,
 {
     #[tracing::instrument(level = "trace", skip(self), err)]
     fn read_dir(&self, path: &std::path::Path) -> crate::Result<crate::ReadDir> {
-        self.0.read_dir(path)
+        let result = self.inner.read_dir(path);
+        if self.recorder.is_none() {
+            return result;
+        }
+
+        // The entries need to be collected to be recorded
+        let result = result.and_then(|entries| entries.collect::<crate::Result<Vec<_>>>());
+        self.record(
+            || FsOperation::ReadDir {
+                path: path.to_path_buf(),
+            },
+            &result,
+            |entries| FsOutcome::Entries(entries.clone()),
+        );
+        result.map(crate::ReadDir::new)
     }
 
     #[tracing::instrument(level = "trace", skip(self), err)]
     fn create_dir(&self, path: &std::path::Path) -> crate::Result<()> {
-        self.0.create_dir(path)
+        let result = self.inner.create_dir(path);
+        self.record(
+            || FsOperation::CreateDir {
+                path: path.to_path_buf(),
+            },
+            &result,
+            |_| FsOutcome::Done,
+        );
+        result
     }
 
     #[tracing::instrument(level = "trace", skip(self), err)]
     fn remove_dir(&self, path: &std::path::Path) -> crate::Result<()> {
-        self.0.remove_dir(path)
+        let result = self.inner.remove_dir(path);
+        self.record(
+            || FsOperation::RemoveDir {
+                path: path.to_path_buf(),
+            },
+            &result,
+            |_| FsOutcome::Done,
+        );
+        result
     }
 
     #[tracing::instrument(level = "trace", skip(self), err)]
@@ -60,23 +302,144 @@ where
         from: &'a std::path::Path,
         to: &'a std::path::Path,
     ) -> BoxFuture<'a, crate::Result<()>> {
-        Box::pin(async { self.0.rename(from, to).await })
+        Box::pin(async {
+            let result = self.inner.rename(from, to).await;
+            self.record(
+                || FsOperation::Rename {
+                    from: from.to_path_buf(),
+                    to: to.to_path_buf(),
+                },
+                &result,
+                |_| FsOutcome::Done,
+            );
+            result
+        })
     }
 
     #[tracing::instrument(level = "trace", skip(self), err)]
     fn metadata(&self, path: &std::path::Path) -> crate::Result<crate::Metadata> {
-        self.0.metadata(path)
+        let result = self.inner.metadata(path);
+        self.record(
+            || FsOperation::Metadata {
+                path: path.to_path_buf(),
+            },
+            &result,
+            |metadata| FsOutcome::Metadata(metadata.clone()),
+        );
+        result
+    }
+
+    #[tracing::instrument(level = "trace", skip(self), err)]
+    fn symlink_metadata(&self, path: &std::path::Path) -> crate::Result<crate::Metadata> {
+        let result = self.inner.symlink_metadata(path);
+        self.record(
+            || FsOperation::SymlinkMetadata {
+                path: path.to_path_buf(),
+            },
+            &result,
+            |metadata| FsOutcome::Metadata(metadata.clone()),
+        );
+        result
     }
 
     #[tracing::instrument(level = "trace", skip(self), err)]
     fn remove_file(&self, path: &std::path::Path) -> crate::Result<()> {
-        self.0.remove_file(path)
+        let result = self.inner.remove_file(path);
+        self.record(
+            || FsOperation::RemoveFile {
+                path: path.to_path_buf(),
+            },
+            &result,
+            |_| FsOutcome::Done,
+        );
+        result
     }
 
     #[tracing::instrument(level = "trace", skip(self))]
     fn new_open_options(&self) -> crate::OpenOptions {
         crate::OpenOptions::new(self)
     }
+
+    #[tracing::instrument(level = "trace", skip(self), err)]
+    fn symlink(&self, target: &std::path::Path, link: &std::path::Path) -> crate::Result<()> {
+        let result = self.inner.symlink(target, link);
+        self.record(
+            || FsOperation::Symlink {
+                target: target.to_path_buf(),
+                link: link.to_path_buf(),
+            },
+            &result,
+            |_| FsOutcome::Done,
+        );
+        result
+    }
+
+    #[tracing::instrument(level = "trace", skip(self), err)]
+    fn hard_link(&self, original: &std::path::Path, link: &std::path::Path) -> crate::Result<()> {
+        let result = self.inner.hard_link(original, link);
+        self.record(
+            || FsOperation::HardLink {
+                original: original.to_path_buf(),
+                link: link.to_path_buf(),
+            },
+            &result,
+            |_| FsOutcome::Done,
+        );
+        result
+    }
+
+    #[tracing::instrument(level = "trace", skip(self), err)]
+    fn read_link(&self, path: &std::path::Path) -> crate::Result<PathBuf> {
+        let result = self.inner.read_link(path);
+        self.record(
+            || FsOperation::ReadLink {
+                path: path.to_path_buf(),
+            },
+            &result,
+            |target| FsOutcome::Path(target.clone()),
+        );
+        result
+    }
+
+    #[tracing::instrument(level = "trace", skip(self), err)]
+    fn set_permissions(&self, path: &std::path::Path, mode: u32) -> crate::Result<()> {
+        let result = self.inner.set_permissions(path, mode);
+        self.record(
+            || FsOperation::SetPermissions {
+                path: path.to_path_buf(),
+                mode,
+            },
+            &result,
+            |_| FsOutcome::Done,
+        );
+        result
+    }
+
+    #[tracing::instrument(level = "trace", skip(self), err)]
+    fn set_times(
+        &self,
+        path: &std::path::Path,
+        accessed: Option<SystemTime>,
+        modified: Option<SystemTime>,
+    ) -> crate::Result<()> {
+        let result = self.inner.set_times(path, accessed, modified);
+        self.record(
+            || FsOperation::SetTimes {
+                path: path.to_path_buf(),
+                accessed,
+                modified,
+            },
+            &result,
+            |_| FsOutcome::Done,
+        );
+        result
+    }
+
+    #[tracing::instrument(level = "trace", skip(self), err)]
+    fn watch(&self, path: &std::path::Path, recursive: bool) -> crate::Result<crate::FsWatcher> {
+        // Events can't be replayed, so watches aren't recorded
+        self.inner.watch(path, recursive)
+    }
 }
 
 impl<F> FileOpener for TraceFileSystem<F>
@@ -89,18 +452,65 @@ where
         path: &std::path::Path,
         conf: &OpenOptionsConfig,
     ) -> crate::Result<Box<dyn crate::VirtualFile + Send + Sync + 'static>> {
-        let file = self.0.new_open_options().options(conf.clone()).open(path)?;
+        let result = self
+            .inner
+            .new_open_options()
+            .options(conf.clone())
+            .open(path);
+
+        let recording = self.recorder.as_ref().map(|recorder| {
+            let handle = recorder.next_handle();
+            recorder.record(
+                FsOperation::Open {
+                    path: path.to_path_buf(),
+                    options: conf.clone(),
+                },
+                FsOutcome::of(&result, |file| FsOutcome::Opened {
+                    handle,
+                    size: file.size(),
+                }),
+            );
+            Recording {
+                recorder: recorder.clone(),
+                handle,
+            }
+        });
+
         Ok(Box::new(TraceFile {
-            file,
+            file: result?,
             path: path.to_owned(),
+            recording,
         }))
     }
 }
 
+/// Where the operations on a [`TraceFile`] are recorded.
+#[derive(Debug, Clone)]
+struct Recording {
+    recorder: TraceRecorder,
+    handle: u64,
+}
+
+impl Recording {
+    fn record_io<T>(
+        &self,
+        operation: FsOperation,
+        result: &std::io::Result<T>,
+        outcome: impl FnOnce(&T) -> FsOutcome,
+    ) {
+        let outcome = match result {
+            Ok(value) => outcome(value),
+            Err(e) => FsOutcome::Failed(FsError::from(std::io::Error::from(e.kind()))),
+        };
+        self.recorder.record(operation, outcome);
+    }
+}
+
 #[derive(Debug)]
 struct TraceFile {
     path: PathBuf,
     file: Box<dyn crate::VirtualFile + Send + Sync + 'static>,
+    recording: Option<Recording>,
 }
 
 impl VirtualFile for TraceFile {
@@ -126,12 +536,34 @@ impl VirtualFile for TraceFile {
 
     #[tracing::instrument(level = "trace", skip(self), fields(path=%self.path.display()), err)]
     fn set_len(&mut self, new_size: u64) -> crate::Result<()> {
-        self.file.set_len(new_size)
+        let result = self.file.set_len(new_size);
+        if let Some(recording) = &self.recording {
+            recording.recorder.record(
+                FsOperation::SetLen {
+                    handle: recording.handle,
+                    size: new_size,
+                },
+                FsOutcome::of(&result, |_| FsOutcome::Done),
+            );
+        }
+        result
     }
 
     fn unlink(&mut self) -> BoxFuture<'static, crate::Result<()>> {
         let fut = self.file.unlink();
-        Box::pin(async move { fut.await })
+        let recording = self.recording.clone();
+        Box::pin(async move {
+            let result = fut.await;
+            if let Some(recording) = recording {
+                recording.recorder.record(
+                    FsOperation::Unlink {
+                        handle: recording.handle,
+                    },
+                    FsOutcome::of(&result, |_| FsOutcome::Done),
+                );
+            }
+            result
+        })
     }
 
     #[tracing::instrument(level = "trace", skip_all, fields(path=%self.path.display()))]
@@ -170,12 +602,25 @@ impl AsyncRead for TraceFile {
         cx: &mut Context<'_>,
         buf: &mut ReadBuf<'_>,
     ) -> Poll<std::io::Result<()>> {
+        let filled = buf.filled().len();
+        let len = buf.remaining();
         let result = Pin::new(&mut *self.file).poll_read(cx, buf);
 
         if let Poll::Ready(Err(e)) = &result {
             tracing::trace!(error = e as &dyn std::error::Error);
         }
 
+        if let (Poll::Ready(result), Some(recording)) = (&result, &self.recording) {
+            recording.record_io(
+                FsOperation::Read {
+                    handle: recording.handle,
+                    len,
+                },
+                result,
+                |_| FsOutcome::Read(buf.filled()[filled..].to_vec()),
+            );
+        }
+
         result
     }
 }
@@ -193,6 +638,17 @@ impl AsyncWrite for TraceFile {
             tracing::trace!(error = e as &dyn std::error::Error);
         }
 
+        if let (Poll::Ready(result), Some(recording)) = (&result, &self.recording) {
+            recording.record_io(
+                FsOperation::Write {
+                    handle: recording.handle,
+                    len: buf.len(),
+                },
+                result,
+                |written| FsOutcome::Written(*written),
+            );
+        }
+
         result
     }
 
@@ -239,6 +695,70 @@ impl AsyncSeek for TraceFile {
             tracing::trace!(error = e as &dyn std::error::Error);
         }
 
+        if let (Poll::Ready(result), Some(recording)) = (&result, &self.recording) {
+            recording.record_io(
+                FsOperation::Seek {
+                    handle: recording.handle,
+                },
+                result,
+                |position| FsOutcome::Position(*position),
+            );
+        }
+
         result
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::{mem_fs, ops, ReplayFileSystem};
+
+    #[tokio::test]
+    async fn recorded_operations_can_be_replayed() {
+        let recorder = TraceRecorder::new();
+        let fs =
+            TraceFileSystem::new(mem_fs::FileSystem::default()).with_recorder(recorder.clone());
+        ops::create_dir_all(&fs, "/app").unwrap();
+        ops::write(&fs, "/app/config.toml", b"debug = true")
+            .await
+            .unwrap();
+        assert_eq!(
+            fs.metadata(Path::new("/missing")),
+            Err(FsError::EntryNotFound)
+        );
+        let contents = ops::read_to_string(&fs, "/app/config.toml").await.unwrap();
+
+        let records = recorder.take();
+        assert!(records.contains(&TraceRecord {
+            operation: FsOperation::Metadata {
+                path: PathBuf::from("/missing"),
+            },
+            outcome: FsOutcome::Failed(FsError::EntryNotFound),
+        }));
+
+        // The replay doesn't need the original files
+        let replay = ReplayFileSystem::new(records);
+        ops::create_dir_all(&replay, "/app").unwrap();
+        ops::write(&replay, "/app/config.toml", b"debug = true")
+            .await
+            .unwrap();
+        assert_eq!(
+            replay.metadata(Path::new("/missing")),
+            Err(FsError::EntryNotFound)
+        );
+        assert_eq!(
+            ops::read_to_string(&replay, "/app/config.toml")
+                .await
+                .unwrap(),
+            contents,
+        );
+        assert_eq!(
+            replay.metadata(Path::new("/missing")),
+            Err(FsError::UnknownError),
+            "the operation was only recorded once",
+        );
+    }
+}
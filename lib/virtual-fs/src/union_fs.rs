@@ -4,8 +4,9 @@
 
 use crate::*;
 
+use std::time::SystemTime;
 use std::{
-    path::Path,
+    path::{Path, PathBuf},
     sync::{Arc, Mutex, Weak},
 };
 
@@ -370,6 +371,177 @@ impl FileSystem for UnionFileSystem {
     fn new_open_options(&self) -> OpenOptions {
         OpenOptions::new(self)
     }
+    fn symlink(&self, target: &Path, link: &Path) -> Result<()> {
+        debug!(
+            "symlink: target={}, link={}",
+            target.display(),
+            link.display()
+        );
+        if link.parent().is_none() {
+            return Err(FsError::BaseNotDirectory);
+        }
+        let mut ret_error = FsError::EntryNotFound;
+        let link = link.to_string_lossy();
+        for (path, mount) in filter_mounts(&self.mounts, link.as_ref()) {
+            match mount.fs.symlink(target, Path::new(path.as_str())) {
+                Ok(ret) => {
+                    return Ok(ret);
+                }
+                Err(err) => {
+                    ret_error = err;
+                }
+            }
+        }
+        Err(ret_error)
+    }
+    fn hard_link(&self, original: &Path, link: &Path) -> Result<()> {
+        debug!(
+            "hard_link: original={}, link={}",
+            original.display(),
+            link.display()
+        );
+        if original.parent().is_none() || link.parent().is_none() {
+            return Err(FsError::BaseNotDirectory);
+        }
+        let mut ret_error = FsError::EntryNotFound;
+        let original = original.to_string_lossy();
+        let link = link.to_string_lossy();
+        #[cfg(target_os = "windows")]
+        let link = link.replace('\\', "/");
+        for (path, mount) in filter_mounts(&self.mounts, original.as_ref()) {
+            // Both paths need to be on the same mount point
+            let mut link = if link.starts_with(mount.path.as_str()) {
+                (link[mount.path.len()..]).to_string()
+            } else {
+                ret_error = FsError::InvalidInput;
+                continue;
+            };
+            if !link.starts_with('/') {
+                link = format!("/{}", link);
+            }
+            match mount
+                .fs
+                .hard_link(Path::new(&path), Path::new(link.as_str()))
+            {
+                Ok(ret) => {
+                    return Ok(ret);
+                }
+                Err(err) => {
+                    ret_error = err;
+                }
+            }
+        }
+        Err(ret_error)
+    }
+    fn read_link(&self, path: &Path) -> Result<PathBuf> {
+        debug!("read_link: path={}", path.display());
+        let mut ret_error = FsError::EntryNotFound;
+        let path = path.to_string_lossy();
+        for (path, mount) in filter_mounts(&self.mounts, path.as_ref()) {
+            match mount.fs.read_link(Path::new(path.as_str())) {
+                Ok(ret) => {
+                    return Ok(ret);
+                }
+                Err(err) => {
+                    ret_error = err;
+                }
+            }
+        }
+        Err(ret_error)
+    }
+    fn set_permissions(&self, path: &Path, mode: u32) -> Result<()> {
+        debug!("set_permissions: path={} mode={:o}", path.display(), mode);
+        let mut ret_error = FsError::EntryNotFound;
+        let path = path.to_string_lossy();
+        for (path, mount) in filter_mounts(&self.mounts, path.as_ref()) {
+            match mount.fs.set_permissions(Path::new(path.as_str()), mode) {
+                Ok(ret) => {
+                    return Ok(ret);
+                }
+                Err(err) => {
+                    ret_error = err;
+                }
+            }
+        }
+        Err(ret_error)
+    }
+    fn set_times(
+        &self,
+        path: &Path,
+        accessed: Option<SystemTime>,
+        modified: Option<SystemTime>,
+    ) -> Result<()> {
+        debug!("set_times: path={}", path.display());
+        let mut ret_error = FsError::EntryNotFound;
+        let path = path.to_string_lossy();
+        for (path, mount) in filter_mounts(&self.mounts, path.as_ref()) {
+            match mount
+                .fs
+                .set_times(Path::new(path.as_str()), accessed, modified)
+            {
+                Ok(ret) => {
+                    return Ok(ret);
+                }
+                Err(err) => {
+                    ret_error = err;
+                }
+            }
+        }
+        Err(ret_error)
+    }
+    fn watch(&self, path: &Path, recursive: bool) -> Result<FsWatcher> {
+        debug!("watch: path={} recursive={}", path.display(), recursive);
+        let mut ret_error = FsError::EntryNotFound;
+        let mut watchers = Vec::new();
+        let target = path.to_string_lossy();
+        for (inner_path, mount) in filter_mounts(&self.mounts, target.as_ref()) {
+            match mount.fs.watch(Path::new(inner_path.as_str()), recursive) {
+                Ok(watcher) => watchers.push(unmount_events(watcher, &mount)),
+                Err(err) => {
+                    ret_error = err;
+                }
+            }
+        }
+
+        // The mount points nested below the path are watched as a whole.
+        if recursive {
+            let nested = self.mounts.iter().filter(|mount| {
+                let mount_path = Path::new(&mount.path);
+                mount_path.starts_with(path) && mount_path != path
+            });
+            for mount in nested.filter_map(|mount| mount.strong()) {
+                let root = mount.new_path.as_deref().unwrap_or("/");
+                match mount.fs.watch(Path::new(root), true) {
+                    Ok(watcher) => watchers.push(unmount_events(watcher, &mount)),
+                    Err(err) => {
+                        trace!("watch: skipping {} ({})", mount.path, err);
+                    }
+                }
+            }
+        }
+
+        if watchers.is_empty() {
+            return Err(ret_error);
+        }
+        Ok(FsWatcher::merge(watchers))
+    }
+}
+
+/// Turns the paths reported by the file system of a mount point into paths
+/// of the union.
+fn unmount_events(watcher: FsWatcher, mount: &StrongMountPoint) -> FsWatcher {
+    let mount_path = Path::new(&mount.path).components().collect::<PathBuf>();
+    let inner_root = PathBuf::from(mount.new_path.as_deref().unwrap_or("/"));
+    watcher.map(move |event| {
+        event.map_paths(|path| {
+            let relative = path.strip_prefix(&inner_root).ok()?;
+            if relative.as_os_str().is_empty() {
+                Some(mount_path.clone())
+            } else {
+                Some(mount_path.join(relative))
+            }
+        })
+    })
 }
 
 fn filter_mounts(
@@ -462,11 +634,11 @@ impl FileOpener for UnionFileSystem {
 
 #[cfg(test)]
 mod tests {
-    use std::path::Path;
+    use std::path::{Path, PathBuf};
 
     use tokio::io::AsyncWriteExt;
 
-    use crate::{mem_fs, ops, FileSystem as FileSystemTrait, FsError, UnionFileSystem};
+    use crate::{mem_fs, ops, FileSystem as FileSystemTrait, FsError, FsEvent, UnionFileSystem};
 
     fn gen_filesystem() -> UnionFileSystem {
         let mut union = UnionFileSystem::new();
@@ -479,6 +651,7 @@ mod tests {
         let f = mem_fs::FileSystem::default();
         let g = mem_fs::FileSystem::default();
         let h = mem_fs::FileSystem::default();
+        let i = mem_fs::FileSystem::default();
 
         union.mount("mem_fs_1", "/test_new_filesystem", false, Box::new(a), None);
         union.mount("mem_fs_2", "/test_create_dir", false, Box::new(b), None);
@@ -488,6 +661,7 @@ mod tests {
         union.mount("mem_fs_6", "/test_remove_file", false, Box::new(f), None);
         union.mount("mem_fs_6", "/test_readdir", false, Box::new(g), None);
         union.mount("mem_fs_6", "/test_canonicalize", false, Box::new(h), None);
+        union.mount("mem_fs_7", "/test_links", false, Box::new(i), None);
 
         union
     }
@@ -981,6 +1155,52 @@ mod tests {
         let _ = fs_extra::remove_items(&["./test_readdir"]);
     }
 
+    #[tokio::test]
+    async fn test_links() {
+        let fs = gen_filesystem();
+        ops::write(&fs, "/test_links/file.txt", b"Hello, World!")
+            .await
+            .unwrap();
+
+        assert_eq!(
+            fs.symlink(Path::new("file.txt"), Path::new("/test_links/symlink.txt")),
+            Ok(()),
+            "creating a symlink inside a mount",
+        );
+        assert_eq!(
+            fs.read_link(Path::new("/test_links/symlink.txt")),
+            Ok(Path::new("file.txt").to_path_buf()),
+        );
+        assert!(fs
+            .symlink_metadata(Path::new("/test_links/symlink.txt"))
+            .unwrap()
+            .file_type()
+            .is_symlink());
+
+        assert_eq!(
+            fs.hard_link(
+                Path::new("/test_links/file.txt"),
+                Path::new("/test_links/hard_link.txt")
+            ),
+            Ok(()),
+            "creating a hard link inside a mount",
+        );
+        assert_eq!(
+            fs.hard_link(
+                Path::new("/test_links/file.txt"),
+                Path::new("/test_metadata/hard_link.txt")
+            ),
+            Err(FsError::InvalidInput),
+            "hard links can't cross mount points",
+        );
+        assert_eq!(
+            ops::read_to_string(&fs, "/test_links/hard_link.txt")
+                .await
+                .unwrap(),
+            "Hello, World!",
+        );
+    }
+
     /*
     #[tokio::test]
     async fn test_canonicalize() {
@@ -1108,4 +1328,22 @@ mod tests {
         assert!(ops::is_dir(&fs, "/top-level/nested"));
         assert!(ops::is_file(&fs, "/top-level/nested/another-file.txt"));
     }
+
+    #[tokio::test]
+    async fn test_watch() {
+        let mut union = UnionFileSystem::new();
+        let app = mem_fs::FileSystem::default();
+        union.mount("app", "/app", false, Box::new(app), None);
+
+        let mut watcher = union.watch(Path::new("/"), true).unwrap();
+        ops::write(&union, "/app/index.html", b"<html></html>")
+            .await
+            .unwrap();
+
+        assert_eq!(
+            watcher.try_next(),
+            Some(FsEvent::Create(PathBuf::from("/app/index.html"))),
+            "the paths of the mount point are made absolute",
+        );
+    }
 }
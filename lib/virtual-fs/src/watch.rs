@@ -0,0 +1,283 @@
+//! Change notifications for file systems, similar to `inotify(7)`.
+//!
+//! A file system that supports them hands out an [`FsWatcher`] from
+//! [`crate::FileSystem::watch`], which yields an [`FsEvent`] every time
+//! something changes below the watched path.
+
+use std::{
+    any::Any,
+    fmt,
+    future::poll_fn,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use tokio::sync::mpsc;
+
+/// A change made to a file system.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FsEvent {
+    /// A file, directory or link was created.
+    Create(PathBuf),
+    /// The contents of a file changed.
+    Modify(PathBuf),
+    /// A file, directory or link was removed.
+    Remove(PathBuf),
+    /// Something was moved from one place to another.
+    Rename { from: PathBuf, to: PathBuf },
+}
+
+impl FsEvent {
+    /// The path that changed, or the destination of a rename.
+    pub fn path(&self) -> &Path {
+        match self {
+            FsEvent::Create(path) | FsEvent::Modify(path) | FsEvent::Remove(path) => path,
+            FsEvent::Rename { to, .. } => to,
+        }
+    }
+
+    /// Rewrites the paths of the event, the event is dropped if `f` returns
+    /// `None` for all of them.
+    pub fn map_paths(self, f: impl Fn(&Path) -> Option<PathBuf>) -> Option<FsEvent> {
+        match self {
+            FsEvent::Create(path) => f(&path).map(FsEvent::Create),
+            FsEvent::Modify(path) => f(&path).map(FsEvent::Modify),
+            FsEvent::Remove(path) => f(&path).map(FsEvent::Remove),
+            FsEvent::Rename { from, to } => match (f(&from), f(&to)) {
+                (Some(from), Some(to)) => Some(FsEvent::Rename { from, to }),
+                // Moving something out of sight looks like a removal...
+                (Some(from), None) => Some(FsEvent::Remove(from)),
+                // ... and moving it in like a creation.
+                (None, Some(to)) => Some(FsEvent::Create(to)),
+                (None, None) => None,
+            },
+        }
+    }
+
+    fn concerns(&self, watched: &Path, recursive: bool) -> bool {
+        let matches = |path: &Path| {
+            path == watched
+                || path.parent() == Some(watched)
+                || (recursive && path.starts_with(watched))
+        };
+
+        match self {
+            FsEvent::Rename { from, to } => matches(from) || matches(to),
+            other => matches(other.path()),
+        }
+    }
+}
+
+type EventMapper = Arc<dyn Fn(FsEvent) -> Option<FsEvent> + Send + Sync>;
+
+/// A subscription to the changes made to a file system, changes stop being
+/// recorded once it is dropped.
+#[derive(Default)]
+pub struct FsWatcher {
+    sources: Vec<Source>,
+    /// Whatever needs to be kept alive for the events to keep flowing (e.g.
+    /// the watcher of the host).
+    guards: Vec<Box<dyn Any + Send + Sync>>,
+}
+
+struct Source {
+    events: mpsc::UnboundedReceiver<FsEvent>,
+    map: Option<EventMapper>,
+}
+
+impl FsWatcher {
+    /// Creates a watcher and the sender used to feed it events.
+    pub fn channel() -> (mpsc::UnboundedSender<FsEvent>, FsWatcher) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let watcher = FsWatcher {
+            sources: vec![Source {
+                events: rx,
+                map: None,
+            }],
+            guards: Vec::new(),
+        };
+        (tx, watcher)
+    }
+
+    /// Keeps `guard` alive for as long as the watcher is.
+    pub fn with_guard(mut self, guard: impl Any + Send + Sync) -> Self {
+        self.guards.push(Box::new(guard));
+        self
+    }
+
+    /// Rewrites (or drops) the events coming out of this watcher, e.g. to
+    /// make the paths of a mounted file system absolute.
+    pub fn map(mut self, f: impl Fn(FsEvent) -> Option<FsEvent> + Send + Sync + 'static) -> Self {
+        let f: EventMapper = Arc::new(f);
+        for source in &mut self.sources {
+            source.map = Some(match source.map.take() {
+                Some(previous) => {
+                    let f = f.clone();
+                    Arc::new(move |event| previous(event).and_then(&*f))
+                }
+                None => f.clone(),
+            });
+        }
+        self
+    }
+
+    /// Combines the events of several watchers.
+    pub fn merge(watchers: impl IntoIterator<Item = FsWatcher>) -> Self {
+        let mut merged = FsWatcher::default();
+        for watcher in watchers {
+            merged.sources.extend(watcher.sources);
+            merged.guards.extend(watcher.guards);
+        }
+        merged
+    }
+
+    /// Polls for the next event, `None` means that no more events will come.
+    pub fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<FsEvent>> {
+        let mut i = 0;
+        while i < self.sources.len() {
+            let source = &mut self.sources[i];
+            match source.events.poll_recv(cx) {
+                Poll::Ready(Some(event)) => {
+                    let event = match &source.map {
+                        Some(map) => map(event),
+                        None => Some(event),
+                    };
+                    if let Some(event) = event {
+                        return Poll::Ready(Some(event));
+                    }
+                    // The event was filtered out, try again.
+                }
+                Poll::Ready(None) => {
+                    self.sources.remove(i);
+                }
+                Poll::Pending => i += 1,
+            }
+        }
+
+        if self.sources.is_empty() {
+            Poll::Ready(None)
+        } else {
+            Poll::Pending
+        }
+    }
+
+    /// Waits for the next event.
+    pub async fn next(&mut self) -> Option<FsEvent> {
+        poll_fn(|cx| self.poll_next(cx)).await
+    }
+
+    /// Returns the next event, if one is already waiting.
+    pub fn try_next(&mut self) -> Option<FsEvent> {
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        match self.poll_next(&mut cx) {
+            Poll::Ready(event) => event,
+            Poll::Pending => None,
+        }
+    }
+}
+
+impl fmt::Debug for FsWatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FsWatcher")
+            .field("sources", &self.sources.len())
+            .finish()
+    }
+}
+
+/// Keeps track of the [`FsWatcher`]s of a file system, for the file systems
+/// that know about every change made to them.
+#[derive(Debug, Clone, Default)]
+pub struct WatchRegistry {
+    subscribers: Arc<Mutex<Vec<Subscriber>>>,
+}
+
+#[derive(Debug)]
+struct Subscriber {
+    path: PathBuf,
+    recursive: bool,
+    events: mpsc::UnboundedSender<FsEvent>,
+}
+
+impl WatchRegistry {
+    /// Watches the changes made to `path`, and its direct children when it is
+    /// a directory. With `recursive`, all of its descendants are watched.
+    pub fn subscribe(&self, path: &Path, recursive: bool) -> FsWatcher {
+        let (tx, watcher) = FsWatcher::channel();
+        self.subscribers.lock().unwrap().push(Subscriber {
+            path: path.to_path_buf(),
+            recursive,
+            events: tx,
+        });
+        watcher
+    }
+
+    /// Whether anyone is listening, which is worth checking before doing any
+    /// work to build an event.
+    pub fn is_empty(&self) -> bool {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.retain(|s| !s.events.is_closed());
+        subscribers.is_empty()
+    }
+
+    /// Sends the event to everyone watching the paths it concerns.
+    pub fn notify(&self, event: FsEvent) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.retain(|subscriber| {
+            if !event.concerns(&subscriber.path, subscriber.recursive) {
+                return !subscriber.events.is_closed();
+            }
+            subscriber.events.send(event.clone()).is_ok()
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_matching_events_are_delivered() {
+        let registry = WatchRegistry::default();
+        let mut shallow = registry.subscribe(Path::new("/app"), false);
+        let mut deep = registry.subscribe(Path::new("/app"), true);
+
+        registry.notify(FsEvent::Create(PathBuf::from("/app/main.rs")));
+        registry.notify(FsEvent::Modify(PathBuf::from("/app/src/lib.rs")));
+        registry.notify(FsEvent::Remove(PathBuf::from("/other")));
+
+        assert_eq!(
+            shallow.try_next(),
+            Some(FsEvent::Create(PathBuf::from("/app/main.rs")))
+        );
+        assert_eq!(shallow.try_next(), None, "nested changes are not watched");
+        assert_eq!(
+            deep.try_next(),
+            Some(FsEvent::Create(PathBuf::from("/app/main.rs")))
+        );
+        assert_eq!(
+            deep.try_next(),
+            Some(FsEvent::Modify(PathBuf::from("/app/src/lib.rs")))
+        );
+        assert_eq!(deep.try_next(), None);
+
+        drop(shallow);
+        drop(deep);
+        assert!(registry.is_empty(), "dropped watchers are unsubscribed");
+    }
+
+    #[test]
+    fn renames_across_the_watched_path() {
+        let event = FsEvent::Rename {
+            from: PathBuf::from("/mnt/a"),
+            to: PathBuf::from("/elsewhere/b"),
+        };
+        let unmounted = event.map_paths(|path| {
+            path.strip_prefix("/mnt")
+                .ok()
+                .map(|p| Path::new("/").join(p))
+        });
+        assert_eq!(unmounted, Some(FsEvent::Remove(PathBuf::from("/a"))));
+    }
+}
@@ -292,6 +292,10 @@ fn transform_into_read_dir(path: &Path, fs_entries: &[FsEntry<'_>]) -> crate::Re
                 created: 0,
                 modified: 0,
                 len: e.get_len(),
+                mode: crate::PACKAGE_MODE,
+                uid: 0,
+                gid: 0,
+                ..Default::default()
             }),
         })
         .collect();
@@ -359,6 +363,10 @@ where
                 created: 0,
                 modified: 0,
                 len: fs_entry.get_len(),
+                mode: crate::PACKAGE_MODE,
+                uid: 0,
+                gid: 0,
+                ..Default::default()
             })
         } else if self
             .volumes
@@ -373,6 +381,10 @@ where
                 created: 0,
                 modified: 0,
                 len: 0,
+                mode: crate::DEFAULT_DIR_MODE,
+                uid: 0,
+                gid: 0,
+                ..Default::default()
             })
         } else {
             self.memory.metadata(Path::new(&path))
@@ -410,6 +422,10 @@ where
                 created: 0,
                 modified: 0,
                 len: fs_entry.get_len(),
+                mode: crate::PACKAGE_MODE,
+                uid: 0,
+                gid: 0,
+                ..Default::default()
             })
         } else if self
             .volumes
@@ -424,6 +440,10 @@ where
                 created: 0,
                 modified: 0,
                 len: 0,
+                mode: crate::DEFAULT_DIR_MODE,
+                uid: 0,
+                gid: 0,
+                ..Default::default()
             })
         } else {
             self.memory.symlink_metadata(Path::new(&path))
@@ -281,6 +281,7 @@ fn compat_meta(meta: webc::compat::Metadata) -> Metadata {
                 dir: true,
                 ..Default::default()
             },
+            mode: crate::DEFAULT_DIR_MODE,
             ..Default::default()
         },
         webc::compat::Metadata::File { length } => Metadata {
@@ -289,6 +290,7 @@ fn compat_meta(meta: webc::compat::Metadata) -> Metadata {
                 ..Default::default()
             },
             len: length.try_into().unwrap(),
+            mode: crate::PACKAGE_MODE,
             ..Default::default()
         },
     }
@@ -414,6 +416,10 @@ mod tests {
                     created: 0,
                     modified: 0,
                     len: 6148,
+                    mode: crate::PACKAGE_MODE,
+                    uid: 0,
+                    gid: 0,
+                    ..Default::default()
                 }),
             },
             DirEntry {
@@ -427,6 +433,10 @@ mod tests {
                     created: 0,
                     modified: 0,
                     len: 0,
+                    mode: crate::DEFAULT_DIR_MODE,
+                    uid: 0,
+                    gid: 0,
+                    ..Default::default()
                 }),
             },
             DirEntry {
@@ -440,6 +450,10 @@ mod tests {
                     created: 0,
                     modified: 0,
                     len: 4694941,
+                    mode: crate::PACKAGE_MODE,
+                    uid: 0,
+                    gid: 0,
+                    ..Default::default()
                 }),
             },
             DirEntry {
@@ -453,6 +467,10 @@ mod tests {
                     created: 0,
                     modified: 0,
                     len: 0,
+                    mode: crate::DEFAULT_DIR_MODE,
+                    uid: 0,
+                    gid: 0,
+                    ..Default::default()
                 }),
             },
         ];
@@ -476,6 +494,10 @@ mod tests {
             created: 0,
             modified: 0,
             len: 4694941,
+            mode: crate::PACKAGE_MODE,
+            uid: 0,
+            gid: 0,
+            ..Default::default()
         };
         assert_eq!(
             fs.metadata("/lib/python.wasm".as_ref()).unwrap(),
@@ -502,6 +524,10 @@ mod tests {
                 created: 0,
                 modified: 0,
                 len: 0,
+                mode: crate::DEFAULT_DIR_MODE,
+                uid: 0,
+                gid: 0,
+                ..Default::default()
             },
         );
         assert_eq!(
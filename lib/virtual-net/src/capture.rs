@@ -0,0 +1,1007 @@
+//! Recording of the traffic of a networking implementation.
+//!
+//! [`CaptureNetworking`] wraps another [`VirtualNetworking`] and records
+//! the TCP connections, the payloads sent over them and the UDP and ICMP
+//! datagrams into a [`NetworkCapture`]. The capture can be written out in
+//! the pcapng format, which Wireshark and tcpdump can open.
+//!
+//! Stream sockets only see payloads, so the TCP/IP headers of the packets
+//! in the pcapng file are made up: every connection starts with a three
+//! way handshake, each write becomes a segment and closing a side of the
+//! connection becomes a FIN. Raw sockets are not recorded.
+use crate::{
+    DynVirtualNetworking, IpCidr, IpRoute, Result, SocketStatus, StreamSecurity,
+    VirtualConnectedSocket, VirtualConnectionlessSocket, VirtualIcmpSocket, VirtualIoSource,
+    VirtualNetworking, VirtualRawSocket, VirtualSocket, VirtualTcpListener, VirtualTcpSocket,
+    VirtualUdpSocket,
+};
+use bytes::Bytes;
+use derivative::Derivative;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::mem::MaybeUninit;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use virtual_mio::InterestHandler;
+
+/// Link type of packets that start with an IPv4 or IPv6 header
+const LINKTYPE_RAW: u16 = 101;
+
+const IPV4_HEADER_SIZE: usize = 20;
+const IPV6_HEADER_SIZE: usize = 40;
+const TCP_HEADER_SIZE: usize = 20;
+const UDP_HEADER_SIZE: usize = 8;
+
+/// Largest payload that fits in a made up TCP segment
+const MAX_SEGMENT_SIZE: usize = u16::MAX as usize - IPV4_HEADER_SIZE - TCP_HEADER_SIZE;
+
+/// Largest payload that fits in a made up UDP datagram, the rest of larger
+/// ones is cut off in the capture
+const MAX_DATAGRAM_SIZE: usize = u16::MAX as usize - IPV4_HEADER_SIZE - UDP_HEADER_SIZE;
+
+/// Largest ICMP packet that fits in a made up IP packet, the rest of larger
+/// ones is cut off in the capture
+const MAX_ICMP_SIZE: usize = u16::MAX as usize - IPV4_HEADER_SIZE;
+
+const PROTOCOL_ICMP: u8 = 1;
+const PROTOCOL_TCP: u8 = 6;
+const PROTOCOL_UDP: u8 = 17;
+const PROTOCOL_ICMPV6: u8 = 58;
+
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_PSH: u8 = 0x08;
+const TCP_ACK: u8 = 0x10;
+
+/// Which way a recorded event went
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureDirection {
+    /// From the local socket to the peer
+    Outbound,
+    /// From the peer to the local socket
+    Inbound,
+}
+
+/// Something that happened on a socket
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CaptureEvent {
+    /// A TCP connection was established, outbound connections were opened
+    /// by the local socket and inbound ones were accepted by it
+    Connect,
+    /// Payload sent over a TCP connection
+    Data(Bytes),
+    /// One side of a TCP connection was closed
+    Close,
+    /// A UDP datagram
+    Udp(Bytes),
+    /// An ICMP packet, including its ICMP header
+    Icmp(Bytes),
+}
+
+/// An event recorded by a [`NetworkCapture`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaptureRecord {
+    /// Time since the UNIX epoch at which the event happened
+    pub timestamp: Duration,
+    pub local: SocketAddr,
+    pub peer: SocketAddr,
+    pub direction: CaptureDirection,
+    pub event: CaptureEvent,
+}
+
+#[derive(Default)]
+struct CaptureState {
+    records: Vec<CaptureRecord>,
+    /// Writer that the records are streamed to instead of being kept
+    sink: Option<PcapngWriter<Box<dyn Write + Send>>>,
+}
+
+/// The traffic recorded by a [`CaptureNetworking`].
+///
+/// The records are either kept in memory or, when the capture was created
+/// with [`NetworkCapture::with_writer`], written out as pcapng as soon as
+/// they are recorded.
+#[derive(Derivative, Clone, Default)]
+#[derivative(Debug)]
+pub struct NetworkCapture {
+    #[derivative(Debug = "ignore")]
+    state: Arc<Mutex<CaptureState>>,
+}
+
+impl NetworkCapture {
+    /// Creates a capture that keeps the records in memory
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a capture that streams the records to `writer` in the
+    /// pcapng format
+    pub fn with_writer(writer: impl Write + Send + 'static) -> io::Result<Self> {
+        let writer: Box<dyn Write + Send> = Box::new(writer);
+        let sink = PcapngWriter::new(writer)?;
+        Ok(Self {
+            state: Arc::new(Mutex::new(CaptureState {
+                records: Vec::new(),
+                sink: Some(sink),
+            })),
+        })
+    }
+
+    /// Returns the records that are kept in memory
+    pub fn records(&self) -> Vec<CaptureRecord> {
+        self.state.lock().unwrap().records.clone()
+    }
+
+    /// Writes the records that are kept in memory in the pcapng format
+    pub fn write_pcapng(&self, writer: impl Write) -> io::Result<()> {
+        let records = self.records();
+        let mut writer = PcapngWriter::new(writer)?;
+        for record in records.iter() {
+            writer.write_record(record)?;
+        }
+        writer.flush()
+    }
+
+    fn record(
+        &self,
+        local: SocketAddr,
+        peer: SocketAddr,
+        direction: CaptureDirection,
+        event: CaptureEvent,
+    ) {
+        let record = CaptureRecord {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default(),
+            local,
+            peer,
+            direction,
+            event,
+        };
+
+        let mut state = self.state.lock().unwrap();
+        match state.sink.as_mut() {
+            Some(sink) => {
+                // Flush every record as the capture is usually looked at
+                // after the process was killed
+                if let Err(err) = sink.write_record(&record).and_then(|_| sink.flush()) {
+                    tracing::warn!("unable to write the network capture - {}", err);
+                    state.sink.take();
+                }
+            }
+            None => state.records.push(record),
+        }
+    }
+}
+
+/// Sequence numbers and closed sides of a made up TCP connection, indexed
+/// by the side that sends (local first)
+#[derive(Default)]
+struct TcpStream {
+    seq: [u32; 2],
+    closed: [bool; 2],
+}
+
+/// Turns records into the packets of a pcapng file.
+struct PcapngWriter<W: Write> {
+    writer: W,
+    streams: HashMap<(SocketAddr, SocketAddr), TcpStream>,
+    ip_id: u16,
+}
+
+impl<W: Write> PcapngWriter<W> {
+    /// Writes the section header and the description of the one interface
+    /// that all the packets are captured on
+    fn new(mut writer: W) -> io::Result<Self> {
+        // Section header block
+        writer.write_all(&0x0A0D_0D0Au32.to_le_bytes())?;
+        writer.write_all(&28u32.to_le_bytes())?;
+        writer.write_all(&0x1A2B_3C4Du32.to_le_bytes())?;
+        writer.write_all(&1u16.to_le_bytes())?;
+        writer.write_all(&0u16.to_le_bytes())?;
+        writer.write_all(&(-1i64).to_le_bytes())?;
+        writer.write_all(&28u32.to_le_bytes())?;
+
+        // Interface description block, timestamps are in microseconds
+        writer.write_all(&1u32.to_le_bytes())?;
+        writer.write_all(&20u32.to_le_bytes())?;
+        writer.write_all(&LINKTYPE_RAW.to_le_bytes())?;
+        writer.write_all(&0u16.to_le_bytes())?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(&20u32.to_le_bytes())?;
+
+        Ok(Self {
+            writer,
+            streams: HashMap::new(),
+            ip_id: 0,
+        })
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    fn write_record(&mut self, record: &CaptureRecord) -> io::Result<()> {
+        let (src, dst, side) = match record.direction {
+            CaptureDirection::Outbound => (record.local, record.peer, 0),
+            CaptureDirection::Inbound => (record.peer, record.local, 1),
+        };
+        let key = (record.local, record.peer);
+
+        match &record.event {
+            CaptureEvent::Connect => {
+                self.streams.insert(
+                    key,
+                    TcpStream {
+                        seq: [1, 1],
+                        closed: [false, false],
+                    },
+                );
+                self.write_tcp(record.timestamp, src, dst, 0, 0, TCP_SYN, &[])?;
+                self.write_tcp(record.timestamp, dst, src, 0, 1, TCP_SYN | TCP_ACK, &[])?;
+                self.write_tcp(record.timestamp, src, dst, 1, 1, TCP_ACK, &[])?;
+            }
+            CaptureEvent::Data(data) => {
+                for chunk in data.chunks(MAX_SEGMENT_SIZE) {
+                    let stream = self.streams.entry(key).or_default();
+                    let (seq, ack) = (stream.seq[side], stream.seq[1 - side]);
+                    stream.seq[side] = seq.wrapping_add(chunk.len() as u32);
+                    let flags = TCP_PSH | TCP_ACK;
+                    self.write_tcp(record.timestamp, src, dst, seq, ack, flags, chunk)?;
+                }
+            }
+            CaptureEvent::Close => {
+                let stream = self.streams.entry(key).or_default();
+                let (seq, ack) = (stream.seq[side], stream.seq[1 - side]);
+                stream.seq[side] = seq.wrapping_add(1);
+                stream.closed[side] = true;
+                if stream.closed == [true, true] {
+                    self.streams.remove(&key);
+                }
+                self.write_tcp(record.timestamp, src, dst, seq, ack, TCP_FIN | TCP_ACK, &[])?;
+            }
+            CaptureEvent::Udp(data) => {
+                let data = &data[..data.len().min(MAX_DATAGRAM_SIZE)];
+                let len = (UDP_HEADER_SIZE + data.len()) as u16;
+                let mut datagram = Vec::with_capacity(len as usize);
+                datagram.extend_from_slice(&src.port().to_be_bytes());
+                datagram.extend_from_slice(&dst.port().to_be_bytes());
+                datagram.extend_from_slice(&len.to_be_bytes());
+                datagram.extend_from_slice(&[0, 0]);
+                datagram.extend_from_slice(data);
+                self.write_ip(record.timestamp, src.ip(), dst.ip(), PROTOCOL_UDP, datagram)?;
+            }
+            CaptureEvent::Icmp(data) => {
+                let protocol = match same_family(src.ip(), dst.ip()) {
+                    (IpAddr::V4(_), _) => PROTOCOL_ICMP,
+                    (IpAddr::V6(_), _) => PROTOCOL_ICMPV6,
+                };
+                let data = &data[..data.len().min(MAX_ICMP_SIZE)];
+                self.write_ip(
+                    record.timestamp,
+                    src.ip(),
+                    dst.ip(),
+                    protocol,
+                    data.to_vec(),
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn write_tcp(
+        &mut self,
+        timestamp: Duration,
+        src: SocketAddr,
+        dst: SocketAddr,
+        seq: u32,
+        ack: u32,
+        flags: u8,
+        payload: &[u8],
+    ) -> io::Result<()> {
+        let mut segment = Vec::with_capacity(TCP_HEADER_SIZE + payload.len());
+        segment.extend_from_slice(&src.port().to_be_bytes());
+        segment.extend_from_slice(&dst.port().to_be_bytes());
+        segment.extend_from_slice(&seq.to_be_bytes());
+        segment.extend_from_slice(&ack.to_be_bytes());
+        segment.push(((TCP_HEADER_SIZE / 4) as u8) << 4);
+        segment.push(flags);
+        segment.extend_from_slice(&u16::MAX.to_be_bytes());
+        segment.extend_from_slice(&[0, 0, 0, 0]);
+        segment.extend_from_slice(payload);
+        self.write_ip(timestamp, src.ip(), dst.ip(), PROTOCOL_TCP, segment)
+    }
+
+    /// Writes a packet that carries `payload`, filling in the checksum of
+    /// the TCP or UDP header that it starts with. The payload has to fit in
+    /// an IPv4 packet.
+    fn write_ip(
+        &mut self,
+        timestamp: Duration,
+        src: IpAddr,
+        dst: IpAddr,
+        protocol: u8,
+        mut payload: Vec<u8>,
+    ) -> io::Result<()> {
+        let (src, dst) = same_family(src, dst);
+        if payload.len() > u16::MAX as usize - IPV4_HEADER_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the payload does not fit in an IP packet",
+            ));
+        }
+        let len = payload.len() as u16;
+
+        let checksum_at = match protocol {
+            PROTOCOL_TCP => Some(16),
+            PROTOCOL_UDP => Some(6),
+            _ => None,
+        };
+        if let Some(at) = checksum_at {
+            let pseudo = pseudo_header(src, dst, protocol, len);
+            let mut sum = checksum(&[&pseudo, &payload]);
+            if protocol == PROTOCOL_UDP && sum == 0 {
+                sum = 0xFFFF;
+            }
+            payload[at..at + 2].copy_from_slice(&sum.to_be_bytes());
+        }
+
+        let mut packet = match (src, dst) {
+            (IpAddr::V4(src), IpAddr::V4(dst)) => {
+                self.ip_id = self.ip_id.wrapping_add(1);
+                let mut header = Vec::with_capacity(IPV4_HEADER_SIZE + payload.len());
+                header.push(0x45);
+                header.push(0);
+                header.extend_from_slice(&(IPV4_HEADER_SIZE as u16 + len).to_be_bytes());
+                header.extend_from_slice(&self.ip_id.to_be_bytes());
+                header.extend_from_slice(&0x4000u16.to_be_bytes());
+                header.push(64);
+                header.push(protocol);
+                header.extend_from_slice(&[0, 0]);
+                header.extend_from_slice(&src.octets());
+                header.extend_from_slice(&dst.octets());
+                let sum = checksum(&[&header]);
+                header[10..12].copy_from_slice(&sum.to_be_bytes());
+                header
+            }
+            (src, dst) => {
+                let mut header = Vec::with_capacity(IPV6_HEADER_SIZE + payload.len());
+                header.extend_from_slice(&0x6000_0000u32.to_be_bytes());
+                header.extend_from_slice(&len.to_be_bytes());
+                header.push(protocol);
+                header.push(64);
+                header.extend_from_slice(&to_ipv6(src).octets());
+                header.extend_from_slice(&to_ipv6(dst).octets());
+                header
+            }
+        };
+        packet.extend_from_slice(&payload);
+        self.write_packet(timestamp, &packet)
+    }
+
+    /// Writes an enhanced packet block
+    fn write_packet(&mut self, timestamp: Duration, packet: &[u8]) -> io::Result<()> {
+        let micros = timestamp.as_micros() as u64;
+        let padding = (4 - packet.len() % 4) % 4;
+        let total = (32 + packet.len() + padding) as u32;
+
+        self.writer.write_all(&6u32.to_le_bytes())?;
+        self.writer.write_all(&total.to_le_bytes())?;
+        self.writer.write_all(&0u32.to_le_bytes())?;
+        self.writer
+            .write_all(&((micros >> 32) as u32).to_le_bytes())?;
+        self.writer.write_all(&(micros as u32).to_le_bytes())?;
+        self.writer
+            .write_all(&(packet.len() as u32).to_le_bytes())?;
+        self.writer
+            .write_all(&(packet.len() as u32).to_le_bytes())?;
+        self.writer.write_all(packet)?;
+        self.writer.write_all(&[0u8; 3][..padding])?;
+        self.writer.write_all(&total.to_le_bytes())
+    }
+}
+
+fn to_ipv6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+/// Returns the addresses as IPv6 addresses unless they are both IPv4
+fn same_family(src: IpAddr, dst: IpAddr) -> (IpAddr, IpAddr) {
+    match (src, dst) {
+        (IpAddr::V4(_), IpAddr::V4(_)) => (src, dst),
+        _ => (to_ipv6(src).into(), to_ipv6(dst).into()),
+    }
+}
+
+/// The header that the TCP and UDP checksums are calculated over
+fn pseudo_header(src: IpAddr, dst: IpAddr, protocol: u8, len: u16) -> Vec<u8> {
+    let mut header = Vec::with_capacity(40);
+    match (src, dst) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            header.extend_from_slice(&src.octets());
+            header.extend_from_slice(&dst.octets());
+            header.push(0);
+            header.push(protocol);
+            header.extend_from_slice(&len.to_be_bytes());
+        }
+        (src, dst) => {
+            header.extend_from_slice(&to_ipv6(src).octets());
+            header.extend_from_slice(&to_ipv6(dst).octets());
+            header.extend_from_slice(&(len as u32).to_be_bytes());
+            header.extend_from_slice(&[0, 0, 0, protocol]);
+        }
+    }
+    header
+}
+
+/// The internet checksum of the concatenated `parts`, which all have an
+/// even length except for the last one
+fn checksum(parts: &[&[u8]]) -> u16 {
+    let mut sum = 0u32;
+    for part in parts {
+        for word in part.chunks(2) {
+            let word = match word {
+                [hi, lo] => u16::from_be_bytes([*hi, *lo]),
+                [hi] => u16::from_be_bytes([*hi, 0]),
+                _ => 0,
+            };
+            sum += word as u32;
+        }
+    }
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// A networking implementation that records the traffic of another one
+/// into a [`NetworkCapture`].
+#[derive(Debug)]
+pub struct CaptureNetworking {
+    inner: DynVirtualNetworking,
+    capture: NetworkCapture,
+}
+
+impl CaptureNetworking {
+    pub fn new(inner: DynVirtualNetworking, capture: NetworkCapture) -> Self {
+        Self { inner, capture }
+    }
+
+    pub fn capture(&self) -> &NetworkCapture {
+        &self.capture
+    }
+}
+
+#[async_trait::async_trait]
+impl VirtualNetworking for CaptureNetworking {
+    async fn bridge(
+        &self,
+        network: &str,
+        access_token: &str,
+        security: StreamSecurity,
+    ) -> Result<()> {
+        self.inner.bridge(network, access_token, security).await
+    }
+
+    async fn unbridge(&self) -> Result<()> {
+        self.inner.unbridge().await
+    }
+
+    async fn dhcp_acquire(&self) -> Result<Vec<IpAddr>> {
+        self.inner.dhcp_acquire().await
+    }
+
+    async fn ip_add(&self, ip: IpAddr, prefix: u8) -> Result<()> {
+        self.inner.ip_add(ip, prefix).await
+    }
+
+    async fn ip_remove(&self, ip: IpAddr) -> Result<()> {
+        self.inner.ip_remove(ip).await
+    }
+
+    async fn ip_clear(&self) -> Result<()> {
+        self.inner.ip_clear().await
+    }
+
+    async fn ip_list(&self) -> Result<Vec<IpCidr>> {
+        self.inner.ip_list().await
+    }
+
+    async fn mac(&self) -> Result<[u8; 6]> {
+        self.inner.mac().await
+    }
+
+    async fn gateway_set(&self, ip: IpAddr) -> Result<()> {
+        self.inner.gateway_set(ip).await
+    }
+
+    async fn route_add(
+        &self,
+        cidr: IpCidr,
+        via_router: IpAddr,
+        preferred_until: Option<Duration>,
+        expires_at: Option<Duration>,
+    ) -> Result<()> {
+        self.inner
+            .route_add(cidr, via_router, preferred_until, expires_at)
+            .await
+    }
+
+    async fn route_remove(&self, cidr: IpAddr) -> Result<()> {
+        self.inner.route_remove(cidr).await
+    }
+
+    async fn route_clear(&self) -> Result<()> {
+        self.inner.route_clear().await
+    }
+
+    async fn route_list(&self) -> Result<Vec<IpRoute>> {
+        self.inner.route_list().await
+    }
+
+    async fn bind_raw(&self) -> Result<Box<dyn VirtualRawSocket + Sync>> {
+        self.inner.bind_raw().await
+    }
+
+    async fn listen_tcp(
+        &self,
+        addr: SocketAddr,
+        only_v6: bool,
+        reuse_port: bool,
+        reuse_addr: bool,
+    ) -> Result<Box<dyn VirtualTcpListener + Sync>> {
+        let listener = self
+            .inner
+            .listen_tcp(addr, only_v6, reuse_port, reuse_addr)
+            .await?;
+        Ok(Box::new(CaptureTcpListener {
+            listener,
+            capture: self.capture.clone(),
+        }))
+    }
+
+    async fn bind_udp(
+        &self,
+        addr: SocketAddr,
+        reuse_port: bool,
+        reuse_addr: bool,
+    ) -> Result<Box<dyn VirtualUdpSocket + Sync>> {
+        let socket = self.inner.bind_udp(addr, reuse_port, reuse_addr).await?;
+        Ok(Box::new(CaptureUdpSocket {
+            socket,
+            capture: self.capture.clone(),
+        }))
+    }
+
+    async fn bind_icmp(&self, addr: IpAddr) -> Result<Box<dyn VirtualIcmpSocket + Sync>> {
+        let socket = self.inner.bind_icmp(addr).await?;
+        Ok(Box::new(CaptureIcmpSocket {
+            socket,
+            capture: self.capture.clone(),
+        }))
+    }
+
+    async fn connect_tcp(
+        &self,
+        addr: SocketAddr,
+        peer: SocketAddr,
+    ) -> Result<Box<dyn VirtualTcpSocket + Sync>> {
+        let socket = self.inner.connect_tcp(addr, peer).await?;
+        Ok(Box::new(CaptureTcpSocket::new(
+            socket,
+            peer,
+            CaptureDirection::Outbound,
+            self.capture.clone(),
+        )))
+    }
+
+    async fn resolve(
+        &self,
+        host: &str,
+        port: Option<u16>,
+        dns_server: Option<IpAddr>,
+    ) -> Result<Vec<IpAddr>> {
+        self.inner.resolve(host, port, dns_server).await
+    }
+}
+
+/// A TCP listener whose accepted connections are recorded.
+#[derive(Debug)]
+struct CaptureTcpListener {
+    listener: Box<dyn VirtualTcpListener + Sync>,
+    capture: NetworkCapture,
+}
+
+impl VirtualTcpListener for CaptureTcpListener {
+    fn try_accept(&mut self) -> Result<(Box<dyn VirtualTcpSocket + Sync>, SocketAddr)> {
+        let (socket, peer) = self.listener.try_accept()?;
+        let socket = CaptureTcpSocket::new(
+            socket,
+            peer,
+            CaptureDirection::Inbound,
+            self.capture.clone(),
+        );
+        Ok((Box::new(socket), peer))
+    }
+
+    fn set_handler(&mut self, handler: Box<dyn InterestHandler + Send + Sync>) -> Result<()> {
+        self.listener.set_handler(handler)
+    }
+
+    fn addr_local(&self) -> Result<SocketAddr> {
+        self.listener.addr_local()
+    }
+
+    fn set_ttl(&mut self, ttl: u8) -> Result<()> {
+        self.listener.set_ttl(ttl)
+    }
+
+    fn ttl(&self) -> Result<u8> {
+        self.listener.ttl()
+    }
+}
+
+impl VirtualIoSource for CaptureTcpListener {
+    fn remove_handler(&mut self) {
+        self.listener.remove_handler();
+    }
+}
+
+/// A TCP connection whose payloads are recorded.
+#[derive(Debug)]
+struct CaptureTcpSocket {
+    socket: Box<dyn VirtualTcpSocket + Sync>,
+    capture: NetworkCapture,
+    local: SocketAddr,
+    peer: SocketAddr,
+    /// Sides of the connection that were closed (local first)
+    closed: [bool; 2],
+}
+
+impl CaptureTcpSocket {
+    fn new(
+        socket: Box<dyn VirtualTcpSocket + Sync>,
+        peer: SocketAddr,
+        direction: CaptureDirection,
+        capture: NetworkCapture,
+    ) -> Self {
+        let local = socket
+            .addr_local()
+            .unwrap_or_else(|_| SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0));
+        capture.record(local, peer, direction, CaptureEvent::Connect);
+        Self {
+            socket,
+            capture,
+            local,
+            peer,
+            closed: [false, false],
+        }
+    }
+
+    fn record(&self, direction: CaptureDirection, event: CaptureEvent) {
+        self.capture.record(self.local, self.peer, direction, event);
+    }
+
+    fn record_close(&mut self, direction: CaptureDirection) {
+        let side = match direction {
+            CaptureDirection::Outbound => 0,
+            CaptureDirection::Inbound => 1,
+        };
+        if !self.closed[side] {
+            self.closed[side] = true;
+            self.record(direction, CaptureEvent::Close);
+        }
+    }
+}
+
+impl VirtualTcpSocket for CaptureTcpSocket {
+    fn set_recv_buf_size(&mut self, size: usize) -> Result<()> {
+        self.socket.set_recv_buf_size(size)
+    }
+
+    fn recv_buf_size(&self) -> Result<usize> {
+        self.socket.recv_buf_size()
+    }
+
+    fn set_send_buf_size(&mut self, size: usize) -> Result<()> {
+        self.socket.set_send_buf_size(size)
+    }
+
+    fn send_buf_size(&self) -> Result<usize> {
+        self.socket.send_buf_size()
+    }
+
+    fn set_nodelay(&mut self, reuse: bool) -> Result<()> {
+        self.socket.set_nodelay(reuse)
+    }
+
+    fn nodelay(&self) -> Result<bool> {
+        self.socket.nodelay()
+    }
+
+    fn addr_peer(&self) -> Result<SocketAddr> {
+        self.socket.addr_peer()
+    }
+
+    fn shutdown(&mut self, how: Shutdown) -> Result<()> {
+        self.socket.shutdown(how)?;
+        if matches!(how, Shutdown::Write | Shutdown::Both) {
+            self.record_close(CaptureDirection::Outbound);
+        }
+        Ok(())
+    }
+
+    fn is_closed(&self) -> bool {
+        self.socket.is_closed()
+    }
+}
+
+impl VirtualConnectedSocket for CaptureTcpSocket {
+    fn set_linger(&mut self, linger: Option<Duration>) -> Result<()> {
+        self.socket.set_linger(linger)
+    }
+
+    fn linger(&self) -> Result<Option<Duration>> {
+        self.socket.linger()
+    }
+
+    fn try_send(&mut self, data: &[u8]) -> Result<usize> {
+        let amt = self.socket.try_send(data)?;
+        if amt > 0 {
+            let data = Bytes::copy_from_slice(&data[..amt]);
+            self.record(CaptureDirection::Outbound, CaptureEvent::Data(data));
+        }
+        Ok(amt)
+    }
+
+    fn try_flush(&mut self) -> Result<()> {
+        self.socket.try_flush()
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.socket.close()?;
+        self.record_close(CaptureDirection::Outbound);
+        Ok(())
+    }
+
+    fn try_recv(&mut self, buf: &mut [MaybeUninit<u8>]) -> Result<usize> {
+        let amt = self.socket.try_recv(buf)?;
+        if amt > 0 {
+            let data: Vec<u8> = buf[..amt]
+                .iter()
+                .map(|b| unsafe { b.assume_init() })
+                .collect();
+            self.record(CaptureDirection::Inbound, CaptureEvent::Data(data.into()));
+        } else if !buf.is_empty() {
+            self.record_close(CaptureDirection::Inbound);
+        }
+        Ok(amt)
+    }
+}
+
+impl VirtualSocket for CaptureTcpSocket {
+    fn set_ttl(&mut self, ttl: u32) -> Result<()> {
+        self.socket.set_ttl(ttl)
+    }
+
+    fn ttl(&self) -> Result<u32> {
+        self.socket.ttl()
+    }
+
+    fn addr_local(&self) -> Result<SocketAddr> {
+        self.socket.addr_local()
+    }
+
+    fn status(&self) -> Result<SocketStatus> {
+        self.socket.status()
+    }
+
+    fn set_handler(&mut self, handler: Box<dyn InterestHandler + Send + Sync>) -> Result<()> {
+        self.socket.set_handler(handler)
+    }
+}
+
+impl VirtualIoSource for CaptureTcpSocket {
+    fn remove_handler(&mut self) {
+        self.socket.remove_handler();
+    }
+}
+
+impl Drop for CaptureTcpSocket {
+    fn drop(&mut self) {
+        // Dropping the socket closes the connection
+        self.record_close(CaptureDirection::Outbound);
+    }
+}
+
+/// Records a datagram that was received into `buf`
+fn record_recv(
+    capture: &NetworkCapture,
+    local: Result<SocketAddr>,
+    buf: &[MaybeUninit<u8>],
+    (amt, peer): (usize, SocketAddr),
+    event: fn(Bytes) -> CaptureEvent,
+) {
+    let data: Vec<u8> = buf[..amt]
+        .iter()
+        .map(|b| unsafe { b.assume_init() })
+        .collect();
+    let local = local.unwrap_or_else(|_| SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0));
+    capture.record(local, peer, CaptureDirection::Inbound, event(data.into()));
+}
+
+/// Records a datagram that was sent to `peer`
+fn record_send(
+    capture: &NetworkCapture,
+    local: Result<SocketAddr>,
+    data: &[u8],
+    peer: SocketAddr,
+    event: fn(Bytes) -> CaptureEvent,
+) {
+    let local = local.unwrap_or_else(|_| SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0));
+    let data = Bytes::copy_from_slice(data);
+    capture.record(local, peer, CaptureDirection::Outbound, event(data));
+}
+
+/// A UDP socket whose datagrams are recorded.
+#[derive(Debug)]
+struct CaptureUdpSocket {
+    socket: Box<dyn VirtualUdpSocket + Sync>,
+    capture: NetworkCapture,
+}
+
+impl VirtualUdpSocket for CaptureUdpSocket {
+    fn set_broadcast(&mut self, broadcast: bool) -> Result<()> {
+        self.socket.set_broadcast(broadcast)
+    }
+
+    fn broadcast(&self) -> Result<bool> {
+        self.socket.broadcast()
+    }
+
+    fn set_multicast_loop_v4(&mut self, val: bool) -> Result<()> {
+        self.socket.set_multicast_loop_v4(val)
+    }
+
+    fn multicast_loop_v4(&self) -> Result<bool> {
+        self.socket.multicast_loop_v4()
+    }
+
+    fn set_multicast_loop_v6(&mut self, val: bool) -> Result<()> {
+        self.socket.set_multicast_loop_v6(val)
+    }
+
+    fn multicast_loop_v6(&self) -> Result<bool> {
+        self.socket.multicast_loop_v6()
+    }
+
+    fn set_multicast_ttl_v4(&mut self, ttl: u32) -> Result<()> {
+        self.socket.set_multicast_ttl_v4(ttl)
+    }
+
+    fn multicast_ttl_v4(&self) -> Result<u32> {
+        self.socket.multicast_ttl_v4()
+    }
+
+    fn join_multicast_v4(&mut self, multiaddr: Ipv4Addr, iface: Ipv4Addr) -> Result<()> {
+        self.socket.join_multicast_v4(multiaddr, iface)
+    }
+
+    fn leave_multicast_v4(&mut self, multiaddr: Ipv4Addr, iface: Ipv4Addr) -> Result<()> {
+        self.socket.leave_multicast_v4(multiaddr, iface)
+    }
+
+    fn join_multicast_v6(&mut self, multiaddr: Ipv6Addr, iface: u32) -> Result<()> {
+        self.socket.join_multicast_v6(multiaddr, iface)
+    }
+
+    fn leave_multicast_v6(&mut self, multiaddr: Ipv6Addr, iface: u32) -> Result<()> {
+        self.socket.leave_multicast_v6(multiaddr, iface)
+    }
+
+    fn addr_peer(&self) -> Result<Option<SocketAddr>> {
+        self.socket.addr_peer()
+    }
+}
+
+impl VirtualConnectionlessSocket for CaptureUdpSocket {
+    fn try_send_to(&mut self, data: &[u8], addr: SocketAddr) -> Result<usize> {
+        let amt = self.socket.try_send_to(data, addr)?;
+        let local = self.socket.addr_local();
+        record_send(&self.capture, local, &data[..amt], addr, CaptureEvent::Udp);
+        Ok(amt)
+    }
+
+    fn try_recv_from(&mut self, buf: &mut [MaybeUninit<u8>]) -> Result<(usize, SocketAddr)> {
+        let ret = self.socket.try_recv_from(buf)?;
+        let local = self.socket.addr_local();
+        record_recv(&self.capture, local, buf, ret, CaptureEvent::Udp);
+        Ok(ret)
+    }
+}
+
+impl VirtualSocket for CaptureUdpSocket {
+    fn set_ttl(&mut self, ttl: u32) -> Result<()> {
+        self.socket.set_ttl(ttl)
+    }
+
+    fn ttl(&self) -> Result<u32> {
+        self.socket.ttl()
+    }
+
+    fn addr_local(&self) -> Result<SocketAddr> {
+        self.socket.addr_local()
+    }
+
+    fn status(&self) -> Result<SocketStatus> {
+        self.socket.status()
+    }
+
+    fn set_handler(&mut self, handler: Box<dyn InterestHandler + Send + Sync>) -> Result<()> {
+        self.socket.set_handler(handler)
+    }
+}
+
+impl VirtualIoSource for CaptureUdpSocket {
+    fn remove_handler(&mut self) {
+        self.socket.remove_handler();
+    }
+}
+
+/// An ICMP socket whose packets are recorded.
+#[derive(Debug)]
+struct CaptureIcmpSocket {
+    socket: Box<dyn VirtualIcmpSocket + Sync>,
+    capture: NetworkCapture,
+}
+
+impl VirtualIcmpSocket for CaptureIcmpSocket {}
+
+impl VirtualConnectionlessSocket for CaptureIcmpSocket {
+    fn try_send_to(&mut self, data: &[u8], addr: SocketAddr) -> Result<usize> {
+        let amt = self.socket.try_send_to(data, addr)?;
+        let local = self.socket.addr_local();
+        record_send(&self.capture, local, &data[..amt], addr, CaptureEvent::Icmp);
+        Ok(amt)
+    }
+
+    fn try_recv_from(&mut self, buf: &mut [MaybeUninit<u8>]) -> Result<(usize, SocketAddr)> {
+        let ret = self.socket.try_recv_from(buf)?;
+        let local = self.socket.addr_local();
+        record_recv(&self.capture, local, buf, ret, CaptureEvent::Icmp);
+        Ok(ret)
+    }
+}
+
+impl VirtualSocket for CaptureIcmpSocket {
+    fn set_ttl(&mut self, ttl: u32) -> Result<()> {
+        self.socket.set_ttl(ttl)
+    }
+
+    fn ttl(&self) -> Result<u32> {
+        self.socket.ttl()
+    }
+
+    fn addr_local(&self) -> Result<SocketAddr> {
+        self.socket.addr_local()
+    }
+
+    fn status(&self) -> Result<SocketStatus> {
+        self.socket.status()
+    }
+
+    fn set_handler(&mut self, handler: Box<dyn InterestHandler + Send + Sync>) -> Result<()> {
+        self.socket.set_handler(handler)
+    }
+}
+
+impl VirtualIoSource for CaptureIcmpSocket {
+    fn remove_handler(&mut self) {
+        self.socket.remove_handler();
+    }
+}
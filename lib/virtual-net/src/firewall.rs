@@ -0,0 +1,837 @@
+//! A networking implementation that only lets some of the traffic through.
+//!
+//! [`FirewallNetworking`] wraps another [`VirtualNetworking`] and checks
+//! every connection, listener, datagram and DNS lookup against a
+//! [`FirewallPolicy`] before it is passed on. Anything that the policy
+//! rejects fails with [`NetworkError::PermissionDenied`].
+//!
+//! Rules that name a host match the addresses that the host was resolved
+//! to through this networking implementation, which makes it possible to
+//! let a guest reach `db.internal` without knowing its addresses up front.
+//! The resolver doesn't report the TTL of the records, so an address is
+//! matched by the name for [`DEFAULT_HOST_LIFETIME`] after the lookup (see
+//! [`FirewallNetworking::with_host_lifetime`]) and has to be resolved again
+//! after that.
+//!
+//! The traffic that the guest receives is checked as well: connections that
+//! a listener accepts, and datagrams and ICMP packets that come in, are
+//! dropped unless the policy lets them in. Replies from the last
+//! [`MAX_PEERS`] peers that a socket sent to are always let in.
+use crate::{
+    DynVirtualNetworking, IpCidr, IpRoute, NetworkError, Result, SocketStatus, StreamSecurity,
+    VirtualConnectionlessSocket, VirtualIcmpSocket, VirtualIoSource, VirtualNetworking,
+    VirtualRawSocket, VirtualSocket, VirtualTcpListener, VirtualTcpSocket, VirtualUdpSocket,
+};
+use anyhow::{bail, Context};
+use derivative::Derivative;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::Hash;
+use std::mem::MaybeUninit;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use virtual_mio::InterestHandler;
+
+/// Port that the name server a guest asks for is reached on
+const DNS_PORT: u16 = 53;
+
+/// How long an address matches the names it was resolved from
+pub const DEFAULT_HOST_LIFETIME: Duration = Duration::from_secs(300);
+
+/// The number of peers whose replies a socket lets in
+pub const MAX_PEERS: usize = 1024;
+
+/// Which way the traffic that a rule applies to flows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FirewallDirection {
+    /// Connections, datagrams and DNS lookups made by the guest
+    Egress,
+    /// Connections and datagrams that the guest receives, where the address
+    /// of a rule is matched against the peer and its port against the local
+    /// port
+    Ingress,
+}
+
+/// Protocol of the traffic that a rule applies to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FirewallProtocol {
+    Tcp,
+    Udp,
+    Icmp,
+}
+
+/// Describes the traffic that an allow or deny rule applies to, every
+/// field that is set has to match.
+///
+/// Rules are written as a comma separated list of `key=value` pairs, for
+/// instance `proto=tcp,cidr=10.0.0.0/8,port=5432` or `host=*.internal`.
+/// The keys are `dir` (`in` or `out`), `proto` (`tcp`, `udp` or `icmp`),
+/// `cidr` (an address with an optional prefix), `host` (a DNS name, where
+/// `*.example.com` matches all of its subdomains) and `port` (a port or a
+/// range such as `8000-8080`). For incoming traffic the address is that of
+/// the peer while the port is the local one.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FirewallRule {
+    pub direction: Option<FirewallDirection>,
+    pub protocol: Option<FirewallProtocol>,
+    pub cidr: Option<IpCidr>,
+    pub host: Option<String>,
+    pub ports: Option<RangeInclusive<u16>>,
+}
+
+impl FirewallRule {
+    /// Returns true if the rule applies to traffic for `addr`, which was
+    /// resolved from the `hosts` names
+    fn matches_addr(
+        &self,
+        direction: FirewallDirection,
+        protocol: FirewallProtocol,
+        addr: SocketAddr,
+        hosts: &[&str],
+    ) -> bool {
+        if !self.matches_port(direction, protocol, addr.port()) {
+            return false;
+        }
+        if let Some(cidr) = &self.cidr {
+            if !cidr.contains(addr.ip()) {
+                return false;
+            }
+        }
+        if let Some(pattern) = &self.host {
+            if !hosts.iter().any(|host| host_matches(pattern, host)) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Returns true if the rule applies to traffic for `port`, whatever
+    /// the address is
+    fn matches_port(
+        &self,
+        direction: FirewallDirection,
+        protocol: FirewallProtocol,
+        port: u16,
+    ) -> bool {
+        if self.direction.map(|d| d != direction).unwrap_or(false)
+            || self.protocol.map(|p| p != protocol).unwrap_or(false)
+        {
+            return false;
+        }
+        match &self.ports {
+            // ICMP has no ports, so it never matches a rule that names some
+            Some(_) if protocol == FirewallProtocol::Icmp => false,
+            Some(ports) => ports.contains(&port),
+            None => true,
+        }
+    }
+
+    /// Returns true if the rule applies to DNS lookups of `host`, which
+    /// is only the case for rules that name a host
+    fn matches_name(&self, host: &str) -> bool {
+        match (&self.host, self.direction) {
+            (Some(_), Some(FirewallDirection::Ingress)) => false,
+            (Some(pattern), _) => host_matches(pattern, host),
+            (None, _) => false,
+        }
+    }
+}
+
+impl FromStr for FirewallRule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut rule = FirewallRule::default();
+
+        for pair in s.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair
+                .split_once('=')
+                .with_context(|| format!("Expected a key=value pair, found \"{pair}\""))?;
+            let value = value.trim();
+
+            match key.trim() {
+                "dir" => {
+                    rule.direction = Some(match value {
+                        "in" => FirewallDirection::Ingress,
+                        "out" => FirewallDirection::Egress,
+                        _ => bail!("Unknown direction \"{value}\", expected \"in\" or \"out\""),
+                    })
+                }
+                "proto" => {
+                    rule.protocol = Some(match value {
+                        "tcp" => FirewallProtocol::Tcp,
+                        "udp" => FirewallProtocol::Udp,
+                        "icmp" => FirewallProtocol::Icmp,
+                        _ => bail!("Unknown protocol \"{value}\""),
+                    })
+                }
+                "cidr" => {
+                    let (ip, prefix) = match value.split_once('/') {
+                        Some((ip, prefix)) => (ip, Some(prefix)),
+                        None => (value, None),
+                    };
+                    let ip: IpAddr = ip
+                        .parse()
+                        .with_context(|| format!("Invalid IP address \"{ip}\""))?;
+                    let max_prefix = if ip.is_ipv4() { 32 } else { 128 };
+                    let prefix = match prefix {
+                        Some(prefix) => prefix
+                            .parse()
+                            .ok()
+                            .filter(|prefix| *prefix <= max_prefix)
+                            .with_context(|| format!("Invalid prefix \"{prefix}\""))?,
+                        None => max_prefix,
+                    };
+                    rule.cidr = Some(IpCidr { ip, prefix });
+                }
+                "host" => rule.host = Some(normalize_host(value)),
+                "port" => {
+                    let (start, end) = value.split_once('-').unwrap_or((value, value));
+                    let start: u16 = start
+                        .parse()
+                        .with_context(|| format!("Invalid port \"{start}\""))?;
+                    let end: u16 = end
+                        .parse()
+                        .with_context(|| format!("Invalid port \"{end}\""))?;
+                    if start > end {
+                        bail!("Invalid port range \"{value}\"");
+                    }
+                    rule.ports = Some(start..=end);
+                }
+                other => bail!("Unknown key \"{other}\""),
+            }
+        }
+
+        Ok(rule)
+    }
+}
+
+/// Decides which traffic [`FirewallNetworking`] lets through.
+///
+/// Traffic that matches a deny rule is always rejected. When there are
+/// allow rules, only the traffic that matches one of them is let through,
+/// otherwise everything that is not denied is. A DNS lookup is only
+/// matched by the rules that name a host.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FirewallPolicy {
+    pub allow: Vec<FirewallRule>,
+    pub deny: Vec<FirewallRule>,
+}
+
+impl FirewallPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a rule for traffic that is let through
+    pub fn allow(mut self, rule: FirewallRule) -> Self {
+        self.allow.push(rule);
+        self
+    }
+
+    /// Adds a rule for traffic that is rejected
+    pub fn deny(mut self, rule: FirewallRule) -> Self {
+        self.deny.push(rule);
+        self
+    }
+
+    /// Returns true if the policy lets all the traffic through
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    /// Adds the rules of another policy to this one
+    pub fn update(&mut self, other: FirewallPolicy) {
+        let FirewallPolicy { allow, deny } = other;
+        self.allow.extend(allow);
+        self.deny.extend(deny);
+    }
+
+    fn permits_addr(
+        &self,
+        direction: FirewallDirection,
+        protocol: FirewallProtocol,
+        addr: SocketAddr,
+        hosts: &[&str],
+    ) -> bool {
+        let matches = |rule: &FirewallRule| rule.matches_addr(direction, protocol, addr, hosts);
+        !self.deny.iter().any(matches) && (self.allow.is_empty() || self.allow.iter().any(matches))
+    }
+
+    /// Returns true if some of the traffic that comes in on the local
+    /// `port` is let through. Deny rules that only apply to some peers are
+    /// left to the traffic itself.
+    fn permits_port(&self, protocol: FirewallProtocol, port: u16) -> bool {
+        let matches =
+            |rule: &FirewallRule| rule.matches_port(FirewallDirection::Ingress, protocol, port);
+        !self
+            .deny
+            .iter()
+            .any(|rule| rule.cidr.is_none() && rule.host.is_none() && matches(rule))
+            && (self.allow.is_empty() || self.allow.iter().any(matches))
+    }
+
+    fn permits_name(&self, host: &str) -> bool {
+        !self.deny.iter().any(|rule| rule.matches_name(host))
+            && (self.allow.is_empty() || self.allow.iter().any(|rule| rule.matches_name(host)))
+    }
+}
+
+/// Lower cases a DNS name and strips the trailing dot of a fully qualified
+/// name so that names are compared the same way that DNS does
+fn normalize_host(host: &str) -> String {
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// Returns true if the (normalized) `host` matches `pattern`, which is
+/// either `*`, `*.` followed by a domain or a name
+fn host_matches(pattern: &str, host: &str) -> bool {
+    if pattern == "*" {
+        return true;
+    }
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host
+            .strip_suffix(domain)
+            .map(|sub| sub.len() > 1 && sub.ends_with('.'))
+            .unwrap_or(false),
+        None => pattern == host,
+    }
+}
+
+#[derive(Debug)]
+struct FirewallState {
+    policy: FirewallPolicy,
+    /// Names that addresses were resolved from, with when they expire
+    hosts: Mutex<HashMap<IpAddr, HashMap<String, Instant>>>,
+    host_lifetime: Duration,
+}
+
+impl FirewallState {
+    fn check(
+        &self,
+        direction: FirewallDirection,
+        protocol: FirewallProtocol,
+        addr: SocketAddr,
+    ) -> Result<()> {
+        let now = Instant::now();
+        let mut hosts = self.hosts.lock().unwrap();
+        let names: Vec<&str> = match hosts.get_mut(&addr.ip()) {
+            Some(names) => {
+                names.retain(|_, expires| *expires > now);
+                names.keys().map(String::as_str).collect()
+            }
+            None => Vec::new(),
+        };
+        if self.policy.permits_addr(direction, protocol, addr, &names) {
+            Ok(())
+        } else {
+            tracing::debug!(?direction, ?protocol, %addr, "firewall denied traffic");
+            Err(NetworkError::PermissionDenied)
+        }
+    }
+
+    /// Checks that a listener or socket can be bound to the local `addr`
+    fn check_bind(&self, protocol: FirewallProtocol, addr: SocketAddr) -> Result<()> {
+        if self.policy.permits_port(protocol, addr.port()) {
+            Ok(())
+        } else {
+            tracing::debug!(?protocol, %addr, "firewall denied binding");
+            Err(NetworkError::PermissionDenied)
+        }
+    }
+
+    /// Checks the traffic that comes in from `peer` on the local `port`
+    fn check_ingress(&self, protocol: FirewallProtocol, peer: SocketAddr, port: u16) -> Result<()> {
+        self.check(
+            FirewallDirection::Ingress,
+            protocol,
+            SocketAddr::new(peer.ip(), port),
+        )
+    }
+
+    /// Remembers that `addrs` were resolved from `name`, forgetting the
+    /// names that expired
+    fn resolved(&self, name: &str, addrs: &[IpAddr]) {
+        let now = Instant::now();
+        let mut hosts = self.hosts.lock().unwrap();
+        hosts.retain(|_, names| {
+            names.retain(|_, expires| *expires > now);
+            !names.is_empty()
+        });
+        for addr in addrs {
+            hosts
+                .entry(*addr)
+                .or_default()
+                .insert(name.to_string(), now + self.host_lifetime);
+        }
+    }
+}
+
+/// The peers that a socket sent to, of which only the last [`MAX_PEERS`]
+/// are kept
+#[derive(Debug)]
+struct PeerSet<T> {
+    peers: HashSet<T>,
+    order: VecDeque<T>,
+}
+
+impl<T: Copy + Eq + Hash> PeerSet<T> {
+    fn new() -> Self {
+        Self {
+            peers: HashSet::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    fn insert(&mut self, peer: T) {
+        if !self.peers.insert(peer) {
+            return;
+        }
+        self.order.push_back(peer);
+        if self.order.len() > MAX_PEERS {
+            if let Some(oldest) = self.order.pop_front() {
+                self.peers.remove(&oldest);
+            }
+        }
+    }
+
+    fn contains(&self, peer: &T) -> bool {
+        self.peers.contains(peer)
+    }
+}
+
+/// A networking implementation that filters the traffic of another one
+/// with a [`FirewallPolicy`].
+///
+/// Raw sockets can't be filtered, so they are only available when the
+/// policy lets everything through.
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct FirewallNetworking {
+    inner: DynVirtualNetworking,
+    #[derivative(Debug = "ignore")]
+    state: Arc<FirewallState>,
+}
+
+impl FirewallNetworking {
+    pub fn new(inner: DynVirtualNetworking, policy: FirewallPolicy) -> Self {
+        Self::with_host_lifetime(inner, policy, DEFAULT_HOST_LIFETIME)
+    }
+
+    /// Creates a firewall where addresses match the names they were resolved
+    /// from for `host_lifetime`, rather than [`DEFAULT_HOST_LIFETIME`].
+    pub fn with_host_lifetime(
+        inner: DynVirtualNetworking,
+        policy: FirewallPolicy,
+        host_lifetime: Duration,
+    ) -> Self {
+        Self {
+            inner,
+            state: Arc::new(FirewallState {
+                policy,
+                hosts: Default::default(),
+                host_lifetime,
+            }),
+        }
+    }
+
+    pub fn policy(&self) -> &FirewallPolicy {
+        &self.state.policy
+    }
+}
+
+#[async_trait::async_trait]
+impl VirtualNetworking for FirewallNetworking {
+    async fn bridge(
+        &self,
+        network: &str,
+        access_token: &str,
+        security: StreamSecurity,
+    ) -> Result<()> {
+        self.inner.bridge(network, access_token, security).await
+    }
+
+    async fn unbridge(&self) -> Result<()> {
+        self.inner.unbridge().await
+    }
+
+    async fn dhcp_acquire(&self) -> Result<Vec<IpAddr>> {
+        self.inner.dhcp_acquire().await
+    }
+
+    async fn ip_add(&self, ip: IpAddr, prefix: u8) -> Result<()> {
+        self.inner.ip_add(ip, prefix).await
+    }
+
+    async fn ip_remove(&self, ip: IpAddr) -> Result<()> {
+        self.inner.ip_remove(ip).await
+    }
+
+    async fn ip_clear(&self) -> Result<()> {
+        self.inner.ip_clear().await
+    }
+
+    async fn ip_list(&self) -> Result<Vec<IpCidr>> {
+        self.inner.ip_list().await
+    }
+
+    async fn mac(&self) -> Result<[u8; 6]> {
+        self.inner.mac().await
+    }
+
+    async fn gateway_set(&self, ip: IpAddr) -> Result<()> {
+        self.inner.gateway_set(ip).await
+    }
+
+    async fn route_add(
+        &self,
+        cidr: IpCidr,
+        via_router: IpAddr,
+        preferred_until: Option<Duration>,
+        expires_at: Option<Duration>,
+    ) -> Result<()> {
+        self.inner
+            .route_add(cidr, via_router, preferred_until, expires_at)
+            .await
+    }
+
+    async fn route_remove(&self, cidr: IpAddr) -> Result<()> {
+        self.inner.route_remove(cidr).await
+    }
+
+    async fn route_clear(&self) -> Result<()> {
+        self.inner.route_clear().await
+    }
+
+    async fn route_list(&self) -> Result<Vec<IpRoute>> {
+        self.inner.route_list().await
+    }
+
+    async fn bind_raw(&self) -> Result<Box<dyn VirtualRawSocket + Sync>> {
+        if !self.state.policy.is_empty() {
+            return Err(NetworkError::PermissionDenied);
+        }
+        self.inner.bind_raw().await
+    }
+
+    async fn listen_tcp(
+        &self,
+        addr: SocketAddr,
+        only_v6: bool,
+        reuse_port: bool,
+        reuse_addr: bool,
+    ) -> Result<Box<dyn VirtualTcpListener + Sync>> {
+        self.state.check_bind(FirewallProtocol::Tcp, addr)?;
+        let listener = self
+            .inner
+            .listen_tcp(addr, only_v6, reuse_port, reuse_addr)
+            .await?;
+        Ok(Box::new(FirewallTcpListener {
+            listener,
+            state: self.state.clone(),
+        }))
+    }
+
+    async fn bind_udp(
+        &self,
+        addr: SocketAddr,
+        reuse_port: bool,
+        reuse_addr: bool,
+    ) -> Result<Box<dyn VirtualUdpSocket + Sync>> {
+        // Sockets that let the stack pick the port are how clients send
+        // datagrams, so only the datagrams themselves are checked
+        if addr.port() != 0 {
+            self.state.check_bind(FirewallProtocol::Udp, addr)?;
+        }
+        let socket = self.inner.bind_udp(addr, reuse_port, reuse_addr).await?;
+        Ok(Box::new(FirewallUdpSocket {
+            socket,
+            state: self.state.clone(),
+            peers: PeerSet::new(),
+        }))
+    }
+
+    async fn bind_icmp(&self, addr: IpAddr) -> Result<Box<dyn VirtualIcmpSocket + Sync>> {
+        let socket = self.inner.bind_icmp(addr).await?;
+        Ok(Box::new(FirewallIcmpSocket {
+            socket,
+            state: self.state.clone(),
+            peers: PeerSet::new(),
+        }))
+    }
+
+    async fn connect_tcp(
+        &self,
+        addr: SocketAddr,
+        peer: SocketAddr,
+    ) -> Result<Box<dyn VirtualTcpSocket + Sync>> {
+        self.state
+            .check(FirewallDirection::Egress, FirewallProtocol::Tcp, peer)?;
+        self.inner.connect_tcp(addr, peer).await
+    }
+
+    async fn resolve(
+        &self,
+        host: &str,
+        port: Option<u16>,
+        dns_server: Option<IpAddr>,
+    ) -> Result<Vec<IpAddr>> {
+        // Literal addresses are not looked up so there is nothing to filter
+        if host.parse::<IpAddr>().is_ok() {
+            return self.inner.resolve(host, port, dns_server).await;
+        }
+
+        let name = normalize_host(host);
+        if !self.state.policy.permits_name(&name) {
+            tracing::debug!(host = name.as_str(), "firewall denied DNS lookup");
+            return Err(NetworkError::PermissionDenied);
+        }
+        // The lookup is sent to the name server that the guest picked, so
+        // it has to be one that the guest could reach itself
+        if let Some(ip) = dns_server {
+            self.state.check(
+                FirewallDirection::Egress,
+                FirewallProtocol::Udp,
+                SocketAddr::new(ip, DNS_PORT),
+            )?;
+        }
+
+        let addrs = self.inner.resolve(host, port, dns_server).await?;
+        self.state.resolved(&name, &addrs);
+        Ok(addrs)
+    }
+}
+
+/// A TCP listener that only accepts the connections that the policy
+/// allows, the others are closed as soon as they are accepted.
+#[derive(Derivative)]
+#[derivative(Debug)]
+struct FirewallTcpListener {
+    listener: Box<dyn VirtualTcpListener + Sync>,
+    #[derivative(Debug = "ignore")]
+    state: Arc<FirewallState>,
+}
+
+impl VirtualTcpListener for FirewallTcpListener {
+    fn try_accept(&mut self) -> Result<(Box<dyn VirtualTcpSocket + Sync>, SocketAddr)> {
+        let port = self.listener.addr_local()?.port();
+        loop {
+            let (mut socket, peer) = self.listener.try_accept()?;
+            match self.state.check_ingress(FirewallProtocol::Tcp, peer, port) {
+                Ok(()) => return Ok((socket, peer)),
+                Err(_) => {
+                    socket.close().ok();
+                }
+            }
+        }
+    }
+
+    fn set_handler(&mut self, handler: Box<dyn InterestHandler + Send + Sync>) -> Result<()> {
+        self.listener.set_handler(handler)
+    }
+
+    fn addr_local(&self) -> Result<SocketAddr> {
+        self.listener.addr_local()
+    }
+
+    fn set_ttl(&mut self, ttl: u8) -> Result<()> {
+        self.listener.set_ttl(ttl)
+    }
+
+    fn ttl(&self) -> Result<u8> {
+        self.listener.ttl()
+    }
+}
+
+impl VirtualIoSource for FirewallTcpListener {
+    fn remove_handler(&mut self) {
+        self.listener.remove_handler();
+    }
+}
+
+/// A UDP socket that only sends and receives the datagrams that the policy
+/// allows.
+#[derive(Derivative)]
+#[derivative(Debug)]
+struct FirewallUdpSocket {
+    socket: Box<dyn VirtualUdpSocket + Sync>,
+    #[derivative(Debug = "ignore")]
+    state: Arc<FirewallState>,
+    /// Peers that datagrams were sent to, whose replies are let in
+    peers: PeerSet<SocketAddr>,
+}
+
+impl VirtualUdpSocket for FirewallUdpSocket {
+    fn set_broadcast(&mut self, broadcast: bool) -> Result<()> {
+        self.socket.set_broadcast(broadcast)
+    }
+
+    fn broadcast(&self) -> Result<bool> {
+        self.socket.broadcast()
+    }
+
+    fn set_multicast_loop_v4(&mut self, val: bool) -> Result<()> {
+        self.socket.set_multicast_loop_v4(val)
+    }
+
+    fn multicast_loop_v4(&self) -> Result<bool> {
+        self.socket.multicast_loop_v4()
+    }
+
+    fn set_multicast_loop_v6(&mut self, val: bool) -> Result<()> {
+        self.socket.set_multicast_loop_v6(val)
+    }
+
+    fn multicast_loop_v6(&self) -> Result<bool> {
+        self.socket.multicast_loop_v6()
+    }
+
+    fn set_multicast_ttl_v4(&mut self, ttl: u32) -> Result<()> {
+        self.socket.set_multicast_ttl_v4(ttl)
+    }
+
+    fn multicast_ttl_v4(&self) -> Result<u32> {
+        self.socket.multicast_ttl_v4()
+    }
+
+    fn join_multicast_v4(&mut self, multiaddr: Ipv4Addr, iface: Ipv4Addr) -> Result<()> {
+        self.socket.join_multicast_v4(multiaddr, iface)
+    }
+
+    fn leave_multicast_v4(&mut self, multiaddr: Ipv4Addr, iface: Ipv4Addr) -> Result<()> {
+        self.socket.leave_multicast_v4(multiaddr, iface)
+    }
+
+    fn join_multicast_v6(&mut self, multiaddr: Ipv6Addr, iface: u32) -> Result<()> {
+        self.socket.join_multicast_v6(multiaddr, iface)
+    }
+
+    fn leave_multicast_v6(&mut self, multiaddr: Ipv6Addr, iface: u32) -> Result<()> {
+        self.socket.leave_multicast_v6(multiaddr, iface)
+    }
+
+    fn addr_peer(&self) -> Result<Option<SocketAddr>> {
+        self.socket.addr_peer()
+    }
+}
+
+impl VirtualConnectionlessSocket for FirewallUdpSocket {
+    fn try_send_to(&mut self, data: &[u8], addr: SocketAddr) -> Result<usize> {
+        self.state
+            .check(FirewallDirection::Egress, FirewallProtocol::Udp, addr)?;
+        let ret = self.socket.try_send_to(data, addr)?;
+        self.peers.insert(addr);
+        Ok(ret)
+    }
+
+    fn try_recv_from(&mut self, buf: &mut [MaybeUninit<u8>]) -> Result<(usize, SocketAddr)> {
+        let port = self.socket.addr_local()?.port();
+        loop {
+            let (amt, peer) = self.socket.try_recv_from(buf)?;
+            if self.peers.contains(&peer)
+                || self
+                    .state
+                    .check_ingress(FirewallProtocol::Udp, peer, port)
+                    .is_ok()
+            {
+                return Ok((amt, peer));
+            }
+        }
+    }
+}
+
+impl VirtualSocket for FirewallUdpSocket {
+    fn set_ttl(&mut self, ttl: u32) -> Result<()> {
+        self.socket.set_ttl(ttl)
+    }
+
+    fn ttl(&self) -> Result<u32> {
+        self.socket.ttl()
+    }
+
+    fn addr_local(&self) -> Result<SocketAddr> {
+        self.socket.addr_local()
+    }
+
+    fn status(&self) -> Result<SocketStatus> {
+        self.socket.status()
+    }
+
+    fn set_handler(&mut self, handler: Box<dyn InterestHandler + Send + Sync>) -> Result<()> {
+        self.socket.set_handler(handler)
+    }
+}
+
+impl VirtualIoSource for FirewallUdpSocket {
+    fn remove_handler(&mut self) {
+        self.socket.remove_handler();
+    }
+}
+
+/// An ICMP socket that only sends and receives the packets that the policy
+/// allows.
+#[derive(Derivative)]
+#[derivative(Debug)]
+struct FirewallIcmpSocket {
+    socket: Box<dyn VirtualIcmpSocket + Sync>,
+    #[derivative(Debug = "ignore")]
+    state: Arc<FirewallState>,
+    /// Addresses that packets were sent to, whose replies are let in
+    peers: PeerSet<IpAddr>,
+}
+
+impl VirtualIcmpSocket for FirewallIcmpSocket {}
+
+impl VirtualConnectionlessSocket for FirewallIcmpSocket {
+    fn try_send_to(&mut self, data: &[u8], addr: SocketAddr) -> Result<usize> {
+        self.state
+            .check(FirewallDirection::Egress, FirewallProtocol::Icmp, addr)?;
+        let ret = self.socket.try_send_to(data, addr)?;
+        self.peers.insert(addr.ip());
+        Ok(ret)
+    }
+
+    fn try_recv_from(&mut self, buf: &mut [MaybeUninit<u8>]) -> Result<(usize, SocketAddr)> {
+        loop {
+            let (amt, peer) = self.socket.try_recv_from(buf)?;
+            if self.peers.contains(&peer.ip())
+                || self
+                    .state
+                    .check_ingress(FirewallProtocol::Icmp, peer, 0)
+                    .is_ok()
+            {
+                return Ok((amt, peer));
+            }
+        }
+    }
+}
+
+impl VirtualSocket for FirewallIcmpSocket {
+    fn set_ttl(&mut self, ttl: u32) -> Result<()> {
+        self.socket.set_ttl(ttl)
+    }
+
+    fn ttl(&self) -> Result<u32> {
+        self.socket.ttl()
+    }
+
+    fn addr_local(&self) -> Result<SocketAddr> {
+        self.socket.addr_local()
+    }
+
+    fn status(&self) -> Result<SocketStatus> {
+        self.socket.status()
+    }
+
+    fn set_handler(&mut self, handler: Box<dyn InterestHandler + Send + Sync>) -> Result<()> {
+        self.socket.set_handler(handler)
+    }
+}
+
+impl VirtualIoSource for FirewallIcmpSocket {
+    fn remove_handler(&mut self) {
+        self.socket.remove_handler();
+    }
+}
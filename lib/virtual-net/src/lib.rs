@@ -1,23 +1,43 @@
 #![cfg_attr(docsrs, feature(doc_cfg, doc_auto_cfg))]
 
+pub mod capture;
 #[cfg(any(feature = "remote"))]
 pub mod client;
+pub mod firewall;
 #[cfg(feature = "host-net")]
 pub mod host;
+pub mod link;
+pub mod loopback;
 pub mod meta;
+#[cfg(feature = "resolver")]
+pub mod resolver;
 #[cfg(any(feature = "remote"))]
 pub mod rx_tx;
 #[cfg(any(feature = "remote"))]
 pub mod server;
+#[cfg(feature = "stack")]
+pub mod stack;
 #[cfg(feature = "tokio")]
 #[cfg(test)]
 mod tests;
 
+pub use capture::{
+    CaptureDirection, CaptureEvent, CaptureNetworking, CaptureRecord, NetworkCapture,
+};
 #[cfg(any(feature = "remote"))]
 pub use client::{RemoteNetworkingClient, RemoteNetworkingClientDriver};
+pub use firewall::{
+    FirewallDirection, FirewallNetworking, FirewallPolicy, FirewallProtocol, FirewallRule,
+};
+pub use link::{EthernetPort, EthernetSwitch, UdpTunnel};
+pub use loopback::{LoopbackNetworking, LoopbackSwitch};
 use pin_project_lite::pin_project;
+#[cfg(feature = "resolver")]
+pub use resolver::{ResolverConfig, ResolverNetworking};
 #[cfg(any(feature = "remote"))]
 pub use server::{RemoteNetworkingServer, RemoteNetworkingServerDriver};
+#[cfg(feature = "stack")]
+pub use stack::{StackNetworking, StackNetworkingDriver};
 use std::fmt;
 use std::mem::MaybeUninit;
 pub use std::net::IpAddr;
@@ -52,6 +72,27 @@ pub struct IpCidr {
     pub prefix: u8,
 }
 
+impl IpCidr {
+    /// Returns true if `ip` is on the subnet of this CIDR
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.ip, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix.min(32) as u32)
+                    .unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.prefix.min(128) as u32)
+                    .unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
 /// Represents a routing entry in the routing table of the interface
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct IpRoute {
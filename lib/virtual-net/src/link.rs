@@ -0,0 +1,299 @@
+//! Links that carry Ethernet frames between networking stacks.
+//!
+//! A link is anything that implements [`VirtualRawSocket`], so the same
+//! stack can be attached to an [`EthernetSwitch`] that lives in memory, to a
+//! [`UdpTunnel`] that carries the frames to another host, or to the raw
+//! socket of a remote network.
+#![allow(unused_variables)]
+use crate::{
+    NetworkError, Result, SocketStatus, VirtualIoSource, VirtualNetworking, VirtualRawSocket,
+    VirtualSocket, VirtualUdpSocket,
+};
+use derivative::Derivative;
+use std::collections::{HashMap, VecDeque};
+use std::mem::MaybeUninit;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex, Weak};
+use virtual_mio::{InterestHandler, InterestType};
+
+/// Number of frames that can be queued on a port of a switch before new
+/// ones are dropped.
+const FRAME_QUEUE_SIZE: usize = 1024;
+
+/// Size of the header of an Ethernet frame, which holds the destination
+/// and source MAC addresses followed by the ether type.
+const ETHERNET_HEADER_SIZE: usize = 14;
+
+type Handler = Box<dyn InterestHandler + Send + Sync>;
+
+type PortId = u64;
+
+type MacAddress = [u8; 6];
+
+#[derive(Default)]
+struct SwitchState {
+    ports: HashMap<PortId, Weak<Mutex<PortState>>>,
+    /// The port that frames for a MAC address are forwarded to, which is
+    /// learnt from the source address of the frames sent by the ports
+    macs: HashMap<MacAddress, PortId>,
+    next_port: PortId,
+}
+
+/// A learning Ethernet switch that lives in memory.
+///
+/// Frames sent to a MAC address that the switch has seen before are only
+/// forwarded to the port that the address was seen on, everything else is
+/// flooded to all the other ports.
+#[derive(Derivative, Clone, Default)]
+#[derivative(Debug)]
+pub struct EthernetSwitch {
+    #[derivative(Debug = "ignore")]
+    state: Arc<Mutex<SwitchState>>,
+}
+
+impl EthernetSwitch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Plugs a new port into the switch
+    pub fn port(&self) -> EthernetPort {
+        let port = Arc::new(Mutex::new(PortState::default()));
+
+        let mut state = self.state.lock().unwrap();
+        let id = state.next_port;
+        state.next_port += 1;
+        state.ports.insert(id, Arc::downgrade(&port));
+
+        EthernetPort {
+            state: port,
+            switch: self.clone(),
+            id,
+            ttl: 64,
+        }
+    }
+}
+
+#[derive(Default)]
+struct PortState {
+    queue: VecDeque<Vec<u8>>,
+    handler: Option<Handler>,
+    promiscuous: bool,
+}
+
+/// A port on an [`EthernetSwitch`].
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct EthernetPort {
+    #[derivative(Debug = "ignore")]
+    state: Arc<Mutex<PortState>>,
+    switch: EthernetSwitch,
+    id: PortId,
+    ttl: u32,
+}
+
+impl VirtualRawSocket for EthernetPort {
+    fn try_send(&mut self, data: &[u8]) -> Result<usize> {
+        if data.len() < ETHERNET_HEADER_SIZE {
+            return Err(NetworkError::InvalidInput);
+        }
+        let mut dst = MacAddress::default();
+        dst.copy_from_slice(&data[..6]);
+        let mut src = MacAddress::default();
+        src.copy_from_slice(&data[6..12]);
+
+        let ports: Vec<_> = {
+            let mut switch = self.switch.state.lock().unwrap();
+            if src[0] & 0x01 == 0 {
+                switch.macs.insert(src, self.id);
+            }
+            switch.ports.retain(|_, port| port.strong_count() > 0);
+
+            // Broadcast and multicast frames have the lowest bit of the
+            // first octet set
+            let target = match dst[0] & 0x01 {
+                0 => switch.macs.get(&dst).copied(),
+                _ => None,
+            };
+            switch
+                .ports
+                .iter()
+                .filter(|(id, _)| **id != self.id)
+                .filter_map(|(id, port)| {
+                    let port = port.upgrade()?;
+                    match target {
+                        Some(target) if target != *id && !port.lock().unwrap().promiscuous => None,
+                        _ => Some(port),
+                    }
+                })
+                .collect()
+        };
+
+        for port in ports {
+            let mut port = port.lock().unwrap();
+            if port.queue.len() < FRAME_QUEUE_SIZE {
+                port.queue.push_back(data.to_vec());
+                if let Some(handler) = port.handler.as_mut() {
+                    handler.interest(InterestType::Readable);
+                }
+            }
+        }
+        Ok(data.len())
+    }
+
+    fn try_flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn try_recv(&mut self, buf: &mut [MaybeUninit<u8>]) -> Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        let frame = state.queue.pop_front().ok_or(NetworkError::WouldBlock)?;
+
+        let amt = buf.len().min(frame.len());
+        for (dst, src) in buf.iter_mut().zip(&frame[..amt]) {
+            dst.write(*src);
+        }
+        Ok(amt)
+    }
+
+    fn set_promiscuous(&mut self, promiscuous: bool) -> Result<()> {
+        self.state.lock().unwrap().promiscuous = promiscuous;
+        Ok(())
+    }
+
+    fn promiscuous(&self) -> Result<bool> {
+        Ok(self.state.lock().unwrap().promiscuous)
+    }
+}
+
+impl VirtualSocket for EthernetPort {
+    fn set_ttl(&mut self, ttl: u32) -> Result<()> {
+        self.ttl = ttl;
+        Ok(())
+    }
+
+    fn ttl(&self) -> Result<u32> {
+        Ok(self.ttl)
+    }
+
+    fn addr_local(&self) -> Result<SocketAddr> {
+        Err(NetworkError::Unsupported)
+    }
+
+    fn status(&self) -> Result<SocketStatus> {
+        Ok(SocketStatus::Opened)
+    }
+
+    fn set_handler(&mut self, mut handler: Handler) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if !state.queue.is_empty() {
+            handler.interest(InterestType::Readable);
+        }
+        handler.interest(InterestType::Writable);
+        state.handler.replace(handler);
+        Ok(())
+    }
+}
+
+impl VirtualIoSource for EthernetPort {
+    fn remove_handler(&mut self) {
+        self.state.lock().unwrap().handler.take();
+    }
+}
+
+impl Drop for EthernetPort {
+    fn drop(&mut self) {
+        let mut switch = self.switch.state.lock().unwrap();
+        switch.ports.remove(&self.id);
+        switch.macs.retain(|_, port| *port != self.id);
+    }
+}
+
+/// A link that carries each Ethernet frame in a UDP datagram to a peer,
+/// which makes it possible to bridge stacks that run on different hosts.
+#[derive(Debug)]
+pub struct UdpTunnel {
+    socket: Box<dyn VirtualUdpSocket + Sync>,
+    peer: SocketAddr,
+    promiscuous: bool,
+}
+
+impl UdpTunnel {
+    pub fn new(socket: Box<dyn VirtualUdpSocket + Sync>, peer: SocketAddr) -> Self {
+        Self {
+            socket,
+            peer,
+            promiscuous: false,
+        }
+    }
+
+    /// Opens a tunnel to `peer` from a UDP socket that is bound to any
+    /// address of `net`
+    pub async fn connect(net: &dyn VirtualNetworking, peer: SocketAddr) -> Result<Self> {
+        let addr: IpAddr = match peer {
+            SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+            SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+        };
+        let socket = net.bind_udp(SocketAddr::new(addr, 0), false, false).await?;
+        Ok(Self::new(socket, peer))
+    }
+}
+
+impl VirtualRawSocket for UdpTunnel {
+    fn try_send(&mut self, data: &[u8]) -> Result<usize> {
+        self.socket.try_send_to(data, self.peer)
+    }
+
+    fn try_flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn try_recv(&mut self, buf: &mut [MaybeUninit<u8>]) -> Result<usize> {
+        // Datagrams from anyone other than the peer are not part of the
+        // tunnel and are dropped
+        loop {
+            let (amt, from) = self.socket.try_recv_from(buf)?;
+            if from == self.peer {
+                return Ok(amt);
+            }
+        }
+    }
+
+    fn set_promiscuous(&mut self, promiscuous: bool) -> Result<()> {
+        // Every frame that comes out of the tunnel is already received
+        self.promiscuous = promiscuous;
+        Ok(())
+    }
+
+    fn promiscuous(&self) -> Result<bool> {
+        Ok(self.promiscuous)
+    }
+}
+
+impl VirtualSocket for UdpTunnel {
+    fn set_ttl(&mut self, ttl: u32) -> Result<()> {
+        self.socket.set_ttl(ttl)
+    }
+
+    fn ttl(&self) -> Result<u32> {
+        self.socket.ttl()
+    }
+
+    fn addr_local(&self) -> Result<SocketAddr> {
+        self.socket.addr_local()
+    }
+
+    fn status(&self) -> Result<SocketStatus> {
+        self.socket.status()
+    }
+
+    fn set_handler(&mut self, handler: Handler) -> Result<()> {
+        self.socket.set_handler(handler)
+    }
+}
+
+impl VirtualIoSource for UdpTunnel {
+    fn remove_handler(&mut self) {
+        self.socket.remove_handler();
+    }
+}
@@ -0,0 +1,851 @@
+//! A private network that lives in memory, without any host sockets.
+//!
+//! Every [`LoopbackNetworking`] that is created with
+//! [`LoopbackSwitch::interface`] is an interface plugged into the same
+//! switch. Interfaces are given addresses with `ip_add` and can reach the
+//! addresses of the other interfaces that they have a route to, either
+//! because the address is on the same subnet as one of their own addresses
+//! or because of a route that was added with `route_add` or `gateway_set`.
+//! The loopback addresses (`127.0.0.0/8` and `::1`) always lead back to the
+//! interface itself.
+//!
+//! This makes it possible to run several WASIX processes that talk to each
+//! other over TCP and UDP inside one host process, which is mostly useful
+//! for tests.
+#![allow(unused_variables)]
+use crate::{
+    IpCidr, IpRoute, NetworkError, Result, SocketStatus, VirtualConnectedSocket,
+    VirtualConnectionlessSocket, VirtualIoSource, VirtualNetworking, VirtualSocket,
+    VirtualTcpListener, VirtualTcpSocket, VirtualUdpSocket,
+};
+use derivative::Derivative;
+use std::collections::{HashMap, VecDeque};
+use std::mem::MaybeUninit;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr};
+use std::ops::RangeInclusive;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+use virtual_mio::{InterestHandler, InterestType};
+
+/// Number of bytes buffered in each direction of a TCP stream.
+const STREAM_BUFFER_SIZE: usize = 256 * 1024;
+
+/// Number of datagrams that can be queued on a UDP socket before new ones
+/// are dropped.
+const DATAGRAM_QUEUE_SIZE: usize = 256;
+
+/// Number of connections that can wait to be accepted by a TCP listener.
+const LISTEN_BACKLOG: usize = 128;
+
+/// Ports that are handed out to sockets that did not pick one.
+const EPHEMERAL_PORTS: RangeInclusive<u16> = 49152..=65535;
+
+const DEFAULT_TTL: u32 = 64;
+
+type Handler = Box<dyn InterestHandler + Send + Sync>;
+
+type InterfaceId = u64;
+
+/// Sockets by the interface and address that they are bound to
+type Bindings<T> = HashMap<(InterfaceId, SocketAddr), Weak<Mutex<T>>>;
+
+fn notify(handler: &mut Option<Handler>, interest: InterestType) {
+    if let Some(handler) = handler.as_mut() {
+        handler.interest(interest);
+    }
+}
+
+/// The unspecified address of the same family as `addr`, with the same port.
+fn unspecified(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V4(addr) => SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), addr.port()),
+        SocketAddr::V6(addr) => SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), addr.port()),
+    }
+}
+
+/// Finds the socket that is bound to `addr` on an interface, either to the
+/// address itself or to the unspecified address.
+fn lookup<T>(
+    table: &Bindings<T>,
+    interface: InterfaceId,
+    addr: SocketAddr,
+) -> Option<Arc<Mutex<T>>> {
+    [addr, unspecified(addr)]
+        .iter()
+        .find_map(|addr| table.get(&(interface, *addr)))
+        .and_then(|socket| socket.upgrade())
+}
+
+#[derive(Debug, Default)]
+struct Interface {
+    ips: Vec<IpCidr>,
+    routes: Vec<IpRoute>,
+}
+
+#[derive(Default)]
+struct SwitchState {
+    interfaces: HashMap<InterfaceId, Interface>,
+    listeners: Bindings<ListenerState>,
+    udp_sockets: Bindings<UdpState>,
+    next_interface: InterfaceId,
+    next_port: u16,
+}
+
+impl SwitchState {
+    /// The interface that has been given `ip`.
+    fn owner(&self, ip: IpAddr) -> Option<InterfaceId> {
+        self.interfaces
+            .iter()
+            .find(|(_, interface)| interface.ips.iter().any(|cidr| cidr.ip == ip))
+            .map(|(id, _)| *id)
+    }
+
+    /// Returns true if a socket of the interface can bind to `ip`.
+    fn is_local(&self, interface: InterfaceId, ip: IpAddr) -> bool {
+        ip.is_unspecified() || ip.is_loopback() || self.owner(ip) == Some(interface)
+    }
+
+    /// Works out which interface the packets that `from` sends to `ip` end
+    /// up at. Fails with `AddressNotAvailable` when `from` has no route to
+    /// the address, and with `ConnectionRefused` when no interface has it.
+    fn route(&self, from: InterfaceId, ip: IpAddr) -> Result<InterfaceId> {
+        if ip.is_loopback() {
+            return Ok(from);
+        }
+        let routed = match self.interfaces.get(&from) {
+            Some(interface) => {
+                interface.ips.iter().any(|cidr| cidr.contains(ip))
+                    || interface.routes.iter().any(|route| route.cidr.contains(ip))
+            }
+            None => false,
+        };
+        if !routed {
+            return Err(NetworkError::AddressNotAvailable);
+        }
+        self.owner(ip).ok_or(NetworkError::ConnectionRefused)
+    }
+
+    /// Picks the address that `from` sends packets to `ip` from, preferring
+    /// one on the same subnet.
+    fn source_ip(&self, from: InterfaceId, ip: IpAddr) -> Result<IpAddr> {
+        if ip.is_loopback() {
+            return Ok(ip);
+        }
+        let ips = match self.interfaces.get(&from) {
+            Some(interface) => &interface.ips,
+            None => return Err(NetworkError::AddressNotAvailable),
+        };
+        ips.iter()
+            .find(|cidr| cidr.contains(ip))
+            .or_else(|| ips.iter().find(|cidr| cidr.ip.is_ipv4() == ip.is_ipv4()))
+            .map(|cidr| cidr.ip)
+            .ok_or(NetworkError::AddressNotAvailable)
+    }
+
+    /// Hands out a port that nothing is bound to on the interface.
+    fn ephemeral_port(&mut self, interface: InterfaceId, ip: IpAddr) -> Result<u16> {
+        let count = EPHEMERAL_PORTS.end() - EPHEMERAL_PORTS.start() + 1;
+        for _ in 0..count {
+            let port = EPHEMERAL_PORTS.start() + self.next_port % count;
+            self.next_port = self.next_port.wrapping_add(1) % count;
+
+            let addr = SocketAddr::new(ip, port);
+            let key = (interface, addr);
+            let taken = matches!(self.listeners.get(&key), Some(l) if l.strong_count() > 0)
+                || matches!(self.udp_sockets.get(&key), Some(s) if s.strong_count() > 0);
+            if !taken {
+                return Ok(port);
+            }
+        }
+        Err(NetworkError::AddressInUse)
+    }
+
+    /// Checks that a socket can bind to `addr` on the interface, and picks
+    /// a port for it if it did not ask for one.
+    fn bind_addr<T>(
+        &mut self,
+        interface: InterfaceId,
+        mut addr: SocketAddr,
+        table: fn(&mut Self) -> &mut Bindings<T>,
+    ) -> Result<SocketAddr> {
+        if !self.is_local(interface, addr.ip()) {
+            return Err(NetworkError::AddressNotAvailable);
+        }
+        if addr.port() == 0 {
+            addr.set_port(self.ephemeral_port(interface, addr.ip())?);
+        }
+
+        let table = table(self);
+        table.retain(|_, socket| socket.strong_count() > 0);
+        if table.contains_key(&(interface, addr)) {
+            return Err(NetworkError::AddressInUse);
+        }
+        Ok(addr)
+    }
+}
+
+/// A switch that connects [`LoopbackNetworking`] interfaces together.
+#[derive(Derivative, Clone, Default)]
+#[derivative(Debug)]
+pub struct LoopbackSwitch {
+    #[derivative(Debug = "ignore")]
+    state: Arc<Mutex<SwitchState>>,
+}
+
+impl LoopbackSwitch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Plugs a new interface into the switch, it has no addresses until
+    /// they are added with `ip_add`.
+    pub fn interface(&self) -> LoopbackNetworking {
+        let mut state = self.state.lock().unwrap();
+        let id = state.next_interface;
+        state.next_interface += 1;
+        state.interfaces.insert(id, Interface::default());
+        LoopbackNetworking {
+            switch: self.clone(),
+            id,
+        }
+    }
+}
+
+/// An interface on a [`LoopbackSwitch`].
+#[derive(Debug)]
+pub struct LoopbackNetworking {
+    switch: LoopbackSwitch,
+    id: InterfaceId,
+}
+
+impl LoopbackNetworking {
+    fn with_interface<T>(&self, f: impl FnOnce(&mut Interface) -> T) -> T {
+        let mut state = self.switch.state.lock().unwrap();
+        f(state.interfaces.entry(self.id).or_default())
+    }
+}
+
+impl Drop for LoopbackNetworking {
+    fn drop(&mut self) {
+        let mut state = self.switch.state.lock().unwrap();
+        state.interfaces.remove(&self.id);
+    }
+}
+
+#[async_trait::async_trait]
+impl VirtualNetworking for LoopbackNetworking {
+    async fn ip_add(&self, ip: IpAddr, prefix: u8) -> Result<()> {
+        let max_prefix = if ip.is_ipv4() { 32 } else { 128 };
+        if prefix > max_prefix || ip.is_unspecified() || ip.is_loopback() {
+            return Err(NetworkError::InvalidInput);
+        }
+
+        let mut state = self.switch.state.lock().unwrap();
+        if matches!(state.owner(ip), Some(owner) if owner != self.id) {
+            return Err(NetworkError::AddressInUse);
+        }
+        let interface = state.interfaces.entry(self.id).or_default();
+        interface.ips.retain(|cidr| cidr.ip != ip);
+        interface.ips.push(IpCidr { ip, prefix });
+        Ok(())
+    }
+
+    async fn ip_remove(&self, ip: IpAddr) -> Result<()> {
+        self.with_interface(|interface| {
+            let len = interface.ips.len();
+            interface.ips.retain(|cidr| cidr.ip != ip);
+            if interface.ips.len() == len {
+                return Err(NetworkError::AddressNotAvailable);
+            }
+            Ok(())
+        })
+    }
+
+    async fn ip_clear(&self) -> Result<()> {
+        self.with_interface(|interface| interface.ips.clear());
+        Ok(())
+    }
+
+    async fn ip_list(&self) -> Result<Vec<IpCidr>> {
+        Ok(self.with_interface(|interface| interface.ips.clone()))
+    }
+
+    async fn mac(&self) -> Result<[u8; 6]> {
+        // A locally administered address that is unique on the switch
+        let id = (self.id as u32).to_be_bytes();
+        Ok([0x02, 0x00, id[0], id[1], id[2], id[3]])
+    }
+
+    async fn gateway_set(&self, ip: IpAddr) -> Result<()> {
+        let cidr = IpCidr {
+            ip: match ip {
+                IpAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+                IpAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+            },
+            prefix: 0,
+        };
+        self.route_add(cidr, ip, None, None).await
+    }
+
+    async fn route_add(
+        &self,
+        cidr: IpCidr,
+        via_router: IpAddr,
+        preferred_until: Option<Duration>,
+        expires_at: Option<Duration>,
+    ) -> Result<()> {
+        self.with_interface(|interface| {
+            interface.routes.retain(|route| route.cidr != cidr);
+            interface.routes.push(IpRoute {
+                cidr,
+                via_router,
+                preferred_until,
+                expires_at,
+            });
+        });
+        Ok(())
+    }
+
+    async fn route_remove(&self, cidr: IpAddr) -> Result<()> {
+        self.with_interface(|interface| interface.routes.retain(|route| route.cidr.ip != cidr));
+        Ok(())
+    }
+
+    async fn route_clear(&self) -> Result<()> {
+        self.with_interface(|interface| interface.routes.clear());
+        Ok(())
+    }
+
+    async fn route_list(&self) -> Result<Vec<IpRoute>> {
+        Ok(self.with_interface(|interface| interface.routes.clone()))
+    }
+
+    async fn listen_tcp(
+        &self,
+        addr: SocketAddr,
+        only_v6: bool,
+        reuse_port: bool,
+        reuse_addr: bool,
+    ) -> Result<Box<dyn VirtualTcpListener + Sync>> {
+        let mut state = self.switch.state.lock().unwrap();
+        let addr = state.bind_addr(self.id, addr, |state| &mut state.listeners)?;
+
+        let listener = Arc::new(Mutex::new(ListenerState::default()));
+        state
+            .listeners
+            .insert((self.id, addr), Arc::downgrade(&listener));
+        Ok(Box::new(LoopbackTcpListener {
+            state: listener,
+            addr,
+            ttl: DEFAULT_TTL as u8,
+        }))
+    }
+
+    async fn bind_udp(
+        &self,
+        addr: SocketAddr,
+        reuse_port: bool,
+        reuse_addr: bool,
+    ) -> Result<Box<dyn VirtualUdpSocket + Sync>> {
+        let mut state = self.switch.state.lock().unwrap();
+        let addr = state.bind_addr(self.id, addr, |state| &mut state.udp_sockets)?;
+
+        let socket = Arc::new(Mutex::new(UdpState::default()));
+        state
+            .udp_sockets
+            .insert((self.id, addr), Arc::downgrade(&socket));
+        Ok(Box::new(LoopbackUdpSocket {
+            state: socket,
+            switch: self.switch.clone(),
+            interface: self.id,
+            addr,
+            ttl: DEFAULT_TTL,
+            broadcast: false,
+            multicast_loop_v4: true,
+            multicast_loop_v6: true,
+            multicast_ttl_v4: 1,
+        }))
+    }
+
+    async fn connect_tcp(
+        &self,
+        addr: SocketAddr,
+        peer: SocketAddr,
+    ) -> Result<Box<dyn VirtualTcpSocket + Sync>> {
+        let (listener, local) = {
+            let mut state = self.switch.state.lock().unwrap();
+            let target = state.route(self.id, peer.ip())?;
+            let listener = lookup(&state.listeners, target, peer);
+            let listener = listener.ok_or(NetworkError::ConnectionRefused)?;
+
+            let ip = match addr.ip() {
+                ip if ip.is_unspecified() => state.source_ip(self.id, peer.ip())?,
+                ip if state.is_local(self.id, ip) => ip,
+                _ => return Err(NetworkError::AddressNotAvailable),
+            };
+            let port = match addr.port() {
+                0 => state.ephemeral_port(self.id, ip)?,
+                port => port,
+            };
+            (listener, SocketAddr::new(ip, port))
+        };
+
+        let mut listener = listener.lock().unwrap();
+        if listener.backlog.len() >= LISTEN_BACKLOG {
+            return Err(NetworkError::ConnectionRefused);
+        }
+        let (client, server) = LoopbackTcpStream::pair(local, peer);
+        listener.backlog.push_back((server, local));
+        notify(&mut listener.handler, InterestType::Readable);
+        Ok(Box::new(client))
+    }
+
+    async fn resolve(
+        &self,
+        host: &str,
+        port: Option<u16>,
+        dns_server: Option<IpAddr>,
+    ) -> Result<Vec<IpAddr>> {
+        // There is no name server on the switch so only addresses resolve
+        if host.eq_ignore_ascii_case("localhost") {
+            return Ok(vec![Ipv4Addr::LOCALHOST.into(), Ipv6Addr::LOCALHOST.into()]);
+        }
+        Ok(host.parse::<IpAddr>().into_iter().collect())
+    }
+}
+
+#[derive(Default)]
+struct ListenerState {
+    backlog: VecDeque<(LoopbackTcpStream, SocketAddr)>,
+    handler: Option<Handler>,
+}
+
+/// A TCP listener on a [`LoopbackSwitch`].
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct LoopbackTcpListener {
+    #[derivative(Debug = "ignore")]
+    state: Arc<Mutex<ListenerState>>,
+    addr: SocketAddr,
+    ttl: u8,
+}
+
+impl VirtualTcpListener for LoopbackTcpListener {
+    fn try_accept(&mut self) -> Result<(Box<dyn VirtualTcpSocket + Sync>, SocketAddr)> {
+        let mut state = self.state.lock().unwrap();
+        let (stream, addr) = state.backlog.pop_front().ok_or(NetworkError::WouldBlock)?;
+        Ok((Box::new(stream), addr))
+    }
+
+    fn set_handler(&mut self, mut handler: Handler) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if !state.backlog.is_empty() {
+            handler.interest(InterestType::Readable);
+        }
+        state.handler.replace(handler);
+        Ok(())
+    }
+
+    fn addr_local(&self) -> Result<SocketAddr> {
+        Ok(self.addr)
+    }
+
+    fn set_ttl(&mut self, ttl: u8) -> Result<()> {
+        self.ttl = ttl;
+        Ok(())
+    }
+
+    fn ttl(&self) -> Result<u8> {
+        Ok(self.ttl)
+    }
+}
+
+impl VirtualIoSource for LoopbackTcpListener {
+    fn remove_handler(&mut self) {
+        self.state.lock().unwrap().handler.take();
+    }
+}
+
+#[derive(Default)]
+struct Pipe {
+    buffer: VecDeque<u8>,
+    /// Nothing more will be written into the pipe
+    closed: bool,
+}
+
+#[derive(Default)]
+struct StreamState {
+    /// `pipes[end]` holds the data received by that end
+    pipes: [Pipe; 2],
+    handlers: [Option<Handler>; 2],
+    dropped: [bool; 2],
+}
+
+/// One end of a TCP connection on a [`LoopbackSwitch`].
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct LoopbackTcpStream {
+    #[derivative(Debug = "ignore")]
+    state: Arc<Mutex<StreamState>>,
+    end: usize,
+    local: SocketAddr,
+    peer: SocketAddr,
+    ttl: u32,
+    nodelay: bool,
+    linger: Option<Duration>,
+}
+
+impl LoopbackTcpStream {
+    /// Creates both ends of a connection from `local` to `peer`.
+    fn pair(local: SocketAddr, peer: SocketAddr) -> (Self, Self) {
+        let state = Arc::new(Mutex::new(StreamState::default()));
+        let client = Self {
+            state: state.clone(),
+            end: 0,
+            local,
+            peer,
+            ttl: DEFAULT_TTL,
+            nodelay: false,
+            linger: None,
+        };
+        let server = Self {
+            state,
+            end: 1,
+            local: peer,
+            peer: local,
+            ttl: DEFAULT_TTL,
+            nodelay: false,
+            linger: None,
+        };
+        (client, server)
+    }
+
+    fn peer_end(&self) -> usize {
+        1 - self.end
+    }
+}
+
+impl VirtualTcpSocket for LoopbackTcpStream {
+    fn set_recv_buf_size(&mut self, size: usize) -> Result<()> {
+        Ok(())
+    }
+
+    fn recv_buf_size(&self) -> Result<usize> {
+        Ok(STREAM_BUFFER_SIZE)
+    }
+
+    fn set_send_buf_size(&mut self, size: usize) -> Result<()> {
+        Ok(())
+    }
+
+    fn send_buf_size(&self) -> Result<usize> {
+        Ok(STREAM_BUFFER_SIZE)
+    }
+
+    fn set_nodelay(&mut self, nodelay: bool) -> Result<()> {
+        self.nodelay = nodelay;
+        Ok(())
+    }
+
+    fn nodelay(&self) -> Result<bool> {
+        Ok(self.nodelay)
+    }
+
+    fn addr_peer(&self) -> Result<SocketAddr> {
+        Ok(self.peer)
+    }
+
+    fn shutdown(&mut self, how: Shutdown) -> Result<()> {
+        let peer = self.peer_end();
+        let mut state = self.state.lock().unwrap();
+        if matches!(how, Shutdown::Write | Shutdown::Both) {
+            state.pipes[peer].closed = true;
+            notify(&mut state.handlers[peer], InterestType::Readable);
+        }
+        if matches!(how, Shutdown::Read | Shutdown::Both) {
+            let pipe = &mut state.pipes[self.end];
+            pipe.closed = true;
+            pipe.buffer.clear();
+            notify(&mut state.handlers[peer], InterestType::Writable);
+        }
+        Ok(())
+    }
+
+    fn is_closed(&self) -> bool {
+        self.state.lock().unwrap().dropped[self.peer_end()]
+    }
+}
+
+impl VirtualConnectedSocket for LoopbackTcpStream {
+    fn set_linger(&mut self, linger: Option<Duration>) -> Result<()> {
+        self.linger = linger;
+        Ok(())
+    }
+
+    fn linger(&self) -> Result<Option<Duration>> {
+        Ok(self.linger)
+    }
+
+    fn try_send(&mut self, data: &[u8]) -> Result<usize> {
+        let peer = self.peer_end();
+        let mut state = self.state.lock().unwrap();
+        if state.dropped[peer] {
+            return Err(NetworkError::ConnectionReset);
+        }
+        let pipe = &mut state.pipes[peer];
+        if pipe.closed {
+            return Err(NetworkError::BrokenPipe);
+        }
+        if data.is_empty() {
+            return Ok(0);
+        }
+
+        let amt = STREAM_BUFFER_SIZE
+            .saturating_sub(pipe.buffer.len())
+            .min(data.len());
+        if amt == 0 {
+            return Err(NetworkError::WouldBlock);
+        }
+        pipe.buffer.extend(&data[..amt]);
+        notify(&mut state.handlers[peer], InterestType::Readable);
+        Ok(amt)
+    }
+
+    fn try_flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.shutdown(Shutdown::Both)
+    }
+
+    fn try_recv(&mut self, buf: &mut [MaybeUninit<u8>]) -> Result<usize> {
+        let peer = self.peer_end();
+        let mut state = self.state.lock().unwrap();
+        let pipe = &mut state.pipes[self.end];
+        if pipe.buffer.is_empty() {
+            return match pipe.closed {
+                true => Ok(0),
+                false => Err(NetworkError::WouldBlock),
+            };
+        }
+
+        let amt = buf.len().min(pipe.buffer.len());
+        for (dst, src) in buf.iter_mut().zip(pipe.buffer.drain(..amt)) {
+            dst.write(src);
+        }
+        notify(&mut state.handlers[peer], InterestType::Writable);
+        Ok(amt)
+    }
+}
+
+impl VirtualSocket for LoopbackTcpStream {
+    fn set_ttl(&mut self, ttl: u32) -> Result<()> {
+        self.ttl = ttl;
+        Ok(())
+    }
+
+    fn ttl(&self) -> Result<u32> {
+        Ok(self.ttl)
+    }
+
+    fn addr_local(&self) -> Result<SocketAddr> {
+        Ok(self.local)
+    }
+
+    fn status(&self) -> Result<SocketStatus> {
+        match self.is_closed() {
+            true => Ok(SocketStatus::Closed),
+            false => Ok(SocketStatus::Opened),
+        }
+    }
+
+    fn set_handler(&mut self, mut handler: Handler) -> Result<()> {
+        let peer = self.peer_end();
+        let mut state = self.state.lock().unwrap();
+        let pipe = &state.pipes[self.end];
+        if !pipe.buffer.is_empty() || pipe.closed {
+            handler.interest(InterestType::Readable);
+        }
+        if state.dropped[peer] || state.pipes[peer].buffer.len() < STREAM_BUFFER_SIZE {
+            handler.interest(InterestType::Writable);
+        }
+        state.handlers[self.end].replace(handler);
+        Ok(())
+    }
+}
+
+impl VirtualIoSource for LoopbackTcpStream {
+    fn remove_handler(&mut self) {
+        self.state.lock().unwrap().handlers[self.end].take();
+    }
+}
+
+impl Drop for LoopbackTcpStream {
+    fn drop(&mut self) {
+        let peer = self.peer_end();
+        let mut state = self.state.lock().unwrap();
+        state.dropped[self.end] = true;
+        state.pipes[peer].closed = true;
+        state.handlers[self.end].take();
+        notify(&mut state.handlers[peer], InterestType::Readable);
+        notify(&mut state.handlers[peer], InterestType::Writable);
+        notify(&mut state.handlers[peer], InterestType::Closed);
+    }
+}
+
+#[derive(Default)]
+struct UdpState {
+    queue: VecDeque<(Vec<u8>, SocketAddr)>,
+    handler: Option<Handler>,
+}
+
+/// A UDP socket on a [`LoopbackSwitch`].
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct LoopbackUdpSocket {
+    #[derivative(Debug = "ignore")]
+    state: Arc<Mutex<UdpState>>,
+    switch: LoopbackSwitch,
+    interface: InterfaceId,
+    addr: SocketAddr,
+    ttl: u32,
+    broadcast: bool,
+    multicast_loop_v4: bool,
+    multicast_loop_v6: bool,
+    multicast_ttl_v4: u32,
+}
+
+impl VirtualUdpSocket for LoopbackUdpSocket {
+    fn set_broadcast(&mut self, broadcast: bool) -> Result<()> {
+        self.broadcast = broadcast;
+        Ok(())
+    }
+
+    fn broadcast(&self) -> Result<bool> {
+        Ok(self.broadcast)
+    }
+
+    fn set_multicast_loop_v4(&mut self, val: bool) -> Result<()> {
+        self.multicast_loop_v4 = val;
+        Ok(())
+    }
+
+    fn multicast_loop_v4(&self) -> Result<bool> {
+        Ok(self.multicast_loop_v4)
+    }
+
+    fn set_multicast_loop_v6(&mut self, val: bool) -> Result<()> {
+        self.multicast_loop_v6 = val;
+        Ok(())
+    }
+
+    fn multicast_loop_v6(&self) -> Result<bool> {
+        Ok(self.multicast_loop_v6)
+    }
+
+    fn set_multicast_ttl_v4(&mut self, ttl: u32) -> Result<()> {
+        self.multicast_ttl_v4 = ttl;
+        Ok(())
+    }
+
+    fn multicast_ttl_v4(&self) -> Result<u32> {
+        Ok(self.multicast_ttl_v4)
+    }
+
+    fn join_multicast_v4(&mut self, multiaddr: Ipv4Addr, iface: Ipv4Addr) -> Result<()> {
+        Err(NetworkError::Unsupported)
+    }
+
+    fn leave_multicast_v4(&mut self, multiaddr: Ipv4Addr, iface: Ipv4Addr) -> Result<()> {
+        Err(NetworkError::Unsupported)
+    }
+
+    fn join_multicast_v6(&mut self, multiaddr: Ipv6Addr, iface: u32) -> Result<()> {
+        Err(NetworkError::Unsupported)
+    }
+
+    fn leave_multicast_v6(&mut self, multiaddr: Ipv6Addr, iface: u32) -> Result<()> {
+        Err(NetworkError::Unsupported)
+    }
+
+    fn addr_peer(&self) -> Result<Option<SocketAddr>> {
+        Ok(None)
+    }
+}
+
+impl VirtualConnectionlessSocket for LoopbackUdpSocket {
+    fn try_send_to(&mut self, data: &[u8], addr: SocketAddr) -> Result<usize> {
+        let (target, from) = {
+            let state = self.switch.state.lock().unwrap();
+            let ip = match self.addr.ip() {
+                ip if ip.is_unspecified() => state.source_ip(self.interface, addr.ip())?,
+                ip => ip,
+            };
+            // Like on a real network, datagrams that nobody is listening
+            // for are dropped
+            let target = match state.route(self.interface, addr.ip()) {
+                Ok(target) => lookup(&state.udp_sockets, target, addr),
+                Err(NetworkError::ConnectionRefused) => None,
+                Err(err) => return Err(err),
+            };
+            (target, SocketAddr::new(ip, self.addr.port()))
+        };
+
+        if let Some(target) = target {
+            let mut target = target.lock().unwrap();
+            if target.queue.len() < DATAGRAM_QUEUE_SIZE {
+                target.queue.push_back((data.to_vec(), from));
+                notify(&mut target.handler, InterestType::Readable);
+            }
+        }
+        Ok(data.len())
+    }
+
+    fn try_recv_from(&mut self, buf: &mut [MaybeUninit<u8>]) -> Result<(usize, SocketAddr)> {
+        let mut state = self.state.lock().unwrap();
+        let (data, from) = state.queue.pop_front().ok_or(NetworkError::WouldBlock)?;
+
+        // The part of the datagram that does not fit is discarded
+        let amt = buf.len().min(data.len());
+        for (dst, src) in buf.iter_mut().zip(&data[..amt]) {
+            dst.write(*src);
+        }
+        Ok((amt, from))
+    }
+}
+
+impl VirtualSocket for LoopbackUdpSocket {
+    fn set_ttl(&mut self, ttl: u32) -> Result<()> {
+        self.ttl = ttl;
+        Ok(())
+    }
+
+    fn ttl(&self) -> Result<u32> {
+        Ok(self.ttl)
+    }
+
+    fn addr_local(&self) -> Result<SocketAddr> {
+        Ok(self.addr)
+    }
+
+    fn status(&self) -> Result<SocketStatus> {
+        Ok(SocketStatus::Opened)
+    }
+
+    fn set_handler(&mut self, mut handler: Handler) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if !state.queue.is_empty() {
+            handler.interest(InterestType::Readable);
+        }
+        handler.interest(InterestType::Writable);
+        state.handler.replace(handler);
+        Ok(())
+    }
+}
+
+impl VirtualIoSource for LoopbackUdpSocket {
+    fn remove_handler(&mut self) {
+        self.state.lock().unwrap().handler.take();
+    }
+}
@@ -0,0 +1,553 @@
+//! A resolver that sits in front of the name resolution of a networking
+//! implementation.
+//!
+//! [`ResolverNetworking`] wraps another [`VirtualNetworking`] and answers
+//! `resolve` itself: names in the hosts map of its [`ResolverConfig`]
+//! resolve to fixed addresses, like they would with `/etc/hosts`, and when
+//! name servers are configured the other names are looked up by sending
+//! DNS queries over UDP through the wrapped networking. The answers of the
+//! name servers are cached, separately for each name server, for as long
+//! as their TTL allows.
+//!
+//! Without name servers the names that are not in the hosts map are passed
+//! on to the wrapped networking, so a resolver with only a hosts map is a
+//! way to point a few names at local services.
+use crate::{
+    DynVirtualNetworking, IpCidr, IpRoute, NetworkError, Result, StreamSecurity,
+    VirtualConnectionlessSocketExt, VirtualIcmpSocket, VirtualNetworking, VirtualRawSocket,
+    VirtualTcpListener, VirtualTcpSocket, VirtualUdpSocket,
+};
+use derivative::Derivative;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::mem::MaybeUninit;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Port that name servers listen on unless told otherwise.
+pub const DNS_PORT: u16 = 53;
+
+/// How long to wait for a name server to answer when the configuration
+/// does not say.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Largest DNS message that is accepted, queries are sent without EDNS so
+/// the answers should fit in 512 bytes.
+const MAX_MESSAGE_SIZE: usize = 4096;
+
+const DNS_HEADER_SIZE: usize = 12;
+
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+
+/// Recursion desired
+const FLAG_RD: u16 = 0x0100;
+/// The message is a response
+const FLAG_QR: u16 = 0x8000;
+
+const RCODE_NXDOMAIN: u16 = 3;
+
+/// Configures how a [`ResolverNetworking`] resolves names.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResolverConfig {
+    /// Names that resolve to fixed addresses without a lookup, the names
+    /// are lower case and without a trailing dot
+    pub hosts: HashMap<String, Vec<IpAddr>>,
+    /// Name servers that are asked in turn until one of them answers
+    pub nameservers: Vec<SocketAddr>,
+    /// How long to wait for each name server, five seconds by default
+    pub timeout: Option<Duration>,
+}
+
+impl ResolverConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes `name` resolve to `ip`, calling this several times for the
+    /// same name gives it several addresses
+    pub fn host(mut self, name: &str, ip: IpAddr) -> Self {
+        self.add_host(name, ip);
+        self
+    }
+
+    /// Makes `name` resolve to `ip`, calling this several times for the
+    /// same name gives it several addresses
+    pub fn add_host(&mut self, name: &str, ip: IpAddr) {
+        self.hosts.entry(normalize_name(name)).or_default().push(ip);
+    }
+
+    /// Adds a name server that names are looked up with
+    pub fn nameserver(mut self, addr: SocketAddr) -> Self {
+        self.nameservers.push(addr);
+        self
+    }
+
+    /// Returns true if the configuration leaves resolving names to the
+    /// wrapped networking
+    pub fn is_empty(&self) -> bool {
+        self.hosts.is_empty() && self.nameservers.is_empty()
+    }
+
+    /// Adds the hosts and name servers of another configuration to this one
+    pub fn update(&mut self, other: ResolverConfig) {
+        let ResolverConfig {
+            hosts,
+            nameservers,
+            timeout,
+        } = other;
+        for (name, ips) in hosts {
+            self.hosts.entry(name).or_default().extend(ips);
+        }
+        self.nameservers.extend(nameservers);
+        self.timeout = timeout.or(self.timeout);
+    }
+}
+
+/// Lower cases a name and strips the trailing dot of a fully qualified
+/// name, which is how names are compared
+fn normalize_name(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    addrs: Vec<IpAddr>,
+    expires_at: Instant,
+}
+
+/// A networking implementation that resolves names with a
+/// [`ResolverConfig`] and forwards everything else to another one.
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct ResolverNetworking {
+    inner: DynVirtualNetworking,
+    config: ResolverConfig,
+    /// Answers keyed by the name and the name server that gave them
+    #[derivative(Debug = "ignore")]
+    cache: Mutex<HashMap<(String, SocketAddr), CacheEntry>>,
+    /// Source of the IDs of the queries, which are hashed so that they
+    /// can't be guessed
+    #[derivative(Debug = "ignore")]
+    random: RandomState,
+    queries: AtomicU64,
+}
+
+impl ResolverNetworking {
+    pub fn new(inner: DynVirtualNetworking, config: ResolverConfig) -> Self {
+        Self {
+            inner,
+            config,
+            cache: Default::default(),
+            random: RandomState::new(),
+            queries: AtomicU64::new(0),
+        }
+    }
+
+    pub fn config(&self) -> &ResolverConfig {
+        &self.config
+    }
+
+    /// Removes all the answers of the name servers from the cache
+    pub fn clear_cache(&self) {
+        self.cache.lock().unwrap().clear();
+    }
+
+    fn query_id(&self) -> u16 {
+        let mut hasher = self.random.build_hasher();
+        hasher.write_u64(self.queries.fetch_add(1, Ordering::Relaxed));
+        hasher.finish() as u16
+    }
+
+    /// Looks up the IPv4 and IPv6 addresses of `name` with a name server,
+    /// returning them with the number of seconds they can be cached for
+    async fn lookup(&self, nameserver: SocketAddr, name: &str) -> Result<(Vec<IpAddr>, u32)> {
+        let local = match nameserver {
+            SocketAddr::V4(_) => SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0),
+            SocketAddr::V6(_) => SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 0),
+        };
+        let mut socket = self.inner.bind_udp(local, false, false).await?;
+
+        let mut pending = Vec::new();
+        for qtype in [TYPE_A, TYPE_AAAA] {
+            let id = self.query_id();
+            socket
+                .send_to(&encode_query(id, name, qtype)?, nameserver)
+                .await?;
+            pending.push((id, qtype));
+        }
+
+        let mut addrs = Vec::new();
+        let mut ttl = u32::MAX;
+        let receive = async {
+            let mut buf = [MaybeUninit::new(0u8); MAX_MESSAGE_SIZE];
+            while !pending.is_empty() {
+                let (amt, from) = socket.recv_from(&mut buf).await?;
+                if from != nameserver {
+                    continue;
+                }
+                let message: Vec<u8> = buf[..amt]
+                    .iter()
+                    .map(|b| unsafe { b.assume_init() })
+                    .collect();
+                // Stray or malformed answers, and answers to a question
+                // that was not asked, are ignored like a real resolver would
+                let answer = match decode_answer(&message) {
+                    Ok(answer)
+                        if answer.name == name && pending.contains(&(answer.id, answer.qtype)) =>
+                    {
+                        answer
+                    }
+                    _ => continue,
+                };
+                if answer.failed {
+                    return Err(NetworkError::ConnectionRefused);
+                }
+                pending.retain(|query| *query != (answer.id, answer.qtype));
+                if let Some(answer_ttl) = answer.ttl {
+                    ttl = ttl.min(answer_ttl);
+                }
+                addrs.extend(answer.addrs);
+            }
+            Ok::<_, NetworkError>(())
+        };
+        let timeout = self.config.timeout.unwrap_or(DEFAULT_TIMEOUT);
+        tokio::time::timeout(timeout, receive)
+            .await
+            .map_err(|_| NetworkError::TimedOut)??;
+
+        // IPv4 addresses first, the same way the host resolver orders them
+        addrs.sort_by_key(|addr| addr.is_ipv6());
+        Ok((addrs, ttl))
+    }
+}
+
+#[async_trait::async_trait]
+impl VirtualNetworking for ResolverNetworking {
+    async fn bridge(
+        &self,
+        network: &str,
+        access_token: &str,
+        security: StreamSecurity,
+    ) -> Result<()> {
+        self.inner.bridge(network, access_token, security).await
+    }
+
+    async fn unbridge(&self) -> Result<()> {
+        self.inner.unbridge().await
+    }
+
+    async fn dhcp_acquire(&self) -> Result<Vec<IpAddr>> {
+        self.inner.dhcp_acquire().await
+    }
+
+    async fn ip_add(&self, ip: IpAddr, prefix: u8) -> Result<()> {
+        self.inner.ip_add(ip, prefix).await
+    }
+
+    async fn ip_remove(&self, ip: IpAddr) -> Result<()> {
+        self.inner.ip_remove(ip).await
+    }
+
+    async fn ip_clear(&self) -> Result<()> {
+        self.inner.ip_clear().await
+    }
+
+    async fn ip_list(&self) -> Result<Vec<IpCidr>> {
+        self.inner.ip_list().await
+    }
+
+    async fn mac(&self) -> Result<[u8; 6]> {
+        self.inner.mac().await
+    }
+
+    async fn gateway_set(&self, ip: IpAddr) -> Result<()> {
+        self.inner.gateway_set(ip).await
+    }
+
+    async fn route_add(
+        &self,
+        cidr: IpCidr,
+        via_router: IpAddr,
+        preferred_until: Option<Duration>,
+        expires_at: Option<Duration>,
+    ) -> Result<()> {
+        self.inner
+            .route_add(cidr, via_router, preferred_until, expires_at)
+            .await
+    }
+
+    async fn route_remove(&self, cidr: IpAddr) -> Result<()> {
+        self.inner.route_remove(cidr).await
+    }
+
+    async fn route_clear(&self) -> Result<()> {
+        self.inner.route_clear().await
+    }
+
+    async fn route_list(&self) -> Result<Vec<IpRoute>> {
+        self.inner.route_list().await
+    }
+
+    async fn bind_raw(&self) -> Result<Box<dyn VirtualRawSocket + Sync>> {
+        self.inner.bind_raw().await
+    }
+
+    async fn listen_tcp(
+        &self,
+        addr: SocketAddr,
+        only_v6: bool,
+        reuse_port: bool,
+        reuse_addr: bool,
+    ) -> Result<Box<dyn VirtualTcpListener + Sync>> {
+        self.inner
+            .listen_tcp(addr, only_v6, reuse_port, reuse_addr)
+            .await
+    }
+
+    async fn bind_udp(
+        &self,
+        addr: SocketAddr,
+        reuse_port: bool,
+        reuse_addr: bool,
+    ) -> Result<Box<dyn VirtualUdpSocket + Sync>> {
+        self.inner.bind_udp(addr, reuse_port, reuse_addr).await
+    }
+
+    async fn bind_icmp(&self, addr: IpAddr) -> Result<Box<dyn VirtualIcmpSocket + Sync>> {
+        self.inner.bind_icmp(addr).await
+    }
+
+    async fn connect_tcp(
+        &self,
+        addr: SocketAddr,
+        peer: SocketAddr,
+    ) -> Result<Box<dyn VirtualTcpSocket + Sync>> {
+        self.inner.connect_tcp(addr, peer).await
+    }
+
+    async fn resolve(
+        &self,
+        host: &str,
+        port: Option<u16>,
+        dns_server: Option<IpAddr>,
+    ) -> Result<Vec<IpAddr>> {
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            return Ok(vec![ip]);
+        }
+
+        let name = normalize_name(host);
+        if let Some(addrs) = self.config.hosts.get(&name) {
+            return Ok(addrs.clone());
+        }
+
+        // A name server that the guest asked for takes the place of the
+        // configured ones
+        let nameservers = match dns_server {
+            Some(ip) => vec![SocketAddr::new(ip, DNS_PORT)],
+            None if self.config.nameservers.is_empty() => {
+                return self.inner.resolve(host, port, dns_server).await;
+            }
+            None => self.config.nameservers.clone(),
+        };
+        if name == "localhost" {
+            return Ok(vec![Ipv4Addr::LOCALHOST.into(), Ipv6Addr::LOCALHOST.into()]);
+        }
+
+        {
+            let cache = self.cache.lock().unwrap();
+            let now = Instant::now();
+            for nameserver in nameservers.iter() {
+                if let Some(entry) = cache.get(&(name.clone(), *nameserver)) {
+                    if entry.expires_at > now {
+                        return Ok(entry.addrs.clone());
+                    }
+                }
+            }
+        }
+
+        let mut ret = Err(NetworkError::AddressNotAvailable);
+        for nameserver in nameservers {
+            match self.lookup(nameserver, &name).await {
+                Ok((addrs, ttl)) => {
+                    if ttl > 0 && !addrs.is_empty() {
+                        let entry = CacheEntry {
+                            addrs: addrs.clone(),
+                            expires_at: Instant::now() + Duration::from_secs(ttl as u64),
+                        };
+                        self.cache.lock().unwrap().insert((name, nameserver), entry);
+                    }
+                    return Ok(addrs);
+                }
+                Err(err) => {
+                    tracing::debug!(%nameserver, "name server did not answer - {}", err);
+                    ret = Err(err);
+                }
+            }
+        }
+        ret
+    }
+}
+
+/// Encodes a recursive query for the `qtype` records of `name`
+fn encode_query(id: u16, name: &str, qtype: u16) -> Result<Vec<u8>> {
+    if name.is_empty() || name.len() > 253 {
+        return Err(NetworkError::InvalidInput);
+    }
+
+    let mut query = Vec::with_capacity(DNS_HEADER_SIZE + name.len() + 6);
+    query.extend_from_slice(&id.to_be_bytes());
+    query.extend_from_slice(&FLAG_RD.to_be_bytes());
+    query.extend_from_slice(&1u16.to_be_bytes());
+    query.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+    for label in name.split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(NetworkError::InvalidInput);
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&qtype.to_be_bytes());
+    query.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(query)
+}
+
+/// The addresses in the answer to a query
+struct Answer {
+    id: u16,
+    /// Name that the answer is for, normalized like the queried names
+    name: String,
+    /// Type of the records that the answer is for
+    qtype: u16,
+    /// The name server was unable to answer the query
+    failed: bool,
+    addrs: Vec<IpAddr>,
+    /// Smallest TTL of the address records, if there are any
+    ttl: Option<u32>,
+}
+
+/// Decodes the A and AAAA records of the answer to a query, the records
+/// of the aliases that lead to them are skipped
+fn decode_answer(message: &[u8]) -> Result<Answer> {
+    let u16_at = |at: usize| -> Result<u16> {
+        message
+            .get(at..at + 2)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+            .ok_or(NetworkError::InvalidData)
+    };
+
+    let id = u16_at(0)?;
+    let flags = u16_at(2)?;
+    if flags & FLAG_QR == 0 {
+        return Err(NetworkError::InvalidData);
+    }
+
+    // The answer repeats the one question of the query, which is what ties
+    // it to the query on top of the ID
+    if u16_at(4)? != 1 {
+        return Err(NetworkError::InvalidData);
+    }
+    let name = read_name(message, DNS_HEADER_SIZE)?;
+    let mut at = skip_name(message, DNS_HEADER_SIZE)?;
+    let qtype = u16_at(at)?;
+    if u16_at(at + 2)? != CLASS_IN {
+        return Err(NetworkError::InvalidData);
+    }
+    at += 4;
+
+    let mut answer = Answer {
+        id,
+        name,
+        qtype,
+        failed: false,
+        addrs: Vec::new(),
+        ttl: None,
+    };
+    match flags & 0x000F {
+        0 => {}
+        RCODE_NXDOMAIN => return Ok(answer),
+        _ => {
+            answer.failed = true;
+            return Ok(answer);
+        }
+    }
+
+    let records = u16_at(6)?;
+    for _ in 0..records {
+        at = skip_name(message, at)?;
+        let rtype = u16_at(at)?;
+        let class = u16_at(at + 2)?;
+        let mut ttl = u32::from(u16_at(at + 4)?) << 16 | u32::from(u16_at(at + 6)?);
+        // TTLs with the top bit set are treated as zero (RFC 2181 section 8)
+        if ttl & 0x8000_0000 != 0 {
+            ttl = 0;
+        }
+        let len = u16_at(at + 8)? as usize;
+        let data = message
+            .get(at + 10..at + 10 + len)
+            .ok_or(NetworkError::InvalidData)?;
+        at += 10 + len;
+
+        let addr: IpAddr = match (rtype, class, len) {
+            (TYPE_A, CLASS_IN, 4) => Ipv4Addr::new(data[0], data[1], data[2], data[3]).into(),
+            (TYPE_AAAA, CLASS_IN, 16) => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(data);
+                Ipv6Addr::from(octets).into()
+            }
+            _ => continue,
+        };
+        answer.addrs.push(addr);
+        answer.ttl = Some(answer.ttl.unwrap_or(u32::MAX).min(ttl));
+    }
+    Ok(answer)
+}
+
+/// Returns the offset just past the (possibly compressed) name at `at`
+fn skip_name(message: &[u8], mut at: usize) -> Result<usize> {
+    loop {
+        let len = *message.get(at).ok_or(NetworkError::InvalidData)?;
+        match len {
+            0 => return Ok(at + 1),
+            // A pointer to the rest of the name ends it
+            len if len & 0xC0 == 0xC0 => return Ok(at + 2),
+            len => at += 1 + len as usize,
+        }
+    }
+}
+
+/// Reads the (possibly compressed) name at `at`, normalized the same way
+/// as the names that are looked up
+fn read_name(message: &[u8], mut at: usize) -> Result<String> {
+    let mut labels = Vec::new();
+    // Pointers only lead backwards in a well formed message, the limit
+    // stops the ones that loop
+    let mut jumps = 0;
+    loop {
+        let len = *message.get(at).ok_or(NetworkError::InvalidData)?;
+        match len {
+            0 => break,
+            len if len & 0xC0 == 0xC0 => {
+                let low = *message.get(at + 1).ok_or(NetworkError::InvalidData)?;
+                jumps += 1;
+                if jumps > 16 {
+                    return Err(NetworkError::InvalidData);
+                }
+                at = ((len & 0x3F) as usize) << 8 | low as usize;
+            }
+            len => {
+                let label = message
+                    .get(at + 1..at + 1 + len as usize)
+                    .ok_or(NetworkError::InvalidData)?;
+                labels.push(String::from_utf8_lossy(label).to_ascii_lowercase());
+                at += 1 + len as usize;
+            }
+        }
+    }
+    Ok(labels.join("."))
+}
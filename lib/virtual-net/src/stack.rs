@@ -0,0 +1,1547 @@
+//! A [`VirtualNetworking`] that runs its own TCP/IP stack in userspace and
+//! exchanges Ethernet frames with the outside world over a link.
+//!
+//! The link is any [`VirtualRawSocket`], for instance a port on an
+//! [`EthernetSwitch`](crate::link::EthernetSwitch), a
+//! [`UdpTunnel`](crate::link::UdpTunnel) or the raw socket of a remote
+//! network. As the stack owns the interface it can hand out raw and ICMP
+//! sockets, acquire addresses with DHCP and keep its own routing table
+//! without needing any privileges on the host.
+//!
+//! Given an uplink, the stack can also be bridged with a remote network, in
+//! which case its frames go through a [`UdpTunnel`] to the peer that is
+//! named by the bridge instead of over the link it was created with.
+//!
+//! The stack only makes progress while the [`StackNetworkingDriver`] that
+//! is returned alongside it is polled, which is normally done by spawning
+//! it on the runtime.
+#![allow(unused_variables)]
+use crate::{
+    link::UdpTunnel, DynVirtualNetworking, IpCidr, IpRoute, NetworkError, Result, SocketStatus,
+    StreamSecurity, VirtualConnectedSocket, VirtualConnectionlessSocket, VirtualIcmpSocket,
+    VirtualIoSource, VirtualNetworking, VirtualRawSocket, VirtualSocket, VirtualTcpListener,
+    VirtualTcpSocket, VirtualUdpSocket,
+};
+use derivative::Derivative;
+use smoltcp::iface::{Config, Interface, Route, SocketHandle, SocketSet};
+use smoltcp::phy::{Device, DeviceCapabilities, Medium, RxToken, TxToken};
+use smoltcp::socket::{dhcpv4, icmp, tcp, udp, AnySocket, Socket};
+use smoltcp::time::Instant;
+use smoltcp::wire::{
+    EthernetAddress, HardwareAddress, Icmpv4Message, Icmpv4Packet, IpAddress, IpCidr as StackCidr,
+    IpEndpoint, IpListenEndpoint, Ipv4Cidr,
+};
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::mem::MaybeUninit;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr};
+use std::ops::RangeInclusive;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+#[allow(unused_imports, dead_code)]
+use tracing::{debug, error, info, trace, warn};
+use virtual_mio::{InterestHandler, InterestType};
+
+/// Largest Ethernet frame that is sent or received over the link, which
+/// is the standard MTU plus the Ethernet header.
+const FRAME_SIZE: usize = 1514;
+
+/// Number of bytes buffered in each direction of a TCP connection.
+const TCP_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Number of datagrams, and the number of bytes that they may use, that
+/// are buffered in each direction of a UDP or ICMP socket.
+const DATAGRAM_QUEUE_SIZE: usize = 64;
+const DATAGRAM_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Number of frames that can be queued on a raw socket before new ones are
+/// dropped.
+const FRAME_QUEUE_SIZE: usize = 1024;
+
+/// Number of sockets that wait for connections on behalf of a listener,
+/// which limits how many connections can be in the middle of their
+/// handshake at once.
+const LISTEN_SOCKETS: usize = 4;
+
+/// Number of connections that can wait to be accepted by a listener.
+const LISTEN_BACKLOG: usize = 128;
+
+/// Ports that are handed out to sockets that did not pick one.
+const EPHEMERAL_PORTS: RangeInclusive<u16> = 49152..=65535;
+
+const DEFAULT_TTL: u8 = 64;
+
+type Handler = Box<dyn InterestHandler + Send + Sync>;
+
+fn to_socket_addr(endpoint: IpEndpoint) -> SocketAddr {
+    SocketAddr::new(endpoint.addr.into(), endpoint.port)
+}
+
+fn to_endpoint(addr: SocketAddr) -> IpEndpoint {
+    IpEndpoint::new(addr.ip().into(), addr.port())
+}
+
+fn to_listen_endpoint(addr: SocketAddr) -> IpListenEndpoint {
+    IpListenEndpoint {
+        addr: match addr.ip().is_unspecified() {
+            true => None,
+            false => Some(addr.ip().into()),
+        },
+        port: addr.port(),
+    }
+}
+
+fn to_instant(time: Option<Duration>) -> Option<Instant> {
+    time.map(|time| Instant::from_micros(time.as_micros() as i64))
+}
+
+fn from_instant(time: Option<Instant>) -> Option<Duration> {
+    time.map(|time| Duration::from_micros(time.total_micros() as u64))
+}
+
+fn new_tcp_socket() -> tcp::Socket<'static> {
+    tcp::Socket::new(
+        tcp::SocketBuffer::new(vec![0u8; TCP_BUFFER_SIZE]),
+        tcp::SocketBuffer::new(vec![0u8; TCP_BUFFER_SIZE]),
+    )
+}
+
+/// Raises the interest of a TCP socket that is ready
+fn notify_tcp(socket: &tcp::Socket, handler: &mut Handler) {
+    let state = socket.state();
+    let connecting = matches!(
+        state,
+        tcp::State::Listen | tcp::State::SynSent | tcp::State::SynReceived
+    );
+    // A connection that will not receive any more data reads as EOF
+    if socket.can_recv() || (!connecting && !socket.may_recv()) {
+        handler.interest(InterestType::Readable);
+    }
+    if socket.can_send() {
+        handler.interest(InterestType::Writable);
+    }
+    if state == tcp::State::Closed {
+        handler.interest(InterestType::Closed);
+    }
+}
+
+fn notify_udp(socket: &udp::Socket, handler: &mut Handler) {
+    if socket.can_recv() {
+        handler.interest(InterestType::Readable);
+    }
+    if socket.can_send() {
+        handler.interest(InterestType::Writable);
+    }
+}
+
+fn notify_icmp(socket: &icmp::Socket, handler: &mut Handler) {
+    if socket.can_recv() {
+        handler.interest(InterestType::Readable);
+    }
+    if socket.can_send() {
+        handler.interest(InterestType::Writable);
+    }
+}
+
+#[derive(Default)]
+struct RawTap {
+    queue: VecDeque<Vec<u8>>,
+    handler: Option<Handler>,
+    promiscuous: bool,
+}
+
+/// Attaches the stack to the link, and hands a copy of every frame that is
+/// received to the raw sockets.
+struct LinkDevice {
+    link: Box<dyn VirtualRawSocket + Sync>,
+    mac: [u8; 6],
+    taps: Vec<Weak<Mutex<RawTap>>>,
+}
+
+impl LinkDevice {
+    fn recv_frame(&mut self) -> Option<Vec<u8>> {
+        let mut buf = [MaybeUninit::<u8>::uninit(); FRAME_SIZE];
+        let amt = self.link.try_recv(&mut buf).ok()?;
+        let frame: Vec<u8> = buf[..amt]
+            .iter()
+            .map(|b| unsafe { b.assume_init() })
+            .collect();
+
+        // Unless they are promiscuous, raw sockets only see the frames
+        // that are addressed to this interface
+        let for_us = frame.len() >= 6 && (frame[..6] == self.mac || frame[0] & 0x01 == 0x01);
+        self.taps.retain(|tap| tap.strong_count() > 0);
+        for tap in self.taps.iter().filter_map(|tap| tap.upgrade()) {
+            let mut tap = tap.lock().unwrap();
+            if (for_us || tap.promiscuous) && tap.queue.len() < FRAME_QUEUE_SIZE {
+                tap.queue.push_back(frame.clone());
+                if let Some(handler) = tap.handler.as_mut() {
+                    handler.interest(InterestType::Readable);
+                }
+            }
+        }
+        Some(frame)
+    }
+}
+
+impl Device for LinkDevice {
+    type RxToken<'a> = FrameRxToken where Self: 'a;
+    type TxToken<'a> = FrameTxToken<'a> where Self: 'a;
+
+    fn receive(&mut self, timestamp: Instant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        let frame = self.recv_frame()?;
+        Some((
+            FrameRxToken { frame },
+            FrameTxToken {
+                link: &mut self.link,
+            },
+        ))
+    }
+
+    fn transmit(&mut self, timestamp: Instant) -> Option<Self::TxToken<'_>> {
+        Some(FrameTxToken {
+            link: &mut self.link,
+        })
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        let mut caps = DeviceCapabilities::default();
+        caps.medium = Medium::Ethernet;
+        caps.max_transmission_unit = FRAME_SIZE;
+        caps
+    }
+}
+
+struct FrameRxToken {
+    frame: Vec<u8>,
+}
+
+impl RxToken for FrameRxToken {
+    fn consume<R, F>(mut self, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        f(&mut self.frame)
+    }
+}
+
+struct FrameTxToken<'a> {
+    link: &'a mut Box<dyn VirtualRawSocket + Sync>,
+}
+
+impl<'a> TxToken for FrameTxToken<'a> {
+    fn consume<R, F>(self, len: usize, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        let mut frame = vec![0u8; len];
+        let ret = f(&mut frame);
+        // Like a real network card, frames that the link can not take are
+        // dropped and left to the protocols to recover from
+        if let Err(err) = self.link.try_send(&frame) {
+            trace!("dropped outgoing frame - {}", err);
+        }
+        ret
+    }
+}
+
+struct ListenerState {
+    endpoint: IpListenEndpoint,
+    /// Sockets that are listening for, or in the middle of accepting, a
+    /// connection
+    pending: Vec<SocketHandle>,
+    /// Connections that are waiting for `try_accept`
+    accepted: VecDeque<SocketHandle>,
+    handler: Option<Handler>,
+}
+
+struct DhcpState {
+    handle: SocketHandle,
+    /// The address that was leased from the DHCP server
+    address: Option<Ipv4Cidr>,
+    wakers: Vec<Waker>,
+}
+
+struct StackState {
+    iface: Interface,
+    device: LinkDevice,
+    sockets: SocketSet<'static>,
+    handlers: HashMap<SocketHandle, Handler>,
+    listeners: HashMap<u64, ListenerState>,
+    next_listener: u64,
+    /// Sockets that were dropped while their connection was still open,
+    /// which are removed once it has been closed
+    closing: Vec<SocketHandle>,
+    dhcp: Option<DhcpState>,
+    /// The link that the stack was created with, while the stack is bridged
+    unbridged: Option<Box<dyn VirtualRawSocket + Sync>>,
+    next_port: u16,
+    next_ident: u16,
+    driver: Option<Waker>,
+}
+
+impl StackState {
+    /// Wakes up the driver so that the stack processes the changes that
+    /// were made to its sockets
+    fn wake_driver(&mut self) {
+        if let Some(driver) = self.driver.take() {
+            driver.wake();
+        }
+    }
+
+    fn poll(&mut self) {
+        self.iface
+            .poll(Instant::now(), &mut self.device, &mut self.sockets);
+        self.poll_dhcp();
+        self.poll_listeners();
+
+        let sockets = &mut self.sockets;
+        self.closing.retain(|handle| {
+            let socket = sockets.get::<tcp::Socket>(*handle);
+            if matches!(socket.state(), tcp::State::Closed | tcp::State::TimeWait) {
+                sockets.remove(*handle);
+                return false;
+            }
+            true
+        });
+
+        for (handle, socket) in self.sockets.iter() {
+            let handler = match self.handlers.get_mut(&handle) {
+                Some(handler) => handler,
+                None => continue,
+            };
+            match socket {
+                Socket::Tcp(socket) => notify_tcp(socket, handler),
+                Socket::Udp(socket) => notify_udp(socket, handler),
+                Socket::Icmp(socket) => notify_icmp(socket, handler),
+                _ => {}
+            }
+        }
+    }
+
+    /// Moves the connections that have been established to the accept
+    /// queue of their listener, and puts new sockets in their place
+    fn poll_listeners(&mut self) {
+        for listener in self.listeners.values_mut() {
+            let mut n = 0;
+            while n < listener.pending.len() {
+                let handle = listener.pending[n];
+                let socket = self.sockets.get_mut::<tcp::Socket>(handle);
+                match socket.state() {
+                    tcp::State::Listen | tcp::State::SynReceived => n += 1,
+                    // The handshake was reset so the socket goes back to
+                    // listening
+                    tcp::State::Closed => {
+                        socket.listen(listener.endpoint).ok();
+                        n += 1;
+                    }
+                    _ => {
+                        listener.pending.remove(n);
+                        listener.accepted.push_back(handle);
+                    }
+                }
+            }
+
+            while listener.pending.len() < LISTEN_SOCKETS
+                && listener.pending.len() + listener.accepted.len() < LISTEN_BACKLOG
+            {
+                let mut socket = new_tcp_socket();
+                if socket.listen(listener.endpoint).is_err() {
+                    break;
+                }
+                listener.pending.push(self.sockets.add(socket));
+            }
+
+            if !listener.accepted.is_empty() {
+                if let Some(handler) = listener.handler.as_mut() {
+                    handler.interest(InterestType::Readable);
+                }
+            }
+        }
+    }
+
+    /// Applies the lease that the DHCP client acquired to the interface
+    fn poll_dhcp(&mut self) {
+        let dhcp = match self.dhcp.as_mut() {
+            Some(dhcp) => dhcp,
+            None => return,
+        };
+        let lease = match self.sockets.get_mut::<dhcpv4::Socket>(dhcp.handle).poll() {
+            None => return,
+            Some(dhcpv4::Event::Configured(config)) => Some((config.address, config.router)),
+            Some(dhcpv4::Event::Deconfigured) => None,
+        };
+
+        let previous = dhcp.address.take();
+        self.iface.update_ip_addrs(|addrs| {
+            addrs.retain(|addr| Some(*addr) != previous.map(StackCidr::Ipv4));
+            if let Some((address, _)) = lease {
+                addrs.push(StackCidr::Ipv4(address)).ok();
+            }
+        });
+        self.iface.routes_mut().remove_default_ipv4_route();
+        if let Some((address, router)) = lease {
+            debug!("acquired {} from DHCP", address);
+            if let Some(router) = router {
+                self.iface.routes_mut().add_default_ipv4_route(router).ok();
+            }
+            dhcp.address = Some(address);
+            for waker in dhcp.wakers.drain(..) {
+                waker.wake();
+            }
+        }
+    }
+
+    /// Returns true if a TCP listener or UDP socket is bound to the port
+    fn is_port_used(&self, port: u16, tcp: bool) -> bool {
+        match tcp {
+            true => self
+                .listeners
+                .values()
+                .any(|listener| listener.endpoint.port == port),
+            false => self.sockets.iter().any(|(_, socket)| match socket {
+                Socket::Udp(socket) => socket.endpoint().port == port,
+                _ => false,
+            }),
+        }
+    }
+
+    /// Hands out a port that nothing of the same protocol is bound to
+    fn ephemeral_port(&mut self, tcp: bool) -> Result<u16> {
+        let count = EPHEMERAL_PORTS.end() - EPHEMERAL_PORTS.start() + 1;
+        for _ in 0..count {
+            let port = EPHEMERAL_PORTS.start() + self.next_port % count;
+            self.next_port = self.next_port.wrapping_add(1) % count;
+            if !self.is_port_used(port, tcp) {
+                return Ok(port);
+            }
+        }
+        Err(NetworkError::AddressInUse)
+    }
+
+    /// Returns true if sockets can bind to `ip`
+    fn is_local(&self, ip: IpAddr) -> bool {
+        ip.is_unspecified()
+            || ip.is_loopback()
+            || self
+                .iface
+                .ip_addrs()
+                .iter()
+                .any(|cidr| IpAddr::from(cidr.address()) == ip)
+    }
+
+    /// Checks that a socket can bind to `addr` and picks a port for it if
+    /// it did not ask for one
+    fn bind_addr(&mut self, mut addr: SocketAddr, tcp: bool) -> Result<SocketAddr> {
+        if !self.is_local(addr.ip()) {
+            return Err(NetworkError::AddressNotAvailable);
+        }
+        match addr.port() {
+            0 => addr.set_port(self.ephemeral_port(tcp)?),
+            port if self.is_port_used(port, tcp) => return Err(NetworkError::AddressInUse),
+            _ => {}
+        }
+        Ok(addr)
+    }
+
+    fn with_socket<T, R>(&mut self, handle: SocketHandle, f: impl FnOnce(&mut T) -> R) -> R
+    where
+        T: AnySocket<'static>,
+    {
+        let ret = f(self.sockets.get_mut::<T>(handle));
+        self.wake_driver();
+        ret
+    }
+}
+
+/// A [`VirtualNetworking`] with a TCP/IP stack that runs in userspace
+/// on top of a link.
+#[derive(Derivative, Clone)]
+#[derivative(Debug)]
+pub struct StackNetworking {
+    #[derivative(Debug = "ignore")]
+    state: Arc<Mutex<StackState>>,
+    mac: [u8; 6],
+    #[derivative(Debug = "ignore")]
+    uplink: Option<DynVirtualNetworking>,
+}
+
+impl StackNetworking {
+    /// Creates a stack with the given MAC address that sends and receives
+    /// frames over the link, the stack has no addresses until they are
+    /// added or acquired with DHCP
+    pub fn new(
+        link: Box<dyn VirtualRawSocket + Sync>,
+        mac: [u8; 6],
+    ) -> (Self, StackNetworkingDriver) {
+        let mut device = LinkDevice {
+            link,
+            mac,
+            taps: Vec::new(),
+        };
+
+        let mut config = Config::new(HardwareAddress::Ethernet(EthernetAddress(mac)));
+        config.random_seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_nanos() as u64)
+            .unwrap_or_default();
+        let iface = Interface::new(config, &mut device, Instant::now());
+
+        let state = Arc::new(Mutex::new(StackState {
+            iface,
+            device,
+            sockets: SocketSet::new(Vec::new()),
+            handlers: HashMap::new(),
+            listeners: HashMap::new(),
+            next_listener: 0,
+            closing: Vec::new(),
+            dhcp: None,
+            unbridged: None,
+            next_port: 0,
+            next_ident: 1,
+            driver: None,
+        }));
+        let driver = StackNetworkingDriver {
+            state: state.clone(),
+            sleep: Box::pin(tokio::time::sleep(Duration::ZERO)),
+        };
+        let net = Self {
+            state,
+            mac,
+            uplink: None,
+        };
+        (net, driver)
+    }
+
+    /// Bridges go through UDP sockets of `uplink`, without one the stack
+    /// can not be bridged
+    pub fn with_uplink(mut self, uplink: DynVirtualNetworking) -> Self {
+        self.uplink.replace(uplink);
+        self
+    }
+
+    /// Swaps the link that the stack sends and receives frames over
+    fn set_link(
+        &self,
+        mut link: Box<dyn VirtualRawSocket + Sync>,
+    ) -> Box<dyn VirtualRawSocket + Sync> {
+        let mut state = self.state.lock().unwrap();
+        std::mem::swap(&mut state.device.link, &mut link);
+        link.remove_handler();
+        state.wake_driver();
+        link
+    }
+}
+
+#[async_trait::async_trait]
+impl VirtualNetworking for StackNetworking {
+    /// Sends the frames of the stack through a [`UdpTunnel`] to `network`,
+    /// which is the address of the peer. The tunnel is not encrypted or
+    /// authenticated, so the access token is not used.
+    async fn bridge(
+        &self,
+        network: &str,
+        access_token: &str,
+        security: StreamSecurity,
+    ) -> Result<()> {
+        if !matches!(
+            security,
+            StreamSecurity::Unencrypted | StreamSecurity::AnyEncyption
+        ) {
+            return Err(NetworkError::Unsupported);
+        }
+        let peer: SocketAddr = network.parse().map_err(|_| NetworkError::InvalidInput)?;
+        let uplink = self.uplink.as_ref().ok_or(NetworkError::Unsupported)?;
+        let tunnel = UdpTunnel::connect(uplink.as_ref(), peer).await?;
+
+        let previous = self.set_link(Box::new(tunnel));
+        // Bridging again replaces the tunnel, the original link is kept
+        // for when the stack is unbridged
+        let mut state = self.state.lock().unwrap();
+        if state.unbridged.is_none() {
+            state.unbridged.replace(previous);
+        }
+        Ok(())
+    }
+
+    async fn unbridge(&self) -> Result<()> {
+        let link = self
+            .state
+            .lock()
+            .unwrap()
+            .unbridged
+            .take()
+            .ok_or(NetworkError::NotConnected)?;
+        self.set_link(link);
+        Ok(())
+    }
+
+    async fn dhcp_acquire(&self) -> Result<Vec<IpAddr>> {
+        {
+            let mut state = self.state.lock().unwrap();
+            if state.dhcp.is_none() {
+                let handle = state.sockets.add(dhcpv4::Socket::new());
+                state.dhcp.replace(DhcpState {
+                    handle,
+                    address: None,
+                    wakers: Vec::new(),
+                });
+                state.wake_driver();
+            }
+        }
+
+        futures_util::future::poll_fn(|cx| {
+            let mut state = self.state.lock().unwrap();
+            let dhcp = match state.dhcp.as_mut() {
+                Some(dhcp) => dhcp,
+                None => return Poll::Ready(Err(NetworkError::ConnectionAborted)),
+            };
+            match dhcp.address {
+                Some(address) => Poll::Ready(Ok(vec![IpAddr::V4(address.address().into())])),
+                None => {
+                    dhcp.wakers.push(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        })
+        .await
+    }
+
+    async fn ip_add(&self, ip: IpAddr, prefix: u8) -> Result<()> {
+        let max_prefix = if ip.is_ipv4() { 32 } else { 128 };
+        if prefix > max_prefix || ip.is_unspecified() {
+            return Err(NetworkError::InvalidInput);
+        }
+        let cidr = StackCidr::new(ip.into(), prefix);
+
+        let mut state = self.state.lock().unwrap();
+        let mut ret = Ok(());
+        state.iface.update_ip_addrs(|addrs| {
+            addrs.retain(|addr| addr.address() != cidr.address());
+            if addrs.push(cidr).is_err() {
+                ret = Err(NetworkError::InsufficientMemory);
+            }
+        });
+        state.wake_driver();
+        ret
+    }
+
+    async fn ip_remove(&self, ip: IpAddr) -> Result<()> {
+        let ip: IpAddress = ip.into();
+
+        let mut state = self.state.lock().unwrap();
+        let mut ret = Err(NetworkError::AddressNotAvailable);
+        state.iface.update_ip_addrs(|addrs| {
+            let len = addrs.len();
+            addrs.retain(|addr| addr.address() != ip);
+            if addrs.len() != len {
+                ret = Ok(());
+            }
+        });
+        ret
+    }
+
+    async fn ip_clear(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.iface.update_ip_addrs(|addrs| addrs.clear());
+        Ok(())
+    }
+
+    async fn ip_list(&self) -> Result<Vec<IpCidr>> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .iface
+            .ip_addrs()
+            .iter()
+            .map(|cidr| IpCidr {
+                ip: cidr.address().into(),
+                prefix: cidr.prefix_len(),
+            })
+            .collect())
+    }
+
+    async fn mac(&self) -> Result<[u8; 6]> {
+        Ok(self.mac)
+    }
+
+    async fn gateway_set(&self, ip: IpAddr) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let ret = match ip {
+            IpAddr::V4(ip) => state.iface.routes_mut().add_default_ipv4_route(ip.into()),
+            IpAddr::V6(ip) => state.iface.routes_mut().add_default_ipv6_route(ip.into()),
+        };
+        ret.map(|_| ())
+            .map_err(|_| NetworkError::InsufficientMemory)
+    }
+
+    async fn route_add(
+        &self,
+        cidr: IpCidr,
+        via_router: IpAddr,
+        preferred_until: Option<Duration>,
+        expires_at: Option<Duration>,
+    ) -> Result<()> {
+        let max_prefix = if cidr.ip.is_ipv4() { 32 } else { 128 };
+        if cidr.prefix > max_prefix {
+            return Err(NetworkError::InvalidInput);
+        }
+        let route = Route {
+            cidr: StackCidr::new(cidr.ip.into(), cidr.prefix),
+            via_router: via_router.into(),
+            preferred_until: to_instant(preferred_until),
+            expires_at: to_instant(expires_at),
+        };
+
+        let mut state = self.state.lock().unwrap();
+        let mut ret = Ok(());
+        state.iface.routes_mut().update(|routes| {
+            routes.retain(|existing| existing.cidr != route.cidr);
+            if routes.push(route).is_err() {
+                ret = Err(NetworkError::InsufficientMemory);
+            }
+        });
+        ret
+    }
+
+    async fn route_remove(&self, cidr: IpAddr) -> Result<()> {
+        let ip: IpAddress = cidr.into();
+
+        let mut state = self.state.lock().unwrap();
+        state
+            .iface
+            .routes_mut()
+            .update(|routes| routes.retain(|route| route.cidr.address() != ip));
+        Ok(())
+    }
+
+    async fn route_clear(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.iface.routes_mut().update(|routes| routes.clear());
+        Ok(())
+    }
+
+    async fn route_list(&self) -> Result<Vec<IpRoute>> {
+        let mut state = self.state.lock().unwrap();
+        let mut ret = Vec::new();
+        state.iface.routes_mut().update(|routes| {
+            ret = routes
+                .iter()
+                .map(|route| IpRoute {
+                    cidr: IpCidr {
+                        ip: route.cidr.address().into(),
+                        prefix: route.cidr.prefix_len(),
+                    },
+                    via_router: route.via_router.into(),
+                    preferred_until: from_instant(route.preferred_until),
+                    expires_at: from_instant(route.expires_at),
+                })
+                .collect();
+        });
+        Ok(ret)
+    }
+
+    async fn bind_raw(&self) -> Result<Box<dyn VirtualRawSocket + Sync>> {
+        let tap = Arc::new(Mutex::new(RawTap::default()));
+        let mut state = self.state.lock().unwrap();
+        state.device.taps.push(Arc::downgrade(&tap));
+        Ok(Box::new(StackRawSocket {
+            state: self.state.clone(),
+            tap,
+            ttl: DEFAULT_TTL as u32,
+        }))
+    }
+
+    async fn listen_tcp(
+        &self,
+        addr: SocketAddr,
+        only_v6: bool,
+        reuse_port: bool,
+        reuse_addr: bool,
+    ) -> Result<Box<dyn VirtualTcpListener + Sync>> {
+        let mut state = self.state.lock().unwrap();
+        let addr = state.bind_addr(addr, true)?;
+
+        let id = state.next_listener;
+        state.next_listener += 1;
+        state.listeners.insert(
+            id,
+            ListenerState {
+                endpoint: to_listen_endpoint(addr),
+                pending: Vec::new(),
+                accepted: VecDeque::new(),
+                handler: None,
+            },
+        );
+        state.poll_listeners();
+        state.wake_driver();
+
+        Ok(Box::new(StackTcpListener {
+            state: self.state.clone(),
+            id,
+            addr,
+            ttl: DEFAULT_TTL,
+        }))
+    }
+
+    async fn bind_udp(
+        &self,
+        addr: SocketAddr,
+        reuse_port: bool,
+        reuse_addr: bool,
+    ) -> Result<Box<dyn VirtualUdpSocket + Sync>> {
+        let mut state = self.state.lock().unwrap();
+        let addr = state.bind_addr(addr, false)?;
+
+        let mut socket = udp::Socket::new(
+            udp::PacketBuffer::new(
+                vec![udp::PacketMetadata::EMPTY; DATAGRAM_QUEUE_SIZE],
+                vec![0u8; DATAGRAM_BUFFER_SIZE],
+            ),
+            udp::PacketBuffer::new(
+                vec![udp::PacketMetadata::EMPTY; DATAGRAM_QUEUE_SIZE],
+                vec![0u8; DATAGRAM_BUFFER_SIZE],
+            ),
+        );
+        socket
+            .bind(to_listen_endpoint(addr))
+            .map_err(|_| NetworkError::AddressNotAvailable)?;
+        let handle = state.sockets.add(socket);
+
+        Ok(Box::new(StackUdpSocket {
+            state: self.state.clone(),
+            handle,
+            addr,
+            broadcast: false,
+            multicast_loop_v4: true,
+            multicast_loop_v6: true,
+            multicast_ttl_v4: 1,
+        }))
+    }
+
+    async fn bind_icmp(&self, addr: IpAddr) -> Result<Box<dyn VirtualIcmpSocket + Sync>> {
+        let mut state = self.state.lock().unwrap();
+        if !state.is_local(addr) {
+            return Err(NetworkError::AddressNotAvailable);
+        }
+
+        let ident = state.next_ident;
+        state.next_ident = state.next_ident.wrapping_add(1).max(1);
+
+        let mut socket = icmp::Socket::new(
+            icmp::PacketBuffer::new(
+                vec![icmp::PacketMetadata::EMPTY; DATAGRAM_QUEUE_SIZE],
+                vec![0u8; DATAGRAM_BUFFER_SIZE],
+            ),
+            icmp::PacketBuffer::new(
+                vec![icmp::PacketMetadata::EMPTY; DATAGRAM_QUEUE_SIZE],
+                vec![0u8; DATAGRAM_BUFFER_SIZE],
+            ),
+        );
+        socket
+            .bind(icmp::Endpoint::Ident(ident))
+            .map_err(|_| NetworkError::AddressInUse)?;
+        let handle = state.sockets.add(socket);
+
+        Ok(Box::new(StackIcmpSocket {
+            state: self.state.clone(),
+            handle,
+            addr: SocketAddr::new(addr, 0),
+            ident,
+            ttl: DEFAULT_TTL as u32,
+        }))
+    }
+
+    async fn connect_tcp(
+        &self,
+        addr: SocketAddr,
+        peer: SocketAddr,
+    ) -> Result<Box<dyn VirtualTcpSocket + Sync>> {
+        let socket = {
+            let mut state = self.state.lock().unwrap();
+            let mut addr = addr;
+            if addr.port() == 0 {
+                addr.set_port(state.ephemeral_port(true)?);
+            }
+
+            let mut socket = new_tcp_socket();
+            let state = &mut *state;
+            socket
+                .connect(
+                    state.iface.context(),
+                    to_endpoint(peer),
+                    to_listen_endpoint(addr),
+                )
+                .map_err(|err| match err {
+                    tcp::ConnectError::Unaddressable => NetworkError::AddressNotAvailable,
+                    _ => NetworkError::InvalidInput,
+                })?;
+            let handle = state.sockets.add(socket);
+            state.wake_driver();
+
+            StackTcpSocket {
+                state: self.state.clone(),
+                handle,
+                linger: None,
+            }
+        };
+
+        // Wait for the handshake to finish
+        futures_util::future::poll_fn(|cx| {
+            let mut state = self.state.lock().unwrap();
+            match state.sockets.get::<tcp::Socket>(socket.handle).state() {
+                tcp::State::SynSent | tcp::State::SynReceived => {
+                    state.handlers.insert(socket.handle, cx.waker().into());
+                    Poll::Pending
+                }
+                tcp::State::Closed => Poll::Ready(Err(NetworkError::ConnectionRefused)),
+                _ => Poll::Ready(Ok(())),
+            }
+        })
+        .await?;
+
+        Ok(Box::new(socket))
+    }
+
+    async fn resolve(
+        &self,
+        host: &str,
+        port: Option<u16>,
+        dns_server: Option<IpAddr>,
+    ) -> Result<Vec<IpAddr>> {
+        // The stack has no resolver of its own so only addresses resolve
+        if host.eq_ignore_ascii_case("localhost") {
+            return Ok(vec![Ipv4Addr::LOCALHOST.into(), Ipv6Addr::LOCALHOST.into()]);
+        }
+        Ok(host.parse::<IpAddr>().into_iter().collect())
+    }
+}
+
+/// Drives the TCP/IP stack of a [`StackNetworking`], it finishes once the
+/// networking and all of its sockets have been dropped.
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct StackNetworkingDriver {
+    #[derivative(Debug = "ignore")]
+    state: Arc<Mutex<StackState>>,
+    #[derivative(Debug = "ignore")]
+    sleep: Pin<Box<tokio::time::Sleep>>,
+}
+
+impl Future for StackNetworkingDriver {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if Arc::strong_count(&self.state) == 1 {
+            return Poll::Ready(());
+        }
+
+        loop {
+            let delay = {
+                let mut guard = self.state.lock().unwrap();
+                let state = &mut *guard;
+                state.driver.replace(cx.waker().clone());
+
+                let handler: Handler = cx.waker().into();
+                if let Err(err) = state.device.link.set_handler(handler) {
+                    warn!("failed to register the link with the driver - {}", err);
+                    return Poll::Ready(());
+                }
+
+                state.poll();
+                state.iface.poll_delay(Instant::now(), &state.sockets)
+            };
+
+            // Sleep until the next timer of the stack expires
+            let delay = match delay {
+                Some(delay) => Duration::from_micros(delay.total_micros()),
+                None => return Poll::Pending,
+            };
+            self.sleep
+                .as_mut()
+                .reset(tokio::time::Instant::now() + delay);
+            if self.sleep.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+        }
+    }
+}
+
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct StackTcpListener {
+    #[derivative(Debug = "ignore")]
+    state: Arc<Mutex<StackState>>,
+    id: u64,
+    addr: SocketAddr,
+    ttl: u8,
+}
+
+impl VirtualTcpListener for StackTcpListener {
+    fn try_accept(&mut self) -> Result<(Box<dyn VirtualTcpSocket + Sync>, SocketAddr)> {
+        let mut state = self.state.lock().unwrap();
+        let handle = state
+            .listeners
+            .get_mut(&self.id)
+            .and_then(|listener| listener.accepted.pop_front())
+            .ok_or(NetworkError::WouldBlock)?;
+
+        let socket = state.sockets.get_mut::<tcp::Socket>(handle);
+        socket.set_hop_limit(Some(self.ttl.max(1)));
+        let peer = socket
+            .remote_endpoint()
+            .map(to_socket_addr)
+            .ok_or(NetworkError::ConnectionAborted)?;
+        state.poll_listeners();
+        state.wake_driver();
+
+        let socket = StackTcpSocket {
+            state: self.state.clone(),
+            handle,
+            linger: None,
+        };
+        Ok((Box::new(socket), peer))
+    }
+
+    fn set_handler(&mut self, mut handler: Handler) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let listener = state
+            .listeners
+            .get_mut(&self.id)
+            .ok_or(NetworkError::InvalidFd)?;
+        if !listener.accepted.is_empty() {
+            handler.interest(InterestType::Readable);
+        }
+        listener.handler.replace(handler);
+        Ok(())
+    }
+
+    fn addr_local(&self) -> Result<SocketAddr> {
+        Ok(self.addr)
+    }
+
+    fn set_ttl(&mut self, ttl: u8) -> Result<()> {
+        self.ttl = ttl;
+        Ok(())
+    }
+
+    fn ttl(&self) -> Result<u8> {
+        Ok(self.ttl)
+    }
+}
+
+impl VirtualIoSource for StackTcpListener {
+    fn remove_handler(&mut self) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(listener) = state.listeners.get_mut(&self.id) {
+            listener.handler.take();
+        }
+    }
+}
+
+impl Drop for StackTcpListener {
+    fn drop(&mut self) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(listener) = state.listeners.remove(&self.id) {
+            // Connections that were never accepted are reset
+            for handle in listener.pending.into_iter().chain(listener.accepted) {
+                state.sockets.get_mut::<tcp::Socket>(handle).abort();
+                state.closing.push(handle);
+            }
+        }
+        state.wake_driver();
+    }
+}
+
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct StackTcpSocket {
+    #[derivative(Debug = "ignore")]
+    state: Arc<Mutex<StackState>>,
+    handle: SocketHandle,
+    linger: Option<Duration>,
+}
+
+impl StackTcpSocket {
+    fn with_socket<R>(&self, f: impl FnOnce(&mut tcp::Socket<'static>) -> R) -> R {
+        self.state.lock().unwrap().with_socket(self.handle, f)
+    }
+}
+
+impl VirtualTcpSocket for StackTcpSocket {
+    fn set_recv_buf_size(&mut self, size: usize) -> Result<()> {
+        Ok(())
+    }
+
+    fn recv_buf_size(&self) -> Result<usize> {
+        Ok(self.with_socket(|socket| socket.recv_capacity()))
+    }
+
+    fn set_send_buf_size(&mut self, size: usize) -> Result<()> {
+        Ok(())
+    }
+
+    fn send_buf_size(&self) -> Result<usize> {
+        Ok(self.with_socket(|socket| socket.send_capacity()))
+    }
+
+    fn set_nodelay(&mut self, nodelay: bool) -> Result<()> {
+        self.with_socket(|socket| socket.set_nagle_enabled(!nodelay));
+        Ok(())
+    }
+
+    fn nodelay(&self) -> Result<bool> {
+        Ok(self.with_socket(|socket| !socket.nagle_enabled()))
+    }
+
+    fn addr_peer(&self) -> Result<SocketAddr> {
+        self.with_socket(|socket| socket.remote_endpoint())
+            .map(to_socket_addr)
+            .ok_or(NetworkError::NotConnected)
+    }
+
+    fn shutdown(&mut self, how: Shutdown) -> Result<()> {
+        // TCP can only half close the sending side of a connection
+        if matches!(how, Shutdown::Write | Shutdown::Both) {
+            self.with_socket(|socket| socket.close());
+        }
+        Ok(())
+    }
+
+    fn is_closed(&self) -> bool {
+        self.with_socket(|socket| !socket.is_open())
+    }
+}
+
+impl VirtualConnectedSocket for StackTcpSocket {
+    fn set_linger(&mut self, linger: Option<Duration>) -> Result<()> {
+        self.linger = linger;
+        Ok(())
+    }
+
+    fn linger(&self) -> Result<Option<Duration>> {
+        Ok(self.linger)
+    }
+
+    fn try_send(&mut self, data: &[u8]) -> Result<usize> {
+        self.with_socket(|socket| {
+            if !socket.may_send() {
+                return Err(match socket.state() {
+                    tcp::State::SynSent | tcp::State::SynReceived => NetworkError::WouldBlock,
+                    tcp::State::Closed => NetworkError::ConnectionReset,
+                    _ => NetworkError::BrokenPipe,
+                });
+            }
+            match socket.send_slice(data) {
+                Ok(0) if !data.is_empty() => Err(NetworkError::WouldBlock),
+                Ok(amt) => Ok(amt),
+                Err(_) => Err(NetworkError::BrokenPipe),
+            }
+        })
+    }
+
+    fn try_flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.shutdown(Shutdown::Both)
+    }
+
+    fn try_recv(&mut self, buf: &mut [MaybeUninit<u8>]) -> Result<usize> {
+        self.with_socket(|socket| {
+            let ret = socket.recv(|data| {
+                let amt = buf.len().min(data.len());
+                for (dst, src) in buf.iter_mut().zip(&data[..amt]) {
+                    dst.write(*src);
+                }
+                (amt, amt)
+            });
+            match ret {
+                Ok(0) if !buf.is_empty() => Err(NetworkError::WouldBlock),
+                Ok(amt) => Ok(amt),
+                Err(tcp::RecvError::Finished) => Ok(0),
+                Err(_) => Err(NetworkError::ConnectionReset),
+            }
+        })
+    }
+}
+
+impl VirtualSocket for StackTcpSocket {
+    fn set_ttl(&mut self, ttl: u32) -> Result<()> {
+        if ttl == 0 || ttl > u8::MAX as u32 {
+            return Err(NetworkError::InvalidInput);
+        }
+        self.with_socket(|socket| socket.set_hop_limit(Some(ttl as u8)));
+        Ok(())
+    }
+
+    fn ttl(&self) -> Result<u32> {
+        Ok(self.with_socket(|socket| socket.hop_limit().unwrap_or(DEFAULT_TTL)) as u32)
+    }
+
+    fn addr_local(&self) -> Result<SocketAddr> {
+        self.with_socket(|socket| socket.local_endpoint())
+            .map(to_socket_addr)
+            .ok_or(NetworkError::NotConnected)
+    }
+
+    fn status(&self) -> Result<SocketStatus> {
+        Ok(self.with_socket(|socket| match socket.state() {
+            tcp::State::SynSent | tcp::State::SynReceived => SocketStatus::Opening,
+            tcp::State::Closed => SocketStatus::Closed,
+            _ => SocketStatus::Opened,
+        }))
+    }
+
+    fn set_handler(&mut self, mut handler: Handler) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        notify_tcp(state.sockets.get::<tcp::Socket>(self.handle), &mut handler);
+        state.handlers.insert(self.handle, handler);
+        Ok(())
+    }
+}
+
+impl VirtualIoSource for StackTcpSocket {
+    fn remove_handler(&mut self) {
+        self.state.lock().unwrap().handlers.remove(&self.handle);
+    }
+}
+
+impl Drop for StackTcpSocket {
+    fn drop(&mut self) {
+        // The connection is closed gracefully and the socket is removed by
+        // the driver once that has finished
+        let mut state = self.state.lock().unwrap();
+        state.handlers.remove(&self.handle);
+        state.sockets.get_mut::<tcp::Socket>(self.handle).close();
+        state.closing.push(self.handle);
+        state.wake_driver();
+    }
+}
+
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct StackUdpSocket {
+    #[derivative(Debug = "ignore")]
+    state: Arc<Mutex<StackState>>,
+    handle: SocketHandle,
+    addr: SocketAddr,
+    broadcast: bool,
+    multicast_loop_v4: bool,
+    multicast_loop_v6: bool,
+    multicast_ttl_v4: u32,
+}
+
+impl StackUdpSocket {
+    fn with_socket<R>(&self, f: impl FnOnce(&mut udp::Socket<'static>) -> R) -> R {
+        self.state.lock().unwrap().with_socket(self.handle, f)
+    }
+}
+
+impl VirtualUdpSocket for StackUdpSocket {
+    fn set_broadcast(&mut self, broadcast: bool) -> Result<()> {
+        self.broadcast = broadcast;
+        Ok(())
+    }
+
+    fn broadcast(&self) -> Result<bool> {
+        Ok(self.broadcast)
+    }
+
+    fn set_multicast_loop_v4(&mut self, val: bool) -> Result<()> {
+        self.multicast_loop_v4 = val;
+        Ok(())
+    }
+
+    fn multicast_loop_v4(&self) -> Result<bool> {
+        Ok(self.multicast_loop_v4)
+    }
+
+    fn set_multicast_loop_v6(&mut self, val: bool) -> Result<()> {
+        self.multicast_loop_v6 = val;
+        Ok(())
+    }
+
+    fn multicast_loop_v6(&self) -> Result<bool> {
+        Ok(self.multicast_loop_v6)
+    }
+
+    fn set_multicast_ttl_v4(&mut self, ttl: u32) -> Result<()> {
+        self.multicast_ttl_v4 = ttl;
+        Ok(())
+    }
+
+    fn multicast_ttl_v4(&self) -> Result<u32> {
+        Ok(self.multicast_ttl_v4)
+    }
+
+    fn join_multicast_v4(&mut self, multiaddr: Ipv4Addr, iface: Ipv4Addr) -> Result<()> {
+        Err(NetworkError::Unsupported)
+    }
+
+    fn leave_multicast_v4(&mut self, multiaddr: Ipv4Addr, iface: Ipv4Addr) -> Result<()> {
+        Err(NetworkError::Unsupported)
+    }
+
+    fn join_multicast_v6(&mut self, multiaddr: Ipv6Addr, iface: u32) -> Result<()> {
+        Err(NetworkError::Unsupported)
+    }
+
+    fn leave_multicast_v6(&mut self, multiaddr: Ipv6Addr, iface: u32) -> Result<()> {
+        Err(NetworkError::Unsupported)
+    }
+
+    fn addr_peer(&self) -> Result<Option<SocketAddr>> {
+        Ok(None)
+    }
+}
+
+impl VirtualConnectionlessSocket for StackUdpSocket {
+    fn try_send_to(&mut self, data: &[u8], addr: SocketAddr) -> Result<usize> {
+        self.with_socket(|socket| match socket.send_slice(data, to_endpoint(addr)) {
+            Ok(()) => Ok(data.len()),
+            Err(udp::SendError::BufferFull) => Err(NetworkError::WouldBlock),
+            Err(_) => Err(NetworkError::InvalidInput),
+        })
+    }
+
+    fn try_recv_from(&mut self, buf: &mut [MaybeUninit<u8>]) -> Result<(usize, SocketAddr)> {
+        self.with_socket(|socket| {
+            let (data, meta) = socket.recv().map_err(|_| NetworkError::WouldBlock)?;
+
+            // The part of the datagram that does not fit is discarded
+            let amt = buf.len().min(data.len());
+            for (dst, src) in buf.iter_mut().zip(&data[..amt]) {
+                dst.write(*src);
+            }
+            Ok((amt, to_socket_addr(meta.endpoint)))
+        })
+    }
+}
+
+impl VirtualSocket for StackUdpSocket {
+    fn set_ttl(&mut self, ttl: u32) -> Result<()> {
+        if ttl == 0 || ttl > u8::MAX as u32 {
+            return Err(NetworkError::InvalidInput);
+        }
+        self.with_socket(|socket| socket.set_hop_limit(Some(ttl as u8)));
+        Ok(())
+    }
+
+    fn ttl(&self) -> Result<u32> {
+        Ok(self.with_socket(|socket| socket.hop_limit().unwrap_or(DEFAULT_TTL)) as u32)
+    }
+
+    fn addr_local(&self) -> Result<SocketAddr> {
+        Ok(self.addr)
+    }
+
+    fn status(&self) -> Result<SocketStatus> {
+        Ok(SocketStatus::Opened)
+    }
+
+    fn set_handler(&mut self, mut handler: Handler) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        notify_udp(state.sockets.get::<udp::Socket>(self.handle), &mut handler);
+        state.handlers.insert(self.handle, handler);
+        Ok(())
+    }
+}
+
+impl VirtualIoSource for StackUdpSocket {
+    fn remove_handler(&mut self) {
+        self.state.lock().unwrap().handlers.remove(&self.handle);
+    }
+}
+
+impl Drop for StackUdpSocket {
+    fn drop(&mut self) {
+        let mut state = self.state.lock().unwrap();
+        state.handlers.remove(&self.handle);
+        state.sockets.remove(self.handle);
+    }
+}
+
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct StackIcmpSocket {
+    #[derivative(Debug = "ignore")]
+    state: Arc<Mutex<StackState>>,
+    handle: SocketHandle,
+    addr: SocketAddr,
+    ident: u16,
+    ttl: u32,
+}
+
+impl StackIcmpSocket {
+    fn with_socket<R>(&self, f: impl FnOnce(&mut icmp::Socket<'static>) -> R) -> R {
+        self.state.lock().unwrap().with_socket(self.handle, f)
+    }
+}
+
+impl VirtualIcmpSocket for StackIcmpSocket {}
+
+impl VirtualConnectionlessSocket for StackIcmpSocket {
+    fn try_send_to(&mut self, data: &[u8], addr: SocketAddr) -> Result<usize> {
+        // Like the ping sockets on Linux the identifier of echo requests is
+        // replaced with the one of the socket, so that the replies make it
+        // back here
+        let mut packet = data.to_vec();
+        if let Ok(mut echo) = Icmpv4Packet::new_checked(&mut packet[..]) {
+            if addr.is_ipv4() && echo.msg_type() == Icmpv4Message::EchoRequest {
+                echo.set_echo_ident(self.ident);
+                echo.fill_checksum();
+            }
+        }
+
+        self.with_socket(
+            |socket| match socket.send_slice(&packet, addr.ip().into()) {
+                Ok(()) => Ok(data.len()),
+                Err(icmp::SendError::BufferFull) => Err(NetworkError::WouldBlock),
+                Err(_) => Err(NetworkError::InvalidInput),
+            },
+        )
+    }
+
+    fn try_recv_from(&mut self, buf: &mut [MaybeUninit<u8>]) -> Result<(usize, SocketAddr)> {
+        self.with_socket(|socket| {
+            let (data, from) = socket.recv().map_err(|_| NetworkError::WouldBlock)?;
+
+            let amt = buf.len().min(data.len());
+            for (dst, src) in buf.iter_mut().zip(&data[..amt]) {
+                dst.write(*src);
+            }
+            Ok((amt, SocketAddr::new(from.into(), 0)))
+        })
+    }
+}
+
+impl VirtualSocket for StackIcmpSocket {
+    fn set_ttl(&mut self, ttl: u32) -> Result<()> {
+        if ttl == 0 || ttl > u8::MAX as u32 {
+            return Err(NetworkError::InvalidInput);
+        }
+        self.with_socket(|socket| socket.set_hop_limit(Some(ttl as u8)));
+        self.ttl = ttl;
+        Ok(())
+    }
+
+    fn ttl(&self) -> Result<u32> {
+        Ok(self.ttl)
+    }
+
+    fn addr_local(&self) -> Result<SocketAddr> {
+        Ok(self.addr)
+    }
+
+    fn status(&self) -> Result<SocketStatus> {
+        Ok(SocketStatus::Opened)
+    }
+
+    fn set_handler(&mut self, mut handler: Handler) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        notify_icmp(state.sockets.get::<icmp::Socket>(self.handle), &mut handler);
+        state.handlers.insert(self.handle, handler);
+        Ok(())
+    }
+}
+
+impl VirtualIoSource for StackIcmpSocket {
+    fn remove_handler(&mut self) {
+        self.state.lock().unwrap().handlers.remove(&self.handle);
+    }
+}
+
+impl Drop for StackIcmpSocket {
+    fn drop(&mut self) {
+        let mut state = self.state.lock().unwrap();
+        state.handlers.remove(&self.handle);
+        state.sockets.remove(self.handle);
+    }
+}
+
+/// A raw socket that sends frames straight onto the link of the stack and
+/// receives a copy of the frames that arrive on it.
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct StackRawSocket {
+    #[derivative(Debug = "ignore")]
+    state: Arc<Mutex<StackState>>,
+    #[derivative(Debug = "ignore")]
+    tap: Arc<Mutex<RawTap>>,
+    ttl: u32,
+}
+
+impl VirtualRawSocket for StackRawSocket {
+    fn try_send(&mut self, data: &[u8]) -> Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        state.device.link.try_send(data)
+    }
+
+    fn try_flush(&mut self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.device.link.try_flush()
+    }
+
+    fn try_recv(&mut self, buf: &mut [MaybeUninit<u8>]) -> Result<usize> {
+        let mut tap = self.tap.lock().unwrap();
+        let frame = tap.queue.pop_front().ok_or(NetworkError::WouldBlock)?;
+
+        let amt = buf.len().min(frame.len());
+        for (dst, src) in buf.iter_mut().zip(&frame[..amt]) {
+            dst.write(*src);
+        }
+        Ok(amt)
+    }
+
+    fn set_promiscuous(&mut self, promiscuous: bool) -> Result<()> {
+        // The link has to hand over every frame for the socket to see them
+        let mut state = self.state.lock().unwrap();
+        if promiscuous {
+            state.device.link.set_promiscuous(true)?;
+        }
+        self.tap.lock().unwrap().promiscuous = promiscuous;
+        Ok(())
+    }
+
+    fn promiscuous(&self) -> Result<bool> {
+        Ok(self.tap.lock().unwrap().promiscuous)
+    }
+}
+
+impl VirtualSocket for StackRawSocket {
+    fn set_ttl(&mut self, ttl: u32) -> Result<()> {
+        self.ttl = ttl;
+        Ok(())
+    }
+
+    fn ttl(&self) -> Result<u32> {
+        Ok(self.ttl)
+    }
+
+    fn addr_local(&self) -> Result<SocketAddr> {
+        Err(NetworkError::Unsupported)
+    }
+
+    fn status(&self) -> Result<SocketStatus> {
+        Ok(SocketStatus::Opened)
+    }
+
+    fn set_handler(&mut self, mut handler: Handler) -> Result<()> {
+        let mut tap = self.tap.lock().unwrap();
+        if !tap.queue.is_empty() {
+            handler.interest(InterestType::Readable);
+        }
+        handler.interest(InterestType::Writable);
+        tap.handler.replace(handler);
+        Ok(())
+    }
+}
+
+impl VirtualIoSource for StackRawSocket {
+    fn remove_handler(&mut self) {
+        self.tap.lock().unwrap().handler.take();
+    }
+}
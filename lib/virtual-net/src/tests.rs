@@ -201,3 +201,1105 @@ async fn test_tcp_with_large_pipe_json_using_cbor() {
     let (client, server) = setup_pipe(1024000, FrameSerializationFormat::Cbor).await;
     test_tcp(client, server).await
 }
+
+#[traced_test]
+#[tokio::test]
+async fn test_loopback_tcp() {
+    let switch = LoopbackSwitch::new();
+    let app = switch.interface();
+    let db = switch.interface();
+    app.ip_add(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 24)
+        .await
+        .unwrap();
+    db.ip_add(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)), 24)
+        .await
+        .unwrap();
+
+    let mut listener = db
+        .listen_tcp(
+            SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 5432),
+            false,
+            false,
+            false,
+        )
+        .await
+        .unwrap();
+
+    const TEST1: &str = "SELECT 1;";
+    const TEST2: &str = "1";
+
+    let server = tokio::task::spawn(async move {
+        let (mut socket, addr) = listener.accept().await.unwrap();
+        assert_eq!(addr.ip(), IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)));
+        assert_eq!(socket.addr_peer().unwrap(), addr);
+
+        let mut buf = [0u8; TEST1.len()];
+        socket.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, TEST1.as_bytes());
+
+        socket.write_all(TEST2.as_bytes()).await.unwrap();
+    });
+
+    let peer = SocketAddr::new(Ipv4Addr::new(10, 0, 0, 2).into(), 5432);
+    let mut socket = app
+        .connect_tcp(SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0), peer)
+        .await
+        .unwrap();
+    assert_eq!(socket.addr_peer().unwrap(), peer);
+    socket.write_all(TEST1.as_bytes()).await.unwrap();
+
+    let mut buf = [0u8; TEST2.len()];
+    socket.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, TEST2.as_bytes());
+
+    server.await.unwrap();
+    assert_eq!(socket.read(&mut buf).await.unwrap(), 0);
+}
+
+#[traced_test]
+#[tokio::test]
+async fn test_loopback_udp() {
+    let switch = LoopbackSwitch::new();
+    let a = switch.interface();
+    let b = switch.interface();
+    a.ip_add(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 24)
+        .await
+        .unwrap();
+    b.ip_add(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)), 24)
+        .await
+        .unwrap();
+
+    let mut sender = a
+        .bind_udp(
+            SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0),
+            false,
+            false,
+        )
+        .await
+        .unwrap();
+    let mut receiver = b
+        .bind_udp(
+            SocketAddr::new(Ipv4Addr::new(10, 0, 0, 2).into(), 53),
+            false,
+            false,
+        )
+        .await
+        .unwrap();
+
+    let peer = receiver.addr_local().unwrap();
+    sender.send_to(b"ping", peer).await.unwrap();
+
+    let mut buf = [MaybeUninit::new(0u8); 16];
+    let (read, from) = receiver.recv_from(&mut buf).await.unwrap();
+    let buf: Vec<u8> = buf[..read]
+        .iter()
+        .map(|b| unsafe { b.assume_init() })
+        .collect();
+    assert_eq!(buf, b"ping");
+    assert_eq!(from.ip(), IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)));
+    assert_eq!(from.port(), sender.addr_local().unwrap().port());
+}
+
+#[traced_test]
+#[tokio::test]
+async fn test_loopback_routes() {
+    let switch = LoopbackSwitch::new();
+    let a = switch.interface();
+    let b = switch.interface();
+    a.ip_add(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 24)
+        .await
+        .unwrap();
+    b.ip_add(IpAddr::V4(Ipv4Addr::new(10, 1, 0, 1)), 24)
+        .await
+        .unwrap();
+    assert_eq!(
+        a.ip_add(IpAddr::V4(Ipv4Addr::new(10, 1, 0, 1)), 24).await,
+        Err(NetworkError::AddressInUse)
+    );
+
+    let _listener = b
+        .listen_tcp(
+            SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 80),
+            false,
+            false,
+            false,
+        )
+        .await
+        .unwrap();
+    let local = SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0);
+
+    // The loopback address of an interface only leads back to itself
+    let ret = a
+        .connect_tcp(local, SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 80))
+        .await;
+    assert_eq!(ret.err(), Some(NetworkError::ConnectionRefused));
+
+    let peer = SocketAddr::new(Ipv4Addr::new(10, 1, 0, 1).into(), 80);
+    let ret = a.connect_tcp(local, peer).await;
+    assert_eq!(ret.err(), Some(NetworkError::AddressNotAvailable));
+
+    a.route_add(
+        IpCidr {
+            ip: IpAddr::V4(Ipv4Addr::new(10, 1, 0, 0)),
+            prefix: 16,
+        },
+        IpAddr::V4(Ipv4Addr::new(10, 0, 0, 254)),
+        None,
+        None,
+    )
+    .await
+    .unwrap();
+    let socket = a.connect_tcp(local, peer).await.unwrap();
+    assert_eq!(
+        socket.addr_local().unwrap().ip(),
+        IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))
+    );
+}
+
+#[test]
+fn test_firewall_rule_parse() {
+    let rule: FirewallRule = "dir=out, proto=tcp, cidr=10.0.0.0/8, port=5432-5440"
+        .parse()
+        .unwrap();
+    assert_eq!(
+        rule,
+        FirewallRule {
+            direction: Some(FirewallDirection::Egress),
+            protocol: Some(FirewallProtocol::Tcp),
+            cidr: Some(IpCidr {
+                ip: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 0)),
+                prefix: 8,
+            }),
+            host: None,
+            ports: Some(5432..=5440),
+        }
+    );
+
+    let rule: FirewallRule = "host=*.Internal.,port=443".parse().unwrap();
+    assert_eq!(rule.host.as_deref(), Some("*.internal"));
+    assert_eq!(rule.ports, Some(443..=443));
+
+    let rule: FirewallRule = "cidr=fd00::1".parse().unwrap();
+    assert_eq!(rule.cidr.unwrap().prefix, 128);
+
+    assert!("proto=sctp".parse::<FirewallRule>().is_err());
+    assert!("cidr=10.0.0.0/33".parse::<FirewallRule>().is_err());
+    assert!("port=90-80".parse::<FirewallRule>().is_err());
+    assert!("tcp".parse::<FirewallRule>().is_err());
+}
+
+#[traced_test]
+#[tokio::test]
+async fn test_firewall_tcp() {
+    let switch = LoopbackSwitch::new();
+    let app = switch.interface();
+    let db = switch.interface();
+    app.ip_add(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 24)
+        .await
+        .unwrap();
+    db.ip_add(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)), 24)
+        .await
+        .unwrap();
+
+    let any = SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0);
+    let _db_listener = db
+        .listen_tcp(SocketAddr::new(any.ip(), 5432), false, false, false)
+        .await
+        .unwrap();
+    let _ssh_listener = db
+        .listen_tcp(SocketAddr::new(any.ip(), 22), false, false, false)
+        .await
+        .unwrap();
+    let _local_listener = app
+        .listen_tcp(SocketAddr::new(any.ip(), 80), false, false, false)
+        .await
+        .unwrap();
+
+    let policy = FirewallPolicy::new()
+        .allow("proto=tcp,cidr=10.0.0.2,port=5432".parse().unwrap())
+        .allow("host=localhost,port=80".parse().unwrap())
+        .deny("cidr=::1".parse().unwrap());
+    let firewall = FirewallNetworking::new(Arc::new(app), policy);
+
+    let db_addr = SocketAddr::new(Ipv4Addr::new(10, 0, 0, 2).into(), 5432);
+    firewall.connect_tcp(any, db_addr).await.unwrap();
+    let ret = firewall
+        .connect_tcp(any, SocketAddr::new(db_addr.ip(), 22))
+        .await;
+    assert_eq!(ret.err(), Some(NetworkError::PermissionDenied));
+
+    let ret = firewall
+        .listen_tcp(SocketAddr::new(any.ip(), 8080), false, false, false)
+        .await;
+    assert_eq!(ret.err(), Some(NetworkError::PermissionDenied));
+    assert_eq!(
+        firewall.bind_raw().await.err(),
+        Some(NetworkError::PermissionDenied)
+    );
+
+    // Hosts that are allowed by name can only be reached once resolved
+    let local = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 80);
+    let ret = firewall.connect_tcp(any, local).await;
+    assert_eq!(ret.err(), Some(NetworkError::PermissionDenied));
+    assert_eq!(
+        firewall.resolve("example.com", None, None).await,
+        Err(NetworkError::PermissionDenied)
+    );
+    // The name server that the guest asks for has to be reachable too
+    let dns_server = Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 53)));
+    assert_eq!(
+        firewall.resolve("localhost", None, dns_server).await,
+        Err(NetworkError::PermissionDenied)
+    );
+    let addrs = firewall.resolve("LocalHost", None, None).await.unwrap();
+    assert!(addrs.contains(&local.ip()));
+    firewall.connect_tcp(any, local).await.unwrap();
+    let ret = firewall
+        .connect_tcp(any, SocketAddr::new(local.ip(), 81))
+        .await;
+    assert_eq!(ret.err(), Some(NetworkError::PermissionDenied));
+
+    // Deny rules win over the names that allowed an address
+    let ret = firewall
+        .connect_tcp(any, SocketAddr::new(Ipv6Addr::LOCALHOST.into(), 80))
+        .await;
+    assert_eq!(ret.err(), Some(NetworkError::PermissionDenied));
+}
+
+#[traced_test]
+#[tokio::test]
+async fn test_firewall_udp() {
+    let switch = LoopbackSwitch::new();
+    let a = switch.interface();
+    let b = switch.interface();
+    a.ip_add(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 24)
+        .await
+        .unwrap();
+    b.ip_add(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)), 24)
+        .await
+        .unwrap();
+
+    let policy = FirewallPolicy::new()
+        .deny("proto=udp,port=53".parse().unwrap())
+        .deny("dir=in".parse().unwrap());
+    let firewall = FirewallNetworking::new(Arc::new(a), policy);
+
+    let ret = firewall
+        .bind_udp(
+            SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 5353),
+            false,
+            false,
+        )
+        .await;
+    assert_eq!(ret.err(), Some(NetworkError::PermissionDenied));
+    let mut sender = firewall
+        .bind_udp(
+            SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0),
+            false,
+            false,
+        )
+        .await
+        .unwrap();
+    let mut receiver = b
+        .bind_udp(
+            SocketAddr::new(Ipv4Addr::new(10, 0, 0, 2).into(), 54),
+            false,
+            false,
+        )
+        .await
+        .unwrap();
+
+    let dns = SocketAddr::new(Ipv4Addr::new(10, 0, 0, 2).into(), 53);
+    assert_eq!(
+        sender.send_to(b"query", dns).await,
+        Err(NetworkError::PermissionDenied)
+    );
+
+    let peer = receiver.addr_local().unwrap();
+    sender.send_to(b"ping", peer).await.unwrap();
+
+    let mut buf = [MaybeUninit::new(0u8); 16];
+    let (read, _) = receiver.recv_from(&mut buf).await.unwrap();
+    assert_eq!(read, 4);
+}
+
+#[traced_test]
+#[tokio::test]
+async fn test_firewall_ingress() {
+    let switch = LoopbackSwitch::new();
+    let host = switch.interface();
+    let friend = switch.interface();
+    let stranger = switch.interface();
+    for (net, ip) in [(&host, 1), (&friend, 2), (&stranger, 3)] {
+        net.ip_add(IpAddr::V4(Ipv4Addr::new(10, 0, 0, ip)), 24)
+            .await
+            .unwrap();
+    }
+
+    let policy = FirewallPolicy::new()
+        .allow("dir=out".parse().unwrap())
+        .allow("dir=in,cidr=10.0.0.2,port=80".parse().unwrap());
+    let firewall = FirewallNetworking::new(Arc::new(host), policy);
+
+    let any = SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0);
+    let ret = firewall
+        .listen_tcp(SocketAddr::new(any.ip(), 8080), false, false, false)
+        .await;
+    assert_eq!(ret.err(), Some(NetworkError::PermissionDenied));
+
+    // Connections from peers that are not let in are closed
+    let mut listener = firewall
+        .listen_tcp(SocketAddr::new(any.ip(), 80), false, false, false)
+        .await
+        .unwrap();
+    let server = SocketAddr::new(Ipv4Addr::new(10, 0, 0, 1).into(), 80);
+    let _denied = stranger.connect_tcp(any, server).await.unwrap();
+    let _allowed = friend.connect_tcp(any, server).await.unwrap();
+    let (_, peer) = listener.accept().await.unwrap();
+    assert_eq!(peer.ip(), IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)));
+    assert_eq!(listener.try_accept().err(), Some(NetworkError::WouldBlock));
+
+    // Datagrams are only let in from allowed peers and from the peers that
+    // the socket sent to
+    let mut socket = firewall
+        .bind_udp(SocketAddr::new(any.ip(), 80), false, false)
+        .await
+        .unwrap();
+    let mut echo = stranger
+        .bind_udp(SocketAddr::new(any.ip(), 7), false, false)
+        .await
+        .unwrap();
+    let mut other = stranger.bind_udp(any, false, false).await.unwrap();
+    other.send_to(b"spam", server).await.unwrap();
+    let echo_addr = SocketAddr::new(Ipv4Addr::new(10, 0, 0, 3).into(), 7);
+    socket.send_to(b"ping", echo_addr).await.unwrap();
+
+    let mut buf = [MaybeUninit::new(0u8); 16];
+    let (_, from) = echo.recv_from(&mut buf).await.unwrap();
+    echo.send_to(b"pong", from).await.unwrap();
+    let (read, from) = socket.recv_from(&mut buf).await.unwrap();
+    assert_eq!(read, 4);
+    assert_eq!(from, echo_addr);
+    assert_eq!(
+        socket.try_recv_from(&mut buf).err(),
+        Some(NetworkError::WouldBlock)
+    );
+}
+
+#[traced_test]
+#[tokio::test]
+async fn test_firewall_host_expiry() {
+    let switch = LoopbackSwitch::new();
+    let app = switch.interface();
+    app.ip_add(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 24)
+        .await
+        .unwrap();
+
+    let any = SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0);
+    let _listener = app
+        .listen_tcp(SocketAddr::new(any.ip(), 80), false, false, false)
+        .await
+        .unwrap();
+
+    let policy = FirewallPolicy::new().allow("host=localhost,port=80".parse().unwrap());
+    let firewall =
+        FirewallNetworking::with_host_lifetime(Arc::new(app), policy, Duration::from_millis(100));
+
+    let local = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 80);
+    firewall.resolve("localhost", None, None).await.unwrap();
+    firewall.connect_tcp(any, local).await.unwrap();
+
+    // The address is denied again once the name it was resolved from expires
+    std::thread::sleep(Duration::from_millis(200));
+    let ret = firewall.connect_tcp(any, local).await;
+    assert_eq!(ret.err(), Some(NetworkError::PermissionDenied));
+    firewall.resolve("localhost", None, None).await.unwrap();
+    firewall.connect_tcp(any, local).await.unwrap();
+}
+
+#[traced_test]
+#[tokio::test]
+async fn test_capture_tcp() {
+    let switch = LoopbackSwitch::new();
+    let app = switch.interface();
+    let db = switch.interface();
+    app.ip_add(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 24)
+        .await
+        .unwrap();
+    db.ip_add(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)), 24)
+        .await
+        .unwrap();
+
+    let capture = NetworkCapture::new();
+    let db = CaptureNetworking::new(Arc::new(db), capture.clone());
+    let mut listener = db
+        .listen_tcp(
+            SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 5432),
+            false,
+            false,
+            false,
+        )
+        .await
+        .unwrap();
+
+    let server = tokio::task::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut buf = [0u8; 9];
+        socket.read_exact(&mut buf).await.unwrap();
+        socket.write_all(b"1").await.unwrap();
+    });
+
+    let peer = SocketAddr::new(Ipv4Addr::new(10, 0, 0, 2).into(), 5432);
+    let mut socket = app
+        .connect_tcp(SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0), peer)
+        .await
+        .unwrap();
+    socket.write_all(b"SELECT 1;").await.unwrap();
+    let mut buf = [0u8; 1];
+    socket.read_exact(&mut buf).await.unwrap();
+    server.await.unwrap();
+
+    let records = capture.records();
+    let events: Vec<_> = records
+        .iter()
+        .map(|record| (record.direction, record.event.clone()))
+        .collect();
+    assert_eq!(
+        events,
+        vec![
+            (CaptureDirection::Inbound, CaptureEvent::Connect),
+            (
+                CaptureDirection::Inbound,
+                CaptureEvent::Data(Bytes::from_static(b"SELECT 1;"))
+            ),
+            (
+                CaptureDirection::Outbound,
+                CaptureEvent::Data(Bytes::from_static(b"1"))
+            ),
+            (CaptureDirection::Outbound, CaptureEvent::Close),
+        ]
+    );
+    assert!(records.iter().all(|record| record.local.port() == 5432
+        && record.peer.ip() == IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))));
+
+    // Handshake, two segments and a FIN
+    let mut pcapng = Vec::new();
+    capture.write_pcapng(&mut pcapng).unwrap();
+    let packets = pcapng_packets(&pcapng);
+    assert_eq!(packets.len(), 6);
+    for packet in packets.iter() {
+        assert_eq!(packet[0], 0x45);
+        assert_eq!(packet[9], 6);
+        assert_eq!(internet_checksum(&packet[..20]), 0);
+    }
+    // The client sent the SYN and then the query
+    assert_eq!(&packets[0][12..16], &[10, 0, 0, 1]);
+    assert_eq!(packets[0][20 + 13], 0x02);
+    assert_eq!(&packets[3][40..], b"SELECT 1;");
+    assert_eq!(&packets[4][12..16], &[10, 0, 0, 2]);
+    assert_eq!(&packets[4][40..], b"1");
+    assert_eq!(packets[5][20 + 13], 0x11);
+}
+
+#[traced_test]
+#[tokio::test]
+async fn test_capture_udp() {
+    let switch = LoopbackSwitch::new();
+    let a = switch.interface();
+    let b = switch.interface();
+    a.ip_add(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 24)
+        .await
+        .unwrap();
+    b.ip_add(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)), 24)
+        .await
+        .unwrap();
+
+    let capture = NetworkCapture::new();
+    let inner = Arc::new(a);
+    let a = CaptureNetworking::new(inner.clone(), capture.clone());
+    let mut sender = a
+        .bind_udp(
+            SocketAddr::new(Ipv4Addr::new(10, 0, 0, 1).into(), 5353),
+            false,
+            false,
+        )
+        .await
+        .unwrap();
+    let mut receiver = b
+        .bind_udp(
+            SocketAddr::new(Ipv4Addr::new(10, 0, 0, 2).into(), 53),
+            false,
+            false,
+        )
+        .await
+        .unwrap();
+
+    let peer = receiver.addr_local().unwrap();
+    sender.send_to(b"query", peer).await.unwrap();
+    let mut buf = [MaybeUninit::new(0u8); 16];
+    let (_, from) = receiver.recv_from(&mut buf).await.unwrap();
+    receiver.send_to(b"answer", from).await.unwrap();
+    sender.recv_from(&mut buf).await.unwrap();
+
+    let records = capture.records();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].direction, CaptureDirection::Outbound);
+    assert_eq!(
+        records[0].event,
+        CaptureEvent::Udp(Bytes::from_static(b"query"))
+    );
+    assert_eq!(records[1].direction, CaptureDirection::Inbound);
+    assert_eq!(
+        records[1].event,
+        CaptureEvent::Udp(Bytes::from_static(b"answer"))
+    );
+    assert_eq!(records[1].peer, peer);
+
+    let mut pcapng = Vec::new();
+    capture.write_pcapng(&mut pcapng).unwrap();
+    let packets = pcapng_packets(&pcapng);
+    assert_eq!(packets.len(), 2);
+    assert_eq!(packets[0][9], 17);
+    assert_eq!(&packets[0][20..24], &[0x14, 0xe9, 0, 53]);
+    assert_eq!(&packets[0][28..], b"query");
+    assert_eq!(&packets[1][28..], b"answer");
+    // The checksum of the UDP header includes the pseudo header
+    let mut pseudo = packets[1][12..20].to_vec();
+    pseudo.extend_from_slice(&[0, 17, 0, 14]);
+    pseudo.extend_from_slice(&packets[1][20..]);
+    assert_eq!(internet_checksum(&pseudo), 0);
+
+    // Datagrams that don't fit in an IP packet are cut off
+    let capture = NetworkCapture::new();
+    let a = CaptureNetworking::new(inner, capture.clone());
+    let mut sender = a
+        .bind_udp(
+            SocketAddr::new(Ipv4Addr::new(10, 0, 0, 1).into(), 0),
+            false,
+            false,
+        )
+        .await
+        .unwrap();
+    sender.send_to(&[7u8; 70000], peer).await.unwrap();
+    let mut pcapng = Vec::new();
+    capture.write_pcapng(&mut pcapng).unwrap();
+    let packets = pcapng_packets(&pcapng);
+    assert_eq!(packets.len(), 1);
+    assert_eq!(packets[0].len(), u16::MAX as usize);
+    assert_eq!(&packets[0][2..4], &[0xff, 0xff]);
+    assert_eq!(&packets[0][24..26], &[0xff, 0xeb]);
+}
+
+/// Returns the packets of the enhanced packet blocks of a pcapng file
+fn pcapng_packets(mut pcapng: &[u8]) -> Vec<Vec<u8>> {
+    let u32_at = |data: &[u8], at: usize| u32::from_le_bytes(data[at..at + 4].try_into().unwrap());
+    let mut packets = Vec::new();
+    assert_eq!(u32_at(pcapng, 0), 0x0A0D0D0A);
+    while !pcapng.is_empty() {
+        let len = u32_at(pcapng, 4) as usize;
+        assert_eq!(u32_at(pcapng, len - 4) as usize, len);
+        if u32_at(pcapng, 0) == 6 {
+            let captured = u32_at(pcapng, 20) as usize;
+            packets.push(pcapng[28..28 + captured].to_vec());
+        }
+        pcapng = &pcapng[len..];
+    }
+    packets
+}
+
+fn internet_checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = data
+        .chunks(2)
+        .map(|word| u16::from_be_bytes([word[0], *word.get(1).unwrap_or(&0)]) as u32)
+        .sum();
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Answers the DNS queries that arrive on `socket` with `addr` for the A
+/// and AAAA records of any name, counting the queries in `queries`
+#[cfg(feature = "resolver")]
+async fn serve_dns(
+    mut socket: Box<dyn VirtualUdpSocket + Sync>,
+    addr: IpAddr,
+    ttl: u32,
+    queries: Arc<AtomicU16>,
+) {
+    let mut buf = [MaybeUninit::new(0u8); 512];
+    loop {
+        let (amt, from) = socket.recv_from(&mut buf).await.unwrap();
+        let query: Vec<u8> = buf[..amt]
+            .iter()
+            .map(|b| unsafe { b.assume_init() })
+            .collect();
+        queries.fetch_add(1, Ordering::SeqCst);
+        socket
+            .send_to(&dns_answer(&query, addr, ttl), from)
+            .await
+            .unwrap();
+    }
+}
+
+/// Builds the answer to a DNS query with `addr` as the only record
+#[cfg(feature = "resolver")]
+fn dns_answer(query: &[u8], addr: IpAddr, ttl: u32) -> Vec<u8> {
+    let qtype = u16::from_be_bytes([query[query.len() - 4], query[query.len() - 3]]);
+    let rdata = match (qtype, addr) {
+        (1, IpAddr::V4(ip)) => Some(ip.octets().to_vec()),
+        (28, IpAddr::V6(ip)) => Some(ip.octets().to_vec()),
+        _ => None,
+    };
+
+    let mut answer = query[..2].to_vec();
+    answer.extend_from_slice(&[0x81, 0x80, 0, 1, 0, rdata.is_some() as u8, 0, 0, 0, 0]);
+    answer.extend_from_slice(&query[12..]);
+    if let Some(rdata) = rdata {
+        answer.extend_from_slice(&[0xC0, 12]);
+        answer.extend_from_slice(&qtype.to_be_bytes());
+        answer.extend_from_slice(&[0, 1]);
+        answer.extend_from_slice(&ttl.to_be_bytes());
+        answer.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        answer.extend_from_slice(&rdata);
+    }
+    answer
+}
+
+#[cfg(feature = "resolver")]
+#[traced_test]
+#[tokio::test]
+async fn test_resolver_hosts() {
+    let switch = LoopbackSwitch::new();
+    let config = ResolverConfig::new()
+        .host("DB.internal.", IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)))
+        .host("db.internal", IpAddr::V6(Ipv6Addr::LOCALHOST));
+    let net = ResolverNetworking::new(Arc::new(switch.interface()), config);
+
+    assert_eq!(
+        net.resolve("db.Internal", None, None).await.unwrap(),
+        vec![
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)),
+            IpAddr::V6(Ipv6Addr::LOCALHOST)
+        ]
+    );
+    // Other names are left to the wrapped networking
+    assert_eq!(
+        net.resolve("localhost", None, None).await.unwrap(),
+        vec![
+            IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(Ipv6Addr::LOCALHOST)
+        ]
+    );
+    assert_eq!(
+        net.resolve("example.com", None, None).await.unwrap(),
+        Vec::<IpAddr>::new()
+    );
+}
+
+#[cfg(feature = "resolver")]
+#[traced_test]
+#[tokio::test]
+async fn test_resolver_nameserver() {
+    let switch = LoopbackSwitch::new();
+    let app = switch.interface();
+    let dns = switch.interface();
+    app.ip_add(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 24)
+        .await
+        .unwrap();
+    dns.ip_add(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 53)), 24)
+        .await
+        .unwrap();
+
+    let cached = Arc::new(AtomicU16::new(0));
+    let socket = dns
+        .bind_udp(
+            SocketAddr::new(Ipv4Addr::new(10, 0, 0, 53).into(), 53),
+            false,
+            false,
+        )
+        .await
+        .unwrap();
+    let answer = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+    tokio::task::spawn(serve_dns(socket, answer, 300, cached.clone()));
+
+    let uncached = Arc::new(AtomicU16::new(0));
+    let socket = dns
+        .bind_udp(
+            SocketAddr::new(Ipv4Addr::new(10, 0, 0, 53).into(), 5353),
+            false,
+            false,
+        )
+        .await
+        .unwrap();
+    let other = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 3));
+    tokio::task::spawn(serve_dns(socket, other, 0, uncached.clone()));
+
+    // The first name server does not answer so the second one is asked
+    let config = ResolverConfig {
+        timeout: Some(Duration::from_millis(100)),
+        ..Default::default()
+    }
+    .nameserver(SocketAddr::new(Ipv4Addr::new(10, 0, 0, 54).into(), 53))
+    .nameserver(SocketAddr::new(Ipv4Addr::new(10, 0, 0, 53).into(), 53));
+    let app = Arc::new(app);
+    let net = ResolverNetworking::new(app.clone(), config);
+
+    assert_eq!(
+        net.resolve("db.internal", None, None).await.unwrap(),
+        vec![answer]
+    );
+    assert_eq!(cached.load(Ordering::SeqCst), 2);
+    assert_eq!(
+        net.resolve("DB.internal.", None, None).await.unwrap(),
+        vec![answer]
+    );
+    assert_eq!(cached.load(Ordering::SeqCst), 2);
+    net.clear_cache();
+    net.resolve("db.internal", None, None).await.unwrap();
+    assert_eq!(cached.load(Ordering::SeqCst), 4);
+
+    // Answers without a TTL are not cached
+    let config =
+        ResolverConfig::new().nameserver(SocketAddr::new(Ipv4Addr::new(10, 0, 0, 53).into(), 5353));
+    let net = ResolverNetworking::new(app.clone(), config);
+    for _ in 0..2 {
+        assert_eq!(
+            net.resolve("db.internal", None, None).await.unwrap(),
+            vec![other]
+        );
+    }
+    assert_eq!(uncached.load(Ordering::SeqCst), 4);
+
+    // The name server that the caller asks for is used instead
+    let dns_server = Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 53)));
+    assert_eq!(
+        net.resolve("db.internal", None, dns_server).await.unwrap(),
+        vec![answer]
+    );
+    assert_eq!(
+        net.resolve("bad..name", None, None).await,
+        Err(NetworkError::InvalidInput)
+    );
+}
+
+#[cfg(feature = "resolver")]
+#[traced_test]
+#[tokio::test]
+async fn test_resolver_answers() {
+    let switch = LoopbackSwitch::new();
+    let app = switch.interface();
+    let dns = switch.interface();
+    app.ip_add(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 24)
+        .await
+        .unwrap();
+    for ip in [53, 55, 56] {
+        dns.ip_add(IpAddr::V4(Ipv4Addr::new(10, 0, 0, ip)), 24)
+            .await
+            .unwrap();
+    }
+
+    let queries = Arc::new(AtomicU16::new(0));
+    let answer = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+    let socket = dns
+        .bind_udp(
+            SocketAddr::new(Ipv4Addr::new(10, 0, 0, 53).into(), 53),
+            false,
+            false,
+        )
+        .await
+        .unwrap();
+    tokio::task::spawn(serve_dns(socket, answer, 300, queries.clone()));
+
+    let clamped = Arc::new(AtomicU16::new(0));
+    let other = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 3));
+    let socket = dns
+        .bind_udp(
+            SocketAddr::new(Ipv4Addr::new(10, 0, 0, 55).into(), 53),
+            false,
+            false,
+        )
+        .await
+        .unwrap();
+    tokio::task::spawn(serve_dns(socket, other, 0x8000_0000, clamped.clone()));
+
+    // A name server that answers a different question than it was asked
+    let mut socket = dns
+        .bind_udp(
+            SocketAddr::new(Ipv4Addr::new(10, 0, 0, 56).into(), 53),
+            false,
+            false,
+        )
+        .await
+        .unwrap();
+    tokio::task::spawn(async move {
+        let mut buf = [MaybeUninit::new(0u8); 512];
+        loop {
+            let (amt, from) = socket.recv_from(&mut buf).await.unwrap();
+            let mut query: Vec<u8> = buf[..amt]
+                .iter()
+                .map(|b| unsafe { b.assume_init() })
+                .collect();
+            query[13] = b'x';
+            let answer = dns_answer(&query, other, 300);
+            socket.send_to(&answer, from).await.unwrap();
+        }
+    });
+
+    let config = ResolverConfig {
+        timeout: Some(Duration::from_millis(100)),
+        ..Default::default()
+    }
+    .nameserver(SocketAddr::new(Ipv4Addr::new(10, 0, 0, 53).into(), 53));
+    let net = ResolverNetworking::new(Arc::new(app), config);
+    assert_eq!(
+        net.resolve("db.internal", None, None).await.unwrap(),
+        vec![answer]
+    );
+    assert_eq!(queries.load(Ordering::SeqCst), 2);
+
+    // The cached answer of one name server is not given out for another
+    let dns_server = Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 55)));
+    assert_eq!(
+        net.resolve("db.internal", None, dns_server).await.unwrap(),
+        vec![other]
+    );
+
+    // TTLs with the top bit set count as zero so the answer is not cached
+    net.resolve("db.internal", None, dns_server).await.unwrap();
+    assert_eq!(clamped.load(Ordering::SeqCst), 4);
+
+    let dns_server = Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 56)));
+    assert_eq!(
+        net.resolve("db.internal", None, dns_server).await,
+        Err(NetworkError::TimedOut)
+    );
+}
+
+#[traced_test]
+#[tokio::test]
+async fn test_ethernet_switch() {
+    fn frame(dst: [u8; 6], src: [u8; 6]) -> Vec<u8> {
+        let mut frame = Vec::new();
+        frame.extend_from_slice(&dst);
+        frame.extend_from_slice(&src);
+        frame.extend_from_slice(&[0x08, 0x00]);
+        frame.extend_from_slice(b"payload");
+        frame
+    }
+    fn recv(port: &mut EthernetPort) -> Option<Vec<u8>> {
+        let mut buf = [MaybeUninit::new(0u8); 64];
+        let read = port.try_recv(&mut buf).ok()?;
+        Some(
+            buf[..read]
+                .iter()
+                .map(|b| unsafe { b.assume_init() })
+                .collect(),
+        )
+    }
+
+    let switch = EthernetSwitch::new();
+    let mut a = switch.port();
+    let mut b = switch.port();
+    let mut c = switch.port();
+    let mac_a = [0x02, 0, 0, 0, 0, 1];
+    let mac_b = [0x02, 0, 0, 0, 0, 2];
+
+    // Broadcasts are flooded to every other port
+    let broadcast = frame([0xff; 6], mac_a);
+    a.try_send(&broadcast).unwrap();
+    assert_eq!(recv(&mut a), None);
+    assert_eq!(recv(&mut b), Some(broadcast.clone()));
+    assert_eq!(recv(&mut c), Some(broadcast));
+
+    // Once the switch has learnt where a MAC address is, frames for it only
+    // go to that port unless another port is promiscuous
+    let unicast = frame(mac_a, mac_b);
+    b.try_send(&unicast).unwrap();
+    assert_eq!(recv(&mut a), Some(unicast.clone()));
+    assert_eq!(recv(&mut c), None);
+
+    c.set_promiscuous(true).unwrap();
+    b.try_send(&unicast).unwrap();
+    assert_eq!(recv(&mut a), Some(unicast.clone()));
+    assert_eq!(recv(&mut c), Some(unicast));
+}
+
+#[cfg(feature = "stack")]
+async fn setup_stack(switch: &EthernetSwitch, ip: Ipv4Addr) -> StackNetworking {
+    let mac = [0x02, 0, 0, 0, 0, ip.octets()[3]];
+    let (stack, driver) = StackNetworking::new(Box::new(switch.port()), mac);
+    tokio::task::spawn(driver);
+    stack.ip_add(IpAddr::V4(ip), 24).await.unwrap();
+    stack
+}
+
+#[cfg(feature = "stack")]
+#[traced_test]
+#[tokio::test]
+async fn test_stack_tcp() {
+    let switch = EthernetSwitch::new();
+    let server = setup_stack(&switch, Ipv4Addr::new(10, 0, 0, 1)).await;
+    let client = setup_stack(&switch, Ipv4Addr::new(10, 0, 0, 2)).await;
+
+    let addr = SocketAddr::new(Ipv4Addr::new(10, 0, 0, 1).into(), 80);
+    let mut listener = server
+        .listen_tcp(
+            SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 80),
+            false,
+            false,
+            false,
+        )
+        .await
+        .unwrap();
+
+    const TEST1: &str = "GET / HTTP/1.1\r\n\r\n";
+    const TEST2: &str = "HTTP/1.1 204 No Content\r\n\r\n";
+
+    let task = tokio::task::spawn(async move {
+        let (mut socket, addr) = listener.accept().await.unwrap();
+        assert_eq!(addr.ip(), IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)));
+
+        let mut buf = [0u8; TEST1.len()];
+        socket.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, TEST1.as_bytes());
+
+        socket.write_all(TEST2.as_bytes()).await.unwrap();
+        socket.close().unwrap();
+    });
+
+    let mut socket = client
+        .connect_tcp(SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0), addr)
+        .await
+        .unwrap();
+    assert_eq!(socket.addr_peer().unwrap(), addr);
+    socket.write_all(TEST1.as_bytes()).await.unwrap();
+
+    let mut buf = [0u8; TEST2.len()];
+    socket.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, TEST2.as_bytes());
+    assert_eq!(socket.read(&mut buf).await.unwrap(), 0);
+    task.await.unwrap();
+
+    // Nothing listens on this port so the connection is reset
+    let ret = client
+        .connect_tcp(
+            SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0),
+            SocketAddr::new(addr.ip(), 81),
+        )
+        .await;
+    assert_eq!(ret.err(), Some(NetworkError::ConnectionRefused));
+}
+
+#[cfg(feature = "stack")]
+#[traced_test]
+#[tokio::test]
+async fn test_stack_udp() {
+    let switch = EthernetSwitch::new();
+    let a = setup_stack(&switch, Ipv4Addr::new(10, 0, 0, 1)).await;
+    let b = setup_stack(&switch, Ipv4Addr::new(10, 0, 0, 2)).await;
+
+    let mut sender = a
+        .bind_udp(
+            SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0),
+            false,
+            false,
+        )
+        .await
+        .unwrap();
+    let mut receiver = b
+        .bind_udp(
+            SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 53),
+            false,
+            false,
+        )
+        .await
+        .unwrap();
+
+    let peer = SocketAddr::new(Ipv4Addr::new(10, 0, 0, 2).into(), 53);
+    sender.send_to(b"ping", peer).await.unwrap();
+
+    let mut buf = [MaybeUninit::new(0u8); 16];
+    let (read, from) = receiver.recv_from(&mut buf).await.unwrap();
+    let buf: Vec<u8> = buf[..read]
+        .iter()
+        .map(|b| unsafe { b.assume_init() })
+        .collect();
+    assert_eq!(buf, b"ping");
+    assert_eq!(from.ip(), IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)));
+    assert_eq!(from.port(), sender.addr_local().unwrap().port());
+}
+
+#[cfg(feature = "stack")]
+#[traced_test]
+#[tokio::test]
+async fn test_stack_bridge() {
+    let switch = EthernetSwitch::new();
+    let a = setup_stack(&switch, Ipv4Addr::new(10, 0, 0, 1)).await;
+    let ret = a
+        .bridge("10.1.0.1:4789", "", StreamSecurity::Unencrypted)
+        .await;
+    assert_eq!(ret.err(), Some(NetworkError::Unsupported));
+
+    // The other end of the tunnel is a plain UDP socket of the uplink
+    let uplink = LoopbackSwitch::new().interface();
+    uplink
+        .ip_add(IpAddr::V4(Ipv4Addr::new(10, 1, 0, 1)), 24)
+        .await
+        .unwrap();
+    let mut tunnel = uplink
+        .bind_udp(
+            SocketAddr::new(Ipv4Addr::new(10, 1, 0, 1).into(), 4789),
+            false,
+            false,
+        )
+        .await
+        .unwrap();
+    let a = a.with_uplink(Arc::new(uplink));
+    let ret = a
+        .bridge("10.1.0.1:4789", "", StreamSecurity::DoubleEncryption)
+        .await;
+    assert_eq!(ret.err(), Some(NetworkError::Unsupported));
+    a.bridge("10.1.0.1:4789", "", StreamSecurity::Unencrypted)
+        .await
+        .unwrap();
+
+    // Sending to a neighbour makes the stack look up its MAC address, and
+    // the ARP request comes out of the tunnel
+    let mut sender = a
+        .bind_udp(
+            SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0),
+            false,
+            false,
+        )
+        .await
+        .unwrap();
+    let peer = SocketAddr::new(Ipv4Addr::new(10, 0, 0, 2).into(), 53);
+    sender.send_to(b"ping", peer).await.unwrap();
+
+    let mut buf = [MaybeUninit::new(0u8); 1514];
+    let (read, _) = tunnel.recv_from(&mut buf).await.unwrap();
+    let frame: Vec<u8> = buf[..read]
+        .iter()
+        .map(|b| unsafe { b.assume_init() })
+        .collect();
+    assert_eq!(&frame[6..12], &[0x02, 0, 0, 0, 0, 1]);
+    assert_eq!(&frame[12..14], &[0x08, 0x06]);
+
+    // Once unbridged the stack is back on the switch
+    a.unbridge().await.unwrap();
+    assert_eq!(a.unbridge().await.err(), Some(NetworkError::NotConnected));
+    let b = setup_stack(&switch, Ipv4Addr::new(10, 0, 0, 2)).await;
+    let mut receiver = b
+        .bind_udp(
+            SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 53),
+            false,
+            false,
+        )
+        .await
+        .unwrap();
+    sender.send_to(b"ping", peer).await.unwrap();
+    let (read, from) = receiver.recv_from(&mut buf).await.unwrap();
+    assert_eq!(read, 4);
+    assert_eq!(from.ip(), IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)));
+}
@@ -5,7 +5,7 @@ use std::mem::MaybeUninit;
 use wasmer::{FromToNativeWasmType, MemorySize, ValueType};
 
 use super::{
-    Errno, ErrnoSignal, EventFdReadwrite, Eventtype, Fd, JoinStatusType, Signal,
+    Errno, ErrnoSignal, EventFdReadwrite, Eventtype, Fd, Filesize, JoinStatusType, Pid, Signal,
     Snapshot0SubscriptionClock, SubscriptionClock, SubscriptionFsReadwrite, Userdata,
 };
 
@@ -482,3 +482,425 @@ where
     #[inline]
     fn zero_padding_bytes(&self, _bytes: &mut [MaybeUninit<u8>]) {}
 }
+
+#[doc = " Type of an advisory file lock."]
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, num_enum :: TryFromPrimitive, Hash)]
+pub enum LockType {
+    #[doc = " A shared (read) lock, which any number of owners can hold at once."]
+    Shared,
+    #[doc = " An exclusive (write) lock, which only one owner can hold at a time."]
+    Exclusive,
+    #[doc = " Releases a lock."]
+    Unlock,
+    #[doc = " Unknown."]
+    Unknown = 255,
+}
+
+unsafe impl ValueType for LockType {
+    #[inline]
+    fn zero_padding_bytes(&self, _bytes: &mut [MaybeUninit<u8>]) {}
+}
+
+unsafe impl wasmer::FromToNativeWasmType for LockType {
+    type Native = i32;
+
+    fn to_native(self) -> Self::Native {
+        self as i32
+    }
+
+    fn from_native(n: Self::Native) -> Self {
+        match n {
+            0 => Self::Shared,
+            1 => Self::Exclusive,
+            2 => Self::Unlock,
+
+            q => {
+                tracing::debug!("could not serialize number {q} to enum LockType");
+                Self::Unknown
+            }
+        }
+    }
+
+    fn is_from_store(&self, _store: &impl wasmer::AsStoreRef) -> bool {
+        false
+    }
+}
+
+#[doc = " A lock on a range of bytes of a file, as returned by `fd_lock_get`."]
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct FileLock {
+    #[doc = " The first byte of the range."]
+    pub start: Filesize,
+    #[doc = " The length of the range, where zero means up to the end of the file."]
+    pub len: Filesize,
+    #[doc = " The type of lock, or `unlock` if there is no conflicting lock."]
+    pub type_: LockType,
+    #[doc = " The process holding the lock."]
+    pub pid: Pid,
+}
+
+unsafe impl ValueType for FileLock {
+    #[inline]
+    fn zero_padding_bytes(&self, _bytes: &mut [MaybeUninit<u8>]) {}
+}
+
+#[doc = " A resource that the usage of a process can be limited on."]
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, num_enum :: TryFromPrimitive, Hash)]
+pub enum RlimitResource {
+    #[doc = " The number of file descriptors the process can have open."]
+    OpenFiles,
+    #[doc = " The number of threads the process can run at once."]
+    Threads,
+    #[doc = " The size in bytes that the linear memory of a new process can grow to."]
+    Memory,
+    #[doc = " The wall-clock time in seconds that the threads of the process can spend outside of"]
+    #[doc = " blocking syscalls, added up over all the threads. This is not the CPU time: time a"]
+    #[doc = " thread waits for the host to schedule it counts as well."]
+    RunTime,
+    #[doc = " The size in bytes that the process can grow a file to."]
+    FileSize,
+    #[doc = " The number of child processes the process can run at once."]
+    Processes,
+    #[doc = " Unknown."]
+    Unknown = 255,
+}
+
+unsafe impl ValueType for RlimitResource {
+    #[inline]
+    fn zero_padding_bytes(&self, _bytes: &mut [MaybeUninit<u8>]) {}
+}
+
+unsafe impl wasmer::FromToNativeWasmType for RlimitResource {
+    type Native = i32;
+
+    fn to_native(self) -> Self::Native {
+        self as i32
+    }
+
+    fn from_native(n: Self::Native) -> Self {
+        match n {
+            0 => Self::OpenFiles,
+            1 => Self::Threads,
+            2 => Self::Memory,
+            3 => Self::RunTime,
+            4 => Self::FileSize,
+            5 => Self::Processes,
+
+            q => {
+                tracing::debug!("could not serialize number {q} to enum RlimitResource");
+                Self::Unknown
+            }
+        }
+    }
+
+    fn is_from_store(&self, _store: &impl wasmer::AsStoreRef) -> bool {
+        false
+    }
+}
+
+#[doc = " The limits on a resource, where `u64::MAX` means there is no limit."]
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Rlimit {
+    #[doc = " The soft limit, which is the one that is enforced."]
+    pub cur: u64,
+    #[doc = " The hard limit, which is the most that the soft limit can be raised to."]
+    pub max: u64,
+}
+
+unsafe impl ValueType for Rlimit {
+    #[inline]
+    fn zero_padding_bytes(&self, _bytes: &mut [MaybeUninit<u8>]) {}
+}
+
+#[doc = " A set of signals, where signal `n` is bit `1 << n`."]
+pub type Sigset = u64;
+
+#[doc = " How `thread_sigmask` changes the signal mask of a thread."]
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, num_enum :: TryFromPrimitive, Hash)]
+pub enum SigmaskHow {
+    #[doc = " Adds the signals in the set to the mask."]
+    Block,
+    #[doc = " Removes the signals in the set from the mask."]
+    Unblock,
+    #[doc = " Replaces the mask with the set."]
+    Setmask,
+    #[doc = " Unknown."]
+    Unknown = 255,
+}
+
+unsafe impl ValueType for SigmaskHow {
+    #[inline]
+    fn zero_padding_bytes(&self, _bytes: &mut [MaybeUninit<u8>]) {}
+}
+
+unsafe impl wasmer::FromToNativeWasmType for SigmaskHow {
+    type Native = i32;
+
+    fn to_native(self) -> Self::Native {
+        self as i32
+    }
+
+    fn from_native(n: Self::Native) -> Self {
+        match n {
+            0 => Self::Block,
+            1 => Self::Unblock,
+            2 => Self::Setmask,
+
+            q => {
+                tracing::debug!("could not serialize number {q} to enum SigmaskHow");
+                Self::Unknown
+            }
+        }
+    }
+
+    fn is_from_store(&self, _store: &impl wasmer::AsStoreRef) -> bool {
+        false
+    }
+}
+
+#[doc = " What a process does when it receives a signal."]
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, num_enum :: TryFromPrimitive, Hash)]
+pub enum SigDisposition {
+    #[doc = " The default action of the signal, which either terminates the process or ignores the signal."]
+    Default,
+    #[doc = " The signal is discarded."]
+    Ignore,
+    #[doc = " The signal is passed to the signal callback of the process."]
+    Handler,
+    #[doc = " Unknown."]
+    Unknown = 255,
+}
+
+unsafe impl ValueType for SigDisposition {
+    #[inline]
+    fn zero_padding_bytes(&self, _bytes: &mut [MaybeUninit<u8>]) {}
+}
+
+wai_bindgen_rust::bitflags::bitflags! {
+    #[doc = " Flags that change how a signal is handled."]
+    #[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
+    pub struct SigactionFlags : u32 {
+        #[doc = " Blocking syscalls that the handler interrupted are restarted rather"]
+        #[doc = " than failing with `EINTR`."]
+        const RESTART = 1 << 0;
+        #[doc = " The handler wants the details of the signal, which it reads with"]
+        #[doc = " `thread_siginfo_get`."]
+        const SIGINFO = 1 << 1;
+        #[doc = " The disposition is reset to the default once the handler is called."]
+        const RESETHAND = 1 << 2;
+        #[doc = " The signal is not blocked while its own handler runs."]
+        const NODEFER = 1 << 3;
+        #[doc = " The handler runs on the alternate signal stack of the thread."]
+        const ONSTACK = 1 << 4;
+    }
+}
+
+// TODO: if necessary, must be implemented in wit-bindgen
+unsafe impl ValueType for SigactionFlags {
+    #[inline]
+    fn zero_padding_bytes(&self, _bytes: &mut [MaybeUninit<u8>]) {}
+}
+
+#[doc = " How a process handles a signal, as used by `proc_sigaction`."]
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Sigaction {
+    #[doc = " Signals that are blocked while the handler runs."]
+    pub mask: Sigset,
+    #[doc = " What to do with the signal."]
+    pub disposition: SigDisposition,
+    #[doc = " Flags that change how the signal is handled."]
+    pub flags: SigactionFlags,
+}
+
+unsafe impl ValueType for Sigaction {
+    #[inline]
+    fn zero_padding_bytes(&self, _bytes: &mut [MaybeUninit<u8>]) {}
+}
+
+#[doc = " Where a signal came from."]
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, num_enum :: TryFromPrimitive, Hash)]
+pub enum SignalCode {
+    #[doc = " Raised by the runtime, for instance when a limit is reached or the terminal is interrupted."]
+    Kernel,
+    #[doc = " Sent by a process with `proc_raise`, `proc_signal` or `thread_signal`."]
+    User,
+    #[doc = " Raised by a timer set with `proc_raise_interval`."]
+    Timer,
+    #[doc = " Unknown."]
+    Unknown = 255,
+}
+
+unsafe impl ValueType for SignalCode {
+    #[inline]
+    fn zero_padding_bytes(&self, _bytes: &mut [MaybeUninit<u8>]) {}
+}
+
+#[doc = " The details of a signal, as returned by `thread_siginfo_get`."]
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Siginfo {
+    #[doc = " Value that was sent along with the signal."]
+    pub value: u64,
+    #[doc = " The process that sent the signal."]
+    pub pid: Pid,
+    #[doc = " The user that sent the signal."]
+    pub uid: u32,
+    #[doc = " Where the signal came from."]
+    pub code: SignalCode,
+    #[doc = " The signal number."]
+    pub signo: u32,
+}
+
+unsafe impl ValueType for Siginfo {
+    #[inline]
+    fn zero_padding_bytes(&self, _bytes: &mut [MaybeUninit<u8>]) {}
+}
+
+wai_bindgen_rust::bitflags::bitflags! {
+    #[doc = " State of an alternate signal stack."]
+    #[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
+    pub struct SignalStackFlags : u64 {
+        #[doc = " The thread is currently running on the stack."]
+        const ONSTACK = 1 << 0;
+        #[doc = " The stack is disabled."]
+        const DISABLE = 1 << 1;
+    }
+}
+
+// TODO: if necessary, must be implemented in wit-bindgen
+unsafe impl ValueType for SignalStackFlags {
+    #[inline]
+    fn zero_padding_bytes(&self, _bytes: &mut [MaybeUninit<u8>]) {}
+}
+
+#[doc = " An alternate stack that signal handlers can run on, as used by `thread_sigaltstack`."]
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SignalStack {
+    #[doc = " The lowest address of the stack."]
+    pub base: u64,
+    #[doc = " The size of the stack in bytes."]
+    pub size: u64,
+    #[doc = " State of the stack."]
+    pub flags: SignalStackFlags,
+}
+
+unsafe impl ValueType for SignalStack {
+    #[inline]
+    fn zero_padding_bytes(&self, _bytes: &mut [MaybeUninit<u8>]) {}
+}
+
+wai_bindgen_rust::bitflags::bitflags! {
+    #[doc = " Access that the program wants to a memory mapping, as used by `fd_mmap`."]
+    #[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
+    pub struct MmapProt : u32 {
+        #[doc = " The mapping can be read."]
+        const READ = 1 << 0;
+        #[doc = " The mapping can be written to."]
+        const WRITE = 1 << 1;
+        #[doc = " The mapping can be executed."]
+        const EXEC = 1 << 2;
+    }
+}
+
+// TODO: if necessary, must be implemented in wit-bindgen
+unsafe impl ValueType for MmapProt {
+    #[inline]
+    fn zero_padding_bytes(&self, _bytes: &mut [MaybeUninit<u8>]) {}
+}
+
+unsafe impl wasmer::FromToNativeWasmType for MmapProt {
+    type Native = i32;
+
+    fn to_native(self) -> Self::Native {
+        self.bits() as i32
+    }
+
+    fn from_native(n: Self::Native) -> Self {
+        Self::from_bits_truncate(n as u32)
+    }
+
+    fn is_from_store(&self, _store: &impl wasmer::AsStoreRef) -> bool {
+        false
+    }
+}
+
+wai_bindgen_rust::bitflags::bitflags! {
+    #[doc = " How a memory mapping is shared, as used by `fd_mmap`."]
+    #[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
+    pub struct MmapFlags : u32 {
+        #[doc = " Changes are written back to the file and seen by the processes that"]
+        #[doc = " share the mapping."]
+        const SHARED = 1 << 0;
+        #[doc = " Changes are private to the process and never written back."]
+        const PRIVATE = 1 << 1;
+        #[doc = " The mapping is not backed by a file and starts out zeroed."]
+        const ANONYMOUS = 1 << 2;
+    }
+}
+
+// TODO: if necessary, must be implemented in wit-bindgen
+unsafe impl ValueType for MmapFlags {
+    #[inline]
+    fn zero_padding_bytes(&self, _bytes: &mut [MaybeUninit<u8>]) {}
+}
+
+unsafe impl wasmer::FromToNativeWasmType for MmapFlags {
+    type Native = i32;
+
+    fn to_native(self) -> Self::Native {
+        self.bits() as i32
+    }
+
+    fn from_native(n: Self::Native) -> Self {
+        Self::from_bits_truncate(n as u32)
+    }
+
+    fn is_from_store(&self, _store: &impl wasmer::AsStoreRef) -> bool {
+        false
+    }
+}
+
+wai_bindgen_rust::bitflags::bitflags! {
+    #[doc = " How a memory mapping is synchronized, as used by `fd_msync`."]
+    #[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
+    pub struct MsyncFlags : u32 {
+        #[doc = " The write back is scheduled but the call does not wait for it."]
+        const ASYNC = 1 << 0;
+        #[doc = " The call waits for the write back to finish."]
+        const SYNC = 1 << 1;
+        #[doc = " Changes made by other processes that share the mapping are"]
+        #[doc = " copied into the mapping."]
+        const INVALIDATE = 1 << 2;
+    }
+}
+
+// TODO: if necessary, must be implemented in wit-bindgen
+unsafe impl ValueType for MsyncFlags {
+    #[inline]
+    fn zero_padding_bytes(&self, _bytes: &mut [MaybeUninit<u8>]) {}
+}
+
+unsafe impl wasmer::FromToNativeWasmType for MsyncFlags {
+    type Native = i32;
+
+    fn to_native(self) -> Self::Native {
+        self.bits() as i32
+    }
+
+    fn from_native(n: Self::Native) -> Self {
+        Self::from_bits_truncate(n as u32)
+    }
+
+    fn is_from_store(&self, _store: &impl wasmer::AsStoreRef) -> bool {
+        false
+    }
+}
@@ -83,7 +83,10 @@ pub fn spawn_exec_module(
 
         // Determine if we are going to create memory and import it or just rely on self creation of memory
         let memory_spawn = match shared_memory {
-            Some(ty) => SpawnMemoryType::CreateMemoryOfType(ty),
+            Some(mut ty) => {
+                env.process.limits().limit_memory(&mut ty);
+                SpawnMemoryType::CreateMemoryOfType(ty)
+            }
             None => SpawnMemoryType::CreateMemory,
         };
 
@@ -23,6 +23,7 @@ pub struct BinFactory {
     pub(crate) commands: Commands,
     runtime: Arc<dyn Runtime + Send + Sync + 'static>,
     pub(crate) local: Arc<RwLock<HashMap<String, Option<BinaryPackage>>>>,
+    check_exec_permission: bool,
 }
 
 impl BinFactory {
@@ -31,9 +32,16 @@ impl BinFactory {
             commands: Commands::new_with_builtins(runtime.clone()),
             runtime,
             local: Arc::new(RwLock::new(HashMap::new())),
+            check_exec_permission: false,
         }
     }
 
+    /// Only loads the files of the file system that have an execute
+    /// permission bit set, see [`crate::capabilities::CapabilityFilesystemV1`]
+    pub fn set_check_exec_permission(&mut self, check: bool) {
+        self.check_exec_permission = check;
+    }
+
     pub fn runtime(&self) -> &(dyn Runtime + Send + Sync) {
         self.runtime.deref()
     }
@@ -72,7 +80,14 @@ impl BinFactory {
         // Check the filesystem for the file
         if name.starts_with('/') {
             if let Some(fs) = fs {
-                match load_package_from_filesystem(fs, name.as_ref(), self.runtime()).await {
+                match load_package_from_filesystem(
+                    fs,
+                    name.as_ref(),
+                    self.runtime(),
+                    self.check_exec_permission,
+                )
+                .await
+                {
                     Ok(pkg) => {
                         cache.insert(name, Some(pkg.clone()));
                         return Some(pkg);
@@ -98,7 +113,17 @@ async fn load_package_from_filesystem(
     fs: &dyn FileSystem,
     path: &Path,
     rt: &(dyn Runtime + Send + Sync),
+    check_exec_permission: bool,
 ) -> Result<BinaryPackage, anyhow::Error> {
+    if check_exec_permission {
+        let metadata = fs
+            .metadata(path)
+            .context("Unable to read the file metadata")?;
+        if !metadata.is_executable() {
+            anyhow::bail!("The file is not executable");
+        }
+    }
+
     let mut f = fs
         .new_open_options()
         .read(true)
@@ -115,3 +140,42 @@ async fn load_package_from_filesystem(
 
     Ok(pkg)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(all(feature = "host-fs", feature = "sys-thread"))]
+    #[tokio::test]
+    async fn exec_permission_is_only_checked_when_asked_for() {
+        use crate::{runtime::task_manager::tokio::TokioTaskManager, PluggableRuntime};
+
+        let temp = tempfile::TempDir::new().unwrap();
+        let path = temp.path().join("program.wasm");
+        std::fs::write(&path, b"not a package").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        }
+
+        let fs = virtual_fs::host_fs::FileSystem::new(tokio::runtime::Handle::current());
+        let rt = PluggableRuntime::new(Arc::new(TokioTaskManager::new(
+            tokio::runtime::Handle::current(),
+        )));
+
+        // Host files without the execute bit are loaded like before
+        let err = load_package_from_filesystem(&fs, &path, &rt, false)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Unable to parse the WEBC file");
+
+        #[cfg(unix)]
+        {
+            let err = load_package_from_filesystem(&fs, &path, &rt, true)
+                .await
+                .unwrap_err();
+            assert_eq!(err.to_string(), "The file is not executable");
+        }
+    }
+}
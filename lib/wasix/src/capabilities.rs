@@ -1,11 +1,26 @@
-use crate::http::HttpClientCapabilityV1;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use virtual_net::{FirewallPolicy, ResolverConfig};
+
+use crate::{
+    http::HttpClientCapabilityV1, os::task::limits::ResourceLimits,
+    runtime::module_cache::ModuleHash,
+};
 
 /// Defines capabilities for a Wasi environment.
 #[derive(Clone, Debug)]
 pub struct Capabilities {
     pub insecure_allow_all: bool,
     pub http_client: HttpClientCapabilityV1,
+    pub networking: CapabilityNetworkingV1,
     pub threading: CapabilityThreadingV1,
+    pub unix_sockets: CapabilityUnixSocketsV1,
+    pub snapshot: CapabilitySnapshotV1,
+    pub limits: CapabilityLimitsV1,
+    pub filesystem: CapabilityFilesystemV1,
 }
 
 impl Capabilities {
@@ -13,7 +28,12 @@ impl Capabilities {
         Self {
             insecure_allow_all: false,
             http_client: Default::default(),
+            networking: Default::default(),
             threading: Default::default(),
+            unix_sockets: Default::default(),
+            snapshot: Default::default(),
+            limits: Default::default(),
+            filesystem: Default::default(),
         }
     }
 
@@ -23,11 +43,21 @@ impl Capabilities {
         let Capabilities {
             insecure_allow_all,
             http_client,
+            networking,
             threading,
+            unix_sockets,
+            snapshot,
+            limits,
+            filesystem,
         } = other;
         self.insecure_allow_all |= insecure_allow_all;
         self.http_client.update(http_client);
+        self.networking.update(networking);
         self.threading.update(threading);
+        self.unix_sockets.update(unix_sockets);
+        self.snapshot.update(snapshot);
+        self.limits.update(limits);
+        self.filesystem.update(filesystem);
     }
 }
 
@@ -37,6 +67,30 @@ impl Default for Capabilities {
     }
 }
 
+/// Defines which traffic the networking of the guest lets through and how
+/// it resolves names.
+#[derive(Debug, Default, Clone)]
+pub struct CapabilityNetworkingV1 {
+    /// Rules for the connections, listeners, datagrams and DNS lookups
+    /// of the guest.
+    ///
+    /// An empty policy lets all the traffic through.
+    pub firewall: FirewallPolicy,
+
+    /// Hosts and name servers that the names are resolved with.
+    ///
+    /// An empty configuration leaves name resolution to the runtime.
+    pub resolver: ResolverConfig,
+}
+
+impl CapabilityNetworkingV1 {
+    pub fn update(&mut self, other: CapabilityNetworkingV1) {
+        let CapabilityNetworkingV1 { firewall, resolver } = other;
+        self.firewall.update(firewall);
+        self.resolver.update(resolver);
+    }
+}
+
 /// Defines threading related permissions.
 #[derive(Debug, Default, Clone)]
 pub struct CapabilityThreadingV1 {
@@ -60,3 +114,83 @@ impl CapabilityThreadingV1 {
         self.max_threads = max_threads.or(self.max_threads);
     }
 }
+
+/// Defines which Unix sockets of the host can be reached.
+#[derive(Debug, Default, Clone)]
+pub struct CapabilityUnixSocketsV1 {
+    /// Unix sockets of the host that connections are forwarded to, keyed by
+    /// the path that the guest connects to.
+    pub host_sockets: HashMap<PathBuf, PathBuf>,
+}
+
+impl CapabilityUnixSocketsV1 {
+    pub fn update(&mut self, other: CapabilityUnixSocketsV1) {
+        let CapabilityUnixSocketsV1 { host_sockets } = other;
+        self.host_sockets.extend(host_sockets);
+    }
+
+    /// Returns the host socket that connections to `path` are forwarded to.
+    pub fn host_socket(&self, path: &Path) -> Option<&Path> {
+        self.host_sockets.get(path).map(|host| host.as_path())
+    }
+}
+
+/// Defines whether the guest may save a snapshot of itself.
+#[derive(Debug, Default, Clone)]
+pub struct CapabilitySnapshotV1 {
+    /// File on the host that `proc_snapshot` writes the snapshot to.
+    ///
+    /// [`None`] means snapshots are not allowed.
+    pub path: Option<PathBuf>,
+    /// The hash of the module being run. Snapshots record it, and are only
+    /// restored into a module with the same hash.
+    ///
+    /// Snapshots can neither be taken nor restored when it is [`None`].
+    pub module_hash: Option<ModuleHash>,
+}
+
+impl CapabilitySnapshotV1 {
+    pub fn update(&mut self, other: CapabilitySnapshotV1) {
+        let CapabilitySnapshotV1 { path, module_hash } = other;
+        self.path = path.or(self.path.take());
+        self.module_hash = module_hash.or(self.module_hash.take());
+    }
+}
+
+/// Defines the resource limits that the guest runs with.
+#[derive(Debug, Default, Clone)]
+pub struct CapabilityLimitsV1 {
+    /// Limits of the first process, child processes inherit the limits of
+    /// their parent.
+    ///
+    /// [`None`] means the process is not limited.
+    pub rlimits: Option<ResourceLimits>,
+}
+
+impl CapabilityLimitsV1 {
+    pub fn update(&mut self, other: CapabilityLimitsV1) {
+        let CapabilityLimitsV1 { rlimits } = other;
+        self.rlimits = rlimits.or(self.rlimits.take());
+    }
+}
+
+/// Defines how the file system of the guest is used.
+#[derive(Debug, Default, Clone)]
+pub struct CapabilityFilesystemV1 {
+    /// Only lets the guest run files that have an execute permission bit
+    /// set (default = false)
+    ///
+    /// Files written by the guest and files of the host without the bit,
+    /// along with every file of a file system that doesn't keep track of
+    /// permissions, can't be run when this is set.
+    pub check_exec_permission: bool,
+}
+
+impl CapabilityFilesystemV1 {
+    pub fn update(&mut self, other: CapabilityFilesystemV1) {
+        let CapabilityFilesystemV1 {
+            check_exec_permission,
+        } = other;
+        self.check_exec_permission |= check_exec_permission;
+    }
+}
@@ -108,6 +108,23 @@ impl std::fmt::Debug for InodeValFilePollGuard {
                     InodeSocketKind::Raw(..) => {
                         write!(f, "guard-raw-socket(fd={}, peb={})", self.fd, self.peb)
                     }
+                    InodeSocketKind::UnixListener { .. } => {
+                        write!(f, "guard-unix-listener(fd={}, peb={})", self.fd, self.peb)
+                    }
+                    InodeSocketKind::UnixStream { ref socket, .. } => {
+                        if socket.is_closed() {
+                            write!(
+                                f,
+                                "guard-unix-stream (closed, fd={}, peb={})",
+                                self.fd, self.peb
+                            )
+                        } else {
+                            write!(f, "guard-unix-stream(fd={}, peb={})", self.fd, self.peb)
+                        }
+                    }
+                    InodeSocketKind::UnixDatagram { .. } => {
+                        write!(f, "guard-unix-datagram(fd={}, peb={})", self.fd, self.peb)
+                    }
                     _ => write!(f, "guard-socket(fd={}), peb={})", self.fd, self.peb),
                 }
             }
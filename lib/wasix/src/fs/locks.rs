@@ -0,0 +1,524 @@
+//! Advisory file locks, as taken with `flock()` and `fcntl()`.
+//!
+//! The locks are recorded against the device and inode of the file, so every
+//! path to it sees them, in a table that is shared by every process of a
+//! control plane, so they are seen by forked and spawned processes alike.
+//! Like on Linux the two kinds of locks are independent of each other:
+//!
+//! * `flock()` locks cover the whole file and are owned by the open file
+//!   description they were taken through, which is shared by duplicated and
+//!   inherited descriptors. They go away when the last of those descriptors
+//!   is closed.
+//! * `fcntl()` locks cover a range of bytes and are owned by the process.
+//!   They all go away when the process closes *any* descriptor of the file,
+//!   or when it exits.
+//!
+//! Files that live on the host also take the matching host lock (see
+//! [`VirtualFile::host_lock`](virtual_fs::VirtualFile::host_lock)) so that
+//! programs outside of the sandbox see them too. The host lock is held by
+//! the open file handle of the inode, so it is lost if the file is reopened
+//! while locked.
+
+use std::{
+    collections::HashMap,
+    sync::{atomic::AtomicU64, Arc, Mutex, Weak},
+};
+
+use tokio::sync::{futures::Notified, Notify};
+use virtual_fs::{FsError, Metadata};
+
+use super::Fd;
+use crate::WasiProcessId;
+
+/// End of a range that runs up to the end of the file.
+pub const LOCK_TO_EOF: u64 = u64::MAX;
+
+/// The file that a lock is on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LockedFile {
+    dev: u64,
+    ino: u64,
+}
+
+impl LockedFile {
+    /// The file described by `metadata`, or [`None`] when its file system
+    /// doesn't number its entries.
+    pub fn of(metadata: &Metadata) -> Option<Self> {
+        (metadata.ino != 0).then_some(LockedFile {
+            dev: metadata.dev,
+            ino: metadata.ino,
+        })
+    }
+}
+
+/// An open file description, which is shared by the descriptors that were
+/// duplicated from each other or inherited by a child process.
+///
+/// Those descriptors share the cursor of the description, so it is used to
+/// tell descriptions apart. The description is closed once the last of them
+/// is dropped.
+#[derive(Debug, Clone)]
+pub struct OpenFile(Weak<AtomicU64>);
+
+impl OpenFile {
+    pub fn of(fd: &Fd) -> Self {
+        OpenFile(Arc::downgrade(&fd.offset))
+    }
+
+    fn is_open(&self) -> bool {
+        self.0.strong_count() > 0
+    }
+}
+
+impl PartialEq for OpenFile {
+    fn eq(&self, other: &Self) -> bool {
+        Weak::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for OpenFile {}
+
+/// Who a lock belongs to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LockOwner {
+    /// A `flock()` lock, owned by the open file description that took it.
+    File(OpenFile),
+    /// A `fcntl()` lock, owned by the whole process.
+    Process(WasiProcessId),
+}
+
+impl LockOwner {
+    /// The process that owns a `fcntl()` lock.
+    pub fn pid(&self) -> Option<WasiProcessId> {
+        match self {
+            Self::File(_) => None,
+            Self::Process(pid) => Some(*pid),
+        }
+    }
+
+    /// Locks of open file descriptions that have been closed are gone.
+    fn is_alive(&self) -> bool {
+        match self {
+            Self::File(open_file) => open_file.is_open(),
+            Self::Process(_) => true,
+        }
+    }
+
+    /// Only locks of the same kind can get in the way of each other.
+    fn same_family(&self, other: &LockOwner) -> bool {
+        matches!(
+            (self, other),
+            (Self::File(_), Self::File(_)) | (Self::Process(_), Self::Process(_))
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockKind {
+    Shared,
+    Exclusive,
+}
+
+/// A lock that is held on a range of a file, `end` is exclusive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockRecord {
+    pub owner: LockOwner,
+    pub kind: LockKind,
+    pub start: u64,
+    pub end: u64,
+}
+
+impl LockRecord {
+    fn overlaps(&self, start: u64, end: u64) -> bool {
+        self.start < end && start < self.end
+    }
+}
+
+/// Why a lock could not be taken.
+#[derive(Debug)]
+pub enum LockConflict {
+    /// Another owner in the sandbox holds a conflicting lock.
+    Held(LockRecord),
+    /// The host refused the lock.
+    Host(FsError),
+}
+
+#[derive(Debug, Default)]
+pub struct FileLocks {
+    files: Mutex<HashMap<LockedFile, Vec<LockRecord>>>,
+    /// Woken up whenever a lock is released.
+    released: Notify,
+}
+
+impl FileLocks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a future that completes the next time a lock is released.
+    ///
+    /// Create it before trying to take a lock so that a release that
+    /// happens in between is not missed.
+    pub fn released(&self) -> Notified<'_> {
+        self.released.notified()
+    }
+
+    /// Returns the first lock held by someone else that stops `owner` from
+    /// taking a lock of `kind` on the range.
+    pub fn conflict(
+        &self,
+        file: LockedFile,
+        owner: &LockOwner,
+        kind: LockKind,
+        start: u64,
+        end: u64,
+    ) -> Option<LockRecord> {
+        let files = self.files.lock().unwrap();
+        files
+            .get(&file)
+            .and_then(|records| Self::find_conflict(records, owner, kind, start, end).cloned())
+    }
+
+    /// Takes a lock on the range, replacing any lock that `owner` already
+    /// holds on it.
+    ///
+    /// `host_lock` is called once the table has no conflict, before the lock
+    /// is recorded, so that the matching host lock can be taken while no one
+    /// else can change the table.
+    pub fn try_lock<F>(
+        &self,
+        file: LockedFile,
+        owner: LockOwner,
+        kind: LockKind,
+        start: u64,
+        end: u64,
+        host_lock: F,
+    ) -> Result<(), LockConflict>
+    where
+        F: FnOnce() -> Result<(), FsError>,
+    {
+        let mut files = self.files.lock().unwrap();
+        let records = files.entry(file).or_default();
+        records.retain(|record| record.owner.is_alive());
+        if let Some(conflict) = Self::find_conflict(records, &owner, kind, start, end) {
+            return Err(LockConflict::Held(conflict.clone()));
+        }
+        if let Err(err) = host_lock() {
+            if records.is_empty() {
+                files.remove(&file);
+            }
+            return Err(LockConflict::Host(err));
+        }
+
+        let downgraded = Self::remove_range(records, &owner, start, end);
+        records.push(LockRecord {
+            owner,
+            kind,
+            start,
+            end,
+        });
+        drop(files);
+
+        if downgraded {
+            self.released.notify_waiters();
+        }
+        Ok(())
+    }
+
+    /// Releases the part of the locks of `owner` that falls in the range.
+    pub fn unlock(&self, file: LockedFile, owner: &LockOwner, start: u64, end: u64) {
+        let mut files = self.files.lock().unwrap();
+        let released = match files.get_mut(&file) {
+            Some(records) => {
+                let released = Self::remove_range(records, owner, start, end);
+                if records.is_empty() {
+                    files.remove(&file);
+                }
+                released
+            }
+            None => false,
+        };
+        drop(files);
+
+        if released {
+            self.released.notify_waiters();
+        }
+    }
+
+    /// Releases every lock on the file that belongs to `owner`.
+    pub fn release(&self, file: LockedFile, owner: &LockOwner) {
+        self.unlock(file, owner, 0, LOCK_TO_EOF);
+    }
+
+    /// Releases every lock held by a process, for when it exits, along with
+    /// the locks of the open file descriptions that have been closed.
+    pub fn release_process(&self, pid: WasiProcessId) {
+        self.release_where(|owner| owner.pid() == Some(pid));
+    }
+
+    /// Forgets the locks of the open file descriptions that have been closed,
+    /// waking up anyone waiting for them.
+    pub fn release_closed(&self) {
+        self.release_where(|_| false);
+    }
+
+    fn release_where(&self, released_owner: impl Fn(&LockOwner) -> bool) {
+        let mut files = self.files.lock().unwrap();
+        let mut released = false;
+        files.retain(|_, records| {
+            let before = records.len();
+            records.retain(|record| record.owner.is_alive() && !released_owner(&record.owner));
+            released |= records.len() != before;
+            !records.is_empty()
+        });
+        drop(files);
+
+        if released {
+            self.released.notify_waiters();
+        }
+    }
+
+    /// Returns true if anyone still holds a lock on part of the range.
+    pub fn is_locked(&self, file: LockedFile, start: u64, end: u64) -> bool {
+        let files = self.files.lock().unwrap();
+        match files.get(&file) {
+            Some(records) => records
+                .iter()
+                .any(|record| record.owner.is_alive() && record.overlaps(start, end)),
+            None => false,
+        }
+    }
+
+    fn find_conflict<'a>(
+        records: &'a [LockRecord],
+        owner: &LockOwner,
+        kind: LockKind,
+        start: u64,
+        end: u64,
+    ) -> Option<&'a LockRecord> {
+        records.iter().find(|record| {
+            record.owner != *owner
+                && record.owner.is_alive()
+                && record.owner.same_family(owner)
+                && record.overlaps(start, end)
+                && (kind == LockKind::Exclusive || record.kind == LockKind::Exclusive)
+        })
+    }
+
+    /// Cuts the range out of the locks of `owner`, splitting the ones that
+    /// stick out on both sides. Returns true if anything was removed.
+    fn remove_range(
+        records: &mut Vec<LockRecord>,
+        owner: &LockOwner,
+        start: u64,
+        end: u64,
+    ) -> bool {
+        let mut removed = false;
+        let mut remaining = Vec::with_capacity(records.len());
+        for record in records.drain(..) {
+            if record.owner != *owner || !record.overlaps(start, end) {
+                remaining.push(record);
+                continue;
+            }
+            removed = true;
+            if record.start < start {
+                remaining.push(LockRecord {
+                    end: start,
+                    ..record.clone()
+                });
+            }
+            if end < record.end {
+                remaining.push(LockRecord {
+                    start: end,
+                    ..record
+                });
+            }
+        }
+        *records = remaining;
+        removed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILE: LockedFile = LockedFile { dev: 1, ino: 2 };
+
+    fn process(pid: u32) -> LockOwner {
+        LockOwner::Process(pid.into())
+    }
+
+    #[test]
+    fn shared_locks_conflict_with_exclusive_ones() {
+        let locks = FileLocks::new();
+
+        locks
+            .try_lock(FILE, process(1), LockKind::Shared, 0, 100, || Ok(()))
+            .unwrap();
+        locks
+            .try_lock(FILE, process(2), LockKind::Shared, 50, LOCK_TO_EOF, || {
+                Ok(())
+            })
+            .unwrap();
+
+        match locks.try_lock(FILE, process(3), LockKind::Exclusive, 90, 95, || Ok(())) {
+            Err(LockConflict::Held(conflict)) => assert_eq!(conflict.owner, process(1)),
+            other => panic!("unexpected result: {other:?}"),
+        }
+        assert!(locks
+            .try_lock(FILE, process(3), LockKind::Exclusive, 0, 50, || Ok(()))
+            .is_err());
+
+        // flock() locks do not see the fcntl() ones
+        let cursor = Arc::new(AtomicU64::new(0));
+        let owner = LockOwner::File(OpenFile(Arc::downgrade(&cursor)));
+        locks
+            .try_lock(FILE, owner, LockKind::Exclusive, 0, LOCK_TO_EOF, || Ok(()))
+            .unwrap();
+    }
+
+    #[test]
+    fn unlocking_part_of_a_range_splits_it() {
+        let locks = FileLocks::new();
+
+        locks
+            .try_lock(FILE, process(1), LockKind::Exclusive, 0, 100, || Ok(()))
+            .unwrap();
+        locks.unlock(FILE, &process(1), 40, 60);
+
+        assert!(locks
+            .try_lock(FILE, process(2), LockKind::Exclusive, 40, 60, || Ok(()))
+            .is_ok());
+        assert!(locks
+            .conflict(FILE, &process(2), LockKind::Shared, 30, 40)
+            .is_some());
+        assert!(locks
+            .conflict(FILE, &process(2), LockKind::Shared, 60, 61)
+            .is_some());
+
+        locks.release_process(1.into());
+        assert!(locks
+            .conflict(FILE, &process(3), LockKind::Shared, 0, 40)
+            .is_none());
+        assert!(locks.is_locked(FILE, 40, 60));
+        assert!(!locks.is_locked(FILE, 0, 40));
+    }
+
+    #[test]
+    fn relocking_replaces_the_lock_of_the_owner() {
+        let locks = FileLocks::new();
+
+        locks
+            .try_lock(
+                FILE,
+                process(1),
+                LockKind::Exclusive,
+                0,
+                LOCK_TO_EOF,
+                || Ok(()),
+            )
+            .unwrap();
+        locks
+            .try_lock(
+                FILE,
+                process(1),
+                LockKind::Shared,
+                0,
+                LOCK_TO_EOF,
+                || Ok(()),
+            )
+            .unwrap();
+
+        locks
+            .try_lock(
+                FILE,
+                process(2),
+                LockKind::Shared,
+                0,
+                LOCK_TO_EOF,
+                || Ok(()),
+            )
+            .unwrap();
+        assert!(locks
+            .try_lock(FILE, process(1), LockKind::Exclusive, 0, 10, || Ok(()))
+            .is_err());
+    }
+
+    #[test]
+    fn host_conflicts_leave_the_table_alone() {
+        let locks = FileLocks::new();
+
+        locks
+            .try_lock(
+                FILE,
+                process(1),
+                LockKind::Shared,
+                0,
+                LOCK_TO_EOF,
+                || Ok(()),
+            )
+            .unwrap();
+        let result = locks.try_lock(
+            FILE,
+            process(1),
+            LockKind::Exclusive,
+            0,
+            LOCK_TO_EOF,
+            || Err(FsError::WouldBlock),
+        );
+        assert!(matches!(
+            result,
+            Err(LockConflict::Host(FsError::WouldBlock))
+        ));
+
+        // The shared lock is still held
+        assert!(locks
+            .conflict(FILE, &process(2), LockKind::Exclusive, 0, 1)
+            .is_some());
+        assert!(locks
+            .conflict(FILE, &process(2), LockKind::Shared, 0, 1)
+            .is_none());
+    }
+
+    #[test]
+    fn flock_locks_belong_to_the_open_file_description() {
+        let locks = FileLocks::new();
+        let cursor = Arc::new(AtomicU64::new(0));
+        let other_cursor = Arc::new(AtomicU64::new(0));
+        let open_file = || LockOwner::File(OpenFile(Arc::downgrade(&cursor)));
+        let other_open_file = LockOwner::File(OpenFile(Arc::downgrade(&other_cursor)));
+
+        locks
+            .try_lock(
+                FILE,
+                open_file(),
+                LockKind::Exclusive,
+                0,
+                LOCK_TO_EOF,
+                || Ok(()),
+            )
+            .unwrap();
+        // A duplicated descriptor shares the lock of the description
+        assert!(locks
+            .conflict(FILE, &open_file(), LockKind::Exclusive, 0, LOCK_TO_EOF)
+            .is_none());
+        assert!(locks
+            .conflict(FILE, &other_open_file, LockKind::Shared, 0, LOCK_TO_EOF)
+            .is_some());
+
+        // Closing the last descriptor of the description releases the lock
+        drop(cursor);
+        assert!(!locks.is_locked(FILE, 0, LOCK_TO_EOF));
+        locks
+            .try_lock(
+                FILE,
+                other_open_file,
+                LockKind::Exclusive,
+                0,
+                LOCK_TO_EOF,
+                || Ok(()),
+            )
+            .unwrap();
+    }
+}
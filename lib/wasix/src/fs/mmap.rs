@@ -0,0 +1,450 @@
+//! Memory mapped files, as created with `mmap()`.
+//!
+//! WebAssembly has no way of mapping a file into linear memory, so instead
+//! the program allocates the region itself and `fd_mmap` copies the contents
+//! of the file into it. To find out what the program changed, a copy of the
+//! region is kept as it was the last time it was synced. When the mapping is
+//! synced with `fd_msync` or unmapped with `fd_munmap` the pages that differ
+//! from that copy are dirty and get written back to the file. The mappings
+//! that are left when the process exits are written back as well.
+//!
+//! Until then the file doesn't have the changes, so `fd_read` (and other
+//! processes that open the file) don't see what was written through a
+//! mapping before it is synced.
+//!
+//! Shared mappings keep the contents they share in a [`SharedRegion`] that
+//! forked processes inherit along with the mapping. Each process merges its
+//! own changes into the region when it syncs, and copies in the changes that
+//! the other processes made when it syncs with `MsyncFlags::INVALIDATE`.
+//! Writes to the file with `fd_write` are copied straight into the shared
+//! mappings of the file.
+//!
+//! Private mappings are never written back, so nothing is recorded for them
+//! besides where they are.
+
+use std::{
+    collections::BTreeMap,
+    ops::Range,
+    sync::{Arc, Mutex, RwLock},
+};
+
+use derivative::Derivative;
+use virtual_fs::VirtualFile;
+use wasmer_wasix_types::wasi::Errno;
+
+use super::Inode;
+
+/// Granularity at which dirty ranges are tracked
+pub(crate) const MMAP_PAGE_SIZE: usize = 4096;
+
+/// The memory mappings of a process, by their address.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct MemoryMaps {
+    maps: BTreeMap<u64, MemoryMap>,
+}
+
+impl MemoryMaps {
+    /// Records a new mapping, which fails with `EINVAL` if it overlaps with
+    /// one of the existing ones.
+    pub fn insert(&mut self, map: MemoryMap) -> Result<(), Errno> {
+        if self.overlapping(map.addr, map.len).next().is_some() {
+            return Err(Errno::Inval);
+        }
+        self.maps.insert(map.addr, map);
+        Ok(())
+    }
+
+    /// Removes the mappings in a range of memory. Mappings can only be
+    /// removed as a whole, so this fails with `EINVAL` if the range only
+    /// covers part of one.
+    pub fn remove(&mut self, addr: u64, len: u64) -> Result<Vec<MemoryMap>, Errno> {
+        let end = addr.saturating_add(len);
+        let mut removed = Vec::new();
+        for map in self.overlapping(addr, len) {
+            if map.addr < addr || map.end() > end {
+                return Err(Errno::Inval);
+            }
+            removed.push(map.addr);
+        }
+        Ok(removed
+            .into_iter()
+            .filter_map(|addr| self.maps.remove(&addr))
+            .collect())
+    }
+
+    /// Returns the mappings that overlap with a range of memory.
+    pub fn overlapping(&self, addr: u64, len: u64) -> impl Iterator<Item = &MemoryMap> {
+        self.maps
+            .range(..addr.saturating_add(len))
+            .map(|(_, map)| map)
+            .filter(move |map| map.end() > addr)
+    }
+
+    /// Returns the mappings that overlap with a range of memory.
+    pub fn overlapping_mut(&mut self, addr: u64, len: u64) -> impl Iterator<Item = &mut MemoryMap> {
+        self.maps
+            .range_mut(..addr.saturating_add(len))
+            .map(|(_, map)| map)
+            .filter(move |map| map.end() > addr)
+    }
+
+    /// Returns true if any of the mappings are shared mappings of a file.
+    pub fn maps_file(&self, ino: Inode) -> bool {
+        self.maps.values().any(|map| map.file_ino() == Some(ino))
+    }
+
+    /// Copies data that was written to a file into the shared mappings of
+    /// the file. Returns where the data has to be copied to in the memory of
+    /// this process, as the address and the range of the data.
+    pub fn file_written(
+        &mut self,
+        ino: Inode,
+        offset: u64,
+        data: &[u8],
+    ) -> Vec<(u64, Range<usize>)> {
+        self.maps
+            .values_mut()
+            .filter_map(|map| {
+                map.file_written(ino, offset, data)
+                    .map(|(at, range)| (map.addr + at as u64, range))
+            })
+            .collect()
+    }
+
+    /// Removes all the mappings, which happens when the process execs
+    /// another program.
+    pub fn clear(&mut self) {
+        self.maps.clear();
+    }
+
+    /// Removes all the mappings and returns them, so that they can be written
+    /// back when the process exits.
+    pub fn take_all(&mut self) -> Vec<MemoryMap> {
+        std::mem::take(&mut self.maps).into_values().collect()
+    }
+}
+
+/// A region of linear memory that a file (or anonymous memory) is mapped to.
+#[derive(Debug, Clone)]
+pub(crate) struct MemoryMap {
+    /// Address of the first byte of the mapping
+    pub addr: u64,
+    /// Length of the mapping in bytes
+    pub len: u64,
+    /// Set for mappings whose changes are shared
+    shared: Option<SharedMap>,
+}
+
+#[derive(Debug, Clone)]
+struct SharedMap {
+    /// Contents that are shared with the other processes
+    region: Arc<Mutex<SharedRegion>>,
+    /// Contents of the mapping as of the last time this process synced it
+    synced: Vec<u8>,
+    /// Set if the process can write to the mapping
+    writable: bool,
+}
+
+/// The contents of a shared mapping, which every process that shares the
+/// mapping has a reference to.
+#[derive(Debug)]
+pub(crate) struct SharedRegion {
+    data: Vec<u8>,
+    file: Option<MappedFile>,
+}
+
+/// The file that a shared mapping is written back to.
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub(crate) struct MappedFile {
+    /// The inode of the file
+    pub ino: Inode,
+    /// Offset in the file where the mapping starts
+    pub offset: u64,
+    /// The file handle that changes are written back through, which keeps
+    /// the file open after its descriptor is closed
+    #[derivative(Debug = "ignore")]
+    pub handle: Arc<RwLock<Box<dyn VirtualFile + Send + Sync + 'static>>>,
+}
+
+/// Data that has to be written back to a file.
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub(crate) struct FileWrite {
+    #[derivative(Debug = "ignore")]
+    pub handle: Arc<RwLock<Box<dyn VirtualFile + Send + Sync + 'static>>>,
+    pub offset: u64,
+    #[derivative(Debug = "ignore")]
+    pub data: Vec<u8>,
+}
+
+/// What syncing a mapping changed.
+#[derive(Debug, Default)]
+pub(crate) struct MapSync {
+    /// Ranges of the mapping that other processes changed, which have been
+    /// updated in the contents that were passed in and must be copied back
+    /// into memory
+    pub refreshed: Vec<Range<usize>>,
+    /// Dirty ranges that have to be written back to the file
+    pub writes: Vec<FileWrite>,
+}
+
+impl MemoryMap {
+    /// A private mapping, whose contents are only seen by this process.
+    pub fn new_private(addr: u64, len: u64) -> Self {
+        Self {
+            addr,
+            len,
+            shared: None,
+        }
+    }
+
+    /// A shared mapping, which starts out with the contents that were copied
+    /// into memory.
+    pub fn new_shared(addr: u64, data: Vec<u8>, file: Option<MappedFile>, writable: bool) -> Self {
+        Self {
+            addr,
+            len: data.len() as u64,
+            shared: Some(SharedMap {
+                synced: data.clone(),
+                region: Arc::new(Mutex::new(SharedRegion { data, file })),
+                writable,
+            }),
+        }
+    }
+
+    /// Address just past the end of the mapping
+    pub fn end(&self) -> u64 {
+        self.addr + self.len
+    }
+
+    /// Returns true if changes to the mapping are shared.
+    pub fn is_shared(&self) -> bool {
+        self.shared.is_some()
+    }
+
+    fn file_ino(&self) -> Option<Inode> {
+        let shared = self.shared.as_ref()?;
+        let region = shared.region.lock().unwrap();
+        region.file.as_ref().map(|file| file.ino)
+    }
+
+    /// Syncs the mapping with the other processes that share it, where
+    /// `contents` is what is currently in memory. The changes this process
+    /// made are merged into the shared region, and if `invalidate` is set
+    /// then the changes of the other processes are copied into `contents`.
+    pub fn sync(&mut self, contents: &mut [u8], invalidate: bool) -> MapSync {
+        let shared = match self.shared.as_mut() {
+            Some(shared) => shared,
+            None => return MapSync::default(),
+        };
+        let mut region = shared.region.lock().unwrap();
+        let len = contents.len().min(shared.synced.len());
+
+        // Only the bytes that changed are merged so that changes that other
+        // processes made to the same page are kept
+        let mut dirty = Vec::new();
+        if shared.writable {
+            for start in (0..len).step_by(MMAP_PAGE_SIZE) {
+                let end = (start + MMAP_PAGE_SIZE).min(len);
+                let local = &contents[start..end];
+                let synced = &mut shared.synced[start..end];
+                if local == synced {
+                    continue;
+                }
+                for (i, (local, synced)) in local.iter().zip(synced.iter()).enumerate() {
+                    if local != synced {
+                        region.data[start + i] = *local;
+                    }
+                }
+                synced.copy_from_slice(local);
+                push_range(&mut dirty, start..end);
+            }
+        }
+
+        let mut refreshed = Vec::new();
+        if invalidate {
+            for start in (0..len).step_by(MMAP_PAGE_SIZE) {
+                let end = (start + MMAP_PAGE_SIZE).min(len);
+                let remote = &region.data[start..end];
+                if remote == &shared.synced[start..end] {
+                    continue;
+                }
+                contents[start..end].copy_from_slice(remote);
+                shared.synced[start..end].copy_from_slice(remote);
+                push_range(&mut refreshed, start..end);
+            }
+        }
+
+        let writes = match region.file.as_ref() {
+            Some(file) => dirty
+                .into_iter()
+                .map(|range| FileWrite {
+                    handle: file.handle.clone(),
+                    offset: file.offset + range.start as u64,
+                    data: region.data[range].to_vec(),
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        MapSync { refreshed, writes }
+    }
+
+    /// Copies data that was written to a file into the mapping if it is a
+    /// shared mapping of that part of the file. Returns where in the mapping
+    /// the data goes and the range of the data that goes there.
+    fn file_written(
+        &mut self,
+        ino: Inode,
+        offset: u64,
+        data: &[u8],
+    ) -> Option<(usize, Range<usize>)> {
+        let shared = self.shared.as_mut()?;
+        let mut region = shared.region.lock().unwrap();
+        let file = region.file.as_ref().filter(|file| file.ino == ino)?;
+
+        let start = offset.max(file.offset);
+        let end = (offset + data.len() as u64).min(file.offset + self.len);
+        if start >= end {
+            return None;
+        }
+        let at = (start - file.offset) as usize;
+        let range = (start - offset) as usize..(end - offset) as usize;
+        let len = range.len();
+
+        region.data[at..at + len].copy_from_slice(&data[range.clone()]);
+        shared.synced[at..at + len].copy_from_slice(&data[range.clone()]);
+        Some((at, range))
+    }
+}
+
+/// Adds a range to a list of ranges, joining it with the last one if they
+/// are next to each other.
+fn push_range(ranges: &mut Vec<Range<usize>>, range: Range<usize>) {
+    match ranges.last_mut() {
+        Some(last) if last.end == range.start => last.end = range.end,
+        _ => ranges.push(range),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(offset: u64) -> MappedFile {
+        MappedFile {
+            ino: Inode(7),
+            offset,
+            handle: Arc::new(RwLock::new(Box::<virtual_fs::NullFile>::default())),
+        }
+    }
+
+    #[test]
+    fn test_mmap_dirty_pages_are_written_back() {
+        let len = MMAP_PAGE_SIZE * 4;
+        let mut map = MemoryMap::new_shared(0x1000, vec![0; len], Some(file(100)), true);
+
+        // Nothing changed
+        let mut contents = vec![0; len];
+        let sync = map.sync(&mut contents, false);
+        assert!(sync.writes.is_empty());
+
+        // Two neighbouring pages and one on its own
+        contents[MMAP_PAGE_SIZE - 1] = 1;
+        contents[MMAP_PAGE_SIZE] = 2;
+        contents[MMAP_PAGE_SIZE * 3 + 5] = 3;
+        let sync = map.sync(&mut contents, false);
+        let writes = sync
+            .writes
+            .iter()
+            .map(|write| (write.offset, write.data.len()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            writes,
+            vec![
+                (100, MMAP_PAGE_SIZE * 2),
+                (100 + MMAP_PAGE_SIZE as u64 * 3, MMAP_PAGE_SIZE)
+            ]
+        );
+        assert_eq!(sync.writes[0].data[MMAP_PAGE_SIZE - 1], 1);
+        assert_eq!(sync.writes[0].data[MMAP_PAGE_SIZE], 2);
+
+        // Once synced the pages are clean again
+        assert!(map.sync(&mut contents, false).writes.is_empty());
+    }
+
+    #[test]
+    fn test_mmap_shared_across_fork() {
+        let len = MMAP_PAGE_SIZE * 2;
+        let mut parent = MemoryMap::new_shared(0, vec![0; len], None, true);
+        let mut child = parent.clone();
+        let mut parent_contents = vec![0; len];
+        let mut child_contents = vec![0; len];
+
+        // Both processes write to the same page
+        child_contents[10] = 1;
+        parent_contents[20] = 2;
+        assert!(child.sync(&mut child_contents, false).writes.is_empty());
+
+        // The parent keeps its own change and picks up the one of the child
+        let sync = parent.sync(&mut parent_contents, true);
+        assert_eq!(sync.refreshed, vec![0..MMAP_PAGE_SIZE]);
+        assert_eq!(parent_contents[10], 1);
+        assert_eq!(parent_contents[20], 2);
+
+        // The child only sees the change once it invalidates
+        child.sync(&mut child_contents, false);
+        assert_eq!(child_contents[20], 0);
+        child.sync(&mut child_contents, true);
+        assert_eq!(child_contents, parent_contents);
+    }
+
+    #[test]
+    fn test_mmap_file_writes_update_the_mapping() {
+        let len = MMAP_PAGE_SIZE;
+        let mut maps = MemoryMaps::default();
+        maps.insert(MemoryMap::new_shared(
+            0x1000,
+            vec![0; len],
+            Some(file(100)),
+            true,
+        ))
+        .unwrap();
+        maps.insert(MemoryMap::new_private(0x10000, len as u64))
+            .unwrap();
+        assert!(maps.maps_file(Inode(7)));
+        assert!(!maps.maps_file(Inode(8)));
+
+        // The write starts before the mapping does
+        let updates = maps.file_written(Inode(7), 90, &[1; 20]);
+        assert_eq!(updates, vec![(0x1000, 10..20)]);
+
+        // A write through the file is not dirty
+        let mut contents = vec![0; len];
+        contents[..10].copy_from_slice(&[1; 10]);
+        let mut map = maps.remove(0x1000, len as u64).unwrap().remove(0);
+        assert!(map.sync(&mut contents, false).writes.is_empty());
+    }
+
+    #[test]
+    fn test_mmap_overlapping_maps() {
+        let mut maps = MemoryMaps::default();
+        maps.insert(MemoryMap::new_private(0x1000, 0x1000)).unwrap();
+        maps.insert(MemoryMap::new_private(0x3000, 0x1000)).unwrap();
+        assert_eq!(
+            maps.insert(MemoryMap::new_private(0x1800, 0x1000)).err(),
+            Some(Errno::Inval)
+        );
+        maps.insert(MemoryMap::new_private(0x2000, 0x1000)).unwrap();
+
+        assert_eq!(maps.overlapping(0x1fff, 2).count(), 2);
+        assert_eq!(maps.remove(0x1000, 0x1800).err(), Some(Errno::Inval));
+        assert_eq!(maps.remove(0x1000, 0x2000).unwrap().len(), 2);
+        assert_eq!(maps.overlapping(0, u64::MAX).count(), 1);
+        assert!(maps.remove(0x1000, 0x2000).unwrap().is_empty());
+
+        // What is left gets written back when the process exits
+        assert_eq!(maps.take_all().len(), 1);
+        assert_eq!(maps.overlapping(0, u64::MAX).count(), 0);
+    }
+}
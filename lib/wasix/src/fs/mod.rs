@@ -1,6 +1,9 @@
 mod fd;
 mod inode_guard;
+mod locks;
+mod mmap;
 mod notification;
+mod watch_file;
 
 use std::{
     borrow::{Borrow, Cow},
@@ -35,7 +38,14 @@ pub(crate) use self::inode_guard::{
     InodeValFilePollGuard, InodeValFilePollGuardJoin, InodeValFilePollGuardMode,
     InodeValFileReadGuard, InodeValFileWriteGuard, WasiStateFileGuard, POLL_GUARD_MAX_RET,
 };
+pub(crate) use self::locks::{
+    FileLocks, LockConflict, LockKind, LockOwner, LockRecord, LockedFile, OpenFile, LOCK_TO_EOF,
+};
+pub(crate) use self::mmap::{FileWrite, MappedFile, MemoryMap, MemoryMaps};
 pub use self::notification::NotificationInner;
+pub use self::watch_file::{
+    WatchFile, WATCH_CREATE, WATCH_DELETE, WATCH_MODIFY, WATCH_MOVED_FROM, WATCH_MOVED_TO,
+};
 use crate::syscalls::map_io_err;
 use crate::{bin_factory::BinaryPackage, state::PreopenedDir, ALL_RIGHTS};
 
@@ -338,6 +348,47 @@ impl FileSystem for WasiFsRoot {
             WasiFsRoot::Backing(fs) => fs.remove_file(path),
         }
     }
+    fn symlink(&self, target: &Path, link: &Path) -> virtual_fs::Result<()> {
+        match self {
+            WasiFsRoot::Sandbox(fs) => fs.symlink(target, link),
+            WasiFsRoot::Backing(fs) => fs.symlink(target, link),
+        }
+    }
+    fn hard_link(&self, original: &Path, link: &Path) -> virtual_fs::Result<()> {
+        match self {
+            WasiFsRoot::Sandbox(fs) => fs.hard_link(original, link),
+            WasiFsRoot::Backing(fs) => fs.hard_link(original, link),
+        }
+    }
+    fn read_link(&self, path: &Path) -> virtual_fs::Result<PathBuf> {
+        match self {
+            WasiFsRoot::Sandbox(fs) => fs.read_link(path),
+            WasiFsRoot::Backing(fs) => fs.read_link(path),
+        }
+    }
+    fn set_permissions(&self, path: &Path, mode: u32) -> virtual_fs::Result<()> {
+        match self {
+            WasiFsRoot::Sandbox(fs) => fs.set_permissions(path, mode),
+            WasiFsRoot::Backing(fs) => fs.set_permissions(path, mode),
+        }
+    }
+    fn set_times(
+        &self,
+        path: &Path,
+        accessed: Option<std::time::SystemTime>,
+        modified: Option<std::time::SystemTime>,
+    ) -> virtual_fs::Result<()> {
+        match self {
+            WasiFsRoot::Sandbox(fs) => fs.set_times(path, accessed, modified),
+            WasiFsRoot::Backing(fs) => fs.set_times(path, accessed, modified),
+        }
+    }
+    fn watch(&self, path: &Path, recursive: bool) -> virtual_fs::Result<virtual_fs::FsWatcher> {
+        match self {
+            WasiFsRoot::Sandbox(fs) => fs.watch(path, recursive),
+            WasiFsRoot::Backing(fs) => fs.watch(path, recursive),
+        }
+    }
     fn new_open_options(&self) -> OpenOptions {
         match self {
             WasiFsRoot::Sandbox(fs) => fs.new_open_options(),
@@ -387,7 +438,7 @@ async fn merge_filesystems(
     files.try_collect().await
 }
 
-fn create_dir_all(fs: &dyn FileSystem, path: &Path) -> Result<(), virtual_fs::FsError> {
+pub(crate) fn create_dir_all(fs: &dyn FileSystem, path: &Path) -> Result<(), virtual_fs::FsError> {
     if fs.metadata(path).is_ok() {
         return Ok(());
     }
@@ -420,6 +471,10 @@ pub struct WasiFs {
     // but it shouldn't be necessary
     // It should not be necessary at all.
     is_wasix: AtomicBool,
+
+    /// The most file descriptors that can be open at once, which mirrors the
+    /// open files limit of the process that owns this file system
+    max_fds: AtomicU64,
 }
 
 impl WasiFs {
@@ -429,6 +484,20 @@ impl WasiFs {
         self.is_wasix.load(Ordering::Relaxed)
     }
 
+    /// Limits the number of file descriptors that can be open at once,
+    /// creating more fails with `EMFILE`
+    pub fn set_max_fds(&self, max_fds: u64) {
+        self.max_fds.store(max_fds, Ordering::Release);
+    }
+
+    /// Fails with `EMFILE` if no more file descriptors can be opened
+    fn check_fd_limit(&self, fd_map: &HashMap<WasiFd, Fd>) -> Result<(), Errno> {
+        if fd_map.len() as u64 >= self.max_fds.load(Ordering::Acquire) {
+            return Err(Errno::Mfile);
+        }
+        Ok(())
+    }
+
     pub fn set_is_wasix(&self, is_wasix: bool) {
         self.is_wasix.store(is_wasix, Ordering::SeqCst);
     }
@@ -442,6 +511,7 @@ impl WasiFs {
             next_fd: AtomicU32::new(self.next_fd.load(Ordering::SeqCst)),
             current_dir: Mutex::new(self.current_dir.lock().unwrap().clone()),
             is_wasix: AtomicBool::new(self.is_wasix.load(Ordering::Acquire)),
+            max_fds: AtomicU64::new(self.max_fds.load(Ordering::Acquire)),
             root_fs: self.root_fs.clone(),
             root_inode: self.root_inode.clone(),
             has_unioned: Arc::new(Mutex::new(HashSet::new())),
@@ -717,6 +787,7 @@ impl WasiFs {
             next_fd: AtomicU32::new(3),
             current_dir: Mutex::new("/".to_string()),
             is_wasix: AtomicBool::new(false),
+            max_fds: AtomicU64::new(u64::MAX),
             root_fs: fs_backing,
             root_inode: root_inode.clone(),
             has_unioned: Arc::new(Mutex::new(HashSet::new())),
@@ -1045,6 +1116,9 @@ impl WasiFs {
         'path_iter: for (i, component) in path.components().enumerate() {
             // used to terminate symlink resolution properly
             let last_component = i + 1 == n_components;
+            // set when the component itself is a symlink that is being
+            // followed, its target then takes the place of the component
+            let mut following_component = false;
             // for each component traverse file structure
             // loading inodes as necessary
             'symlink_resolution: while symlink_count < MAX_SYMLINKS {
@@ -1110,14 +1184,14 @@ impl WasiFs {
                                 }
                             } else if file_type.is_symlink() {
                                 should_insert = false;
-                                let link_value = file.read_link().map_err(map_io_err)?;
+                                let link_value = self
+                                    .root_fs
+                                    .read_link(&file)
+                                    .map_err(fs_error_into_wasi_err)?;
                                 debug!("attempting to decompose path {:?}", link_value);
 
-                                let (pre_open_dir_fd, relative_path) = if link_value.is_relative() {
-                                    self.path_into_pre_open_and_relative_path(&file)?
-                                } else {
-                                    unimplemented!("Absolute symlinks are not yet supported");
-                                };
+                                let (pre_open_dir_fd, relative_path) =
+                                    self.path_into_pre_open_and_relative_path(&file)?;
                                 loop_for_symlink = true;
                                 symlink_count += 1;
                                 Kind::Symlink {
@@ -1205,6 +1279,7 @@ impl WasiFs {
 
                             if loop_for_symlink && follow_symlinks {
                                 debug!("Following symlink to {:?}", cur_inode);
+                                following_component = true;
                                 continue 'symlink_resolution;
                             }
                         }
@@ -1239,21 +1314,21 @@ impl WasiFs {
                         path_to_symlink,
                         relative_path,
                     } => {
-                        let new_base_dir = *base_po_dir;
-                        let new_base_inode = self.get_fd_inode(new_base_dir)?;
-
-                        // allocate to reborrow mutabily to recur
-                        let new_path = {
-                            /*if let Kind::Root { .. } = self.inodes[base_po_dir].kind {
-                                assert!(false, "symlinks should never be relative to the root");
-                            }*/
+                        // absolute targets are looked up from the preopened
+                        // directory that contains them
+                        let (new_base_dir, new_path) = if relative_path.is_absolute() {
+                            let (fd, path) =
+                                self.path_into_pre_open_and_relative_path(relative_path)?;
+                            (fd, path.to_string_lossy().to_string())
+                        } else {
                             let mut base = path_to_symlink.clone();
                             // remove the symlink file itself from the path, leaving just the path from the base
                             // to the dir containing the symlink
                             base.pop();
                             base.push(relative_path);
-                            base.to_string_lossy().to_string()
+                            (*base_po_dir, base.to_string_lossy().to_string())
                         };
+                        let new_base_inode = self.get_fd_inode(new_base_dir)?;
                         debug!("Following symlink recursively");
                         drop(guard);
                         let symlink_inode = self.get_inode_at_path_inner(
@@ -1264,6 +1339,9 @@ impl WasiFs {
                             follow_symlinks,
                         )?;
                         cur_inode = symlink_inode;
+                        if following_component {
+                            break 'symlink_resolution;
+                        }
                         // if we're at the very end and we found a file, then we're done
                         // TODO: figure out if this should also happen for directories?
                         let guard = cur_inode.read();
@@ -1346,32 +1424,6 @@ impl WasiFs {
         }
     }
 
-    /// finds the number of directories between the fd and the inode if they're connected
-    /// expects inode to point to a directory
-    pub(crate) fn path_depth_from_fd(&self, fd: WasiFd, inode: InodeGuard) -> Result<usize, Errno> {
-        let mut counter = 0;
-        let base_inode = self.get_fd_inode(fd)?;
-        let mut cur_inode = inode;
-
-        while cur_inode.ino() != base_inode.ino() {
-            counter += 1;
-
-            let processing_cur_inode = cur_inode.clone();
-            let guard = processing_cur_inode.read();
-
-            match guard.deref() {
-                Kind::Dir { parent, .. } => {
-                    if let Some(p) = parent.upgrade() {
-                        cur_inode = p;
-                    }
-                }
-                _ => return Err(Errno::Inval),
-            }
-        }
-
-        Ok(counter)
-    }
-
     /// gets a host file from a base directory and a path
     /// this function ensures the fs remains sandboxed
     // NOTE: follow symlinks is super weird right now
@@ -1420,6 +1472,23 @@ impl WasiFs {
             .map(|v| (v, new_entity_name))
     }
 
+    /// Returns where the last component of `path` lives in the file system,
+    /// without following it when it is a symlink
+    pub(crate) fn get_entry_path_at_path(
+        &self,
+        inodes: &WasiInodes,
+        base: WasiFd,
+        path: &Path,
+    ) -> Result<PathBuf, Errno> {
+        let (parent_inode, entry_name) = self.get_parent_inode_at_path(inodes, base, path, true)?;
+        let guard = parent_inode.read();
+        match guard.deref() {
+            Kind::Dir { path, .. } => Ok(path.join(entry_name)),
+            Kind::Root { .. } => Err(Errno::Notcapable),
+            _ => Err(Errno::Notdir),
+        }
+    }
+
     pub fn get_fd(&self, fd: WasiFd) -> Result<Fd, Errno> {
         self.fd_map
             .read()
@@ -1429,6 +1498,18 @@ impl WasiFs {
             .map(|a| a.clone())
     }
 
+    /// Looks up the inode of `path` in the root file system, starting from
+    /// the preopened directory it is in so the inode is linked to its parents.
+    pub(crate) fn get_inode_at_fs_path(
+        &self,
+        inodes: &WasiInodes,
+        path: &Path,
+    ) -> Result<InodeGuard, Errno> {
+        let (base, rel_path) = self.path_into_pre_open_and_relative_path(path)?;
+        let base_inode = self.get_fd_inode(base)?;
+        self.get_inode_at_path_inner(inodes, base_inode, &rel_path.to_string_lossy(), 0, true)
+    }
+
     pub fn get_fd_inode(&self, fd: WasiFd) -> Result<InodeGuard, Errno> {
         self.fd_map
             .read()
@@ -1660,7 +1741,11 @@ impl WasiFs {
             idx,
             __WASI_STDIN_FILENO | __WASI_STDOUT_FILENO | __WASI_STDERR_FILENO
         );
-        self.fd_map.write().unwrap().insert(
+        let mut fd_map = self.fd_map.write().unwrap();
+        if !fd_map.contains_key(&idx) {
+            self.check_fd_limit(&fd_map)?;
+        }
+        fd_map.insert(
             idx,
             Fd {
                 rights,
@@ -1677,8 +1762,10 @@ impl WasiFs {
 
     pub fn clone_fd(&self, fd: WasiFd) -> Result<WasiFd, Errno> {
         let fd = self.get_fd(fd)?;
+        let mut fd_map = self.fd_map.write().unwrap();
+        self.check_fd_limit(&fd_map)?;
         let idx = self.next_fd.fetch_add(1, Ordering::SeqCst);
-        self.fd_map.write().unwrap().insert(
+        fd_map.insert(
             idx,
             Fd {
                 rights: fd.rights,
@@ -1961,6 +2048,8 @@ pub fn fs_error_from_wasi_err(err: Errno) -> FsError {
         Errno::Again => FsError::WouldBlock,
         Errno::Nospc => FsError::WriteZero,
         Errno::Notempty => FsError::DirectoryNotEmpty,
+        Errno::Loop => FsError::TooManyLinks,
+        Errno::Notsup => FsError::Unsupported,
         _ => FsError::UnknownError,
     }
 }
@@ -1990,7 +2079,91 @@ pub fn fs_error_into_wasi_err(fs_error: FsError) -> Errno {
         FsError::WouldBlock => Errno::Again,
         FsError::WriteZero => Errno::Nospc,
         FsError::DirectoryNotEmpty => Errno::Notempty,
-        FsError::StorageFull => Errno::Overflow,
+        FsError::StorageFull => Errno::Nospc,
+        FsError::TooManyLinks => Errno::Loop,
+        FsError::Unsupported => Errno::Notsup,
         FsError::Lock | FsError::UnknownError => Errno::Io,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use virtual_fs::TmpFileSystem;
+
+    use super::*;
+
+    fn sandbox() -> (WasiFs, WasiInodes, WasiFd) {
+        let root_fs = TmpFileSystem::new();
+        root_fs.create_dir(Path::new("/data")).unwrap();
+        root_fs.create_dir(Path::new("/data/dir")).unwrap();
+        root_fs
+            .new_open_options()
+            .create(true)
+            .write(true)
+            .open("/data/dir/file.txt")
+            .unwrap();
+        root_fs
+            .symlink(Path::new("file.txt"), Path::new("/data/dir/relative"))
+            .unwrap();
+        root_fs
+            .symlink(Path::new("/data/dir/file.txt"), Path::new("/data/absolute"))
+            .unwrap();
+        root_fs
+            .symlink(Path::new("dir"), Path::new("/data/to-dir"))
+            .unwrap();
+
+        let inodes = WasiInodes::new();
+        let preopen = PreopenedDir {
+            path: "/".into(),
+            alias: None,
+            read: true,
+            write: true,
+            create: true,
+        };
+        let fs = WasiFs::new_with_preopen(
+            &inodes,
+            &[preopen],
+            &[],
+            WasiFsRoot::Sandbox(Arc::new(root_fs)),
+        )
+        .unwrap();
+        let fd = *fs.preopen_fds.read().unwrap().last().unwrap();
+
+        (fs, inodes, fd)
+    }
+
+    fn resolves_to_file(fs: &WasiFs, inodes: &WasiInodes, fd: WasiFd, path: &str) {
+        let inode = fs.get_inode_at_path(inodes, fd, path, true).unwrap();
+        let guard = inode.read();
+        match guard.deref() {
+            Kind::File { path, .. } => assert_eq!(path, Path::new("/data/dir/file.txt")),
+            other => panic!("{path} resolved to {other:?}"),
+        };
+    }
+
+    #[tokio::test]
+    async fn symlinks_are_followed() {
+        let (fs, inodes, fd) = sandbox();
+
+        resolves_to_file(&fs, &inodes, fd, "data/dir/relative");
+        resolves_to_file(&fs, &inodes, fd, "data/absolute");
+        resolves_to_file(&fs, &inodes, fd, "data/to-dir/file.txt");
+        resolves_to_file(&fs, &inodes, fd, "data/to-dir/relative");
+    }
+
+    #[tokio::test]
+    async fn the_last_symlink_is_kept_when_not_following() {
+        let (fs, inodes, fd) = sandbox();
+
+        let inode = fs
+            .get_inode_at_path(&inodes, fd, "data/absolute", false)
+            .unwrap();
+        assert!(matches!(inode.read().deref(), Kind::Symlink { .. }));
+
+        let path = fs
+            .get_entry_path_at_path(&inodes, fd, Path::new("data/to-dir/relative"))
+            .unwrap();
+        assert_eq!(path, Path::new("/data/dir/relative"));
+        assert_eq!(fs.root_fs.read_link(&path).unwrap(), Path::new("file.txt"));
+    }
+}
@@ -0,0 +1,280 @@
+//! The file behind the descriptors created by `fd_watch_create`.
+//!
+//! Reading it returns the changes made to the watched paths, as records laid
+//! out like the `inotify_event` of Linux (all fields are little endian):
+//!
+//! | field    | type        | description                                  |
+//! |----------|-------------|----------------------------------------------|
+//! | `wd`     | `u32`       | the watch returned by `fd_watch_add`         |
+//! | `mask`   | `u32`       | one of the `WATCH_*` constants               |
+//! | `cookie` | `u32`       | pairs the two halves of a rename             |
+//! | `len`    | `u32`       | the length of `name`                         |
+//! | `name`   | `[u8; len]` | the path, relative to the watched one        |
+//!
+//! Only whole records are returned by a read, and a read into a buffer that
+//! is too small for the next record fails with `EINVAL`.
+
+use std::{
+    collections::VecDeque,
+    io,
+    path::{Path, PathBuf},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures::future::BoxFuture;
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
+use virtual_fs::{FsError, FsEvent, FsWatcher, VirtualFile};
+
+/// The contents of a file changed.
+pub const WATCH_MODIFY: u32 = 0x0000_0002;
+/// Something was moved away, the cookie matches the [`WATCH_MOVED_TO`] record
+/// that follows it.
+pub const WATCH_MOVED_FROM: u32 = 0x0000_0040;
+/// Something was moved in.
+pub const WATCH_MOVED_TO: u32 = 0x0000_0080;
+/// Something was created.
+pub const WATCH_CREATE: u32 = 0x0000_0100;
+/// Something was removed.
+pub const WATCH_DELETE: u32 = 0x0000_0200;
+
+#[derive(Debug, Default)]
+pub struct WatchFile {
+    watches: Vec<Watch>,
+    last_wd: u32,
+    last_cookie: u32,
+    /// The encoded records waiting to be read.
+    pending: VecDeque<Vec<u8>>,
+}
+
+#[derive(Debug)]
+struct Watch {
+    wd: u32,
+    path: PathBuf,
+    watcher: FsWatcher,
+}
+
+impl WatchFile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts reporting the events of `watcher`, which watches `path`, and
+    /// returns the descriptor of the watch.
+    pub fn add(&mut self, path: PathBuf, watcher: FsWatcher) -> u32 {
+        self.last_wd += 1;
+        self.watches.push(Watch {
+            wd: self.last_wd,
+            path,
+            watcher,
+        });
+        self.last_wd
+    }
+
+    /// Stops watching, returns `false` when the watch doesn't exist.
+    pub fn remove(&mut self, wd: u32) -> bool {
+        let len = self.watches.len();
+        self.watches.retain(|watch| watch.wd != wd);
+        self.watches.len() != len
+    }
+
+    /// Encodes all the events that are ready, the waker of `cx` is woken
+    /// when more of them come.
+    fn poll_events(&mut self, cx: &mut Context<'_>) {
+        for watch in &mut self.watches {
+            while let Poll::Ready(Some(event)) = watch.watcher.poll_next(cx) {
+                let name = |path: &Path| {
+                    let relative = path.strip_prefix(&watch.path).unwrap_or(path);
+                    relative.to_string_lossy().into_owned().into_bytes()
+                };
+
+                match event {
+                    FsEvent::Create(path) => {
+                        self.pending
+                            .push_back(encode(watch.wd, WATCH_CREATE, 0, &name(&path)));
+                    }
+                    FsEvent::Modify(path) => {
+                        self.pending
+                            .push_back(encode(watch.wd, WATCH_MODIFY, 0, &name(&path)));
+                    }
+                    FsEvent::Remove(path) => {
+                        self.pending
+                            .push_back(encode(watch.wd, WATCH_DELETE, 0, &name(&path)));
+                    }
+                    FsEvent::Rename { from, to } => {
+                        self.last_cookie = self.last_cookie.wrapping_add(1).max(1);
+                        let cookie = self.last_cookie;
+                        self.pending.push_back(encode(
+                            watch.wd,
+                            WATCH_MOVED_FROM,
+                            cookie,
+                            &name(&from),
+                        ));
+                        self.pending.push_back(encode(
+                            watch.wd,
+                            WATCH_MOVED_TO,
+                            cookie,
+                            &name(&to),
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    fn pending_bytes(&self) -> usize {
+        self.pending.iter().map(|record| record.len()).sum()
+    }
+}
+
+fn encode(wd: u32, mask: u32, cookie: u32, name: &[u8]) -> Vec<u8> {
+    let mut record = Vec::with_capacity(16 + name.len());
+    record.extend_from_slice(&wd.to_le_bytes());
+    record.extend_from_slice(&mask.to_le_bytes());
+    record.extend_from_slice(&cookie.to_le_bytes());
+    record.extend_from_slice(&(name.len() as u32).to_le_bytes());
+    record.extend_from_slice(name);
+    record
+}
+
+impl VirtualFile for WatchFile {
+    fn last_accessed(&self) -> u64 {
+        0
+    }
+
+    fn last_modified(&self) -> u64 {
+        0
+    }
+
+    fn created_time(&self) -> u64 {
+        0
+    }
+
+    fn size(&self) -> u64 {
+        self.pending_bytes() as u64
+    }
+
+    fn set_len(&mut self, _new_size: u64) -> virtual_fs::Result<()> {
+        Err(FsError::PermissionDenied)
+    }
+
+    fn unlink(&mut self) -> BoxFuture<'static, virtual_fs::Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn poll_read_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        this.poll_events(cx);
+        match this.pending_bytes() {
+            0 => Poll::Pending,
+            len => Poll::Ready(Ok(len)),
+        }
+    }
+
+    fn poll_write_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        // Nothing can be written to a watch
+        Poll::Pending
+    }
+}
+
+impl AsyncRead for WatchFile {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        this.poll_events(cx);
+        if this.pending.is_empty() {
+            return Poll::Pending;
+        }
+
+        let mut read_any = false;
+        while let Some(record) = this.pending.front() {
+            if record.len() > buf.remaining() {
+                break;
+            }
+            buf.put_slice(record);
+            this.pending.pop_front();
+            read_any = true;
+        }
+
+        if !read_any {
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the buffer is too small for the next event",
+            )));
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for WatchFile {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        _buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "watches can't be written to",
+        )))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncSeek for WatchFile {
+    fn start_seek(self: Pin<&mut Self>, _position: io::SeekFrom) -> io::Result<()> {
+        // Reads always return the next events, wherever the cursor is
+        Ok(())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Poll::Ready(Ok(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+    use virtual_fs::{mem_fs, FileSystem};
+
+    #[tokio::test]
+    async fn events_are_encoded_as_records() {
+        let fs = mem_fs::FileSystem::default();
+        fs.create_dir(Path::new("/app")).unwrap();
+
+        let mut file = WatchFile::new();
+        let wd = file.add(
+            PathBuf::from("/app"),
+            fs.watch(Path::new("/app"), false).unwrap(),
+        );
+        fs.new_open_options()
+            .create(true)
+            .write(true)
+            .open("/app/index.html")
+            .unwrap();
+
+        let mut buf = [0u8; 64];
+        let read = file.read(&mut buf).await.unwrap();
+
+        let mut expected = Vec::new();
+        expected.extend_from_slice(&wd.to_le_bytes());
+        expected.extend_from_slice(&WATCH_CREATE.to_le_bytes());
+        expected.extend_from_slice(&0u32.to_le_bytes());
+        expected.extend_from_slice(&10u32.to_le_bytes());
+        expected.extend_from_slice(b"index.html");
+        assert_eq!(&buf[..read], expected.as_slice());
+
+        assert!(file.remove(wd));
+        assert!(!file.remove(wd), "the watch is already gone");
+    }
+}
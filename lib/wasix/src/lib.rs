@@ -85,6 +85,7 @@ pub use crate::{
     os::{
         task::{
             control_plane::WasiControlPlane,
+            limits::{ResourceLimit, ResourceLimits},
             process::{WasiProcess, WasiProcessId},
             thread::{WasiThread, WasiThreadError, WasiThreadHandle, WasiThreadId},
         },
@@ -93,8 +94,8 @@ pub use crate::{
     rewind::*,
     runtime::{task_manager::VirtualTaskManager, PluggableRuntime, Runtime},
     state::{
-        WasiEnv, WasiEnvBuilder, WasiEnvInit, WasiFunctionEnv, WasiInstanceHandles,
-        WasiStateCreationError, ALL_RIGHTS,
+        ProcessSnapshot, SnapshotError, WasiEnv, WasiEnvBuilder, WasiEnvInit, WasiFunctionEnv,
+        WasiInstanceHandles, WasiStateCreationError, ALL_RIGHTS,
     },
     syscalls::{rewind, rewind_ext, types, unwind},
     utils::is_wasix_module,
@@ -105,6 +106,9 @@ pub use crate::{
     },
 };
 
+#[cfg(feature = "sys-tunables")]
+pub use crate::os::task::limits::LimitingTunables;
+
 /// This is returned in `RuntimeError`.
 /// Use `downcast` or `downcast_ref` to retrieve the `ExitCode`.
 #[derive(Error, Debug)]
@@ -200,6 +204,8 @@ pub enum WasiRuntimeError {
     Runtime(#[from] RuntimeError),
     #[error("Memory access error")]
     Thread(#[from] WasiThreadError),
+    #[error("Unable to restore the snapshot")]
+    Snapshot(#[from] SnapshotError),
 }
 
 impl WasiRuntimeError {
@@ -453,6 +459,17 @@ fn wasix_exports_32(mut store: &mut impl AsStoreMut, env: &FunctionEnv<WasiEnv>)
         "fd_tell" => Function::new_typed_with_env(&mut store, env, fd_tell::<Memory32>),
         "fd_write" => Function::new_typed_with_env(&mut store, env, fd_write::<Memory32>),
         "fd_pipe" => Function::new_typed_with_env(&mut store, env, fd_pipe::<Memory32>),
+        "fd_watch_create" => Function::new_typed_with_env(&mut store, env, fd_watch_create::<Memory32>),
+        "fd_watch_add" => Function::new_typed_with_env(&mut store, env, fd_watch_add::<Memory32>),
+        "fd_watch_remove" => Function::new_typed_with_env(&mut store, env, fd_watch_remove),
+        "fd_filestat_set_mode" => Function::new_typed_with_env(&mut store, env, fd_filestat_set_mode),
+        "path_filestat_set_mode" => Function::new_typed_with_env(&mut store, env, path_filestat_set_mode::<Memory32>),
+        "fd_flock" => Function::new_typed_with_env(&mut store, env, fd_flock),
+        "fd_lock" => Function::new_typed_with_env(&mut store, env, fd_lock),
+        "fd_lock_get" => Function::new_typed_with_env(&mut store, env, fd_lock_get::<Memory32>),
+        "fd_mmap" => Function::new_typed_with_env(&mut store, env, fd_mmap::<Memory32>),
+        "fd_msync" => Function::new_typed_with_env(&mut store, env, fd_msync::<Memory32>),
+        "fd_munmap" => Function::new_typed_with_env(&mut store, env, fd_munmap::<Memory32>),
         "path_create_directory" => Function::new_typed_with_env(&mut store, env, path_create_directory::<Memory32>),
         "path_filestat_get" => Function::new_typed_with_env(&mut store, env, path_filestat_get::<Memory32>),
         "path_filestat_set_times" => Function::new_typed_with_env(&mut store, env, path_filestat_set_times::<Memory32>),
@@ -468,6 +485,17 @@ fn wasix_exports_32(mut store: &mut impl AsStoreMut, env: &FunctionEnv<WasiEnv>)
         "proc_fork" => Function::new_typed_with_env(&mut store, env, proc_fork::<Memory32>),
         "proc_join" => Function::new_typed_with_env(&mut store, env, proc_join::<Memory32>),
         "proc_signal" => Function::new_typed_with_env(&mut store, env, proc_signal::<Memory32>),
+        "proc_signal_group" => Function::new_typed_with_env(&mut store, env, proc_signal_group::<Memory32>),
+        "proc_setpgid" => Function::new_typed_with_env(&mut store, env, proc_setpgid::<Memory32>),
+        "proc_getpgid" => Function::new_typed_with_env(&mut store, env, proc_getpgid::<Memory32>),
+        "proc_setsid" => Function::new_typed_with_env(&mut store, env, proc_setsid::<Memory32>),
+        "proc_getsid" => Function::new_typed_with_env(&mut store, env, proc_getsid::<Memory32>),
+        "proc_snapshot" => Function::new_typed_with_env(&mut store, env, proc_snapshot::<Memory32>),
+        "proc_rlimit_get" => Function::new_typed_with_env(&mut store, env, proc_rlimit_get::<Memory32>),
+        "proc_rlimit_set" => Function::new_typed_with_env(&mut store, env, proc_rlimit_set::<Memory32>),
+        "proc_sigaction" => Function::new_typed_with_env(&mut store, env, proc_sigaction::<Memory32>),
+        "proc_sigpending" => Function::new_typed_with_env(&mut store, env, proc_sigpending::<Memory32>),
+        "proc_sigsuspend" => Function::new_typed_with_env(&mut store, env, proc_sigsuspend::<Memory32>),
         "proc_exec" => Function::new_typed_with_env(&mut store, env, proc_exec::<Memory32>),
         "proc_raise" => Function::new_typed_with_env(&mut store, env, proc_raise),
         "proc_raise_interval" => Function::new_typed_with_env(&mut store, env, proc_raise_interval),
@@ -477,6 +505,13 @@ fn wasix_exports_32(mut store: &mut impl AsStoreMut, env: &FunctionEnv<WasiEnv>)
         "random_get" => Function::new_typed_with_env(&mut store, env, random_get::<Memory32>),
         "tty_get" => Function::new_typed_with_env(&mut store, env, tty_get::<Memory32>),
         "tty_set" => Function::new_typed_with_env(&mut store, env, tty_set::<Memory32>),
+        "tty_getpgrp" => Function::new_typed_with_env(&mut store, env, tty_getpgrp::<Memory32>),
+        "tty_setpgrp" => Function::new_typed_with_env(&mut store, env, tty_setpgrp::<Memory32>),
+        "pty_open" => Function::new_typed_with_env(&mut store, env, pty_open::<Memory32>),
+        "pty_get" => Function::new_typed_with_env(&mut store, env, pty_get::<Memory32>),
+        "pty_set" => Function::new_typed_with_env(&mut store, env, pty_set::<Memory32>),
+        "pty_getpgrp" => Function::new_typed_with_env(&mut store, env, pty_getpgrp::<Memory32>),
+        "pty_setpgrp" => Function::new_typed_with_env(&mut store, env, pty_setpgrp::<Memory32>),
         "getcwd" => Function::new_typed_with_env(&mut store, env, getcwd::<Memory32>),
         "chdir" => Function::new_typed_with_env(&mut store, env, chdir::<Memory32>),
         "callback_signal" => Function::new_typed_with_env(&mut store, env, callback_signal::<Memory32>),
@@ -485,6 +520,9 @@ fn wasix_exports_32(mut store: &mut impl AsStoreMut, env: &FunctionEnv<WasiEnv>)
         "thread_sleep" => Function::new_typed_with_env(&mut store, env, thread_sleep::<Memory32>),
         "thread_id" => Function::new_typed_with_env(&mut store, env, thread_id::<Memory32>),
         "thread_signal" => Function::new_typed_with_env(&mut store, env, thread_signal),
+        "thread_sigmask" => Function::new_typed_with_env(&mut store, env, thread_sigmask::<Memory32>),
+        "thread_sigaltstack" => Function::new_typed_with_env(&mut store, env, thread_sigaltstack::<Memory32>),
+        "thread_siginfo_get" => Function::new_typed_with_env(&mut store, env, thread_siginfo_get::<Memory32>),
         "thread_join" => Function::new_typed_with_env(&mut store, env, thread_join::<Memory32>),
         "thread_parallelism" => Function::new_typed_with_env(&mut store, env, thread_parallelism::<Memory32>),
         "thread_exit" => Function::new_typed_with_env(&mut store, env, thread_exit),
@@ -509,7 +547,9 @@ fn wasix_exports_32(mut store: &mut impl AsStoreMut, env: &FunctionEnv<WasiEnv>)
         "port_route_list" => Function::new_typed_with_env(&mut store, env, port_route_list::<Memory32>),
         "sock_status" => Function::new_typed_with_env(&mut store, env, sock_status::<Memory32>),
         "sock_addr_local" => Function::new_typed_with_env(&mut store, env, sock_addr_local::<Memory32>),
+        "sock_addr_local_unix" => Function::new_typed_with_env(&mut store, env, sock_addr_local_unix::<Memory32>),
         "sock_addr_peer" => Function::new_typed_with_env(&mut store, env, sock_addr_peer::<Memory32>),
+        "sock_addr_peer_unix" => Function::new_typed_with_env(&mut store, env, sock_addr_peer_unix::<Memory32>),
         "sock_open" => Function::new_typed_with_env(&mut store, env, sock_open::<Memory32>),
         "sock_set_opt_flag" => Function::new_typed_with_env(&mut store, env, sock_set_opt_flag),
         "sock_get_opt_flag" => Function::new_typed_with_env(&mut store, env, sock_get_opt_flag::<Memory32>),
@@ -522,10 +562,12 @@ fn wasix_exports_32(mut store: &mut impl AsStoreMut, env: &FunctionEnv<WasiEnv>)
         "sock_join_multicast_v6" => Function::new_typed_with_env(&mut store, env, sock_join_multicast_v6::<Memory32>),
         "sock_leave_multicast_v6" => Function::new_typed_with_env(&mut store, env, sock_leave_multicast_v6::<Memory32>),
         "sock_bind" => Function::new_typed_with_env(&mut store, env, sock_bind::<Memory32>),
+        "sock_bind_unix" => Function::new_typed_with_env(&mut store, env, sock_bind_unix::<Memory32>),
         "sock_listen" => Function::new_typed_with_env(&mut store, env, sock_listen::<Memory32>),
         "sock_accept" => Function::new_typed_with_env(&mut store, env, sock_accept_v2::<Memory32>),
         "sock_accept_v2" => Function::new_typed_with_env(&mut store, env, sock_accept_v2::<Memory32>),
         "sock_connect" => Function::new_typed_with_env(&mut store, env, sock_connect::<Memory32>),
+        "sock_connect_unix" => Function::new_typed_with_env(&mut store, env, sock_connect_unix::<Memory32>),
         "sock_recv" => Function::new_typed_with_env(&mut store, env, sock_recv::<Memory32>),
         "sock_recv_from" => Function::new_typed_with_env(&mut store, env, sock_recv_from::<Memory32>),
         "sock_send" => Function::new_typed_with_env(&mut store, env, sock_send::<Memory32>),
@@ -574,6 +616,17 @@ fn wasix_exports_64(mut store: &mut impl AsStoreMut, env: &FunctionEnv<WasiEnv>)
         "fd_tell" => Function::new_typed_with_env(&mut store, env, fd_tell::<Memory64>),
         "fd_write" => Function::new_typed_with_env(&mut store, env, fd_write::<Memory64>),
         "fd_pipe" => Function::new_typed_with_env(&mut store, env, fd_pipe::<Memory64>),
+        "fd_watch_create" => Function::new_typed_with_env(&mut store, env, fd_watch_create::<Memory64>),
+        "fd_watch_add" => Function::new_typed_with_env(&mut store, env, fd_watch_add::<Memory64>),
+        "fd_watch_remove" => Function::new_typed_with_env(&mut store, env, fd_watch_remove),
+        "fd_filestat_set_mode" => Function::new_typed_with_env(&mut store, env, fd_filestat_set_mode),
+        "path_filestat_set_mode" => Function::new_typed_with_env(&mut store, env, path_filestat_set_mode::<Memory64>),
+        "fd_flock" => Function::new_typed_with_env(&mut store, env, fd_flock),
+        "fd_lock" => Function::new_typed_with_env(&mut store, env, fd_lock),
+        "fd_lock_get" => Function::new_typed_with_env(&mut store, env, fd_lock_get::<Memory64>),
+        "fd_mmap" => Function::new_typed_with_env(&mut store, env, fd_mmap::<Memory64>),
+        "fd_msync" => Function::new_typed_with_env(&mut store, env, fd_msync::<Memory64>),
+        "fd_munmap" => Function::new_typed_with_env(&mut store, env, fd_munmap::<Memory64>),
         "path_create_directory" => Function::new_typed_with_env(&mut store, env, path_create_directory::<Memory64>),
         "path_filestat_get" => Function::new_typed_with_env(&mut store, env, path_filestat_get::<Memory64>),
         "path_filestat_set_times" => Function::new_typed_with_env(&mut store, env, path_filestat_set_times::<Memory64>),
@@ -589,6 +642,17 @@ fn wasix_exports_64(mut store: &mut impl AsStoreMut, env: &FunctionEnv<WasiEnv>)
         "proc_fork" => Function::new_typed_with_env(&mut store, env, proc_fork::<Memory64>),
         "proc_join" => Function::new_typed_with_env(&mut store, env, proc_join::<Memory64>),
         "proc_signal" => Function::new_typed_with_env(&mut store, env, proc_signal::<Memory64>),
+        "proc_signal_group" => Function::new_typed_with_env(&mut store, env, proc_signal_group::<Memory64>),
+        "proc_setpgid" => Function::new_typed_with_env(&mut store, env, proc_setpgid::<Memory64>),
+        "proc_getpgid" => Function::new_typed_with_env(&mut store, env, proc_getpgid::<Memory64>),
+        "proc_setsid" => Function::new_typed_with_env(&mut store, env, proc_setsid::<Memory64>),
+        "proc_getsid" => Function::new_typed_with_env(&mut store, env, proc_getsid::<Memory64>),
+        "proc_snapshot" => Function::new_typed_with_env(&mut store, env, proc_snapshot::<Memory64>),
+        "proc_rlimit_get" => Function::new_typed_with_env(&mut store, env, proc_rlimit_get::<Memory64>),
+        "proc_rlimit_set" => Function::new_typed_with_env(&mut store, env, proc_rlimit_set::<Memory64>),
+        "proc_sigaction" => Function::new_typed_with_env(&mut store, env, proc_sigaction::<Memory64>),
+        "proc_sigpending" => Function::new_typed_with_env(&mut store, env, proc_sigpending::<Memory64>),
+        "proc_sigsuspend" => Function::new_typed_with_env(&mut store, env, proc_sigsuspend::<Memory64>),
         "proc_exec" => Function::new_typed_with_env(&mut store, env, proc_exec::<Memory64>),
         "proc_raise" => Function::new_typed_with_env(&mut store, env, proc_raise),
         "proc_raise_interval" => Function::new_typed_with_env(&mut store, env, proc_raise_interval),
@@ -598,6 +662,13 @@ fn wasix_exports_64(mut store: &mut impl AsStoreMut, env: &FunctionEnv<WasiEnv>)
         "random_get" => Function::new_typed_with_env(&mut store, env, random_get::<Memory64>),
         "tty_get" => Function::new_typed_with_env(&mut store, env, tty_get::<Memory64>),
         "tty_set" => Function::new_typed_with_env(&mut store, env, tty_set::<Memory64>),
+        "tty_getpgrp" => Function::new_typed_with_env(&mut store, env, tty_getpgrp::<Memory64>),
+        "tty_setpgrp" => Function::new_typed_with_env(&mut store, env, tty_setpgrp::<Memory64>),
+        "pty_open" => Function::new_typed_with_env(&mut store, env, pty_open::<Memory64>),
+        "pty_get" => Function::new_typed_with_env(&mut store, env, pty_get::<Memory64>),
+        "pty_set" => Function::new_typed_with_env(&mut store, env, pty_set::<Memory64>),
+        "pty_getpgrp" => Function::new_typed_with_env(&mut store, env, pty_getpgrp::<Memory64>),
+        "pty_setpgrp" => Function::new_typed_with_env(&mut store, env, pty_setpgrp::<Memory64>),
         "getcwd" => Function::new_typed_with_env(&mut store, env, getcwd::<Memory64>),
         "chdir" => Function::new_typed_with_env(&mut store, env, chdir::<Memory64>),
         "callback_signal" => Function::new_typed_with_env(&mut store, env, callback_signal::<Memory64>),
@@ -606,6 +677,9 @@ fn wasix_exports_64(mut store: &mut impl AsStoreMut, env: &FunctionEnv<WasiEnv>)
         "thread_sleep" => Function::new_typed_with_env(&mut store, env, thread_sleep::<Memory64>),
         "thread_id" => Function::new_typed_with_env(&mut store, env, thread_id::<Memory64>),
         "thread_signal" => Function::new_typed_with_env(&mut store, env, thread_signal),
+        "thread_sigmask" => Function::new_typed_with_env(&mut store, env, thread_sigmask::<Memory64>),
+        "thread_sigaltstack" => Function::new_typed_with_env(&mut store, env, thread_sigaltstack::<Memory64>),
+        "thread_siginfo_get" => Function::new_typed_with_env(&mut store, env, thread_siginfo_get::<Memory64>),
         "thread_join" => Function::new_typed_with_env(&mut store, env, thread_join::<Memory64>),
         "thread_parallelism" => Function::new_typed_with_env(&mut store, env, thread_parallelism::<Memory64>),
         "thread_exit" => Function::new_typed_with_env(&mut store, env, thread_exit),
@@ -630,7 +704,9 @@ fn wasix_exports_64(mut store: &mut impl AsStoreMut, env: &FunctionEnv<WasiEnv>)
         "port_route_list" => Function::new_typed_with_env(&mut store, env, port_route_list::<Memory64>),
         "sock_status" => Function::new_typed_with_env(&mut store, env, sock_status::<Memory64>),
         "sock_addr_local" => Function::new_typed_with_env(&mut store, env, sock_addr_local::<Memory64>),
+        "sock_addr_local_unix" => Function::new_typed_with_env(&mut store, env, sock_addr_local_unix::<Memory64>),
         "sock_addr_peer" => Function::new_typed_with_env(&mut store, env, sock_addr_peer::<Memory64>),
+        "sock_addr_peer_unix" => Function::new_typed_with_env(&mut store, env, sock_addr_peer_unix::<Memory64>),
         "sock_open" => Function::new_typed_with_env(&mut store, env, sock_open::<Memory64>),
         "sock_set_opt_flag" => Function::new_typed_with_env(&mut store, env, sock_set_opt_flag),
         "sock_get_opt_flag" => Function::new_typed_with_env(&mut store, env, sock_get_opt_flag::<Memory64>),
@@ -643,10 +719,12 @@ fn wasix_exports_64(mut store: &mut impl AsStoreMut, env: &FunctionEnv<WasiEnv>)
         "sock_join_multicast_v6" => Function::new_typed_with_env(&mut store, env, sock_join_multicast_v6::<Memory64>),
         "sock_leave_multicast_v6" => Function::new_typed_with_env(&mut store, env, sock_leave_multicast_v6::<Memory64>),
         "sock_bind" => Function::new_typed_with_env(&mut store, env, sock_bind::<Memory64>),
+        "sock_bind_unix" => Function::new_typed_with_env(&mut store, env, sock_bind_unix::<Memory64>),
         "sock_listen" => Function::new_typed_with_env(&mut store, env, sock_listen::<Memory64>),
         "sock_accept" => Function::new_typed_with_env(&mut store, env, sock_accept_v2::<Memory64>),
         "sock_accept_v2" => Function::new_typed_with_env(&mut store, env, sock_accept_v2::<Memory64>),
         "sock_connect" => Function::new_typed_with_env(&mut store, env, sock_connect::<Memory64>),
+        "sock_connect_unix" => Function::new_typed_with_env(&mut store, env, sock_connect_unix::<Memory64>),
         "sock_recv" => Function::new_typed_with_env(&mut store, env, sock_recv::<Memory64>),
         "sock_recv_from" => Function::new_typed_with_env(&mut store, env, sock_recv_from::<Memory64>),
         "sock_send" => Function::new_typed_with_env(&mut store, env, sock_send::<Memory64>),
@@ -1,6 +1,7 @@
 use std::{
     intrinsics::transmute,
     net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::Path,
     time::Duration,
 };
 
@@ -17,6 +18,7 @@ use wasmer_wasix_types::{
 };
 
 pub mod socket;
+pub mod unix;
 
 #[allow(dead_code)]
 pub(crate) fn read_ip<M: MemorySize>(
@@ -234,6 +236,34 @@ pub(crate) fn write_ip_port<M: MemorySize>(
     Ok(())
 }
 
+/// Writes the path of a Unix socket into a buffer of `max_path_len` bytes,
+/// the length of the path is written back into `path_len` and is zero for
+/// unnamed sockets.
+pub(crate) fn write_unix_path<M: MemorySize>(
+    memory: &MemoryView,
+    path_ptr: WasmPtr<u8, M>,
+    path_len: WasmPtr<M::Offset, M>,
+    path: Option<&Path>,
+) -> Result<(), Errno> {
+    let path = path
+        .map(|path| path.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let path = path.as_bytes();
+
+    let path_len = path_len.deref(memory);
+    let max_path_len: u64 = path_len.read().map_err(crate::mem_error_to_wasi)?.into();
+    let len: M::Offset = path.len().try_into().map_err(|_| Errno::Overflow)?;
+    path_len.write(len).map_err(crate::mem_error_to_wasi)?;
+    if path.len() as u64 > max_path_len {
+        return Err(Errno::Range);
+    }
+
+    path_ptr
+        .slice(memory, len)
+        .and_then(|slice| slice.write_slice(path))
+        .map_err(crate::mem_error_to_wasi)
+}
+
 #[allow(dead_code)]
 pub(crate) fn read_route<M: MemorySize>(
     memory: &MemoryView,
@@ -2,6 +2,7 @@ use std::{
     future::Future,
     mem::MaybeUninit,
     net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::PathBuf,
     pin::Pin,
     sync::{Arc, RwLock},
     task::Poll,
@@ -21,8 +22,15 @@ use virtual_net::{
 use wasmer_types::MemorySize;
 use wasmer_wasix_types::wasi::{Addressfamily, Errno, Rights, SockProto, Sockoption, Socktype};
 
+use super::unix::{
+    SocketFile, UnixDatagram, UnixListener, UnixSocketBinding, UnixSocketRegistry, UnixStream,
+};
 use crate::{net::net_error_into_wasi_err, VirtualTaskManager};
 
+/// The socket that [`InodeSocket::bind`] is waiting for
+type BindFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<InodeSocketKind, NetworkError>> + Send + 'a>>;
+
 #[derive(Debug)]
 #[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
 pub enum InodeHttpSocketType {
@@ -42,6 +50,7 @@ pub enum InodeSocketKind {
         ty: Socktype,
         pt: SockProto,
         addr: Option<SocketAddr>,
+        unix_path: Option<UnixSocketBinding>,
         only_v6: bool,
         reuse_port: bool,
         reuse_addr: bool,
@@ -67,6 +76,18 @@ pub enum InodeSocketKind {
         socket: Box<dyn VirtualUdpSocket + Sync>,
         peer: Option<SocketAddr>,
     },
+    UnixListener {
+        socket: UnixListener,
+        accept_timeout: Option<Duration>,
+    },
+    UnixStream {
+        socket: UnixStream,
+        write_timeout: Option<Duration>,
+        read_timeout: Option<Duration>,
+    },
+    UnixDatagram {
+        socket: UnixDatagram,
+    },
 }
 
 pub enum WasiSocketOption {
@@ -183,7 +204,7 @@ pub struct InodeSocket {
 impl InodeSocket {
     pub fn new(kind: InodeSocketKind) -> Self {
         let handler_state: StatefulHandlerState = Default::default();
-        if let InodeSocketKind::TcpStream { .. } = &kind {
+        if let InodeSocketKind::TcpStream { .. } | InodeSocketKind::UnixStream { .. } = &kind {
             handler_state.set(InterestType::Writable);
         }
         Self {
@@ -210,12 +231,13 @@ impl InodeSocket {
             .flatten()
             .unwrap_or(Duration::from_secs(30));
 
-        let socket = {
+        let socket: BindFuture<'_> = {
             let mut inner = self.inner.protected.write().unwrap();
             match &mut inner.kind {
                 InodeSocketKind::PreSocket {
                     family,
                     ty,
+                    pt,
                     addr,
                     reuse_port,
                     reuse_addr,
@@ -257,7 +279,26 @@ impl InodeSocket {
                             let reuse_addr = *reuse_addr;
                             drop(inner);
 
-                            net.bind_udp(addr, reuse_port, reuse_addr)
+                            Box::pin(async move {
+                                net.bind_udp(addr, reuse_port, reuse_addr)
+                                    .await
+                                    .map(|socket| InodeSocketKind::UdpSocket { socket, peer: None })
+                            })
+                        }
+                        Socktype::Raw => {
+                            // ICMP sockets are bound to an address while other raw
+                            // sockets see all the frames of the interface
+                            let pt = *pt;
+                            drop(inner);
+
+                            Box::pin(async move {
+                                match pt {
+                                    SockProto::Icmp | SockProto::Icmpv6 => {
+                                        net.bind_icmp(addr.ip()).await.map(InodeSocketKind::Icmp)
+                                    }
+                                    _ => net.bind_raw().await.map(InodeSocketKind::Raw),
+                                }
+                            })
                         }
                         _ => return Err(Errno::Inval),
                     }
@@ -269,7 +310,7 @@ impl InodeSocket {
         tokio::select! {
             socket = socket => {
                 let socket = socket.map_err(net_error_into_wasi_err)?;
-                Ok(Some(InodeSocket::new(InodeSocketKind::UdpSocket { socket, peer: None })))
+                Ok(Some(InodeSocket::new(socket)))
             },
             _ = tasks.sleep_now(timeout) => Err(Errno::Timedout)
         }
@@ -279,7 +320,7 @@ impl InodeSocket {
         &self,
         tasks: &dyn VirtualTaskManager,
         net: &dyn VirtualNetworking,
-        _backlog: usize,
+        backlog: usize,
     ) -> Result<Option<InodeSocket>, Errno> {
         let timeout = self
             .opt_time(TimeType::AcceptTimeout)
@@ -290,6 +331,24 @@ impl InodeSocket {
         let socket = {
             let inner = self.inner.protected.read().unwrap();
             match &inner.kind {
+                InodeSocketKind::PreSocket {
+                    family: Addressfamily::Unix,
+                    ty: Socktype::Stream,
+                    unix_path,
+                    ..
+                } => {
+                    let socket = match unix_path {
+                        Some(binding) => binding.listen(backlog),
+                        None => {
+                            tracing::warn!("wasi[?]::sock_listen - failed - path not set");
+                            return Err(Errno::Inval);
+                        }
+                    };
+                    return Ok(Some(InodeSocket::new(InodeSocketKind::UnixListener {
+                        socket,
+                        accept_timeout: Some(timeout),
+                    })));
+                }
                 InodeSocketKind::PreSocket {
                     ty,
                     addr,
@@ -340,7 +399,7 @@ impl InodeSocket {
         tasks: &dyn VirtualTaskManager,
         nonblocking: bool,
         timeout: Option<Duration>,
-    ) -> Result<(Box<dyn VirtualTcpSocket + Sync>, SocketAddr), Errno> {
+    ) -> Result<(InodeSocketKind, Option<SocketAddr>), Errno> {
         struct SocketAccepter<'a> {
             sock: &'a InodeSocket,
             nonblocking: bool,
@@ -355,33 +414,55 @@ impl InodeSocket {
             }
         }
         impl<'a> Future for SocketAccepter<'a> {
-            type Output = Result<(Box<dyn VirtualTcpSocket + Sync>, SocketAddr), Errno>;
+            type Output = Result<(InodeSocketKind, Option<SocketAddr>), Errno>;
             fn poll(
                 mut self: Pin<&mut Self>,
                 cx: &mut std::task::Context<'_>,
             ) -> std::task::Poll<Self::Output> {
                 loop {
                     let mut inner = self.sock.inner.protected.write().unwrap();
-                    return match &mut inner.kind {
-                        InodeSocketKind::TcpListener { socket, .. } => match socket.try_accept() {
-                            Ok((child, addr)) => Poll::Ready(Ok((child, addr))),
-                            Err(NetworkError::WouldBlock) if self.nonblocking => {
-                                Poll::Ready(Err(Errno::Again))
-                            }
-                            Err(NetworkError::WouldBlock) if !self.handler_registered => {
-                                let res = socket.set_handler(cx.waker().into());
-                                if let Err(err) = res {
-                                    return Poll::Ready(Err(net_error_into_wasi_err(err)));
-                                }
-                                drop(inner);
-                                self.handler_registered = true;
-                                continue;
+                    let res = match &mut inner.kind {
+                        InodeSocketKind::TcpListener { socket, .. } => {
+                            socket.try_accept().map(|(child, addr)| {
+                                let kind = InodeSocketKind::TcpStream {
+                                    socket: child,
+                                    write_timeout: None,
+                                    read_timeout: None,
+                                };
+                                (kind, Some(addr))
+                            })
+                        }
+                        InodeSocketKind::UnixListener { socket, .. } => {
+                            socket.try_accept().map(|child| {
+                                let kind = InodeSocketKind::UnixStream {
+                                    socket: child,
+                                    write_timeout: None,
+                                    read_timeout: None,
+                                };
+                                (kind, None)
+                            })
+                        }
+                        InodeSocketKind::PreSocket { .. } => {
+                            return Poll::Ready(Err(Errno::Notconn))
+                        }
+                        _ => return Poll::Ready(Err(Errno::Notsup)),
+                    };
+                    return match res {
+                        Ok(accepted) => Poll::Ready(Ok(accepted)),
+                        Err(NetworkError::WouldBlock) if self.nonblocking => {
+                            Poll::Ready(Err(Errno::Again))
+                        }
+                        Err(NetworkError::WouldBlock) if !self.handler_registered => {
+                            let res = inner.set_handler(cx.waker().into());
+                            if let Err(err) = res {
+                                return Poll::Ready(Err(net_error_into_wasi_err(err)));
                             }
-                            Err(NetworkError::WouldBlock) => Poll::Pending,
-                            Err(err) => Poll::Ready(Err(net_error_into_wasi_err(err))),
-                        },
-                        InodeSocketKind::PreSocket { .. } => Poll::Ready(Err(Errno::Notconn)),
-                        _ => Poll::Ready(Err(Errno::Notsup)),
+                            drop(inner);
+                            self.handler_registered = true;
+                            continue;
+                        }
+                        Err(NetworkError::WouldBlock) => Poll::Pending,
+                        Err(err) => Poll::Ready(Err(net_error_into_wasi_err(err))),
                     };
                 }
             }
@@ -412,6 +493,13 @@ impl InodeSocket {
             InodeSocketKind::Icmp(_) => {}
             InodeSocketKind::UdpSocket { .. } => {}
             InodeSocketKind::Raw(_) => {}
+            InodeSocketKind::UnixListener { .. } => {}
+            InodeSocketKind::UnixStream { socket, .. } => {
+                socket
+                    .shutdown(std::net::Shutdown::Both)
+                    .map_err(net_error_into_wasi_err)?;
+            }
+            InodeSocketKind::UnixDatagram { .. } => {}
             InodeSocketKind::PreSocket { .. } => return Err(Errno::Notconn),
         };
         Ok(())
@@ -432,6 +520,10 @@ impl InodeSocket {
         let connect = {
             let mut inner = self.inner.protected.write().unwrap();
             match &mut inner.kind {
+                InodeSocketKind::PreSocket {
+                    family: Addressfamily::Unix,
+                    ..
+                } => return Err(Errno::Afnosupport),
                 InodeSocketKind::PreSocket {
                     ty,
                     addr,
@@ -480,6 +572,158 @@ impl InodeSocket {
         })))
     }
 
+    /// Binds a Unix socket to `file`, found at `path`. The caller is
+    /// responsible for creating the file that represents it.
+    pub fn bind_unix(
+        &self,
+        registry: &UnixSocketRegistry,
+        file: SocketFile,
+        path: PathBuf,
+    ) -> Result<Option<InodeSocket>, Errno> {
+        let mut inner = self.inner.protected.write().unwrap();
+        match &mut inner.kind {
+            InodeSocketKind::PreSocket {
+                family: Addressfamily::Unix,
+                ty,
+                unix_path,
+                ..
+            } => match *ty {
+                _ if unix_path.is_some() => Err(Errno::Inval),
+                Socktype::Stream => {
+                    let binding = registry
+                        .bind_stream(file, path)
+                        .map_err(net_error_into_wasi_err)?;
+                    unix_path.replace(binding);
+                    Ok(None)
+                }
+                Socktype::Dgram => Ok(Some(InodeSocket::new(InodeSocketKind::UnixDatagram {
+                    socket: registry
+                        .bind_datagram(file, path)
+                        .map_err(net_error_into_wasi_err)?,
+                }))),
+                _ => Err(Errno::Inval),
+            },
+            InodeSocketKind::PreSocket { .. } => Err(Errno::Afnosupport),
+            _ => Err(Errno::Inval),
+        }
+    }
+
+    /// Connects a Unix socket to the socket bound to `file`, found at `peer`,
+    /// or to the `host` socket when the connection is forwarded to the host.
+    pub async fn connect_unix(
+        &mut self,
+        tasks: &dyn VirtualTaskManager,
+        registry: &UnixSocketRegistry,
+        peer: PathBuf,
+        file: Option<SocketFile>,
+        host: Option<PathBuf>,
+    ) -> Result<Option<InodeSocket>, Errno> {
+        // Files that aren't numbered by their file system can't have a
+        // socket bound to them
+        let file = file.ok_or(NetworkError::ConnectionRefused);
+        let mut inner = self.inner.protected.write().unwrap();
+        match &mut inner.kind {
+            InodeSocketKind::PreSocket {
+                family: Addressfamily::Unix,
+                ty: Socktype::Stream,
+                unix_path,
+                write_timeout,
+                read_timeout,
+                ..
+            } => {
+                let local = unix_path
+                    .as_ref()
+                    .map(|binding| binding.path().to_path_buf());
+                let socket = match host {
+                    #[cfg(all(unix, feature = "host-vnet"))]
+                    Some(host) => super::unix::connect_host(tasks, &host, local, peer),
+                    #[cfg(not(all(unix, feature = "host-vnet")))]
+                    Some(_) => {
+                        let _ = tasks;
+                        Err(NetworkError::Unsupported)
+                    }
+                    None => file.and_then(|file| registry.connect(file, &peer, local)),
+                }
+                .map_err(net_error_into_wasi_err)?;
+
+                Ok(Some(InodeSocket::new(InodeSocketKind::UnixStream {
+                    socket,
+                    write_timeout: *write_timeout,
+                    read_timeout: *read_timeout,
+                })))
+            }
+            InodeSocketKind::PreSocket {
+                family: Addressfamily::Unix,
+                ty: Socktype::Dgram,
+                ..
+            } => {
+                let mut socket = UnixDatagram::new(None);
+                socket
+                    .connect(registry, file.map_err(net_error_into_wasi_err)?, peer)
+                    .map_err(net_error_into_wasi_err)?;
+                Ok(Some(InodeSocket::new(InodeSocketKind::UnixDatagram {
+                    socket,
+                })))
+            }
+            InodeSocketKind::UnixDatagram { socket } => {
+                socket
+                    .connect(registry, file.map_err(net_error_into_wasi_err)?, peer)
+                    .map_err(net_error_into_wasi_err)?;
+                Ok(None)
+            }
+            InodeSocketKind::PreSocket {
+                family: Addressfamily::Unix,
+                ..
+            } => Err(Errno::Notsup),
+            InodeSocketKind::PreSocket { .. } => Err(Errno::Afnosupport),
+            _ => Err(Errno::Isconn),
+        }
+    }
+
+    /// Returns the path a Unix socket is bound to, [`None`] for unnamed
+    /// sockets.
+    pub fn addr_local_unix(&self) -> Result<Option<PathBuf>, Errno> {
+        let inner = self.inner.protected.read().unwrap();
+        Ok(match &inner.kind {
+            InodeSocketKind::PreSocket {
+                family: Addressfamily::Unix,
+                unix_path,
+                ..
+            } => unix_path
+                .as_ref()
+                .map(|binding| binding.path().to_path_buf()),
+            InodeSocketKind::UnixListener { socket, .. } => Some(socket.addr_local().to_path_buf()),
+            InodeSocketKind::UnixStream { socket, .. } => {
+                socket.addr_local().map(|path| path.to_path_buf())
+            }
+            InodeSocketKind::UnixDatagram { socket } => {
+                socket.addr_local().map(|path| path.to_path_buf())
+            }
+            _ => return Err(Errno::Afnosupport),
+        })
+    }
+
+    /// Returns the path of the socket a Unix socket is connected to, [`None`]
+    /// when that socket is unnamed.
+    pub fn addr_peer_unix(&self) -> Result<Option<PathBuf>, Errno> {
+        let inner = self.inner.protected.read().unwrap();
+        Ok(match &inner.kind {
+            InodeSocketKind::UnixStream { socket, .. } => {
+                socket.addr_peer().map(|path| path.to_path_buf())
+            }
+            InodeSocketKind::UnixDatagram { socket } => match socket.addr_peer() {
+                Some(path) => Some(path.to_path_buf()),
+                None => return Err(Errno::Notconn),
+            },
+            InodeSocketKind::PreSocket {
+                family: Addressfamily::Unix,
+                ..
+            }
+            | InodeSocketKind::UnixListener { .. } => return Err(Errno::Notconn),
+            _ => return Err(Errno::Afnosupport),
+        })
+    }
+
     pub fn status(&self) -> Result<WasiSocketStatus, Errno> {
         let inner = self.inner.protected.read().unwrap();
         Ok(match &inner.kind {
@@ -487,6 +731,9 @@ impl InodeSocket {
             InodeSocketKind::TcpListener { .. } => WasiSocketStatus::Opened,
             InodeSocketKind::TcpStream { .. } => WasiSocketStatus::Opened,
             InodeSocketKind::UdpSocket { .. } => WasiSocketStatus::Opened,
+            InodeSocketKind::UnixListener { .. } => WasiSocketStatus::Opened,
+            InodeSocketKind::UnixStream { .. } => WasiSocketStatus::Opened,
+            InodeSocketKind::UnixDatagram { .. } => WasiSocketStatus::Opened,
             _ => WasiSocketStatus::Failed,
         })
     }
@@ -734,6 +981,11 @@ impl InodeSocket {
                 write_timeout,
                 read_timeout,
                 ..
+            }
+            | InodeSocketKind::UnixStream {
+                write_timeout,
+                read_timeout,
+                ..
             } => {
                 match ty {
                     TimeType::WriteTimeout => *write_timeout = timeout,
@@ -742,7 +994,8 @@ impl InodeSocket {
                 }
                 Ok(())
             }
-            InodeSocketKind::TcpListener { accept_timeout, .. } => {
+            InodeSocketKind::TcpListener { accept_timeout, .. }
+            | InodeSocketKind::UnixListener { accept_timeout, .. } => {
                 match ty {
                     TimeType::AcceptTimeout => *accept_timeout = timeout,
                     _ => return Err(Errno::Inval),
@@ -776,12 +1029,18 @@ impl InodeSocket {
                 read_timeout,
                 write_timeout,
                 ..
+            }
+            | InodeSocketKind::UnixStream {
+                read_timeout,
+                write_timeout,
+                ..
             } => Ok(match ty {
                 TimeType::ReadTimeout => *read_timeout,
                 TimeType::WriteTimeout => *write_timeout,
                 _ => return Err(Errno::Inval),
             }),
-            InodeSocketKind::TcpListener { accept_timeout, .. } => Ok(match ty {
+            InodeSocketKind::TcpListener { accept_timeout, .. }
+            | InodeSocketKind::UnixListener { accept_timeout, .. } => Ok(match ty {
                 TimeType::AcceptTimeout => *accept_timeout,
                 _ => return Err(Errno::Inval),
             }),
@@ -928,6 +1187,8 @@ impl InodeSocket {
                     let res = match &mut inner.kind {
                         InodeSocketKind::Raw(socket) => socket.try_send(self.data),
                         InodeSocketKind::TcpStream { socket, .. } => socket.try_send(self.data),
+                        InodeSocketKind::UnixStream { socket, .. } => socket.try_send(self.data),
+                        InodeSocketKind::UnixDatagram { socket } => socket.try_send(self.data),
                         InodeSocketKind::UdpSocket { socket, peer } => {
                             if let Some(peer) = peer {
                                 socket.try_send_to(self.data, *peer)
@@ -1088,6 +1349,8 @@ impl InodeSocket {
                     let res = match &mut inner.kind {
                         InodeSocketKind::Raw(socket) => socket.try_recv(self.data),
                         InodeSocketKind::TcpStream { socket, .. } => socket.try_recv(self.data),
+                        InodeSocketKind::UnixStream { socket, .. } => socket.try_recv(self.data),
+                        InodeSocketKind::UnixDatagram { socket } => socket.try_recv(self.data),
                         InodeSocketKind::UdpSocket { socket, peer } => {
                             if let Some(peer) = peer {
                                 match socket.try_recv_from(self.data) {
@@ -1223,6 +1486,9 @@ impl InodeSocket {
             InodeSocketKind::TcpStream { socket, .. } => {
                 socket.shutdown(how).map_err(net_error_into_wasi_err)?;
             }
+            InodeSocketKind::UnixStream { socket, .. } => {
+                socket.shutdown(how).map_err(net_error_into_wasi_err)?;
+            }
             InodeSocketKind::PreSocket { .. } => return Err(Errno::Notconn),
             _ => return Err(Errno::Notsup),
         }
@@ -1235,6 +1501,8 @@ impl InodeSocket {
             match &mut guard.kind {
                 InodeSocketKind::TcpStream { .. }
                 | InodeSocketKind::UdpSocket { .. }
+                | InodeSocketKind::UnixStream { .. }
+                | InodeSocketKind::UnixDatagram { .. }
                 | InodeSocketKind::Raw(..) => true,
                 _ => false,
             }
@@ -1252,6 +1520,9 @@ impl InodeSocketProtected {
             InodeSocketKind::UdpSocket { socket, .. } => socket.remove_handler(),
             InodeSocketKind::Raw(socket) => socket.remove_handler(),
             InodeSocketKind::Icmp(socket) => socket.remove_handler(),
+            InodeSocketKind::UnixListener { socket, .. } => socket.remove_handler(),
+            InodeSocketKind::UnixStream { socket, .. } => socket.remove_handler(),
+            InodeSocketKind::UnixDatagram { socket } => socket.remove_handler(),
             InodeSocketKind::PreSocket { .. } => {}
         }
     }
@@ -1266,6 +1537,9 @@ impl InodeSocketProtected {
             InodeSocketKind::UdpSocket { socket, .. } => socket.set_handler(handler),
             InodeSocketKind::Raw(socket) => socket.set_handler(handler),
             InodeSocketKind::Icmp(socket) => socket.set_handler(handler),
+            InodeSocketKind::UnixListener { socket, .. } => socket.set_handler(handler),
+            InodeSocketKind::UnixStream { socket, .. } => socket.set_handler(handler),
+            InodeSocketKind::UnixDatagram { socket } => socket.set_handler(handler),
             InodeSocketKind::PreSocket { .. } => Err(virtual_net::NetworkError::NotConnected),
         }
     }
@@ -0,0 +1,850 @@
+//! Unix domain sockets shared by the processes of a control plane.
+//!
+//! Sockets are bound to paths of the virtual file system. Binding creates a
+//! file at the path and records the socket in the [`UnixSocketRegistry`] of
+//! the control plane under the device and inode numbers of that file, which
+//! is where connecting processes look it up. Renaming the file keeps the
+//! socket reachable, while removing it or binding in another file system
+//! never reaches it.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    mem::MaybeUninit,
+    net::Shutdown,
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex, Weak},
+};
+
+use derivative::Derivative;
+use virtual_fs::Metadata;
+use virtual_mio::{InterestHandler, InterestType};
+use virtual_net::{NetworkError, Result};
+
+/// Number of bytes buffered in each direction of a stream.
+const STREAM_BUFFER_SIZE: usize = 256 * 1024;
+
+/// Number of datagrams that can be queued on a datagram socket.
+const DATAGRAM_QUEUE_SIZE: usize = 256;
+
+type Handler = Box<dyn InterestHandler + Send + Sync>;
+
+fn notify(handler: &mut Option<Handler>, interest: InterestType) {
+    if let Some(handler) = handler.as_mut() {
+        handler.interest(interest);
+    }
+}
+
+/// Turns the path a socket is bound or connected to into an absolute path
+/// without `.` and `..` components.
+pub(crate) fn resolve_path(current_dir: &str, path: &str) -> PathBuf {
+    let mut resolved = PathBuf::from("/");
+    for component in Path::new(current_dir).join(path).components() {
+        match component {
+            Component::Normal(name) => resolved.push(name),
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            Component::RootDir | Component::CurDir | Component::Prefix(_) => {}
+        }
+    }
+    resolved
+}
+
+/// The file a socket is bound to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SocketFile {
+    dev: u64,
+    ino: u64,
+}
+
+impl SocketFile {
+    /// Identifies the file of `metadata`, [`None`] when its file system
+    /// doesn't number its entries.
+    pub fn of(metadata: &Metadata) -> Option<Self> {
+        (metadata.ino != 0).then_some(SocketFile {
+            dev: metadata.dev,
+            ino: metadata.ino,
+        })
+    }
+}
+
+#[derive(Debug)]
+enum Binding {
+    /// Held by the [`UnixSocketBinding`] of a socket that isn't listening
+    Stream(Weak<()>),
+    Listener(Weak<Mutex<ListenerState>>),
+    Datagram(Weak<Mutex<DatagramState>>),
+}
+
+impl Binding {
+    fn is_alive(&self) -> bool {
+        match self {
+            Binding::Stream(binding) => binding.strong_count() > 0,
+            Binding::Listener(listener) => listener.strong_count() > 0,
+            Binding::Datagram(socket) => socket.strong_count() > 0,
+        }
+    }
+}
+
+/// Keeps track of the files that Unix sockets are bound to.
+#[derive(Debug, Clone, Default)]
+pub struct UnixSocketRegistry {
+    bindings: Arc<Mutex<HashMap<SocketFile, Binding>>>,
+}
+
+impl UnixSocketRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn insert(&self, file: SocketFile, binding: Binding) -> Result<()> {
+        let mut bindings = self.bindings.lock().unwrap();
+        bindings.retain(|_, binding| binding.is_alive());
+        if bindings.contains_key(&file) {
+            return Err(NetworkError::AddressInUse);
+        }
+        bindings.insert(file, binding);
+        Ok(())
+    }
+
+    /// Binds a stream socket to `file`, found at `path`. Connections are
+    /// refused until it starts listening.
+    pub fn bind_stream(&self, file: SocketFile, path: PathBuf) -> Result<UnixSocketBinding> {
+        let token = Arc::new(());
+        self.insert(file, Binding::Stream(Arc::downgrade(&token)))?;
+        Ok(UnixSocketBinding {
+            registry: self.clone(),
+            file,
+            path,
+            token,
+        })
+    }
+
+    /// Binds a datagram socket to `file`, found at `path`.
+    pub fn bind_datagram(&self, file: SocketFile, path: PathBuf) -> Result<UnixDatagram> {
+        let socket = UnixDatagram::new(Some(path));
+        self.insert(file, Binding::Datagram(Arc::downgrade(&socket.state)))?;
+        Ok(socket)
+    }
+
+    /// Forgets the socket bound to `file`, which is called when the file is
+    /// removed. The socket keeps working but can't be reached anymore.
+    pub fn unbind(&self, file: SocketFile) {
+        self.bindings.lock().unwrap().remove(&file);
+    }
+
+    /// Connects to the stream socket listening on `file`, found at `path`.
+    pub fn connect(
+        &self,
+        file: SocketFile,
+        path: &Path,
+        local: Option<PathBuf>,
+    ) -> Result<UnixStream> {
+        let listener = match self.bindings.lock().unwrap().get(&file) {
+            Some(Binding::Listener(listener)) => listener.upgrade(),
+            _ => None,
+        };
+        let listener = listener.ok_or(NetworkError::ConnectionRefused)?;
+
+        let mut listener = listener.lock().unwrap();
+        if listener.backlog.len() >= listener.max_backlog {
+            return Err(NetworkError::ConnectionRefused);
+        }
+
+        let (mut client, mut server) = UnixStream::pair();
+        client.local = local.clone();
+        client.peer = Some(path.to_path_buf());
+        server.local = Some(path.to_path_buf());
+        server.peer = local;
+
+        listener.backlog.push_back(server);
+        notify(&mut listener.handler, InterestType::Readable);
+        Ok(client)
+    }
+
+    fn datagram(&self, file: SocketFile) -> Result<Weak<Mutex<DatagramState>>> {
+        match self.bindings.lock().unwrap().get(&file) {
+            Some(Binding::Datagram(socket)) if socket.strong_count() > 0 => Ok(socket.clone()),
+            _ => Err(NetworkError::ConnectionRefused),
+        }
+    }
+}
+
+/// The file a stream socket was bound to before it starts listening.
+#[derive(Debug, Clone)]
+pub struct UnixSocketBinding {
+    registry: UnixSocketRegistry,
+    file: SocketFile,
+    path: PathBuf,
+    /// Keeps the binding alive until the socket listens or is dropped
+    token: Arc<()>,
+}
+
+impl UnixSocketBinding {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Starts accepting connections on the bound file, unless it was removed
+    /// in the meantime.
+    pub fn listen(&self, backlog: usize) -> UnixListener {
+        let state = Arc::new(Mutex::new(ListenerState {
+            backlog: VecDeque::new(),
+            max_backlog: backlog.max(1),
+            handler: None,
+        }));
+        let mut bindings = self.registry.bindings.lock().unwrap();
+        if let Some(binding) = bindings.get_mut(&self.file) {
+            let ours = Arc::downgrade(&self.token);
+            if matches!(binding, Binding::Stream(token) if token.ptr_eq(&ours)) {
+                *binding = Binding::Listener(Arc::downgrade(&state));
+            }
+        }
+        drop(bindings);
+
+        UnixListener {
+            state,
+            path: self.path.clone(),
+        }
+    }
+}
+
+struct ListenerState {
+    backlog: VecDeque<UnixStream>,
+    max_backlog: usize,
+    handler: Option<Handler>,
+}
+
+/// A stream socket that accepts connections on a path.
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct UnixListener {
+    #[derivative(Debug = "ignore")]
+    state: Arc<Mutex<ListenerState>>,
+    path: PathBuf,
+}
+
+impl UnixListener {
+    pub fn try_accept(&mut self) -> Result<UnixStream> {
+        let mut state = self.state.lock().unwrap();
+        state.backlog.pop_front().ok_or(NetworkError::WouldBlock)
+    }
+
+    pub fn addr_local(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn set_handler(&mut self, mut handler: Handler) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if !state.backlog.is_empty() {
+            handler.interest(InterestType::Readable);
+        }
+        state.handler.replace(handler);
+        Ok(())
+    }
+
+    pub fn remove_handler(&mut self) {
+        self.state.lock().unwrap().handler.take();
+    }
+}
+
+#[derive(Default)]
+struct Pipe {
+    buffer: VecDeque<u8>,
+    /// Nothing more will be written into the pipe
+    closed: bool,
+    /// What is written into the pipe is thrown away
+    discard: bool,
+}
+
+#[derive(Default)]
+struct StreamState {
+    /// `pipes[end]` holds the data received by that end
+    pipes: [Pipe; 2],
+    handlers: [Option<Handler>; 2],
+    dropped: [bool; 2],
+}
+
+/// One end of a connected stream socket.
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct UnixStream {
+    #[derivative(Debug = "ignore")]
+    state: Arc<Mutex<StreamState>>,
+    end: usize,
+    local: Option<PathBuf>,
+    peer: Option<PathBuf>,
+}
+
+impl UnixStream {
+    /// Creates a pair of unnamed streams that are connected to each other.
+    pub fn pair() -> (Self, Self) {
+        let state = Arc::new(Mutex::new(StreamState::default()));
+        let first = Self {
+            state: state.clone(),
+            end: 0,
+            local: None,
+            peer: None,
+        };
+        let second = Self {
+            state,
+            end: 1,
+            local: None,
+            peer: None,
+        };
+        (first, second)
+    }
+
+    /// Creates a stream with nobody on the other end, which stands in for a
+    /// connection that was accepted while recording a journal when it is
+    /// replayed. What is written to it is thrown away and it reads as closed,
+    /// the data it received is replayed from the journal.
+    pub fn stand_in() -> Self {
+        let mut state = StreamState::default();
+        state.pipes[0].closed = true;
+        state.pipes[1].discard = true;
+        Self {
+            state: Arc::new(Mutex::new(state)),
+            end: 0,
+            local: None,
+            peer: None,
+        }
+    }
+
+    fn peer_end(&self) -> usize {
+        1 - self.end
+    }
+
+    /// The path this end is bound to, [`None`] for unnamed sockets.
+    pub fn addr_local(&self) -> Option<&Path> {
+        self.local.as_deref()
+    }
+
+    /// The path the other end is bound to, [`None`] for unnamed sockets.
+    pub fn addr_peer(&self) -> Option<&Path> {
+        self.peer.as_deref()
+    }
+
+    pub fn try_send(&mut self, data: &[u8]) -> Result<usize> {
+        let peer = self.peer_end();
+        let mut state = self.state.lock().unwrap();
+        if state.dropped[peer] {
+            return Err(NetworkError::ConnectionReset);
+        }
+        let pipe = &mut state.pipes[peer];
+        if pipe.closed {
+            return Err(NetworkError::BrokenPipe);
+        }
+        if data.is_empty() || pipe.discard {
+            return Ok(data.len());
+        }
+
+        let amt = STREAM_BUFFER_SIZE
+            .saturating_sub(pipe.buffer.len())
+            .min(data.len());
+        if amt == 0 {
+            return Err(NetworkError::WouldBlock);
+        }
+        pipe.buffer.extend(&data[..amt]);
+        notify(&mut state.handlers[peer], InterestType::Readable);
+        Ok(amt)
+    }
+
+    pub fn try_recv(&mut self, buf: &mut [MaybeUninit<u8>]) -> Result<usize> {
+        let peer = self.peer_end();
+        let mut state = self.state.lock().unwrap();
+        let pipe = &mut state.pipes[self.end];
+        if pipe.buffer.is_empty() {
+            return match pipe.closed {
+                true => Ok(0),
+                false => Err(NetworkError::WouldBlock),
+            };
+        }
+
+        let amt = buf.len().min(pipe.buffer.len());
+        for (dst, src) in buf.iter_mut().zip(pipe.buffer.drain(..amt)) {
+            dst.write(src);
+        }
+        notify(&mut state.handlers[peer], InterestType::Writable);
+        Ok(amt)
+    }
+
+    pub fn shutdown(&mut self, how: Shutdown) -> Result<()> {
+        let peer = self.peer_end();
+        let mut state = self.state.lock().unwrap();
+        if matches!(how, Shutdown::Write | Shutdown::Both) {
+            state.pipes[peer].closed = true;
+            notify(&mut state.handlers[peer], InterestType::Readable);
+        }
+        if matches!(how, Shutdown::Read | Shutdown::Both) {
+            let pipe = &mut state.pipes[self.end];
+            pipe.closed = true;
+            pipe.buffer.clear();
+            notify(&mut state.handlers[peer], InterestType::Writable);
+        }
+        Ok(())
+    }
+
+    /// Returns true when the other end has been dropped.
+    pub fn is_closed(&self) -> bool {
+        self.state.lock().unwrap().dropped[self.peer_end()]
+    }
+
+    pub fn set_handler(&mut self, mut handler: Handler) -> Result<()> {
+        let peer = self.peer_end();
+        let mut state = self.state.lock().unwrap();
+        let pipe = &state.pipes[self.end];
+        if !pipe.buffer.is_empty() || pipe.closed {
+            handler.interest(InterestType::Readable);
+        }
+        if state.dropped[peer] || state.pipes[peer].buffer.len() < STREAM_BUFFER_SIZE {
+            handler.interest(InterestType::Writable);
+        }
+        state.handlers[self.end].replace(handler);
+        Ok(())
+    }
+
+    pub fn remove_handler(&mut self) {
+        self.state.lock().unwrap().handlers[self.end].take();
+    }
+}
+
+impl Drop for UnixStream {
+    fn drop(&mut self) {
+        let peer = self.peer_end();
+        let mut state = self.state.lock().unwrap();
+        state.dropped[self.end] = true;
+        state.pipes[peer].closed = true;
+        state.handlers[self.end].take();
+        notify(&mut state.handlers[peer], InterestType::Readable);
+        notify(&mut state.handlers[peer], InterestType::Writable);
+        notify(&mut state.handlers[peer], InterestType::Closed);
+    }
+}
+
+#[derive(Default)]
+struct DatagramState {
+    queue: VecDeque<(Vec<u8>, Option<PathBuf>)>,
+    handler: Option<Handler>,
+    /// Sockets that are waiting for room in the queue
+    blocked: Vec<Weak<Mutex<DatagramState>>>,
+}
+
+/// A datagram socket, it can only send once it is connected to a bound
+/// datagram socket.
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct UnixDatagram {
+    #[derivative(Debug = "ignore")]
+    state: Arc<Mutex<DatagramState>>,
+    local: Option<PathBuf>,
+    #[derivative(Debug = "ignore")]
+    peer: Option<(PathBuf, Weak<Mutex<DatagramState>>)>,
+}
+
+impl UnixDatagram {
+    /// Creates a datagram socket that is bound to `local`.
+    pub fn new(local: Option<PathBuf>) -> Self {
+        Self {
+            state: Default::default(),
+            local,
+            peer: None,
+        }
+    }
+
+    /// Sends all the datagrams of this socket to the socket bound to `file`,
+    /// found at `path`.
+    pub fn connect(
+        &mut self,
+        registry: &UnixSocketRegistry,
+        file: SocketFile,
+        path: PathBuf,
+    ) -> Result<()> {
+        let target = registry.datagram(file)?;
+        self.peer.replace((path, target));
+        Ok(())
+    }
+
+    pub fn addr_local(&self) -> Option<&Path> {
+        self.local.as_deref()
+    }
+
+    pub fn addr_peer(&self) -> Option<&Path> {
+        self.peer.as_ref().map(|(path, _)| path.as_path())
+    }
+
+    pub fn try_send(&mut self, data: &[u8]) -> Result<usize> {
+        let (_, target) = self.peer.as_ref().ok_or(NetworkError::NotConnected)?;
+        let target = target.upgrade().ok_or(NetworkError::ConnectionRefused)?;
+
+        let mut target = target.lock().unwrap();
+        if target.queue.len() >= DATAGRAM_QUEUE_SIZE {
+            target.blocked.push(Arc::downgrade(&self.state));
+            return Err(NetworkError::WouldBlock);
+        }
+        target.queue.push_back((data.to_vec(), self.local.clone()));
+        notify(&mut target.handler, InterestType::Readable);
+        Ok(data.len())
+    }
+
+    /// Receives the next datagram and the path of the socket that sent it,
+    /// the part of the datagram that doesn't fit in `buf` is discarded.
+    pub fn try_recv_from(
+        &mut self,
+        buf: &mut [MaybeUninit<u8>],
+    ) -> Result<(usize, Option<PathBuf>)> {
+        let (data, sender, blocked) = {
+            let mut state = self.state.lock().unwrap();
+            let (data, sender) = state.queue.pop_front().ok_or(NetworkError::WouldBlock)?;
+            (data, sender, std::mem::take(&mut state.blocked))
+        };
+
+        // Wake up the senders after releasing the lock, they might be
+        // trying to send to this socket
+        for sender in blocked.into_iter().filter_map(|sender| sender.upgrade()) {
+            notify(&mut sender.lock().unwrap().handler, InterestType::Writable);
+        }
+
+        let amt = buf.len().min(data.len());
+        for (dst, src) in buf.iter_mut().zip(&data[..amt]) {
+            dst.write(*src);
+        }
+        Ok((amt, sender))
+    }
+
+    pub fn try_recv(&mut self, buf: &mut [MaybeUninit<u8>]) -> Result<usize> {
+        self.try_recv_from(buf).map(|(amt, _)| amt)
+    }
+
+    pub fn set_handler(&mut self, mut handler: Handler) -> Result<()> {
+        let writable = match self.peer.as_ref().map(|(_, target)| target.upgrade()) {
+            Some(Some(target)) => target.lock().unwrap().queue.len() < DATAGRAM_QUEUE_SIZE,
+            _ => true,
+        };
+
+        let mut state = self.state.lock().unwrap();
+        if !state.queue.is_empty() {
+            handler.interest(InterestType::Readable);
+        }
+        if writable {
+            handler.interest(InterestType::Writable);
+        }
+        state.handler.replace(handler);
+        Ok(())
+    }
+
+    pub fn remove_handler(&mut self) {
+        self.state.lock().unwrap().handler.take();
+    }
+}
+
+/// Connects to a Unix socket of the host, the data of the returned stream is
+/// pumped to and from it by a task of `tasks`.
+#[cfg(all(unix, feature = "host-vnet"))]
+pub(crate) fn connect_host(
+    tasks: &dyn crate::VirtualTaskManager,
+    host_path: &Path,
+    local: Option<PathBuf>,
+    peer: PathBuf,
+) -> Result<UnixStream> {
+    let host = std::os::unix::net::UnixStream::connect(host_path)
+        .and_then(|host| host.set_nonblocking(true).map(|_| host))
+        .map_err(virtual_net::io_err_into_net_error)?;
+
+    let (mut guest, bridge) = UnixStream::pair();
+    guest.local = local;
+    guest.peer = Some(peer);
+
+    let host_path = host_path.to_path_buf();
+    tasks
+        .task_shared(Box::new(move || {
+            Box::pin(async move {
+                match tokio::net::UnixStream::from_std(host) {
+                    Ok(host) => host_bridge::pump(bridge, host).await,
+                    Err(err) => {
+                        tracing::debug!(path = %host_path.display(), %err, "host socket bridge failed")
+                    }
+                }
+            })
+        }))
+        .map_err(|_| NetworkError::IOError)?;
+
+    Ok(guest)
+}
+
+#[cfg(all(unix, feature = "host-vnet"))]
+mod host_bridge {
+    use std::{future::poll_fn, pin::Pin, task::Poll};
+
+    use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+    use super::*;
+
+    /// Copies data between `bridge` and `host` until both directions are
+    /// closed or one of them fails.
+    pub(super) async fn pump(mut bridge: UnixStream, mut host: tokio::net::UnixStream) {
+        let mut to_host = Vec::new();
+        let mut to_guest = Vec::new();
+        let mut guest_closed = false;
+        let mut host_closed = false;
+        let mut host_shutdown = false;
+        let mut handler_registered = false;
+
+        poll_fn(|cx| {
+            if !handler_registered {
+                if bridge.set_handler(cx.waker().into()).is_err() {
+                    return Poll::Ready(());
+                }
+                handler_registered = true;
+            }
+
+            loop {
+                let mut progress = false;
+
+                if to_host.is_empty() && !guest_closed {
+                    let mut buf = [MaybeUninit::uninit(); 8192];
+                    match bridge.try_recv(&mut buf) {
+                        Ok(0) => guest_closed = true,
+                        Ok(amt) => to_host.extend(
+                            // SAFETY: try_recv initialized the first `amt` bytes
+                            buf[..amt].iter().map(|b| unsafe { b.assume_init() }),
+                        ),
+                        Err(NetworkError::WouldBlock) => {}
+                        Err(_) => return Poll::Ready(()),
+                    }
+                    progress |= guest_closed || !to_host.is_empty();
+                }
+                if !to_host.is_empty() {
+                    match Pin::new(&mut host).poll_write(cx, &to_host) {
+                        Poll::Ready(Ok(amt)) => {
+                            to_host.drain(..amt);
+                            progress = true;
+                        }
+                        Poll::Ready(Err(_)) => return Poll::Ready(()),
+                        Poll::Pending => {}
+                    }
+                } else if guest_closed && !host_shutdown {
+                    match Pin::new(&mut host).poll_shutdown(cx) {
+                        Poll::Ready(_) => host_shutdown = true,
+                        Poll::Pending => {}
+                    }
+                }
+
+                if to_guest.is_empty() && !host_closed {
+                    let mut buf = [0u8; 8192];
+                    let mut read = ReadBuf::new(&mut buf);
+                    match Pin::new(&mut host).poll_read(cx, &mut read) {
+                        Poll::Ready(Ok(())) if read.filled().is_empty() => {
+                            host_closed = true;
+                            bridge.shutdown(Shutdown::Write).ok();
+                            progress = true;
+                        }
+                        Poll::Ready(Ok(())) => {
+                            to_guest.extend_from_slice(read.filled());
+                            progress = true;
+                        }
+                        Poll::Ready(Err(_)) => return Poll::Ready(()),
+                        Poll::Pending => {}
+                    }
+                }
+                if !to_guest.is_empty() {
+                    match bridge.try_send(&to_guest) {
+                        Ok(amt) => {
+                            to_guest.drain(..amt);
+                            progress = true;
+                        }
+                        Err(NetworkError::WouldBlock) => {}
+                        Err(_) => return Poll::Ready(()),
+                    }
+                }
+
+                if guest_closed && host_closed && to_host.is_empty() && to_guest.is_empty() {
+                    return Poll::Ready(());
+                }
+                if !progress {
+                    return Poll::Pending;
+                }
+            }
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(dev: u64, ino: u64) -> SocketFile {
+        SocketFile { dev, ino }
+    }
+
+    fn recv(stream: &mut UnixStream) -> Result<Vec<u8>> {
+        let mut buf = [MaybeUninit::uninit(); 64];
+        let amt = stream.try_recv(&mut buf)?;
+        Ok(buf[..amt]
+            .iter()
+            .map(|b| unsafe { b.assume_init() })
+            .collect())
+    }
+
+    #[test]
+    fn streams_connect_through_the_registry() {
+        let registry = UnixSocketRegistry::new();
+        let path = PathBuf::from("/tmp/server.sock");
+        let server_file = file(1, 2);
+        assert_eq!(
+            registry.connect(server_file, &path, None).unwrap_err(),
+            NetworkError::ConnectionRefused
+        );
+
+        let binding = registry.bind_stream(server_file, path.clone()).unwrap();
+        assert_eq!(
+            registry.connect(server_file, &path, None).unwrap_err(),
+            NetworkError::ConnectionRefused,
+            "connections are refused until the socket listens"
+        );
+        let mut listener = binding.listen(1);
+
+        let mut client = registry.connect(server_file, &path, None).unwrap();
+        let mut server = listener.try_accept().unwrap();
+        assert_eq!(client.addr_peer(), Some(path.as_path()));
+        assert_eq!(server.addr_peer(), None);
+
+        client.try_send(b"ping").unwrap();
+        assert_eq!(recv(&mut server).unwrap(), b"ping");
+        assert_eq!(recv(&mut server).unwrap_err(), NetworkError::WouldBlock);
+
+        drop(client);
+        assert_eq!(recv(&mut server).unwrap(), b"", "end of stream");
+        assert!(server.is_closed());
+        assert_eq!(
+            server.try_send(b"pong").unwrap_err(),
+            NetworkError::ConnectionReset
+        );
+
+        drop(listener);
+        assert_eq!(
+            registry.connect(server_file, &path, None).unwrap_err(),
+            NetworkError::ConnectionRefused
+        );
+    }
+
+    #[test]
+    fn sockets_are_bound_to_files() {
+        let registry = UnixSocketRegistry::new();
+        let path = PathBuf::from("/tmp/server.sock");
+        let binding = registry.bind_stream(file(1, 2), path.clone()).unwrap();
+        let _listener = binding.listen(1);
+
+        assert_eq!(
+            registry
+                .bind_datagram(file(1, 2), path.clone())
+                .unwrap_err(),
+            NetworkError::AddressInUse,
+            "the file already has a socket"
+        );
+        assert_eq!(
+            registry.connect(file(3, 2), &path, None).unwrap_err(),
+            NetworkError::ConnectionRefused,
+            "the same inode of another file system is another file"
+        );
+        registry.connect(file(1, 2), &path, None).unwrap();
+
+        registry.unbind(file(1, 2));
+        assert_eq!(
+            registry.connect(file(1, 2), &path, None).unwrap_err(),
+            NetworkError::ConnectionRefused,
+            "the file was removed"
+        );
+        registry.bind_stream(file(1, 2), path).unwrap();
+    }
+
+    #[test]
+    fn a_removed_file_is_not_bound_when_listening() {
+        let registry = UnixSocketRegistry::new();
+        let path = PathBuf::from("/tmp/server.sock");
+        let binding = registry.bind_stream(file(1, 2), path.clone()).unwrap();
+        registry.unbind(file(1, 2));
+
+        let _other = registry.bind_stream(file(1, 2), path.clone()).unwrap();
+        let _listener = binding.listen(1);
+        assert_eq!(
+            registry.connect(file(1, 2), &path, None).unwrap_err(),
+            NetworkError::ConnectionRefused,
+            "the other socket isn't listening"
+        );
+    }
+
+    #[test]
+    fn shutting_down_the_write_side_ends_the_stream() {
+        let (mut first, mut second) = UnixStream::pair();
+        first.try_send(b"last words").unwrap();
+        first.shutdown(Shutdown::Write).unwrap();
+
+        assert_eq!(recv(&mut second).unwrap(), b"last words");
+        assert_eq!(recv(&mut second).unwrap(), b"");
+        assert_eq!(
+            first.try_send(b"more").unwrap_err(),
+            NetworkError::BrokenPipe
+        );
+        second.try_send(b"reply").unwrap();
+        assert_eq!(recv(&mut first).unwrap(), b"reply");
+    }
+
+    #[test]
+    fn stand_ins_throw_away_what_is_written() {
+        let mut stream = UnixStream::stand_in();
+        let data = vec![0; STREAM_BUFFER_SIZE * 2];
+        assert_eq!(stream.try_send(&data).unwrap(), data.len());
+        assert_eq!(recv(&mut stream).unwrap(), b"");
+        assert!(!stream.is_closed());
+    }
+
+    #[test]
+    fn datagrams_are_delivered_with_their_sender() {
+        let registry = UnixSocketRegistry::new();
+        let mut server = registry
+            .bind_datagram(file(1, 2), PathBuf::from("/run/log"))
+            .unwrap();
+        let mut client = registry
+            .bind_datagram(file(1, 3), PathBuf::from("/run/client"))
+            .unwrap();
+        assert_eq!(
+            client.try_send(b"hello").unwrap_err(),
+            NetworkError::NotConnected
+        );
+
+        client
+            .connect(&registry, file(1, 2), PathBuf::from("/run/log"))
+            .unwrap();
+        client.try_send(b"hello").unwrap();
+        client.try_send(b"world").unwrap();
+
+        let mut buf = [MaybeUninit::uninit(); 3];
+        let (amt, sender) = server.try_recv_from(&mut buf).unwrap();
+        assert_eq!(amt, 3, "datagrams are truncated");
+        assert_eq!(sender, Some(PathBuf::from("/run/client")));
+        let (amt, _) = server.try_recv_from(&mut buf).unwrap();
+        assert_eq!(amt, 3);
+        assert_eq!(
+            server.try_recv(&mut buf).unwrap_err(),
+            NetworkError::WouldBlock
+        );
+    }
+
+    #[test]
+    fn paths_are_resolved_against_the_current_directory() {
+        assert_eq!(
+            resolve_path("/home", "app.sock"),
+            Path::new("/home/app.sock")
+        );
+        assert_eq!(
+            resolve_path("/home", "./run/../app.sock"),
+            Path::new("/home/app.sock")
+        );
+        assert_eq!(
+            resolve_path("/home", "/tmp/app.sock"),
+            Path::new("/tmp/app.sock")
+        );
+    }
+}
@@ -6,7 +6,13 @@ use std::{
     },
 };
 
-use crate::{WasiProcess, WasiProcessId};
+use wasmer_wasix_types::{
+    types::Signal,
+    wasi::{RlimitResource, Siginfo},
+};
+
+use super::limits::ResourceLimits;
+use crate::{fs::FileLocks, net::unix::UnixSocketRegistry, WasiProcess, WasiProcessId};
 
 #[derive(Debug, Clone)]
 pub struct WasiControlPlane {
@@ -41,6 +47,9 @@ pub struct ControlPlaneConfig {
     pub max_task_count: Option<usize>,
     /// Flag that indicates if asynchronous threading is enables (opt-in)
     pub enable_asynchronous_threading: bool,
+    /// Resource limits that processes start with, child processes inherit
+    /// the limits of their parent instead.
+    pub default_limits: ResourceLimits,
 }
 
 impl ControlPlaneConfig {
@@ -48,6 +57,7 @@ impl ControlPlaneConfig {
         Self {
             max_task_count: None,
             enable_asynchronous_threading: false,
+            default_limits: ResourceLimits::default(),
         }
     }
 }
@@ -65,6 +75,12 @@ struct State {
     /// Total number of active tasks (threads) across all processes.
     task_count: Arc<AtomicUsize>,
 
+    /// Unix sockets that the processes have bound to a path.
+    unix_sockets: UnixSocketRegistry,
+
+    /// Advisory locks that the processes hold on files.
+    file_locks: FileLocks,
+
     /// Mutable state.
     mutable: RwLock<MutableState>,
 }
@@ -75,6 +91,8 @@ struct MutableState {
     process_seed: u32,
     /// The processes running on this machine
     processes: HashMap<WasiProcessId, WasiProcess>,
+    /// The foreground process group of each session that picked one
+    foreground_groups: HashMap<WasiProcessId, WasiProcessId>,
     // TODO: keep a queue of terminated process ids for id reuse.
 }
 
@@ -84,9 +102,12 @@ impl WasiControlPlane {
             state: Arc::new(State {
                 config,
                 task_count: Arc::new(AtomicUsize::new(0)),
+                unix_sockets: UnixSocketRegistry::new(),
+                file_locks: FileLocks::new(),
                 mutable: RwLock::new(MutableState {
                     process_seed: 0,
                     processes: Default::default(),
+                    foreground_groups: Default::default(),
                 }),
             }),
         }
@@ -106,6 +127,16 @@ impl WasiControlPlane {
         &self.state.config
     }
 
+    /// Returns the Unix sockets bound by the processes of this control plane
+    pub(crate) fn unix_sockets(&self) -> &UnixSocketRegistry {
+        &self.state.unix_sockets
+    }
+
+    /// Returns the advisory file locks held by the processes of this control plane
+    pub(crate) fn file_locks(&self) -> &FileLocks {
+        &self.state.file_locks
+    }
+
     /// Register a new task.
     ///
     // Currently just increments the task counter.
@@ -124,6 +155,22 @@ impl WasiControlPlane {
     // FIXME: De-register terminated processes!
     // Currently they just accumulate.
     pub fn new_process(&self) -> Result<WasiProcess, ControlPlaneError> {
+        self.create_process(None)
+    }
+
+    /// Creates a new process that is a child of `parent`, it starts out in
+    /// the process group and session of its parent
+    pub fn new_child_process(
+        &self,
+        parent: &WasiProcess,
+    ) -> Result<WasiProcess, ControlPlaneError> {
+        self.create_process(Some(parent))
+    }
+
+    fn create_process(
+        &self,
+        parent: Option<&WasiProcess>,
+    ) -> Result<WasiProcess, ControlPlaneError> {
         if let Some(max) = self.state.config.max_task_count {
             if self.active_task_count() >= max {
                 // NOTE: task count is not incremented here, only when new threads are spawned.
@@ -134,11 +181,15 @@ impl WasiControlPlane {
 
         // Create the process first to do all the allocations before locking.
         let mut proc = WasiProcess::new(WasiProcessId::from(0), self.handle());
+        proc.set_limits(self.state.config.default_limits.clone());
 
         let mut mutable = self.state.mutable.write().unwrap();
 
         let pid = mutable.next_process_id()?;
         proc.set_pid(pid);
+        if let Some(parent) = parent {
+            proc.set_parent(parent);
+        }
         mutable.processes.insert(pid, proc.clone());
         Ok(proc)
     }
@@ -159,6 +210,42 @@ impl WasiControlPlane {
             .get(&pid)
             .cloned()
     }
+
+    /// Returns the processes in a process group that are still running
+    pub fn process_group(&self, pgid: WasiProcessId) -> Vec<WasiProcess> {
+        self.state
+            .mutable
+            .read()
+            .unwrap()
+            .processes
+            .values()
+            .filter(|process| process.pgid() == pgid && process.try_join().is_none())
+            .cloned()
+            .collect()
+    }
+
+    /// Signals every process in a process group, returns false if the group
+    /// has no processes left
+    pub fn signal_group(&self, pgid: WasiProcessId, signal: Signal, info: Siginfo) -> bool {
+        let processes = self.process_group(pgid);
+        for process in processes.iter() {
+            process.deliver_signal(signal, info);
+        }
+        !processes.is_empty()
+    }
+
+    /// Returns the foreground process group of a session, which is the one
+    /// that gets the signals raised by the terminal
+    pub fn foreground_group(&self, sid: WasiProcessId) -> Option<WasiProcessId> {
+        let mutable = self.state.mutable.read().unwrap();
+        mutable.foreground_groups.get(&sid).copied()
+    }
+
+    /// Changes the foreground process group of a session
+    pub fn set_foreground_group(&self, sid: WasiProcessId, pgid: WasiProcessId) {
+        let mut mutable = self.state.mutable.write().unwrap();
+        mutable.foreground_groups.insert(sid, pgid);
+    }
 }
 
 impl MutableState {
@@ -199,11 +286,26 @@ pub enum ControlPlaneError {
         /// The maximum number of tasks.
         max: usize,
     },
+    /// The process has reached one of its resource limits.
+    #[error("The process has reached its limit on {resource:?}")]
+    ResourceLimitReached {
+        /// The resource that ran out.
+        resource: RlimitResource,
+    },
 }
 
 #[cfg(test)]
 mod tests {
+    use wasmer_wasix_types::{
+        types::Signal,
+        wasi::{SigDisposition, Sigaction, SigactionFlags},
+    };
+
     use super::*;
+    use crate::os::task::{
+        limits::ResourceLimit,
+        signal::{default_siginfo, sigset_of},
+    };
 
     /// Simple test to ensure task limits are respected.
     #[test]
@@ -211,6 +313,7 @@ mod tests {
         let p = WasiControlPlane::new(ControlPlaneConfig {
             max_task_count: Some(2),
             enable_asynchronous_threading: false,
+            default_limits: ResourceLimits::default(),
         });
 
         let p1 = p.new_process().unwrap();
@@ -229,6 +332,7 @@ mod tests {
         let p = WasiControlPlane::new(ControlPlaneConfig {
             max_task_count: Some(2),
             enable_asynchronous_threading: false,
+            default_limits: ResourceLimits::default(),
         });
 
         let p1 = p.new_process().unwrap();
@@ -245,4 +349,112 @@ mod tests {
             ControlPlaneError::TaskLimitReached { max: 2 }
         );
     }
+
+    /// Threads beyond the limit of the process are refused.
+    #[test]
+    fn test_control_plane_thread_limits() {
+        let p = WasiControlPlane::new(ControlPlaneConfig {
+            default_limits: ResourceLimits {
+                threads: ResourceLimit::fixed(2),
+                ..Default::default()
+            },
+            ..ControlPlaneConfig::new()
+        });
+
+        let p1 = p.new_process().unwrap();
+        let _main = p1.new_thread().unwrap();
+        let t1 = p1.new_thread().unwrap();
+
+        assert_eq!(
+            p1.new_thread().unwrap_err(),
+            ControlPlaneError::ResourceLimitReached {
+                resource: RlimitResource::Threads
+            }
+        );
+
+        drop(t1);
+        let _t2 = p1.new_thread().unwrap();
+    }
+
+    /// Signals sent to a process go to a thread that has not blocked them,
+    /// blocked signals wait until they are unblocked and ignored ones are
+    /// discarded.
+    #[test]
+    fn test_control_plane_signal_delivery() {
+        let p = WasiControlPlane::new(ControlPlaneConfig::new());
+        let p1 = p.new_process().unwrap();
+        let main_handle = p1.new_thread().unwrap();
+        let t1_handle = p1.new_thread().unwrap();
+        let (main, t1) = (main_handle.as_thread(), t1_handle.as_thread());
+
+        main.set_signal_mask(sigset_of(Signal::Sigusr1));
+        p1.signal_process(Signal::Sigusr1);
+        assert!(main.pop_signals().is_empty());
+        assert_eq!(t1.pop_signals(), vec![Signal::Sigusr1]);
+
+        t1.set_signal_mask(sigset_of(Signal::Sigusr1) | sigset_of(Signal::Sigkill));
+        p1.signal_process(Signal::Sigusr1);
+        assert!(t1.pop_signals().is_empty());
+        assert_eq!(main.pending_signals(), sigset_of(Signal::Sigusr1));
+        assert!(main.pop_signals().is_empty());
+
+        main.set_signal_mask(0);
+        assert_eq!(main.pop_signals(), vec![Signal::Sigusr1]);
+        assert_eq!(t1.signal_mask(), sigset_of(Signal::Sigusr1));
+
+        p1.set_signal_action(
+            Signal::Sigusr2,
+            Sigaction {
+                mask: 0,
+                disposition: SigDisposition::Ignore,
+                flags: SigactionFlags::empty(),
+            },
+        );
+        p1.signal_process(Signal::Sigusr2);
+        assert_eq!(main.pending_signals(), 0);
+        assert_eq!(t1.pending_signals(), 0);
+    }
+
+    /// Children start out in the process group of their parent, signals sent
+    /// to a group reach all of its processes and stopping a child tells the
+    /// parent about it.
+    #[test]
+    fn test_control_plane_job_control() {
+        use futures::FutureExt;
+
+        let p = WasiControlPlane::new(ControlPlaneConfig::new());
+        let parent = p.new_process().unwrap();
+        let parent_handle = parent.new_thread().unwrap();
+        let child = p.new_child_process(&parent).unwrap();
+        let child_handle = child.new_thread().unwrap();
+        let (parent_main, child_main) = (parent_handle.as_thread(), child_handle.as_thread());
+
+        assert_eq!(child.ppid(), parent.pid());
+        assert_eq!(child.pgid(), parent.pid());
+        assert_eq!(child.sid(), parent.pid());
+
+        child.set_pgid(child.pid());
+        assert_eq!(p.process_group(parent.pid()).len(), 1);
+        assert!(p.signal_group(
+            child.pid(),
+            Signal::Sigtstp,
+            default_siginfo(Signal::Sigtstp)
+        ));
+        assert!(parent_main.pop_signals().is_empty());
+        assert_eq!(child_main.pop_signals(), vec![Signal::Sigtstp]);
+
+        child.stop(Signal::Sigtstp);
+        assert_eq!(parent_main.pop_signals(), vec![Signal::Sigchld]);
+        assert_eq!(child.wait_stopped().now_or_never(), Some(Signal::Sigtstp));
+        assert_eq!(child.wait_stopped().now_or_never(), None);
+
+        p.signal_group(
+            child.pid(),
+            Signal::Sigcont,
+            default_siginfo(Signal::Sigcont),
+        );
+        assert_eq!(child.stopped_by(), None);
+        assert!(child.wait_until_continued().now_or_never().is_some());
+        assert_eq!(parent_main.pop_signals(), vec![Signal::Sigchld]);
+    }
 }
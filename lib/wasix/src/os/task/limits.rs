@@ -0,0 +1,422 @@
+//! Per-process resource limits, in the style of `setrlimit()`.
+//!
+//! Every [`WasiProcess`](super::process::WasiProcess) has its own set of
+//! limits. The first process of a control plane gets the defaults from the
+//! [`ControlPlaneConfig`](super::control_plane::ControlPlaneConfig) and child
+//! processes inherit the limits of their parent when they are forked or
+//! spawned.
+//!
+//! Like on POSIX a process can lower its limits, and raise its soft limits up
+//! to the hard ones, but it can never raise a hard limit again.
+//!
+//! The memory limit caps the maximum size of the linear memory, which is
+//! what `memory.grow` checks. Memories that are imported are capped when
+//! they are created for the process. Memories that a module defines itself
+//! are created by the engine, so they are only capped when its tunables are
+//! [`LimitingTunables`], which needs the `sys-tunables` feature.
+
+#[cfg(feature = "sys-tunables")]
+use std::{cell::Cell, ptr::NonNull};
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "sys-tunables")]
+use wasmer::{
+    vm::{
+        MemoryError, MemoryStyle, TableStyle, VMConfig, VMMemory, VMMemoryDefinition, VMTable,
+        VMTableDefinition,
+    },
+    NativeEngineExt, TableType, Tunables,
+};
+use wasmer::{MemoryType, WASM_PAGE_SIZE};
+use wasmer_wasix_types::wasi::{Errno, Rlimit, RlimitResource, Snapshot0Clockid};
+
+use crate::syscalls::platform_clock_time_get;
+
+/// The limit on a resource.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResourceLimit {
+    /// The limit that is enforced.
+    pub soft: u64,
+    /// The most that the soft limit can be raised to.
+    pub hard: u64,
+}
+
+impl ResourceLimit {
+    /// The value of a limit that is not limited.
+    pub const INFINITY: u64 = u64::MAX;
+
+    /// No limit at all.
+    pub const UNLIMITED: Self = Self {
+        soft: Self::INFINITY,
+        hard: Self::INFINITY,
+    };
+
+    pub fn new(soft: u64, hard: u64) -> Self {
+        Self { soft, hard }
+    }
+
+    /// A limit that the process can not raise.
+    pub fn fixed(limit: u64) -> Self {
+        Self::new(limit, limit)
+    }
+
+    /// Returns the enforced limit, if there is one.
+    pub fn get(&self) -> Option<u64> {
+        if self.soft == Self::INFINITY {
+            None
+        } else {
+            Some(self.soft)
+        }
+    }
+}
+
+impl Default for ResourceLimit {
+    fn default() -> Self {
+        Self::UNLIMITED
+    }
+}
+
+impl From<ResourceLimit> for Rlimit {
+    fn from(limit: ResourceLimit) -> Self {
+        Rlimit {
+            cur: limit.soft,
+            max: limit.hard,
+        }
+    }
+}
+
+/// The limits of a process.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResourceLimits {
+    /// The number of file descriptors that can be open at once.
+    pub open_files: ResourceLimit,
+    /// The number of threads that can run at once, including the main thread.
+    pub threads: ResourceLimit,
+    /// The size in bytes that the linear memory can grow to. This is applied
+    /// when the memory is created, so a new limit takes effect on the next
+    /// process that is spawned (see the [module docs](self)).
+    pub memory: ResourceLimit,
+    /// The wall-clock time in seconds that the threads can spend outside of
+    /// blocking syscalls, added up over all the threads. This is not the CPU
+    /// time, the host doesn't report that. The process
+    /// gets a `SIGXCPU` once it goes over the soft limit, as that is the
+    /// signal programs expect for running out of time, and is killed when it
+    /// goes over the hard limit.
+    pub run_time: ResourceLimit,
+    /// The size in bytes that files can be grown to.
+    pub file_size: ResourceLimit,
+    /// The number of child processes that can run at once.
+    pub processes: ResourceLimit,
+}
+
+impl ResourceLimits {
+    /// Returns the limit on a resource.
+    pub fn get(&self, resource: RlimitResource) -> Result<ResourceLimit, Errno> {
+        let limit = match resource {
+            RlimitResource::OpenFiles => self.open_files,
+            RlimitResource::Threads => self.threads,
+            RlimitResource::Memory => self.memory,
+            RlimitResource::RunTime => self.run_time,
+            RlimitResource::FileSize => self.file_size,
+            RlimitResource::Processes => self.processes,
+            RlimitResource::Unknown => return Err(Errno::Inval),
+        };
+        Ok(limit)
+    }
+
+    /// Changes the limit on a resource, which fails with `EPERM` if it would
+    /// raise the hard limit.
+    pub fn set(&mut self, resource: RlimitResource, limit: ResourceLimit) -> Result<(), Errno> {
+        let current = self.get(resource)?;
+        if limit.soft > limit.hard {
+            return Err(Errno::Inval);
+        }
+        if limit.hard > current.hard {
+            return Err(Errno::Perm);
+        }
+        match resource {
+            RlimitResource::OpenFiles => self.open_files = limit,
+            RlimitResource::Threads => self.threads = limit,
+            RlimitResource::Memory => self.memory = limit,
+            RlimitResource::RunTime => self.run_time = limit,
+            RlimitResource::FileSize => self.file_size = limit,
+            RlimitResource::Processes => self.processes = limit,
+            RlimitResource::Unknown => return Err(Errno::Inval),
+        }
+        Ok(())
+    }
+
+    /// Caps the maximum size of a memory that is about to be created.
+    pub(crate) fn limit_memory(&self, ty: &mut MemoryType) {
+        if let Some(limit) = self.memory.get() {
+            cap_memory(ty, limit);
+        }
+    }
+
+    /// Instantiates a module for a process with these limits, which caps the
+    /// memories the module defines itself when the engine has
+    /// [`LimitingTunables`].
+    pub(crate) fn instantiate<R>(&self, instantiate: impl FnOnce() -> R) -> R {
+        #[cfg(feature = "sys-tunables")]
+        let _limit = MemoryLimitGuard::set(self.memory.get());
+        instantiate()
+    }
+}
+
+fn cap_memory(ty: &mut MemoryType, limit: u64) {
+    let pages = (limit / WASM_PAGE_SIZE as u64).min(u32::MAX as u64) as u32;
+    let maximum = match ty.maximum {
+        Some(maximum) => maximum.0.min(pages),
+        None => pages,
+    };
+    ty.maximum = Some(maximum.into());
+}
+
+#[cfg(feature = "sys-tunables")]
+thread_local! {
+    /// The memory limit of the process that is being instantiated on this
+    /// thread
+    static MEMORY_LIMIT: Cell<Option<u64>> = Cell::new(None);
+}
+
+/// Sets the memory limit of the thread until it is dropped.
+#[cfg(feature = "sys-tunables")]
+struct MemoryLimitGuard(Option<u64>);
+
+#[cfg(feature = "sys-tunables")]
+impl MemoryLimitGuard {
+    fn set(limit: Option<u64>) -> Self {
+        Self(MEMORY_LIMIT.with(|cell| cell.replace(limit)))
+    }
+}
+
+#[cfg(feature = "sys-tunables")]
+impl Drop for MemoryLimitGuard {
+    fn drop(&mut self) {
+        MEMORY_LIMIT.with(|cell| cell.set(self.0));
+    }
+}
+
+/// Tunables that cap the memories a module defines itself with the memory
+/// limit of the process it is instantiated for, and otherwise defer to the
+/// tunables they wrap.
+///
+/// [`PluggableRuntime`](crate::PluggableRuntime) installs them on its
+/// engine, the stores that processes are instantiated in need to use it.
+#[cfg(feature = "sys-tunables")]
+pub struct LimitingTunables {
+    /// A clone of the engine from before the tunables were installed, which
+    /// holds the tunables that are wrapped
+    base: wasmer::Engine,
+}
+
+#[cfg(feature = "sys-tunables")]
+impl LimitingTunables {
+    /// Wraps the tunables of `engine`.
+    pub fn install(engine: &mut wasmer::Engine) {
+        let base = engine.clone();
+        engine.set_tunables(LimitingTunables { base });
+    }
+
+    fn limit(ty: &MemoryType) -> MemoryType {
+        let mut ty = *ty;
+        if let Some(limit) = MEMORY_LIMIT.with(Cell::get) {
+            cap_memory(&mut ty, limit);
+        }
+        ty
+    }
+}
+
+#[cfg(feature = "sys-tunables")]
+impl Tunables for LimitingTunables {
+    fn memory_style(&self, memory: &MemoryType) -> MemoryStyle {
+        self.base.tunables().memory_style(memory)
+    }
+
+    fn table_style(&self, table: &TableType) -> TableStyle {
+        self.base.tunables().table_style(table)
+    }
+
+    fn create_host_memory(
+        &self,
+        ty: &MemoryType,
+        style: &MemoryStyle,
+    ) -> Result<VMMemory, MemoryError> {
+        self.base
+            .tunables()
+            .create_host_memory(&Self::limit(ty), style)
+    }
+
+    unsafe fn create_vm_memory(
+        &self,
+        ty: &MemoryType,
+        style: &MemoryStyle,
+        vm_definition_location: NonNull<VMMemoryDefinition>,
+    ) -> Result<VMMemory, MemoryError> {
+        self.base
+            .tunables()
+            .create_vm_memory(&Self::limit(ty), style, vm_definition_location)
+    }
+
+    fn create_host_table(&self, ty: &TableType, style: &TableStyle) -> Result<VMTable, String> {
+        self.base.tunables().create_host_table(ty, style)
+    }
+
+    unsafe fn create_vm_table(
+        &self,
+        ty: &TableType,
+        style: &TableStyle,
+        vm_definition_location: NonNull<VMTableDefinition>,
+    ) -> Result<VMTable, String> {
+        self.base
+            .tunables()
+            .create_vm_table(ty, style, vm_definition_location)
+    }
+
+    fn vmconfig(&self) -> &VMConfig {
+        self.base.tunables().vmconfig()
+    }
+}
+
+/// Keeps track of the time the threads of a process spent running.
+///
+/// This is not the CPU time: the sandbox has no way of knowing how much time
+/// the host spends on a thread, so it is the wall-clock time each of the
+/// threads has been alive minus the time they spent blocked waiting in a
+/// syscall. Time that a thread spends in a deep sleep, or waiting for the
+/// host to schedule it, counts as running.
+///
+/// The time is read from the monotonic clock of the host. Changes that happen
+/// while the clock can't be read are not counted, the error is returned by
+/// [`RunClock::elapsed()`] instead.
+#[derive(Debug)]
+pub(crate) struct RunClock {
+    /// Time (in nanoseconds) the threads had been alive for as of `last_change`
+    alive: u128,
+    /// When the number of threads last changed
+    last_change: u128,
+    /// Time (in nanoseconds) the threads spent blocked
+    blocked: AtomicU64,
+    /// Set once the process has been told it went over the soft limit
+    pub(crate) exceeded_soft: bool,
+}
+
+impl RunClock {
+    pub fn new() -> Self {
+        // There are no threads yet, so the time until the first one starts
+        // doesn't count
+        Self {
+            alive: 0,
+            last_change: 0,
+            blocked: AtomicU64::new(0),
+            exceeded_soft: false,
+        }
+    }
+
+    /// Must be called before the number of threads changes.
+    pub fn threads_changing(&mut self, thread_count: u32) {
+        if let Ok(now) = Self::now() {
+            self.alive += now.saturating_sub(self.last_change) * thread_count as u128;
+            self.last_change = now;
+        }
+    }
+
+    /// Adds time (in nanoseconds) that a thread spent blocked.
+    pub fn add_blocked(&self, nanos: u64) {
+        self.blocked.fetch_add(nanos, Ordering::Relaxed);
+    }
+
+    /// Returns the time spent running so far.
+    pub fn elapsed(&self, thread_count: u32) -> Result<Duration, Errno> {
+        let alive =
+            self.alive + Self::now()?.saturating_sub(self.last_change) * thread_count as u128;
+        let nanos = alive.saturating_sub(self.blocked.load(Ordering::Relaxed) as u128);
+        Ok(Duration::from_nanos(nanos.min(u64::MAX as u128) as u64))
+    }
+
+    /// The current time of the clock, in nanoseconds.
+    pub fn now() -> Result<u128, Errno> {
+        platform_clock_time_get(Snapshot0Clockid::Monotonic, 1_000_000).map(|time| time as u128)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hard_limits_can_only_be_lowered() {
+        let mut limits = ResourceLimits::default();
+        limits
+            .set(RlimitResource::OpenFiles, ResourceLimit::new(64, 1024))
+            .unwrap();
+
+        // Soft limits move freely below the hard one
+        limits
+            .set(RlimitResource::OpenFiles, ResourceLimit::new(1024, 1024))
+            .unwrap();
+        assert_eq!(
+            limits.set(RlimitResource::OpenFiles, ResourceLimit::new(2048, 1024)),
+            Err(Errno::Inval)
+        );
+        assert_eq!(
+            limits.set(RlimitResource::OpenFiles, ResourceLimit::UNLIMITED),
+            Err(Errno::Perm)
+        );
+        assert_eq!(
+            limits.get(RlimitResource::OpenFiles),
+            Ok(ResourceLimit::new(1024, 1024))
+        );
+        assert_eq!(
+            limits.get(RlimitResource::Threads),
+            Ok(ResourceLimit::UNLIMITED)
+        );
+    }
+
+    #[test]
+    fn memory_limit_caps_the_maximum() {
+        let limits = ResourceLimits {
+            memory: ResourceLimit::fixed(16 * WASM_PAGE_SIZE as u64 + 100),
+            ..Default::default()
+        };
+
+        let mut ty = MemoryType::new(1, None, false);
+        limits.limit_memory(&mut ty);
+        assert_eq!(ty.maximum, Some(16.into()));
+
+        let mut ty = MemoryType::new(1, Some(8), true);
+        limits.limit_memory(&mut ty);
+        assert_eq!(ty.maximum, Some(8.into()));
+    }
+
+    #[cfg(feature = "sys-tunables")]
+    #[test]
+    fn memory_limit_caps_memories_the_module_defines() {
+        let mut engine = wasmer::Engine::default();
+        LimitingTunables::install(&mut engine);
+        let mut store = wasmer::Store::new(engine);
+        let module =
+            wasmer::Module::new(&store, r#"(module (memory (export "memory") 1))"#).unwrap();
+        let limits = ResourceLimits {
+            memory: ResourceLimit::fixed(4 * WASM_PAGE_SIZE as u64),
+            ..Default::default()
+        };
+
+        let instance = limits
+            .instantiate(|| wasmer::Instance::new(&mut store, &module, &wasmer::imports! {}))
+            .unwrap();
+        let memory = instance.exports.get_memory("memory").unwrap();
+        assert_eq!(memory.ty(&store).maximum, Some(4.into()));
+        memory.grow(&mut store, 3).unwrap();
+        assert!(memory.grow(&mut store, 1).is_err());
+
+        // Without limits the module gets the memory it asks for
+        let instance = wasmer::Instance::new(&mut store, &module, &wasmer::imports! {}).unwrap();
+        let memory = instance.exports.get_memory("memory").unwrap();
+        assert_eq!(memory.ty(&store).maximum, None);
+    }
+}
@@ -1,6 +1,7 @@
 //! OS task management for processes and threads.
 
 pub mod control_plane;
+pub mod limits;
 pub mod process;
 pub mod signal;
 mod task_join_handle;
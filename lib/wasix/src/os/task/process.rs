@@ -5,24 +5,32 @@ use std::{
     convert::TryInto,
     sync::{
         atomic::{AtomicU32, Ordering},
-        Arc, RwLock, RwLockReadGuard, RwLockWriteGuard, Weak,
+        Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard, Weak,
     },
     time::Duration,
 };
+use tokio::sync::Notify;
 use tracing::trace;
 use wasmer_wasix_types::{
     types::Signal,
-    wasi::{Errno, ExitCode, Snapshot0Clockid},
+    wasi::{
+        Errno, ExitCode, RlimitResource, SigDisposition, Sigaction, Siginfo, SignalCode,
+        Snapshot0Clockid,
+    },
 };
 
 use crate::{
-    os::task::signal::WasiSignalInterval, syscalls::platform_clock_time_get, WasiThread,
-    WasiThreadHandle, WasiThreadId,
+    fs::MemoryMaps, os::task::signal::WasiSignalInterval, syscalls::platform_clock_time_get,
+    WasiThread, WasiThreadHandle, WasiThreadId,
 };
 
 use super::{
     control_plane::{ControlPlaneError, WasiControlPlaneHandle},
-    signal::{SignalDeliveryError, SignalHandlerAbi},
+    limits::{ResourceLimits, RunClock},
+    signal::{
+        default_sigaction, default_siginfo, new_siginfo, SignalDelivery, SignalDeliveryError,
+        SignalHandlerAbi, STOP_SIGNALS,
+    },
     task_join_handle::OwnedTaskStatus,
 };
 
@@ -90,6 +98,12 @@ pub struct WasiProcess {
     pub(crate) finished: Arc<OwnedTaskStatus>,
     /// Number of threads waiting for children to exit
     pub(crate) waiting: Arc<AtomicU32>,
+    /// Limits on the resources this process can use
+    pub(crate) limits: Arc<RwLock<ResourceLimits>>,
+    /// Notified whenever the process is stopped or continued
+    pub(crate) job_changed: Arc<Notify>,
+    /// Files that are mapped into the memory of the process
+    pub(crate) memory_maps: Arc<Mutex<MemoryMaps>>,
 }
 
 // TODO: fields should be private and only accessed via methods.
@@ -103,8 +117,20 @@ pub struct WasiProcessInner {
     pub thread_count: u32,
     /// Signals that will be triggered at specific intervals
     pub signal_intervals: HashMap<Signal, WasiSignalInterval>,
+    /// How the signals that were set with `proc_sigaction` are handled
+    pub(crate) signal_actions: HashMap<Signal, Sigaction>,
     /// List of all the children spawned from this thread
     pub children: Vec<WasiProcess>,
+    /// Time the threads of this process spent running
+    pub(crate) run_clock: RunClock,
+    /// The process group that this process belongs to
+    pub(crate) pgid: WasiProcessId,
+    /// The session that the process group belongs to
+    pub(crate) sid: WasiProcessId,
+    /// The signal that stopped the process, if it is stopped
+    pub(crate) stopped: Option<Signal>,
+    /// Set once the parent was told that the process stopped
+    pub(crate) stop_reported: bool,
 }
 
 // TODO: why do we need this, how is it used?
@@ -138,15 +164,40 @@ impl WasiProcess {
                 threads: Default::default(),
                 thread_count: Default::default(),
                 signal_intervals: Default::default(),
+                signal_actions: Default::default(),
                 children: Default::default(),
+                run_clock: RunClock::new(),
+                pgid: pid,
+                sid: pid,
+                stopped: None,
+                stop_reported: false,
             })),
             finished: Arc::new(OwnedTaskStatus::default()),
             waiting: Arc::new(AtomicU32::new(0)),
+            limits: Default::default(),
+            job_changed: Arc::new(Notify::new()),
+            memory_maps: Default::default(),
         }
     }
 
+    /// Sets the process ID, a process starts out as the leader of its own
+    /// process group and session
     pub(super) fn set_pid(&mut self, pid: WasiProcessId) {
         self.pid = pid;
+        let mut inner = self.inner.write().unwrap();
+        inner.pid = pid;
+        inner.pgid = pid;
+        inner.sid = pid;
+    }
+
+    /// Makes this process a child of `parent`, which puts it in the process
+    /// group and session of its parent
+    pub(super) fn set_parent(&mut self, parent: &WasiProcess) {
+        self.parent = Some(Arc::downgrade(&parent.inner));
+        let (pgid, sid) = (parent.pgid(), parent.sid());
+        let mut inner = self.inner.write().unwrap();
+        inner.pgid = pgid;
+        inner.sid = sid;
     }
 
     /// Gets the process ID of this process
@@ -154,6 +205,48 @@ impl WasiProcess {
         self.pid
     }
 
+    /// Returns the resource limits of this process
+    pub fn limits(&self) -> ResourceLimits {
+        self.limits.read().unwrap().clone()
+    }
+
+    /// Replaces the resource limits of this process
+    pub fn set_limits(&self, limits: ResourceLimits) {
+        *self.limits.write().unwrap() = limits;
+    }
+
+    /// Returns the files that are mapped into the memory of this process
+    pub(crate) fn memory_maps(&self) -> MutexGuard<'_, MemoryMaps> {
+        self.memory_maps.lock().unwrap()
+    }
+
+    /// Returns the time the threads of this process spent running so far,
+    /// see [`ResourceLimits::run_time`], or the error of the host clock when
+    /// it can't be read
+    pub fn run_time(&self) -> Result<Duration, Errno> {
+        let inner = self.inner.read().unwrap();
+        inner.run_clock.elapsed(inner.thread_count)
+    }
+
+    /// Starts timing a thread of this process that is about to block, the
+    /// time until the guard is dropped does not count towards the run time
+    pub(crate) fn start_blocking(&self) -> BlockingGuard {
+        BlockingGuard {
+            inner: self.inner.clone(),
+            started: RunClock::now(),
+        }
+    }
+
+    /// Returns the number of children of this process that are still running
+    pub fn running_children(&self) -> usize {
+        let inner = self.inner.read().unwrap();
+        inner
+            .children
+            .iter()
+            .filter(|child| child.try_join().is_none())
+            .count()
+    }
+
     /// Gets the process ID of the parent process
     pub fn ppid(&self) -> WasiProcessId {
         self.parent
@@ -164,6 +257,29 @@ impl WasiProcess {
             .unwrap_or(WasiProcessId(0))
     }
 
+    /// Gets the ID of the process group this process belongs to
+    pub fn pgid(&self) -> WasiProcessId {
+        self.inner.read().unwrap().pgid
+    }
+
+    /// Gets the ID of the session this process belongs to
+    pub fn sid(&self) -> WasiProcessId {
+        self.inner.read().unwrap().sid
+    }
+
+    /// Moves the process into another process group
+    pub(crate) fn set_pgid(&self, pgid: WasiProcessId) {
+        self.inner.write().unwrap().pgid = pgid;
+    }
+
+    /// Makes the process the leader of a new session and of a new process
+    /// group within it
+    pub(crate) fn start_session(&self) {
+        let mut inner = self.inner.write().unwrap();
+        inner.pgid = inner.pid;
+        inner.sid = inner.pid;
+    }
+
     /// Gains write access to the process internals
     // TODO: Make this private, all inner access should be exposed with methods.
     pub fn write(&self) -> RwLockWriteGuard<WasiProcessInner> {
@@ -187,6 +303,17 @@ impl WasiProcess {
             inner.thread_count == 0
         };
 
+        // The main thread always gets to run, other threads count against the limit
+        if !is_main {
+            if let Some(max) = self.limits.read().unwrap().threads.get() {
+                if self.active_threads() as u64 >= max {
+                    return Err(ControlPlaneError::ResourceLimitReached {
+                        resource: RlimitResource::Threads,
+                    });
+                }
+            }
+        }
+
         // Generate a new process ID (this is because the process ID and thread ID
         // address space must not overlap in libc). For the main proecess the TID=PID
         let tid: WasiThreadId = if is_main {
@@ -207,6 +334,8 @@ impl WasiProcess {
         // Insert the thread into the pool
         let ctrl = WasiThread::new(self.pid(), tid, is_main, finished, task_count_guard);
         inner.threads.insert(tid, ctrl.clone());
+        let thread_count = inner.thread_count;
+        inner.run_clock.threads_changing(thread_count);
         inner.thread_count += 1;
 
         Ok(WasiThreadHandle::new(ctrl, &self.inner))
@@ -220,6 +349,11 @@ impl WasiProcess {
 
     /// Signals a particular thread in the process
     pub fn signal_thread(&self, tid: &WasiThreadId, signal: Signal) {
+        self.signal_thread_with_info(tid, signal, default_siginfo(signal))
+    }
+
+    /// Signals a particular thread in the process along with the details of the signal
+    pub fn signal_thread_with_info(&self, tid: &WasiThreadId, signal: Signal, info: Siginfo) {
         // Sometimes we will signal the process rather than the thread hence this libc hardcoded value
         let mut tid = tid.raw();
         if tid == 1073741823 {
@@ -230,9 +364,13 @@ impl WasiProcess {
         let pid = self.pid();
         tracing::trace!(%pid, %tid, "signal-thread({:?})", signal);
 
+        self.apply_job_control(signal);
+        if self.ignores_signal(signal) {
+            return;
+        }
         let inner = self.inner.read().unwrap();
         if let Some(thread) = inner.threads.get(&tid) {
-            thread.signal(signal);
+            thread.signal_with_info(signal, info);
         } else {
             trace!(
                 "wasi[{}]::lost-signal(tid={}, sig={:?})",
@@ -243,8 +381,19 @@ impl WasiProcess {
         }
     }
 
-    /// Signals all the threads in this process
+    /// Signals the process
     pub fn signal_process(&self, signal: Signal) {
+        self.signal_process_with_info(signal, default_siginfo(signal))
+    }
+
+    /// Signals the process on behalf of another process
+    pub fn signal_process_from(&self, signal: Signal, sender: WasiProcessId) {
+        self.signal_process_with_info(signal, new_siginfo(signal, SignalCode::User, Some(sender)))
+    }
+
+    /// Signals the process along with the details of the signal, which like
+    /// on POSIX is delivered to one of the threads that has not blocked it
+    pub fn signal_process_with_info(&self, signal: Signal, info: Siginfo) {
         let pid = self.pid();
         tracing::trace!(%pid, "signal-process({:?})", signal);
 
@@ -253,7 +402,7 @@ impl WasiProcess {
             if self.waiting.load(Ordering::Acquire) > 0 {
                 let mut triggered = false;
                 for child in inner.children.iter() {
-                    child.signal_process(signal);
+                    child.signal_process_with_info(signal, info);
                     triggered = true;
                 }
                 if triggered {
@@ -261,12 +410,213 @@ impl WasiProcess {
                 }
             }
         }
+        self.deliver_signal(signal, info);
+    }
+
+    /// Delivers a signal to the process, which unlike
+    /// [`WasiProcess::signal_process_with_info`] never passes it on to the
+    /// children that the process is waiting on
+    pub(crate) fn deliver_signal(&self, signal: Signal, info: Siginfo) {
+        let pid = self.pid();
+        self.apply_job_control(signal);
+        if self.ignores_signal(signal) {
+            return;
+        }
+
+        // The main thread takes the signal if it can, when every thread has
+        // blocked it then it waits on the main thread until it is unblocked
+        let inner = self.inner.read().unwrap();
+        let main = inner.threads.get(&WasiThreadId::from(pid.raw()));
+        let thread = main
+            .filter(|thread| !thread.blocks_signal(signal))
+            .or_else(|| {
+                inner
+                    .threads
+                    .values()
+                    .find(|thread| !thread.blocks_signal(signal))
+            })
+            .or(main)
+            .or_else(|| inner.threads.values().next());
+        if let Some(thread) = thread {
+            thread.signal_with_info(signal, info);
+        }
+    }
+
+    /// Applies the effects that job control signals have as soon as they are
+    /// sent, `SIGCONT` and `SIGKILL` continue a stopped process even when they
+    /// are blocked or ignored
+    fn apply_job_control(&self, signal: Signal) {
+        if matches!(signal, Signal::Sigcont | Signal::Sigkill) {
+            {
+                let inner = self.inner.read().unwrap();
+                for thread in inner.threads.values() {
+                    for stop in STOP_SIGNALS {
+                        thread.discard_signal(stop);
+                    }
+                }
+            }
+            self.resume();
+        } else if STOP_SIGNALS.contains(&signal) {
+            let inner = self.inner.read().unwrap();
+            for thread in inner.threads.values() {
+                thread.discard_signal(Signal::Sigcont);
+            }
+        }
+    }
+
+    /// Stops the process, its threads wait in their next syscall until it
+    /// is continued and the parent is told about it with a `SIGCHLD`
+    pub fn stop(&self, signal: Signal) {
+        {
+            let mut inner = self.inner.write().unwrap();
+            if inner.stopped.is_some() {
+                return;
+            }
+            inner.stopped = Some(signal);
+            inner.stop_reported = false;
+        }
+        trace!(pid = %self.pid(), "process stopped by {:?}", signal);
+        self.job_changed.notify_waiters();
+        self.signal_parent();
+    }
+
+    /// Continues the process if it was stopped
+    pub fn resume(&self) {
+        let was_stopped = self.inner.write().unwrap().stopped.take().is_some();
+        if was_stopped {
+            trace!(pid = %self.pid(), "process continued");
+            self.job_changed.notify_waiters();
+            self.signal_parent();
+        }
+    }
+
+    /// Returns the signal that stopped the process, if it is stopped
+    pub fn stopped_by(&self) -> Option<Signal> {
+        self.inner.read().unwrap().stopped
+    }
+
+    /// Waits until the process is no longer stopped
+    pub async fn wait_until_continued(&self) {
+        loop {
+            let changed = self.job_changed.notified();
+            if self.stopped_by().is_none() {
+                return;
+            }
+            changed.await;
+        }
+    }
+
+    /// Waits until the process is stopped and returns the signal that
+    /// stopped it, each stop is only returned once
+    pub async fn wait_stopped(&self) -> Signal {
+        loop {
+            let changed = self.job_changed.notified();
+            {
+                let mut inner = self.inner.write().unwrap();
+                if let Some(signal) = inner.stopped {
+                    if !inner.stop_reported {
+                        inner.stop_reported = true;
+                        return signal;
+                    }
+                }
+            }
+            changed.await;
+        }
+    }
+
+    /// Sends a `SIGCHLD` to the parent of this process
+    pub(crate) fn signal_parent(&self) {
+        let ppid = self.ppid();
+        if ppid.raw() == 0 {
+            return;
+        }
+        let parent = self
+            .compute
+            .upgrade()
+            .and_then(|control_plane| control_plane.get_process(ppid));
+        if let Some(parent) = parent {
+            let info = new_siginfo(Signal::Sigchld, SignalCode::Kernel, Some(self.pid()));
+            parent.deliver_signal(Signal::Sigchld, info);
+        }
+    }
+
+    /// Signals every thread in this process, which wakes them all up
+    pub fn signal_threads(&self, signal: Signal) {
         let inner = self.inner.read().unwrap();
         for thread in inner.threads.values() {
             thread.signal(signal);
         }
     }
 
+    /// Returns how the process handles a signal, which was set with
+    /// `proc_sigaction`
+    pub fn signal_action(&self, signal: Signal) -> Option<Sigaction> {
+        let inner = self.inner.read().unwrap();
+        inner.signal_actions.get(&signal).copied()
+    }
+
+    /// Changes how the process handles a signal and returns how it was
+    /// handled before, signals that are now ignored are discarded
+    pub fn set_signal_action(&self, signal: Signal, action: Sigaction) -> Option<Sigaction> {
+        let old = {
+            let mut inner = self.inner.write().unwrap();
+            inner.signal_actions.insert(signal, action)
+        };
+        if self.ignores_signal(signal) {
+            let inner = self.inner.read().unwrap();
+            for thread in inner.threads.values() {
+                thread.discard_signal(signal);
+            }
+        }
+        old
+    }
+
+    /// Returns how the process handles every signal that was set with
+    /// `proc_sigaction`
+    pub fn signal_actions(&self) -> HashMap<Signal, Sigaction> {
+        self.inner.read().unwrap().signal_actions.clone()
+    }
+
+    /// Replaces how the process handles signals, which is used when forking
+    pub fn set_signal_actions(&self, actions: HashMap<Signal, Sigaction>) {
+        self.inner.write().unwrap().signal_actions = actions;
+    }
+
+    /// Resets the signals that have a handler to their default disposition,
+    /// which is what happens when a process execs another program
+    pub fn reset_signal_handlers(&self) {
+        let mut inner = self.inner.write().unwrap();
+        for action in inner.signal_actions.values_mut() {
+            if action.disposition == SigDisposition::Handler {
+                *action = default_sigaction();
+            }
+        }
+    }
+
+    /// Works out what to do with a signal that was delivered to one of the
+    /// threads of this process
+    pub(crate) fn signal_delivery(&self, signal: Signal, has_callback: bool) -> SignalDelivery {
+        match signal {
+            Signal::Sigkill => return SignalDelivery::Terminate,
+            Signal::Sigstop => return SignalDelivery::Stop,
+            _ => {}
+        }
+        match self.signal_action(signal) {
+            Some(action) => SignalDelivery::for_action(signal, &action, has_callback),
+            None => SignalDelivery::legacy_for(signal, has_callback),
+        }
+    }
+
+    /// Returns true if the signal would be discarded straight away
+    fn ignores_signal(&self, signal: Signal) -> bool {
+        match self.signal_action(signal) {
+            Some(action) => {
+                SignalDelivery::for_action(signal, &action, true) == SignalDelivery::Ignore
+            }
+            None => false,
+        }
+    }
+
     /// Signals one of the threads every interval
     pub fn signal_interval(&self, signal: Signal, interval: Option<Duration>, repeat: bool) {
         let mut inner = self.inner.write().unwrap();
@@ -369,21 +719,77 @@ impl WasiProcess {
         Ok(Some((child.pid, code)))
     }
 
+    /// Waits for any of the children to be stopped, see
+    /// [`WasiProcess::wait_stopped`]
+    pub async fn join_stopped_child(&self) -> (WasiProcessId, Signal) {
+        let children: Vec<_> = {
+            let inner = self.inner.read().unwrap();
+            inner.children.clone()
+        };
+        if children.is_empty() {
+            return futures::future::pending().await;
+        }
+
+        let waits = children.into_iter().map(|child| {
+            Box::pin(async move {
+                let signal = child.wait_stopped().await;
+                (child.pid, signal)
+            })
+        });
+        futures::future::select_all(waits).await.0
+    }
+
     /// Terminate the process and all its threads
     pub fn terminate(&self, exit_code: ExitCode) {
         // FIXME: this is wrong, threads might still be running!
         // Need special logic for the main thread.
-        let guard = self.inner.read().unwrap();
-        for thread in guard.threads.values() {
-            thread.set_status_finished(Ok(exit_code))
+        {
+            let guard = self.inner.read().unwrap();
+            for thread in guard.threads.values() {
+                thread.set_status_finished(Ok(exit_code))
+            }
         }
+
+        // Threads that are stopped need to wake up so they can exit
+        self.inner.write().unwrap().stopped = None;
+        self.job_changed.notify_waiters();
+    }
+}
+
+/// Guard that records how long a thread was blocked for, see
+/// [`WasiProcess::start_blocking`]
+pub(crate) struct BlockingGuard {
+    inner: Arc<RwLock<WasiProcessInner>>,
+    started: Result<u128, Errno>,
+}
+
+impl Drop for BlockingGuard {
+    fn drop(&mut self) {
+        // The error surfaces when the run time is read
+        let (Ok(started), Ok(now)) = (self.started, RunClock::now()) else {
+            return;
+        };
+        let blocked = now.saturating_sub(started);
+        let blocked = blocked.min(u64::MAX as u128) as u64;
+        self.inner.read().unwrap().run_clock.add_blocked(blocked);
     }
 }
 
 impl SignalHandlerAbi for WasiProcess {
     fn signal(&self, sig: u8) -> Result<(), SignalDeliveryError> {
         if let Ok(sig) = sig.try_into() {
-            self.signal_process(sig);
+            // Signals from the terminal go to the foreground process group
+            // once the session picked one with `tty_setpgrp`
+            let control_plane = self.compute.upgrade();
+            let foreground = control_plane
+                .as_ref()
+                .and_then(|control_plane| control_plane.foreground_group(self.sid()));
+            match (control_plane, foreground) {
+                (Some(control_plane), Some(pgid)) => {
+                    control_plane.signal_group(pgid, sig, default_siginfo(sig));
+                }
+                _ => self.signal_process(sig),
+            }
             Ok(())
         } else {
             Err(SignalDeliveryError)
@@ -1,6 +1,22 @@
-use std::time::Duration;
+use std::{collections::HashMap, time::Duration};
 
-use wasmer_wasix_types::types::Signal;
+use wasmer_wasix_types::{
+    types::Signal,
+    wasi::{SigDisposition, Sigaction, SigactionFlags, Siginfo, SignalCode, SignalStack, Sigset},
+};
+
+use super::process::WasiProcessId;
+
+/// Signals that can not be caught, blocked or ignored
+pub const UNBLOCKABLE_SIGNALS: Sigset = (1 << Signal::Sigkill as u8) | (1 << Signal::Sigstop as u8);
+
+/// Signals that stop the process by default, these are used for job control
+pub const STOP_SIGNALS: [Signal; 4] = [
+    Signal::Sigstop,
+    Signal::Sigtstp,
+    Signal::Sigttin,
+    Signal::Sigttou,
+];
 
 #[derive(thiserror::Error, Debug)]
 #[error("Signal could not be delivered")]
@@ -26,3 +42,101 @@ pub struct WasiSignalInterval {
     /// Last time that a signal was triggered
     pub last_signal: u128,
 }
+
+/// Returns the set that only holds `signal`
+pub fn sigset_of(signal: Signal) -> Sigset {
+    1 << signal as u8
+}
+
+/// The details of a signal that did not come with any
+pub fn default_siginfo(signal: Signal) -> Siginfo {
+    new_siginfo(signal, SignalCode::Kernel, None)
+}
+
+/// The details of a signal raised by `sender`, or by the runtime itself
+pub fn new_siginfo(signal: Signal, code: SignalCode, sender: Option<WasiProcessId>) -> Siginfo {
+    Siginfo {
+        value: 0,
+        pid: sender.map(|pid| pid.raw()).unwrap_or_default(),
+        uid: 0,
+        code,
+        signo: signal as u32,
+    }
+}
+
+/// The disposition that a process starts with for every signal
+pub fn default_sigaction() -> Sigaction {
+    Sigaction {
+        mask: 0,
+        disposition: SigDisposition::Default,
+        flags: SigactionFlags::empty(),
+    }
+}
+
+/// What a thread does with a signal that is delivered to it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SignalDelivery {
+    /// The process is terminated
+    Terminate,
+    /// The signal is discarded
+    Ignore,
+    /// The signal callback is invoked
+    Handler(Sigaction),
+    /// The process is stopped until it gets a `SIGCONT`
+    Stop,
+}
+
+impl SignalDelivery {
+    /// The default action for a signal
+    pub fn default_for(signal: Signal) -> Self {
+        match signal {
+            Signal::Signone
+            | Signal::Sigchld
+            | Signal::Sigcont
+            | Signal::Sigurg
+            | Signal::Sigwinch => Self::Ignore,
+            Signal::Sigstop | Signal::Sigtstp | Signal::Sigttin | Signal::Sigttou => Self::Stop,
+            _ => Self::Terminate,
+        }
+    }
+
+    /// The action for a signal whose disposition was never set, which keeps
+    /// the behavior that guests built before `proc_sigaction` existed expect
+    pub fn legacy_for(signal: Signal, has_callback: bool) -> Self {
+        match signal {
+            Signal::Sigkill => Self::Terminate,
+            Signal::Sigstop => Self::Stop,
+            _ if has_callback => Self::Handler(Sigaction {
+                disposition: SigDisposition::Handler,
+                ..default_sigaction()
+            }),
+            Signal::Sigint | Signal::Sigquit | Signal::Sigabrt => Self::Terminate,
+            Signal::Sigtstp | Signal::Sigttin | Signal::Sigttou => Self::Stop,
+            _ => Self::Ignore,
+        }
+    }
+
+    /// The action for a signal with the disposition `action`
+    pub fn for_action(signal: Signal, action: &Sigaction, has_callback: bool) -> Self {
+        match action.disposition {
+            SigDisposition::Ignore => Self::Ignore,
+            SigDisposition::Handler if has_callback => Self::Handler(*action),
+            _ => Self::default_for(signal),
+        }
+    }
+}
+
+/// The signal state of a thread, other than the signals themselves
+#[derive(Debug, Default)]
+pub(crate) struct ThreadSignalState {
+    /// Details of the signals that are waiting to be processed
+    pub pending_info: HashMap<Signal, Siginfo>,
+    /// Details of the signals whose handlers are running, the innermost last
+    pub handling: Vec<Siginfo>,
+    /// The alternate stack that handlers can run on
+    pub stack: Option<SignalStack>,
+    /// Set while a handler is running on the alternate stack
+    pub on_stack: bool,
+    /// Number of handlers that have been invoked
+    pub handled: u64,
+}
@@ -1,7 +1,10 @@
 use std::{
     collections::HashMap,
     ops::{Deref, DerefMut},
-    sync::{Arc, Mutex, RwLock, Weak},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, RwLock, Weak,
+    },
     task::Waker,
 };
 
@@ -9,7 +12,7 @@ use bytes::{Bytes, BytesMut};
 use wasmer::{ExportError, InstantiationError, MemoryError};
 use wasmer_wasix_types::{
     types::Signal,
-    wasi::{Errno, ExitCode},
+    wasi::{Errno, ExitCode, Siginfo, SignalStack, Sigset},
 };
 
 use crate::{
@@ -19,6 +22,7 @@ use crate::{
 
 use super::{
     control_plane::TaskCountGuard,
+    signal::{default_siginfo, sigset_of, ThreadSignalState, UNBLOCKABLE_SIGNALS},
     task_join_handle::{OwnedTaskStatus, TaskJoinHandle},
 };
 
@@ -168,6 +172,8 @@ struct WasiThreadState {
     pid: WasiProcessId,
     id: WasiThreadId,
     signals: Mutex<(Vec<Signal>, Vec<Waker>)>,
+    signal_mask: AtomicU64,
+    signal_state: Mutex<ThreadSignalState>,
     stack: Mutex<ThreadStack>,
     status: Arc<OwnedTaskStatus>,
 
@@ -193,6 +199,8 @@ impl WasiThread {
                 id,
                 status,
                 signals: Mutex::new((Vec::new(), Vec::new())),
+                signal_mask: AtomicU64::new(0),
+                signal_state: Mutex::new(ThreadSignalState::default()),
                 stack: Mutex::new(ThreadStack::default()),
                 _task_count_guard: guard,
             }),
@@ -262,18 +270,76 @@ impl WasiThread {
 
     /// Adds a signal for this thread to process
     pub fn signal(&self, signal: Signal) {
+        self.signal_with_info(signal, default_siginfo(signal));
+    }
+
+    /// Adds a signal for this thread to process along with its details
+    pub fn signal_with_info(&self, signal: Signal, info: Siginfo) {
         let mut guard = self.state.signals.lock().unwrap();
         if !guard.0.contains(&signal) {
             guard.0.push(signal);
+            self.state
+                .signal_state
+                .lock()
+                .unwrap()
+                .pending_info
+                .insert(signal, info);
+        }
+        if !self.blocks_signal(signal) {
+            guard.1.drain(..).for_each(|w| w.wake());
+        }
+    }
+
+    /// Returns the signals that this thread has blocked
+    pub fn signal_mask(&self) -> Sigset {
+        self.state.signal_mask.load(Ordering::Acquire)
+    }
+
+    /// Changes the signals that this thread has blocked and returns the
+    /// previous mask, `SIGKILL` and `SIGSTOP` can never be blocked
+    pub fn set_signal_mask(&self, mask: Sigset) -> Sigset {
+        let mut guard = self.state.signals.lock().unwrap();
+        let old = self
+            .state
+            .signal_mask
+            .swap(mask & !UNBLOCKABLE_SIGNALS, Ordering::AcqRel);
+
+        // Wake up anyone waiting on signals that are no longer blocked
+        if guard.0.iter().any(|s| !self.blocks_signal(*s)) {
+            guard.1.drain(..).for_each(|w| w.wake());
         }
-        guard.1.drain(..).for_each(|w| w.wake());
+        old
+    }
+
+    /// Returns true if this thread has blocked the signal
+    pub fn blocks_signal(&self, signal: Signal) -> bool {
+        self.signal_mask() & sigset_of(signal) != 0
+    }
+
+    /// Returns the signals that are waiting to be processed, including the
+    /// ones that are blocked
+    pub fn pending_signals(&self) -> Sigset {
+        let guard = self.state.signals.lock().unwrap();
+        guard.0.iter().fold(0, |set, s| set | sigset_of(*s))
+    }
+
+    /// Discards a signal that is waiting to be processed
+    pub fn discard_signal(&self, signal: Signal) {
+        let mut guard = self.state.signals.lock().unwrap();
+        guard.0.retain(|s| *s != signal);
+        self.state
+            .signal_state
+            .lock()
+            .unwrap()
+            .pending_info
+            .remove(&signal);
     }
 
     /// Returns all the signals that are waiting to be processed
     pub fn has_signal(&self, signals: &[Signal]) -> bool {
         let guard = self.state.signals.lock().unwrap();
         for s in guard.0.iter() {
-            if signals.contains(s) {
+            if signals.contains(s) && !self.blocks_signal(*s) {
                 return true;
             }
         }
@@ -283,8 +349,7 @@ impl WasiThread {
     /// Returns all the signals that are waiting to be processed
     pub fn pop_signals_or_subscribe(&self, waker: &Waker) -> Option<Vec<Signal>> {
         let mut guard = self.state.signals.lock().unwrap();
-        let mut ret = Vec::new();
-        std::mem::swap(&mut ret, &mut guard.0);
+        let ret = self.take_unblocked(&mut guard.0);
         match ret.is_empty() {
             true => {
                 if !guard.1.iter().any(|w| w.will_wake(waker)) {
@@ -299,7 +364,7 @@ impl WasiThread {
     /// Returns all the signals that are waiting to be processed
     pub fn has_signals_or_subscribe(&self, waker: &Waker) -> bool {
         let mut guard = self.state.signals.lock().unwrap();
-        let has_signals = !guard.0.is_empty();
+        let has_signals = guard.0.iter().any(|s| !self.blocks_signal(*s));
         if !has_signals && !guard.1.iter().any(|w| w.will_wake(waker)) {
             guard.1.push(waker.clone());
         }
@@ -309,11 +374,77 @@ impl WasiThread {
     /// Returns all the signals that are waiting to be processed
     pub fn pop_signals(&self) -> Vec<Signal> {
         let mut guard = self.state.signals.lock().unwrap();
+        self.take_unblocked(&mut guard.0)
+    }
+
+    /// Removes the signals that are not blocked, blocked ones stay pending
+    /// until they are unblocked
+    fn take_unblocked(&self, signals: &mut Vec<Signal>) -> Vec<Signal> {
+        let mask = self.signal_mask();
         let mut ret = Vec::new();
-        std::mem::swap(&mut ret, &mut guard.0);
+        signals.retain(|s| {
+            if mask & sigset_of(*s) == 0 {
+                ret.push(*s);
+                false
+            } else {
+                true
+            }
+        });
         ret
     }
 
+    /// Takes the details of a signal that was popped
+    pub fn take_siginfo(&self, signal: Signal) -> Siginfo {
+        self.state
+            .signal_state
+            .lock()
+            .unwrap()
+            .pending_info
+            .remove(&signal)
+            .unwrap_or_else(|| default_siginfo(signal))
+    }
+
+    /// Returns the details of the signal whose handler is running
+    pub fn current_siginfo(&self) -> Option<Siginfo> {
+        let state = self.state.signal_state.lock().unwrap();
+        state.handling.last().copied()
+    }
+
+    /// Records that the handler of a signal is about to run, returns false
+    /// if the thread was already on its alternate stack
+    pub(crate) fn enter_signal_handler(&self, info: Siginfo, on_stack: bool) -> bool {
+        let mut state = self.state.signal_state.lock().unwrap();
+        state.handling.push(info);
+        state.handled += 1;
+        on_stack && !std::mem::replace(&mut state.on_stack, true)
+    }
+
+    /// Records that the handler of a signal has returned
+    pub(crate) fn leave_signal_handler(&self, left_stack: bool) {
+        let mut state = self.state.signal_state.lock().unwrap();
+        state.handling.pop();
+        if left_stack {
+            state.on_stack = false;
+        }
+    }
+
+    /// Number of signal handlers that this thread has invoked
+    pub fn signals_handled(&self) -> u64 {
+        self.state.signal_state.lock().unwrap().handled
+    }
+
+    /// Returns the alternate stack that signal handlers run on and whether
+    /// a handler is currently running on it
+    pub fn signal_stack(&self) -> (Option<SignalStack>, bool) {
+        let state = self.state.signal_state.lock().unwrap();
+        (state.stack, state.on_stack)
+    }
+
+    /// Changes the alternate stack that signal handlers run on
+    pub fn set_signal_stack(&self, stack: Option<SignalStack>) {
+        self.state.signal_state.lock().unwrap().stack = stack;
+    }
+
     /// Adds a stack snapshot and removes dead ones
     pub fn add_snapshot(
         &self,
@@ -440,6 +571,16 @@ impl WasiThread {
         let mut stack_guard = self.state.stack.lock().unwrap();
         std::mem::swap(stack_guard.deref_mut(), &mut stack);
     }
+
+    /// Copies the signal mask and alternate signal stack of another thread,
+    /// signals that are waiting to be processed are not copied
+    pub fn copy_signal_state_from(&self, other: &WasiThread) {
+        self.set_signal_mask(other.signal_mask());
+        let (stack, on_stack) = other.signal_stack();
+        let mut state = self.state.signal_state.lock().unwrap();
+        state.stack = stack;
+        state.on_stack = on_stack;
+    }
 }
 
 #[derive(Debug)]
@@ -483,6 +624,8 @@ impl Drop for WasiThreadHandleProtected {
             if let Some(ctrl) = inner.threads.remove(&id) {
                 ctrl.set_status_finished(Ok(Errno::Success.into()));
             }
+            let thread_count = inner.thread_count;
+            inner.run_clock.threads_changing(thread_count);
             inner.thread_count -= 1;
         }
     }
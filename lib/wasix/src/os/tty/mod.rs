@@ -14,6 +14,7 @@ use super::task::signal::SignalHandlerAbi;
 
 const TTY_MOBILE_PAUSE: u128 = std::time::Duration::from_millis(200).as_nanos();
 
+pub mod pty;
 pub mod tty_sys;
 
 #[derive(Debug)]
@@ -226,10 +227,24 @@ impl Tty {
         })
     }
 
-    fn on_ctrl_c(mut self, _data: Cow<'static, [u8]>) -> BoxFuture<'static, Self> {
+    fn on_ctrl_c(self, _data: Cow<'static, [u8]>) -> BoxFuture<'static, Self> {
+        self.raise_signal(Signal::Sigint)
+    }
+
+    fn on_ctrl_z(self, _data: Cow<'static, [u8]>) -> BoxFuture<'static, Self> {
+        self.raise_signal(Signal::Sigtstp)
+    }
+
+    fn on_ctrl_backslash(self, _data: Cow<'static, [u8]>) -> BoxFuture<'static, Self> {
+        self.raise_signal(Signal::Sigquit)
+    }
+
+    /// Sends a signal to the foreground processes and throws away the line
+    /// that was being typed
+    fn raise_signal(mut self, signal: Signal) -> BoxFuture<'static, Self> {
         Box::pin(async move {
             if let Some(signaler) = self.signaler.as_ref() {
-                signaler.signal(Signal::Sigint as u8).ok();
+                signaler.signal(signal as u8).ok();
 
                 let (echo, _line_buffering) = {
                     let options = self.options.inner.lock().unwrap();
@@ -364,6 +379,8 @@ impl Tty {
             return match String::from_utf8_lossy(data.as_ref()).as_ref() {
                 "\r" | "\u{000A}" => self.on_enter(data),
                 "\u{0003}" => self.on_ctrl_c(data),
+                "\u{001A}" => self.on_ctrl_z(data),
+                "\u{001C}" => self.on_ctrl_backslash(data),
                 "\u{007F}" => self.on_backspace(data),
                 "\u{0009}" => self.on_tab(data),
                 "\u{001B}\u{005B}\u{0044}" => self.on_cursor_left(data),
@@ -0,0 +1,432 @@
+//! Pseudo-terminals, which connect a program that acts as the terminal (the
+//! master side), such as `script` or an SSH server, to the programs that run
+//! on that terminal (the slave side).
+//!
+//! Whatever is written to the master goes through the same line discipline
+//! as the console (see [`Tty`]) before the slave can read it, so it is echoed
+//! back and buffered into lines according to the [`TtyOptions`] of the
+//! pseudo-terminal. Whatever is written to the slave can be read from the
+//! master.
+
+use std::{
+    collections::VecDeque,
+    io::{self, SeekFrom},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use futures::future::BoxFuture;
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
+use virtual_fs::{FsError, Pipe, VirtualFile};
+use wasmer_wasix_types::wasi::Signal;
+
+use super::{InputEvent, Tty, TtyBridge, TtyOptions, WasiTtyState};
+use crate::{
+    os::task::{
+        control_plane::WasiControlPlaneHandle,
+        signal::{default_siginfo, SignalDeliveryError, SignalHandlerAbi},
+    },
+    runtime::task_manager::InlineWaker,
+    WasiProcessId,
+};
+
+/// A pseudo-terminal, which is shared by its master and slave sides
+#[derive(Debug, Clone)]
+pub struct Pty {
+    inner: Arc<PtyInner>,
+}
+
+#[derive(Debug)]
+struct PtyInner {
+    /// The line discipline that processes what is written to the master
+    line_discipline: Mutex<LineDiscipline>,
+    /// The options of this terminal
+    options: TtyOptions,
+    /// The process group that gets the signals raised by this terminal
+    foreground: Arc<Mutex<Option<Foreground>>>,
+}
+
+#[derive(Debug)]
+struct LineDiscipline {
+    /// Taken out by the writer that runs input through it
+    tty: Option<Tty>,
+    /// Input that was written while the line discipline was taken out
+    pending: VecDeque<Vec<u8>>,
+}
+
+/// A process group in the foreground of a pseudo-terminal
+#[derive(Debug, Clone)]
+struct Foreground {
+    control_plane: WasiControlPlaneHandle,
+    pgid: WasiProcessId,
+}
+
+impl Foreground {
+    fn signal(&self, signal: Signal) {
+        if let Some(control_plane) = self.control_plane.upgrade() {
+            control_plane.signal_group(self.pgid, signal, default_siginfo(signal));
+        }
+    }
+}
+
+/// Sends the signals raised by the line discipline to the foreground group
+#[derive(Debug)]
+struct PtySignaler {
+    foreground: Arc<Mutex<Option<Foreground>>>,
+}
+
+impl SignalHandlerAbi for PtySignaler {
+    fn signal(&self, signal: u8) -> Result<(), SignalDeliveryError> {
+        let signal: Signal = signal.try_into().map_err(|_| SignalDeliveryError)?;
+        if let Some(foreground) = self.foreground.lock().unwrap().as_ref() {
+            foreground.signal(signal);
+        }
+        Ok(())
+    }
+}
+
+impl Pty {
+    /// Creates a new pseudo-terminal and returns its master and slave sides
+    pub fn open(options: TtyOptions) -> (PtyMaster, PtySlave) {
+        let (master, slave) = Pipe::channel();
+        let foreground = Arc::new(Mutex::new(None));
+
+        // The line discipline passes the input on to the slave and echoes
+        // it back to the master
+        let mut tty = Tty::new(
+            Box::new(master.clone()),
+            Box::new(slave.clone()),
+            false,
+            options.clone(),
+        );
+        tty.set_signaler(Box::new(PtySignaler {
+            foreground: foreground.clone(),
+        }));
+
+        let pty = Pty {
+            inner: Arc::new(PtyInner {
+                line_discipline: Mutex::new(LineDiscipline {
+                    tty: Some(tty),
+                    pending: VecDeque::new(),
+                }),
+                options,
+                foreground,
+            }),
+        };
+        (
+            PtyMaster {
+                pty: pty.clone(),
+                pipe: master,
+            },
+            PtySlave { pty, pipe: slave },
+        )
+    }
+
+    /// Returns the options of this terminal, changes to them take effect
+    /// straight away
+    pub fn options(&self) -> TtyOptions {
+        self.inner.options.clone()
+    }
+
+    /// Returns the process group that gets the signals raised by this
+    /// terminal, such as `SIGINT` for Ctrl-C
+    pub fn foreground_group(&self) -> Option<WasiProcessId> {
+        let foreground = self.inner.foreground.lock().unwrap();
+        foreground.as_ref().map(|foreground| foreground.pgid)
+    }
+
+    /// Changes the process group that gets the signals raised by this terminal
+    pub fn set_foreground_group(&self, control_plane: WasiControlPlaneHandle, pgid: WasiProcessId) {
+        let mut foreground = self.inner.foreground.lock().unwrap();
+        foreground.replace(Foreground {
+            control_plane,
+            pgid,
+        });
+    }
+
+    /// Changes the size of the terminal, the foreground process group is
+    /// told about it with a `SIGWINCH`
+    pub fn resize(&self, cols: u32, rows: u32) {
+        let options = &self.inner.options;
+        if options.cols() == cols && options.rows() == rows {
+            return;
+        }
+        options.set_cols(cols);
+        options.set_rows(rows);
+        self.raise(Signal::Sigwinch);
+    }
+
+    /// Sends a signal to the foreground process group
+    fn raise(&self, signal: Signal) {
+        let foreground = self.inner.foreground.lock().unwrap().clone();
+        if let Some(foreground) = foreground {
+            foreground.signal(signal);
+        }
+    }
+
+    /// Runs what was written to the master through the line discipline.
+    ///
+    /// The lock is released while the line discipline runs. Input that is
+    /// written in the meantime is queued up for the writer that took the
+    /// line discipline out, so that it keeps its order.
+    fn input(&self, data: &[u8]) {
+        let mut guard = self.inner.line_discipline.lock().unwrap();
+        guard.pending.push_back(data.to_vec());
+        let mut tty = match guard.tty.take() {
+            Some(tty) => tty,
+            None => return,
+        };
+        while let Some(data) = guard.pending.pop_front() {
+            drop(guard);
+            // The line discipline only writes to pipes so it never has to wait
+            tty = InlineWaker::block_on(tty.on_event(InputEvent::Raw(data)));
+            guard = self.inner.line_discipline.lock().unwrap();
+        }
+        guard.tty.replace(tty);
+    }
+}
+
+impl TtyBridge for Pty {
+    fn reset(&self) {
+        let options = &self.inner.options;
+        options.set_echo(true);
+        options.set_line_buffering(true);
+        options.set_line_feeds(true);
+    }
+
+    fn tty_get(&self) -> WasiTtyState {
+        let options = &self.inner.options;
+        WasiTtyState {
+            cols: options.cols(),
+            rows: options.rows(),
+            // The size in pixels is not known
+            width: 0,
+            height: 0,
+            stdin_tty: true,
+            stdout_tty: true,
+            stderr_tty: true,
+            echo: options.echo(),
+            line_buffered: options.line_buffering(),
+            line_feeds: options.line_feeds(),
+        }
+    }
+
+    fn tty_set(&self, tty_state: WasiTtyState) {
+        let options = &self.inner.options;
+        options.set_echo(tty_state.echo);
+        options.set_line_buffering(tty_state.line_buffered);
+        options.set_line_feeds(tty_state.line_feeds);
+        self.resize(tty_state.cols, tty_state.rows);
+    }
+}
+
+/// The master side of a pseudo-terminal, which is used by the program that
+/// acts as the terminal
+#[derive(Debug)]
+pub struct PtyMaster {
+    pty: Pty,
+    pipe: Pipe,
+}
+
+impl PtyMaster {
+    /// Returns the pseudo-terminal that this is the master of
+    pub fn pty(&self) -> &Pty {
+        &self.pty
+    }
+}
+
+impl Drop for PtyMaster {
+    fn drop(&mut self) {
+        // Like when a terminal is closed the slave reads the end of the file
+        // and the foreground processes are hung up on
+        self.pipe.close();
+        self.pty.raise(Signal::Sighup);
+    }
+}
+
+/// The slave side of a pseudo-terminal, which is used by the programs that
+/// run on the terminal
+#[derive(Debug)]
+pub struct PtySlave {
+    pty: Pty,
+    pipe: Pipe,
+}
+
+impl PtySlave {
+    /// Returns the pseudo-terminal that this is the slave of
+    pub fn pty(&self) -> &Pty {
+        &self.pty
+    }
+}
+
+impl Drop for PtySlave {
+    fn drop(&mut self) {
+        // The master reads the end of the file once the slave is closed
+        self.pipe.close();
+    }
+}
+
+impl AsyncWrite for PtyMaster {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.pty.input(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for PtySlave {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        // Line feeds are turned into a carriage return and line feed, like
+        // the `ONLCR` flag of a real terminal
+        if self.pty.inner.options.line_feeds() && buf.contains(&b'\n') {
+            let mut data = Vec::with_capacity(buf.len() + 16);
+            for byte in buf.iter().copied() {
+                if byte == b'\n' && data.last() != Some(&b'\r') {
+                    data.push(b'\r');
+                }
+                data.push(byte);
+            }
+            return match Pin::new(&mut self.pipe).poll_write(cx, &data) {
+                Poll::Ready(Ok(_)) => Poll::Ready(Ok(buf.len())),
+                res => res,
+            };
+        }
+        Pin::new(&mut self.pipe).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.pipe).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.pipe).poll_shutdown(cx)
+    }
+}
+
+/// Implements the parts of [`VirtualFile`] that both sides share, which
+/// read from and seek in their end of the pipe
+macro_rules! impl_pty_file {
+    ($side:ty) => {
+        impl AsyncRead for $side {
+            fn poll_read(
+                mut self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+                buf: &mut ReadBuf<'_>,
+            ) -> Poll<io::Result<()>> {
+                Pin::new(&mut self.pipe).poll_read(cx, buf)
+            }
+        }
+
+        impl AsyncSeek for $side {
+            fn start_seek(mut self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+                Pin::new(&mut self.pipe).start_seek(position)
+            }
+
+            fn poll_complete(
+                mut self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+            ) -> Poll<io::Result<u64>> {
+                Pin::new(&mut self.pipe).poll_complete(cx)
+            }
+        }
+
+        impl VirtualFile for $side {
+            fn last_accessed(&self) -> u64 {
+                0
+            }
+
+            fn last_modified(&self) -> u64 {
+                0
+            }
+
+            fn created_time(&self) -> u64 {
+                0
+            }
+
+            fn size(&self) -> u64 {
+                0
+            }
+
+            fn set_len(&mut self, _new_size: u64) -> virtual_fs::Result<()> {
+                Err(FsError::PermissionDenied)
+            }
+
+            fn unlink(&mut self) -> BoxFuture<'static, virtual_fs::Result<()>> {
+                Box::pin(async { Ok(()) })
+            }
+
+            fn is_open(&self) -> bool {
+                self.pipe.is_open()
+            }
+
+            fn poll_read_ready(
+                mut self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+            ) -> Poll<io::Result<usize>> {
+                Pin::new(&mut self.pipe).poll_read_ready(cx)
+            }
+
+            fn poll_write_ready(
+                mut self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+            ) -> Poll<io::Result<usize>> {
+                Pin::new(&mut self.pipe).poll_write_ready(cx)
+            }
+        }
+    };
+}
+
+impl_pty_file!(PtyMaster);
+impl_pty_file!(PtySlave);
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_pty_line_discipline() {
+        let (mut master, mut slave) = Pty::open(TtyOptions::default());
+
+        // Input is echoed to the master and only reaches the slave once the
+        // line is finished
+        master.write_all(b"l").await.unwrap();
+        master.write_all(b"s").await.unwrap();
+        master.write_all(b"\r").await.unwrap();
+        let mut buf = [0u8; 16];
+        let read = master.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..read], b"l");
+        let read = slave.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..read], b"ls\n");
+
+        // Output has its line feeds turned into carriage returns
+        slave.write_all(b"a\nb").await.unwrap();
+        let mut output = Vec::new();
+        while output.len() < 6 {
+            let read = master.read(&mut buf).await.unwrap();
+            output.extend_from_slice(&buf[..read]);
+        }
+        assert_eq!(output, b"s\na\r\nb");
+
+        // The slave reads the end of the file once the master is closed
+        drop(master);
+        assert_eq!(slave.read(&mut buf).await.unwrap(), 0);
+    }
+}
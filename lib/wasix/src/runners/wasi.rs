@@ -188,9 +188,11 @@ impl crate::runners::Runner for WasiRunner {
         let module = runtime.load_module_sync(cmd.atom())?;
         let store = runtime.new_store();
 
-        self.prepare_webc_env(command_name, &wasi, pkg, runtime, None)
-            .context("Unable to prepare the WASI environment")?
-            .run_with_store_async(module, store)?;
+        let mut builder = self
+            .prepare_webc_env(command_name, &wasi, pkg, runtime, None)
+            .context("Unable to prepare the WASI environment")?;
+        builder.capabilities_mut().snapshot.module_hash = Some(*cmd.hash());
+        builder.run_with_store_async(module, store)?;
 
         Ok(())
     }
@@ -268,6 +268,39 @@ impl<F: FileSystem> virtual_fs::FileSystem for RelativeOrAbsolutePathHack<F> {
     fn new_open_options(&self) -> virtual_fs::OpenOptions {
         virtual_fs::OpenOptions::new(self)
     }
+
+    fn symlink_metadata(&self, path: &Path) -> virtual_fs::Result<virtual_fs::Metadata> {
+        self.execute(path, |fs, p| fs.symlink_metadata(p))
+    }
+
+    fn symlink(&self, target: &Path, link: &Path) -> virtual_fs::Result<()> {
+        self.execute(link, |fs, p| fs.symlink(target, p))
+    }
+
+    fn hard_link(&self, original: &Path, link: &Path) -> virtual_fs::Result<()> {
+        self.0.hard_link(original, link)
+    }
+
+    fn read_link(&self, path: &Path) -> virtual_fs::Result<PathBuf> {
+        self.execute(path, |fs, p| fs.read_link(p))
+    }
+
+    fn set_permissions(&self, path: &Path, mode: u32) -> virtual_fs::Result<()> {
+        self.execute(path, |fs, p| fs.set_permissions(p, mode))
+    }
+
+    fn set_times(
+        &self,
+        path: &Path,
+        accessed: Option<std::time::SystemTime>,
+        modified: Option<std::time::SystemTime>,
+    ) -> virtual_fs::Result<()> {
+        self.execute(path, |fs, p| fs.set_times(p, accessed, modified))
+    }
+
+    fn watch(&self, path: &Path, recursive: bool) -> virtual_fs::Result<virtual_fs::FsWatcher> {
+        self.execute(path, |fs, p| fs.watch(p, recursive))
+    }
 }
 
 impl<F: FileSystem> virtual_fs::FileOpener for RelativeOrAbsolutePathHack<F> {
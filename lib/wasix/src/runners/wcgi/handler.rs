@@ -56,6 +56,11 @@ impl Handler {
                 insecure_allow_all: true,
                 http_client: HttpClientCapabilityV1::new_allow_all(),
                 threading: Default::default(),
+                networking: Default::default(),
+                unix_sockets: Default::default(),
+                snapshot: Default::default(),
+                limits: Default::default(),
+                filesystem: Default::default(),
             });
 
         let module = self.module.clone();
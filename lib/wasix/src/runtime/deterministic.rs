@@ -0,0 +1,149 @@
+//! Sources of time and entropy for reproducible runs.
+//!
+//! A [`Determinism`] replaces the host clock and the host random number
+//! generator for everything running on a runtime. Time only moves forward
+//! when the guest looks at it or sleeps, and all random data is drawn from a
+//! PRNG seeded up front, so two runs with the same seed observe the same
+//! values.
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use rand::{rngs::StdRng, RngCore, SeedableRng};
+use wasmer_wasix_types::wasi::{Snapshot0Clockid, Timestamp};
+
+/// Wall clock time reported before the guest has done anything
+/// (2020-01-01T00:00:00Z).
+const DEFAULT_EPOCH: Duration = Duration::from_secs(1_577_836_800);
+
+/// How far the clock moves every time the guest reads it.
+const DEFAULT_TICK: Duration = Duration::from_micros(1);
+
+/// A virtual clock and a seeded PRNG shared by a deterministic runtime.
+#[derive(Debug)]
+pub struct Determinism {
+    seed: u64,
+    epoch: Duration,
+    tick: Duration,
+    /// Nanoseconds elapsed on the virtual clock.
+    elapsed: AtomicU64,
+    rng: Mutex<StdRng>,
+}
+
+impl Determinism {
+    pub fn new(seed: u64) -> Self {
+        Determinism {
+            seed,
+            epoch: DEFAULT_EPOCH,
+            tick: DEFAULT_TICK,
+            elapsed: AtomicU64::new(0),
+            rng: Mutex::new(StdRng::seed_from_u64(seed)),
+        }
+    }
+
+    /// Set the wall clock time the virtual clock starts at.
+    pub fn with_epoch(mut self, epoch: Duration) -> Self {
+        self.epoch = epoch;
+        self
+    }
+
+    /// Set how far the clock advances every time it is read.
+    pub fn with_tick(mut self, tick: Duration) -> Self {
+        self.tick = tick;
+        self
+    }
+
+    /// The seed the PRNG was created with.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// The resolution of the virtual clock.
+    pub fn resolution(&self) -> Timestamp {
+        self.tick.as_nanos().max(1) as Timestamp
+    }
+
+    /// Time elapsed on the virtual clock, without advancing it.
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_nanos(self.elapsed.load(Ordering::SeqCst))
+    }
+
+    /// Move the virtual clock forward.
+    pub fn advance(&self, time: Duration) {
+        self.elapsed
+            .fetch_add(time.as_nanos() as u64, Ordering::SeqCst);
+    }
+
+    /// Move the virtual clock forward to `elapsed`, if it isn't there already.
+    pub fn advance_to(&self, elapsed: Duration) {
+        self.elapsed
+            .fetch_max(elapsed.as_nanos() as u64, Ordering::SeqCst);
+    }
+
+    /// Read one of the guest visible clocks.
+    ///
+    /// Every read advances the clock by one tick so guests that spin on the
+    /// clock still make progress.
+    pub fn clock_time_get(&self, clock_id: Snapshot0Clockid) -> Timestamp {
+        let tick = self.tick.as_nanos() as u64;
+        let elapsed = self.elapsed.fetch_add(tick, Ordering::SeqCst) + tick;
+
+        match clock_id {
+            Snapshot0Clockid::Realtime => self.epoch.as_nanos() as Timestamp + elapsed,
+            _ => elapsed,
+        }
+    }
+
+    /// Fill a buffer with data from the seeded PRNG.
+    pub fn fill_bytes(&self, buf: &mut [u8]) {
+        self.rng.lock().unwrap().fill_bytes(buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_same_seed_produces_the_same_bytes() {
+        let first = Determinism::new(42);
+        let second = Determinism::new(42);
+        let mut a = [0; 64];
+        let mut b = [0; 64];
+
+        first.fill_bytes(&mut a);
+        second.fill_bytes(&mut b);
+
+        assert_eq!(a, b);
+        second.fill_bytes(&mut b);
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn the_clock_only_moves_when_observed() {
+        let determinism = Determinism::new(0).with_tick(Duration::from_millis(1));
+
+        assert_eq!(
+            determinism.clock_time_get(Snapshot0Clockid::Monotonic),
+            1_000_000
+        );
+        assert_eq!(
+            determinism.clock_time_get(Snapshot0Clockid::Monotonic),
+            2_000_000
+        );
+
+        determinism.advance(Duration::from_secs(1));
+        determinism.advance_to(Duration::from_millis(500));
+
+        assert_eq!(determinism.elapsed(), Duration::from_millis(1002));
+        assert_eq!(
+            determinism.clock_time_get(Snapshot0Clockid::Realtime),
+            DEFAULT_EPOCH.as_nanos() as Timestamp + 1_003_000_000
+        );
+    }
+}
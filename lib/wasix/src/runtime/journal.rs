@@ -0,0 +1,731 @@
+//! Recording and replaying the values a guest receives from the host.
+//!
+//! A [`SyscallJournal`] in record mode writes down everything that flowed
+//! from the host into the guest while it ran: the arguments and environment
+//! it was started with, clock readings, random bytes, the data returned by
+//! reads from files, pipes and sockets, the events returned by `poll_oneoff`,
+//! directory listings and file metadata. In replay mode the same syscalls are
+//! answered from the journal instead, so a run can be reproduced offline
+//! exactly as it happened.
+//!
+//! Everything else (opening files, connecting sockets, changing the mode of
+//! a file, ...) still goes through the runtime, so the replaying runtime
+//! needs the same descriptors to exist. Those changes don't reach the guest
+//! other than through the metadata it reads back, which is journaled.
+//! The peers of accepted connections aren't there any more when a journal is
+//! replayed, so `sock_accept` hands out a stand-in connection instead: its
+//! reads are replayed like any other and what is written to it is dropped.
+//!
+//! Reads, polls, directory listings and metadata are kept apart per process
+//! (and per thread for polls), as the processes of a run reuse the same
+//! file descriptor and thread numbers. Replaying needs the processes to get
+//! the same IDs as they did when recording, which they do as long as they
+//! are spawned in the same order.
+//!
+//! A replay that runs out of entries has diverged from the recording, it
+//! logs a warning and falls back to the host.
+//!
+//! A journal file starts with [`MAGIC`] and the [`VERSION`] of its format,
+//! followed by the entries. Entries are buffered while recording, they are
+//! written out whenever a process exits, when [`SyscallJournal::flush()`] is
+//! called and when the journal is dropped.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::File,
+    io::{self, BufWriter, Read, Write},
+    net::SocketAddr,
+    path::Path,
+    sync::Mutex,
+};
+
+use derivative::Derivative;
+use serde::{Deserialize, Serialize};
+use wasmer_wasix_types::wasi::{
+    Errno, Event, EventFdReadwrite, EventUnion, Eventrwflags, Eventtype, Fd as WasiFd, Filestat,
+    Filetype, Snapshot0Clockid,
+};
+
+/// The bytes a journal file starts with.
+pub const MAGIC: [u8; 8] = *b"\0wasixjl";
+/// The version of the format of the entries, written after [`MAGIC`].
+pub const VERSION: u32 = 2;
+
+/// The bytes a read or an `fd_readdir` produced.
+pub type ReadResult = Result<Vec<u8>, Errno>;
+
+/// The data received by a `sock_recv_from` and the address it came from.
+pub type RecvFromResult = Result<(Vec<u8>, SocketAddr), Errno>;
+
+/// The metadata returned by `fd_filestat_get` or `path_filestat_get`.
+pub type FilestatResult = Result<JournalFilestat, Errno>;
+
+/// The address of the peer of a connection a `sock_accept` returned, which
+/// is [`None`] for connections to Unix sockets.
+pub type AcceptResult = Result<Option<SocketAddr>, Errno>;
+
+/// A value the host handed to the guest.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum JournalEntry {
+    /// The arguments and environment variables a program was started with.
+    Environment {
+        args: Vec<String>,
+        envs: Vec<Vec<u8>>,
+    },
+    /// A reading of one of the clocks.
+    ClockTime { clock_id: u32, time: i64 },
+    /// Bytes handed out by `random_get`.
+    RandomBytes(Vec<u8>),
+    /// The outcome of a read from a file, pipe or socket of process `pid`.
+    Read {
+        pid: u32,
+        fd: WasiFd,
+        result: Result<Vec<u8>, Errno>,
+    },
+    /// The outcome of a `sock_recv_from`, with the address the data came from.
+    RecvFrom {
+        pid: u32,
+        fd: WasiFd,
+        result: RecvFromResult,
+    },
+    /// The events a `poll_oneoff` made by thread `tid` of process `pid`
+    /// returned.
+    Poll {
+        pid: u32,
+        tid: u32,
+        events: Vec<JournalEvent>,
+    },
+    /// The entries an `fd_readdir` wrote into its buffer.
+    Readdir {
+        pid: u32,
+        fd: WasiFd,
+        result: Result<Vec<u8>, Errno>,
+    },
+    /// The metadata of an open file, or of `path` relative to `fd`.
+    Filestat {
+        pid: u32,
+        fd: WasiFd,
+        path: Option<String>,
+        result: FilestatResult,
+    },
+    /// A connection accepted by the listening socket `fd`.
+    Accept {
+        pid: u32,
+        fd: WasiFd,
+        result: AcceptResult,
+    },
+}
+
+/// An [`Event`] returned by `poll_oneoff`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournalEvent {
+    pub userdata: u64,
+    pub error: Errno,
+    pub type_: Eventtype,
+    /// The bytes available and the state of the file descriptor, for the
+    /// events of file descriptors.
+    pub fd_readwrite: Option<(u64, Eventrwflags)>,
+}
+
+impl From<&Event> for JournalEvent {
+    fn from(event: &Event) -> Self {
+        let fd_readwrite = match event.type_ {
+            Eventtype::FdRead | Eventtype::FdWrite => {
+                let fd_readwrite = unsafe { event.u.fd_readwrite };
+                Some((fd_readwrite.nbytes, fd_readwrite.flags))
+            }
+            Eventtype::Clock | Eventtype::Unknown => None,
+        };
+        JournalEvent {
+            userdata: event.userdata,
+            error: event.error,
+            type_: event.type_,
+            fd_readwrite,
+        }
+    }
+}
+
+impl From<JournalEvent> for Event {
+    fn from(event: JournalEvent) -> Self {
+        Event {
+            userdata: event.userdata,
+            error: event.error,
+            type_: event.type_,
+            u: match event.fd_readwrite {
+                Some((nbytes, flags)) => EventUnion {
+                    fd_readwrite: EventFdReadwrite { nbytes, flags },
+                },
+                None => EventUnion { clock: 0 },
+            },
+        }
+    }
+}
+
+/// A [`Filestat`], with its file type as a number.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournalFilestat {
+    pub dev: u64,
+    pub ino: u64,
+    pub filetype: u8,
+    pub nlink: u64,
+    pub size: u64,
+    pub atim: u64,
+    pub mtim: u64,
+    pub ctim: u64,
+}
+
+impl From<&Filestat> for JournalFilestat {
+    fn from(stat: &Filestat) -> Self {
+        JournalFilestat {
+            dev: stat.st_dev,
+            ino: stat.st_ino,
+            filetype: stat.st_filetype as u8,
+            nlink: stat.st_nlink,
+            size: stat.st_size,
+            atim: stat.st_atim,
+            mtim: stat.st_mtim,
+            ctim: stat.st_ctim,
+        }
+    }
+}
+
+impl From<JournalFilestat> for Filestat {
+    fn from(stat: JournalFilestat) -> Self {
+        let filetype = match stat.filetype {
+            1 => Filetype::BlockDevice,
+            2 => Filetype::CharacterDevice,
+            3 => Filetype::Directory,
+            4 => Filetype::RegularFile,
+            5 => Filetype::SocketDgram,
+            6 => Filetype::SocketStream,
+            7 => Filetype::SymbolicLink,
+            8 => Filetype::SocketRaw,
+            9 => Filetype::SocketSeqpacket,
+            _ => Filetype::Unknown,
+        };
+        Filestat {
+            st_dev: stat.dev,
+            st_ino: stat.ino,
+            st_filetype: filetype,
+            st_nlink: stat.nlink,
+            st_size: stat.size,
+            st_atim: stat.atim,
+            st_mtim: stat.mtim,
+            st_ctim: stat.ctim,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum JournalError {
+    #[error("unable to access the journal")]
+    Io(#[from] io::Error),
+    #[error("the journal is corrupt")]
+    Format(#[from] bincode::Error),
+    #[error("not a syscall journal")]
+    NotAJournal,
+    #[error("version {0} syscall journals are not supported")]
+    UnsupportedVersion(u32),
+}
+
+/// Records or replays the values a guest receives from the host.
+///
+/// Attach it to a runtime with
+/// [`PluggableRuntime::set_journal()`](crate::PluggableRuntime::set_journal).
+#[derive(Debug)]
+pub struct SyscallJournal {
+    mode: Mode,
+}
+
+#[derive(Derivative)]
+#[derivative(Debug)]
+enum Mode {
+    Record(#[derivative(Debug = "ignore")] Mutex<BufWriter<Box<dyn Write + Send + Sync>>>),
+    Replay(Mutex<Box<ReplayState>>),
+}
+
+/// The journal entries waiting to be replayed. Reads are matched per process
+/// and file descriptor, so threads reading different descriptors can
+/// interleave differently from the recording.
+#[derive(Debug, Default)]
+struct ReplayState {
+    environments: VecDeque<(Vec<String>, Vec<Vec<u8>>)>,
+    clocks: HashMap<u32, VecDeque<i64>>,
+    random: VecDeque<u8>,
+    reads: HashMap<(u32, WasiFd), VecDeque<ReadResult>>,
+    recv_froms: HashMap<(u32, WasiFd), VecDeque<RecvFromResult>>,
+    polls: HashMap<(u32, u32), VecDeque<Vec<JournalEvent>>>,
+    readdirs: HashMap<(u32, WasiFd), VecDeque<ReadResult>>,
+    filestats: HashMap<(u32, WasiFd, Option<String>), VecDeque<FilestatResult>>,
+    accepts: HashMap<(u32, WasiFd), VecDeque<AcceptResult>>,
+}
+
+impl SyscallJournal {
+    /// Record into `writer`, starting with the header of a journal. The
+    /// entries are buffered, see the [module documentation](self) for when
+    /// they are written out.
+    pub fn record(mut writer: impl Write + Send + Sync + 'static) -> Result<Self, JournalError> {
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.flush()?;
+        let writer: Box<dyn Write + Send + Sync> = Box::new(writer);
+        Ok(SyscallJournal {
+            mode: Mode::Record(Mutex::new(BufWriter::new(writer))),
+        })
+    }
+
+    /// Record into a new file at `path`.
+    pub fn record_to_file(path: impl AsRef<Path>) -> Result<Self, JournalError> {
+        let file = File::create(path)?;
+        Self::record(file)
+    }
+
+    /// Replay the entries of a journal.
+    pub fn replay(entries: impl IntoIterator<Item = JournalEntry>) -> Self {
+        let mut state = ReplayState::default();
+        for entry in entries {
+            match entry {
+                JournalEntry::Environment { args, envs } => {
+                    state.environments.push_back((args, envs))
+                }
+                JournalEntry::ClockTime { clock_id, time } => {
+                    state.clocks.entry(clock_id).or_default().push_back(time)
+                }
+                JournalEntry::RandomBytes(bytes) => state.random.extend(bytes),
+                JournalEntry::Read { pid, fd, result } => {
+                    state.reads.entry((pid, fd)).or_default().push_back(result)
+                }
+                JournalEntry::RecvFrom { pid, fd, result } => state
+                    .recv_froms
+                    .entry((pid, fd))
+                    .or_default()
+                    .push_back(result),
+                JournalEntry::Poll { pid, tid, events } => {
+                    state.polls.entry((pid, tid)).or_default().push_back(events)
+                }
+                JournalEntry::Readdir { pid, fd, result } => state
+                    .readdirs
+                    .entry((pid, fd))
+                    .or_default()
+                    .push_back(result),
+                JournalEntry::Filestat {
+                    pid,
+                    fd,
+                    path,
+                    result,
+                } => state
+                    .filestats
+                    .entry((pid, fd, path))
+                    .or_default()
+                    .push_back(result),
+                JournalEntry::Accept { pid, fd, result } => state
+                    .accepts
+                    .entry((pid, fd))
+                    .or_default()
+                    .push_back(result),
+            }
+        }
+
+        SyscallJournal {
+            mode: Mode::Replay(Mutex::new(Box::new(state))),
+        }
+    }
+
+    /// Replay the journal in the file at `path`.
+    pub fn replay_file(path: impl AsRef<Path>) -> Result<Self, JournalError> {
+        let file = io::BufReader::new(File::open(path)?);
+        Ok(Self::replay(Self::read_entries(file)?))
+    }
+
+    /// Read all of the entries written by a recording journal.
+    pub fn read_entries(mut reader: impl Read) -> Result<Vec<JournalEntry>, JournalError> {
+        let mut magic = [0; MAGIC.len()];
+        let mut version = [0; 4];
+        match reader
+            .read_exact(&mut magic)
+            .and_then(|_| reader.read_exact(&mut version))
+        {
+            Ok(()) if magic == MAGIC => {}
+            Ok(()) => return Err(JournalError::NotAJournal),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                return Err(JournalError::NotAJournal)
+            }
+            Err(e) => return Err(e.into()),
+        }
+        match u32::from_le_bytes(version) {
+            VERSION => {}
+            version => return Err(JournalError::UnsupportedVersion(version)),
+        }
+
+        let mut entries = Vec::new();
+        loop {
+            match bincode::deserialize_from(&mut reader) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => match *e {
+                    bincode::ErrorKind::Io(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                        return Ok(entries)
+                    }
+                    _ => return Err(e.into()),
+                },
+            }
+        }
+    }
+
+    pub fn is_replaying(&self) -> bool {
+        matches!(self.mode, Mode::Replay(_))
+    }
+
+    /// Writes out the entries that were recorded so far.
+    pub fn flush(&self) -> Result<(), JournalError> {
+        if let Mode::Record(writer) = &self.mode {
+            writer.lock().unwrap().flush()?;
+        }
+        Ok(())
+    }
+
+    fn record_entry(&self, entry: JournalEntry) {
+        if let Mode::Record(writer) = &self.mode {
+            let result = bincode::serialize(&entry)
+                .map_err(JournalError::from)
+                .and_then(|bytes| Ok(writer.lock().unwrap().write_all(&bytes)?));
+            if let Err(e) = result {
+                tracing::warn!(
+                    error = &e as &dyn std::error::Error,
+                    "Unable to write to the syscall journal",
+                );
+            }
+        }
+    }
+
+    fn replay_state(&self) -> Option<std::sync::MutexGuard<'_, Box<ReplayState>>> {
+        match &self.mode {
+            Mode::Replay(state) => Some(state.lock().unwrap()),
+            Mode::Record(_) => None,
+        }
+    }
+
+    /// Takes the next recorded value with `next`, when replaying. Running out
+    /// of values means the replay diverged from the recording, the caller
+    /// then falls back to the host.
+    fn replay_next<T>(
+        &self,
+        what: &str,
+        next: impl FnOnce(&mut ReplayState) -> Option<T>,
+    ) -> Option<T> {
+        let mut state = self.replay_state()?;
+        let value = next(&mut state);
+        if value.is_none() {
+            tracing::warn!("Ran out of {what} in the journal, using the host instead");
+        }
+        value
+    }
+
+    /// The arguments and environment variables to start a program with.
+    pub(crate) fn environment(
+        &self,
+        args: Vec<String>,
+        envs: Vec<Vec<u8>>,
+    ) -> (Vec<String>, Vec<Vec<u8>>) {
+        if let Some(mut state) = self.replay_state() {
+            return match state.environments.pop_front() {
+                Some(environment) => environment,
+                None => {
+                    tracing::warn!("The environment isn't in the journal");
+                    (args, envs)
+                }
+            };
+        }
+
+        self.record_entry(JournalEntry::Environment {
+            args: args.clone(),
+            envs: envs.clone(),
+        });
+        (args, envs)
+    }
+
+    /// Read a clock, with `live` reading it from the host.
+    pub(crate) fn clock_time_get(
+        &self,
+        clock_id: Snapshot0Clockid,
+        live: impl FnOnce() -> Result<i64, Errno>,
+    ) -> Result<i64, Errno> {
+        let clock_id = clock_id as u32;
+        if let Some(time) = self.replay_next("clock readings", |state| {
+            state.clocks.get_mut(&clock_id)?.pop_front()
+        }) {
+            return Ok(time);
+        }
+
+        let time = live()?;
+        self.record_entry(JournalEntry::ClockTime { clock_id, time });
+        Ok(time)
+    }
+
+    /// Fill `buf` with random bytes, with `live` getting them from the host.
+    pub(crate) fn random_get(
+        &self,
+        buf: &mut [u8],
+        live: impl FnOnce(&mut [u8]) -> Result<(), Errno>,
+    ) -> Result<(), Errno> {
+        if let Some(mut state) = self.replay_state() {
+            if state.random.len() >= buf.len() {
+                let len = buf.len();
+                for (byte, recorded) in buf.iter_mut().zip(state.random.drain(..len)) {
+                    *byte = recorded;
+                }
+                return Ok(());
+            }
+            tracing::warn!("Ran out of random bytes in the journal");
+        }
+
+        live(buf)?;
+        self.record_entry(JournalEntry::RandomBytes(buf.to_vec()));
+        Ok(())
+    }
+
+    /// The next recorded read from `fd` of process `pid`, when replaying.
+    pub(crate) fn replay_read(&self, pid: u32, fd: WasiFd) -> Option<Result<Vec<u8>, Errno>> {
+        self.replay_next("reads", |state| {
+            state.reads.get_mut(&(pid, fd))?.pop_front()
+        })
+    }
+
+    pub(crate) fn record_read(&self, pid: u32, fd: WasiFd, result: Result<Vec<u8>, Errno>) {
+        self.record_entry(JournalEntry::Read { pid, fd, result });
+    }
+
+    /// The next recorded `sock_recv_from` on `fd` of process `pid`, when
+    /// replaying.
+    pub(crate) fn replay_recv_from(&self, pid: u32, fd: WasiFd) -> Option<RecvFromResult> {
+        self.replay_next("socket reads", |state| {
+            state.recv_froms.get_mut(&(pid, fd))?.pop_front()
+        })
+    }
+
+    pub(crate) fn record_recv_from(&self, pid: u32, fd: WasiFd, result: RecvFromResult) {
+        self.record_entry(JournalEntry::RecvFrom { pid, fd, result });
+    }
+
+    /// The events of the next recorded `poll_oneoff` made by thread `tid` of
+    /// process `pid`, when replaying.
+    pub(crate) fn replay_poll(&self, pid: u32, tid: u32) -> Option<Vec<Event>> {
+        let events = self.replay_next("polls", |state| {
+            state.polls.get_mut(&(pid, tid))?.pop_front()
+        })?;
+        Some(events.into_iter().map(Event::from).collect())
+    }
+
+    pub(crate) fn record_poll(&self, pid: u32, tid: u32, events: &[Event]) {
+        self.record_entry(JournalEntry::Poll {
+            pid,
+            tid,
+            events: events.iter().map(JournalEvent::from).collect(),
+        });
+    }
+
+    /// The next recorded `fd_readdir` of `fd` of process `pid`, when
+    /// replaying.
+    pub(crate) fn replay_readdir(&self, pid: u32, fd: WasiFd) -> Option<Result<Vec<u8>, Errno>> {
+        self.replay_next("directory listings", |state| {
+            state.readdirs.get_mut(&(pid, fd))?.pop_front()
+        })
+    }
+
+    pub(crate) fn record_readdir(&self, pid: u32, fd: WasiFd, result: Result<Vec<u8>, Errno>) {
+        self.record_entry(JournalEntry::Readdir { pid, fd, result });
+    }
+
+    /// Get the metadata of `fd` of process `pid`, or of `path` relative to
+    /// it, with `live` getting it from the host.
+    pub(crate) fn filestat(
+        &self,
+        pid: u32,
+        fd: WasiFd,
+        path: Option<&str>,
+        live: impl FnOnce() -> Result<Filestat, Errno>,
+    ) -> Result<Filestat, Errno> {
+        let key = (pid, fd, path.map(str::to_string));
+        if let Some(result) = self.replay_next("file metadata", |state| {
+            state.filestats.get_mut(&key)?.pop_front()
+        }) {
+            return result.map(Filestat::from);
+        }
+
+        let result = live();
+        self.record_entry(JournalEntry::Filestat {
+            pid,
+            fd,
+            path: key.2,
+            result: result.as_ref().map(JournalFilestat::from).map_err(|e| *e),
+        });
+        result
+    }
+
+    /// The next connection recorded as accepted by `fd` of process `pid`,
+    /// when replaying.
+    pub(crate) fn replay_accept(&self, pid: u32, fd: WasiFd) -> Option<AcceptResult> {
+        self.replay_next("accepted connections", |state| {
+            state.accepts.get_mut(&(pid, fd))?.pop_front()
+        })
+    }
+
+    pub(crate) fn record_accept(&self, pid: u32, fd: WasiFd, result: AcceptResult) {
+        self.record_entry(JournalEntry::Accept { pid, fd, result });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[derive(Debug, Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn replays_what_was_recorded() {
+        let buffer = SharedBuffer::default();
+        let journal = SyscallJournal::record(buffer.clone()).unwrap();
+
+        let (args, envs) = journal.environment(vec!["prog".to_string()], vec![b"A=1".to_vec()]);
+        let time = journal
+            .clock_time_get(Snapshot0Clockid::Monotonic, || Ok(42))
+            .unwrap();
+        let mut random = [0; 4];
+        journal
+            .random_get(&mut random, |buf| {
+                buf.copy_from_slice(&[1, 2, 3, 4]);
+                Ok(())
+            })
+            .unwrap();
+        journal.record_read(1, 3, Ok(b"hello".to_vec()));
+        journal.record_read(1, 3, Err(Errno::Again));
+        journal.record_poll(
+            1,
+            1,
+            &[Event {
+                userdata: 7,
+                error: Errno::Success,
+                type_: Eventtype::FdRead,
+                u: EventUnion {
+                    fd_readwrite: EventFdReadwrite {
+                        nbytes: 5,
+                        flags: Eventrwflags::empty(),
+                    },
+                },
+            }],
+        );
+        journal.record_readdir(1, 4, Ok(b"entries".to_vec()));
+        let stat = journal
+            .filestat(1, 4, Some("file"), || {
+                Ok(Filestat {
+                    st_dev: 1,
+                    st_ino: 2,
+                    st_filetype: Filetype::RegularFile,
+                    st_nlink: 1,
+                    st_size: 3,
+                    st_atim: 4,
+                    st_mtim: 5,
+                    st_ctim: 6,
+                })
+            })
+            .unwrap();
+        let peer = SocketAddr::from(([10, 0, 0, 1], 4000));
+        journal.record_accept(1, 5, Ok(Some(peer)));
+        journal.flush().unwrap();
+
+        let entries = SyscallJournal::read_entries(&buffer.0.lock().unwrap()[..]).unwrap();
+        assert_eq!(entries.len(), 9);
+        let journal = SyscallJournal::replay(entries);
+
+        assert_eq!(journal.environment(Vec::new(), Vec::new()), (args, envs));
+        assert_eq!(
+            journal.clock_time_get(Snapshot0Clockid::Monotonic, || Ok(0)),
+            Ok(time)
+        );
+        let mut replayed = [0; 2];
+        journal.random_get(&mut replayed, |_| Ok(())).unwrap();
+        assert_eq!(replayed, [1, 2]);
+        journal.random_get(&mut replayed, |_| Ok(())).unwrap();
+        assert_eq!(replayed, [3, 4]);
+        assert_eq!(journal.replay_read(1, 3), Some(Ok(b"hello".to_vec())));
+        assert_eq!(journal.replay_read(1, 3), Some(Err(Errno::Again)));
+        assert_eq!(journal.replay_read(1, 3), None);
+        let events = journal.replay_poll(1, 1).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].userdata, 7);
+        assert_eq!(unsafe { events[0].u.fd_readwrite.nbytes }, 5);
+        assert!(journal.replay_poll(1, 2).is_none());
+        assert_eq!(journal.replay_readdir(1, 4), Some(Ok(b"entries".to_vec())));
+        let replayed = journal
+            .filestat(1, 4, Some("file"), || Err(Errno::Noent))
+            .unwrap();
+        assert_eq!(
+            JournalFilestat::from(&replayed),
+            JournalFilestat::from(&stat)
+        );
+        assert_eq!(
+            journal
+                .filestat(1, 4, None, || Err(Errno::Noent))
+                .unwrap_err(),
+            Errno::Noent
+        );
+        assert_eq!(journal.replay_accept(1, 5), Some(Ok(Some(peer))));
+        assert_eq!(journal.replay_accept(1, 5), None);
+    }
+
+    #[test]
+    fn processes_are_replayed_separately() {
+        let buffer = SharedBuffer::default();
+        let journal = SyscallJournal::record(buffer.clone()).unwrap();
+
+        // Both processes read their stdin and poll from their main thread
+        journal.record_read(1, 0, Ok(b"first".to_vec()));
+        journal.record_read(2, 0, Ok(b"second".to_vec()));
+        journal.record_poll(2, 1, &[]);
+        journal.record_read(1, 0, Ok(Vec::new()));
+        journal.record_poll(1, 1, &[]);
+        journal.record_read(2, 0, Ok(Vec::new()));
+        drop(journal);
+
+        let entries = SyscallJournal::read_entries(&buffer.0.lock().unwrap()[..]).unwrap();
+        let journal = SyscallJournal::replay(entries);
+        assert_eq!(journal.replay_read(2, 0), Some(Ok(b"second".to_vec())));
+        assert_eq!(journal.replay_read(2, 0), Some(Ok(Vec::new())));
+        assert_eq!(journal.replay_read(2, 0), None);
+        assert!(journal.replay_poll(1, 1).is_some());
+        assert!(journal.replay_poll(1, 1).is_none());
+        assert_eq!(journal.replay_read(1, 0), Some(Ok(b"first".to_vec())));
+        assert_eq!(journal.replay_read(1, 0), Some(Ok(Vec::new())));
+        assert!(journal.replay_poll(2, 1).is_some());
+    }
+
+    #[test]
+    fn journals_start_with_a_header() {
+        let buffer = SharedBuffer::default();
+        SyscallJournal::record(buffer.clone()).unwrap();
+        let header = buffer.0.lock().unwrap().clone();
+        assert_eq!(header.len(), MAGIC.len() + 4);
+        assert!(SyscallJournal::read_entries(&header[..])
+            .unwrap()
+            .is_empty());
+
+        let err = SyscallJournal::read_entries(&b"not a journal"[..]).unwrap_err();
+        assert!(matches!(err, JournalError::NotAJournal));
+        let mut newer = MAGIC.to_vec();
+        newer.extend((VERSION + 1).to_le_bytes());
+        let err = SyscallJournal::read_entries(&newer[..]).unwrap_err();
+        assert!(matches!(err, JournalError::UnsupportedVersion(v) if v == VERSION + 1));
+    }
+}
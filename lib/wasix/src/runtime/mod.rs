@@ -1,9 +1,15 @@
+pub mod deterministic;
+pub mod journal;
 pub mod module_cache;
 pub mod package_loader;
 pub mod resolver;
 pub mod task_manager;
 
-pub use self::task_manager::{SpawnMemoryType, VirtualTaskManager};
+pub use self::{
+    deterministic::Determinism,
+    journal::SyscallJournal,
+    task_manager::{SpawnMemoryType, VirtualTaskManager},
+};
 use self::{
     module_cache::{CacheError, ModuleHash},
     task_manager::InlineWaker,
@@ -30,6 +36,13 @@ use crate::{
     WasiTtyState,
 };
 
+#[cfg(feature = "stack-vnet")]
+use self::task_manager::VirtualTaskManagerExt;
+#[cfg(feature = "stack-vnet")]
+use std::net::SocketAddr;
+#[cfg(feature = "stack-vnet")]
+use virtual_net::{EthernetSwitch, NetworkError, StackNetworking, UdpTunnel, VirtualRawSocket};
+
 /// Runtime components used when running WebAssembly programs.
 ///
 /// Think of this as the "System" in "WebAssembly Systems Interface".
@@ -89,6 +102,18 @@ where
         None
     }
 
+    /// The virtual clock and seeded PRNG used in place of the host ones when
+    /// running deterministically.
+    fn determinism(&self) -> Option<&Arc<Determinism>> {
+        None
+    }
+
+    /// The journal the values received from the host are recorded into, or
+    /// replayed from.
+    fn journal(&self) -> Option<&Arc<SyscallJournal>> {
+        None
+    }
+
     /// Load a a Webassembly module, trying to use a pre-compiled version if possible.
     fn load_module<'a>(&'a self, wasm: &'a [u8]) -> BoxFuture<'a, Result<Module, anyhow::Error>> {
         let engine = self.engine();
@@ -180,6 +205,8 @@ pub struct PluggableRuntime {
     pub module_cache: Arc<dyn ModuleCache + Send + Sync>,
     #[derivative(Debug = "ignore")]
     pub tty: Option<Arc<dyn TtyBridge + Send + Sync>>,
+    pub determinism: Option<Arc<Determinism>>,
+    pub journal: Option<Arc<SyscallJournal>>,
 }
 
 impl PluggableRuntime {
@@ -211,6 +238,8 @@ impl PluggableRuntime {
             http_client,
             engine: None,
             tty: None,
+            determinism: None,
+            journal: None,
             source: Arc::new(source),
             package_loader: Arc::new(loader),
             module_cache: Arc::new(module_cache::in_memory()),
@@ -225,8 +254,13 @@ impl PluggableRuntime {
         self
     }
 
+    /// Sets the engine that modules are compiled and instantiated with.
+    ///
+    /// With the `sys-tunables` feature the tunables of the engine are wrapped
+    /// in [`LimitingTunables`](crate::LimitingTunables), so that the memory
+    /// limit of a process also caps the memories its module defines.
     pub fn set_engine(&mut self, engine: Option<wasmer::Engine>) -> &mut Self {
-        self.engine = engine;
+        self.engine = engine.map(limit_engine);
         self
     }
 
@@ -256,6 +290,29 @@ impl PluggableRuntime {
         self
     }
 
+    /// Run deterministically, with a virtual clock and a PRNG seeded with
+    /// `seed` in place of the host ones.
+    ///
+    /// This also replaces the task manager with a
+    /// [`DeterministicTaskManager`](task_manager::deterministic::DeterministicTaskManager)
+    /// driven by the same clock.
+    #[cfg(feature = "sys-thread")]
+    pub fn set_deterministic(&mut self, seed: u64) -> &mut Self {
+        let determinism = Arc::new(Determinism::new(seed));
+        self.rt = Arc::new(task_manager::deterministic::DeterministicTaskManager::new(
+            determinism.clone(),
+        ));
+        self.determinism = Some(determinism);
+        self
+    }
+
+    /// Record the values the guest receives from the host into `journal`, or
+    /// replay them from it.
+    pub fn set_journal(&mut self, journal: SyscallJournal) -> &mut Self {
+        self.journal = Some(Arc::new(journal));
+        self
+    }
+
     pub fn set_http_client(
         &mut self,
         client: impl HttpClient + Send + Sync + 'static,
@@ -263,6 +320,51 @@ impl PluggableRuntime {
         self.http_client = Some(Arc::new(client));
         self
     }
+
+    /// Gives the guests a userspace TCP/IP stack that exchanges Ethernet
+    /// frames over `link`, in place of the current networking.
+    ///
+    /// The current networking becomes the uplink of the stack, which carries
+    /// its UDP tunnels. The stack gets a random MAC address and is driven by
+    /// the task manager.
+    #[cfg(feature = "stack-vnet")]
+    pub fn set_stack_networking(&mut self, link: StackLink) -> Result<&mut Self, NetworkError> {
+        let uplink = self.networking.clone();
+        let link: Box<dyn VirtualRawSocket + Sync> = match link {
+            StackLink::Switch(switch) => Box::new(switch.port()),
+            StackLink::UdpTunnel(peer) => {
+                let uplink = uplink.clone();
+                let tunnel = self.rt.spawn_and_block_on(async move {
+                    UdpTunnel::connect(uplink.as_ref(), peer).await
+                })?;
+                Box::new(tunnel)
+            }
+        };
+
+        // A unicast address that is administered locally
+        let mut mac = [0u8; 6];
+        getrandom::getrandom(&mut mac).map_err(|_| NetworkError::UnknownError)?;
+        mac[0] = (mac[0] & 0xfc) | 0x02;
+
+        let (stack, driver) = StackNetworking::new(link, mac);
+        self.rt
+            .task_shared(Box::new(move || Box::pin(driver)))
+            .map_err(|_| NetworkError::UnknownError)?;
+        self.networking = Arc::new(stack.with_uplink(uplink));
+        Ok(self)
+    }
+}
+
+/// The link that the TCP/IP stack of
+/// [`PluggableRuntime::set_stack_networking`] sends its frames over.
+#[cfg(feature = "stack-vnet")]
+#[derive(Debug, Clone)]
+pub enum StackLink {
+    /// A port on a switch in memory, so the stack only reaches the other
+    /// stacks on the switch until it is bridged
+    Switch(EthernetSwitch),
+    /// A UDP tunnel to a peer, through the networking of the runtime
+    UdpTunnel(SocketAddr),
 }
 
 impl Runtime for PluggableRuntime {
@@ -286,15 +388,12 @@ impl Runtime for PluggableRuntime {
         if let Some(engine) = self.engine.clone() {
             engine
         } else {
-            wasmer::Engine::default()
+            limit_engine(wasmer::Engine::default())
         }
     }
 
     fn new_store(&self) -> wasmer::Store {
-        self.engine
-            .clone()
-            .map(wasmer::Store::new)
-            .unwrap_or_default()
+        wasmer::Store::new(self.engine())
     }
 
     fn task_manager(&self) -> &Arc<dyn VirtualTaskManager> {
@@ -308,4 +407,21 @@ impl Runtime for PluggableRuntime {
     fn module_cache(&self) -> Arc<dyn ModuleCache + Send + Sync> {
         self.module_cache.clone()
     }
+
+    fn determinism(&self) -> Option<&Arc<Determinism>> {
+        self.determinism.as_ref()
+    }
+
+    fn journal(&self) -> Option<&Arc<SyscallJournal>> {
+        self.journal.as_ref()
+    }
+}
+
+/// Makes the memory limits of processes apply to the memories that their
+/// modules define themselves, when the `sys-tunables` feature is enabled.
+#[allow(unused_mut)]
+fn limit_engine(mut engine: wasmer::Engine) -> wasmer::Engine {
+    #[cfg(feature = "sys-tunables")]
+    crate::LimitingTunables::install(&mut engine);
+    engine
 }
@@ -0,0 +1,497 @@
+//! A task manager that runs everything in a reproducible order.
+//!
+//! Futures are polled by a single executor thread in the order they were
+//! woken, and blocking work (including WebAssembly threads) runs to completion
+//! one job at a time on a single worker thread. Sleeping never waits on the
+//! host clock: once nothing is left to run, the virtual clock of the attached
+//! [`Determinism`] jumps straight to the earliest pending timer.
+//!
+//! Because only one WebAssembly thread makes progress at a time, guests that
+//! block one thread while waiting on another need asynchronous threading
+//! (deep sleep) enabled so the waiting thread gives the worker back.
+//!
+//! The clock only moves when nothing is left to run, so tasks that are
+//! spawned from outside the task manager while it is idle may start after
+//! the clock has already moved. Runs are reproducible when everything after
+//! the first task is spawned by the tasks themselves.
+//!
+//! Both threads run inside a tokio runtime so the host file system and host
+//! networking keep working, but only the order in which guest tasks run and
+//! the virtual clock are reproducible. Host I/O completes whenever the host
+//! gets to it, and the timeouts of host sockets use the host clock, so runs
+//! that talk to the network are not guaranteed to replay the same way.
+
+use std::{
+    cell::Cell,
+    collections::{BTreeMap, HashMap, VecDeque},
+    pin::Pin,
+    sync::{Arc, Condvar, Mutex, MutexGuard, Weak},
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+use futures::{future::BoxFuture, task::ArcWake, Future};
+use tokio::runtime::Handle;
+
+use crate::{
+    os::task::thread::WasiThreadError, runtime::deterministic::Determinism, WasiFunctionEnv,
+};
+
+use super::{tokio::RuntimeOrHandle, TaskWasm, TaskWasmRunProperties, VirtualTaskManager};
+
+thread_local! {
+    /// Set on the thread that runs blocking jobs.
+    static IS_WORKER: Cell<bool> = const { Cell::new(false) };
+}
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+#[derive(Default)]
+struct Timer {
+    waker: Option<Waker>,
+    /// The worker thread is parked on this timer.
+    worker: bool,
+}
+
+#[derive(Default)]
+struct State {
+    next_id: u64,
+    futures: HashMap<u64, BoxFuture<'static, ()>>,
+    ready: VecDeque<u64>,
+    jobs: VecDeque<Job>,
+    timers: BTreeMap<(Duration, u64), Timer>,
+    job_running: bool,
+    worker_sleeping: bool,
+    shutdown: bool,
+}
+
+impl State {
+    fn next_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    /// Nothing can happen until the virtual clock moves.
+    fn is_idle(&self) -> bool {
+        self.ready.is_empty()
+            && (self.worker_sleeping || (!self.job_running && self.jobs.is_empty()))
+    }
+}
+
+struct Shared {
+    determinism: Arc<Determinism>,
+    /// Entered by the executor and the worker for host I/O
+    handle: Handle,
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    fn spawn(&self, future: BoxFuture<'static, ()>) {
+        let mut state = self.lock();
+        let id = state.next_id();
+        state.futures.insert(id, future);
+        state.ready.push_back(id);
+        self.changed.notify_all();
+    }
+
+    fn push_job(&self, job: Job) {
+        self.lock().jobs.push_back(job);
+        self.changed.notify_all();
+    }
+
+    fn run_executor(self: Arc<Self>) {
+        let _guard = self.handle.enter();
+        let mut state = self.lock();
+        loop {
+            if state.shutdown {
+                return;
+            }
+
+            if let Some(id) = state.ready.pop_front() {
+                let mut future = match state.futures.remove(&id) {
+                    Some(future) => future,
+                    None => continue,
+                };
+                drop(state);
+
+                let waker = futures::task::waker(Arc::new(TaskWaker {
+                    shared: Arc::downgrade(&self),
+                    id,
+                }));
+                let mut cx = Context::from_waker(&waker);
+                if future.as_mut().poll(&mut cx).is_ready() {
+                    drop(future);
+                    state = self.lock();
+                } else {
+                    state = self.lock();
+                    state.futures.insert(id, future);
+                }
+                continue;
+            }
+
+            if state.is_idle() {
+                if let Some(((deadline, _), timer)) = state.timers.pop_first() {
+                    self.determinism.advance_to(deadline);
+                    if timer.worker {
+                        state.worker_sleeping = false;
+                    }
+                    self.changed.notify_all();
+                    drop(state);
+
+                    if let Some(waker) = timer.waker {
+                        waker.wake();
+                    }
+
+                    state = self.lock();
+                    continue;
+                }
+            }
+
+            state = self.changed.wait(state).unwrap();
+        }
+    }
+
+    fn run_worker(self: Arc<Self>) {
+        IS_WORKER.with(|is_worker| is_worker.set(true));
+        let _guard = self.handle.enter();
+
+        let mut state = self.lock();
+        loop {
+            if state.shutdown {
+                return;
+            }
+
+            if let Some(job) = state.jobs.pop_front() {
+                state.job_running = true;
+                drop(state);
+
+                job();
+
+                state = self.lock();
+                state.job_running = false;
+                self.changed.notify_all();
+                continue;
+            }
+
+            state = self.changed.wait(state).unwrap();
+        }
+    }
+}
+
+struct TaskWaker {
+    shared: Weak<Shared>,
+    id: u64,
+}
+
+impl ArcWake for TaskWaker {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        if let Some(shared) = arc_self.shared.upgrade() {
+            let mut state = shared.lock();
+            if !state.ready.contains(&arc_self.id) {
+                state.ready.push_back(arc_self.id);
+                shared.changed.notify_all();
+            }
+        }
+    }
+}
+
+/// A timer on the virtual clock.
+struct Sleep {
+    shared: Arc<Shared>,
+    key: (Duration, u64),
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut state = self.shared.lock();
+        let mut worker_sleeping = state.worker_sleeping;
+
+        let poll = match state.timers.get_mut(&self.key) {
+            Some(timer) => {
+                timer.waker = Some(cx.waker().clone());
+                if IS_WORKER.with(|is_worker| is_worker.get()) {
+                    timer.worker = true;
+                    worker_sleeping = true;
+                }
+                Poll::Pending
+            }
+            None => Poll::Ready(()),
+        };
+
+        if worker_sleeping != state.worker_sleeping {
+            state.worker_sleeping = worker_sleeping;
+            self.shared.changed.notify_all();
+        }
+        poll
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        if let Some(timer) = state.timers.remove(&self.key) {
+            if timer.worker {
+                state.worker_sleeping = false;
+                self.shared.changed.notify_all();
+            }
+        }
+    }
+}
+
+/// Stops the executor and worker threads once the last handle goes away.
+struct Threads {
+    shared: Arc<Shared>,
+    #[allow(dead_code)]
+    rt: RuntimeOrHandle,
+}
+
+impl Drop for Threads {
+    fn drop(&mut self) {
+        self.shared.lock().shutdown = true;
+        self.shared.changed.notify_all();
+    }
+}
+
+/// A task manager that runs tasks one at a time in a fixed order and sleeps
+/// on a virtual clock.
+#[derive(Clone)]
+pub struct DeterministicTaskManager {
+    threads: Arc<Threads>,
+}
+
+impl DeterministicTaskManager {
+    /// Creates a task manager that does host I/O on the current tokio
+    /// runtime, or on a runtime of its own when there is none.
+    pub fn new(determinism: Arc<Determinism>) -> Self {
+        let rt = match Handle::try_current() {
+            Ok(handle) => RuntimeOrHandle::from(handle),
+            Err(_) => tokio::runtime::Builder::new_multi_thread()
+                .worker_threads(1)
+                .enable_all()
+                .build()
+                .expect("failed to create a tokio runtime")
+                .into(),
+        };
+        Self::with_runtime(determinism, rt)
+    }
+
+    /// Creates a task manager that does host I/O on `rt`.
+    pub fn with_runtime(determinism: Arc<Determinism>, rt: impl Into<RuntimeOrHandle>) -> Self {
+        let rt = rt.into();
+        let shared = Arc::new(Shared {
+            determinism,
+            handle: rt.handle().clone(),
+            state: Mutex::new(State::default()),
+            changed: Condvar::new(),
+        });
+
+        let executor = shared.clone();
+        std::thread::Builder::new()
+            .name("deterministic-executor".to_string())
+            .spawn(move || executor.run_executor())
+            .expect("failed to spawn the executor thread");
+
+        let worker = shared.clone();
+        std::thread::Builder::new()
+            .name("deterministic-worker".to_string())
+            .spawn(move || worker.run_worker())
+            .expect("failed to spawn the worker thread");
+
+        Self {
+            threads: Arc::new(Threads { shared, rt }),
+        }
+    }
+
+    /// The clock and PRNG this task manager advances.
+    pub fn determinism(&self) -> &Arc<Determinism> {
+        &self.threads.shared.determinism
+    }
+}
+
+impl std::fmt::Debug for DeterministicTaskManager {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DeterministicTaskManager")
+            .field("determinism", self.determinism())
+            .finish()
+    }
+}
+
+impl VirtualTaskManager for DeterministicTaskManager {
+    /// See [`VirtualTaskManager::sleep_now`].
+    fn sleep_now(&self, time: Duration) -> Pin<Box<dyn Future<Output = ()> + Send + Sync>> {
+        let shared = self.threads.shared.clone();
+        let deadline = shared.determinism.elapsed() + time;
+
+        let key = {
+            let mut state = shared.lock();
+            let key = (deadline, state.next_id());
+            state.timers.insert(key, Timer::default());
+            key
+        };
+        shared.changed.notify_all();
+
+        Box::pin(Sleep { shared, key })
+    }
+
+    /// See [`VirtualTaskManager::task_shared`].
+    fn task_shared(
+        &self,
+        task: Box<dyn FnOnce() -> BoxFuture<'static, ()> + Send + 'static>,
+    ) -> Result<(), WasiThreadError> {
+        self.threads
+            .shared
+            .spawn(Box::pin(async move { task().await }));
+        Ok(())
+    }
+
+    /// See [`VirtualTaskManager::task_wasm`].
+    fn task_wasm(&self, task: TaskWasm) -> Result<(), WasiThreadError> {
+        // Create the context on a new store
+        let run = task.run;
+        let (ctx, store) = WasiFunctionEnv::new_with_store(
+            task.module,
+            task.env,
+            task.snapshot,
+            task.spawn_type,
+            task.update_layout,
+        )?;
+
+        // If we have a trigger then we first need to run
+        // the poller to completion
+        if let Some(trigger) = task.trigger {
+            let trigger = trigger();
+            let shared = self.threads.shared.clone();
+            self.threads.shared.spawn(Box::pin(async move {
+                let result = trigger.await;
+                shared.push_job(Box::new(move || {
+                    run(TaskWasmRunProperties {
+                        ctx,
+                        store,
+                        trigger_result: Some(result),
+                    });
+                }));
+            }));
+        } else {
+            self.threads.shared.push_job(Box::new(move || {
+                run(TaskWasmRunProperties {
+                    ctx,
+                    store,
+                    trigger_result: None,
+                });
+            }));
+        }
+        Ok(())
+    }
+
+    /// See [`VirtualTaskManager::task_dedicated`].
+    fn task_dedicated(
+        &self,
+        task: Box<dyn FnOnce() + Send + 'static>,
+    ) -> Result<(), WasiThreadError> {
+        self.threads.shared.push_job(task);
+        Ok(())
+    }
+
+    /// See [`VirtualTaskManager::thread_parallelism`].
+    fn thread_parallelism(&self) -> Result<usize, WasiThreadError> {
+        Ok(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use super::*;
+
+    /// Runs tasks that sleep for random amounts of virtual time and returns
+    /// the order they woke up in, when, and what they drew from the PRNG.
+    fn run_schedule(seed: u64) -> Vec<(u32, Duration, u64)> {
+        let tasks = DeterministicTaskManager::new(Arc::new(Determinism::new(seed)));
+        let (sender, receiver) = mpsc::channel();
+
+        // The tasks are spawned by a task so that the clock can't move
+        // before all of them are running
+        let tasks2 = tasks.clone();
+        tasks
+            .task_shared(Box::new(move || {
+                Box::pin(async move {
+                    for task in 0..8 {
+                        let tasks3 = tasks2.clone();
+                        let sender = sender.clone();
+                        tasks2
+                            .task_shared(Box::new(move || {
+                                Box::pin(async move {
+                                    let determinism = tasks3.determinism().clone();
+                                    for _ in 0..4 {
+                                        let mut delay = [0; 2];
+                                        determinism.fill_bytes(&mut delay);
+                                        let delay = u16::from_le_bytes(delay) as u64;
+                                        tasks3.sleep_now(Duration::from_millis(delay)).await;
+
+                                        let mut value = [0; 8];
+                                        determinism.fill_bytes(&mut value);
+                                        sender
+                                            .send((
+                                                task,
+                                                determinism.elapsed(),
+                                                u64::from_le_bytes(value),
+                                            ))
+                                            .unwrap();
+                                    }
+                                })
+                            }))
+                            .unwrap();
+                    }
+                })
+            }))
+            .unwrap();
+
+        receiver.iter().collect()
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_schedule() {
+        let first = run_schedule(7);
+        let second = run_schedule(7);
+        assert_eq!(first.len(), 32);
+        assert_eq!(first, second);
+
+        assert_ne!(first, run_schedule(8));
+        assert!(
+            first.windows(2).all(|pair| pair[0].1 <= pair[1].1),
+            "tasks wake up in the order of their deadlines"
+        );
+    }
+
+    #[test]
+    fn host_io_can_use_the_tokio_runtime() {
+        let tasks = DeterministicTaskManager::new(Arc::new(Determinism::new(0)));
+        let (sender, receiver) = mpsc::channel();
+
+        let sender2 = sender.clone();
+        tasks
+            .task_dedicated(Box::new(move || {
+                sender2.send(Handle::try_current().is_ok()).unwrap();
+            }))
+            .unwrap();
+        tasks
+            .task_shared(Box::new(move || {
+                Box::pin(async move {
+                    let result = tokio::task::spawn_blocking(|| 42).await.unwrap();
+                    sender.send(result == 42).unwrap();
+                })
+            }))
+            .unwrap();
+
+        assert!(receiver.recv().unwrap());
+        assert!(receiver.recv().unwrap());
+    }
+}
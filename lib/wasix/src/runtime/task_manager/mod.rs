@@ -1,5 +1,7 @@
 // TODO: should be behind a different , tokio specific feature flag.
 #[cfg(feature = "sys-thread")]
+pub mod deterministic;
+#[cfg(feature = "sys-thread")]
 pub mod tokio;
 
 use std::ops::Deref;
@@ -7,9 +7,9 @@ use std::{
 };
 
 use bytes::Bytes;
-use rand::Rng;
 use thiserror::Error;
 use virtual_fs::{ArcFile, FsError, TmpFileSystem, VirtualFile};
+use virtual_net::{FirewallPolicy, ResolverConfig};
 use wasmer::{AsStoreMut, Instance, Module, RuntimeError, Store};
 use wasmer_wasix_types::wasi::{Errno, ExitCode};
 
@@ -21,7 +21,7 @@ use crate::{
     fs::{WasiFs, WasiFsRoot, WasiInodes},
     os::task::control_plane::{ControlPlaneConfig, ControlPlaneError, WasiControlPlane},
     runtime::task_manager::InlineWaker,
-    state::WasiState,
+    state::{ProcessSnapshot, WasiState},
     syscalls::types::{__WASI_STDERR_FILENO, __WASI_STDIN_FILENO, __WASI_STDOUT_FILENO},
     RewindState, Runtime, WasiEnv, WasiError, WasiFunctionEnv, WasiRuntimeError,
 };
@@ -70,6 +70,12 @@ pub struct WasiEnvBuilder {
     pub(super) map_commands: HashMap<String, PathBuf>,
 
     pub(super) capabilites: Capabilities,
+
+    /// Seed for running deterministically with the default runtime.
+    pub(super) deterministic: Option<u64>,
+
+    /// Snapshot of a process to resume instead of starting afresh.
+    pub(super) snapshot: Option<Arc<ProcessSnapshot>>,
 }
 
 impl std::fmt::Debug for WasiEnvBuilder {
@@ -85,6 +91,8 @@ impl std::fmt::Debug for WasiEnvBuilder {
             .field("stderr_override exists", &self.stderr.is_some())
             .field("stdin_override exists", &self.stdin.is_some())
             .field("runtime_override_exists", &self.runtime.is_some())
+            .field("deterministic", &self.deterministic)
+            .field("restoring_snapshot", &self.snapshot.is_some())
             .finish()
     }
 }
@@ -114,6 +122,8 @@ pub enum WasiStateCreationError {
     WasiIncludePackageError(String),
     #[error("control plane error")]
     ControlPlane(#[from] ControlPlaneError),
+    #[error("unable to restore the snapshot: `{0}`")]
+    SnapshotRestoreError(String),
 }
 
 fn validate_mapped_dir_alias(alias: &str) -> Result<(), WasiStateCreationError> {
@@ -560,6 +570,39 @@ impl WasiEnvBuilder {
         self.runtime = Some(runtime);
     }
 
+    /// Run deterministically: clocks read from a virtual clock that only
+    /// advances on guest activity, `random_get` draws from a PRNG seeded with
+    /// `seed` and tasks run one at a time in a fixed order.
+    ///
+    /// This configures the default runtime. A runtime passed to
+    /// [`WasiEnvBuilder::runtime()`] has to be made deterministic itself (see
+    /// [`PluggableRuntime::set_deterministic()`](crate::PluggableRuntime::set_deterministic)).
+    pub fn deterministic(mut self, seed: u64) -> Self {
+        self.set_deterministic(seed);
+        self
+    }
+
+    pub fn set_deterministic(&mut self, seed: u64) {
+        self.deterministic = Some(seed);
+    }
+
+    /// Resume a process from a snapshot instead of starting the program from
+    /// the beginning.
+    ///
+    /// The arguments and environment variables are taken from the snapshot.
+    /// Preopened directories, stdio and the file system have to be set up on
+    /// this builder the same way they were when the snapshot was taken. The
+    /// snapshot is only restored if the module hash in the snapshot
+    /// capabilities is the one it was taken from.
+    pub fn restore_snapshot(mut self, snapshot: ProcessSnapshot) -> Self {
+        self.set_restore_snapshot(snapshot);
+        self
+    }
+
+    pub fn set_restore_snapshot(&mut self, snapshot: ProcessSnapshot) {
+        self.snapshot = Some(Arc::new(snapshot));
+    }
+
     pub fn capabilities(mut self, capabilities: Capabilities) -> Self {
         self.set_capabilities(capabilities);
         self
@@ -573,6 +616,32 @@ impl WasiEnvBuilder {
         self.capabilites = capabilities;
     }
 
+    /// Restricts the networking of the guest to the traffic that `policy`
+    /// lets through.
+    pub fn firewall(mut self, policy: FirewallPolicy) -> Self {
+        self.set_firewall(policy);
+        self
+    }
+
+    /// Restricts the networking of the guest to the traffic that `policy`
+    /// lets through.
+    pub fn set_firewall(&mut self, policy: FirewallPolicy) {
+        self.capabilites.networking.firewall = policy;
+    }
+
+    /// Resolves the names that the guest looks up with the hosts and name
+    /// servers of `config` instead of leaving it to the runtime.
+    pub fn resolver(mut self, config: ResolverConfig) -> Self {
+        self.set_resolver(config);
+        self
+    }
+
+    /// Resolves the names that the guest looks up with the hosts and name
+    /// servers of `config` instead of leaving it to the runtime.
+    pub fn set_resolver(&mut self, config: ResolverConfig) {
+        self.capabilites.networking.resolver = config;
+    }
+
     /// Consumes the [`WasiEnvBuilder`] and produces a [`WasiEnvInit`], which
     /// can be used to construct a new [`WasiEnv`].
     ///
@@ -679,10 +748,17 @@ impl WasiEnvBuilder {
             if let Some(f) = &self.setup_fs_fn {
                 f(&inodes, &mut wasi_fs).map_err(WasiStateCreationError::WasiFsSetupError)?;
             }
+
+            if let Some(snapshot) = &self.snapshot {
+                snapshot
+                    .check_module(self.capabilites.snapshot.module_hash)
+                    .and_then(|_| snapshot.restore_fs(&inodes, &wasi_fs))
+                    .map_err(|e| WasiStateCreationError::SnapshotRestoreError(e.to_string()))?;
+            }
             wasi_fs
         };
 
-        let envs = self
+        let envs: Vec<Vec<u8>> = self
             .envs
             .into_iter()
             .map(|(key, value)| {
@@ -695,20 +771,18 @@ impl WasiEnvBuilder {
             })
             .collect();
 
-        let state = WasiState {
-            fs: wasi_fs,
-            secret: rand::thread_rng().gen::<[u8; 32]>(),
-            inodes,
-            args: self.args.clone(),
-            preopen: self.vfs_preopens.clone(),
-            futexs: Default::default(),
-            clock_offset: Default::default(),
-            envs,
-        };
-
+        let deterministic = self.deterministic;
         let runtime = self.runtime.unwrap_or_else(|| {
             #[cfg(feature = "sys-thread")]
             {
+                if let Some(seed) = deterministic {
+                    let determinism = Arc::new(crate::runtime::Determinism::new(seed));
+                    let tasks = crate::runtime::task_manager::deterministic::DeterministicTaskManager::new(determinism.clone());
+                    let mut runtime = PluggableRuntime::new(Arc::new(tasks));
+                    runtime.determinism = Some(determinism);
+                    return Arc::new(runtime);
+                }
+
                 Arc::new(PluggableRuntime::new(Arc::new(crate::runtime::task_manager::tokio::TokioTaskManager::default())))
             }
 
@@ -717,17 +791,44 @@ impl WasiEnvBuilder {
                 panic!("this build does not support a default runtime - specify one with WasiEnvBuilder::runtime()");
             }
         });
+        if deterministic.is_some() && runtime.determinism().is_none() {
+            tracing::warn!("The runtime is not deterministic, ignoring the deterministic seed");
+        }
+
+        let (args, envs) = match (&self.snapshot, runtime.journal()) {
+            (Some(snapshot), _) => (snapshot.args().to_vec(), snapshot.envs().to_vec()),
+            (None, Some(journal)) => journal.environment(self.args.clone(), envs),
+            (None, None) => (self.args.clone(), envs),
+        };
+        let clock_offset = self
+            .snapshot
+            .as_ref()
+            .map(|snapshot| snapshot.clock_offset())
+            .unwrap_or_default();
+
+        let state = WasiState {
+            fs: wasi_fs,
+            secret: super::generate_secret(runtime.as_ref()),
+            inodes,
+            args,
+            preopen: self.vfs_preopens.clone(),
+            futexs: Default::default(),
+            clock_offset: std::sync::Mutex::new(clock_offset),
+            envs,
+        };
 
         let uses = self.uses;
         let map_commands = self.map_commands;
 
-        let bin_factory = BinFactory::new(runtime.clone());
-
         let capabilities = self.capabilites;
 
+        let mut bin_factory = BinFactory::new(runtime.clone());
+        bin_factory.set_check_exec_permission(capabilities.filesystem.check_exec_permission);
+
         let plane_config = ControlPlaneConfig {
             max_task_count: capabilities.threading.max_threads,
             enable_asynchronous_threading: capabilities.threading.enable_asynchronous_threading,
+            default_limits: capabilities.limits.rlimits.clone().unwrap_or_default(),
         };
         let control_plane = WasiControlPlane::new(plane_config);
 
@@ -815,11 +916,23 @@ impl WasiEnvBuilder {
             );
         }
 
+        let snapshot = self.snapshot.clone();
         let (instance, env) = self.instantiate(module, store)?;
 
         let start = instance.exports.get_function("_start")?;
         env.data(&store).thread.set_status_running();
 
+        if let Some(snapshot) = snapshot {
+            snapshot.restore_memory(&env, store)?;
+            let (rewind_state, rewind_result) = snapshot.rewind_state();
+            let errno = rewind_instance(store, &env, rewind_state, rewind_result);
+            if errno != Errno::Success {
+                let exit_code = ExitCode::from(errno);
+                env.cleanup(store, Some(exit_code));
+                return Err(WasiRuntimeError::Wasi(WasiError::Exit(exit_code)));
+            }
+        }
+
         let result = crate::run_wasi_func_start(start, store);
         let (result, exit_code) = wasi_exit_code(result);
 
@@ -859,10 +972,19 @@ impl WasiEnvBuilder {
         #[cfg(feature = "sys-thread")]
         let _guard = _guard.as_ref().map(|r| r.enter());
 
+        let snapshot = self.snapshot.clone();
         let (_, env) = self.instantiate(module, &mut store)?;
 
         env.data(&store).thread.set_status_running();
 
+        let rewind_state = match snapshot {
+            Some(snapshot) => {
+                snapshot.restore_memory(&env, &mut store)?;
+                Some(snapshot.rewind_state())
+            }
+            None => None,
+        };
+
         let tasks = env.data(&store).tasks().clone();
         let pid = env.data(&store).pid();
         let tid = env.data(&store).tid();
@@ -873,7 +995,7 @@ impl WasiEnvBuilder {
         let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
 
         tasks.task_dedicated(Box::new(move || {
-            run_with_deep_sleep(store, None, env, tx);
+            run_with_deep_sleep(store, rewind_state, env, tx);
         }))?;
 
         let result = InlineWaker::block_on(rx.recv());
@@ -929,23 +1051,7 @@ fn run_with_deep_sleep(
 ) {
     if let Some((rewind_state, rewind_result)) = rewind_state {
         tracing::trace!("Rewinding");
-        let errno = if rewind_state.is_64bit {
-            crate::rewind_ext::<wasmer_types::Memory64>(
-                env.env.clone().into_mut(&mut store),
-                rewind_state.memory_stack,
-                rewind_state.rewind_stack,
-                rewind_state.store_data,
-                rewind_result,
-            )
-        } else {
-            crate::rewind_ext::<wasmer_types::Memory32>(
-                env.env.clone().into_mut(&mut store),
-                rewind_state.memory_stack,
-                rewind_state.rewind_stack,
-                rewind_state.store_data,
-                rewind_result,
-            )
-        };
+        let errno = rewind_instance(&mut store, &env, rewind_state, rewind_result);
 
         if errno != Errno::Success {
             let exit_code = ExitCode::from(errno);
@@ -981,6 +1087,33 @@ fn run_with_deep_sleep(
     handle_result(store, env, result, sender);
 }
 
+/// Prepare an instance to rewind back into the call it was unwound from the
+/// next time it is started.
+fn rewind_instance(
+    store: &mut Store,
+    env: &WasiFunctionEnv,
+    rewind_state: RewindState,
+    rewind_result: Bytes,
+) -> Errno {
+    if rewind_state.is_64bit {
+        crate::rewind_ext::<wasmer_types::Memory64>(
+            env.env.clone().into_mut(store),
+            rewind_state.memory_stack,
+            rewind_state.rewind_stack,
+            rewind_state.store_data,
+            rewind_result,
+        )
+    } else {
+        crate::rewind_ext::<wasmer_types::Memory32>(
+            env.env.clone().into_mut(store),
+            rewind_state.memory_stack,
+            rewind_state.rewind_stack,
+            rewind_state.store_data,
+            rewind_result,
+        )
+    }
+}
+
 fn handle_result(
     mut store: Store,
     env: WasiFunctionEnv,
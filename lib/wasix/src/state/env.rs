@@ -8,17 +8,19 @@ use std::{
 
 use derivative::Derivative;
 use futures::future::BoxFuture;
-use rand::Rng;
 use tracing::{trace, warn};
 use virtual_fs::{FileSystem, FsError, StaticFile, VirtualFile};
-use virtual_net::DynVirtualNetworking;
+use virtual_net::{DynVirtualNetworking, FirewallNetworking, ResolverNetworking};
 use wasmer::{
     AsStoreMut, AsStoreRef, FunctionEnvMut, Global, Instance, Memory, MemoryType, MemoryView,
-    Module, TypedFunction,
+    Module, TypedFunction, Value,
 };
 use wasmer_wasix_types::{
     types::Signal,
-    wasi::{Errno, ExitCode, Snapshot0Clockid},
+    wasi::{
+        Errno, ExitCode, RlimitResource, Sigaction, SigactionFlags, Siginfo, SignalCode,
+        Snapshot0Clockid,
+    },
 };
 
 use crate::{
@@ -28,7 +30,9 @@ use crate::{
     import_object_for_all_wasi_versions,
     os::task::{
         control_plane::ControlPlaneError,
+        limits::ResourceLimit,
         process::{WasiProcess, WasiProcessId},
+        signal::{default_sigaction, new_siginfo, sigset_of, SignalDelivery},
         thread::{WasiMemoryLayout, WasiThread, WasiThreadHandle, WasiThreadId},
     },
     runtime::{resolver::PackageSpecifier, task_manager::InlineWaker, SpawnMemoryType},
@@ -243,7 +247,7 @@ impl WasiEnvInit {
 
         Self {
             state: WasiState {
-                secret: rand::thread_rng().gen::<[u8; 32]>(),
+                secret: super::generate_secret(self.runtime.as_ref()),
                 inodes,
                 fs,
                 futexs: Default::default(),
@@ -293,6 +297,9 @@ pub struct WasiEnv {
     pub owned_handles: Vec<WasiThreadHandle>,
     /// Implementation of the WASI runtime.
     pub runtime: Arc<dyn Runtime + Send + Sync + 'static>,
+    /// Networking of the runtime, with the resolver and the firewall of the
+    /// capabilities in front of it
+    net: DynVirtualNetworking,
 
     pub capabilities: Capabilities,
 
@@ -326,6 +333,7 @@ impl Clone for WasiEnv {
             inner: Default::default(),
             owned_handles: self.owned_handles.clone(),
             runtime: self.runtime.clone(),
+            net: self.net.clone(),
             capabilities: self.capabilities.clone(),
             enable_deep_sleep: self.enable_deep_sleep,
         }
@@ -340,11 +348,26 @@ impl WasiEnv {
 
     /// Forking the WasiState is used when either fork or vfork is called
     pub fn fork(&self) -> Result<(Self, WasiThreadHandle), ControlPlaneError> {
-        let process = self.control_plane.new_process()?;
+        let limits = self.process.limits();
+        if let Some(max) = limits.processes.get() {
+            if self.process.running_children() as u64 >= max {
+                return Err(ControlPlaneError::ResourceLimitReached {
+                    resource: RlimitResource::Processes,
+                });
+            }
+        }
+
+        // The child inherits the limits, signal dispositions, process group,
+        // session and memory mappings of its parent
+        let process = self.control_plane.new_child_process(&self.process)?;
+        process.set_limits(limits);
+        process.set_signal_actions(self.process.signal_actions());
+        *process.memory_maps() = self.process.memory_maps().clone();
         let handle = process.new_thread()?;
 
         let thread = handle.as_thread();
         thread.copy_stack_from(&self.thread);
+        thread.copy_signal_state_from(&self.thread);
 
         let state = Arc::new(self.state.fork());
 
@@ -362,6 +385,7 @@ impl WasiEnv {
             inner: Default::default(),
             owned_handles: Vec::new(),
             runtime: self.runtime.clone(),
+            net: self.net.clone(),
             capabilities: self.capabilities.clone(),
             enable_deep_sleep: self.enable_deep_sleep,
         };
@@ -421,12 +445,16 @@ impl WasiEnv {
             state: Arc::new(init.state),
             inner: Default::default(),
             owned_handles: Vec::new(),
+            net: Self::instance_net(init.runtime.as_ref(), &init.capabilities),
             runtime: init.runtime,
             bin_factory: init.bin_factory,
             enable_deep_sleep: init.capabilities.threading.enable_asynchronous_threading,
             capabilities: init.capabilities,
         };
         env.owned_handles.push(thread);
+        env.state
+            .fs
+            .set_max_fds(env.process.limits().open_files.soft);
 
         // TODO: should not be here - should be callers responsibility!
         for pkg in &init.webc_dependencies {
@@ -458,6 +486,7 @@ impl WasiEnv {
         let env = Self::from_init(init)?;
 
         let pid = env.process.pid();
+        let limits = env.process.limits();
 
         let mut store = store.as_store_mut();
 
@@ -468,13 +497,12 @@ impl WasiEnv {
         let shared_memory = module.imports().memories().next().map(|a| *a.ty());
 
         // Determine if we are going to create memory and import it or just rely on self creation of memory
-        let spawn_type = if let Some(t) = spawn_type {
-            SpawnMemoryType::CreateMemoryOfType(t)
-        } else {
-            match shared_memory {
-                Some(ty) => SpawnMemoryType::CreateMemoryOfType(ty),
-                None => SpawnMemoryType::CreateMemory,
+        let spawn_type = match spawn_type.or(shared_memory) {
+            Some(mut ty) => {
+                limits.limit_memory(&mut ty);
+                SpawnMemoryType::CreateMemoryOfType(ty)
             }
+            None => SpawnMemoryType::CreateMemory,
         };
         let memory = tasks.build_memory(&mut store, spawn_type)?;
 
@@ -490,16 +518,17 @@ impl WasiEnv {
         };
 
         // Construct the instance.
-        let instance = match Instance::new(&mut store, &module, &import_object) {
-            Ok(a) => a,
-            Err(err) => {
-                tracing::error!("wasi[{}]::wasm instantiate error ({})", pid, err);
-                func_env
-                    .data(&store)
-                    .blocking_cleanup(Some(Errno::Noexec.into()));
-                return Err(err.into());
-            }
-        };
+        let instance =
+            match limits.instantiate(|| Instance::new(&mut store, &module, &import_object)) {
+                Ok(a) => a,
+                Err(err) => {
+                    tracing::error!("wasi[{}]::wasm instantiate error ({})", pid, err);
+                    func_env
+                        .data(&store)
+                        .blocking_cleanup(Some(Errno::Noexec.into()));
+                    return Err(err.into());
+                }
+            };
 
         // Run initializers.
         instance_init_callback(&instance, &store).unwrap();
@@ -561,133 +590,255 @@ impl WasiEnv {
     pub(crate) fn process_signals_and_exit(
         ctx: &mut FunctionEnvMut<'_, Self>,
     ) -> Result<Result<bool, Errno>, WasiError> {
-        // If a signal handler has never been set then we need to handle signals
-        // differently
-        let env = ctx.data();
-        let inner = env
-            .try_inner()
-            .ok_or_else(|| WasiError::Exit(Errno::Fault.into()))?;
-        if !inner.signal_set {
-            let signals = env.thread.pop_signals();
-            if !signals.is_empty() {
-                for sig in signals {
-                    if sig == Signal::Sigint
-                        || sig == Signal::Sigquit
-                        || sig == Signal::Sigkill
-                        || sig == Signal::Sigabrt
-                    {
-                        let exit_code = env.thread.set_or_get_exit_code_for_signal(sig);
-                        return Err(WasiError::Exit(exit_code));
-                    } else {
-                        trace!("wasi[{}]::signal-ignored: {:?}", env.pid(), sig);
-                    }
-                }
-                return Ok(Ok(true));
-            }
-        }
-
         // Check for forced exit
+        let env = ctx.data();
         if let Some(forced_exit) = env.should_exit() {
             return Err(WasiError::Exit(forced_exit));
         }
+        match env.check_run_time_limit() {
+            Ok(Some(exit_code)) => return Err(WasiError::Exit(exit_code)),
+            Ok(None) => {}
+            Err(err) => return Ok(Err(err)),
+        }
 
         Self::process_signals(ctx)
     }
 
-    /// Porcesses any signals that are batched up
+    /// Raises `SIGXCPU` once the process goes over its soft run time limit
+    /// and returns the exit code to kill it with when it goes over the hard
+    /// one. Fails when there is a limit but the host clock can't be read.
+    fn check_run_time_limit(&self) -> Result<Option<ExitCode>, Errno> {
+        let limit = self.process.limits.read().unwrap().run_time;
+        let Some(soft) = limit.get() else {
+            return Ok(None);
+        };
+
+        let used = self.process.run_time()?.as_secs();
+        if limit.hard != ResourceLimit::INFINITY && used >= limit.hard {
+            tracing::debug!(pid = %self.pid(), %used, "process went over its run time limit");
+            let exit_code = self.thread.set_or_get_exit_code_for_signal(Signal::Sigkill);
+            self.process.terminate(exit_code);
+            return Ok(Some(exit_code));
+        }
+        if used >= soft {
+            let first_time = {
+                let mut inner = self.process.inner.write().unwrap();
+                !std::mem::replace(&mut inner.run_clock.exceeded_soft, true)
+            };
+            if first_time {
+                self.process.signal_process(Signal::Sigxcpu);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Porcesses any signals that are batched up, returns true if a signal
+    /// handler ran that did not ask for syscalls to be restarted
     pub(crate) fn process_signals(
         ctx: &mut FunctionEnvMut<'_, Self>,
     ) -> Result<Result<bool, Errno>, WasiError> {
-        // If a signal handler has never been set then we need to handle signals
-        // differently
         let env = ctx.data();
-        let inner = env
-            .try_inner()
-            .ok_or_else(|| WasiError::Exit(Errno::Fault.into()))?;
-        if !inner.signal_set {
-            return Ok(Ok(false));
+        if env.try_inner().is_none() {
+            return Err(WasiError::Exit(Errno::Fault.into()));
         }
 
-        // Check for any signals that we need to trigger
-        // (but only if a signal handler is registered)
-        let ret = if inner.signal.as_ref().is_some() {
-            let signals = env.thread.pop_signals();
-            Self::process_signals_internal(ctx, signals)?
-        } else {
-            false
-        };
-
+        let signals = env.thread.pop_signals();
+        let ret = Self::process_signals_internal(ctx, signals)?;
         Ok(Ok(ret))
     }
 
+    /// Delivers signals that were popped from the thread, returns true if a
+    /// signal handler ran that did not ask for syscalls to be restarted
     pub(crate) fn process_signals_internal(
         ctx: &mut FunctionEnvMut<'_, Self>,
         mut signals: Vec<Signal>,
     ) -> Result<bool, WasiError> {
         let env = ctx.data();
-        let inner = env
+        let handler = env
             .try_inner()
-            .ok_or_else(|| WasiError::Exit(Errno::Fault.into()))?;
-        if let Some(handler) = inner.signal.clone() {
-            // We might also have signals that trigger on timers
-            let mut now = 0;
-            let has_signal_interval = {
-                let mut any = false;
-                let inner = env.process.inner.read().unwrap();
-                if !inner.signal_intervals.is_empty() {
-                    now = platform_clock_time_get(Snapshot0Clockid::Monotonic, 1_000_000).unwrap()
-                        as u128;
-                    for signal in inner.signal_intervals.values() {
-                        let elapsed = now - signal.last_signal;
-                        if elapsed >= signal.interval.as_nanos() {
-                            any = true;
-                            break;
-                        }
+            .ok_or_else(|| WasiError::Exit(Errno::Fault.into()))?
+            .signal
+            .clone();
+
+        // We might also have signals that trigger on timers
+        let mut now = 0;
+        let has_signal_interval = {
+            let mut any = false;
+            let inner = env.process.inner.read().unwrap();
+            if !inner.signal_intervals.is_empty() {
+                now = platform_clock_time_get(Snapshot0Clockid::Monotonic, 1_000_000).unwrap()
+                    as u128;
+                for signal in inner.signal_intervals.values() {
+                    let elapsed = now - signal.last_signal;
+                    if elapsed >= signal.interval.as_nanos() {
+                        any = true;
+                        break;
                     }
                 }
-                any
-            };
-            if has_signal_interval {
+            }
+            any
+        };
+        if has_signal_interval {
+            {
                 let mut inner = env.process.inner.write().unwrap();
                 for signal in inner.signal_intervals.values_mut() {
                     let elapsed = now - signal.last_signal;
                     if elapsed >= signal.interval.as_nanos() {
                         signal.last_signal = now;
-                        signals.push(signal.signal);
+                        let info = new_siginfo(signal.signal, SignalCode::Timer, None);
+                        env.thread.signal_with_info(signal.signal, info);
                     }
                 }
             }
+            signals.extend(env.thread.pop_signals());
+        }
 
-            for signal in signals {
-                tracing::trace!(
-                    "wasi[{}]::processing-signal: {:?}",
-                    ctx.data().pid(),
-                    signal
-                );
-                if let Err(err) = handler.call(ctx, signal as i32) {
-                    match err.downcast::<WasiError>() {
-                        Ok(wasi_err) => {
-                            warn!(
-                                "wasi[{}]::signal handler wasi error - {}",
-                                ctx.data().pid(),
-                                wasi_err
-                            );
-                            return Err(wasi_err);
-                        }
-                        Err(runtime_err) => {
-                            warn!(
-                                "wasi[{}]::signal handler runtime error - {}",
-                                ctx.data().pid(),
-                                runtime_err
-                            );
-                            return Err(WasiError::Exit(Errno::Intr.into()));
+        let mut interrupted = false;
+        for signal in signals {
+            let env = ctx.data();
+            let info = env.thread.take_siginfo(signal);
+            match env.process.signal_delivery(signal, handler.is_some()) {
+                SignalDelivery::Terminate => {
+                    let exit_code = env.terminate_for_signal(signal);
+                    return Err(WasiError::Exit(exit_code));
+                }
+                SignalDelivery::Ignore => {
+                    trace!("wasi[{}]::signal-ignored: {:?}", env.pid(), signal);
+                }
+                SignalDelivery::Stop => {
+                    env.process.stop(signal);
+                }
+                SignalDelivery::Handler(action) => {
+                    if let Some(handler) = handler.as_ref() {
+                        Self::call_signal_handler(ctx, handler, signal, info, action)?;
+                        if !action.flags.contains(SigactionFlags::RESTART) {
+                            interrupted = true;
                         }
                     }
                 }
             }
-            Ok(true)
-        } else {
-            Ok(false)
+        }
+        ctx.data().wait_while_stopped()?;
+        Ok(interrupted)
+    }
+
+    /// Parks the thread for as long as the process is stopped by a job
+    /// control signal
+    fn wait_while_stopped(&self) -> Result<(), WasiError> {
+        if self.process.stopped_by().is_some() {
+            let _blocking = self.process.start_blocking();
+            InlineWaker::block_on(self.process.wait_until_continued());
+        }
+        match self.should_exit() {
+            Some(exit_code) => Err(WasiError::Exit(exit_code)),
+            None => Ok(()),
+        }
+    }
+
+    /// Terminates the process because of a signal and returns the exit code
+    fn terminate_for_signal(&self, signal: Signal) -> ExitCode {
+        let exit_code = self.thread.set_or_get_exit_code_for_signal(signal);
+        self.process.terminate(exit_code);
+
+        // The other threads might be waiting on a signal the main thread
+        // blocked, so they are woken up so they can exit as well
+        if !self.thread.is_main() {
+            self.process.signal_threads(Signal::Sigkill);
+        }
+        exit_code
+    }
+
+    /// Invokes the signal callback the way `action` asks for
+    fn call_signal_handler(
+        ctx: &mut FunctionEnvMut<'_, Self>,
+        handler: &TypedFunction<i32, ()>,
+        signal: Signal,
+        info: Siginfo,
+        action: Sigaction,
+    ) -> Result<(), WasiError> {
+        let env = ctx.data();
+        tracing::trace!("wasi[{}]::processing-signal: {:?}", env.pid(), signal);
+
+        // The signals in the mask of the action, along with the signal itself,
+        // are blocked while the handler runs
+        let mut mask = action.mask;
+        if !action.flags.contains(SigactionFlags::NODEFER) {
+            mask |= sigset_of(signal);
+        }
+        let old_mask = env.thread.set_signal_mask(env.thread.signal_mask() | mask);
+        if action.flags.contains(SigactionFlags::RESETHAND) {
+            env.process.set_signal_action(signal, default_sigaction());
+        }
+
+        // Handlers that ask for it run on the alternate stack, unless the
+        // thread is already running on it
+        let stack = match env.thread.signal_stack() {
+            (Some(stack), _) if action.flags.contains(SigactionFlags::ONSTACK) => Some(stack),
+            _ => None,
+        };
+        let switched = env.thread.enter_signal_handler(info, stack.is_some());
+        let old_stack_pointer = match stack {
+            Some(stack) if switched => {
+                let top = (stack.base + stack.size) & !15;
+                Self::swap_stack_pointer(ctx, top)
+            }
+            _ => None,
+        };
+
+        let res = handler.call(ctx, signal as i32);
+
+        if let Some(old_stack_pointer) = old_stack_pointer {
+            Self::restore_stack_pointer(ctx, old_stack_pointer);
+        }
+        let env = ctx.data();
+        env.thread.leave_signal_handler(switched);
+        env.thread.set_signal_mask(old_mask);
+
+        if let Err(err) = res {
+            match err.downcast::<WasiError>() {
+                Ok(wasi_err) => {
+                    warn!(
+                        "wasi[{}]::signal handler wasi error - {}",
+                        ctx.data().pid(),
+                        wasi_err
+                    );
+                    return Err(wasi_err);
+                }
+                Err(runtime_err) => {
+                    warn!(
+                        "wasi[{}]::signal handler runtime error - {}",
+                        ctx.data().pid(),
+                        runtime_err
+                    );
+                    return Err(WasiError::Exit(Errno::Intr.into()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Points `__stack_pointer` at `stack_pointer` and returns its old value
+    fn swap_stack_pointer(ctx: &mut FunctionEnvMut<'_, Self>, stack_pointer: u64) -> Option<Value> {
+        let global = ctx.data().try_inner()?.stack_pointer.clone()?;
+        let old = global.get(ctx);
+        let new = match old {
+            Value::I32(_) => Value::I32(stack_pointer as i32),
+            Value::I64(_) => Value::I64(stack_pointer as i64),
+            _ => return None,
+        };
+        global.set(ctx, new).ok()?;
+        Some(old)
+    }
+
+    fn restore_stack_pointer(ctx: &mut FunctionEnvMut<'_, Self>, stack_pointer: Value) {
+        let global = ctx
+            .data()
+            .try_inner()
+            .and_then(|inner| inner.stack_pointer.clone());
+        if let Some(global) = global {
+            if let Err(err) = global.set(ctx, stack_pointer) {
+                warn!("unable to restore the stack pointer - {}", err);
+            }
         }
     }
 
@@ -711,7 +862,26 @@ impl WasiEnv {
 
     /// Accesses the virtual networking implementation
     pub fn net(&self) -> &DynVirtualNetworking {
-        self.runtime.networking()
+        &self.net
+    }
+
+    /// Puts the resolver and the firewall of the capabilities in front of
+    /// the networking of the runtime, the firewall goes first so that it
+    /// sees the names that the guest looks up
+    fn instance_net(
+        runtime: &(dyn Runtime + Send + Sync),
+        capabilities: &Capabilities,
+    ) -> DynVirtualNetworking {
+        let mut net = runtime.networking().clone();
+        let resolver = &capabilities.networking.resolver;
+        if !resolver.is_empty() {
+            net = Arc::new(ResolverNetworking::new(net, resolver.clone()));
+        }
+        let policy = &capabilities.networking.firewall;
+        if !policy.is_empty() {
+            net = Arc::new(FirewallNetworking::new(net, policy.clone()));
+        }
+        net
     }
 
     /// Providers safe access to the initialized part of WasiEnv
@@ -1025,12 +1195,20 @@ impl WasiEnv {
             trace!("wasi[{}]:: cleaning up open file handles", self.pid());
 
             // Now send a signal that the thread is terminated
-            self.process.signal_process(Signal::Sigquit);
+            self.process.signal_threads(Signal::Sigquit);
 
             // Terminate the process
             let exit_code = exit_code.unwrap_or_else(|| Errno::Canceled.into());
             self.process.terminate(exit_code);
 
+            // Let the parent know that the child has exited
+            self.process.signal_parent();
+
+            // Release any advisory file locks the process is still holding
+            if let Some(control_plane) = self.process.compute.upgrade() {
+                control_plane.file_locks().release_process(self.pid());
+            }
+
             let timeout = self.tasks().sleep_now(CLEANUP_TIMEOUT);
             let state = self.state.clone();
             Box::pin(async move {
@@ -42,6 +42,7 @@ impl WasiFunctionEnv {
         // Create a new store and put the memory object in it
         // (but only if it has imported memory)
         let mut store = env.runtime.new_store();
+        let limits = env.process.limits();
         let memory = env
             .tasks()
             .build_memory(&mut store.as_store_mut(), spawn_type)?;
@@ -54,10 +55,12 @@ impl WasiFunctionEnv {
             import_object.define("env", "memory", memory);
         }
 
-        let instance = Instance::new(&mut store, &module, &import_object).map_err(|err| {
-            tracing::warn!("failed to create instance - {}", err);
-            WasiThreadError::InstanceCreateFailed(Box::new(err))
-        })?;
+        let instance = limits
+            .instantiate(|| Instance::new(&mut store, &module, &import_object))
+            .map_err(|err| {
+                tracing::warn!("failed to create instance - {}", err);
+                WasiThreadError::InstanceCreateFailed(Box::new(err))
+            })?;
 
         init(&instance, &store).map_err(|err| {
             tracing::warn!("failed to init instance - {}", err);
@@ -213,7 +216,27 @@ impl WasiFunctionEnv {
             self.data(store).tid()
         );
 
+        // The shared mappings are written back while the memory and the
+        // files are still around
+        let env = self.data(store);
+        if env.thread.is_main() {
+            if let Some(memory) = env.try_memory_view(store) {
+                crate::syscalls::write_back_memory_maps_on_exit(env, &memory);
+            }
+        }
+
         // Cleans up all the open files (if this is the main thread)
         self.data(store).blocking_cleanup(exit_code);
+
+        // The journal is buffered, what the process recorded is written out
+        // in case the runtime exits without dropping it
+        let env = self.data(store);
+        if env.thread.is_main() {
+            if let Some(journal) = env.runtime().journal() {
+                if let Err(err) = journal.flush() {
+                    tracing::warn!("failed to write out the syscall journal - {}", err);
+                }
+            }
+        }
     }
 }
@@ -19,6 +19,7 @@ mod builder;
 mod env;
 mod func_env;
 mod handles;
+mod snapshot;
 mod types;
 
 use std::{
@@ -29,6 +30,7 @@ use std::{
     time::Duration,
 };
 
+use rand::Rng;
 #[cfg(feature = "enable-serde")]
 use serde::{Deserialize, Serialize};
 use virtual_fs::{FileOpener, FileSystem, FsError, OpenOptions, VirtualFile};
@@ -38,6 +40,7 @@ pub use self::{
     builder::*,
     env::{WasiEnv, WasiEnvInit, WasiInstanceHandles},
     func_env::WasiFunctionEnv,
+    snapshot::{ProcessSnapshot, SnapshotError},
     types::*,
 };
 pub use crate::fs::{InodeGuard, InodeWeakGuard};
@@ -45,8 +48,10 @@ use crate::{
     fs::{fs_error_into_wasi_err, WasiFs, WasiFsRoot, WasiInodes, WasiStateFileGuard},
     syscalls::types::*,
     utils::WasiParkingLot,
+    Runtime,
 };
 pub(crate) use handles::*;
+pub(crate) use snapshot::SnapshotResult;
 
 /// all the rights enabled
 pub const ALL_RIGHTS: Rights = Rights::all();
@@ -154,6 +159,26 @@ impl WasiState {
     // }
 }
 
+/// Generates the secret used to sign stack snapshots, drawing from the seeded
+/// PRNG when the runtime is deterministic and going through the runtime's
+/// journal when there is one.
+pub(crate) fn generate_secret(runtime: &(dyn Runtime + Send + Sync)) -> [u8; 32] {
+    let fill = |secret: &mut [u8]| {
+        match runtime.determinism() {
+            Some(determinism) => determinism.fill_bytes(secret),
+            None => rand::thread_rng().fill(secret),
+        }
+        Ok(())
+    };
+
+    let mut secret = [0; 32];
+    let _ = match runtime.journal() {
+        Some(journal) => journal.random_get(&mut secret, fill),
+        None => fill(&mut secret),
+    };
+    secret
+}
+
 // Implementations of direct to FS calls so that we can easily change their implementation
 impl WasiState {
     pub(crate) fn fs_read_dir<P: AsRef<Path>>(
@@ -0,0 +1,576 @@
+//! Saving an idle process to disk and resuming it later.
+//!
+//! A [`ProcessSnapshot`] holds everything needed to carry on running a
+//! single threaded WASIX process: its linear memory and globals, the call
+//! stack that asyncify captured when it was unwound, its arguments and
+//! environment, and the file descriptors it has open. A guest takes one by
+//! calling `proc_snapshot`, which writes it to the file the host allowed with
+//! [`CapabilitySnapshotV1`](crate::capabilities::CapabilitySnapshotV1), and
+//! it is resumed with [`WasiEnvBuilder::restore_snapshot()`].
+//!
+//! Only regular files and directories survive a snapshot. The contents of
+//! every open file are stored in the snapshot and written back on restore if
+//! the file no longer exists, so files in a temporary file system move with
+//! the process, while files that still exist are reopened as they are.
+//! Directories that are gone are created again, empty. Preopened directories
+//! and stdio are taken from the environment the snapshot is restored into.
+//! Sockets, pipes and processes with more than one thread can't be
+//! snapshotted.
+//!
+//! A snapshot records the hash of the module it was taken from, given by
+//! [`CapabilitySnapshotV1::module_hash`](crate::capabilities::CapabilitySnapshotV1::module_hash),
+//! and is only restored into a module with the same hash.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufReader, BufWriter},
+    ops::Deref,
+    path::{Path, PathBuf},
+    sync::{atomic::Ordering, Arc, RwLock},
+};
+
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use virtual_fs::{AsyncReadExt, AsyncWriteExt, FileSystem, FsError, VirtualFile};
+use wasmer::{FunctionEnvMut, MemoryAccessError, MemoryError, Pages, Store, WASM_PAGE_SIZE};
+use wasmer_wasix_types::{
+    types::__WASI_STDERR_FILENO,
+    wasi::{Errno, Fd as WasiFd, Fdflags, Rights, Snapshot0Clockid},
+};
+
+use crate::{
+    fs::{create_dir_all, fs_error_into_wasi_err, Fd, Kind, WasiFs, WasiFsRoot, WasiInodes},
+    runtime::{module_cache::ModuleHash, task_manager::InlineWaker},
+    RewindState, WasiEnv, WasiFunctionEnv,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum SnapshotError {
+    #[error("unable to access the snapshot")]
+    Io(#[from] io::Error),
+    #[error("the snapshot is corrupt")]
+    Format(#[from] bincode::Error),
+    #[error("only processes with a single thread can be snapshotted")]
+    MultipleThreads,
+    #[error("the hash of the module is unknown")]
+    UnknownModule,
+    #[error("the snapshot was taken from a different module")]
+    WrongModule,
+    #[error("file descriptor {0} can't be snapshotted")]
+    UnsupportedFd(WasiFd),
+    #[error("preopened directory `{0}` is missing")]
+    MissingPreopen(String),
+    #[error("file system error")]
+    Fs(#[from] FsError),
+    #[error("unable to restore a file descriptor")]
+    Wasi(#[from] Errno),
+    #[error("the instance has no memory")]
+    NoMemory,
+    #[error("unable to grow the memory")]
+    MemoryGrow(#[from] MemoryError),
+    #[error("unable to access the memory")]
+    MemoryAccess(#[from] MemoryAccessError),
+}
+
+impl From<SnapshotError> for Errno {
+    fn from(err: SnapshotError) -> Self {
+        match err {
+            SnapshotError::Io(_) | SnapshotError::Format(_) => Errno::Io,
+            SnapshotError::MultipleThreads
+            | SnapshotError::UnknownModule
+            | SnapshotError::UnsupportedFd(_) => Errno::Notsup,
+            SnapshotError::WrongModule => Errno::Inval,
+            SnapshotError::MissingPreopen(_) => Errno::Noent,
+            SnapshotError::Fs(err) => fs_error_into_wasi_err(err),
+            SnapshotError::Wasi(errno) => errno,
+            SnapshotError::NoMemory
+            | SnapshotError::MemoryGrow(_)
+            | SnapshotError::MemoryAccess(_) => Errno::Fault,
+        }
+    }
+}
+
+/// The value `proc_snapshot` returns to the guest.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct SnapshotResult {
+    pub restored: bool,
+    pub ret: Errno,
+}
+
+/// An idle process, frozen so it can be resumed later.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProcessSnapshot {
+    /// The hash of the module the snapshot was taken from.
+    module_hash: [u8; 32],
+    args: Vec<String>,
+    envs: Vec<Vec<u8>>,
+    current_dir: String,
+    clock_offset: Vec<(u32, i64)>,
+    next_fd: WasiFd,
+    fds: Vec<FdSnapshot>,
+    /// The contents of linear memory.
+    memory: Vec<u8>,
+    /// The stack and globals captured when the process was unwound.
+    memory_stack: Vec<u8>,
+    rewind_stack: Vec<u8>,
+    store_data: Vec<u8>,
+    is_64bit: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct FdSnapshot {
+    fd: WasiFd,
+    rights: u64,
+    rights_inheriting: u64,
+    flags: u16,
+    open_flags: u16,
+    offset: u64,
+    kind: FdKindSnapshot,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum FdKindSnapshot {
+    /// Stdio and other files the host provides, which are taken from the
+    /// environment being restored into.
+    Special,
+    /// A preopened directory, which the environment being restored into must
+    /// preopen as well.
+    Preopen {
+        name: String,
+    },
+    Dir {
+        path: PathBuf,
+    },
+    File {
+        path: PathBuf,
+        contents: Vec<u8>,
+    },
+}
+
+impl ProcessSnapshot {
+    /// Capture the process that `ctx` belongs to, which has just been unwound.
+    pub(crate) fn capture(
+        ctx: &FunctionEnvMut<'_, WasiEnv>,
+        rewind: &RewindState,
+    ) -> Result<Self, SnapshotError> {
+        let env = ctx.data();
+        if env.process.read().threads.len() > 1 {
+            return Err(SnapshotError::MultipleThreads);
+        }
+        let module_hash = env
+            .capabilities
+            .snapshot
+            .module_hash
+            .ok_or(SnapshotError::UnknownModule)?;
+
+        let memory = env
+            .try_memory_view(ctx)
+            .ok_or(SnapshotError::NoMemory)?
+            .copy_to_vec()?;
+
+        let state = env.state();
+        let mut fds = state
+            .fs
+            .fd_map
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(fd, entry)| FdSnapshot::capture(&state.fs, *fd, entry))
+            .collect::<Result<Vec<_>, _>>()?;
+        fds.sort_by_key(|fd| fd.fd);
+
+        let clock_offset = state
+            .clock_offset
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(clock_id, offset)| (*clock_id as u32, *offset))
+            .collect();
+
+        Ok(ProcessSnapshot {
+            module_hash: module_hash.as_bytes(),
+            args: state.args.clone(),
+            envs: state.envs.clone(),
+            current_dir: state.fs.current_dir.lock().unwrap().clone(),
+            clock_offset,
+            next_fd: state.fs.next_fd.load(Ordering::SeqCst),
+            fds,
+            memory,
+            memory_stack: rewind.memory_stack.to_vec(),
+            rewind_stack: rewind.rewind_stack.to_vec(),
+            store_data: rewind.store_data.to_vec(),
+            is_64bit: rewind.is_64bit,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, SnapshotError> {
+        Ok(bincode::serialize(self)?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SnapshotError> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+
+    /// Write the snapshot to a file at `path`.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SnapshotError> {
+        let file = BufWriter::new(File::create(path)?);
+        bincode::serialize_into(file, self)?;
+        Ok(())
+    }
+
+    /// Read a snapshot from the file at `path`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SnapshotError> {
+        let file = BufReader::new(File::open(path)?);
+        Ok(bincode::deserialize_from(file)?)
+    }
+
+    /// Checks that the snapshot was taken from the module with `module_hash`.
+    pub(crate) fn check_module(
+        &self,
+        module_hash: Option<ModuleHash>,
+    ) -> Result<(), SnapshotError> {
+        match module_hash {
+            Some(hash) if hash.as_bytes() == self.module_hash => Ok(()),
+            Some(_) => Err(SnapshotError::WrongModule),
+            None => Err(SnapshotError::UnknownModule),
+        }
+    }
+
+    /// The arguments the process was started with.
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    /// The environment variables of the process, as `key=value`.
+    pub fn envs(&self) -> &[Vec<u8>] {
+        &self.envs
+    }
+
+    pub(crate) fn clock_offset(&self) -> HashMap<Snapshot0Clockid, i64> {
+        self.clock_offset
+            .iter()
+            .filter_map(|(clock_id, offset)| {
+                Some((Snapshot0Clockid::try_from(*clock_id).ok()?, *offset))
+            })
+            .collect()
+    }
+
+    /// Reopen the file descriptors of the process in a freshly built file
+    /// system.
+    pub(crate) fn restore_fs(&self, inodes: &WasiInodes, fs: &WasiFs) -> Result<(), SnapshotError> {
+        for snapshot in &self.fds {
+            snapshot.restore(inodes, fs)?;
+        }
+
+        fs.next_fd.fetch_max(self.next_fd, Ordering::SeqCst);
+        fs.set_current_dir(&self.current_dir);
+        Ok(())
+    }
+
+    /// Put the linear memory of the process back into a new instance.
+    pub(crate) fn restore_memory(
+        &self,
+        env: &WasiFunctionEnv,
+        store: &mut Store,
+    ) -> Result<(), SnapshotError> {
+        let memory = env
+            .data(store)
+            .try_memory_clone()
+            .ok_or(SnapshotError::NoMemory)?;
+
+        let size = memory.view(store).data_size();
+        let wanted = self.memory.len() as u64;
+        if wanted > size {
+            let page_size = WASM_PAGE_SIZE as u64;
+            let pages = (wanted - size + page_size - 1) / page_size;
+            memory.grow(store, Pages(pages as u32))?;
+        }
+
+        memory.view(store).write(0, &self.memory)?;
+        Ok(())
+    }
+
+    /// The state that rewinds the restored instance back into
+    /// `proc_snapshot`, and what the call returns.
+    pub(crate) fn rewind_state(&self) -> (RewindState, Bytes) {
+        let rewind = RewindState {
+            memory_stack: Bytes::from(self.memory_stack.clone()),
+            rewind_stack: Bytes::from(self.rewind_stack.clone()),
+            store_data: Bytes::from(self.store_data.clone()),
+            is_64bit: self.is_64bit,
+        };
+        let result = SnapshotResult {
+            restored: true,
+            ret: Errno::Success,
+        };
+        (rewind, bincode::serialize(&result).unwrap().into())
+    }
+}
+
+impl FdSnapshot {
+    fn capture(fs: &WasiFs, fd: WasiFd, entry: &Fd) -> Result<Self, SnapshotError> {
+        let kind = if entry.is_stdio {
+            FdKindSnapshot::Special
+        } else if entry.inode.is_preopened {
+            FdKindSnapshot::Preopen {
+                name: entry.inode.name.to_string(),
+            }
+        } else {
+            let guard = entry.inode.read();
+            match guard.deref() {
+                Kind::File { fd: Some(_), .. } => FdKindSnapshot::Special,
+                Kind::File { path, .. } => FdKindSnapshot::File {
+                    path: path.clone(),
+                    contents: read_file(&fs.root_fs, path)?,
+                },
+                Kind::Dir { path, .. } => FdKindSnapshot::Dir { path: path.clone() },
+                _ => return Err(SnapshotError::UnsupportedFd(fd)),
+            }
+        };
+
+        Ok(FdSnapshot {
+            fd,
+            rights: entry.rights.bits(),
+            rights_inheriting: entry.rights_inheriting.bits(),
+            flags: entry.flags.bits(),
+            open_flags: entry.open_flags,
+            offset: entry.offset.load(Ordering::SeqCst),
+            kind,
+        })
+    }
+
+    fn restore(&self, inodes: &WasiInodes, fs: &WasiFs) -> Result<(), SnapshotError> {
+        let inode = match &self.kind {
+            FdKindSnapshot::Special => {
+                if self.fd > __WASI_STDERR_FILENO && fs.get_fd(self.fd).is_err() {
+                    tracing::warn!(fd = self.fd, "Special file is missing after restoring");
+                }
+                return Ok(());
+            }
+            FdKindSnapshot::Preopen { name } => {
+                return match fs.get_fd(self.fd) {
+                    Ok(entry) if entry.inode.is_preopened && entry.inode.name.as_ref() == name => {
+                        Ok(())
+                    }
+                    _ => Err(SnapshotError::MissingPreopen(name.clone())),
+                };
+            }
+            FdKindSnapshot::Dir { path } => {
+                create_dir_all(&fs.root_fs, path)?;
+                fs.get_inode_at_fs_path(inodes, path)?
+            }
+            FdKindSnapshot::File { path, contents } => {
+                let handle = restore_file(&fs.root_fs, path, contents, self.open_flags)?;
+                let kind = Kind::File {
+                    handle: Some(Arc::new(RwLock::new(handle))),
+                    path: path.clone(),
+                    fd: None,
+                };
+                fs.create_inode(inodes, kind, false, file_name(path))?
+            }
+        };
+
+        fs.create_fd_ext(
+            Rights::from_bits_truncate(self.rights),
+            Rights::from_bits_truncate(self.rights_inheriting),
+            Fdflags::from_bits_truncate(self.flags),
+            self.open_flags,
+            inode,
+            self.fd,
+        )?;
+        if let Ok(entry) = fs.get_fd(self.fd) {
+            entry.offset.store(self.offset, Ordering::SeqCst);
+        }
+        Ok(())
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn read_file(root: &WasiFsRoot, path: &Path) -> Result<Vec<u8>, SnapshotError> {
+    let mut file = root.new_open_options().read(true).open(path)?;
+    let mut contents = Vec::new();
+    InlineWaker::block_on(file.read_to_end(&mut contents))?;
+    Ok(contents)
+}
+
+/// Open a file again, bringing it back from the snapshot if it is gone.
+fn restore_file(
+    root: &WasiFsRoot,
+    path: &Path,
+    contents: &[u8],
+    open_flags: u16,
+) -> Result<Box<dyn VirtualFile + Send + Sync + 'static>, SnapshotError> {
+    if root.metadata(path).is_err() {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            create_dir_all(root, parent)?;
+        }
+        let mut file = root
+            .new_open_options()
+            .write(true)
+            .create(true)
+            .open(path)?;
+        InlineWaker::block_on(file.write_all(contents))?;
+    }
+
+    let file = root
+        .new_open_options()
+        .read(open_flags & Fd::READ != 0)
+        .write(open_flags & (Fd::WRITE | Fd::APPEND) != 0)
+        .append(open_flags & Fd::APPEND != 0)
+        .open(path)?;
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use virtual_fs::TmpFileSystem;
+    use wasmer::Module;
+
+    use super::*;
+    use crate::WasiEnvBuilder;
+
+    const MODULE: &str = r#"(module (memory (export "memory") 1) (func (export "_start")))"#;
+
+    fn builder(fs: TmpFileSystem, module_hash: ModuleHash) -> WasiEnvBuilder {
+        let mut builder = WasiEnvBuilder::new("program")
+            .arg("arg")
+            .fs(Box::new(fs))
+            .preopen_dir("/")
+            .unwrap();
+        builder.capabilities_mut().snapshot.module_hash = Some(module_hash);
+        builder
+    }
+
+    #[tokio::test]
+    async fn a_process_is_restored_from_its_snapshot() {
+        let module_hash = ModuleHash::sha256(MODULE);
+        let fs = TmpFileSystem::new();
+        create_dir_all(&fs, Path::new("/tmp/dir")).unwrap();
+        let mut file = fs
+            .new_open_options()
+            .write(true)
+            .create(true)
+            .open("/tmp/data.txt")
+            .unwrap();
+        InlineWaker::block_on(file.write_all(b"hello")).unwrap();
+
+        let mut store = Store::default();
+        let module = Module::new(&store, MODULE).unwrap();
+        let (_, env) = builder(fs, module_hash)
+            .instantiate(module, &mut store)
+            .unwrap();
+        let state = env.data(&store).state();
+        let dir = state
+            .fs
+            .get_inode_at_fs_path(&state.inodes, Path::new("/tmp/dir"))
+            .unwrap();
+        let dir_fd = state
+            .fs
+            .create_fd(
+                Rights::all(),
+                Rights::all(),
+                Fdflags::empty(),
+                Fd::READ,
+                dir,
+            )
+            .unwrap();
+        let file = state
+            .fs
+            .get_inode_at_fs_path(&state.inodes, Path::new("/tmp/data.txt"))
+            .unwrap();
+        let file_fd = state
+            .fs
+            .create_fd(
+                Rights::all(),
+                Rights::all(),
+                Fdflags::empty(),
+                Fd::READ,
+                file,
+            )
+            .unwrap();
+        state
+            .fs
+            .get_fd(file_fd)
+            .unwrap()
+            .offset
+            .store(2, Ordering::SeqCst);
+        env.data(&store)
+            .try_memory_view(&store)
+            .unwrap()
+            .write(0, b"memory")
+            .unwrap();
+
+        let rewind = RewindState {
+            memory_stack: Bytes::from_static(b"memory stack"),
+            rewind_stack: Bytes::from_static(b"rewind stack"),
+            store_data: Bytes::from_static(b"store data"),
+            is_64bit: false,
+        };
+        let ctx = env.env.clone().into_mut(&mut store);
+        let snapshot = ProcessSnapshot::capture(&ctx, &rewind).unwrap();
+        let snapshot = ProcessSnapshot::from_bytes(&snapshot.to_bytes().unwrap()).unwrap();
+
+        // The files of the process are gone from the new file system
+        let mut store = Store::default();
+        let module = Module::new(&store, MODULE).unwrap();
+        let (_, env) = builder(TmpFileSystem::new(), module_hash)
+            .restore_snapshot(snapshot.clone())
+            .instantiate(module, &mut store)
+            .unwrap();
+        snapshot.restore_memory(&env, &mut store).unwrap();
+
+        let data = env.data(&store);
+        assert_eq!(
+            &data.try_memory_view(&store).unwrap().copy_to_vec().unwrap()[..6],
+            b"memory"
+        );
+        let state = data.state();
+        assert_eq!(state.args, ["program", "arg"]);
+        let file = state.fs.get_fd(file_fd).unwrap();
+        assert_eq!(file.offset.load(Ordering::SeqCst), 2);
+        assert_eq!(
+            read_file(&state.fs.root_fs, Path::new("/tmp/data.txt")).unwrap(),
+            b"hello"
+        );
+        let dir = state.fs.get_fd(dir_fd).unwrap();
+        let parent = match dir.inode.read().deref() {
+            Kind::Dir { parent, .. } => parent.upgrade().unwrap(),
+            _ => panic!("not a directory"),
+        };
+        match parent.read().deref() {
+            Kind::Dir { path, .. } => assert_eq!(path, Path::new("/tmp")),
+            _ => panic!("the parent is not a directory"),
+        }
+        let (rewind_state, _) = snapshot.rewind_state();
+        assert_eq!(rewind_state.rewind_stack, rewind.rewind_stack);
+
+        // Snapshots only go back into the module they were taken from
+        let mut store = Store::default();
+        let module = Module::new(&store, MODULE).unwrap();
+        let result = builder(TmpFileSystem::new(), ModuleHash::sha256("another module"))
+            .restore_snapshot(snapshot)
+            .instantiate(module, &mut store);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn missing_files_are_brought_back() {
+        let root = WasiFsRoot::Sandbox(Arc::new(TmpFileSystem::new()));
+        let path = Path::new("/tmp/data.txt");
+
+        let mut file = restore_file(&root, path, b"hello", Fd::READ | Fd::WRITE).unwrap();
+        let mut contents = Vec::new();
+        InlineWaker::block_on(file.read_to_end(&mut contents)).unwrap();
+        assert_eq!(contents, b"hello");
+
+        // Files that still exist are left alone
+        restore_file(&root, path, b"goodbye", Fd::READ).unwrap();
+        assert_eq!(read_file(&root, path).unwrap(), b"hello");
+    }
+}
@@ -93,6 +93,7 @@ pub(crate) use self::types::{
 use self::{state::WasiInstanceGuardMemory, utils::WasiDummyWaker};
 pub(crate) use crate::os::task::{
     process::{WasiProcessId, WasiProcessWait},
+    signal::SignalDelivery,
     thread::{WasiThread, WasiThreadId},
 };
 pub(crate) use crate::{
@@ -323,10 +324,13 @@ where
                 return Poll::Ready(Ok(res));
             }
             if let Some(signals) = self.ctx.data().thread.pop_signals_or_subscribe(cx.waker()) {
-                if let Err(err) = WasiEnv::process_signals_internal(self.ctx, signals) {
-                    return Poll::Ready(Err(err));
+                match WasiEnv::process_signals_internal(self.ctx, signals) {
+                    Ok(true) => return Poll::Ready(Ok(Err(Errno::Intr))),
+                    // The signals were ignored or their handlers asked for the
+                    // syscall to be restarted, so it carries on waiting
+                    Ok(false) => cx.waker().wake_by_ref(),
+                    Err(err) => return Poll::Ready(Err(err)),
                 }
-                return Poll::Ready(Ok(Err(Errno::Intr)));
             }
             Poll::Pending
         }
@@ -335,6 +339,7 @@ where
     // Block on the work
     let mut pinned_work = Box::pin(work);
     let tasks = env.tasks().clone();
+    let _blocking = env.process.start_blocking();
     let poller = Poller { ctx, pinned_work };
     block_on_with_timeout(&tasks, timeout, poller)
 }
@@ -344,12 +349,25 @@ where
 ///  or in the instant response)
 pub type AsyncifyFuture = dyn Future<Output = Bytes> + Send + Sync + 'static;
 
+/// What a syscall that is blocked in [`__asyncify_with_deep_sleep`] does
+/// when a signal arrives
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum OnSignal {
+    /// The signal is delivered, and the syscall is interrupted unless the
+    /// handler asked for syscalls to be restarted
+    Interrupt,
+    /// The signal is delivered and the syscall carries on waiting
+    Restart,
+    /// The signal is left pending, for the module that replaced this one
+    Defer,
+}
+
 // This poller will process any signals when the main working function is idle
 struct AsyncifyPoller<'a, 'b, 'c, T, Fut>
 where
     Fut: Future<Output = T> + Send + Sync + 'static,
 {
-    process_signals: bool,
+    on_signal: OnSignal,
     ctx: &'b mut FunctionEnvMut<'c, WasiEnv>,
     work: &'a mut Pin<Box<Fut>>,
 }
@@ -357,11 +375,12 @@ impl<'a, 'b, 'c, T, Fut> Future for AsyncifyPoller<'a, 'b, 'c, T, Fut>
 where
     Fut: Future<Output = T> + Send + Sync + 'static,
 {
-    type Output = Result<T, WasiError>;
+    /// The result of the work, or [`None`] if a signal interrupted it
+    type Output = Result<Option<T>, WasiError>;
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         if let Poll::Ready(res) = self.work.as_mut().poll(cx) {
-            return Poll::Ready(Ok(res));
+            return Poll::Ready(Ok(Some(res)));
         }
 
         let env = self.ctx.data();
@@ -371,17 +390,16 @@ where
                 Errno::Child.into()
             }))));
         }
-        if self.process_signals && env.thread.has_signals_or_subscribe(cx.waker()) {
-            let signals = env.thread.signals().lock().unwrap();
-            for sig in signals.0.iter() {
-                if *sig == Signal::Sigint
-                    || *sig == Signal::Sigquit
-                    || *sig == Signal::Sigkill
-                    || *sig == Signal::Sigabrt
-                {
-                    let exit_code = env.thread.set_or_get_exit_code_for_signal(*sig);
-                    return Poll::Ready(Err(WasiError::Exit(exit_code)));
+        if self.on_signal == OnSignal::Defer {
+            return Poll::Pending;
+        }
+        if let Some(signals) = env.thread.pop_signals_or_subscribe(cx.waker()) {
+            match WasiEnv::process_signals_internal(self.ctx, signals) {
+                Ok(true) if self.on_signal == OnSignal::Interrupt => {
+                    return Poll::Ready(Ok(None));
                 }
+                Ok(_) => cx.waker().wake_by_ref(),
+                Err(err) => return Poll::Ready(Err(err)),
             }
         }
         Poll::Pending
@@ -392,6 +410,9 @@ pub enum AsyncifyAction<'a, R> {
     /// Indicates that asyncify callback finished and the
     /// caller now has ownership of the ctx again
     Finish(FunctionEnvMut<'a, WasiEnv>, R),
+    /// Indicates that a signal handler interrupted the work, so the
+    /// syscall fails with `EINTR`
+    Interrupted(FunctionEnvMut<'a, WasiEnv>),
     /// Indicates that asyncify should unwind by immediately exiting
     /// the current function
     Unwind,
@@ -409,21 +430,16 @@ pub enum AsyncifyAction<'a, R> {
 pub(crate) fn __asyncify_with_deep_sleep<M: MemorySize, T, Fut>(
     mut ctx: FunctionEnvMut<'_, WasiEnv>,
     deep_sleep_time: Duration,
+    on_signal: OnSignal,
     work: Fut,
 ) -> Result<AsyncifyAction<'_, T>, WasiError>
 where
     T: serde::Serialize + serde::de::DeserializeOwned,
     Fut: Future<Output = T> + Send + Sync + 'static,
 {
-    // Determine if we should process signals or now
-    let process_signals = ctx
-        .data()
-        .try_inner()
-        .map(|i| !i.signal_set)
-        .unwrap_or(true);
-
     // Box up the trigger
     let mut trigger = Box::pin(work);
+    let _blocking = ctx.data().process.start_blocking();
 
     // Define the work
     let tasks = ctx.data().tasks().clone();
@@ -447,12 +463,14 @@ where
         Ok(tokio::select! {
             // Inner wait with finializer
             res = AsyncifyPoller {
-                process_signals,
+                on_signal,
                 ctx: &mut ctx,
                 work: &mut trigger,
             } => {
-                let result = res?;
-                AsyncifyAction::Finish(ctx, result)
+                match res? {
+                    Some(result) => AsyncifyAction::Finish(ctx, result),
+                    None => AsyncifyAction::Interrupted(ctx),
+                }
             },
             // Determines when and if we should go into a deep sleep
             _ = deep_sleep_wait => {
@@ -515,6 +533,7 @@ where
 
     // Block until the work is finished or until we
     // unload the thread using asyncify
+    let _blocking = env.process.start_blocking();
     Ok(InlineWaker::block_on(work))
 }
 
@@ -789,11 +808,134 @@ pub(crate) fn write_buffer_array<M: MemorySize>(
     Errno::Success
 }
 
-pub(crate) fn get_current_time_in_nanos() -> Result<Timestamp, Errno> {
-    let now = platform_clock_time_get(Snapshot0Clockid::Monotonic, 1_000_000).unwrap() as u128;
+/// Reads one of the guest visible clocks, using the virtual clock instead of
+/// the host one when the runtime is deterministic.
+pub(crate) fn guest_clock_time_get(
+    env: &WasiEnv,
+    clock_id: Snapshot0Clockid,
+    precision: Timestamp,
+) -> Result<i64, Errno> {
+    let read = || match env.runtime().determinism() {
+        Some(determinism) => Ok(determinism.clock_time_get(clock_id) as i64),
+        None => platform_clock_time_get(clock_id, precision),
+    };
+    match env.runtime().journal() {
+        Some(journal) => journal.clock_time_get(clock_id, read),
+        None => read(),
+    }
+}
+
+/// Gets the metadata of `fd`, or of `path` relative to it, through the
+/// runtime's journal.
+pub(crate) fn journal_filestat(
+    env: &WasiEnv,
+    fd: WasiFd,
+    path: Option<&str>,
+    stat: impl FnOnce() -> Result<Filestat, Errno>,
+) -> Result<Filestat, Errno> {
+    match env.runtime().journal() {
+        Some(journal) => journal.filestat(env.pid().raw(), fd, path, stat),
+        None => stat(),
+    }
+}
+
+/// Runs a read into `iovs` through the runtime's journal, recording the bytes
+/// it produced or, when replaying, copying the recorded bytes into `iovs`
+/// instead of reading from `fd`.
+///
+/// A replayed read moves the cursor of files forward like `read` would have
+/// when `update_cursor` is set.
+pub(crate) fn journal_read<M: MemorySize>(
+    ctx: &mut FunctionEnvMut<'_, WasiEnv>,
+    fd: WasiFd,
+    iovs: WasmPtr<__wasi_iovec_t<M>, M>,
+    iovs_len: M::Offset,
+    update_cursor: bool,
+    read: impl FnOnce(&mut FunctionEnvMut<'_, WasiEnv>) -> Result<Result<usize, Errno>, WasiError>,
+) -> Result<Result<usize, Errno>, WasiError> {
+    let journal = match ctx.data().runtime().journal() {
+        Some(journal) => journal.clone(),
+        None => return read(ctx),
+    };
+
+    let pid = ctx.data().pid().raw();
+    if let Some(result) = journal.replay_read(pid, fd) {
+        let env = ctx.data();
+        let memory = unsafe { env.memory_view(&ctx) };
+        let iovs_arr = wasi_try_mem_ok_ok!(iovs.slice(&memory, iovs_len));
+        let bytes_read =
+            wasi_try_ok_ok!(result.and_then(|data| copy_from_slice(&data, &memory, iovs_arr)));
+        if update_cursor {
+            let fd_entry = wasi_try_ok_ok!(env.state.fs.get_fd(fd));
+            let seekable = matches!(
+                fd_entry.inode.read().deref(),
+                Kind::File { .. } | Kind::Buffer { .. }
+            );
+            if !fd_entry.is_stdio && seekable {
+                fd_entry
+                    .offset
+                    .fetch_add(bytes_read as u64, Ordering::AcqRel);
+            }
+        }
+        return Ok(Ok(bytes_read));
+    }
+
+    let result = read(ctx)?;
+
+    let env = ctx.data();
+    let memory = unsafe { env.memory_view(&ctx) };
+    let recorded = match result {
+        Ok(bytes_read) => {
+            let iovs_arr = wasi_try_mem_ok_ok!(iovs.slice(&memory, iovs_len));
+            Ok(wasi_try_ok_ok!(gather_bytes(&memory, iovs_arr, bytes_read)))
+        }
+        Err(err) => Err(err),
+    };
+    journal.record_read(pid, fd, recorded);
+
+    Ok(result)
+}
+
+/// Reads the first `len` bytes that were stored in `iovs_arr`.
+pub(crate) fn gather_bytes<M: MemorySize>(
+    memory: &MemoryView,
+    iovs_arr: WasmSlice<__wasi_iovec_t<M>>,
+    len: usize,
+) -> Result<Vec<u8>, Errno> {
+    let mut data = Vec::with_capacity(len);
+    for iov in iovs_arr.iter() {
+        if data.len() >= len {
+            break;
+        }
+        let iov = iov.read().map_err(mem_error_to_wasi)?;
+        let buf_len = from_offset::<M>(iov.buf_len)?.min(len - data.len());
+        let bytes = WasmPtr::<u8, M>::new(iov.buf)
+            .slice(memory, to_offset::<M>(buf_len)?)
+            .map_err(mem_error_to_wasi)?
+            .read_to_vec()
+            .map_err(mem_error_to_wasi)?;
+        data.extend_from_slice(&bytes);
+    }
+    Ok(data)
+}
+
+pub(crate) fn get_current_time_in_nanos(env: &WasiEnv) -> Result<Timestamp, Errno> {
+    let now = guest_clock_time_get(env, Snapshot0Clockid::Monotonic, 1_000_000)? as u128;
     Ok(now as Timestamp)
 }
 
+/// Fails with `EFBIG` (and raises `SIGXFSZ`) if a file would grow past the
+/// file size limit of the process
+pub(crate) fn check_file_size_limit(env: &WasiEnv, size: Filesize) -> Result<(), Errno> {
+    match env.process.limits().file_size.get() {
+        Some(max) if size > max => {
+            env.thread.signal(Signal::Sigxfsz);
+            Err(Errno::Fbig)
+        }
+        _ => Ok(()),
+    }
+}
+
 pub(crate) fn get_stack_lower(env: &WasiEnv) -> u64 {
     env.layout.stack_lower
 }
@@ -1252,6 +1394,13 @@ pub(crate) fn _prepare_wasi(wasi_env: &mut WasiEnv, args: Option<Vec<String>>) {
         wasi_env.state = Arc::new(wasi_state);
     }
 
+    // The signal handlers belong to the old program so they go back to the
+    // default, while signals that were ignored stay ignored
+    wasi_env.process.reset_signal_handlers();
+
+    // The memory of the old program goes away along with its mappings
+    wasi_env.process.memory_maps().clear();
+
     // Close any files after the STDERR that are not preopened
     let close_fds = {
         let preopen_fds = {
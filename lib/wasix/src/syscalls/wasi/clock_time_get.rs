@@ -21,7 +21,7 @@ pub fn clock_time_get<M: MemorySize>(
     let env = ctx.data();
     let memory = unsafe { env.memory_view(&ctx) };
 
-    let mut t_out = wasi_try!(platform_clock_time_get(clock_id, precision));
+    let mut t_out = wasi_try!(guest_clock_time_get(env, clock_id, precision));
     {
         let guard = env.state.clock_offset.lock().unwrap();
         if let Some(offset) = guard.get(&clock_id) {
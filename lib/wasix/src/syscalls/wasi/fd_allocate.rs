@@ -26,6 +26,7 @@ pub fn fd_allocate(
         return Errno::Access;
     }
     let new_size = wasi_try!(offset.checked_add(len).ok_or(Errno::Inval));
+    wasi_try!(check_file_size_limit(env, new_size));
     {
         let mut guard = inode.write();
         match guard.deref_mut() {
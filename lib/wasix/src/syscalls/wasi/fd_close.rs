@@ -18,7 +18,11 @@ pub fn fd_close(mut ctx: FunctionEnvMut<'_, WasiEnv>, fd: WasiFd) -> Result<Errn
 
     let env = ctx.data();
     let (_, mut state) = unsafe { env.get_memory_and_wasi_state(&ctx, 0) };
+    let lock_target = lock_target(env, fd).ok();
     wasi_try_ok!(state.fs.close_fd(fd));
+    if let Some(lock_target) = lock_target {
+        release_fd_locks(env, lock_target);
+    }
 
     Ok(Errno::Success)
 }
@@ -45,7 +45,7 @@ pub(crate) fn fd_filestat_get_internal(
         return Err(Errno::Access);
     }
 
-    state.fs.filestat_fd(fd)
+    journal_filestat(env, fd, None, || state.fs.filestat_fd(fd))
 }
 
 /// ### `fd_filestat_get_old()`
@@ -22,6 +22,7 @@ pub fn fd_filestat_set_size(
     if !fd_entry.rights.contains(Rights::FD_FILESTAT_SET_SIZE) {
         return Errno::Access;
     }
+    wasi_try!(check_file_size_limit(env, st_size));
 
     {
         let mut guard = inode.write();
@@ -38,7 +38,7 @@ pub fn fd_filestat_set_times(
         let time_to_set = if fst_flags.contains(Fstflags::SET_ATIM) {
             st_atim
         } else {
-            wasi_try!(get_current_time_in_nanos())
+            wasi_try!(get_current_time_in_nanos(env))
         };
         inode.stat.write().unwrap().st_atim = time_to_set;
     }
@@ -47,7 +47,7 @@ pub fn fd_filestat_set_times(
         let time_to_set = if fst_flags.contains(Fstflags::SET_MTIM) {
             st_mtim
         } else {
-            wasi_try!(get_current_time_in_nanos())
+            wasi_try!(get_current_time_in_nanos(env))
         };
         inode.stat.write().unwrap().st_mtim = time_to_set;
     }
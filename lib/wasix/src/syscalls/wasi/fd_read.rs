@@ -38,7 +38,9 @@ pub fn fd_read<M: MemorySize>(
         fd_entry.offset.load(Ordering::Acquire) as usize
     };
 
-    let res = fd_read_internal::<M>(&mut ctx, fd, iovs, iovs_len, offset, nread, true)?;
+    let res = journal_read::<M>(&mut ctx, fd, iovs, iovs_len, true, |ctx| {
+        fd_read_internal::<M>(ctx, fd, iovs, iovs_len, offset, nread, true)
+    })?;
     fd_read_internal_handler(ctx, res, nread)
 }
 
@@ -69,7 +71,9 @@ pub fn fd_pread<M: MemorySize>(
     let pid = ctx.data().pid();
     let tid = ctx.data().tid();
 
-    let res = fd_read_internal::<M>(&mut ctx, fd, iovs, iovs_len, offset as usize, nread, false)?;
+    let res = journal_read::<M>(&mut ctx, fd, iovs, iovs_len, false, |ctx| {
+        fd_read_internal::<M>(ctx, fd, iovs, iovs_len, offset as usize, nread, false)
+    })?;
     fd_read_internal_handler::<M>(ctx, res, nread)
 }
 
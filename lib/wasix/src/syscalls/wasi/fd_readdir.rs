@@ -24,6 +24,49 @@ pub fn fd_readdir<M: MemorySize>(
     buf_len: M::Offset,
     cookie: Dircookie,
     bufused: WasmPtr<M::Offset, M>,
+) -> Errno {
+    let journal = match ctx.data().runtime().journal() {
+        Some(journal) => journal.clone(),
+        None => return fd_readdir_internal(&ctx, fd, buf, buf_len, cookie, bufused),
+    };
+
+    let env = ctx.data();
+    let pid = env.pid().raw();
+    if let Some(result) = journal.replay_readdir(pid, fd) {
+        let memory = unsafe { env.memory_view(&ctx) };
+        let data = wasi_try!(result);
+        let used: M::Offset = wasi_try!(data.len().try_into().map_err(|_| Errno::Overflow));
+        if used > buf_len {
+            tracing::warn!("The directory listing in the journal doesn't fit in the buffer");
+            return Errno::Inval;
+        }
+        wasi_try_mem!(wasi_try_mem!(buf.slice(&memory, used)).write_slice(&data));
+        wasi_try_mem!(bufused.write(&memory, used));
+        return Errno::Success;
+    }
+
+    let ret = fd_readdir_internal(&ctx, fd, buf, buf_len, cookie, bufused);
+    let recorded = match ret {
+        Errno::Success => {
+            let memory = unsafe { env.memory_view(&ctx) };
+            let used = wasi_try_mem!(bufused.read(&memory));
+            Ok(wasi_try_mem!(
+                wasi_try_mem!(buf.slice(&memory, used)).read_to_vec()
+            ))
+        }
+        err => Err(err),
+    };
+    journal.record_readdir(pid, fd, recorded);
+    ret
+}
+
+fn fd_readdir_internal<M: MemorySize>(
+    ctx: &FunctionEnvMut<'_, WasiEnv>,
+    fd: WasiFd,
+    buf: WasmPtr<u8, M>,
+    buf_len: M::Offset,
+    cookie: Dircookie,
+    bufused: WasmPtr<M::Offset, M>,
 ) -> Errno {
     let env = ctx.data();
     let (memory, mut state) = unsafe { env.get_memory_and_wasi_state(&ctx, 0) };
@@ -15,6 +15,7 @@ pub fn fd_renumber(ctx: FunctionEnvMut<'_, WasiEnv>, from: WasiFd, to: WasiFd) -
     }
     let env = ctx.data();
     let (_, mut state) = unsafe { env.get_memory_and_wasi_state(&ctx, 0) };
+    let lock_target = lock_target(env, to).ok();
 
     let mut fd_map = state.fs.fd_map.write().unwrap();
     let fd_entry = wasi_try!(fd_map.get_mut(&from).ok_or(Errno::Badf));
@@ -26,7 +27,14 @@ pub fn fd_renumber(ctx: FunctionEnvMut<'_, WasiEnv>, from: WasiFd, to: WasiFd) -
         inode: fd_entry.inode.clone(),
         ..*fd_entry
     };
-    fd_map.insert(to, new_fd_entry);
+    let replaced = fd_map.insert(to, new_fd_entry);
+    drop(fd_map);
+
+    // Replacing `to` closes it, which releases its locks
+    if let (Some(replaced), Some(lock_target)) = (replaced, lock_target) {
+        drop(replaced);
+        release_fd_locks(env, lock_target);
+    }
 
     Errno::Success
 }
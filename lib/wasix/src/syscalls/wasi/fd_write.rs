@@ -114,6 +114,15 @@ pub(crate) fn fd_write_internal<M: MemorySize>(
                         let handle = handle.clone();
                         drop(guard);
 
+                        // Writes stop at the file size limit of the process
+                        let max_size = match env.process.limits().file_size.get() {
+                            Some(max) if !is_stdio => {
+                                wasi_try_ok!(check_file_size_limit(env, offset as u64 + 1));
+                                max
+                            }
+                            _ => u64::MAX,
+                        };
+
                         let res = __asyncify_light(
                             env,
                             if fd_entry.flags.contains(Fdflags::NONBLOCK) {
@@ -137,7 +146,13 @@ pub(crate) fn fd_write_internal<M: MemorySize>(
                                         .map_err(mem_error_to_wasi)?
                                         .access()
                                         .map_err(mem_error_to_wasi)?;
-                                    let local_written = match handle.write(buf.as_ref()).await {
+                                    let room = max_size - offset as u64 - written as u64;
+                                    if room == 0 {
+                                        break;
+                                    }
+                                    let buf = &buf.as_ref()
+                                        [..buf.len().min(room.min(usize::MAX as u64) as usize)];
+                                    let local_written = match handle.write(buf).await {
                                         Ok(s) => s,
                                         Err(_) if written > 0 => break,
                                         Err(err) => return Err(map_io_err(err)),
@@ -158,6 +173,18 @@ pub(crate) fn fd_write_internal<M: MemorySize>(
                             a => a,
                         }));
 
+                        // Shared mappings of the file see what was written straight away
+                        if written > 0 && !is_stdio {
+                            wasi_try_ok!(update_memory_maps::<M>(
+                                env,
+                                &memory,
+                                fd_entry.inode.ino(),
+                                offset as u64,
+                                iovs_arr.as_ref(),
+                                written
+                            ));
+                        }
+
                         (written, true)
                     } else {
                         return Ok(Errno::Inval);
@@ -36,14 +36,9 @@ pub fn path_filestat_get<M: MemorySize>(
     }
     tracing::trace!(path = path_string.as_str());
 
-    let stat = wasi_try!(path_filestat_get_internal(
-        &memory,
-        state,
-        inodes,
-        fd,
-        flags,
-        &path_string
-    ));
+    let stat = wasi_try!(journal_filestat(env, fd, Some(&path_string), || {
+        path_filestat_get_internal(&memory, state, inodes, fd, flags, &path_string)
+    }));
 
     wasi_try_mem!(buf.deref(&memory).write(stat));
 
@@ -116,14 +111,9 @@ pub fn path_filestat_get_old<M: MemorySize>(
     }
     tracing::trace!(path = path_string.as_str());
 
-    let stat = wasi_try!(path_filestat_get_internal(
-        &memory,
-        state,
-        inodes,
-        fd,
-        flags,
-        &path_string
-    ));
+    let stat = wasi_try!(journal_filestat(env, fd, Some(&path_string), || {
+        path_filestat_get_internal(&memory, state, inodes, fd, flags, &path_string)
+    }));
 
     let old_stat = Snapshot0Filestat {
         st_dev: stat.st_dev,
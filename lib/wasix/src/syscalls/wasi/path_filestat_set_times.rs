@@ -68,7 +68,7 @@ pub fn path_filestat_set_times<M: MemorySize>(
         let time_to_set = if fst_flags.contains(Fstflags::SET_ATIM) {
             st_atim
         } else {
-            wasi_try!(get_current_time_in_nanos())
+            wasi_try!(get_current_time_in_nanos(env))
         };
         fd_inode.stat.write().unwrap().st_atim = time_to_set;
     }
@@ -76,7 +76,7 @@ pub fn path_filestat_set_times<M: MemorySize>(
         let time_to_set = if fst_flags.contains(Fstflags::SET_MTIM) {
             st_mtim
         } else {
-            wasi_try!(get_current_time_in_nanos())
+            wasi_try!(get_current_time_in_nanos(env))
         };
         fd_inode.stat.write().unwrap().st_mtim = time_to_set;
     }
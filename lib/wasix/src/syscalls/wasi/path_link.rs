@@ -57,23 +57,39 @@ pub fn path_link<M: MemorySize>(
         &old_path_str,
         old_flags & __WASI_LOOKUP_SYMLINK_FOLLOW != 0,
     ));
+    let source_path = {
+        let guard = source_inode.read();
+        match guard.deref() {
+            Kind::File { path, .. } => path.clone(),
+            // the link itself gets another name
+            Kind::Symlink { .. } => {
+                drop(guard);
+                wasi_try!(state
+                    .fs
+                    .get_entry_path_at_path(inodes, old_fd, Path::new(&old_path_str)))
+            }
+            Kind::Dir { .. } | Kind::Root { .. } => return Errno::Perm,
+            Kind::Buffer { .. }
+            | Kind::Socket { .. }
+            | Kind::Pipe { .. }
+            | Kind::EventNotifications { .. }
+            | Kind::Epoll { .. } => return Errno::Notsup,
+        }
+    };
     let target_path_arg = std::path::PathBuf::from(&new_path_str);
     let (target_parent_inode, new_entry_name) =
         wasi_try!(state
             .fs
             .get_parent_inode_at_path(inodes, new_fd, &target_path_arg, false));
 
-    if source_inode.stat.write().unwrap().st_nlink == Linkcount::max_value() {
-        return Errno::Mlink;
-    }
-    {
-        let mut guard = target_parent_inode.write();
-        match guard.deref_mut() {
-            Kind::Dir { entries, .. } => {
+    let link_path = {
+        let guard = target_parent_inode.read();
+        match guard.deref() {
+            Kind::Dir { entries, path, .. } => {
                 if entries.contains_key(&new_entry_name) {
                     return Errno::Exist;
                 }
-                entries.insert(new_entry_name, source_inode.clone());
+                path.join(&new_entry_name)
             }
             Kind::Root { .. } => return Errno::Inval,
             Kind::File { .. }
@@ -84,8 +100,15 @@ pub fn path_link<M: MemorySize>(
             | Kind::EventNotifications { .. }
             | Kind::Epoll { .. } => return Errno::Notdir,
         }
-    }
-    source_inode.stat.write().unwrap().st_nlink += 1;
+    };
+
+    // The new name is loaded from the file system the next time it is
+    // looked up, so unlinking either name only removes that name
+    wasi_try!(state
+        .fs
+        .root_fs
+        .hard_link(&source_path, &link_path)
+        .map_err(fs_error_into_wasi_err));
 
     Errno::Success
 }
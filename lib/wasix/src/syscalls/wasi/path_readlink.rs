@@ -45,30 +45,30 @@ pub fn path_readlink<M: MemorySize>(
         );
     }
 
-    let inode = wasi_try!(state.fs.get_inode_at_path(inodes, dir_fd, &path_str, false));
+    let link_path =
+        wasi_try!(state
+            .fs
+            .get_entry_path_at_path(inodes, dir_fd, std::path::Path::new(&path_str)));
+    let link_value = wasi_try!(state
+        .fs
+        .root_fs
+        .read_link(&link_path)
+        .map_err(fs_error_into_wasi_err));
 
-    {
-        let guard = inode.read();
-        if let Kind::Symlink { relative_path, .. } = guard.deref() {
-            let rel_path_str = relative_path.to_string_lossy();
-            let buf_len: u64 = buf_len.into();
-            let bytes = rel_path_str.bytes();
-            if bytes.len() as u64 >= buf_len {
-                return Errno::Overflow;
-            }
-            let bytes: Vec<_> = bytes.collect();
+    let rel_path_str = link_value.to_string_lossy();
+    let buf_len: u64 = buf_len.into();
+    let bytes = rel_path_str.bytes();
+    if bytes.len() as u64 >= buf_len {
+        return Errno::Overflow;
+    }
+    let bytes: Vec<_> = bytes.collect();
 
-            let out = wasi_try_mem!(buf.slice(&memory, wasi_try!(to_offset::<M>(bytes.len()))));
-            wasi_try_mem!(out.write_slice(&bytes));
-            // should we null terminate this?
+    let out = wasi_try_mem!(buf.slice(&memory, wasi_try!(to_offset::<M>(bytes.len()))));
+    wasi_try_mem!(out.write_slice(&bytes));
+    // should we null terminate this?
 
-            let bytes_len: M::Offset =
-                wasi_try!(bytes.len().try_into().map_err(|_| Errno::Overflow));
-            wasi_try_mem!(buf_used.deref(&memory).write(bytes_len));
-        } else {
-            return Errno::Inval;
-        }
-    }
+    let bytes_len: M::Offset = wasi_try!(bytes.len().try_into().map_err(|_| Errno::Overflow));
+    wasi_try_mem!(buf_used.deref(&memory).write(bytes_len));
 
     Errno::Success
 }
@@ -1,5 +1,5 @@
 use super::*;
-use crate::syscalls::*;
+use crate::{net::unix::SocketFile, syscalls::*};
 
 /// ### `path_unlink_file()`
 /// Unlink a file, deleting if the number of hardlinks is 1
@@ -33,6 +33,19 @@ pub fn path_unlink_file<M: MemorySize>(
     }
 
     let inode = wasi_try_ok!(state.fs.get_inode_at_path(inodes, fd, &path_str, false));
+
+    // Symlinks are not kept in the entries of their directory, they only
+    // live in the file system
+    if matches!(inode.read().deref(), Kind::Symlink { .. }) {
+        let link_path = wasi_try_ok!(state.fs.get_entry_path_at_path(
+            inodes,
+            fd,
+            std::path::Path::new(&path_str)
+        ));
+        wasi_try_ok!(state.fs_remove_file(link_path));
+        return Ok(Errno::Success);
+    }
+
     let (parent_inode, childs_name) = wasi_try_ok!(state.fs.get_parent_inode_at_path(
         inodes,
         fd,
@@ -69,6 +82,21 @@ pub fn path_unlink_file<M: MemorySize>(
             let mut guard = removed_inode.read();
             match guard.deref() {
                 Kind::File { handle, path, .. } => {
+                    // A Unix socket bound to the file can't be reached anymore
+                    let socket_file = state
+                        .fs
+                        .root_fs
+                        .metadata(path)
+                        .ok()
+                        .and_then(|metadata| SocketFile::of(&metadata));
+                    if let Some(socket_file) = socket_file {
+                        env.process
+                            .compute
+                            .must_upgrade()
+                            .unix_sockets()
+                            .unbind(socket_file);
+                    }
+
                     if let Some(h) = handle {
                         let mut h = h.write().unwrap();
                         let state = state;
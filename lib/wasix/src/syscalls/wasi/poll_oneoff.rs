@@ -311,6 +311,23 @@ where
         };
     }
 
+    // The events are journaled as they are handed to the guest, a replay
+    // hands them over without polling the host
+    let journal = ctx.data().runtime().journal().cloned();
+    if let Some(events) = journal
+        .as_ref()
+        .and_then(|journal| journal.replay_poll(pid.raw(), tid.raw()))
+    {
+        Span::current().record("seen", events.len());
+        return Ok(process_events(&ctx, events));
+    }
+    let process_events = move |ctx: &FunctionEnvMut<'a, WasiEnv>, events: Vec<Event>| {
+        if let Some(journal) = &journal {
+            journal.record_poll(pid.raw(), tid.raw(), &events);
+        }
+        process_events(ctx, events)
+    };
+
     let mut events_seen: u32 = 0;
 
     let batch = {
@@ -436,11 +453,17 @@ where
     let res = __asyncify_with_deep_sleep::<M, Result<Vec<EventResult>, Errno>, _>(
         ctx,
         Duration::from_millis(50),
+        OnSignal::Interrupt,
         Box::pin(trigger),
     )?;
-    if let AsyncifyAction::Finish(mut ctx, events) = res {
-        let events = events.map(|events| events.into_iter().map(EventResult::into_event).collect());
-        process_events(&ctx, events);
+    match res {
+        AsyncifyAction::Finish(mut ctx, events) => {
+            let events =
+                events.map(|events| events.into_iter().map(EventResult::into_event).collect());
+            process_events(&ctx, events);
+        }
+        AsyncifyAction::Interrupted(_) => return Ok(Errno::Intr),
+        AsyncifyAction::Unwind => {}
     }
     Ok(Errno::Success)
 }
@@ -1,5 +1,7 @@
+use wasmer_wasix_types::wasi::SignalCode;
+
 use super::*;
-use crate::syscalls::*;
+use crate::{os::task::signal::new_siginfo, syscalls::*};
 
 /// ### `proc_raise()`
 /// Send a signal to the process of the calling thread.
@@ -9,8 +11,10 @@ use crate::syscalls::*;
 ///   Signal to be raised for this process
 #[instrument(level = "debug", skip_all, fields(sig), ret, err)]
 pub fn proc_raise(mut ctx: FunctionEnvMut<'_, WasiEnv>, sig: Signal) -> Result<Errno, WasiError> {
+    // Like `raise()` the signal goes to the calling thread
     let env = ctx.data();
-    env.process.signal_process(sig);
+    let info = new_siginfo(sig, SignalCode::User, Some(env.pid()));
+    env.process.signal_thread_with_info(&env.tid(), sig, info);
 
     wasi_try_ok!(WasiEnv::process_signals_and_exit(&mut ctx)?);
 
@@ -18,13 +18,23 @@ pub fn random_get<M: MemorySize>(
     let memory = unsafe { env.memory_view(&ctx) };
     let buf_len64: u64 = buf_len.into();
     let mut u8_buffer = vec![0; buf_len64 as usize];
-    let res = getrandom::getrandom(&mut u8_buffer);
+    let fill = |buf: &mut [u8]| match env.runtime().determinism() {
+        Some(determinism) => {
+            determinism.fill_bytes(buf);
+            Ok(())
+        }
+        None => getrandom::getrandom(buf).map_err(|_| Errno::Io),
+    };
+    let res = match env.runtime().journal() {
+        Some(journal) => journal.random_get(&mut u8_buffer[..], fill),
+        None => fill(&mut u8_buffer[..]),
+    };
     match res {
         Ok(()) => {
             let buf = wasi_try_mem!(buf.slice(&memory, buf_len));
             wasi_try_mem!(buf.write_slice(&u8_buffer));
             Errno::Success
         }
-        Err(_) => Errno::Io,
+        Err(err) => err,
     }
 }
@@ -202,11 +202,12 @@ pub fn epoll_wait<'a, M: MemorySize + 'static>(
     let res = __asyncify_with_deep_sleep::<M, Result<Vec<(EpollFd, EpollType)>, Errno>, _>(
         ctx,
         Duration::from_millis(50),
+        OnSignal::Interrupt,
         Box::pin(trigger),
     )?;
-    if let AsyncifyAction::Finish(mut ctx, events) = res {
-        Ok(process_events(&ctx, events))
-    } else {
-        Ok(Errno::Success)
+    match res {
+        AsyncifyAction::Finish(mut ctx, events) => Ok(process_events(&ctx, events)),
+        AsyncifyAction::Interrupted(_) => Ok(Errno::Intr),
+        AsyncifyAction::Unwind => Ok(Errno::Success),
     }
 }
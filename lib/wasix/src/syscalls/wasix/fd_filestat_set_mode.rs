@@ -0,0 +1,40 @@
+use super::*;
+use crate::syscalls::*;
+
+/// ### `fd_filestat_set_mode()`
+/// Changes the permission bits of an open file or directory, like `fchmod()`
+/// does.
+///
+/// Changing the permissions requires the same right as changing the times.
+///
+/// ## Parameters
+///
+/// * `fd` - The file or directory to change
+/// * `mode` - The new permission bits, only the lower 12 bits (`0o7777`) are
+///   kept
+#[instrument(level = "debug", skip_all, fields(%fd, mode = format_args!("{:o}", mode)), ret)]
+pub fn fd_filestat_set_mode(ctx: FunctionEnvMut<'_, WasiEnv>, fd: WasiFd, mode: u32) -> Errno {
+    let env = ctx.data();
+    let (_, state, _) = unsafe { env.get_memory_and_wasi_state_and_inodes(&ctx, 0) };
+    let fd_entry = wasi_try!(state.fs.get_fd(fd));
+    if !fd_entry.rights.contains(Rights::FD_FILESTAT_SET_TIMES) {
+        return Errno::Access;
+    }
+
+    let path = {
+        let guard = fd_entry.inode.read();
+        match guard.deref() {
+            Kind::File { path, .. } | Kind::Dir { path, .. } => path.clone(),
+            Kind::Root { .. } => return Errno::Notcapable,
+            _ => return Errno::Inval,
+        }
+    };
+
+    wasi_try!(state
+        .fs
+        .root_fs
+        .set_permissions(&path, mode)
+        .map_err(fs_error_into_wasi_err));
+
+    Errno::Success
+}
@@ -0,0 +1,29 @@
+use wasmer_wasix_types::wasi::LockType;
+
+use super::*;
+use crate::{fs::LOCK_TO_EOF, syscalls::*};
+
+/// ### `fd_flock()`
+/// Takes or releases an advisory lock on a whole file, like `flock()` does.
+///
+/// The lock belongs to the open file description, which is shared by the
+/// descriptors duplicated from `fd` and those inherited by child processes.
+/// It is released once the last of them is closed.
+///
+/// ## Parameters
+///
+/// * `fd` - The file to lock
+/// * `lock_type` - The type of lock to take, or `unlock` to release it
+/// * `wait` - If true then wait for conflicting locks to be released, otherwise
+///   return `EAGAIN` straight away
+#[instrument(level = "debug", skip_all, fields(pid = ctx.data().process.pid().raw(), %fd, ?lock_type), ret, err)]
+pub fn fd_flock(
+    mut ctx: FunctionEnvMut<'_, WasiEnv>,
+    fd: WasiFd,
+    lock_type: LockType,
+    wait: Bool,
+) -> Result<Errno, WasiError> {
+    wasi_try_ok!(WasiEnv::process_signals_and_exit(&mut ctx)?);
+
+    fd_lock_internal(&mut ctx, fd, None, lock_type, 0, LOCK_TO_EOF, wait)
+}
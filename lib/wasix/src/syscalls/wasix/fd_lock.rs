@@ -0,0 +1,262 @@
+use std::sync::RwLock;
+
+use virtual_fs::FileLockKind;
+use wasmer_wasix_types::wasi::LockType;
+
+use super::*;
+use crate::{
+    fs::{LockConflict, LockKind, LockOwner, LockRecord, LockedFile, OpenFile, LOCK_TO_EOF},
+    syscalls::*,
+};
+
+type FileHandle = Arc<RwLock<Box<dyn VirtualFile + Send + Sync + 'static>>>;
+
+/// How long to wait before trying again when the host holds a conflicting lock
+const HOST_LOCK_RETRY: Duration = Duration::from_millis(50);
+
+/// ### `fd_lock()`
+/// Takes or releases an advisory lock on a range of bytes of a file, like
+/// `fcntl()` does with `F_SETLK` and `F_SETLKW`.
+///
+/// The locks belong to the process. They are all released when the process
+/// closes any of its file descriptors for the file, or when it exits. Files
+/// are told apart by their device and inode, so file systems that don't
+/// number their files return `ENOTSUP`.
+///
+/// ## Parameters
+///
+/// * `fd` - The file to lock
+/// * `lock_type` - The type of lock to take, or `unlock` to release the range
+/// * `start` - The first byte of the range
+/// * `len` - The length of the range, where zero means up to the end of the file
+/// * `wait` - If true then wait for conflicting locks to be released, otherwise
+///   return `EAGAIN` straight away
+#[instrument(level = "debug", skip_all, fields(pid = ctx.data().process.pid().raw(), %fd, ?lock_type, %start, %len), ret, err)]
+pub fn fd_lock(
+    mut ctx: FunctionEnvMut<'_, WasiEnv>,
+    fd: WasiFd,
+    lock_type: LockType,
+    start: Filesize,
+    len: Filesize,
+    wait: Bool,
+) -> Result<Errno, WasiError> {
+    wasi_try_ok!(WasiEnv::process_signals_and_exit(&mut ctx)?);
+
+    let end = wasi_try_ok!(lock_range_end(start, len));
+    let pid = ctx.data().process.pid();
+    fd_lock_internal(&mut ctx, fd, Some(pid), lock_type, start, end, wait)
+}
+
+/// Returns the (exclusive) end of a lock range
+pub(crate) fn lock_range_end(start: Filesize, len: Filesize) -> Result<u64, Errno> {
+    if len == 0 {
+        return Ok(LOCK_TO_EOF);
+    }
+    start.checked_add(len).ok_or(Errno::Inval)
+}
+
+/// The file that a descriptor locks
+pub(crate) struct LockTarget {
+    pub file: LockedFile,
+    /// The open handle of the file, which takes the matching host lock
+    pub handle: Option<FileHandle>,
+    pub rights: Rights,
+    pub open_file: OpenFile,
+}
+
+/// Finds the file that locks taken through `fd` are recorded against, which
+/// needs a file system that numbers its files
+pub(crate) fn lock_target(env: &WasiEnv, fd: WasiFd) -> Result<LockTarget, Errno> {
+    let fd_entry = env.state.fs.get_fd(fd)?;
+    let (path, handle) = {
+        let guard = fd_entry.inode.read();
+        match guard.deref() {
+            Kind::File { handle, path, .. } => (path.clone(), handle.clone()),
+            Kind::Dir { .. } | Kind::Root { .. } => return Err(Errno::Isdir),
+            _ => return Err(Errno::Badf),
+        }
+    };
+    let metadata = env
+        .state
+        .fs
+        .root_fs
+        .metadata(&path)
+        .map_err(fs_error_into_wasi_err)?;
+    let file = LockedFile::of(&metadata).ok_or(Errno::Notsup)?;
+
+    Ok(LockTarget {
+        file,
+        handle,
+        rights: fd_entry.rights,
+        open_file: OpenFile::of(&fd_entry),
+    })
+}
+
+/// Takes or releases a lock owned by the process `owner`, or by the open file
+/// description of `fd` when there is none
+pub(crate) fn fd_lock_internal(
+    ctx: &mut FunctionEnvMut<'_, WasiEnv>,
+    fd: WasiFd,
+    owner: Option<WasiProcessId>,
+    lock_type: LockType,
+    start: u64,
+    end: u64,
+    wait: Bool,
+) -> Result<Errno, WasiError> {
+    let env = ctx.data();
+    let LockTarget {
+        file,
+        handle,
+        rights,
+        open_file,
+    } = wasi_try_ok!(lock_target(env, fd));
+    let owner = match owner {
+        Some(pid) => LockOwner::Process(pid),
+        None => LockOwner::File(open_file),
+    };
+    let control_plane = env.process.compute.must_upgrade();
+
+    let kind = match lock_type {
+        LockType::Shared => LockKind::Shared,
+        LockType::Exclusive => LockKind::Exclusive,
+        LockType::Unlock => {
+            let locks = control_plane.file_locks();
+            locks.unlock(file, &owner, start, end);
+            if !locks.is_locked(file, start, end) {
+                host_unlock(handle.as_ref(), start, end);
+            }
+            return Ok(Errno::Success);
+        }
+        LockType::Unknown => return Ok(Errno::Inval),
+    };
+
+    // Like fcntl(), a range lock needs the file to be open for the matching access
+    if let LockOwner::Process(_) = owner {
+        let needed = match kind {
+            LockKind::Shared => Rights::FD_READ,
+            LockKind::Exclusive => Rights::FD_WRITE,
+        };
+        if !rights.contains(needed) {
+            return Ok(Errno::Badf);
+        }
+    }
+
+    let try_lock = {
+        let control_plane = control_plane.clone();
+        move || {
+            control_plane
+                .file_locks()
+                .try_lock(file, owner.clone(), kind, start, end, || {
+                    host_lock(handle.as_ref(), kind, start, end)
+                })
+        }
+    };
+
+    match try_lock() {
+        Ok(()) => return Ok(Errno::Success),
+        Err(LockConflict::Host(err)) if err != FsError::WouldBlock => {
+            return Ok(fs_error_into_wasi_err(err));
+        }
+        Err(_) if wait == Bool::False => return Ok(Errno::Again),
+        Err(_) => {}
+    }
+
+    // Wait for the lock to be released. The host does not tell us when its
+    // locks are released, and open file descriptions are closed by dropping
+    // them, so those are polled
+    let tasks = env.tasks().clone();
+    let res = __asyncify(ctx, None, async move {
+        loop {
+            let locks = control_plane.file_locks();
+            let released = locks.released();
+            match try_lock() {
+                Ok(()) => return Ok(()),
+                Err(LockConflict::Held(LockRecord {
+                    owner: LockOwner::Process(_),
+                    ..
+                })) => released.await,
+                Err(LockConflict::Held(_)) | Err(LockConflict::Host(FsError::WouldBlock)) => {
+                    tokio::select! {
+                        _ = released => {}
+                        _ = tasks.sleep_now(HOST_LOCK_RETRY) => {}
+                    }
+                }
+                Err(LockConflict::Host(err)) => return Err(fs_error_into_wasi_err(err)),
+            }
+        }
+    })?;
+    wasi_try_ok!(res);
+
+    Ok(Errno::Success)
+}
+
+/// Releases the locks that go away when a descriptor of `target` has been
+/// closed, the target being looked up before closing it
+pub(crate) fn release_fd_locks(env: &WasiEnv, target: LockTarget) {
+    let control_plane = match env.process.compute.upgrade() {
+        Some(control_plane) => control_plane,
+        None => return,
+    };
+
+    // The lock of the open file description goes away with its last
+    // descriptor, which may have been this one
+    let LockTarget {
+        file,
+        handle,
+        open_file,
+        ..
+    } = target;
+    drop(open_file);
+    let locks = control_plane.file_locks();
+    locks.release_closed();
+    locks.release(file, &LockOwner::Process(env.process.pid()));
+    if !locks.is_locked(file, 0, LOCK_TO_EOF) {
+        host_unlock(handle.as_ref(), 0, LOCK_TO_EOF);
+    }
+}
+
+fn host_lock(
+    handle: Option<&FileHandle>,
+    kind: LockKind,
+    start: u64,
+    end: u64,
+) -> Result<(), FsError> {
+    let kind = match kind {
+        LockKind::Shared => FileLockKind::Shared,
+        LockKind::Exclusive => FileLockKind::Exclusive,
+    };
+    match handle {
+        Some(handle) => match handle
+            .read()
+            .unwrap()
+            .host_lock(kind, start, host_len(start, end))
+        {
+            Err(FsError::Unsupported) => Ok(()),
+            res => res,
+        },
+        None => Ok(()),
+    }
+}
+
+fn host_unlock(handle: Option<&FileHandle>, start: u64, end: u64) {
+    if let Some(handle) = handle {
+        let res =
+            handle
+                .read()
+                .unwrap()
+                .host_lock(FileLockKind::Unlock, start, host_len(start, end));
+        if let Err(err) = res {
+            if err != FsError::Unsupported {
+                debug!(%err, "unable to release the host lock");
+            }
+        }
+    }
+}
+
+fn host_len(start: u64, end: u64) -> u64 {
+    if end == LOCK_TO_EOF {
+        0
+    } else {
+        end - start
+    }
+}
@@ -0,0 +1,75 @@
+use wasmer_wasix_types::wasi::{FileLock, LockType};
+
+use super::*;
+use crate::{
+    fs::{LockKind, LockOwner, LOCK_TO_EOF},
+    syscalls::*,
+};
+
+/// ### `fd_lock_get()`
+/// Finds a lock that would stop the process from locking a range of a file,
+/// like `fcntl()` does with `F_GETLK`. Only locks taken by processes in the
+/// sandbox are reported.
+///
+/// ## Parameters
+///
+/// * `fd` - The file to check
+/// * `lock_type` - The type of lock that would be taken
+/// * `start` - The first byte of the range
+/// * `len` - The length of the range, where zero means up to the end of the file
+/// * `ret` - Set to the conflicting lock, or to a lock of type `unlock` when
+///   the range could be locked
+#[instrument(level = "debug", skip_all, fields(pid = ctx.data().process.pid().raw(), %fd, ?lock_type, %start, %len), ret)]
+pub fn fd_lock_get<M: MemorySize>(
+    ctx: FunctionEnvMut<'_, WasiEnv>,
+    fd: WasiFd,
+    lock_type: LockType,
+    start: Filesize,
+    len: Filesize,
+    ret: WasmPtr<FileLock, M>,
+) -> Errno {
+    let kind = match lock_type {
+        LockType::Shared => LockKind::Shared,
+        LockType::Exclusive => LockKind::Exclusive,
+        LockType::Unlock | LockType::Unknown => return Errno::Inval,
+    };
+    let end = wasi_try!(lock_range_end(start, len));
+
+    let env = ctx.data();
+    let target = wasi_try!(lock_target(env, fd));
+    let owner = LockOwner::Process(env.process.pid());
+    let conflict = env.process.compute.must_upgrade().file_locks().conflict(
+        target.file,
+        &owner,
+        kind,
+        start,
+        end,
+    );
+
+    let lock = match conflict {
+        Some(conflict) => FileLock {
+            start: conflict.start,
+            len: if conflict.end == LOCK_TO_EOF {
+                0
+            } else {
+                conflict.end - conflict.start
+            },
+            type_: match conflict.kind {
+                LockKind::Shared => LockType::Shared,
+                LockKind::Exclusive => LockType::Exclusive,
+            },
+            pid: conflict.owner.pid().map_or(0, |pid| pid.raw()),
+        },
+        None => FileLock {
+            start,
+            len,
+            type_: LockType::Unlock,
+            pid: 0,
+        },
+    };
+
+    let memory = unsafe { env.memory_view(&ctx) };
+    wasi_try_mem!(ret.write(&memory, lock));
+
+    Errno::Success
+}
@@ -0,0 +1,157 @@
+use virtual_fs::AsyncReadExt;
+use wasmer_wasix_types::wasi::{MmapFlags, MmapProt};
+
+use super::*;
+use crate::{
+    fs::{Inode, MappedFile, MemoryMap},
+    syscalls::*,
+};
+
+/// ### `fd_mmap()`
+/// Maps a file into memory, like `mmap()`.
+///
+/// The region of memory is allocated by the caller, and the contents of the
+/// file are copied into it. Changes to a shared mapping are written back to
+/// the file when it is synced with `fd_msync`, unmapped with `fd_munmap` or
+/// when the process exits, and forked processes share the mapping with their
+/// parent. Until then `fd_read` doesn't see the changes. Changes to a private
+/// mapping are never written back.
+///
+/// ## Parameters
+///
+/// * `fd` - The file to map, which is ignored for anonymous mappings
+/// * `addr` - The start of the region of memory to map the file into
+/// * `len` - The length of the mapping in bytes
+/// * `prot` - The access that the program wants to the mapping
+/// * `flags` - Whether the mapping is shared or private, and if it is anonymous
+/// * `offset` - Offset in the file where the mapping starts
+// TODO: remove allow once inodes are refactored (see comments on [`WasiState`])
+#[allow(clippy::await_holding_lock)]
+#[instrument(level = "debug", skip_all, fields(%fd, addr = %addr.offset(), %len, ?prot, ?flags, %offset), ret, err)]
+pub fn fd_mmap<M: MemorySize>(
+    mut ctx: FunctionEnvMut<'_, WasiEnv>,
+    fd: WasiFd,
+    addr: WasmPtr<u8, M>,
+    len: M::Offset,
+    prot: MmapProt,
+    flags: MmapFlags,
+    offset: Filesize,
+) -> Result<Errno, WasiError> {
+    wasi_try_ok!(WasiEnv::process_signals_and_exit(&mut ctx)?);
+
+    let env = ctx.data();
+    let memory = unsafe { env.memory_view(&ctx) };
+    let region = wasi_try_mem_ok!(addr.slice(&memory, len));
+    if region.is_empty() || flags.contains(MmapFlags::SHARED) == flags.contains(MmapFlags::PRIVATE)
+    {
+        return Ok(Errno::Inval);
+    }
+    let shared = flags.contains(MmapFlags::SHARED);
+    let writable = prot.contains(MmapProt::WRITE);
+
+    // Anonymous mappings start out zeroed
+    if flags.contains(MmapFlags::ANONYMOUS) {
+        let data = vec![0u8; region.len() as usize];
+        wasi_try_mem_ok!(region.write_slice(&data));
+        let map = if shared {
+            MemoryMap::new_shared(region.offset(), data, None, writable)
+        } else {
+            MemoryMap::new_private(region.offset(), region.len())
+        };
+        wasi_try_ok!(env.process.memory_maps().insert(map));
+        return Ok(Errno::Success);
+    }
+
+    let fd_entry = wasi_try_ok!(env.state.fs.get_fd(fd));
+    if !fd_entry.rights.contains(Rights::FD_READ) {
+        return Ok(Errno::Access);
+    }
+    if shared && writable && !fd_entry.rights.contains(Rights::FD_WRITE) {
+        return Ok(Errno::Access);
+    }
+    let handle = {
+        let guard = fd_entry.inode.read();
+        match guard.deref() {
+            Kind::File {
+                handle: Some(handle),
+                ..
+            } => handle.clone(),
+            _ => return Ok(Errno::Nodev),
+        }
+    };
+
+    // Everything past the end of the file reads as zeros
+    let data = wasi_try_ok!(__asyncify_light(env, None, async {
+        let mut handle = handle.write().unwrap();
+        handle
+            .seek(std::io::SeekFrom::Start(offset))
+            .await
+            .map_err(map_io_err)?;
+
+        let mut data = vec![0u8; region.len() as usize];
+        let mut total_read = 0usize;
+        while total_read < data.len() {
+            let read = handle
+                .read(&mut data[total_read..])
+                .await
+                .map_err(map_io_err)?;
+            if read == 0 {
+                break;
+            }
+            total_read += read;
+        }
+        Ok(data)
+    })?);
+    wasi_try_mem_ok!(region.write_slice(&data));
+
+    let map = if shared {
+        let file = MappedFile {
+            ino: fd_entry.inode.ino(),
+            offset,
+            handle,
+        };
+        MemoryMap::new_shared(region.offset(), data, Some(file), writable)
+    } else {
+        MemoryMap::new_private(region.offset(), region.len())
+    };
+    wasi_try_ok!(env.process.memory_maps().insert(map));
+
+    Ok(Errno::Success)
+}
+
+/// Copies data that was written to a file into the shared mappings of the
+/// file in the memory of this process
+pub(crate) fn update_memory_maps<M: MemorySize>(
+    env: &WasiEnv,
+    memory: &MemoryView,
+    ino: Inode,
+    offset: u64,
+    iovs: &[__wasi_ciovec_t<M>],
+    written: usize,
+) -> Result<(), Errno> {
+    let mut maps = env.process.memory_maps();
+    if !maps.maps_file(ino) {
+        return Ok(());
+    }
+
+    let mut data = Vec::with_capacity(written);
+    for iov in iovs {
+        if data.len() >= written {
+            break;
+        }
+        let buf = WasmPtr::<u8, M>::new(iov.buf)
+            .slice(memory, iov.buf_len)
+            .map_err(mem_error_to_wasi)?
+            .read_to_vec()
+            .map_err(mem_error_to_wasi)?;
+        data.extend_from_slice(&buf);
+    }
+    data.truncate(written);
+
+    for (addr, range) in maps.file_written(ino, offset, &data) {
+        memory
+            .write(addr, &data[range])
+            .map_err(mem_error_to_wasi)?;
+    }
+    Ok(())
+}
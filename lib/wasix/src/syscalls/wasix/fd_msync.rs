@@ -0,0 +1,135 @@
+use wasmer_wasix_types::wasi::MsyncFlags;
+
+use super::*;
+use crate::{
+    fs::{FileWrite, MemoryMap},
+    syscalls::*,
+};
+
+/// ### `fd_msync()`
+/// Writes the changes to the memory mappings in a range of memory back to
+/// their files, like `msync()`.
+///
+/// The write back always finishes before the call returns, even when
+/// `async` is set.
+///
+/// ## Parameters
+///
+/// * `addr` - The start of the range of memory
+/// * `len` - The length of the range in bytes
+/// * `flags` - Set `invalidate` to also copy in the changes that other
+///   processes made to shared mappings
+#[instrument(level = "debug", skip_all, fields(addr = %addr.offset(), %len, ?flags), ret, err)]
+pub fn fd_msync<M: MemorySize>(
+    mut ctx: FunctionEnvMut<'_, WasiEnv>,
+    addr: WasmPtr<u8, M>,
+    len: M::Offset,
+    flags: MsyncFlags,
+) -> Result<Errno, WasiError> {
+    wasi_try_ok!(WasiEnv::process_signals_and_exit(&mut ctx)?);
+
+    if flags.contains(MsyncFlags::ASYNC) && flags.contains(MsyncFlags::SYNC) {
+        return Ok(Errno::Inval);
+    }
+    let invalidate = flags.contains(MsyncFlags::INVALIDATE);
+
+    let env = ctx.data();
+    let memory = unsafe { env.memory_view(&ctx) };
+    let writes = {
+        let mut maps = env.process.memory_maps();
+        let mut writes = Vec::new();
+        let mut found = false;
+        for map in maps.overlapping_mut(addr.offset().into(), len.into()) {
+            writes.extend(wasi_try_ok!(sync_memory_map(&memory, map, invalidate)));
+            found = true;
+        }
+        if !found {
+            return Ok(Errno::Nomem);
+        }
+        writes
+    };
+    wasi_try_ok!(write_back_memory_maps(env, writes)?);
+
+    Ok(Errno::Success)
+}
+
+/// Syncs a memory mapping with what is in memory, returning the dirty
+/// ranges that have to be written back to the file
+pub(crate) fn sync_memory_map(
+    memory: &MemoryView,
+    map: &mut MemoryMap,
+    invalidate: bool,
+) -> Result<Vec<FileWrite>, Errno> {
+    if !map.is_shared() {
+        return Ok(Vec::new());
+    }
+
+    let mut contents = vec![0u8; map.len as usize];
+    memory
+        .read(map.addr, &mut contents)
+        .map_err(mem_error_to_wasi)?;
+    let sync = map.sync(&mut contents, invalidate);
+    for range in sync.refreshed {
+        memory
+            .write(map.addr + range.start as u64, &contents[range])
+            .map_err(mem_error_to_wasi)?;
+    }
+    Ok(sync.writes)
+}
+
+/// Writes the shared mappings of a process that is exiting back to their
+/// files, which removes all of its mappings
+pub(crate) fn write_back_memory_maps_on_exit(env: &WasiEnv, memory: &MemoryView) {
+    let maps = env.process.memory_maps().take_all();
+    let mut writes = Vec::new();
+    for mut map in maps {
+        match sync_memory_map(memory, &mut map, false) {
+            Ok(map_writes) => writes.extend(map_writes),
+            Err(err) => {
+                tracing::warn!(addr = map.addr, %err, "failed to sync a memory mapping on exit")
+            }
+        }
+    }
+    match write_back_memory_maps(env, writes) {
+        Ok(Ok(())) => {}
+        Ok(Err(err)) => tracing::warn!(%err, "failed to write back memory mappings on exit"),
+        Err(err) => tracing::warn!(%err, "failed to write back memory mappings on exit"),
+    }
+}
+
+/// Writes the dirty ranges of memory mappings back to their files
+// TODO: remove allow once inodes are refactored (see comments on [`WasiState`])
+#[allow(clippy::await_holding_lock)]
+pub(crate) fn write_back_memory_maps(
+    env: &WasiEnv,
+    writes: Vec<FileWrite>,
+) -> Result<Result<(), Errno>, WasiError> {
+    if writes.is_empty() {
+        return Ok(Ok(()));
+    }
+
+    __asyncify_light(env, None, async move {
+        for write in writes {
+            let mut handle = write.handle.write().unwrap();
+
+            // Like on POSIX the parts of a mapping that are past the end of
+            // the file are not written back
+            let size = handle.size();
+            if write.offset >= size {
+                continue;
+            }
+            let len = (size - write.offset).min(write.data.len() as u64) as usize;
+
+            handle
+                .seek(std::io::SeekFrom::Start(write.offset))
+                .await
+                .map_err(map_io_err)?;
+            handle
+                .write_all(&write.data[..len])
+                .await
+                .map_err(map_io_err)?;
+            handle.flush().await.map_err(map_io_err)?;
+        }
+        Ok(())
+    })
+}
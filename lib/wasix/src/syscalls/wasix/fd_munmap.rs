@@ -0,0 +1,36 @@
+use super::*;
+use crate::syscalls::*;
+
+/// ### `fd_munmap()`
+/// Removes the memory mappings in a range of memory, like `munmap()`.
+///
+/// Changes to shared mappings are written back to their files first. The
+/// memory itself still belongs to the caller, which frees it afterwards.
+///
+/// ## Parameters
+///
+/// * `addr` - The start of the range of memory
+/// * `len` - The length of the range in bytes, which must cover whole mappings
+#[instrument(level = "debug", skip_all, fields(addr = %addr.offset(), %len), ret, err)]
+pub fn fd_munmap<M: MemorySize>(
+    mut ctx: FunctionEnvMut<'_, WasiEnv>,
+    addr: WasmPtr<u8, M>,
+    len: M::Offset,
+) -> Result<Errno, WasiError> {
+    wasi_try_ok!(WasiEnv::process_signals_and_exit(&mut ctx)?);
+
+    let env = ctx.data();
+    let memory = unsafe { env.memory_view(&ctx) };
+    let maps = wasi_try_ok!(env
+        .process
+        .memory_maps()
+        .remove(addr.offset().into(), len.into()));
+
+    let mut writes = Vec::new();
+    for mut map in maps {
+        writes.extend(wasi_try_ok!(sync_memory_map(&memory, &mut map, false)));
+    }
+    wasi_try_ok!(write_back_memory_maps(env, writes)?);
+
+    Ok(Errno::Success)
+}
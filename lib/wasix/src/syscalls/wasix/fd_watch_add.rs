@@ -0,0 +1,87 @@
+use std::path::PathBuf;
+
+use super::*;
+use crate::{fs::WatchFile, syscalls::*};
+
+/// ### `fd_watch_add()`
+/// Starts watching a file or directory, and the direct children of the
+/// directory, for changes
+/// Inputs:
+/// - `Fd fd`
+///     The watch created by `fd_watch_create`
+/// - `Fd dirfd`
+///     The directory that `path` is relative to
+/// - `const char *path`
+///     String containing the path to watch
+/// - `u32 path_len`
+///     The length of the `path` string
+/// - `Bool recursive`
+///     Whether all the descendants of the directory are watched
+/// Output:
+/// - `u32 wd`
+///     The watch descriptor found in the records of the events
+#[instrument(level = "trace", skip_all, fields(%fd, %dirfd, path = field::Empty, wd = field::Empty), ret)]
+pub fn fd_watch_add<M: MemorySize>(
+    ctx: FunctionEnvMut<'_, WasiEnv>,
+    fd: WasiFd,
+    dirfd: WasiFd,
+    path: WasmPtr<u8, M>,
+    path_len: M::Offset,
+    recursive: Bool,
+    ret_wd: WasmPtr<u32, M>,
+) -> Errno {
+    let env = ctx.data();
+    let (memory, state, inodes) = unsafe { env.get_memory_and_wasi_state_and_inodes(&ctx, 0) };
+
+    let mut path_string = unsafe { get_input_str!(&memory, path, path_len) };
+    if path_string.starts_with("./") {
+        path_string = state.fs.relative_path_to_absolute(path_string);
+    }
+    Span::current().record("path", path_string.as_str());
+
+    let recursive = match recursive {
+        Bool::False => false,
+        Bool::True => true,
+    };
+
+    // Find where the path lives in the file system
+    let inode = wasi_try!(state
+        .fs
+        .get_inode_at_path(inodes, dirfd, &path_string, true));
+    let watched = {
+        let guard = inode.read();
+        match guard.deref() {
+            Kind::File { path, .. } | Kind::Dir { path, .. } => path.clone(),
+            Kind::Root { .. } => PathBuf::from("/"),
+            _ => return Errno::Inval,
+        }
+    };
+
+    let watcher = wasi_try!(state
+        .fs
+        .root_fs
+        .watch(&watched, recursive)
+        .map_err(fs_error_into_wasi_err));
+
+    let wd = {
+        let fd_entry = wasi_try!(state.fs.get_fd(fd));
+        let handle = match fd_entry.inode.read().deref() {
+            Kind::File {
+                handle: Some(handle),
+                ..
+            } => handle.clone(),
+            _ => return Errno::Badf,
+        };
+        let mut handle = handle.write().unwrap();
+        let file = virtual_fs::Upcastable::upcast_any_mut(&mut **handle);
+        match file.downcast_mut::<WatchFile>() {
+            Some(file) => file.add(watched, watcher),
+            None => return Errno::Badf,
+        }
+    };
+    Span::current().record("wd", wd);
+
+    wasi_try_mem!(ret_wd.write(&memory, wd));
+
+    Errno::Success
+}
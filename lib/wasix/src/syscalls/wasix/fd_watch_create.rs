@@ -0,0 +1,43 @@
+use std::{path::PathBuf, sync::RwLock};
+
+use super::*;
+use crate::{fs::WatchFile, syscalls::*};
+
+/// ### `fd_watch_create()`
+/// Creates a file handle that reports the changes made to the file system,
+/// the paths to watch are added with `fd_watch_add`. Reading the handle
+/// returns the changes as records laid out like the `inotify_event` of
+/// Linux.
+/// Output:
+/// - `Fd`
+///     The file handle of the watch
+#[instrument(level = "trace", skip_all, fields(fd = field::Empty), ret)]
+pub fn fd_watch_create<M: MemorySize>(
+    ctx: FunctionEnvMut<'_, WasiEnv>,
+    ret_fd: WasmPtr<WasiFd, M>,
+) -> Errno {
+    let env = ctx.data();
+    let (memory, state, inodes) = unsafe { env.get_memory_and_wasi_state_and_inodes(&ctx, 0) };
+
+    let handle: Box<dyn VirtualFile + Send + Sync + 'static> = Box::new(WatchFile::new());
+    let inode = state.fs.create_inode_with_default_stat(
+        inodes,
+        Kind::File {
+            handle: Some(Arc::new(RwLock::new(handle))),
+            path: PathBuf::new(),
+            fd: None,
+        },
+        false,
+        "watch".to_string().into(),
+    );
+
+    let rights = Rights::FD_READ | Rights::POLL_FD_READWRITE | Rights::FD_FDSTAT_SET_FLAGS;
+    let fd = wasi_try!(state
+        .fs
+        .create_fd(rights, rights, Fdflags::empty(), 0, inode));
+    Span::current().record("fd", fd);
+
+    wasi_try_mem!(ret_fd.write(&memory, fd));
+
+    Errno::Success
+}
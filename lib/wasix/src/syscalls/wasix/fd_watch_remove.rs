@@ -0,0 +1,36 @@
+use super::*;
+use crate::{fs::WatchFile, syscalls::*};
+
+/// ### `fd_watch_remove()`
+/// Stops watching the changes of a path
+/// Inputs:
+/// - `Fd fd`
+///     The watch created by `fd_watch_create`
+/// - `u32 wd`
+///     The watch descriptor returned by `fd_watch_add`
+#[instrument(level = "trace", skip_all, fields(%fd, %wd), ret)]
+pub fn fd_watch_remove(ctx: FunctionEnvMut<'_, WasiEnv>, fd: WasiFd, wd: u32) -> Errno {
+    let env = ctx.data();
+    let state = env.state();
+
+    let fd_entry = wasi_try!(state.fs.get_fd(fd));
+    let handle = match fd_entry.inode.read().deref() {
+        Kind::File {
+            handle: Some(handle),
+            ..
+        } => handle.clone(),
+        _ => return Errno::Badf,
+    };
+    let mut handle = handle.write().unwrap();
+    let file = virtual_fs::Upcastable::upcast_any_mut(&mut **handle);
+    match file.downcast_mut::<WatchFile>() {
+        Some(file) => {
+            if file.remove(wd) {
+                Errno::Success
+            } else {
+                Errno::Inval
+            }
+        }
+        None => Errno::Badf,
+    }
+}
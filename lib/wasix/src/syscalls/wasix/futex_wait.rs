@@ -159,16 +159,24 @@ pub(super) fn futex_wait_internal<M: MemorySize + 'static>(
 
     // We use asyncify on the poller and potentially go into deep sleep
     tracing::trace!("wait on {futex_idx}");
-    let res =
-        __asyncify_with_deep_sleep::<M, _, _>(ctx, Duration::from_millis(50), Box::pin(poller))?;
-    if let AsyncifyAction::Finish(ctx, res) = res {
-        let mut env = ctx.data();
-        let memory = unsafe { env.memory_view(&ctx) };
-        if res {
-            wasi_try_mem_ok!(ret_woken.write(&memory, Bool::True));
-        } else {
-            wasi_try_mem_ok!(ret_woken.write(&memory, Bool::False));
+    let res = __asyncify_with_deep_sleep::<M, _, _>(
+        ctx,
+        Duration::from_millis(50),
+        OnSignal::Interrupt,
+        Box::pin(poller),
+    )?;
+    match res {
+        AsyncifyAction::Finish(ctx, res) => {
+            let mut env = ctx.data();
+            let memory = unsafe { env.memory_view(&ctx) };
+            if res {
+                wasi_try_mem_ok!(ret_woken.write(&memory, Bool::True));
+            } else {
+                wasi_try_mem_ok!(ret_woken.write(&memory, Bool::False));
+            }
         }
+        AsyncifyAction::Interrupted(_) => return Ok(Errno::Intr),
+        AsyncifyAction::Unwind => {}
     }
     Ok(Errno::Success)
 }
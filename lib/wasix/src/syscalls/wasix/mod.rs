@@ -3,11 +3,22 @@ mod chdir;
 mod epoll_create;
 mod epoll_ctl;
 mod epoll_wait;
+mod fd_filestat_set_mode;
+mod fd_flock;
+mod fd_lock;
+mod fd_lock_get;
+mod fd_mmap;
+mod fd_msync;
+mod fd_munmap;
 mod fd_pipe;
+mod fd_watch_add;
+mod fd_watch_create;
+mod fd_watch_remove;
 mod futex_wait;
 mod futex_wake;
 mod futex_wake_all;
 mod getcwd;
+mod path_filestat_set_mode;
 mod port_addr_add;
 mod port_addr_clear;
 mod port_addr_list;
@@ -23,18 +34,38 @@ mod port_route_remove;
 mod port_unbridge;
 mod proc_exec;
 mod proc_fork;
+mod proc_getpgid;
+mod proc_getsid;
 mod proc_id;
 mod proc_join;
 mod proc_parent;
+mod proc_rlimit_get;
+mod proc_rlimit_set;
+mod proc_setpgid;
+mod proc_setsid;
+mod proc_sigaction;
 mod proc_signal;
+mod proc_signal_group;
+mod proc_sigpending;
+mod proc_sigsuspend;
+mod proc_snapshot;
 mod proc_spawn;
+mod pty_get;
+mod pty_getpgrp;
+mod pty_open;
+mod pty_set;
+mod pty_setpgrp;
 mod resolve;
 mod sched_yield;
 mod sock_accept;
 mod sock_addr_local;
+mod sock_addr_local_unix;
 mod sock_addr_peer;
+mod sock_addr_peer_unix;
 mod sock_bind;
+mod sock_bind_unix;
 mod sock_connect;
+mod sock_connect_unix;
 mod sock_get_opt_flag;
 mod sock_get_opt_size;
 mod sock_get_opt_time;
@@ -60,22 +91,38 @@ mod thread_exit;
 mod thread_id;
 mod thread_join;
 mod thread_parallelism;
+mod thread_sigaltstack;
+mod thread_siginfo_get;
+mod thread_sigmask;
 mod thread_signal;
 mod thread_sleep;
 mod thread_spawn;
 mod tty_get;
+mod tty_getpgrp;
 mod tty_set;
+mod tty_setpgrp;
 
 pub use callback_signal::*;
 pub use chdir::*;
 pub use epoll_create::*;
 pub use epoll_ctl::*;
 pub use epoll_wait::*;
+pub use fd_filestat_set_mode::*;
+pub use fd_flock::*;
+pub use fd_lock::*;
+pub use fd_lock_get::*;
+pub use fd_mmap::*;
+pub use fd_msync::*;
+pub use fd_munmap::*;
 pub use fd_pipe::*;
+pub use fd_watch_add::*;
+pub use fd_watch_create::*;
+pub use fd_watch_remove::*;
 pub use futex_wait::*;
 pub use futex_wake::*;
 pub use futex_wake_all::*;
 pub use getcwd::*;
+pub use path_filestat_set_mode::*;
 pub use port_addr_add::*;
 pub use port_addr_clear::*;
 pub use port_addr_list::*;
@@ -91,18 +138,38 @@ pub use port_route_remove::*;
 pub use port_unbridge::*;
 pub use proc_exec::*;
 pub use proc_fork::*;
+pub use proc_getpgid::*;
+pub use proc_getsid::*;
 pub use proc_id::*;
 pub use proc_join::*;
 pub use proc_parent::*;
+pub use proc_rlimit_get::*;
+pub use proc_rlimit_set::*;
+pub use proc_setpgid::*;
+pub use proc_setsid::*;
+pub use proc_sigaction::*;
 pub use proc_signal::*;
+pub use proc_signal_group::*;
+pub use proc_sigpending::*;
+pub use proc_sigsuspend::*;
+pub use proc_snapshot::*;
 pub use proc_spawn::*;
+pub use pty_get::*;
+pub use pty_getpgrp::*;
+pub use pty_open::*;
+pub use pty_set::*;
+pub use pty_setpgrp::*;
 pub use resolve::*;
 pub use sched_yield::*;
 pub use sock_accept::*;
 pub use sock_addr_local::*;
+pub use sock_addr_local_unix::*;
 pub use sock_addr_peer::*;
+pub use sock_addr_peer_unix::*;
 pub use sock_bind::*;
+pub use sock_bind_unix::*;
 pub use sock_connect::*;
+pub use sock_connect_unix::*;
 pub use sock_get_opt_flag::*;
 pub use sock_get_opt_size::*;
 pub use sock_get_opt_time::*;
@@ -128,10 +195,15 @@ pub use thread_exit::*;
 pub use thread_id::*;
 pub use thread_join::*;
 pub use thread_parallelism::*;
+pub use thread_sigaltstack::*;
+pub use thread_siginfo_get::*;
+pub use thread_sigmask::*;
 pub use thread_signal::*;
 pub use thread_sleep::*;
 pub use thread_spawn::*;
 pub use tty_get::*;
+pub use tty_getpgrp::*;
 pub use tty_set::*;
+pub use tty_setpgrp::*;
 
 use tracing::{debug_span, field, instrument, trace_span, Span};
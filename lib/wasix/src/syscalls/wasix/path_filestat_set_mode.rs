@@ -0,0 +1,64 @@
+use super::*;
+use crate::syscalls::*;
+
+/// ### `path_filestat_set_mode()`
+/// Changes the permission bits of a file or directory, like `chmod()` does.
+///
+/// Changing the permissions requires the same right as changing the times.
+///
+/// ## Parameters
+///
+/// * `fd` - The directory relative to which the path is resolved
+/// * `flags` - Flags to control how the path is understood
+/// * `path` - The path of the file or directory to change
+/// * `mode` - The new permission bits, only the lower 12 bits (`0o7777`) are
+///   kept
+#[instrument(level = "debug", skip_all, fields(%fd, path = field::Empty, mode = format_args!("{:o}", mode)), ret)]
+pub fn path_filestat_set_mode<M: MemorySize>(
+    ctx: FunctionEnvMut<'_, WasiEnv>,
+    fd: WasiFd,
+    flags: LookupFlags,
+    path: WasmPtr<u8, M>,
+    path_len: M::Offset,
+    mode: u32,
+) -> Errno {
+    let env = ctx.data();
+    let (memory, state, inodes) = unsafe { env.get_memory_and_wasi_state_and_inodes(&ctx, 0) };
+    let fd_entry = wasi_try!(state.fs.get_fd(fd));
+    if !fd_entry.rights.contains(Rights::PATH_FILESTAT_SET_TIMES) {
+        return Errno::Access;
+    }
+
+    let mut path_string = unsafe { get_input_str!(&memory, path, path_len) };
+    Span::current().record("path", path_string.as_str());
+
+    // Convert relative paths into absolute paths
+    if path_string.starts_with("./") {
+        path_string = ctx.data().state.fs.relative_path_to_absolute(path_string);
+    }
+
+    let file_inode = wasi_try!(state.fs.get_inode_at_path(
+        inodes,
+        fd,
+        &path_string,
+        flags & __WASI_LOOKUP_SYMLINK_FOLLOW != 0,
+    ));
+    let path = {
+        let guard = file_inode.read();
+        match guard.deref() {
+            Kind::File { path, .. } | Kind::Dir { path, .. } => path.clone(),
+            // the permissions of a symlink are not used
+            Kind::Symlink { .. } => return Errno::Notsup,
+            Kind::Root { .. } => return Errno::Notcapable,
+            _ => return Errno::Inval,
+        }
+    };
+
+    wasi_try!(state
+        .fs
+        .root_fs
+        .set_permissions(&path, mode)
+        .map_err(fs_error_into_wasi_err));
+
+    Errno::Success
+}
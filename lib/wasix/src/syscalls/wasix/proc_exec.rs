@@ -223,10 +223,12 @@ pub fn proc_exec<M: MemorySize>(
                 let env = ctx.data();
                 let thread = env.thread.clone();
 
-                // The poller will wait for the process to actually finish
+                // The poller will wait for the process to actually finish, the
+                // signals that arrive in the meantime are for the new module
                 let res = __asyncify_with_deep_sleep::<M, _, _>(
                     ctx,
                     Duration::from_millis(50),
+                    OnSignal::Defer,
                     async move {
                         process
                             .wait_finished()
@@ -243,6 +245,9 @@ pub fn proc_exec<M: MemorySize>(
                         WasiEnv::process_signals_and_exit(&mut ctx)?;
                         Err(WasiError::Exit(Errno::Unknown.into()))
                     }
+                    AsyncifyAction::Interrupted(_) => {
+                        unreachable!("deferred signals do not interrupt the wait")
+                    }
                     AsyncifyAction::Unwind => Ok(()),
                 }
             }
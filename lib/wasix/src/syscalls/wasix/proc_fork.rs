@@ -1,7 +1,7 @@
 use super::*;
 use crate::{
     capture_snapshot,
-    os::task::OwnedTaskStatus,
+    os::task::{control_plane::ControlPlaneError, OwnedTaskStatus},
     runtime::task_manager::{TaskWasm, TaskWasmRunProperties},
     syscalls::*,
     WasiThreadHandle,
@@ -50,6 +50,10 @@ pub fn proc_fork<M: MemorySize>(
     // in the parent process context
     let (mut child_env, mut child_handle) = match ctx.data().fork() {
         Ok(p) => p,
+        Err(ControlPlaneError::ResourceLimitReached { .. }) => {
+            debug!("could not fork process: too many child processes");
+            return Ok(Errno::Again);
+        }
         Err(err) => {
             debug!("could not fork process: {err}");
             // TODO: evaluate the appropriate error code, document it in the spec.
@@ -0,0 +1,31 @@
+use super::*;
+use crate::{syscalls::*, WasiProcess};
+
+/// ### `proc_getpgid()`
+/// Returns the process group that a process belongs to
+///
+/// ## Parameters
+///
+/// * `pid` - The process to look up, zero means this process
+/// * `ret` - Set to the ID of the process group
+#[instrument(level = "trace", skip_all, fields(%pid), ret)]
+pub fn proc_getpgid<M: MemorySize>(
+    ctx: FunctionEnvMut<'_, WasiEnv>,
+    pid: Pid,
+    ret: WasmPtr<Pid, M>,
+) -> Errno {
+    let env = ctx.data();
+    let process = wasi_try!(lookup_process(env, pid));
+
+    let memory = unsafe { env.memory_view(&ctx) };
+    wasi_try_mem!(ret.write(&memory, process.pgid().raw() as Pid));
+    Errno::Success
+}
+
+/// Finds a process by its ID, where zero means the calling process
+pub(crate) fn lookup_process(env: &WasiEnv, pid: Pid) -> Result<WasiProcess, Errno> {
+    if pid == 0 {
+        return Ok(env.process.clone());
+    }
+    env.control_plane.get_process(pid.into()).ok_or(Errno::Srch)
+}
@@ -0,0 +1,23 @@
+use super::*;
+use crate::syscalls::*;
+
+/// ### `proc_getsid()`
+/// Returns the session that a process belongs to
+///
+/// ## Parameters
+///
+/// * `pid` - The process to look up, zero means this process
+/// * `ret` - Set to the ID of the session, which is the ID of its leader
+#[instrument(level = "trace", skip_all, fields(%pid), ret)]
+pub fn proc_getsid<M: MemorySize>(
+    ctx: FunctionEnvMut<'_, WasiEnv>,
+    pid: Pid,
+    ret: WasmPtr<Pid, M>,
+) -> Errno {
+    let env = ctx.data();
+    let process = wasi_try!(lookup_process(env, pid));
+
+    let memory = unsafe { env.memory_view(&ctx) };
+    wasi_try_mem!(ret.write(&memory, process.sid().raw() as Pid));
+    Errno::Success
+}
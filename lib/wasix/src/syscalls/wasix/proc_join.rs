@@ -1,5 +1,6 @@
 use std::task::Waker;
 
+use futures::FutureExt;
 use serde::{Deserialize, Serialize};
 use wasmer::FromToNativeWasmType;
 use wasmer_wasix_types::wasi::{JoinFlags, JoinStatus, JoinStatusType, JoinStatusUnion, OptionPid};
@@ -11,6 +12,8 @@ use crate::{syscalls::*, WasiProcess};
 enum JoinStatusResult {
     Nothing,
     ExitNormal(WasiProcessId, ExitCode),
+    /// The process was stopped by the signal with this number
+    Stopped(WasiProcessId, u8),
     Err(Errno),
 }
 
@@ -20,6 +23,8 @@ enum JoinStatusResult {
 /// ## Parameters
 ///
 /// * `pid` - Handle of the child process to wait on
+/// * `flags` - `WAKE_STOPPED` also returns when the process is stopped and
+///   `NON_BLOCKING` returns straight away when nothing has happened yet
 //#[instrument(level = "trace", skip_all, fields(pid = ctx.data().process.pid().raw()), ret, err)]
 pub fn proc_join<M: MemorySize + 'static>(
     ctx: FunctionEnvMut<'_, WasiEnv>,
@@ -33,7 +38,7 @@ pub fn proc_join<M: MemorySize + 'static>(
 pub(super) fn proc_join_internal<M: MemorySize + 'static>(
     mut ctx: FunctionEnvMut<'_, WasiEnv>,
     pid_ptr: WasmPtr<OptionPid, M>,
-    _flags: JoinFlags,
+    flags: JoinFlags,
     status_ptr: WasmPtr<JoinStatus, M>,
 ) -> Result<Errno, WasiError> {
     wasi_try_ok!(WasiEnv::process_signals_and_exit(&mut ctx)?);
@@ -64,6 +69,20 @@ pub(super) fn proc_join_internal<M: MemorySize + 'static>(
                         },
                     }
                 }
+                JoinStatusResult::Stopped(pid, signal) => {
+                    let option_pid = OptionPid {
+                        tag: OptionTag::Some,
+                        pid: pid.raw() as Pid,
+                    };
+                    pid_ptr.write(&view, option_pid).ok();
+
+                    JoinStatus {
+                        tag: JoinStatusType::Stopped,
+                        u: JoinStatusUnion {
+                            stopped: Signal::try_from(signal).unwrap_or(Signal::Sigstop),
+                        },
+                    }
+                }
                 JoinStatusResult::Err(err) => {
                     ret = err;
                     JoinStatus {
@@ -110,6 +129,8 @@ pub(super) fn proc_join_internal<M: MemorySize + 'static>(
         }
     ));
 
+    let wake_stopped = flags.contains(JoinFlags::WAKE_STOPPED);
+
     // If the ID is maximum then it means wait for any of the children
     let pid = match option_pid {
         None => {
@@ -117,32 +138,47 @@ pub(super) fn proc_join_internal<M: MemorySize + 'static>(
             let pid_ptr = pid_ptr;
             let status_ptr = status_ptr;
 
+            let join = async move {
+                let parent = process.clone();
+                let child_exit = tokio::select! {
+                    res = process.join_any_child() => res,
+                    (pid, signal) = parent.join_stopped_child(), if wake_stopped => {
+                        tracing::trace!(%pid, ?signal, "triggered child stop");
+                        return JoinStatusResult::Stopped(pid, signal as u8);
+                    }
+                };
+                match child_exit {
+                    Ok(Some((pid, exit_code))) => {
+                        tracing::trace!(%pid, %exit_code, "triggered child join");
+                        trace!(ret_id = pid.raw(), exit_code = exit_code.raw());
+                        JoinStatusResult::ExitNormal(pid, exit_code)
+                    }
+                    Ok(None) => {
+                        tracing::trace!("triggered child join (no child)");
+                        JoinStatusResult::Err(Errno::Child)
+                    }
+                    Err(err) => {
+                        tracing::trace!(%err, "error triggered on child join");
+                        JoinStatusResult::Err(err)
+                    }
+                }
+            };
+            if flags.contains(JoinFlags::NON_BLOCKING) {
+                let status = join.now_or_never().unwrap_or(JoinStatusResult::Nothing);
+                return ret_result(ctx, status);
+            }
+
             // We wait for any process to exit (if it takes too long
             // then we go into a deep sleep)
             let res = __asyncify_with_deep_sleep::<M, _, _>(
                 ctx,
                 Duration::from_millis(50),
-                async move {
-                    let child_exit = process.join_any_child().await;
-                    match child_exit {
-                        Ok(Some((pid, exit_code))) => {
-                            tracing::trace!(%pid, %exit_code, "triggered child join");
-                            trace!(ret_id = pid.raw(), exit_code = exit_code.raw());
-                            JoinStatusResult::ExitNormal(pid, exit_code)
-                        }
-                        Ok(None) => {
-                            tracing::trace!("triggered child join (no child)");
-                            JoinStatusResult::Err(Errno::Child)
-                        }
-                        Err(err) => {
-                            tracing::trace!(%err, "error triggered on child join");
-                            JoinStatusResult::Err(err)
-                        }
-                    }
-                },
+                OnSignal::Interrupt,
+                join,
             )?;
             return match res {
                 AsyncifyAction::Finish(ctx, result) => ret_result(ctx, result),
+                AsyncifyAction::Interrupted(_) => Ok(Errno::Intr),
                 AsyncifyAction::Unwind => Ok(Errno::Success),
             };
         }
@@ -154,16 +190,10 @@ pub(super) fn proc_join_internal<M: MemorySize + 'static>(
 
     // Waiting for a process that is an explicit child will join it
     // meaning it will no longer be a sub-process of the main process
+    // once it has finished
     let mut process = {
-        let mut inner = ctx.data().process.inner.write().unwrap();
-        let process = inner
-            .children
-            .iter()
-            .filter(|c| c.pid == pid)
-            .map(Clone::clone)
-            .next();
-        inner.children.retain(|c| c.pid != pid);
-        process
+        let inner = ctx.data().process.inner.read().unwrap();
+        inner.children.iter().find(|c| c.pid == pid).cloned()
     };
 
     // Otherwise it could be the case that we are waiting for a process
@@ -183,15 +213,34 @@ pub(super) fn proc_join_internal<M: MemorySize + 'static>(
         ));
 
         // Wait for the process to finish
-        let process2 = process.clone();
-        let res =
-            __asyncify_with_deep_sleep::<M, _, _>(ctx, Duration::from_millis(50), async move {
-                let exit_code = process.join().await.unwrap_or_else(|_| Errno::Child.into());
-                tracing::trace!(%exit_code, "triggered child join");
-                JoinStatusResult::ExitNormal(pid, exit_code)
-            })?;
+        let parent = ctx.data().process.clone();
+        let join = async move {
+            tokio::select! {
+                res = process.join() => {
+                    let exit_code = res.unwrap_or_else(|_| Errno::Child.into());
+                    tracing::trace!(%exit_code, "triggered child join");
+                    parent.write().children.retain(|c| c.pid != pid);
+                    JoinStatusResult::ExitNormal(pid, exit_code)
+                }
+                signal = process.wait_stopped(), if wake_stopped => {
+                    tracing::trace!(?signal, "triggered child stop");
+                    JoinStatusResult::Stopped(pid, signal as u8)
+                }
+            }
+        };
+        if flags.contains(JoinFlags::NON_BLOCKING) {
+            let status = join.now_or_never().unwrap_or(JoinStatusResult::Nothing);
+            return ret_result(ctx, status);
+        }
+        let res = __asyncify_with_deep_sleep::<M, _, _>(
+            ctx,
+            Duration::from_millis(50),
+            OnSignal::Interrupt,
+            join,
+        )?;
         return match res {
             AsyncifyAction::Finish(ctx, result) => ret_result(ctx, result),
+            AsyncifyAction::Interrupted(_) => Ok(Errno::Intr),
             AsyncifyAction::Unwind => Ok(Errno::Success),
         };
     }
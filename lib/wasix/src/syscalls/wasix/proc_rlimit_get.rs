@@ -0,0 +1,27 @@
+use wasmer_wasix_types::wasi::{Rlimit, RlimitResource};
+
+use super::*;
+use crate::syscalls::*;
+
+/// ### `proc_rlimit_get()`
+/// Returns the limits of the current process on the usage of a resource.
+///
+/// ## Parameters
+///
+/// * `resource` - The resource to return the limits of
+/// * `ret` - Set to the soft and hard limits, where `u64::MAX` means there is
+///   no limit
+#[instrument(level = "trace", skip_all, fields(?resource), ret)]
+pub fn proc_rlimit_get<M: MemorySize>(
+    ctx: FunctionEnvMut<'_, WasiEnv>,
+    resource: RlimitResource,
+    ret: WasmPtr<Rlimit, M>,
+) -> Errno {
+    let env = ctx.data();
+    let limit = wasi_try!(env.process.limits().get(resource));
+
+    let memory = unsafe { env.memory_view(&ctx) };
+    wasi_try_mem!(ret.write(&memory, limit.into()));
+
+    Errno::Success
+}
@@ -0,0 +1,38 @@
+use wasmer_wasix_types::wasi::{Rlimit, RlimitResource};
+
+use super::*;
+use crate::{os::task::limits::ResourceLimit, syscalls::*};
+
+/// ### `proc_rlimit_set()`
+/// Changes the limits of the current process on the usage of a resource,
+/// the new limits are inherited by the child processes created after.
+///
+/// The soft limit can be raised up to the hard limit but the hard limit can
+/// only be lowered, trying to raise it fails with `EPERM`.
+///
+/// ## Parameters
+///
+/// * `resource` - The resource to change the limits of
+/// * `limit` - The new soft and hard limits, where `u64::MAX` means there is
+///   no limit
+#[instrument(level = "debug", skip_all, fields(pid = ctx.data().process.pid().raw(), ?resource), ret)]
+pub fn proc_rlimit_set<M: MemorySize>(
+    ctx: FunctionEnvMut<'_, WasiEnv>,
+    resource: RlimitResource,
+    limit: WasmPtr<Rlimit, M>,
+) -> Errno {
+    let env = ctx.data();
+    let memory = unsafe { env.memory_view(&ctx) };
+    let limit = wasi_try_mem!(limit.read(&memory));
+    let limit = ResourceLimit::new(limit.cur, limit.max);
+
+    {
+        let mut limits = env.process.limits.write().unwrap();
+        wasi_try!(limits.set(resource, limit));
+    }
+    if resource == RlimitResource::OpenFiles {
+        env.state.fs.set_max_fds(limit.soft);
+    }
+
+    Errno::Success
+}
@@ -0,0 +1,52 @@
+use super::*;
+use crate::syscalls::*;
+
+/// ### `proc_setpgid()`
+/// Moves a process into another process group, which is used by shells to
+/// put each job into its own group
+///
+/// ## Parameters
+///
+/// * `pid` - The process to move, which must be this process or one of its
+///   children, zero means this process
+/// * `pgid` - The process group to move it to, which must be in the same
+///   session, zero means a new group whose ID is the ID of the process
+#[instrument(level = "debug", skip_all, fields(pid = ctx.data().process.pid().raw(), process = pid, %pgid), ret)]
+pub fn proc_setpgid<M: MemorySize>(ctx: FunctionEnvMut<'_, WasiEnv>, pid: Pid, pgid: Pid) -> Errno {
+    let env = ctx.data();
+    let current = &env.process;
+
+    let process = if pid == 0 || WasiProcessId::from(pid) == current.pid() {
+        current.clone()
+    } else {
+        let pid: WasiProcessId = pid.into();
+        let inner = current.read();
+        wasi_try!(inner
+            .children
+            .iter()
+            .find(|child| child.pid() == pid)
+            .cloned()
+            .ok_or(Errno::Srch))
+    };
+    let pgid = if pgid == 0 {
+        process.pid()
+    } else {
+        WasiProcessId::from(pgid)
+    };
+
+    // Processes can only move between groups of their own session and a
+    // session leader can not leave its group
+    let sid = current.sid();
+    if process.sid() != sid || process.sid() == process.pid() {
+        return Errno::Perm;
+    }
+    if pgid != process.pid() {
+        let group = env.control_plane.process_group(pgid);
+        if !group.iter().any(|member| member.sid() == sid) {
+            return Errno::Perm;
+        }
+    }
+
+    process.set_pgid(pgid);
+    Errno::Success
+}
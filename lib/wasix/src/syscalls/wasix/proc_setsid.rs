@@ -0,0 +1,27 @@
+use super::*;
+use crate::syscalls::*;
+
+/// ### `proc_setsid()`
+/// Starts a new session with this process as its leader, the process also
+/// becomes the leader of a new process group within the session
+///
+/// Fails with `EPERM` if the process already leads a process group.
+///
+/// ## Parameters
+///
+/// * `ret` - Set to the ID of the new session
+#[instrument(level = "debug", skip_all, fields(pid = ctx.data().process.pid().raw()), ret)]
+pub fn proc_setsid<M: MemorySize>(ctx: FunctionEnvMut<'_, WasiEnv>, ret: WasmPtr<Pid, M>) -> Errno {
+    let env = ctx.data();
+    let process = &env.process;
+
+    let pid = process.pid();
+    if process.pgid() == pid || !env.control_plane.process_group(pid).is_empty() {
+        return Errno::Perm;
+    }
+    process.start_session();
+
+    let memory = unsafe { env.memory_view(&ctx) };
+    wasi_try_mem!(ret.write(&memory, pid.raw() as Pid));
+    Errno::Success
+}
@@ -0,0 +1,53 @@
+use wasmer_wasix_types::wasi::{SigDisposition, Sigaction};
+
+use super::*;
+use crate::{os::task::signal::default_sigaction, syscalls::*};
+
+/// ### `proc_sigaction()`
+/// Changes how the current process handles a signal, like `sigaction()`.
+///
+/// Signals with the `handler` disposition are passed to the callback that
+/// was registered with `callback_signal`. Signals whose disposition was never
+/// set are handled the way they were before this syscall existed.
+///
+/// ## Parameters
+///
+/// * `sig` - The signal to change, which can not be `SIGKILL` or `SIGSTOP`
+/// * `act` - The new disposition of the signal, or null to leave it as is
+/// * `oldact` - Where the previous disposition is written, unless it is null
+#[instrument(level = "debug", skip_all, fields(pid = ctx.data().process.pid().raw(), ?sig), ret, err)]
+pub fn proc_sigaction<M: MemorySize>(
+    mut ctx: FunctionEnvMut<'_, WasiEnv>,
+    sig: Signal,
+    act: WasmPtr<Sigaction, M>,
+    oldact: WasmPtr<Sigaction, M>,
+) -> Result<Errno, WasiError> {
+    wasi_try_ok!(WasiEnv::process_signals_and_exit(&mut ctx)?);
+
+    if sig == Signal::Signone {
+        return Ok(Errno::Inval);
+    }
+
+    let env = ctx.data();
+    let memory = unsafe { env.memory_view(&ctx) };
+    let old = env
+        .process
+        .signal_action(sig)
+        .unwrap_or_else(default_sigaction);
+
+    if !act.is_null() {
+        let action = wasi_try_mem_ok!(act.read(&memory));
+        if sig == Signal::Sigkill || sig == Signal::Sigstop {
+            return Ok(Errno::Inval);
+        }
+        if action.disposition == SigDisposition::Unknown {
+            return Ok(Errno::Inval);
+        }
+        env.process.set_signal_action(sig, action);
+    }
+    if !oldact.is_null() {
+        wasi_try_mem_ok!(oldact.write(&memory, old));
+    }
+
+    Ok(Errno::Success)
+}
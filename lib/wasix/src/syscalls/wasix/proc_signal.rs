@@ -19,7 +19,7 @@ pub fn proc_signal<M: MemorySize>(
         ctx.data().control_plane.get_process(pid)
     };
     if let Some(process) = process {
-        process.signal_process(sig);
+        process.signal_process_from(sig, ctx.data().pid());
     }
 
     wasi_try_ok!(WasiEnv::process_signals_and_exit(&mut ctx)?);
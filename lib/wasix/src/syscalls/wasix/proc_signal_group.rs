@@ -0,0 +1,34 @@
+use wasmer_wasix_types::wasi::SignalCode;
+
+use super::*;
+use crate::{os::task::signal::new_siginfo, syscalls::*};
+
+/// ### `proc_signal_group()`
+/// Sends a signal to every process in a process group
+///
+/// ## Parameters
+///
+/// * `pgid` - The process group to signal, zero means the group of this process
+/// * `sig` - Signal to send to the processes
+#[instrument(level = "trace", skip_all, fields(%pgid, ?sig), ret, err)]
+pub fn proc_signal_group<M: MemorySize>(
+    mut ctx: FunctionEnvMut<'_, WasiEnv>,
+    pgid: Pid,
+    sig: Signal,
+) -> Result<Errno, WasiError> {
+    let env = ctx.data();
+    let pgid = if pgid == 0 {
+        env.process.pgid()
+    } else {
+        WasiProcessId::from(pgid)
+    };
+
+    let info = new_siginfo(sig, SignalCode::User, Some(env.pid()));
+    if !env.control_plane.signal_group(pgid, sig, info) {
+        return Ok(Errno::Srch);
+    }
+
+    wasi_try_ok!(WasiEnv::process_signals_and_exit(&mut ctx)?);
+
+    Ok(Errno::Success)
+}
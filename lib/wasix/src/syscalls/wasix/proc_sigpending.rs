@@ -0,0 +1,26 @@
+use wasmer_wasix_types::wasi::Sigset;
+
+use super::*;
+use crate::syscalls::*;
+
+/// ### `proc_sigpending()`
+/// Returns the signals that are waiting to be delivered to the current
+/// thread because it has blocked them, like `sigpending()`.
+///
+/// ## Parameters
+///
+/// * `ret` - Where the set of pending signals is written
+#[instrument(level = "debug", skip_all, fields(pid = ctx.data().process.pid().raw(), tid = ctx.data().tid().raw()), ret, err)]
+pub fn proc_sigpending<M: MemorySize>(
+    mut ctx: FunctionEnvMut<'_, WasiEnv>,
+    ret: WasmPtr<Sigset, M>,
+) -> Result<Errno, WasiError> {
+    wasi_try_ok!(WasiEnv::process_signals_and_exit(&mut ctx)?);
+
+    let env = ctx.data();
+    let memory = unsafe { env.memory_view(&ctx) };
+    let pending = env.thread.pending_signals() & env.thread.signal_mask();
+    wasi_try_mem_ok!(ret.write(&memory, pending));
+
+    Ok(Errno::Success)
+}
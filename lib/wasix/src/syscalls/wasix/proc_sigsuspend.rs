@@ -0,0 +1,49 @@
+use wasmer_wasix_types::wasi::Sigset;
+
+use super::*;
+use crate::syscalls::*;
+
+/// ### `proc_sigsuspend()`
+/// Replaces the signal mask of the current thread and waits until a signal
+/// is delivered, like `sigsuspend()`.
+///
+/// Once the handler of the signal returns the previous mask is put back and
+/// the call fails with `EINTR`, signals that terminate the process do not
+/// return at all and ignored signals do not end the wait.
+///
+/// ## Parameters
+///
+/// * `mask` - The signals to block while waiting
+#[instrument(level = "debug", skip_all, fields(pid = ctx.data().process.pid().raw(), tid = ctx.data().tid().raw()), ret, err)]
+pub fn proc_sigsuspend<M: MemorySize>(
+    mut ctx: FunctionEnvMut<'_, WasiEnv>,
+    mask: WasmPtr<Sigset, M>,
+) -> Result<Errno, WasiError> {
+    let env = ctx.data();
+    let memory = unsafe { env.memory_view(&ctx) };
+    let mask = wasi_try_mem_ok!(mask.read(&memory));
+
+    let thread = env.thread.clone();
+    let old = thread.set_signal_mask(mask);
+
+    // The wait ends as soon as a handler has run, the signals themselves
+    // are delivered by asyncify
+    let handled = thread.signals_handled();
+    let res = {
+        let thread = thread.clone();
+        __asyncify(&mut ctx, None, async move {
+            futures::future::poll_fn(|_| {
+                if thread.signals_handled() != handled {
+                    Poll::Ready(Err::<(), _>(Errno::Intr))
+                } else {
+                    Poll::Pending
+                }
+            })
+            .await
+        })
+    };
+    thread.set_signal_mask(old);
+    wasi_try_ok!(res?);
+
+    Ok(Errno::Intr)
+}
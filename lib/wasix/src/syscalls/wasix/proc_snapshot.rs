@@ -0,0 +1,89 @@
+use super::*;
+use crate::{
+    state::{ProcessSnapshot, SnapshotResult},
+    syscalls::*,
+};
+
+/// ### `proc_snapshot()`
+/// Saves a snapshot of the current process to the file the host has allowed
+/// and then carries on running. The process must be single threaded.
+///
+/// ## Parameters
+///
+/// * `restored` - Set to false when the snapshot has just been saved and to
+///   true when the process has been resumed from the snapshot
+#[instrument(level = "debug", skip_all, fields(pid = ctx.data().process.pid().raw()), ret, err)]
+pub fn proc_snapshot<M: MemorySize>(
+    mut ctx: FunctionEnvMut<'_, WasiEnv>,
+    restored: WasmPtr<Bool, M>,
+) -> Result<Errno, WasiError> {
+    wasi_try_ok!(WasiEnv::process_signals_and_exit(&mut ctx)?);
+
+    // If we were just rewound (or restored) then return the result
+    if let Some(result) = unsafe { handle_rewind::<M, SnapshotResult>(&mut ctx) } {
+        trace!(restored = result.restored, ret = %result.ret, "handle_rewind");
+        let memory = unsafe { ctx.data().memory_view(&ctx) };
+        let value = if result.restored {
+            Bool::True
+        } else {
+            Bool::False
+        };
+        wasi_try_mem_ok!(restored.write(&memory, value));
+        return Ok(result.ret);
+    }
+
+    let path = match ctx.data().capabilities.snapshot.path.clone() {
+        Some(path) => path,
+        None => return Ok(Errno::Notsup),
+    };
+    if ctx.data().process.read().threads.len() > 1 || ctx.data().vfork.is_some() {
+        return Ok(Errno::Notsup);
+    }
+
+    // Unwind the stack so it can be saved along with the memory
+    unwind::<M, _>(ctx, move |mut ctx, memory_stack, rewind_stack| {
+        let store_data = crate::utils::store::capture_snapshot(&mut ctx.as_store_mut())
+            .serialize()
+            .unwrap();
+        let rewind_state = RewindState {
+            memory_stack: memory_stack.freeze(),
+            rewind_stack: rewind_stack.freeze(),
+            store_data: Bytes::from(store_data),
+            is_64bit: M::is_64bit(),
+        };
+
+        let ret = match ProcessSnapshot::capture(&ctx, &rewind_state)
+            .and_then(|snapshot| snapshot.save(&path))
+        {
+            Ok(()) => {
+                debug!(path = %path.display(), "saved a snapshot of the process");
+                Errno::Success
+            }
+            Err(err) => {
+                warn!(
+                    error = &err as &dyn std::error::Error,
+                    "unable to snapshot the process"
+                );
+                err.into()
+            }
+        };
+
+        // Rewind the stack and carry on
+        match rewind::<M, _>(
+            ctx,
+            rewind_state.memory_stack,
+            rewind_state.rewind_stack,
+            rewind_state.store_data,
+            SnapshotResult {
+                restored: false,
+                ret,
+            },
+        ) {
+            Errno::Success => OnCalledAction::InvokeAgain,
+            err => {
+                warn!("failed - could not rewind the stack - errno={}", err);
+                OnCalledAction::Trap(Box::new(WasiError::Exit(err.into())))
+            }
+        }
+    })
+}
@@ -2,7 +2,7 @@ use virtual_fs::Pipe;
 use wasmer_wasix_types::wasi::ProcessHandles;
 
 use super::*;
-use crate::syscalls::*;
+use crate::{os::task::control_plane::ControlPlaneError, syscalls::*};
 
 /// Spawns a new process within the context of this machine
 ///
@@ -109,6 +109,7 @@ pub fn proc_spawn_internal(
     // Fork the current environment and set the new arguments
     let (mut child_env, handle) = match ctx.data().fork() {
         Ok(x) => x,
+        Err(ControlPlaneError::ResourceLimitReached { .. }) => return Ok(Err(Errno::Again)),
         Err(err) => {
             // TODO: evaluate the appropriate error code, document it in the spec.
             return Ok(Err(Errno::Access));
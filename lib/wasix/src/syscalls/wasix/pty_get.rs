@@ -0,0 +1,37 @@
+use super::*;
+use crate::{os::tty::TtyBridge, syscalls::*};
+
+/// ### `pty_get()`
+/// Retrieves the current state of a pseudo-terminal
+///
+/// ## Parameters
+///
+/// * `fd` - Either side of the pseudo-terminal
+/// * `tty_state` - Set to the state of the pseudo-terminal
+#[instrument(level = "debug", skip_all, fields(%fd), ret)]
+pub fn pty_get<M: MemorySize>(
+    ctx: FunctionEnvMut<'_, WasiEnv>,
+    fd: WasiFd,
+    tty_state: WasmPtr<Tty, M>,
+) -> Errno {
+    let env = ctx.data();
+    let pty = wasi_try!(fd_pty(env, fd));
+
+    let state = pty.tty_get();
+    let state = Tty {
+        cols: state.cols,
+        rows: state.rows,
+        width: state.width,
+        height: state.height,
+        stdin_tty: state.stdin_tty,
+        stdout_tty: state.stdout_tty,
+        stderr_tty: state.stderr_tty,
+        echo: state.echo,
+        line_buffered: state.line_buffered,
+    };
+
+    let memory = unsafe { env.memory_view(&ctx) };
+    wasi_try_mem!(tty_state.write(&memory, state));
+
+    Errno::Success
+}
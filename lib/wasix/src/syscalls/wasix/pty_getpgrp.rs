@@ -0,0 +1,26 @@
+use super::*;
+use crate::syscalls::*;
+
+/// ### `pty_getpgrp()`
+/// Returns the foreground process group of a pseudo-terminal, like
+/// `tcgetpgrp()`
+///
+/// ## Parameters
+///
+/// * `fd` - Either side of the pseudo-terminal
+/// * `ret` - Set to the ID of the foreground process group, or zero if there
+///   is none
+#[instrument(level = "trace", skip_all, fields(%fd), ret)]
+pub fn pty_getpgrp<M: MemorySize>(
+    ctx: FunctionEnvMut<'_, WasiEnv>,
+    fd: WasiFd,
+    ret: WasmPtr<Pid, M>,
+) -> Errno {
+    let env = ctx.data();
+    let pty = wasi_try!(fd_pty(env, fd));
+    let pgid = pty.foreground_group().map(|pgid| pgid.raw()).unwrap_or(0);
+
+    let memory = unsafe { env.memory_view(&ctx) };
+    wasi_try_mem!(ret.write(&memory, pgid as Pid));
+    Errno::Success
+}
@@ -0,0 +1,105 @@
+use std::sync::RwLock;
+
+use super::*;
+use crate::{
+    os::tty::{
+        pty::{Pty, PtyMaster, PtySlave},
+        TtyOptions,
+    },
+    syscalls::*,
+};
+
+/// ### `pty_open()`
+/// Creates a new pseudo-terminal, like `openpty()`
+///
+/// The master side is used by the program that acts as the terminal and the
+/// slave side is handed to the programs that run on it. What is written to
+/// the master goes through the line discipline of the terminal before it
+/// can be read from the slave.
+///
+/// ## Parameters
+///
+/// * `ret_master` - Set to the file handle of the master side
+/// * `ret_slave` - Set to the file handle of the slave side
+#[instrument(level = "debug", skip_all, fields(master = field::Empty, slave = field::Empty), ret)]
+pub fn pty_open<M: MemorySize>(
+    ctx: FunctionEnvMut<'_, WasiEnv>,
+    ret_master: WasmPtr<WasiFd, M>,
+    ret_slave: WasmPtr<WasiFd, M>,
+) -> Errno {
+    let env = ctx.data();
+    let (memory, state, inodes) = unsafe { env.get_memory_and_wasi_state_and_inodes(&ctx, 0) };
+
+    let (master, slave) = Pty::open(TtyOptions::default());
+    let stat = Filestat {
+        st_filetype: Filetype::CharacterDevice,
+        ..Filestat::default()
+    };
+    let master = state.fs.create_inode_with_stat(
+        inodes,
+        Kind::File {
+            handle: Some(Arc::new(RwLock::new(Box::new(master)))),
+            path: "/dev/ptmx".into(),
+            fd: None,
+        },
+        false,
+        "ptmx".into(),
+        stat,
+    );
+    let slave = state.fs.create_inode_with_stat(
+        inodes,
+        Kind::File {
+            handle: Some(Arc::new(RwLock::new(Box::new(slave)))),
+            path: "/dev/pts".into(),
+            fd: None,
+        },
+        false,
+        "pts".into(),
+        stat,
+    );
+
+    let rights = Rights::FD_READ
+        | Rights::FD_WRITE
+        | Rights::FD_SYNC
+        | Rights::FD_DATASYNC
+        | Rights::POLL_FD_READWRITE
+        | Rights::FD_FDSTAT_SET_FLAGS;
+    let master = wasi_try!(state
+        .fs
+        .create_fd(rights, rights, Fdflags::empty(), 0, master));
+    let slave = wasi_try!(state
+        .fs
+        .create_fd(rights, rights, Fdflags::empty(), 0, slave));
+    Span::current()
+        .record("master", master)
+        .record("slave", slave);
+
+    wasi_try_mem!(ret_master.write(&memory, master));
+    wasi_try_mem!(ret_slave.write(&memory, slave));
+
+    Errno::Success
+}
+
+/// Returns the pseudo-terminal that a file handle is either side of
+pub(crate) fn fd_pty(env: &WasiEnv, fd: WasiFd) -> Result<Pty, Errno> {
+    let fd_entry = env.state.fs.get_fd(fd)?;
+    let guard = fd_entry.inode.read();
+    let handle = match guard.deref() {
+        Kind::File {
+            handle: Some(handle),
+            ..
+        } => handle.clone(),
+        _ => return Err(Errno::Notty),
+    };
+    drop(guard);
+
+    let handle = handle.read().unwrap();
+    let file = (**handle).upcast_any_ref();
+    if let Some(master) = file.downcast_ref::<PtyMaster>() {
+        Ok(master.pty().clone())
+    } else if let Some(slave) = file.downcast_ref::<PtySlave>() {
+        Ok(slave.pty().clone())
+    } else {
+        Err(Errno::Notty)
+    }
+}
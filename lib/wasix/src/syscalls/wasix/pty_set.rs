@@ -0,0 +1,38 @@
+use super::*;
+use crate::{os::tty::TtyBridge, syscalls::*};
+
+/// ### `pty_set()`
+/// Updates the state of a pseudo-terminal, the foreground process group
+/// gets a `SIGWINCH` when its size changes
+///
+/// ## Parameters
+///
+/// * `fd` - Either side of the pseudo-terminal
+/// * `tty_state` - The new state of the pseudo-terminal
+#[instrument(level = "debug", skip_all, fields(%fd), ret)]
+pub fn pty_set<M: MemorySize>(
+    ctx: FunctionEnvMut<'_, WasiEnv>,
+    fd: WasiFd,
+    tty_state: WasmPtr<Tty, M>,
+) -> Errno {
+    let env = ctx.data();
+    let pty = wasi_try!(fd_pty(env, fd));
+
+    let memory = unsafe { env.memory_view(&ctx) };
+    let state = wasi_try_mem!(tty_state.read(&memory));
+    let state = crate::os::tty::WasiTtyState {
+        cols: state.cols,
+        rows: state.rows,
+        width: state.width,
+        height: state.height,
+        stdin_tty: state.stdin_tty,
+        stdout_tty: state.stdout_tty,
+        stderr_tty: state.stderr_tty,
+        echo: state.echo,
+        line_buffered: state.line_buffered,
+        line_feeds: true,
+    };
+    pty.tty_set(state);
+
+    Errno::Success
+}
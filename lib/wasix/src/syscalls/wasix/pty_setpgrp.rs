@@ -0,0 +1,36 @@
+use super::*;
+use crate::syscalls::*;
+
+/// ### `pty_setpgrp()`
+/// Changes the foreground process group of a pseudo-terminal, like
+/// `tcsetpgrp()`. The foreground group is the one that gets the signals
+/// raised by the terminal, such as `SIGINT` when Ctrl-C is written to the
+/// master side.
+///
+/// ## Parameters
+///
+/// * `fd` - Either side of the pseudo-terminal
+/// * `pgid` - The process group to move to the foreground, which must be
+///   in the same session
+#[instrument(level = "debug", skip_all, fields(%fd, %pgid), ret)]
+pub fn pty_setpgrp<M: MemorySize>(
+    ctx: FunctionEnvMut<'_, WasiEnv>,
+    fd: WasiFd,
+    pgid: Pid,
+) -> Errno {
+    let env = ctx.data();
+    let pty = wasi_try!(fd_pty(env, fd));
+
+    let pgid: WasiProcessId = pgid.into();
+    let group = env.control_plane.process_group(pgid);
+    if group.is_empty() {
+        return Errno::Srch;
+    }
+    let sid = env.process.sid();
+    if !group.iter().any(|member| member.sid() == sid) {
+        return Errno::Perm;
+    }
+
+    pty.set_foreground_group(env.control_plane.handle(), pgid);
+    Errno::Success
+}
@@ -1,7 +1,13 @@
 use std::task::Waker;
 
 use super::*;
-use crate::{net::socket::TimeType, syscalls::*};
+use crate::{
+    net::{
+        socket::{InodeSocketKind, TimeType},
+        unix::UnixStream,
+    },
+    syscalls::*,
+};
 
 /// ### `sock_accept()`
 /// Accept a new incoming connection.
@@ -67,12 +73,23 @@ pub fn sock_accept_v2<M: MemorySize>(
     let (fd, addr) = wasi_try_ok!(sock_accept_internal(env, sock, fd_flags, nonblocking));
 
     wasi_try_mem_ok!(ro_fd.write(&memory, fd));
-    wasi_try_ok!(crate::net::write_ip_port(
-        &memory,
-        ro_addr,
-        addr.ip(),
-        addr.port()
-    ));
+    match addr {
+        Some(addr) => wasi_try_ok!(crate::net::write_ip_port(
+            &memory,
+            ro_addr,
+            addr.ip(),
+            addr.port()
+        )),
+        // Connections to Unix sockets come from unnamed sockets
+        None => wasi_try_mem_ok!(ro_addr.write(
+            &memory,
+            __wasi_addr_port_t {
+                tag: Addressfamily::Unix,
+                _padding: 0,
+                u: __wasi_addr_port_u { octs: [0; 18] },
+            }
+        )),
+    }
 
     Ok(Errno::Success)
 }
@@ -82,38 +99,61 @@ pub fn sock_accept_internal(
     sock: WasiFd,
     mut fd_flags: Fdflags,
     mut nonblocking: bool,
-) -> Result<(WasiFd, SocketAddr), Errno> {
+) -> Result<(WasiFd, Option<SocketAddr>), Errno> {
     let state = env.state();
     let inodes = &state.inodes;
 
-    let tasks = env.tasks().clone();
-    let (child, addr, fd_flags) = __sock_asyncify(
-        env,
-        sock,
-        Rights::SOCK_ACCEPT,
-        move |socket, fd| async move {
-            if fd.flags.contains(Fdflags::NONBLOCK) {
+    // The peers of a recording aren't around when it is replayed, the guest
+    // gets a stand-in for the connection whose reads come from the journal
+    let pid = env.pid().raw();
+    let journal = env.runtime().journal().cloned();
+    let (child, addr, fd_flags) = match journal
+        .as_ref()
+        .and_then(|journal| journal.replay_accept(pid, sock))
+    {
+        Some(result) => {
+            let addr = result?;
+            if state.fs.get_fd(sock)?.flags.contains(Fdflags::NONBLOCK) {
                 fd_flags.set(Fdflags::NONBLOCK, true);
-                nonblocking = true;
             }
-            let timeout = socket
-                .opt_time(TimeType::AcceptTimeout)
-                .ok()
-                .flatten()
-                .unwrap_or(Duration::from_secs(30));
-            socket
-                .accept(tasks.deref(), nonblocking, Some(timeout))
-                .await
-                .map(|a| (a.0, a.1, fd_flags))
-        },
-    )?;
+            let child = InodeSocketKind::UnixStream {
+                socket: UnixStream::stand_in(),
+                write_timeout: None,
+                read_timeout: None,
+            };
+            (child, addr, fd_flags)
+        }
+        None => {
+            let tasks = env.tasks().clone();
+            let res = __sock_asyncify(
+                env,
+                sock,
+                Rights::SOCK_ACCEPT,
+                move |socket, fd| async move {
+                    if fd.flags.contains(Fdflags::NONBLOCK) {
+                        fd_flags.set(Fdflags::NONBLOCK, true);
+                        nonblocking = true;
+                    }
+                    let timeout = socket
+                        .opt_time(TimeType::AcceptTimeout)
+                        .ok()
+                        .flatten()
+                        .unwrap_or(Duration::from_secs(30));
+                    socket
+                        .accept(tasks.deref(), nonblocking, Some(timeout))
+                        .await
+                        .map(|a| (a.0, a.1, fd_flags))
+                },
+            );
+            if let Some(journal) = &journal {
+                journal.record_accept(pid, sock, res.as_ref().map(|a| a.1).map_err(|e| *e));
+            }
+            res?
+        }
+    };
 
     let kind = Kind::Socket {
-        socket: InodeSocket::new(InodeSocketKind::TcpStream {
-            socket: child,
-            write_timeout: None,
-            read_timeout: None,
-        }),
+        socket: InodeSocket::new(child),
     };
     let inode = state
         .fs
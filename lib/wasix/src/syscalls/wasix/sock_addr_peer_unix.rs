@@ -0,0 +1,41 @@
+use super::*;
+use crate::syscalls::*;
+
+/// ### `sock_addr_peer_unix()`
+/// Returns the path of the socket that a Unix socket is connected to.
+///
+/// Note: This is similar to `getpeername` in POSIX using PF_UNIX
+///
+/// The length of the path is written to `path_len`, it is zero when the
+/// peer is unnamed. When the path doesn't fit in the buffer this
+/// function will return ERANGE.
+///
+/// ## Parameters
+///
+/// * `fd` - Socket that is connected
+/// * `path` - Buffer the path is written to
+/// * `path_len` - Size of the buffer, replaced with the length of the path
+#[instrument(level = "debug", skip_all, fields(%sock, path = field::Empty), ret)]
+pub fn sock_addr_peer_unix<M: MemorySize>(
+    mut ctx: FunctionEnvMut<'_, WasiEnv>,
+    sock: WasiFd,
+    path: WasmPtr<u8, M>,
+    path_len: WasmPtr<M::Offset, M>,
+) -> Errno {
+    let addr = wasi_try!(__sock_actor(
+        &mut ctx,
+        sock,
+        Rights::empty(),
+        |socket, _| socket.addr_peer_unix()
+    ));
+    Span::current().record("path", &format!("{:?}", addr));
+
+    let memory = unsafe { ctx.data().memory_view(&ctx) };
+    wasi_try!(crate::net::write_unix_path(
+        &memory,
+        path,
+        path_len,
+        addr.as_deref()
+    ));
+    Errno::Success
+}
@@ -0,0 +1,67 @@
+use super::*;
+use crate::{net::unix::SocketFile, syscalls::*};
+
+/// ### `sock_bind_unix()`
+/// Bind a socket to a path of the file system
+/// Note: This is similar to `bind` in POSIX using PF_UNIX
+///
+/// A file is created at the path, binding fails when the path already
+/// exists. The socket stays bound to that file until it is removed.
+///
+/// ## Parameters
+///
+/// * `fd` - File descriptor of the socket to be bind
+/// * `path` - Path to bind the socket to
+#[instrument(level = "debug", skip_all, fields(%sock, path = field::Empty), ret)]
+pub fn sock_bind_unix<M: MemorySize>(
+    mut ctx: FunctionEnvMut<'_, WasiEnv>,
+    sock: WasiFd,
+    path: WasmPtr<u8, M>,
+    path_len: M::Offset,
+) -> Errno {
+    let env = ctx.data();
+    let (memory, state) = unsafe { env.get_memory_and_wasi_state(&ctx, 0) };
+    let path = unsafe { get_input_str!(&memory, path, path_len) };
+    let path = {
+        let current_dir = state.fs.current_dir.lock().unwrap();
+        crate::net::unix::resolve_path(current_dir.as_str(), &path)
+    };
+    Span::current().record("path", &format!("{}", path.display()));
+
+    let root_fs = state.fs.root_fs.clone();
+    let registry = env.process.compute.must_upgrade().unix_sockets().clone();
+
+    wasi_try!(__sock_upgrade(
+        &mut ctx,
+        sock,
+        Rights::SOCK_BIND,
+        move |socket| async move {
+            root_fs
+                .new_open_options()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+                .map_err(|err| match err {
+                    FsError::AlreadyExists => Errno::Addrinuse,
+                    err => fs_error_into_wasi_err(err),
+                })?;
+
+            // The socket is bound to the file rather than to its path, file
+            // systems that don't number their entries can't hold sockets
+            let file = root_fs
+                .metadata(&path)
+                .ok()
+                .and_then(|metadata| SocketFile::of(&metadata));
+            let res = match file {
+                Some(file) => socket.bind_unix(&registry, file, path.clone()),
+                None => Err(Errno::Notsup),
+            };
+            if res.is_err() {
+                root_fs.remove_file(&path).ok();
+            }
+            res
+        }
+    ));
+
+    Errno::Success
+}
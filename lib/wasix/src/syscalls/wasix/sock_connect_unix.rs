@@ -0,0 +1,57 @@
+use super::*;
+use crate::{net::unix::SocketFile, syscalls::*};
+
+/// ### `sock_connect_unix()`
+/// Connect a socket to the socket bound to a path of the file system
+///
+/// Connections to the paths that the Unix sockets capability forwards to
+/// the host are made to the host socket instead.
+///
+/// Note: This is similar to `connect` in POSIX using PF_UNIX
+///
+/// ## Parameters
+///
+/// * `fd` - Socket descriptor
+/// * `path` - Path of the socket to connect to
+#[instrument(level = "debug", skip_all, fields(%sock, path = field::Empty), ret)]
+pub fn sock_connect_unix<M: MemorySize>(
+    mut ctx: FunctionEnvMut<'_, WasiEnv>,
+    sock: WasiFd,
+    path: WasmPtr<u8, M>,
+    path_len: M::Offset,
+) -> Errno {
+    let env = ctx.data();
+    let (memory, state) = unsafe { env.get_memory_and_wasi_state(&ctx, 0) };
+    let path = unsafe { get_input_str!(&memory, path, path_len) };
+    let path = {
+        let current_dir = state.fs.current_dir.lock().unwrap();
+        crate::net::unix::resolve_path(current_dir.as_str(), &path)
+    };
+    Span::current().record("path", &format!("{}", path.display()));
+
+    let host = env
+        .capabilities
+        .unix_sockets
+        .host_socket(&path)
+        .map(|host| host.to_path_buf());
+    let file = match state.fs.root_fs.metadata(&path) {
+        Ok(metadata) => SocketFile::of(&metadata),
+        Err(_) if host.is_some() => None,
+        Err(_) => return Errno::Noent,
+    };
+
+    let registry = env.process.compute.must_upgrade().unix_sockets().clone();
+    let tasks = env.tasks().clone();
+    wasi_try!(__sock_upgrade(
+        &mut ctx,
+        sock,
+        Rights::SOCK_CONNECT,
+        move |mut socket| async move {
+            socket
+                .connect_unix(tasks.deref(), &registry, path, file, host)
+                .await
+        }
+    ));
+
+    Errno::Success
+}
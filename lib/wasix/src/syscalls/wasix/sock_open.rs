@@ -14,7 +14,7 @@ use crate::syscalls::*;
 /// ## Parameters
 ///
 /// * `af` - Address family
-/// * `socktype` - Socket type, either datagram or stream
+/// * `socktype` - Socket type, either datagram, stream or raw
 /// * `sock_proto` - Socket protocol
 ///
 /// ## Return
@@ -32,12 +32,13 @@ pub fn sock_open<M: MemorySize>(
     let (memory, state, inodes) = unsafe { env.get_memory_and_wasi_state_and_inodes(&ctx, 0) };
 
     let kind = match ty {
-        Socktype::Stream | Socktype::Dgram => Kind::Socket {
+        Socktype::Stream | Socktype::Dgram | Socktype::Raw => Kind::Socket {
             socket: InodeSocket::new(InodeSocketKind::PreSocket {
                 family: af,
                 ty,
                 pt,
                 addr: None,
+                unix_path: None,
                 only_v6: false,
                 reuse_port: false,
                 reuse_addr: false,
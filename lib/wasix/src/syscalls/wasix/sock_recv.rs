@@ -29,15 +29,17 @@ pub fn sock_recv<M: MemorySize>(
     let pid = ctx.data().pid();
     let tid = ctx.data().tid();
 
-    let res = sock_recv_internal::<M>(
-        &mut ctx,
-        sock,
-        ri_data,
-        ri_data_len,
-        ri_flags,
-        ro_data_len,
-        ro_flags,
-    )?;
+    let res = journal_read::<M>(&mut ctx, sock, ri_data, ri_data_len, false, |ctx| {
+        sock_recv_internal::<M>(
+            ctx,
+            sock,
+            ri_data,
+            ri_data_len,
+            ri_flags,
+            ro_data_len,
+            ro_flags,
+        )
+    })?;
 
     sock_recv_internal_handler(ctx, res, ro_data_len, ro_flags)
 }
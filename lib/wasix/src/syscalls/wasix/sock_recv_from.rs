@@ -51,15 +51,74 @@ pub(super) fn sock_recv_from_internal<M: MemorySize>(
 ) -> Result<Errno, WasiError> {
     wasi_try_ok!(WasiEnv::process_signals_and_exit(&mut ctx)?);
 
+    let journal = ctx.data().runtime().journal().cloned();
+    let pid = ctx.data().pid().raw();
+    let replayed = journal
+        .as_ref()
+        .and_then(|journal| journal.replay_recv_from(pid, sock));
+
+    let (bytes_read, peer) = match replayed {
+        Some(result) => {
+            let (data, peer) = wasi_try_ok!(result);
+            let env = ctx.data();
+            let memory = unsafe { env.memory_view(&ctx) };
+            let iovs_arr = wasi_try_mem_ok!(ri_data.slice(&memory, ri_data_len));
+            (
+                wasi_try_ok!(copy_from_slice(&data, &memory, iovs_arr)),
+                peer,
+            )
+        }
+        None => {
+            let result = sock_recv_from_data::<M>(&mut ctx, sock, ri_data, ri_data_len)?;
+            if let Some(journal) = &journal {
+                let recorded = match result {
+                    Ok((bytes_read, peer)) => {
+                        let env = ctx.data();
+                        let memory = unsafe { env.memory_view(&ctx) };
+                        let iovs_arr = wasi_try_mem_ok!(ri_data.slice(&memory, ri_data_len));
+                        gather_bytes(&memory, iovs_arr, bytes_read).map(|data| (data, peer))
+                    }
+                    Err(err) => Err(err),
+                };
+                journal.record_recv_from(pid, sock, recorded);
+            }
+            wasi_try_ok!(result)
+        }
+    };
+
+    let env = ctx.data();
+    let memory = unsafe { env.memory_view(&ctx) };
+    Span::current()
+        .record("nread", bytes_read)
+        .record("peer", &format!("{:?}", peer));
+
+    wasi_try_ok!(write_ip_port(&memory, ro_addr, peer.ip(), peer.port()));
+
+    let bytes_read: M::Offset = wasi_try_ok!(bytes_read.try_into().map_err(|_| Errno::Overflow));
+    wasi_try_mem_ok!(ro_flags.write(&memory, 0));
+    wasi_try_mem_ok!(ro_data_len.write(&memory, bytes_read));
+
+    Ok(Errno::Success)
+}
+
+/// Receives into `ri_data`, returning the number of bytes stored and the
+/// address they came from.
+fn sock_recv_from_data<M: MemorySize>(
+    ctx: &mut FunctionEnvMut<'_, WasiEnv>,
+    sock: WasiFd,
+    ri_data: WasmPtr<__wasi_iovec_t<M>, M>,
+    ri_data_len: M::Offset,
+) -> Result<Result<(usize, SocketAddr), Errno>, WasiError> {
     let mut env = ctx.data();
     let memory = unsafe { env.memory_view(&ctx) };
-    let iovs_arr = wasi_try_mem_ok!(ri_data.slice(&memory, ri_data_len));
+    let iovs_arr = wasi_try_mem_ok_ok!(ri_data.slice(&memory, ri_data_len));
 
     let max_size = {
         let mut max_size = 0usize;
         for iovs in iovs_arr.iter() {
-            let iovs = wasi_try_mem_ok!(iovs.read());
-            let buf_len: usize = wasi_try_ok!(iovs.buf_len.try_into().map_err(|_| Errno::Overflow));
+            let iovs = wasi_try_mem_ok_ok!(iovs.read());
+            let buf_len: usize =
+                wasi_try_ok_ok!(iovs.buf_len.try_into().map_err(|_| Errno::Overflow));
             max_size += buf_len;
         }
         max_size
@@ -69,7 +128,7 @@ pub(super) fn sock_recv_from_internal<M: MemorySize>(
         if max_size <= 10240 {
             let mut buf: [MaybeUninit<u8>; 10240] = unsafe { MaybeUninit::uninit().assume_init() };
             let writer = &mut buf[..max_size];
-            let (amt, peer) = wasi_try_ok!(__sock_asyncify(
+            let (amt, peer) = wasi_try_ok_ok!(__sock_asyncify(
                 env,
                 sock,
                 Rights::SOCK_RECV,
@@ -89,12 +148,12 @@ pub(super) fn sock_recv_from_internal<M: MemorySize>(
             if amt > 0 {
                 let buf: &[MaybeUninit<u8>] = &buf[..amt];
                 let buf: &[u8] = unsafe { std::mem::transmute(buf) };
-                wasi_try_ok!(copy_from_slice(buf, &memory, iovs_arr).map(|_| (amt, peer)))
+                wasi_try_ok_ok!(copy_from_slice(buf, &memory, iovs_arr).map(|_| (amt, peer)))
             } else {
                 (amt, peer)
             }
         } else {
-            let (data, peer) = wasi_try_ok!(__sock_asyncify(
+            let (data, peer) = wasi_try_ok_ok!(__sock_asyncify(
                 env,
                 sock,
                 Rights::SOCK_RECV_FROM,
@@ -126,21 +185,12 @@ pub(super) fn sock_recv_from_internal<M: MemorySize>(
             let data_len = data.len();
             if data_len > 0 {
                 let mut reader = &data[..];
-                wasi_try_ok!(read_bytes(reader, &memory, iovs_arr).map(|_| (data_len, peer)))
+                wasi_try_ok_ok!(read_bytes(reader, &memory, iovs_arr).map(|_| (data_len, peer)))
             } else {
                 (0, peer)
             }
         }
     };
-    Span::current()
-        .record("nread", bytes_read)
-        .record("peer", &format!("{:?}", peer));
-
-    wasi_try_ok!(write_ip_port(&memory, ro_addr, peer.ip(), peer.port()));
-
-    let bytes_read: M::Offset = wasi_try_ok!(bytes_read.try_into().map_err(|_| Errno::Overflow));
-    wasi_try_mem_ok!(ro_flags.write(&memory, 0));
-    wasi_try_mem_ok!(ro_data_len.write(&memory, bytes_read));
 
-    Ok(Errno::Success)
+    Ok(Ok((bytes_read, peer)))
 }
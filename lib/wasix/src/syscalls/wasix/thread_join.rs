@@ -31,8 +31,12 @@ pub(super) fn thread_join_internal<M: MemorySize + 'static>(
     let tid: WasiThreadId = join_tid.into();
     let other_thread = env.process.get_thread(&tid);
     if let Some(other_thread) = other_thread {
-        let res =
-            __asyncify_with_deep_sleep::<M, _, _>(ctx, Duration::from_millis(50), async move {
+        let res = __asyncify_with_deep_sleep::<M, _, _>(
+            ctx,
+            Duration::from_millis(50),
+            // Like `pthread_join()`, joining is never interrupted
+            OnSignal::Restart,
+            async move {
                 other_thread
                     .join()
                     .await
@@ -42,7 +46,8 @@ pub(super) fn thread_join_internal<M: MemorySize + 'static>(
                     })
                     .unwrap_or_else(|a| a)
                     .raw()
-            })?;
+            },
+        )?;
         Ok(Errno::Success)
     } else {
         Ok(Errno::Success)
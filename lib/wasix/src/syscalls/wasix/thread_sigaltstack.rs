@@ -0,0 +1,63 @@
+use wasmer_wasix_types::wasi::{SignalStack, SignalStackFlags};
+
+use super::*;
+use crate::syscalls::*;
+
+/// The smallest alternate signal stack that can be set
+const MIN_SIGNAL_STACK_SIZE: u64 = 2048;
+
+/// ### `thread_sigaltstack()`
+/// Sets the alternate stack that signal handlers of the current thread run
+/// on when they were set with `SA_ONSTACK`, like `sigaltstack()`.
+///
+/// ## Parameters
+///
+/// * `stack` - The new stack, or null to leave it as is. The stack is turned
+///   off by passing the `disable` flag.
+/// * `old_stack` - Where the previous stack is written, unless it is null
+#[instrument(level = "debug", skip_all, fields(pid = ctx.data().process.pid().raw(), tid = ctx.data().tid().raw()), ret, err)]
+pub fn thread_sigaltstack<M: MemorySize>(
+    mut ctx: FunctionEnvMut<'_, WasiEnv>,
+    stack: WasmPtr<SignalStack, M>,
+    old_stack: WasmPtr<SignalStack, M>,
+) -> Result<Errno, WasiError> {
+    wasi_try_ok!(WasiEnv::process_signals_and_exit(&mut ctx)?);
+
+    let env = ctx.data();
+    let memory = unsafe { env.memory_view(&ctx) };
+    let (current, on_stack) = env.thread.signal_stack();
+
+    if !stack.is_null() {
+        let stack = wasi_try_mem_ok!(stack.read(&memory));
+        if on_stack {
+            return Ok(Errno::Perm);
+        }
+        if stack.flags.contains(SignalStackFlags::DISABLE) {
+            env.thread.set_signal_stack(None);
+        } else {
+            if stack.size < MIN_SIGNAL_STACK_SIZE {
+                return Ok(Errno::Nomem);
+            }
+            if stack.base.checked_add(stack.size).is_none() {
+                return Ok(Errno::Inval);
+            }
+            env.thread.set_signal_stack(Some(SignalStack {
+                flags: SignalStackFlags::empty(),
+                ..stack
+            }));
+        }
+    }
+    if !old_stack.is_null() {
+        let mut old = current.unwrap_or(SignalStack {
+            base: 0,
+            size: 0,
+            flags: SignalStackFlags::DISABLE,
+        });
+        if on_stack {
+            old.flags |= SignalStackFlags::ONSTACK;
+        }
+        wasi_try_mem_ok!(old_stack.write(&memory, old));
+    }
+
+    Ok(Errno::Success)
+}
@@ -0,0 +1,27 @@
+use wasmer_wasix_types::wasi::Siginfo;
+
+use super::*;
+use crate::syscalls::*;
+
+/// ### `thread_siginfo_get()`
+/// Returns the details of the signal whose handler is running on the
+/// current thread, which is how handlers set with `SA_SIGINFO` get their
+/// `siginfo_t`.
+///
+/// ## Parameters
+///
+/// * `ret` - Where the details of the signal are written
+///
+/// Fails with `EINVAL` when no signal handler is running.
+#[instrument(level = "debug", skip_all, fields(pid = ctx.data().process.pid().raw(), tid = ctx.data().tid().raw()), ret)]
+pub fn thread_siginfo_get<M: MemorySize>(
+    ctx: FunctionEnvMut<'_, WasiEnv>,
+    ret: WasmPtr<Siginfo, M>,
+) -> Errno {
+    let env = ctx.data();
+    let memory = unsafe { env.memory_view(&ctx) };
+    let info = wasi_try!(env.thread.current_siginfo().ok_or(Errno::Inval));
+    wasi_try_mem!(ret.write(&memory, info));
+
+    Errno::Success
+}
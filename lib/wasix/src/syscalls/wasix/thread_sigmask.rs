@@ -0,0 +1,47 @@
+use wasmer_wasix_types::wasi::{SigmaskHow, Sigset};
+
+use super::*;
+use crate::syscalls::*;
+
+/// ### `thread_sigmask()`
+/// Changes the signals that the current thread has blocked, like
+/// `pthread_sigmask()`.
+///
+/// Blocked signals stay pending until they are unblocked, any that are
+/// unblocked by this call are delivered before it returns. `SIGKILL` and
+/// `SIGSTOP` can not be blocked and are silently left out of the mask.
+///
+/// ## Parameters
+///
+/// * `how` - Whether the set is added to, removed from or replaces the mask
+/// * `set` - The signals to change, or null to only read the mask
+/// * `oldset` - Where the previous mask is written, unless it is null
+#[instrument(level = "debug", skip_all, fields(pid = ctx.data().process.pid().raw(), tid = ctx.data().tid().raw(), ?how), ret, err)]
+pub fn thread_sigmask<M: MemorySize>(
+    mut ctx: FunctionEnvMut<'_, WasiEnv>,
+    how: SigmaskHow,
+    set: WasmPtr<Sigset, M>,
+    oldset: WasmPtr<Sigset, M>,
+) -> Result<Errno, WasiError> {
+    let env = ctx.data();
+    let memory = unsafe { env.memory_view(&ctx) };
+    let old = env.thread.signal_mask();
+
+    if !set.is_null() {
+        let set = wasi_try_mem_ok!(set.read(&memory));
+        let mask = match how {
+            SigmaskHow::Block => old | set,
+            SigmaskHow::Unblock => old & !set,
+            SigmaskHow::Setmask => set,
+            SigmaskHow::Unknown => return Ok(Errno::Inval),
+        };
+        env.thread.set_signal_mask(mask);
+    }
+    if !oldset.is_null() {
+        wasi_try_mem_ok!(oldset.write(&memory, old));
+    }
+
+    wasi_try_ok!(WasiEnv::process_signals_and_exit(&mut ctx)?);
+
+    Ok(Errno::Success)
+}
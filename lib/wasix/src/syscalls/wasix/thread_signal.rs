@@ -1,5 +1,7 @@
+use wasmer_wasix_types::wasi::SignalCode;
+
 use super::*;
-use crate::syscalls::*;
+use crate::{os::task::signal::new_siginfo, syscalls::*};
 
 /// ### `thread_signal()`
 /// Send a signal to a particular thread in the current process.
@@ -14,8 +16,10 @@ pub fn thread_signal(
     sig: Signal,
 ) -> Result<Errno, WasiError> {
     {
+        let env = ctx.data();
         let tid: WasiThreadId = tid.into();
-        ctx.data().process.signal_thread(&tid, sig);
+        let info = new_siginfo(sig, SignalCode::User, Some(env.pid()));
+        env.process.signal_thread_with_info(&tid, sig, info);
     }
 
     let env = ctx.data();
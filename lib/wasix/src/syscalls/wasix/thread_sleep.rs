@@ -37,10 +37,17 @@ pub(crate) fn thread_sleep_internal<M: MemorySize + 'static>(
     if duration > 0 {
         let duration = Duration::from_nanos(duration);
         let tasks = env.tasks().clone();
-        let res =
-            __asyncify_with_deep_sleep::<M, _, _>(ctx, Duration::from_millis(50), async move {
+        let res = __asyncify_with_deep_sleep::<M, _, _>(
+            ctx,
+            Duration::from_millis(50),
+            OnSignal::Interrupt,
+            async move {
                 tasks.sleep_now(duration).await;
-            })?;
+            },
+        )?;
+        if let AsyncifyAction::Interrupted(_) = res {
+            return Ok(Errno::Intr);
+        }
     }
     Ok(Errno::Success)
 }
@@ -3,7 +3,7 @@ use std::f32::consts::E;
 use super::*;
 use crate::{
     capture_snapshot,
-    os::task::thread::WasiMemoryLayout,
+    os::task::{control_plane::ControlPlaneError, thread::WasiMemoryLayout},
     runtime::task_manager::{TaskWasm, TaskWasmRunProperties},
     syscalls::*,
     WasiThreadHandle,
@@ -75,6 +75,7 @@ pub(crate) fn thread_spawn_internal<M: MemorySize>(
     // Create the handle that represents this thread
     let mut thread_handle = match env.process.new_thread() {
         Ok(h) => Arc::new(h),
+        Err(ControlPlaneError::ResourceLimitReached { .. }) => return Err(Errno::Again),
         Err(err) => {
             error!(
                 stack_base = layout.stack_lower,
@@ -93,6 +94,9 @@ pub(crate) fn thread_spawn_internal<M: MemorySize>(
     thread_env.thread = thread_handle.as_thread();
     thread_env.layout = layout;
 
+    // Like pthreads, the new thread starts with the signal mask of its creator
+    thread_env.thread.set_signal_mask(env.thread.signal_mask());
+
     // TODO: Currently asynchronous threading does not work with multi
     //       threading but it does work for the main thread. This will
     //       require more work to find out why.
@@ -0,0 +1,23 @@
+use super::*;
+use crate::syscalls::*;
+
+/// ### `tty_getpgrp()`
+/// Returns the foreground process group of the terminal that belongs to the
+/// session of this process, like `tcgetpgrp()`
+///
+/// ## Parameters
+///
+/// * `ret` - Set to the ID of the foreground process group
+#[instrument(level = "trace", skip_all, ret)]
+pub fn tty_getpgrp<M: MemorySize>(ctx: FunctionEnvMut<'_, WasiEnv>, ret: WasmPtr<Pid, M>) -> Errno {
+    let env = ctx.data();
+
+    // Until the session picks a foreground group it is the group of the
+    // session leader
+    let sid = env.process.sid();
+    let pgid = env.control_plane.foreground_group(sid).unwrap_or(sid);
+
+    let memory = unsafe { env.memory_view(&ctx) };
+    wasi_try_mem!(ret.write(&memory, pgid.raw() as Pid));
+    Errno::Success
+}
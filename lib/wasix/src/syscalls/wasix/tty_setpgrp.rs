@@ -0,0 +1,27 @@
+use super::*;
+use crate::syscalls::*;
+
+/// ### `tty_setpgrp()`
+/// Changes the foreground process group of the terminal that belongs to the
+/// session of this process, like `tcsetpgrp()`. The foreground group is the
+/// one that gets the signals raised by the terminal, such as `SIGINT` for
+/// Ctrl-C and `SIGTSTP` for Ctrl-Z.
+///
+/// ## Parameters
+///
+/// * `pgid` - The process group to move to the foreground, which must be
+///   in the same session
+#[instrument(level = "debug", skip_all, fields(pid = ctx.data().process.pid().raw(), %pgid), ret)]
+pub fn tty_setpgrp<M: MemorySize>(ctx: FunctionEnvMut<'_, WasiEnv>, pgid: Pid) -> Errno {
+    let env = ctx.data();
+    let pgid: WasiProcessId = pgid.into();
+
+    let sid = env.process.sid();
+    let group = env.control_plane.process_group(pgid);
+    if !group.iter().any(|member| member.sid() == sid) {
+        return Errno::Perm;
+    }
+
+    env.control_plane.set_foreground_group(sid, pgid);
+    Errno::Success
+}